@@ -0,0 +1,586 @@
+use crate::crypto::{KEY_LEN, NONCE_LEN, xor_in_place};
+
+/// Length of an AES-GCM tag in bytes.
+pub const GCM_TAG_LEN: usize = 16;
+
+/// Most bytes one nonce can encrypt: the block counter is 32 bits, and
+/// its first two values go to the tag.
+pub const GCM_MAX_LEN: u64 = ((1 << 32) - 2) * BLOCK_LEN as u64;
+
+const BLOCK_LEN: usize = 16;
+const ROUNDS: usize = 14;
+
+/// Blocks encrypted together, so the AES instructions of one block overlap
+/// with the next and the software S-box fills its 64 lanes.
+const BATCH: usize = 4;
+
+/// Whether AES-GCM runs on the CPU's AES and carry-less multiply
+/// instructions here. Without them it falls back to code that is
+/// constant-time but many times slower than ChaCha20.
+pub fn accelerated() -> bool {
+    #[cfg(all(feature = "std", target_arch = "x86_64"))]
+    if std::arch::is_x86_feature_detected!("aes") && std::arch::is_x86_feature_detected!("pclmulqdq") {
+        return true;
+    }
+    false
+}
+
+/// AES-256 in Galois/Counter Mode (NIST SP 800-38D) with a 12-byte nonce.
+/// The counter for the first byte of the ciphertext is 2, since 1 masks
+/// the tag.
+pub struct Aes256Gcm {
+    round_keys: [[u8; BLOCK_LEN]; ROUNDS + 1],
+    /// The hash key, the encryption of a zero block, as a big-endian
+    /// number so bit 0 of GCM is its top bit.
+    hash_key: u128,
+    accelerated: bool,
+}
+
+impl Aes256Gcm {
+    pub fn new(key: &[u8; KEY_LEN]) -> Self {
+        Self::with_acceleration(key, accelerated())
+    }
+
+    /// `accelerated` must only be true where `accelerated()` is.
+    fn with_acceleration(key: &[u8; KEY_LEN], accelerated: bool) -> Self {
+        let mut aes = Aes256Gcm {
+            round_keys: expand_key(key),
+            hash_key: 0,
+            accelerated,
+        };
+        let mut zero = [[0u8; BLOCK_LEN]];
+        aes.encrypt_blocks(&mut zero);
+        aes.hash_key = u128::from_be_bytes(zero[0]);
+        aes
+    }
+
+    /// XOR `data` with the keystream for `nonce`, starting `position`
+    /// bytes into the ciphertext, as `crypto::chacha20_xor` does.
+    pub fn xor_keystream(&self, nonce: &[u8; NONCE_LEN], position: u64, data: &mut [u8]) {
+        let mut block = position / BLOCK_LEN as u64;
+        let mut skip = (position % BLOCK_LEN as u64) as usize;
+        let mut rest = data;
+        while !rest.is_empty() {
+            let count = (skip + rest.len()).div_ceil(BLOCK_LEN).min(BATCH);
+            let mut keystream = [[0u8; BLOCK_LEN]; BATCH];
+            for (index, counter) in keystream[..count].iter_mut().enumerate() {
+                *counter = counter_block(nonce, (block + index as u64 + 2) as u32);
+            }
+            self.encrypt_blocks(&mut keystream[..count]);
+            let keystream = &keystream.as_flattened()[skip..count * BLOCK_LEN];
+            let take = keystream.len().min(rest.len());
+            let (now, later) = rest.split_at_mut(take);
+            xor_in_place(now, keystream);
+            rest = later;
+            block += count as u64;
+            skip = 0;
+        }
+    }
+
+    /// Start the tag for `nonce` over the associated data `aad`; the
+    /// ciphertext is fed to the result.
+    pub fn tagger(&self, nonce: &[u8; NONCE_LEN], aad: &[u8]) -> GcmTagger {
+        let mut mask = [counter_block(nonce, 1)];
+        self.encrypt_blocks(&mut mask);
+        let mut ghash = Ghash::new(self.hash_key, self.accelerated);
+        ghash.update(aad);
+        ghash.pad();
+        GcmTagger {
+            ghash,
+            mask: mask[0],
+            aad_len: aad.len() as u64,
+            ciphertext_len: 0,
+        }
+    }
+
+    /// The tag over `aad` and `ciphertext` in one call.
+    pub fn tag(&self, nonce: &[u8; NONCE_LEN], aad: &[u8], ciphertext: &[u8]) -> [u8; GCM_TAG_LEN] {
+        let mut tagger = self.tagger(nonce, aad);
+        tagger.update(ciphertext);
+        tagger.finalize()
+    }
+
+    fn encrypt_blocks(&self, blocks: &mut [[u8; BLOCK_LEN]]) {
+        #[cfg(all(feature = "std", target_arch = "x86_64"))]
+        if self.accelerated {
+            // SAFETY: `accelerated` is only set where the CPU has AES.
+            unsafe { encrypt_blocks_aesni(&self.round_keys, blocks) };
+            return;
+        }
+        for batch in blocks.chunks_mut(BATCH) {
+            encrypt_blocks_portable(&self.round_keys, batch);
+        }
+    }
+}
+
+impl Drop for Aes256Gcm {
+    fn drop(&mut self) {
+        self.round_keys = [[0; BLOCK_LEN]; ROUNDS + 1];
+        self.hash_key = 0;
+        core::hint::black_box(&mut *self);
+    }
+}
+
+/// A GCM tag fed the ciphertext piece by piece, for files too large to
+/// hold whole.
+pub struct GcmTagger {
+    ghash: Ghash,
+    mask: [u8; BLOCK_LEN],
+    aad_len: u64,
+    ciphertext_len: u64,
+}
+
+impl GcmTagger {
+    pub fn update(&mut self, ciphertext: &[u8]) {
+        self.ghash.update(ciphertext);
+        self.ciphertext_len += ciphertext.len() as u64;
+    }
+
+    pub fn finalize(mut self) -> [u8; GCM_TAG_LEN] {
+        self.ghash.pad();
+        let mut lengths = [0u8; BLOCK_LEN];
+        lengths[..8].copy_from_slice(&(self.aad_len * 8).to_be_bytes());
+        lengths[8..].copy_from_slice(&(self.ciphertext_len * 8).to_be_bytes());
+        self.ghash.update(&lengths);
+        let mut tag = self.ghash.state.to_be_bytes();
+        xor_in_place(&mut tag, &self.mask);
+        tag
+    }
+}
+
+/// The GHASH function of GCM, keeping back a partial block until more
+/// input or `pad` completes it.
+struct Ghash {
+    hash_key: u128,
+    #[cfg_attr(not(all(feature = "std", target_arch = "x86_64")), allow(dead_code))]
+    accelerated: bool,
+    state: u128,
+    partial: [u8; BLOCK_LEN],
+    partial_len: usize,
+}
+
+impl Drop for Ghash {
+    fn drop(&mut self) {
+        self.hash_key = 0;
+        self.state = 0;
+        core::hint::black_box(&mut *self);
+    }
+}
+
+impl Ghash {
+    fn new(hash_key: u128, accelerated: bool) -> Self {
+        Ghash {
+            hash_key,
+            accelerated,
+            state: 0,
+            partial: [0; BLOCK_LEN],
+            partial_len: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        if self.partial_len > 0 {
+            let take = (BLOCK_LEN - self.partial_len).min(data.len());
+            self.partial[self.partial_len..self.partial_len + take].copy_from_slice(&data[..take]);
+            self.partial_len += take;
+            data = &data[take..];
+            if self.partial_len < BLOCK_LEN {
+                return;
+            }
+            self.absorb(self.partial);
+            self.partial_len = 0;
+        }
+        let mut blocks = data.chunks_exact(BLOCK_LEN);
+        for block in &mut blocks {
+            self.absorb(block.try_into().expect("chunk is a block"));
+        }
+        let rest = blocks.remainder();
+        self.partial[..rest.len()].copy_from_slice(rest);
+        self.partial_len = rest.len();
+    }
+
+    /// Finish a partial block with zeros, as GCM does at the end of the
+    /// associated data and of the ciphertext.
+    fn pad(&mut self) {
+        if self.partial_len > 0 {
+            self.partial[self.partial_len..].fill(0);
+            self.absorb(self.partial);
+            self.partial_len = 0;
+        }
+    }
+
+    fn absorb(&mut self, block: [u8; BLOCK_LEN]) {
+        let x = self.state ^ u128::from_be_bytes(block);
+        #[cfg(all(feature = "std", target_arch = "x86_64"))]
+        if self.accelerated {
+            // SAFETY: `accelerated` is only set where the CPU has PCLMULQDQ.
+            self.state = unsafe { gf_multiply_pclmul(x, self.hash_key) };
+            return;
+        }
+        self.state = gf_multiply_portable(x, self.hash_key);
+    }
+}
+
+/// The nonce followed by a 32-bit big-endian block counter.
+fn counter_block(nonce: &[u8; NONCE_LEN], counter: u32) -> [u8; BLOCK_LEN] {
+    let mut block = [0u8; BLOCK_LEN];
+    block[..NONCE_LEN].copy_from_slice(nonce);
+    block[NONCE_LEN..].copy_from_slice(&counter.to_be_bytes());
+    block
+}
+
+/// Multiply in GCM's field, GF(2^128) with bits in reverse order, one bit
+/// at a time with masks rather than branches, so the time does not depend
+/// on the values.
+fn gf_multiply_portable(x: u128, y: u128) -> u128 {
+    let mut product = 0u128;
+    let mut v = y;
+    for bit in (0..128).rev() {
+        product ^= v & 0u128.wrapping_sub((x >> bit) & 1);
+        v = (v >> 1) ^ ((0xe1 << 120) & 0u128.wrapping_sub(v & 1));
+    }
+    product
+}
+
+/// `gf_multiply_portable` with carry-less multiplication, following
+/// Intel's "Carry-Less Multiplication and Its Usage for Computing the GCM
+/// Mode" (algorithm 5). Big-endian numbers are the byte-reflected blocks
+/// it expects.
+#[cfg(all(feature = "std", target_arch = "x86_64"))]
+#[target_feature(enable = "pclmulqdq,sse2")]
+fn gf_multiply_pclmul(x: u128, y: u128) -> u128 {
+    use core::arch::x86_64::*;
+
+    // SAFETY: u128 and __m128i are both 16 plain bytes.
+    let (a, b) = unsafe { (core::mem::transmute::<u128, __m128i>(x), core::mem::transmute::<u128, __m128i>(y)) };
+    let mut low = _mm_clmulepi64_si128::<0x00>(a, b);
+    let mut middle = _mm_xor_si128(_mm_clmulepi64_si128::<0x10>(a, b), _mm_clmulepi64_si128::<0x01>(a, b));
+    let mut high = _mm_clmulepi64_si128::<0x11>(a, b);
+    low = _mm_xor_si128(low, _mm_slli_si128::<8>(middle));
+    high = _mm_xor_si128(high, _mm_srli_si128::<8>(middle));
+
+    // Shift the 256-bit product left by one, since the operands were
+    // reflected.
+    let low_carry = _mm_srli_epi32::<31>(low);
+    let high_carry = _mm_srli_epi32::<31>(high);
+    low = _mm_slli_epi32::<1>(low);
+    high = _mm_slli_epi32::<1>(high);
+    let crossing = _mm_srli_si128::<12>(low_carry);
+    low = _mm_or_si128(low, _mm_slli_si128::<4>(low_carry));
+    high = _mm_or_si128(high, _mm_slli_si128::<4>(high_carry));
+    high = _mm_or_si128(high, crossing);
+
+    // Reduce modulo x^128 + x^7 + x^2 + x + 1.
+    middle = _mm_xor_si128(
+        _mm_xor_si128(_mm_slli_epi32::<31>(low), _mm_slli_epi32::<30>(low)),
+        _mm_slli_epi32::<25>(low),
+    );
+    let carried = _mm_srli_si128::<4>(middle);
+    low = _mm_xor_si128(low, _mm_slli_si128::<12>(middle));
+    let mut folded = _mm_xor_si128(
+        _mm_xor_si128(_mm_srli_epi32::<1>(low), _mm_srli_epi32::<2>(low)),
+        _mm_srli_epi32::<7>(low),
+    );
+    folded = _mm_xor_si128(folded, carried);
+    low = _mm_xor_si128(low, folded);
+    high = _mm_xor_si128(high, low);
+    // SAFETY: as above.
+    unsafe { core::mem::transmute::<__m128i, u128>(high) }
+}
+
+/// The AES-256 key schedule (FIPS 197 section 5.2).
+fn expand_key(key: &[u8; KEY_LEN]) -> [[u8; BLOCK_LEN]; ROUNDS + 1] {
+    let mut words = [[0u8; 4]; 4 * (ROUNDS + 1)];
+    for (word, bytes) in words.iter_mut().zip(key.chunks_exact(4)) {
+        word.copy_from_slice(bytes);
+    }
+    let mut round_constant = 1u8;
+    for index in 8..words.len() {
+        let mut word = words[index - 1];
+        if index % 8 == 0 {
+            word.rotate_left(1);
+            sub_bytes(&mut word);
+            word[0] ^= round_constant;
+            round_constant = xtime(round_constant);
+        } else if index % 8 == 4 {
+            sub_bytes(&mut word);
+        }
+        for (byte, earlier) in word.iter_mut().zip(words[index - 8]) {
+            *byte ^= earlier;
+        }
+        words[index] = word;
+    }
+    let mut round_keys = [[0u8; BLOCK_LEN]; ROUNDS + 1];
+    for (round_key, four) in round_keys.iter_mut().zip(words.chunks_exact(4)) {
+        round_key.copy_from_slice(four.as_flattened());
+    }
+    words = [[0; 4]; 4 * (ROUNDS + 1)];
+    core::hint::black_box(&mut words);
+    round_keys
+}
+
+/// Encrypt up to `BATCH` blocks without table lookups, so no cache line
+/// touched depends on the key or the data.
+fn encrypt_blocks_portable(round_keys: &[[u8; BLOCK_LEN]; ROUNDS + 1], blocks: &mut [[u8; BLOCK_LEN]]) {
+    for block in blocks.iter_mut() {
+        xor_in_place(block, &round_keys[0]);
+    }
+    for (round, round_key) in round_keys.iter().enumerate().skip(1) {
+        sub_bytes(blocks.as_flattened_mut());
+        for block in blocks.iter_mut() {
+            shift_rows(block);
+            if round < ROUNDS {
+                mix_columns(block);
+            }
+            xor_in_place(block, round_key);
+        }
+    }
+}
+
+/// Encrypt `blocks` with the AES-NI instructions, a batch at a time.
+#[cfg(all(feature = "std", target_arch = "x86_64"))]
+#[target_feature(enable = "aes,sse2")]
+fn encrypt_blocks_aesni(round_keys: &[[u8; BLOCK_LEN]; ROUNDS + 1], blocks: &mut [[u8; BLOCK_LEN]]) {
+    use core::arch::x86_64::*;
+
+    // SAFETY: each round key is 16 bytes, and the unaligned load accepts
+    // any address.
+    let keys = round_keys.map(|key| unsafe { _mm_loadu_si128(key.as_ptr() as *const __m128i) });
+    for batch in blocks.chunks_mut(BATCH) {
+        let mut states = [_mm_setzero_si128(); BATCH];
+        for (state, block) in states.iter_mut().zip(batch.iter()) {
+            // SAFETY: as above, for a 16-byte block.
+            *state = _mm_xor_si128(unsafe { _mm_loadu_si128(block.as_ptr() as *const __m128i) }, keys[0]);
+        }
+        for key in &keys[1..ROUNDS] {
+            for state in states.iter_mut() {
+                *state = _mm_aesenc_si128(*state, *key);
+            }
+        }
+        for (state, block) in states.iter().zip(batch.iter_mut()) {
+            // SAFETY: as above.
+            unsafe { _mm_storeu_si128(block.as_mut_ptr() as *mut __m128i, _mm_aesenclast_si128(*state, keys[ROUNDS])) };
+        }
+    }
+}
+
+/// Replace each byte with its S-box value. The bytes, at most 64, are
+/// spread over the lanes of eight words, one word per bit, and the S-box
+/// is worked out in that form: the inverse in GF(2^8) as x^254, then the
+/// affine map. Every byte takes the same steps whatever its value.
+fn sub_bytes(bytes: &mut [u8]) {
+    let mut x = [0u64; 8];
+    for (lane, &byte) in bytes.iter().enumerate() {
+        for (bit, plane) in x.iter_mut().enumerate() {
+            *plane |= u64::from((byte >> bit) & 1) << lane;
+        }
+    }
+
+    let x2 = square(&x);
+    let x3 = multiply(&x2, &x);
+    let x12 = square(&square(&x3));
+    let x15 = multiply(&x12, &x3);
+    let x240 = square(&square(&square(&square(&x15))));
+    let x252 = multiply(&x240, &x12);
+    let inverse = multiply(&x252, &x2);
+
+    let mut output = [0u64; 8];
+    for (bit, plane) in output.iter_mut().enumerate() {
+        let constant = if (0x63 >> bit) & 1 == 1 { u64::MAX } else { 0 };
+        *plane = inverse[bit]
+            ^ inverse[(bit + 4) % 8]
+            ^ inverse[(bit + 5) % 8]
+            ^ inverse[(bit + 6) % 8]
+            ^ inverse[(bit + 7) % 8]
+            ^ constant;
+    }
+    for (lane, byte) in bytes.iter_mut().enumerate() {
+        *byte = output
+            .iter()
+            .enumerate()
+            .fold(0, |byte, (bit, plane)| byte | (((plane >> lane) & 1) as u8) << bit);
+    }
+}
+
+/// Multiply bytes held one bit per word, lane by lane, in GF(2^8).
+fn multiply(a: &[u64; 8], b: &[u64; 8]) -> [u64; 8] {
+    let mut product = [0u64; 15];
+    for (i, a) in a.iter().enumerate() {
+        for (j, b) in b.iter().enumerate() {
+            product[i + j] ^= a & b;
+        }
+    }
+    reduce(product)
+}
+
+fn square(a: &[u64; 8]) -> [u64; 8] {
+    let mut product = [0u64; 15];
+    for (i, a) in a.iter().enumerate() {
+        product[2 * i] = *a;
+    }
+    reduce(product)
+}
+
+/// Reduce modulo the AES polynomial x^8 + x^4 + x^3 + x + 1.
+fn reduce(mut product: [u64; 15]) -> [u64; 8] {
+    for bit in (8..15).rev() {
+        let high = product[bit];
+        product[bit - 4] ^= high;
+        product[bit - 5] ^= high;
+        product[bit - 7] ^= high;
+        product[bit - 8] ^= high;
+    }
+    product[..8].try_into().expect("eight words")
+}
+
+fn shift_rows(block: &mut [u8; BLOCK_LEN]) {
+    let before = *block;
+    for column in 0..4 {
+        for row in 0..4 {
+            block[4 * column + row] = before[4 * ((column + row) % 4) + row];
+        }
+    }
+}
+
+fn mix_columns(block: &mut [u8; BLOCK_LEN]) {
+    for column in block.chunks_exact_mut(4) {
+        let [a0, a1, a2, a3] = [column[0], column[1], column[2], column[3]];
+        let all = a0 ^ a1 ^ a2 ^ a3;
+        column[0] ^= all ^ xtime(a0 ^ a1);
+        column[1] ^= all ^ xtime(a1 ^ a2);
+        column[2] ^= all ^ xtime(a2 ^ a3);
+        column[3] ^= all ^ xtime(a3 ^ a0);
+    }
+}
+
+/// Multiply by x in GF(2^8), with a mask in place of a branch.
+fn xtime(byte: u8) -> u8 {
+    (byte << 1) ^ (0x1b & 0u8.wrapping_sub(byte >> 7))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use alloc::vec::Vec;
+
+    fn unhex(text: &str) -> Vec<u8> {
+        (0..text.len()).step_by(2).map(|i| u8::from_str_radix(&text[i..i + 2], 16).unwrap()).collect()
+    }
+
+    /// Both implementations, or only the portable one where the CPU has
+    /// no AES instructions.
+    fn both(key: &[u8; KEY_LEN]) -> Vec<Aes256Gcm> {
+        let mut all = alloc::vec![Aes256Gcm::with_acceleration(key, false)];
+        if accelerated() {
+            all.push(Aes256Gcm::with_acceleration(key, true));
+        }
+        all
+    }
+
+    #[test]
+    fn s_box_matches_its_definition() {
+        // The S-box worked out byte by byte: the inverse by search, then
+        // the affine map.
+        let gf_multiply = |mut a: u8, mut b: u8| {
+            let mut product = 0u8;
+            while b != 0 {
+                if b & 1 == 1 {
+                    product ^= a;
+                }
+                a = xtime(a);
+                b >>= 1;
+            }
+            product
+        };
+        let mut bytes: Vec<u8> = (0..=255).collect();
+        for chunk in bytes.chunks_mut(64) {
+            sub_bytes(chunk);
+        }
+        for (value, &substituted) in bytes.iter().enumerate() {
+            let inverse = (0..=255u8).find(|&candidate| gf_multiply(value as u8, candidate) == 1).unwrap_or(0);
+            let affine = inverse
+                ^ inverse.rotate_left(1)
+                ^ inverse.rotate_left(2)
+                ^ inverse.rotate_left(3)
+                ^ inverse.rotate_left(4)
+                ^ 0x63;
+            assert_eq!(substituted, affine, "S-box of {:#04x}", value);
+        }
+    }
+
+    #[test]
+    fn aes_256_known_answer() {
+        // FIPS 197 appendix C.3.
+        let key: [u8; KEY_LEN] = core::array::from_fn(|i| i as u8);
+        for aes in both(&key) {
+            let mut block = [unhex("00112233445566778899aabbccddeeff").try_into().unwrap()];
+            aes.encrypt_blocks(&mut block);
+            assert_eq!(block[0][..], unhex("8ea2b7ca516745bfeafc49904b496089"));
+        }
+    }
+
+    #[test]
+    fn gcm_known_answers() {
+        // Test cases 13, 14, and 16 of McGrew and Viega's GCM specification.
+        let zero_key = [0u8; KEY_LEN];
+        for aes in both(&zero_key) {
+            assert_eq!(aes.tag(&[0; NONCE_LEN], b"", b"")[..], unhex("530f8afbc74536b9a963b4f1c4cb738b"));
+            let mut block = [0u8; BLOCK_LEN];
+            aes.xor_keystream(&[0; NONCE_LEN], 0, &mut block);
+            assert_eq!(block[..], unhex("cea7403d4d606b6e074ec5d3baf39d18"));
+            assert_eq!(aes.tag(&[0; NONCE_LEN], b"", &block)[..], unhex("d0d1c8a799996bf0265b98b5d48ab919"));
+        }
+
+        let key: [u8; KEY_LEN] = unhex("feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308").try_into().unwrap();
+        let nonce: [u8; NONCE_LEN] = unhex("cafebabefacedbaddecaf888").try_into().unwrap();
+        let aad = unhex("feedfacedeadbeeffeedfacedeadbeefabaddad2");
+        let plaintext = unhex(
+            "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a721c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39",
+        );
+        for aes in both(&key) {
+            let mut data = plaintext.clone();
+            aes.xor_keystream(&nonce, 0, &mut data);
+            assert_eq!(
+                data,
+                unhex("522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662")
+            );
+            assert_eq!(aes.tag(&nonce, &aad, &data)[..], unhex("76fc6ece0f4e1768cddf8853bb2d551b"));
+        }
+    }
+
+    #[test]
+    fn keystream_from_any_position_and_tags_in_pieces() {
+        let key = [7u8; KEY_LEN];
+        let nonce = [9u8; NONCE_LEN];
+        for aes in both(&key) {
+            let mut whole = [0u8; 300];
+            aes.xor_keystream(&nonce, 0, &mut whole);
+            for start in [1, 15, 16, 17, 63, 64, 65, 130] {
+                let mut part = [0u8; 100];
+                aes.xor_keystream(&nonce, start as u64, &mut part);
+                assert_eq!(part[..], whole[start..start + 100], "from byte {}", start);
+            }
+            for split in [0, 1, 16, 100, 299, 300] {
+                let mut tagger = aes.tagger(&nonce, b"header");
+                tagger.update(&whole[..split]);
+                tagger.update(&whole[split..]);
+                assert_eq!(tagger.finalize(), aes.tag(&nonce, b"header", &whole), "split at {}", split);
+            }
+        }
+    }
+
+    #[test]
+    fn multiply_paths_agree() {
+        #[cfg(all(feature = "std", target_arch = "x86_64"))]
+        if accelerated() {
+            let mut x = 0x0123_4567_89ab_cdef_fedc_ba98_7654_3210u128;
+            let y = 0x66e9_4bd4_ef8a_2c3b_884c_fa59_ca34_2b2eu128;
+            for _ in 0..100 {
+                // SAFETY: the CPU has PCLMULQDQ, checked above.
+                let fast = unsafe { gf_multiply_pclmul(x, y) };
+                assert_eq!(fast, gf_multiply_portable(x, y));
+                x = fast ^ (x << 3);
+            }
+        }
+    }
+}
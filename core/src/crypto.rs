@@ -0,0 +1,293 @@
+use crate::sha256::{SHA256_LEN, Sha256};
+
+/// Length of a ChaCha20 key in bytes.
+pub const KEY_LEN: usize = 32;
+
+/// Length of a ChaCha20 nonce in bytes.
+pub const NONCE_LEN: usize = 12;
+
+/// Length of an HMAC-SHA256 tag in bytes.
+pub const TAG_LEN: usize = SHA256_LEN;
+
+/// SHA-256 processes input in blocks of this many bytes.
+const SHA256_BLOCK_LEN: usize = 64;
+
+/// HMAC-SHA256 (RFC 2104) that can be fed data piece by piece.
+#[derive(Clone)]
+pub struct HmacSha256 {
+    inner: Sha256,
+    outer: Sha256,
+}
+
+impl HmacSha256 {
+    /// Start a MAC with `key`. Keys longer than a block are hashed first.
+    pub fn new(key: &[u8]) -> Self {
+        let mut block = [0u8; SHA256_BLOCK_LEN];
+        if key.len() > SHA256_BLOCK_LEN {
+            block[..SHA256_LEN].copy_from_slice(&crate::sha256::sha256(key));
+        } else {
+            block[..key.len()].copy_from_slice(key);
+        }
+
+        let mut inner = Sha256::new();
+        inner.update(&block.map(|byte| byte ^ 0x36));
+        let mut outer = Sha256::new();
+        outer.update(&block.map(|byte| byte ^ 0x5c));
+        HmacSha256 { inner, outer }
+    }
+
+    /// Feed more data into the MAC.
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    /// Finish and return the tag.
+    pub fn finalize(self) -> [u8; TAG_LEN] {
+        let mut outer = self.outer;
+        outer.update(&self.inner.finalize());
+        outer.finalize()
+    }
+}
+
+/// Compute HMAC-SHA256 of `data` in one call.
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; TAG_LEN] {
+    let mut mac = HmacSha256::new(key);
+    mac.update(data);
+    mac.finalize()
+}
+
+/// Derive `output.len()` bytes from a password with PBKDF2-HMAC-SHA256
+/// (RFC 8018).
+pub fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32, output: &mut [u8]) {
+    // Keying the MAC is the same for every block, so do it once and clone.
+    let keyed = HmacSha256::new(password);
+    for (index, chunk) in output.chunks_mut(SHA256_LEN).enumerate() {
+        let mut mac = keyed.clone();
+        mac.update(salt);
+        mac.update(&(index as u32 + 1).to_be_bytes());
+        let mut u = mac.finalize();
+        let mut block = u;
+        for _ in 1..iterations {
+            let mut mac = keyed.clone();
+            mac.update(&u);
+            u = mac.finalize();
+            for (b, x) in block.iter_mut().zip(u) {
+                *b ^= x;
+            }
+        }
+        chunk.copy_from_slice(&block[..chunk.len()]);
+    }
+}
+
+/// XOR `data` with the ChaCha20 (RFC 8439) keystream, starting `position`
+/// bytes into the stream. Encryption and decryption are the same operation,
+/// and a nonzero position lets part of a file be decrypted on its own.
+pub fn chacha20_xor(key: &[u8; KEY_LEN], nonce: &[u8; NONCE_LEN], position: u64, data: &mut [u8]) {
+    let mut state = [0u32; 16];
+    state[..4].copy_from_slice(&[0x61707865, 0x3320646e, 0x79622d32, 0x6b206574]);
+    for (word, bytes) in state[4..12].iter_mut().zip(key.chunks_exact(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    for (word, bytes) in state[13..].iter_mut().zip(nonce.chunks_exact(4)) {
+        *word = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+
+    let mut counter = (position / 64) as u32;
+    let mut skip = (position % 64) as usize;
+    let mut rest = data;
+    while !rest.is_empty() {
+        state[12] = counter;
+        let keystream = chacha20_block(&state);
+        let take = (64 - skip).min(rest.len());
+        let (now, later) = rest.split_at_mut(take);
+        xor_in_place(now, &keystream[skip..]);
+        rest = later;
+        skip = 0;
+        counter = counter.wrapping_add(1);
+    }
+}
+
+/// Produce one 64-byte block of keystream from the ChaCha20 state.
+fn chacha20_block(input: &[u32; 16]) -> [u8; 64] {
+    let mut x = *input;
+    for _ in 0..10 {
+        quarter_round(&mut x, 0, 4, 8, 12);
+        quarter_round(&mut x, 1, 5, 9, 13);
+        quarter_round(&mut x, 2, 6, 10, 14);
+        quarter_round(&mut x, 3, 7, 11, 15);
+        quarter_round(&mut x, 0, 5, 10, 15);
+        quarter_round(&mut x, 1, 6, 11, 12);
+        quarter_round(&mut x, 2, 7, 8, 13);
+        quarter_round(&mut x, 3, 4, 9, 14);
+    }
+
+    let mut output = [0u8; 64];
+    for (i, chunk) in output.chunks_exact_mut(4).enumerate() {
+        chunk.copy_from_slice(&x[i].wrapping_add(input[i]).to_le_bytes());
+    }
+    output
+}
+
+fn quarter_round(x: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize) {
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(16);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(12);
+    x[a] = x[a].wrapping_add(x[b]);
+    x[d] = (x[d] ^ x[a]).rotate_left(8);
+    x[c] = x[c].wrapping_add(x[d]);
+    x[b] = (x[b] ^ x[c]).rotate_left(7);
+}
+
+/// XOR `other` into `data`, which must be at least as long. Uses AVX2 when
+/// the CPU has it and 8 bytes at a time otherwise.
+pub fn xor_in_place(data: &mut [u8], other: &[u8]) {
+    let other = &other[..data.len()];
+    #[cfg(all(feature = "std", target_arch = "x86_64"))]
+    if std::arch::is_x86_feature_detected!("avx2") {
+        // SAFETY: the CPU supports AVX2, checked just above.
+        unsafe { xor_in_place_avx2(data, other) };
+        return;
+    }
+    xor_in_place_portable(data, other);
+}
+
+/// `other` must be exactly as long as `data`.
+fn xor_in_place_portable(data: &mut [u8], other: &[u8]) {
+    let mut words = data.chunks_exact_mut(8);
+    let mut other_words = other.chunks_exact(8);
+    for (word, other_word) in (&mut words).zip(&mut other_words) {
+        let x = u64::from_ne_bytes(word.try_into().expect("chunk is 8 bytes"))
+            ^ u64::from_ne_bytes(other_word.try_into().expect("chunk is 8 bytes"));
+        word.copy_from_slice(&x.to_ne_bytes());
+    }
+    for (byte, other_byte) in words
+        .into_remainder()
+        .iter_mut()
+        .zip(other_words.remainder())
+    {
+        *byte ^= other_byte;
+    }
+}
+
+/// `other` must be exactly as long as `data`.
+#[cfg(all(feature = "std", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+fn xor_in_place_avx2(data: &mut [u8], other: &[u8]) {
+    use core::arch::x86_64::{__m256i, _mm256_loadu_si256, _mm256_storeu_si256, _mm256_xor_si256};
+
+    let whole = data.len() - data.len() % 32;
+    for offset in (0..whole).step_by(32) {
+        // SAFETY: offset + 32 <= whole <= the length of both slices, and the
+        // unaligned load and store accept any address.
+        unsafe {
+            let a = _mm256_loadu_si256(data.as_ptr().add(offset) as *const __m256i);
+            let b = _mm256_loadu_si256(other.as_ptr().add(offset) as *const __m256i);
+            _mm256_storeu_si256(
+                data.as_mut_ptr().add(offset) as *mut __m256i,
+                _mm256_xor_si256(a, b),
+            );
+        }
+    }
+    xor_in_place_portable(&mut data[whole..], &other[whole..]);
+}
+
+/// Compare two byte strings in time that depends only on their length, so
+/// a tag check does not reveal how many leading bytes were right.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> alloc::string::String {
+        bytes.iter().map(|byte| alloc::format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn hmac_known_answers() {
+        // RFC 4231 test cases 2 and 6, the second with a key longer than
+        // a block.
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First")),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+        let mut mac = HmacSha256::new(b"Jefe");
+        mac.update(b"what do ya ");
+        mac.update(b"want for nothing?");
+        assert_eq!(mac.finalize(), hmac_sha256(b"Jefe", b"what do ya want for nothing?"));
+    }
+
+    #[test]
+    fn pbkdf2_known_answers() {
+        let mut output = [0u8; 32];
+        pbkdf2_sha256(b"password", b"salt", 1, &mut output);
+        assert_eq!(hex(&output), "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b");
+        pbkdf2_sha256(b"password", b"salt", 4096, &mut output);
+        assert_eq!(hex(&output), "c5e478d59288c841aa530db6845c4c8d962893a001ce4e11a4963873aa98134a");
+        // More than one block, the last one cut short.
+        let mut output = [0u8; 40];
+        pbkdf2_sha256(b"passwordPASSWORDpassword", b"saltSALTsaltSALTsaltSALTsaltSALTsalt", 4096, &mut output);
+        assert_eq!(
+            hex(&output),
+            "348c89dbcbd32b2f32d814b8116e84cf2b17347ebc1800181c4e2a1fb8dd53e1c635518c7dac47e9"
+        );
+    }
+
+    #[test]
+    fn chacha20_known_answer() {
+        // RFC 8439 section 2.4.2, which starts at block 1.
+        let key: [u8; KEY_LEN] = core::array::from_fn(|i| i as u8);
+        let nonce = [0, 0, 0, 0, 0, 0, 0, 0x4a, 0, 0, 0, 0];
+        let mut data = *b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.";
+        chacha20_xor(&key, &nonce, 64, &mut data);
+        assert_eq!(
+            hex(&data),
+            "6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0bf91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d807ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab77937365af90bbf74a35be6b40b8eedf2785e42874d"
+        );
+    }
+
+    #[test]
+    fn chacha20_from_any_position() {
+        let key = [7u8; KEY_LEN];
+        let nonce = [9u8; NONCE_LEN];
+        let mut whole = [0u8; 300];
+        chacha20_xor(&key, &nonce, 0, &mut whole);
+        for start in [1, 63, 64, 65, 130] {
+            let mut part = [0u8; 100];
+            chacha20_xor(&key, &nonce, start as u64, &mut part);
+            assert_eq!(part[..], whole[start..start + 100], "from byte {}", start);
+        }
+        // Encrypting twice gives the input back.
+        let mut data = *b"round trip";
+        chacha20_xor(&key, &nonce, 5, &mut data);
+        chacha20_xor(&key, &nonce, 5, &mut data);
+        assert_eq!(&data, b"round trip");
+    }
+
+    #[test]
+    fn xor_paths_agree() {
+        let other: alloc::vec::Vec<u8> = (0..100u8).collect();
+        for len in [0, 1, 7, 8, 31, 32, 33, 64, 100] {
+            let mut fast = alloc::vec![0xa5; len];
+            let mut portable = fast.clone();
+            xor_in_place(&mut fast, &other);
+            xor_in_place_portable(&mut portable, &other[..len]);
+            assert_eq!(fast, portable, "length {}", len);
+        }
+    }
+
+    #[test]
+    fn constant_time_eq_compares_lengths() {
+        assert!(constant_time_eq(b"abc", b"abc"));
+        assert!(!constant_time_eq(b"abc", b"abd"));
+        assert!(!constant_time_eq(b"abc", b"ab"));
+        assert!(constant_time_eq(b"", b""));
+    }
+}
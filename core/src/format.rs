@@ -0,0 +1,525 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::aes::GCM_TAG_LEN;
+use crate::crypto::{NONCE_LEN, TAG_LEN};
+use crate::sha256::SHA256_LEN;
+
+/// Bytes at the start of every file written by this version of the tool.
+/// Files without them were written before the header existed.
+pub const FILE_MAGIC: &[u8; 4] = b"FENC";
+
+/// Version number stored right after the magic bytes. Versions 1 and 2
+/// used the XOR cipher (version 1 without a metadata block) and can still
+/// be decrypted.
+pub const FORMAT_VERSION: u8 = 3;
+
+/// First version whose payload is encrypted with ChaCha20 and protected by
+/// an HMAC tag.
+pub const SEALED_VERSION: u8 = 3;
+
+/// Version of files whose keys are also wrapped for recipients' public
+/// keys. The body is the same as in version 3; the header adds the
+/// recipient stanzas.
+pub const RECIPIENTS_VERSION: u8 = 4;
+
+/// Version of files encrypted with AES-256-GCM instead of ChaCha20. The
+/// header always carries the recipient count of version 4, which may be
+/// zero, and the tag at the end is GCM's.
+pub const AES_GCM_VERSION: u8 = 5;
+
+/// Length of one recipient stanza: the recipient's public key, an
+/// ephemeral X25519 key, the wrapped file keys, and a tag.
+pub const STANZA_LEN: usize = 32 + 32 + 64 + TAG_LEN;
+
+/// Length of the plain (unencrypted) part of the header.
+pub const HEADER_LEN: usize = FILE_MAGIC.len() + 1;
+
+/// Length of the metadata length field in version 2 and later.
+pub const METADATA_LEN_FIELD: usize = 4;
+
+/// Largest metadata block accepted. Real blocks hold an owner and a few
+/// extended attributes, so anything bigger means a wrong password or a
+/// damaged file.
+pub const MAX_METADATA_LEN: usize = 1 << 20;
+
+/// Length of the random salt fed to the key derivation.
+pub const SALT_LEN: usize = 16;
+
+/// PBKDF2 iterations used for new files.
+pub const DEFAULT_ITERATIONS: u32 = 200_000;
+
+/// Most PBKDF2 iterations accepted from a file, so a crafted header cannot
+/// make opening it take hours.
+pub const MAX_ITERATIONS: u32 = 10_000_000;
+
+/// Length of the sealed header: magic, version, iteration count, salt, and
+/// nonce. All of it is covered by the tag.
+pub const SEALED_HEADER_LEN: usize = HEADER_LEN + 4 + SALT_LEN + NONCE_LEN;
+
+/// Most bytes one file can hold once encrypted: ChaCha20 counts 64-byte
+/// blocks in 32 bits, and a longer payload would reuse keystream.
+pub const MAX_PAYLOAD_LEN: u64 = 64 << 32;
+
+/// Why an encrypted file could not be read. Every length and offset in a
+/// file is treated as untrusted, so malformed input ends up here instead
+/// of causing a panic or a huge allocation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FormatError {
+    /// The data ends before a field it claims to have.
+    Truncated,
+    /// The header names a version this build does not know.
+    UnsupportedVersion(u8),
+    /// The contents do not add up: the stored checksum does not match, or a
+    /// length points past the end. Usually a wrong password.
+    Garbled,
+    /// The metadata block claims to be larger than `MAX_METADATA_LEN`.
+    MetadataTooLarge(usize),
+    /// A record inside the metadata block is malformed.
+    BadRecord,
+    /// The header asks for an iteration count outside the accepted range.
+    BadIterations(u32),
+    /// The tag does not match: a wrong password or a modified file.
+    AuthenticationFailed,
+    /// A length is too large to hold in memory on this system, as with a
+    /// file over 4 GiB written on a 64-bit machine and opened on a 32-bit
+    /// one.
+    TooLarge(u64),
+}
+
+impl fmt::Display for FormatError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormatError::Truncated => write!(f, "The file is too short to be a valid encrypted file."),
+            FormatError::UnsupportedVersion(version) => write!(f, "Unsupported file format version {}.", version),
+            FormatError::Garbled => write!(f, "Checksum mismatch: the password is wrong or the file is damaged."),
+            FormatError::MetadataTooLarge(len) => write!(
+                f,
+                "The metadata block claims {} bytes, more than the {} allowed. The password is wrong or the file is damaged.",
+                len, MAX_METADATA_LEN
+            ),
+            FormatError::BadRecord => write!(f, "The file's metadata block is damaged."),
+            FormatError::BadIterations(iterations) => write!(
+                f,
+                "The file asks for {} key derivation iterations, outside the accepted 1 to {}.",
+                iterations, MAX_ITERATIONS
+            ),
+            FormatError::AuthenticationFailed => {
+                write!(f, "Authentication failed: the password is wrong or the file was modified.")
+            }
+            FormatError::TooLarge(len) => write!(
+                f,
+                "The file holds a part of {} bytes, more than a {}-bit system can hold in memory. Open it on a 64-bit machine.",
+                len,
+                usize::BITS
+            ),
+        }
+    }
+}
+
+/// The parts of a decrypted payload, borrowed from it.
+pub struct Payload<'a> {
+    pub checksum: &'a [u8],
+    pub metadata: &'a [u8],
+    pub plaintext: &'a [u8],
+}
+
+/// Bounds-checked reader over serialized bytes. Every field in the formats
+/// is a fixed width and little-endian, whatever the machine, and lengths
+/// are checked against both the data and the size of `usize`, so files
+/// written on a 64-bit machine read the same on a 32-bit or big-endian
+/// one, or fail with an error.
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    /// Everything not read yet.
+    pub fn rest(&self) -> &'a [u8] {
+        &self.data[self.pos.min(self.data.len())..]
+    }
+
+    pub fn take(&mut self, len: usize) -> Result<&'a [u8], FormatError> {
+        let end = self.pos.checked_add(len).ok_or(FormatError::Truncated)?;
+        let bytes = self.data.get(self.pos..end).ok_or(FormatError::Truncated)?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> Result<[u8; N], FormatError> {
+        Ok(self.take(N)?.try_into().expect("length checked by take"))
+    }
+
+    pub fn u8(&mut self) -> Result<u8, FormatError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, FormatError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, FormatError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, FormatError> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    /// Read a 64-bit length, failing with `TooLarge` if it does not fit in
+    /// this machine's `usize`.
+    pub fn len64(&mut self) -> Result<usize, FormatError> {
+        let len = self.u64()?;
+        usize::try_from(len).map_err(|_| FormatError::TooLarge(len))
+    }
+
+    /// Read a 32-bit length followed by that many bytes.
+    pub fn sized_bytes(&mut self) -> Result<&'a [u8], FormatError> {
+        let len = self.u32()?;
+        self.take(usize::try_from(len).map_err(|_| FormatError::TooLarge(len.into()))?)
+    }
+}
+
+/// Return the format version and the encrypted payload after the header,
+/// or `None` when the data has no header because it was written before
+/// headers were added.
+pub fn split_header(data: &[u8]) -> Result<Option<(u8, &[u8])>, FormatError> {
+    if data.len() < HEADER_LEN || !data.starts_with(FILE_MAGIC) {
+        return Ok(None);
+    }
+    let version = data[FILE_MAGIC.len()];
+    if version == 0 || version > AES_GCM_VERSION {
+        return Err(FormatError::UnsupportedVersion(version));
+    }
+    Ok(Some((version, &data[HEADER_LEN..])))
+}
+
+/// The cipher of a sealed file.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Cipher {
+    /// ChaCha20 with an HMAC-SHA256 tag, in versions 3 and 4.
+    ChaCha20,
+    /// AES-256-GCM, in version 5.
+    AesGcm,
+}
+
+impl Cipher {
+    pub fn name(self) -> &'static str {
+        match self {
+            Cipher::ChaCha20 => "ChaCha20",
+            Cipher::AesGcm => "AES-256-GCM",
+        }
+    }
+
+    /// Length of the tag at the end of a file sealed with this cipher.
+    pub fn tag_len(self) -> usize {
+        match self {
+            Cipher::ChaCha20 => TAG_LEN,
+            Cipher::AesGcm => GCM_TAG_LEN,
+        }
+    }
+}
+
+/// Key derivation settings and nonce stored in a sealed header.
+#[derive(Clone)]
+pub struct SealParams {
+    pub iterations: u32,
+    pub salt: [u8; SALT_LEN],
+    pub nonce: [u8; NONCE_LEN],
+    /// Recipient stanzas. ChaCha20 files with any are written as version 4.
+    pub stanzas: Vec<[u8; STANZA_LEN]>,
+    pub cipher: Cipher,
+}
+
+impl SealParams {
+    /// The version of the header these settings produce.
+    pub fn version(&self) -> u8 {
+        match self.cipher {
+            Cipher::AesGcm => AES_GCM_VERSION,
+            Cipher::ChaCha20 if self.stanzas.is_empty() => SEALED_VERSION,
+            Cipher::ChaCha20 => RECIPIENTS_VERSION,
+        }
+    }
+
+    /// Length of the header these settings produce.
+    pub fn header_len(&self) -> usize {
+        if self.version() == SEALED_VERSION {
+            SEALED_HEADER_LEN
+        } else {
+            SEALED_HEADER_LEN + 1 + self.stanzas.len() * STANZA_LEN
+        }
+    }
+}
+
+/// The parts of a sealed (version 3 and later) file, borrowed from it.
+pub struct Sealed<'a> {
+    pub params: SealParams,
+    /// Everything the tag covers: the header and the ciphertext.
+    pub authenticated: &'a [u8],
+    pub ciphertext: &'a [u8],
+    pub tag: &'a [u8],
+}
+
+/// Write the sealed header for `params`.
+pub fn sealed_header(params: &SealParams) -> Vec<u8> {
+    let mut header = Vec::with_capacity(params.header_len());
+    header.extend_from_slice(FILE_MAGIC);
+    header.push(params.version());
+    header.extend_from_slice(&params.iterations.to_le_bytes());
+    header.extend_from_slice(&params.salt);
+    header.extend_from_slice(&params.nonce);
+    if params.version() != SEALED_VERSION {
+        header.push(params.stanzas.len() as u8);
+        for stanza in &params.stanzas {
+            header.extend_from_slice(stanza);
+        }
+    }
+    header
+}
+
+/// Read the settings from a sealed header at the start of `data`.
+pub fn parse_sealed_header(data: &[u8]) -> Result<SealParams, FormatError> {
+    let mut reader = Reader::new(data);
+    reader.take(FILE_MAGIC.len())?;
+    let version = reader.u8()?;
+    let iterations = reader.u32()?;
+    let salt = reader.array()?;
+    let nonce = reader.array()?;
+    if iterations == 0 || iterations > MAX_ITERATIONS {
+        return Err(FormatError::BadIterations(iterations));
+    }
+
+    let mut stanzas = Vec::new();
+    if version != SEALED_VERSION {
+        for _ in 0..reader.u8()? {
+            stanzas.push(reader.array()?);
+        }
+    }
+    Ok(SealParams {
+        iterations,
+        salt,
+        nonce,
+        stanzas,
+        cipher: if version == AES_GCM_VERSION { Cipher::AesGcm } else { Cipher::ChaCha20 },
+    })
+}
+
+/// Split a whole sealed file into its header settings, ciphertext, and tag.
+pub fn split_sealed(data: &[u8]) -> Result<Sealed<'_>, FormatError> {
+    let params = parse_sealed_header(data)?;
+    let header_len = params.header_len();
+    let tag_len = params.cipher.tag_len();
+    if data.len() < header_len + tag_len {
+        return Err(FormatError::Truncated);
+    }
+    let (authenticated, tag) = data.split_at(data.len() - tag_len);
+    Ok(Sealed {
+        params,
+        authenticated,
+        ciphertext: &authenticated[header_len..],
+        tag,
+    })
+}
+
+/// Lay out the payload before encryption: the SHA-256 of the plaintext, the
+/// metadata block with its length, and the plaintext.
+pub fn build_payload(data: &[u8], metadata: &[u8], checksum: &[u8]) -> Vec<u8> {
+    let mut payload =
+        Vec::with_capacity(checksum.len() + METADATA_LEN_FIELD + metadata.len() + data.len());
+    payload.extend_from_slice(checksum);
+    payload.extend_from_slice(&(metadata.len() as u32).to_le_bytes());
+    payload.extend_from_slice(metadata);
+    payload.extend_from_slice(data);
+    payload
+}
+
+/// Split a decrypted payload into its checksum, metadata block, and
+/// plaintext. Version 1 payloads have no metadata block.
+pub fn parse_payload(version: u8, decrypted: &[u8]) -> Result<Payload<'_>, FormatError> {
+    let (checksum, mut rest) = decrypted
+        .split_at_checked(SHA256_LEN)
+        .ok_or(FormatError::Truncated)?;
+
+    let mut metadata: &[u8] = &[];
+    if version >= 2 {
+        // With a wrong password the length is random, so an oversized
+        // length or one past the end is reported like a checksum mismatch.
+        let len = match metadata_len(rest) {
+            Err(FormatError::MetadataTooLarge(_)) => return Err(FormatError::Garbled),
+            other => other?,
+        };
+        let after_len = &rest[METADATA_LEN_FIELD..];
+        let (block, plaintext) = after_len
+            .split_at_checked(len)
+            .ok_or(FormatError::Garbled)?;
+        metadata = block;
+        rest = plaintext;
+    }
+
+    Ok(Payload {
+        checksum,
+        metadata,
+        plaintext: rest,
+    })
+}
+
+/// Read the metadata length field at the start of `bytes`, rejecting
+/// lengths above `MAX_METADATA_LEN`.
+pub fn metadata_len(bytes: &[u8]) -> Result<usize, FormatError> {
+    let len = Reader::new(bytes).u32()?;
+    match usize::try_from(len) {
+        Ok(len) if len <= MAX_METADATA_LEN => Ok(len),
+        _ => Err(FormatError::MetadataTooLarge(len as usize)),
+    }
+}
+
+/// Append one tagged record to a metadata block.
+pub fn push_record(bytes: &mut Vec<u8>, tag: u8, body: &[u8]) {
+    bytes.push(tag);
+    bytes.extend_from_slice(&(body.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(body);
+}
+
+/// Split a metadata block into its tagged records.
+pub fn read_records(bytes: &[u8]) -> Result<Vec<(u8, &[u8])>, FormatError> {
+    let mut reader = Reader::new(bytes);
+    let mut records = Vec::new();
+    while !reader.is_empty() {
+        let tag = reader.u8().map_err(|_| FormatError::BadRecord)?;
+        let body = reader.sized_bytes().map_err(|_| FormatError::BadRecord)?;
+        records.push((tag, body));
+    }
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn params(stanzas: usize, cipher: Cipher) -> SealParams {
+        SealParams {
+            iterations: 1000,
+            salt: [1; SALT_LEN],
+            nonce: [2; NONCE_LEN],
+            stanzas: alloc::vec![[3; STANZA_LEN]; stanzas],
+            cipher,
+        }
+    }
+
+    #[test]
+    fn reader_refuses_reading_past_the_end() {
+        let mut reader = Reader::new(&[1, 2, 3]);
+        assert_eq!(reader.u16(), Ok(0x0201));
+        assert_eq!(reader.u16(), Err(FormatError::Truncated));
+        assert_eq!(reader.take(usize::MAX), Err(FormatError::Truncated));
+        assert_eq!(reader.u8(), Ok(3));
+        assert!(reader.is_empty());
+        // A length field claiming more than there is.
+        assert_eq!(Reader::new(&[9, 0, 0, 0, 1]).sized_bytes(), Err(FormatError::Truncated));
+        assert_eq!(Reader::new(&[0xff; 8]).take(1).map(|bytes| bytes.len()), Ok(1));
+    }
+
+    #[test]
+    fn split_header_versions() {
+        assert_eq!(split_header(b"plain old data"), Ok(None));
+        assert_eq!(split_header(b"FENC\x03rest"), Ok(Some((3, &b"rest"[..]))));
+        assert_eq!(split_header(b"FENC\x00"), Err(FormatError::UnsupportedVersion(0)));
+        assert_eq!(split_header(b"FENC\x05rest"), Ok(Some((5, &b"rest"[..]))));
+        assert_eq!(split_header(b"FENC\x09"), Err(FormatError::UnsupportedVersion(9)));
+    }
+
+    #[test]
+    fn sealed_header_round_trip() {
+        for (stanzas, cipher, version) in [
+            (0, Cipher::ChaCha20, SEALED_VERSION),
+            (2, Cipher::ChaCha20, RECIPIENTS_VERSION),
+            (0, Cipher::AesGcm, AES_GCM_VERSION),
+            (2, Cipher::AesGcm, AES_GCM_VERSION),
+        ] {
+            let params = params(stanzas, cipher);
+            let header = sealed_header(&params);
+            assert_eq!(header.len(), params.header_len());
+            assert_eq!(header[FILE_MAGIC.len()], version);
+            let parsed = parse_sealed_header(&header).unwrap();
+            assert_eq!(parsed.cipher, cipher);
+            assert_eq!(parsed.iterations, params.iterations);
+            assert_eq!(parsed.salt, params.salt);
+            assert_eq!(parsed.nonce, params.nonce);
+            assert_eq!(parsed.stanzas, params.stanzas);
+            // Every cut of the header is refused rather than misread.
+            for len in 0..header.len() {
+                assert!(parse_sealed_header(&header[..len]).is_err(), "{} bytes of {}", len, header.len());
+            }
+        }
+    }
+
+    #[test]
+    fn hostile_headers() {
+        let mut header = sealed_header(&params(0, Cipher::ChaCha20));
+        header[HEADER_LEN..HEADER_LEN + 4].copy_from_slice(&0u32.to_le_bytes());
+        assert!(matches!(parse_sealed_header(&header), Err(FormatError::BadIterations(0))));
+        header[HEADER_LEN..HEADER_LEN + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(matches!(parse_sealed_header(&header), Err(FormatError::BadIterations(u32::MAX))));
+
+        // A stanza count with no stanzas after it.
+        let mut header = sealed_header(&params(1, Cipher::ChaCha20));
+        header[SEALED_HEADER_LEN] = 255;
+        assert_eq!(parse_sealed_header(&header).err(), Some(FormatError::Truncated));
+
+        // A header with no room for the tag.
+        for cipher in [Cipher::ChaCha20, Cipher::AesGcm] {
+            let mut short = sealed_header(&params(0, cipher));
+            short.resize(short.len() + cipher.tag_len() - 1, 0);
+            assert_eq!(split_sealed(&short).err(), Some(FormatError::Truncated));
+        }
+    }
+
+    #[test]
+    fn payload_round_trip() {
+        let payload = build_payload(b"plaintext", b"meta", &[7; SHA256_LEN]);
+        let parts = parse_payload(SEALED_VERSION, &payload).unwrap();
+        assert_eq!(parts.checksum, &[7; SHA256_LEN]);
+        assert_eq!(parts.metadata, b"meta");
+        assert_eq!(parts.plaintext, b"plaintext");
+        // Version 1 has no metadata block.
+        let parts = parse_payload(1, &payload).unwrap();
+        assert_eq!(parts.plaintext.len(), payload.len() - SHA256_LEN);
+    }
+
+    #[test]
+    fn hostile_payloads() {
+        assert_eq!(parse_payload(SEALED_VERSION, &[0; 10]).err(), Some(FormatError::Truncated));
+        // A metadata length past the end or over the limit reads as a
+        // wrong password.
+        let mut payload = build_payload(b"", b"meta", &[0; SHA256_LEN]);
+        payload[SHA256_LEN..SHA256_LEN + 4].copy_from_slice(&100u32.to_le_bytes());
+        assert_eq!(parse_payload(SEALED_VERSION, &payload).err(), Some(FormatError::Garbled));
+        payload[SHA256_LEN..SHA256_LEN + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(parse_payload(SEALED_VERSION, &payload).err(), Some(FormatError::Garbled));
+        assert!(matches!(metadata_len(&u32::MAX.to_le_bytes()), Err(FormatError::MetadataTooLarge(_))));
+    }
+
+    #[test]
+    fn records_round_trip_and_refuse_damage() {
+        let mut bytes = Vec::new();
+        push_record(&mut bytes, 1, b"first");
+        push_record(&mut bytes, 2, b"");
+        assert_eq!(read_records(&bytes), Ok(alloc::vec![(1, &b"first"[..]), (2, &b""[..])]));
+        for len in 1..bytes.len() {
+            if len == 10 {
+                // Cut right after the first record, which is still whole.
+                continue;
+            }
+            assert_eq!(read_records(&bytes[..len]), Err(FormatError::BadRecord), "{} bytes", len);
+        }
+    }
+}
@@ -0,0 +1,14 @@
+//! The ciphers, key derivation, and file format of File Encryptor, and
+//! sealing and opening files with them, shared by the desktop tool and by
+//! firmware that reads and writes the same files. Without the default
+//! `std` feature it builds for `no_std` targets that have an allocator.
+
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+pub mod aes;
+pub mod crypto;
+pub mod format;
+pub mod seal;
+pub mod sha256;
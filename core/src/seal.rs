@@ -0,0 +1,240 @@
+use alloc::vec::Vec;
+
+use crate::aes::{Aes256Gcm, GcmTagger};
+use crate::crypto::{self, KEY_LEN};
+use crate::format::{self, Cipher, FormatError, SEALED_VERSION, SealParams, Sealed};
+use crate::sha256::sha256;
+
+/// Keys for the cipher and the tag, derived from the password.
+#[derive(Clone)]
+pub struct Keys {
+    pub cipher: [u8; KEY_LEN],
+    pub mac: [u8; KEY_LEN],
+}
+
+impl Drop for Keys {
+    fn drop(&mut self) {
+        // Keys may be kept for a while, so wipe them once they go.
+        self.cipher.fill(0);
+        self.mac.fill(0);
+        core::hint::black_box(&mut *self);
+    }
+}
+
+/// Run the password through PBKDF2 with the salt and iteration count in
+/// `params` and split the result into two keys.
+pub fn derive_keys(password: &[u8], params: &SealParams) -> Keys {
+    let mut derived = [0u8; 2 * KEY_LEN];
+    crypto::pbkdf2_sha256(password, &params.salt, params.iterations, &mut derived);
+    let mut keys = Keys {
+        cipher: [0; KEY_LEN],
+        mac: [0; KEY_LEN],
+    };
+    keys.cipher.copy_from_slice(&derived[..KEY_LEN]);
+    keys.mac.copy_from_slice(&derived[KEY_LEN..]);
+    derived.fill(0);
+    keys
+}
+
+/// Encrypt with the given settings: the header, followed by the SHA-256 of
+/// the plaintext, the metadata block, and the plaintext encrypted with the
+/// cipher in `params`, and finally a tag over everything before it.
+///
+/// The nonce in `params` must be fresh for every file sealed with the
+/// same keys. The caller also keeps `data` and `metadata` together under
+/// `format::MAX_PAYLOAD_LEN`.
+pub fn seal(data: &[u8], metadata: &[u8], password: &[u8], params: &SealParams) -> Vec<u8> {
+    seal_with_keys(data, metadata, &derive_keys(password, params), params)
+}
+
+/// Encrypt as `seal` does, with keys already derived from the password.
+pub fn seal_with_keys(data: &[u8], metadata: &[u8], keys: &Keys, params: &SealParams) -> Vec<u8> {
+    let mut payload = format::build_payload(data, metadata, &sha256(data));
+    xor_keystream(params, keys, 0, &mut payload);
+
+    let mut sealed = format::sealed_header(params);
+    let mut tagger = Tagger::new(params, keys, &sealed);
+    tagger.update(&payload);
+    sealed.reserve(payload.len() + params.cipher.tag_len());
+    sealed.extend_from_slice(&payload);
+    sealed.extend_from_slice(&tagger.finalize());
+    sealed
+}
+
+/// XOR `data` with the keystream of the cipher in `params`, starting
+/// `position` bytes into the payload.
+pub fn xor_keystream(params: &SealParams, keys: &Keys, position: u64, data: &mut [u8]) {
+    match params.cipher {
+        Cipher::ChaCha20 => crypto::chacha20_xor(&keys.cipher, &params.nonce, position, data),
+        Cipher::AesGcm => Aes256Gcm::new(&keys.cipher).xor_keystream(&params.nonce, position, data),
+    }
+}
+
+/// The tag of a sealed file, fed its ciphertext piece by piece: an HMAC
+/// over the header and the ciphertext, or GCM's with the header as the
+/// associated data.
+pub enum Tagger {
+    Hmac(crypto::HmacSha256),
+    Gcm(GcmTagger),
+}
+
+impl Tagger {
+    pub fn new(params: &SealParams, keys: &Keys, header: &[u8]) -> Self {
+        match params.cipher {
+            Cipher::ChaCha20 => {
+                let mut mac = crypto::HmacSha256::new(&keys.mac);
+                mac.update(header);
+                Tagger::Hmac(mac)
+            }
+            Cipher::AesGcm => Tagger::Gcm(Aes256Gcm::new(&keys.cipher).tagger(&params.nonce, header)),
+        }
+    }
+
+    pub fn update(&mut self, ciphertext: &[u8]) {
+        match self {
+            Tagger::Hmac(mac) => mac.update(ciphertext),
+            Tagger::Gcm(tagger) => tagger.update(ciphertext),
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Tagger::Hmac(mac) => mac.finalize().to_vec(),
+            Tagger::Gcm(tagger) => tagger.finalize().to_vec(),
+        }
+    }
+
+    /// Whether the tag matches `tag`, compared in constant time.
+    pub fn matches(self, tag: &[u8]) -> bool {
+        crypto::constant_time_eq(&self.finalize(), tag)
+    }
+}
+
+/// The plaintext of a sealed file and its metadata block, still in the
+/// encoded form `format::read_records` splits.
+pub struct Unsealed {
+    pub plaintext: Vec<u8>,
+    pub metadata: Vec<u8>,
+}
+
+/// Check the tag of a split sealed file with `keys`, then decrypt it and
+/// check the stored checksum.
+pub fn unseal(sealed: &Sealed<'_>, keys: &Keys) -> Result<Unsealed, FormatError> {
+    check_tag(sealed, keys)?;
+    decrypt(sealed, keys)
+}
+
+/// Check the tag of a split sealed file with `keys`. A mismatch means a
+/// wrong password or a modified file.
+pub fn check_tag(sealed: &Sealed<'_>, keys: &Keys) -> Result<(), FormatError> {
+    let header = &sealed.authenticated[..sealed.params.header_len()];
+    let mut tagger = Tagger::new(&sealed.params, keys, header);
+    tagger.update(sealed.ciphertext);
+    if !tagger.matches(sealed.tag) {
+        return Err(FormatError::AuthenticationFailed);
+    }
+    Ok(())
+}
+
+/// Decrypt a split sealed file whose tag `check_tag` accepted, and check
+/// the stored checksum.
+pub fn decrypt(sealed: &Sealed<'_>, keys: &Keys) -> Result<Unsealed, FormatError> {
+    let mut decrypted = sealed.ciphertext.to_vec();
+    xor_keystream(&sealed.params, keys, 0, &mut decrypted);
+    let parts = format::parse_payload(SEALED_VERSION, &decrypted)?;
+    if sha256(parts.plaintext)[..] != parts.checksum[..] {
+        return Err(FormatError::Garbled);
+    }
+    Ok(Unsealed {
+        plaintext: parts.plaintext.to_vec(),
+        metadata: parts.metadata.to_vec(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::NONCE_LEN;
+    use crate::format::{SALT_LEN, split_sealed};
+
+    const CIPHERS: [Cipher; 2] = [Cipher::ChaCha20, Cipher::AesGcm];
+
+    fn params() -> SealParams {
+        SealParams {
+            iterations: 10,
+            salt: [4; SALT_LEN],
+            nonce: [5; NONCE_LEN],
+            stanzas: Vec::new(),
+            cipher: Cipher::ChaCha20,
+        }
+    }
+
+    fn params_for(cipher: Cipher) -> SealParams {
+        SealParams { cipher, ..params() }
+    }
+
+    #[test]
+    fn round_trip() {
+        for cipher in CIPHERS {
+            let params = params_for(cipher);
+            let sealed = seal(b"secret data", b"meta", b"password", &params);
+            assert_eq!(sealed.len(), params.header_len() + 32 + 4 + 4 + 11 + cipher.tag_len());
+            let opened = unseal(&split_sealed(&sealed).unwrap(), &derive_keys(b"password", &params)).unwrap();
+            assert_eq!(opened.plaintext, b"secret data");
+            assert_eq!(opened.metadata, b"meta");
+        }
+    }
+
+    #[test]
+    fn wrong_password_and_tampering_fail() {
+        for cipher in CIPHERS {
+            let params = params_for(cipher);
+            let sealed = seal(b"secret data", b"", b"password", &params);
+            let keys = derive_keys(b"password", &params);
+            let wrong = derive_keys(b"passw0rd", &params);
+            assert_eq!(
+                unseal(&split_sealed(&sealed).unwrap(), &wrong).err(),
+                Some(FormatError::AuthenticationFailed)
+            );
+            for position in [0, 5, sealed.len() / 2, sealed.len() - 1] {
+                let mut damaged = sealed.clone();
+                damaged[position] ^= 1;
+                let result =
+                    split_sealed(&damaged).map_err(|_| ()).and_then(|split| unseal(&split, &keys).map_err(|_| ()));
+                assert!(result.is_err(), "flipped a bit at {} with {}", position, cipher.name());
+            }
+            assert!(split_sealed(&sealed[..sealed.len() - 1]).and_then(|split| unseal(&split, &keys)).is_err());
+        }
+    }
+
+    #[test]
+    fn gcm_files_are_plain_aes_gcm() {
+        // The body and tag are what any AES-256-GCM implementation gives
+        // for the payload with the header as associated data.
+        let params = params_for(Cipher::AesGcm);
+        let keys = derive_keys(b"password", &params);
+        let sealed = seal(b"data", b"", b"password", &params);
+        let split = split_sealed(&sealed).unwrap();
+        let aes = Aes256Gcm::new(&keys.cipher);
+        let header = &sealed[..params.header_len()];
+        assert_eq!(split.tag, aes.tag(&params.nonce, header, split.ciphertext));
+        let mut payload = split.ciphertext.to_vec();
+        aes.xor_keystream(&params.nonce, 0, &mut payload);
+        assert_eq!(payload, format::build_payload(b"data", b"", &sha256(b"data")));
+    }
+
+    #[test]
+    fn checksum_is_checked_after_the_tag() {
+        // A file whose tag matches but whose stored checksum does not, as
+        // from a writer with a bug, is still refused.
+        let params = params();
+        let keys = derive_keys(b"password", &params);
+        let mut payload = format::build_payload(b"data", b"", &[0; crate::sha256::SHA256_LEN]);
+        crypto::chacha20_xor(&keys.cipher, &params.nonce, 0, &mut payload);
+        let mut sealed = format::sealed_header(&params);
+        sealed.extend_from_slice(&payload);
+        let tag = crypto::hmac_sha256(&keys.mac, &sealed);
+        sealed.extend_from_slice(&tag);
+        assert_eq!(unseal(&split_sealed(&sealed).unwrap(), &keys).err(), Some(FormatError::Garbled));
+    }
+}
@@ -0,0 +1,292 @@
+/// Size of a SHA-256 digest in bytes.
+pub const SHA256_LEN: usize = 32;
+
+/// Round constants from the SHA-256 specification (FIPS 180-4).
+const K: [u32; 64] = [
+    0x428a2f98, 0x71374491, 0xb5c0fbcf, 0xe9b5dba5, 0x3956c25b, 0x59f111f1, 0x923f82a4, 0xab1c5ed5,
+    0xd807aa98, 0x12835b01, 0x243185be, 0x550c7dc3, 0x72be5d74, 0x80deb1fe, 0x9bdc06a7, 0xc19bf174,
+    0xe49b69c1, 0xefbe4786, 0x0fc19dc6, 0x240ca1cc, 0x2de92c6f, 0x4a7484aa, 0x5cb0a9dc, 0x76f988da,
+    0x983e5152, 0xa831c66d, 0xb00327c8, 0xbf597fc7, 0xc6e00bf3, 0xd5a79147, 0x06ca6351, 0x14292967,
+    0x27b70a85, 0x2e1b2138, 0x4d2c6dfc, 0x53380d13, 0x650a7354, 0x766a0abb, 0x81c2c92e, 0x92722c85,
+    0xa2bfe8a1, 0xa81a664b, 0xc24b8b70, 0xc76c51a3, 0xd192e819, 0xd6990624, 0xf40e3585, 0x106aa070,
+    0x19a4c116, 0x1e376c08, 0x2748774c, 0x34b0bcb5, 0x391c0cb3, 0x4ed8aa4a, 0x5b9cca4f, 0x682e6ff3,
+    0x748f82ee, 0x78a5636f, 0x84c87814, 0x8cc70208, 0x90befffa, 0xa4506ceb, 0xbef9a3f7, 0xc67178f2,
+];
+
+/// Initial hash values from the SHA-256 specification, which BLAKE3 also
+/// uses as its IV.
+pub const INITIAL_STATE: [u32; 8] = [
+    0x6a09e667, 0xbb67ae85, 0x3c6ef372, 0xa54ff53a, 0x510e527f, 0x9b05688c, 0x1f83d9ab, 0x5be0cd19,
+];
+
+/// Incremental SHA-256 hasher, so large inputs can be hashed piece by piece.
+#[derive(Clone)]
+pub struct Sha256 {
+    state: [u32; 8],
+    buffer: [u8; 64],
+    buffer_len: usize,
+    total_len: u64,
+}
+
+impl Default for Sha256 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sha256 {
+    /// Create a hasher with no data fed into it yet.
+    pub fn new() -> Self {
+        Sha256 {
+            state: INITIAL_STATE,
+            buffer: [0; 64],
+            buffer_len: 0,
+            total_len: 0,
+        }
+    }
+
+    /// Feed more data into the hash.
+    pub fn update(&mut self, mut data: &[u8]) {
+        self.total_len = self.total_len.wrapping_add(data.len() as u64);
+
+        if self.buffer_len > 0 {
+            let take = (64 - self.buffer_len).min(data.len());
+            self.buffer[self.buffer_len..self.buffer_len + take].copy_from_slice(&data[..take]);
+            self.buffer_len += take;
+            data = &data[take..];
+            if self.buffer_len < 64 {
+                return;
+            }
+            let block = self.buffer;
+            self.compress_blocks(&block);
+            self.buffer_len = 0;
+        }
+
+        let whole = data.len() - data.len() % 64;
+        self.compress_blocks(&data[..whole]);
+
+        let rest = &data[whole..];
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffer_len = rest.len();
+    }
+
+    /// Finish the hash and return the digest.
+    pub fn finalize(mut self) -> [u8; SHA256_LEN] {
+        let bit_len = self.total_len.wrapping_mul(8);
+
+        let mut padding = [0u8; 72];
+        padding[0] = 0x80;
+        let pad_len = if self.buffer_len < 56 {
+            56 - self.buffer_len
+        } else {
+            120 - self.buffer_len
+        };
+        padding[pad_len..pad_len + 8].copy_from_slice(&bit_len.to_be_bytes());
+
+        // `update` would count the padding towards the length, so restore it.
+        let total_len = self.total_len;
+        self.update(&padding[..pad_len + 8]);
+        self.total_len = total_len;
+
+        let mut digest = [0u8; SHA256_LEN];
+        for (word, out) in self.state.iter().zip(digest.chunks_exact_mut(4)) {
+            out.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    /// Run the compression function over each 64 byte block of `blocks`,
+    /// using the CPU's SHA extensions when it has them.
+    fn compress_blocks(&mut self, blocks: &[u8]) {
+        #[cfg(all(feature = "std", target_arch = "x86_64"))]
+        if std::arch::is_x86_feature_detected!("sha")
+            && std::arch::is_x86_feature_detected!("sse4.1")
+        {
+            // SAFETY: the CPU supports SHA, SSE4.1, and (implied by those)
+            // SSSE3 and SSE2, checked just above.
+            unsafe { compress_blocks_sha_ni(&mut self.state, blocks) };
+            return;
+        }
+        for block in blocks.chunks_exact(64) {
+            self.compress(block.try_into().expect("chunk is 64 bytes"));
+        }
+    }
+
+    /// Run the compression function over one 64 byte block.
+    fn compress(&mut self, block: &[u8; 64]) {
+        let mut w = [0u32; 64];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..64 {
+            let s0 = w[i - 15].rotate_right(7) ^ w[i - 15].rotate_right(18) ^ (w[i - 15] >> 3);
+            let s1 = w[i - 2].rotate_right(17) ^ w[i - 2].rotate_right(19) ^ (w[i - 2] >> 10);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for i in 0..64 {
+            let s1 = e.rotate_right(6) ^ e.rotate_right(11) ^ e.rotate_right(25);
+            let ch = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(K[i])
+                .wrapping_add(w[i]);
+            let s0 = a.rotate_right(2) ^ a.rotate_right(13) ^ a.rotate_right(22);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(maj);
+
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+
+        for (state, value) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *state = state.wrapping_add(value);
+        }
+    }
+}
+
+/// The compression function built on the x86 SHA extensions, following
+/// Intel's reference code. The instructions want the state split as ABEF
+/// and CDGH, and each `sha256rnds2` runs two rounds.
+#[cfg(all(feature = "std", target_arch = "x86_64"))]
+#[target_feature(enable = "sha,sse2,ssse3,sse4.1")]
+fn compress_blocks_sha_ni(state: &mut [u32; 8], blocks: &[u8]) {
+    use core::arch::x86_64::*;
+
+    // SAFETY: every load and store is unaligned and stays inside `state`,
+    // `K`, or the current 64 byte block.
+    unsafe {
+        let byte_swap = _mm_set_epi64x(0x0c0d0e0f08090a0b, 0x0405060700010203);
+        let abcd = _mm_shuffle_epi32(_mm_loadu_si128(state.as_ptr() as *const __m128i), 0xb1);
+        let efgh = _mm_shuffle_epi32(
+            _mm_loadu_si128(state.as_ptr().add(4) as *const __m128i),
+            0x1b,
+        );
+        let mut abef = _mm_alignr_epi8(abcd, efgh, 8);
+        let mut cdgh = _mm_blend_epi16(efgh, abcd, 0xf0);
+
+        for block in blocks.chunks_exact(64) {
+            let (abef_saved, cdgh_saved) = (abef, cdgh);
+            let mut w = [_mm_setzero_si128(); 4];
+
+            // Sixteen groups of four rounds. The message schedule for later
+            // groups is built up from the four previous ones as it goes.
+            for group in 0..16 {
+                if group < 4 {
+                    let words = _mm_loadu_si128(block.as_ptr().add(group * 16) as *const __m128i);
+                    w[group] = _mm_shuffle_epi8(words, byte_swap);
+                }
+                let k = _mm_loadu_si128(K.as_ptr().add(group * 4) as *const __m128i);
+                let mut message = _mm_add_epi32(w[group % 4], k);
+                cdgh = _mm_sha256rnds2_epu32(cdgh, abef, message);
+                if (3..15).contains(&group) {
+                    let next = (group + 1) % 4;
+                    let shifted = _mm_alignr_epi8(w[group % 4], w[(group + 3) % 4], 4);
+                    w[next] = _mm_sha256msg2_epu32(_mm_add_epi32(w[next], shifted), w[group % 4]);
+                }
+                message = _mm_shuffle_epi32(message, 0x0e);
+                abef = _mm_sha256rnds2_epu32(abef, cdgh, message);
+                if (1..13).contains(&group) {
+                    let previous = (group + 3) % 4;
+                    w[previous] = _mm_sha256msg1_epu32(w[previous], w[group % 4]);
+                }
+            }
+
+            abef = _mm_add_epi32(abef, abef_saved);
+            cdgh = _mm_add_epi32(cdgh, cdgh_saved);
+        }
+
+        let feba = _mm_shuffle_epi32(abef, 0x1b);
+        let dchg = _mm_shuffle_epi32(cdgh, 0xb1);
+        _mm_storeu_si128(
+            state.as_mut_ptr() as *mut __m128i,
+            _mm_blend_epi16(feba, dchg, 0xf0),
+        );
+        _mm_storeu_si128(
+            state.as_mut_ptr().add(4) as *mut __m128i,
+            _mm_alignr_epi8(dchg, feba, 8),
+        );
+    }
+}
+
+/// Hash a whole buffer with SHA-256 in one call.
+pub fn sha256(data: &[u8]) -> [u8; SHA256_LEN] {
+    let mut hasher = Sha256::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> alloc::string::String {
+        bytes.iter().map(|byte| alloc::format!("{:02x}", byte)).collect()
+    }
+
+    #[test]
+    fn known_answers() {
+        // FIPS 180-2 examples, plus the empty message.
+        let cases: [(&[u8], &str); 3] = [
+            (b"", "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"),
+            (b"abc", "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"),
+            (
+                b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+                "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+            ),
+        ];
+        for (message, expected) in cases {
+            assert_eq!(hex(&sha256(message)), expected);
+        }
+        assert_eq!(
+            hex(&sha256(&alloc::vec![b'a'; 1_000_000])),
+            "cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn pieces_match_one_call() {
+        let data: alloc::vec::Vec<u8> = (0..1000u32).map(|i| (i * 7) as u8).collect();
+        let whole = sha256(&data);
+        for piece in [1, 3, 55, 63, 64, 65, 200] {
+            let mut hasher = Sha256::new();
+            for chunk in data.chunks(piece) {
+                hasher.update(chunk);
+            }
+            assert_eq!(hasher.finalize(), whole, "pieces of {}", piece);
+        }
+    }
+
+    #[test]
+    fn padding_boundaries() {
+        // Lengths around where the length field no longer fits in the last
+        // block, checked against the block-at-a-time compression function
+        // so the accelerated path is compared with the portable one.
+        for len in [55, 56, 57, 63, 64, 119, 120, 128] {
+            let data = alloc::vec![0x5a; len];
+            let mut accelerated = Sha256::new();
+            accelerated.update(&data);
+            let mut portable = Sha256::new();
+            for block in data.chunks_exact(64) {
+                portable.compress(block.try_into().unwrap());
+            }
+            portable.total_len = len as u64;
+            let rest = &data[len - len % 64..];
+            portable.buffer[..rest.len()].copy_from_slice(rest);
+            portable.buffer_len = rest.len();
+            assert_eq!(accelerated.state, portable.state, "length {}", len);
+            assert_eq!(accelerated.finalize(), portable.finalize(), "length {}", len);
+        }
+    }
+}
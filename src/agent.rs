@@ -0,0 +1,419 @@
+use std::env;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::base64;
+use crate::checksum;
+use crate::crypto;
+use crate::ed25519::{self, KEY_LEN, SIGNATURE_LEN};
+use crate::sandbox;
+
+// Message numbers from the SSH agent protocol (draft-miller-ssh-agent).
+// Only Ed25519 keys are held, which is all this tool creates.
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENT_SUCCESS: u8 = 6;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+const SSH_AGENTC_ADD_IDENTITY: u8 = 17;
+const SSH_AGENTC_REMOVE_IDENTITY: u8 = 18;
+const SSH_AGENTC_REMOVE_ALL_IDENTITIES: u8 = 19;
+const SSH_AGENTC_LOCK: u8 = 22;
+const SSH_AGENTC_UNLOCK: u8 = 23;
+const SSH_AGENTC_ADD_ID_CONSTRAINED: u8 = 25;
+const SSH_AGENT_CONSTRAIN_LIFETIME: u8 = 1;
+
+const KEY_TYPE: &[u8] = b"ssh-ed25519";
+
+/// Longest message accepted, far above any request for Ed25519 keys, so a
+/// stray client cannot make the agent allocate without limit.
+const MAX_MESSAGE_LEN: u32 = 256 * 1024;
+
+/// The default socket for `agent start`: in `$XDG_RUNTIME_DIR`, which
+/// only the user can enter, or else in the home folder.
+pub fn default_socket() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("file_encryptor_agent.sock"),
+        None => env::home_dir()
+            .unwrap_or_default()
+            .join(".file_encryptor_agent.sock"),
+    }
+}
+
+/// The socket clients talk to: `--socket` if given, or `SSH_AUTH_SOCK`,
+/// so any running SSH agent works as well as ours.
+pub fn client_socket(socket: Option<String>) -> Result<PathBuf, String> {
+    socket
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("SSH_AUTH_SOCK").map(PathBuf::from))
+        .ok_or_else(|| "No agent: SSH_AUTH_SOCK is not set. Start one with 'agent start' or pass --socket.".to_string())
+}
+
+/// A key held by the agent.
+struct Identity {
+    seed: [u8; KEY_LEN],
+    public: [u8; KEY_LEN],
+    comment: Vec<u8>,
+    /// When the key is dropped, if it was added with a lifetime.
+    expires: Option<Instant>,
+}
+
+impl Drop for Identity {
+    fn drop(&mut self) {
+        self.seed.fill(0);
+        std::hint::black_box(&mut self.seed);
+    }
+}
+
+/// Everything the agent holds.
+struct State {
+    identities: Vec<Identity>,
+    /// Salted hash of the passphrase while locked.
+    lock: Option<([u8; 16], [u8; checksum::SHA256_LEN])>,
+    /// Lifetime given to keys added without one (`agent start --ttl`).
+    default_ttl: Option<Duration>,
+}
+
+impl State {
+    fn forget_expired(&mut self) {
+        let now = Instant::now();
+        self.identities
+            .retain(|identity| identity.expires.is_none_or(|expires| expires > now));
+    }
+}
+
+/// Run an agent on `socket` until killed, serving each client on its own
+/// thread. It speaks the SSH agent protocol, so `ssh`, `ssh-add`, and
+/// other tools can use the keys it holds, and keys can be added with
+/// `ssh-add` as well as with `agent add-key`. Once listening it switches
+/// to `user`, if given, and hardens itself (see `sandbox::harden`).
+pub fn serve(socket: &Path, default_ttl: Option<Duration>, user: Option<&str>) -> Result<(), String> {
+    let listener = sandbox::listen(socket, "agent")?;
+    if let Some(warning) = sandbox::harden(user, &listener, socket)? {
+        eprintln!("Warning: {}", warning);
+    }
+
+    let state = Arc::new(Mutex::new(State {
+        identities: Vec::new(),
+        lock: None,
+        default_ttl,
+    }));
+    // Expired keys are dropped within a second, not only when next asked
+    // for, so they do not linger in memory.
+    let sweeper = Arc::clone(&state);
+    thread::spawn(move || {
+        loop {
+            thread::sleep(Duration::from_secs(1));
+            sweeper.lock().unwrap_or_else(|e| e.into_inner()).forget_expired();
+        }
+    });
+
+    for stream in listener.listener.incoming() {
+        let Ok(stream) = stream else { continue };
+        let state = Arc::clone(&state);
+        thread::spawn(move || serve_client(&state, stream));
+    }
+    Ok(())
+}
+
+fn serve_client(state: &Mutex<State>, mut stream: UnixStream) {
+    while let Ok(request) = read_message(&mut stream) {
+        let reply = {
+            let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+            state.forget_expired();
+            handle(&mut state, &request).unwrap_or_else(|| vec![SSH_AGENT_FAILURE])
+        };
+        if write_message(&mut stream, &reply).is_err() {
+            return;
+        }
+    }
+}
+
+/// Answer one request, or `None` for the protocol's generic failure.
+fn handle(state: &mut State, request: &[u8]) -> Option<Vec<u8>> {
+    let (&kind, body) = request.split_first()?;
+    let mut body = Reader(body);
+    let locked = state.lock.is_some();
+    match kind {
+        SSH_AGENTC_REQUEST_IDENTITIES => {
+            let mut reply = vec![SSH_AGENT_IDENTITIES_ANSWER];
+            // A locked agent shows no keys, as OpenSSH's does.
+            let shown: &[Identity] = if locked { &[] } else { &state.identities };
+            put_u32(&mut reply, shown.len() as u32);
+            for identity in shown {
+                put_string(&mut reply, &key_blob(&identity.public));
+                put_string(&mut reply, &identity.comment);
+            }
+            Some(reply)
+        }
+        SSH_AGENTC_SIGN_REQUEST if !locked => {
+            let blob = body.string()?;
+            let data = body.string()?;
+            let identity = state
+                .identities
+                .iter()
+                .find(|identity| key_blob(&identity.public) == blob)?;
+            let mut signature = Vec::new();
+            put_string(&mut signature, KEY_TYPE);
+            put_string(&mut signature, &ed25519::sign(&identity.seed, data));
+            let mut reply = vec![SSH_AGENT_SIGN_RESPONSE];
+            put_string(&mut reply, &signature);
+            Some(reply)
+        }
+        SSH_AGENTC_ADD_IDENTITY | SSH_AGENTC_ADD_ID_CONSTRAINED if !locked => {
+            if body.string()? != KEY_TYPE {
+                return None;
+            }
+            let public: [u8; KEY_LEN] = body.string()?.try_into().ok()?;
+            let private = body.string()?;
+            let comment = body.string()?.to_vec();
+            let seed: [u8; KEY_LEN] = private.get(..KEY_LEN)?.try_into().ok()?;
+            if private.len() != 2 * KEY_LEN || ed25519::public_key(&seed) != public {
+                return None;
+            }
+            let mut ttl = state.default_ttl;
+            while !body.0.is_empty() {
+                // Confirmation and extension constraints cannot be
+                // honored, so keys asking for them are refused.
+                match body.byte()? {
+                    SSH_AGENT_CONSTRAIN_LIFETIME => {
+                        ttl = Some(Duration::from_secs(body.u32()?.into()))
+                    }
+                    _ => return None,
+                }
+            }
+            state.identities.retain(|identity| identity.public != public);
+            state.identities.push(Identity {
+                seed,
+                public,
+                comment,
+                expires: ttl.map(|ttl| Instant::now() + ttl),
+            });
+            Some(vec![SSH_AGENT_SUCCESS])
+        }
+        SSH_AGENTC_REMOVE_IDENTITY if !locked => {
+            let blob = body.string()?;
+            let before = state.identities.len();
+            state
+                .identities
+                .retain(|identity| key_blob(&identity.public) != blob);
+            (state.identities.len() < before).then(|| vec![SSH_AGENT_SUCCESS])
+        }
+        SSH_AGENTC_REMOVE_ALL_IDENTITIES if !locked => {
+            state.identities.clear();
+            Some(vec![SSH_AGENT_SUCCESS])
+        }
+        SSH_AGENTC_LOCK if !locked => {
+            let mut salt = [0u8; 16];
+            crypto::random_bytes(&mut salt).ok()?;
+            state.lock = Some((salt, passphrase_hash(&salt, body.string()?)));
+            Some(vec![SSH_AGENT_SUCCESS])
+        }
+        SSH_AGENTC_UNLOCK if locked => {
+            let (salt, hash) = state.lock?;
+            if crypto::constant_time_eq(&passphrase_hash(&salt, body.string()?), &hash) {
+                state.lock = None;
+                Some(vec![SSH_AGENT_SUCCESS])
+            } else {
+                // Slow down guessing, as OpenSSH does.
+                thread::sleep(Duration::from_secs(1));
+                None
+            }
+        }
+        _ => None,
+    }
+}
+
+fn passphrase_hash(salt: &[u8], passphrase: &[u8]) -> [u8; checksum::SHA256_LEN] {
+    crypto::hmac_sha256(salt, passphrase)
+}
+
+/// Add the key `seed` to the agent on `socket`, dropped after `ttl` if
+/// given.
+pub fn add_key(socket: &Path, seed: &[u8; KEY_LEN], comment: &str, ttl: Option<Duration>) -> Result<(), String> {
+    let public = ed25519::public_key(seed);
+    let mut private = seed.to_vec();
+    private.extend_from_slice(&public);
+    let kind = if ttl.is_some() {
+        SSH_AGENTC_ADD_ID_CONSTRAINED
+    } else {
+        SSH_AGENTC_ADD_IDENTITY
+    };
+    let mut request = vec![kind];
+    put_string(&mut request, KEY_TYPE);
+    put_string(&mut request, &public);
+    put_string(&mut request, &private);
+    put_string(&mut request, comment.as_bytes());
+    if let Some(ttl) = ttl {
+        request.push(SSH_AGENT_CONSTRAIN_LIFETIME);
+        put_u32(&mut request, u32::try_from(ttl.as_secs()).unwrap_or(u32::MAX));
+    }
+    private.fill(0);
+    let reply = call(socket, &request);
+    request.fill(0);
+    expect_success(reply?, "The agent refused the key.")
+}
+
+/// A key the agent holds.
+pub struct Listed {
+    /// The Ed25519 public key, or `None` for keys of other types, which
+    /// another agent may hold.
+    pub public: Option<[u8; KEY_LEN]>,
+    /// The key's fingerprint in OpenSSH's `SHA256:...` form.
+    pub fingerprint: String,
+    pub comment: String,
+}
+
+/// List the keys held by the agent on `socket`.
+pub fn list(socket: &Path) -> Result<Vec<Listed>, String> {
+    let reply = call(socket, &[SSH_AGENTC_REQUEST_IDENTITIES])?;
+    let malformed = || "The agent sent a malformed reply.".to_string();
+    let (&kind, body) = reply.split_first().ok_or_else(malformed)?;
+    if kind != SSH_AGENT_IDENTITIES_ANSWER {
+        return Err(malformed());
+    }
+    let mut body = Reader(body);
+    let count = body.u32().ok_or_else(malformed)?;
+    let mut keys = Vec::new();
+    for _ in 0..count {
+        let blob = body.string().ok_or_else(malformed)?;
+        let comment = body.string().ok_or_else(malformed)?;
+        let mut fields = Reader(blob);
+        let public = match fields.string() {
+            Some(KEY_TYPE) => fields.string().and_then(|key| key.try_into().ok()),
+            _ => None,
+        };
+        keys.push(Listed {
+            public,
+            // OpenSSH leaves the padding off.
+            fingerprint: format!("SHA256:{}", base64::encode(&checksum::sha256(blob)).trim_end_matches('=')),
+            comment: String::from_utf8_lossy(comment).into_owned(),
+        });
+    }
+    Ok(keys)
+}
+
+/// Have the agent on `socket` sign `data` with the Ed25519 key `public`.
+pub fn sign(socket: &Path, public: &[u8; KEY_LEN], data: &[u8]) -> Result<[u8; SIGNATURE_LEN], String> {
+    let mut request = vec![SSH_AGENTC_SIGN_REQUEST];
+    put_string(&mut request, &key_blob(public));
+    put_string(&mut request, data);
+    put_u32(&mut request, 0);
+    let reply = call(socket, &request)?;
+    let refused = || "The agent refused to sign; is the key added and the agent unlocked?".to_string();
+    let (&kind, body) = reply.split_first().ok_or_else(refused)?;
+    if kind != SSH_AGENT_SIGN_RESPONSE {
+        return Err(refused());
+    }
+    let mut signature = Reader(Reader(body).string().ok_or_else(refused)?);
+    if signature.string() != Some(KEY_TYPE) {
+        return Err(refused());
+    }
+    signature
+        .string()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(refused)
+}
+
+/// Lock the agent on `socket` with `passphrase`, or unlock it.
+pub fn set_locked(socket: &Path, locked: bool, passphrase: &str) -> Result<(), String> {
+    let mut request = vec![if locked { SSH_AGENTC_LOCK } else { SSH_AGENTC_UNLOCK }];
+    put_string(&mut request, passphrase.as_bytes());
+    let reply = call(socket, &request)?;
+    expect_success(
+        reply,
+        if locked {
+            "The agent refused to lock; it may be locked already."
+        } else {
+            "The agent refused to unlock; the passphrase is wrong or it is not locked."
+        },
+    )
+}
+
+/// Make the agent on `socket` forget every key.
+pub fn remove_all(socket: &Path) -> Result<(), String> {
+    let reply = call(socket, &[SSH_AGENTC_REMOVE_ALL_IDENTITIES])?;
+    expect_success(reply, "The agent refused to remove the keys; is it locked?")
+}
+
+fn expect_success(reply: Vec<u8>, refusal: &str) -> Result<(), String> {
+    if reply == [SSH_AGENT_SUCCESS] {
+        Ok(())
+    } else {
+        Err(refusal.to_string())
+    }
+}
+
+/// Send one request and wait for the reply.
+fn call(socket: &Path, request: &[u8]) -> Result<Vec<u8>, String> {
+    let mut stream = UnixStream::connect(socket)
+        .map_err(|e| format!("Failed to connect to the agent at '{}': {}", socket.display(), e))?;
+    write_message(&mut stream, request)
+        .and_then(|_| read_message(&mut stream))
+        .map_err(|e| format!("The connection to the agent failed: {}", e))
+}
+
+/// The SSH wire form of an Ed25519 public key.
+fn key_blob(public: &[u8; KEY_LEN]) -> Vec<u8> {
+    let mut blob = Vec::new();
+    put_string(&mut blob, KEY_TYPE);
+    put_string(&mut blob, public);
+    blob
+}
+
+/// Messages are a 32-bit big-endian length and that many bytes.
+fn write_message(stream: &mut UnixStream, message: &[u8]) -> io::Result<()> {
+    let mut framed = Vec::with_capacity(4 + message.len());
+    put_string(&mut framed, message);
+    stream.write_all(&framed)
+}
+
+fn read_message(stream: &mut UnixStream) -> io::Result<Vec<u8>> {
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len);
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "message too long"));
+    }
+    let mut message = vec![0u8; len as usize];
+    stream.read_exact(&mut message)?;
+    Ok(message)
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+fn put_string(out: &mut Vec<u8>, bytes: &[u8]) {
+    put_u32(out, bytes.len() as u32);
+    out.extend_from_slice(bytes);
+}
+
+/// Reads the SSH wire types from a message body.
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Option<u8> {
+        let (&byte, rest) = self.0.split_first()?;
+        self.0 = rest;
+        Some(byte)
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        let (bytes, rest) = self.0.split_first_chunk::<4>()?;
+        self.0 = rest;
+        Some(u32::from_be_bytes(*bytes))
+    }
+
+    fn string(&mut self) -> Option<&'a [u8]> {
+        let len = self.u32()? as usize;
+        let (bytes, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+        Some(bytes)
+    }
+}
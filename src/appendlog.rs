@@ -0,0 +1,805 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::crypto::{self, NONCE_LEN, TAG_LEN};
+use crate::format::{self, Cipher, FormatError, SALT_LEN, SealParams};
+use crate::memory;
+use crate::policy;
+use crate::progress;
+use crate::salvage::{Gap, GapMap};
+use crate::{Keys, sealing};
+
+/// Bytes at the start of an encrypted log. Logs are a format of their own
+/// rather than a version of `FENC`, since they are never read whole.
+const LOG_MAGIC: &[u8; 4] = b"FENL";
+const LOG_VERSION: u8 = 1;
+
+/// Magic, version, iteration count, salt, chunk size, and a tag over all
+/// of those.
+const LOG_HEADER_LEN: usize = LOG_MAGIC.len() + 1 + 4 + SALT_LEN + 4 + TAG_LEN;
+
+/// The chunk size of a log is the most plaintext one record may hold.
+/// `append` splits what it adds into records of that size, and neither
+/// writing nor reading holds more than one record in memory.
+///
+/// Small chunks let `append` and a reader that stops early work in finer
+/// steps and need less memory, but every record costs a length, a tag,
+/// and a write call, which adds up below about 64 KiB. Large chunks are
+/// faster for big appends but are read and checked whole.
+pub const DEFAULT_CHUNK_SIZE: usize = 1 << 20;
+pub const MIN_CHUNK_SIZE: usize = 64 << 10;
+pub const MAX_CHUNK_SIZE: usize = 16 << 20;
+
+/// What an append or decryption covered.
+pub struct LogSummary {
+    /// Bytes of plaintext added or decrypted.
+    pub bytes: u64,
+    /// Records written or read.
+    pub records: u64,
+    /// Plaintext in the whole log afterwards.
+    pub total: u64,
+}
+
+/// The keys and chain state of an encrypted log: a header, then records
+/// that are each sealed on their own. A record is its plaintext length,
+/// a random nonce, the ciphertext, and a tag over the previous tag, the
+/// record number, the length, the nonce, and the ciphertext. The nonce is
+/// random rather than the record number because a log restored from a
+/// backup and then added to would otherwise seal a new record N under the
+/// key and nonce of the old one. Chaining the tags means records cannot
+/// be dropped, reordered, or moved between logs without the reader
+/// noticing; only cutting the log short at a record boundary looks like a
+/// log that has not grown yet.
+struct Log {
+    keys: Keys,
+    chunk_size: usize,
+    /// Tag of the last record, or of the header in an empty log.
+    last_tag: [u8; TAG_LEN],
+    records: u64,
+    /// Plaintext in the log so far.
+    plaintext_len: u64,
+}
+
+impl Log {
+    /// Write the header of a new log to `file`.
+    fn create(file: &mut File, password: &[u8], chunk_size: usize) -> Result<Self, String> {
+        check_chunk_size(chunk_size)?;
+        memory::check_log_chunk_size(chunk_size)?;
+        let policy = policy::get();
+        policy.check_chacha20("encrypted logs")?;
+        if !policy.required_recipients.is_empty() {
+            return Err("The organization policy requires recipient keys, which encrypted logs do not support.".to_string());
+        }
+        let mut params = SealParams {
+            iterations: format::DEFAULT_ITERATIONS.max(policy.min_kdf_iterations),
+            salt: [0; SALT_LEN],
+            nonce: [0; NONCE_LEN],
+            stanzas: Vec::new(),
+            cipher: Cipher::ChaCha20,
+        };
+        crypto::random_bytes(&mut params.salt)?;
+        let keys = progress::deriving_key(params.iterations, || sealing::derive_keys(password, &params));
+
+        let mut header = Vec::with_capacity(LOG_HEADER_LEN);
+        header.extend_from_slice(LOG_MAGIC);
+        header.push(LOG_VERSION);
+        header.extend_from_slice(&params.iterations.to_le_bytes());
+        header.extend_from_slice(&params.salt);
+        header.extend_from_slice(&(chunk_size as u32).to_le_bytes());
+        let tag = crypto::hmac_sha256(&keys.mac, &header);
+        header.extend_from_slice(&tag);
+        file.write_all(&header).map_err(|e| e.to_string())?;
+        Ok(Log {
+            keys,
+            chunk_size,
+            last_tag: tag,
+            records: 0,
+            plaintext_len: 0,
+        })
+    }
+
+    /// Read the header of an existing log and check the password against
+    /// it, leaving `file` just after the header.
+    fn open(file: &mut impl Read, password: &[u8]) -> Result<Self, String> {
+        let mut header = [0u8; LOG_HEADER_LEN];
+        file.read_exact(&mut header).map_err(|e| match e.kind() {
+            io::ErrorKind::UnexpectedEof => format::message(FormatError::Truncated),
+            _ => e.to_string(),
+        })?;
+        if !header.starts_with(LOG_MAGIC) {
+            return Err("This is not an encrypted log.".to_string());
+        }
+        if header[4] != LOG_VERSION {
+            return Err(format::message(FormatError::UnsupportedVersion(header[4])));
+        }
+        let iterations = u32::from_le_bytes(header[5..9].try_into().expect("four bytes"));
+        if iterations == 0 || iterations > format::MAX_ITERATIONS {
+            return Err(format::message(FormatError::BadIterations(iterations)));
+        }
+        let params = SealParams {
+            iterations,
+            salt: header[9..9 + SALT_LEN].try_into().expect("salt length"),
+            nonce: [0; NONCE_LEN],
+            stanzas: Vec::new(),
+            cipher: Cipher::ChaCha20,
+        };
+        let keys = progress::deriving_key(params.iterations, || sealing::derive_keys(password, &params));
+        let (fields, tag) = header.split_at(LOG_HEADER_LEN - TAG_LEN);
+        if !crypto::constant_time_eq(&crypto::hmac_sha256(&keys.mac, fields), tag) {
+            return Err(format::message(FormatError::AuthenticationFailed));
+        }
+        // Checked only once the tag shows the header is genuine, so a
+        // damaged one is reported as such rather than as a bad setting.
+        let chunk_size = u32::from_le_bytes(fields[9 + SALT_LEN..].try_into().expect("four bytes")) as usize;
+        check_chunk_size(chunk_size)?;
+        memory::check_log_chunk_size(chunk_size)?;
+        Ok(Log {
+            keys,
+            chunk_size,
+            last_tag: tag.try_into().expect("tag length"),
+            records: 0,
+            plaintext_len: 0,
+        })
+    }
+
+    /// Skip to the end of the records without decrypting them, picking up
+    /// the last tag to chain the next record to. A record cut short by a
+    /// write that did not finish is an error rather than something to
+    /// write after.
+    fn seek_to_end(&mut self, file: &mut File) -> Result<(), String> {
+        let end = file.metadata().map_err(|e| e.to_string())?.len();
+        let mut pos = LOG_HEADER_LEN as u64;
+        while pos < end {
+            let len = read_record_len(file, self.chunk_size)?;
+            let tag_pos = pos + (4 + NONCE_LEN + len) as u64;
+            if tag_pos + TAG_LEN as u64 > end {
+                return Err(format!(
+                    "The log ends in the middle of record {}; an earlier write did not finish.",
+                    self.records + 1
+                ));
+            }
+            file.seek(SeekFrom::Start(tag_pos)).map_err(|e| e.to_string())?;
+            file.read_exact(&mut self.last_tag).map_err(|e| e.to_string())?;
+            pos = tag_pos + TAG_LEN as u64;
+            self.records += 1;
+            self.plaintext_len += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Encrypt `plaintext` as the next record.
+    fn seal_record(&mut self, plaintext: &[u8]) -> Result<Vec<u8>, String> {
+        let len = plaintext.len() as u32;
+        let mut nonce = [0u8; NONCE_LEN];
+        crypto::random_bytes(&mut nonce)?;
+        let mut record = Vec::with_capacity(4 + NONCE_LEN + plaintext.len() + TAG_LEN);
+        record.extend_from_slice(&len.to_le_bytes());
+        record.extend_from_slice(&nonce);
+        record.extend_from_slice(plaintext);
+        crypto::chacha20_xor(&self.keys.cipher, &nonce, 0, &mut record[4 + NONCE_LEN..]);
+        let tag = self.record_tag(&record);
+        record.extend_from_slice(&tag);
+        self.last_tag = tag;
+        self.records += 1;
+        self.plaintext_len += plaintext.len() as u64;
+        Ok(record)
+    }
+
+    /// Check the tag of the next record (length, nonce, and ciphertext in
+    /// `body`),
+    /// found at byte `at` of the log, and decrypt it in place.
+    fn open_record(&mut self, body: &mut [u8], tag: &[u8], at: u64) -> Result<(), String> {
+        let expected = self.record_tag(body);
+        if !crypto::constant_time_eq(&expected, tag) {
+            let len = (body.len() - 4 - NONCE_LEN) as u64;
+            return Err(format!(
+                "Record {} failed authentication: the log was modified or damaged at bytes {}-{}, which hold plaintext bytes {}-{}.",
+                self.records + 1,
+                at,
+                at + (body.len() + TAG_LEN) as u64,
+                self.plaintext_len,
+                self.plaintext_len + len
+            ));
+        }
+        let (nonce, ciphertext) = body[4..].split_at_mut(NONCE_LEN);
+        crypto::chacha20_xor(&self.keys.cipher, (&*nonce).try_into().expect("nonce length"), 0, ciphertext);
+        self.last_tag = expected;
+        self.records += 1;
+        self.plaintext_len += ciphertext.len() as u64;
+        Ok(())
+    }
+
+    /// Step over a record of `len` bytes whose tag did not match, chaining
+    /// the next one to the `tag` stored for it.
+    fn skip_record(&mut self, len: usize, tag: [u8; TAG_LEN]) {
+        self.last_tag = tag;
+        self.records += 1;
+        self.plaintext_len += len as u64;
+    }
+
+    fn record_tag(&self, body: &[u8]) -> [u8; TAG_LEN] {
+        let mut mac = crypto::HmacSha256::new(&self.keys.mac);
+        mac.update(&self.last_tag);
+        mac.update(&self.records.to_le_bytes());
+        mac.update(body);
+        mac.finalize()
+    }
+}
+
+fn read_record_len(file: &mut impl Read, chunk_size: usize) -> Result<usize, String> {
+    let mut len = [0u8; 4];
+    file.read_exact(&mut len).map_err(|e| e.to_string())?;
+    let len = u32::from_le_bytes(len) as usize;
+    if len > chunk_size {
+        return Err(format::message(FormatError::Garbled));
+    }
+    Ok(len)
+}
+
+/// Lock `file` for the writer using it until it is closed. Two writers
+/// that both added a record N would chain it to the same record N - 1,
+/// and a reader would refuse whichever came second along with everything
+/// after it.
+fn lock_for_writing(file: &File) -> Result<(), String> {
+    match file.try_lock() {
+        Ok(()) => Ok(()),
+        Err(TryLockError::WouldBlock) => {
+            Err("Another process is writing to this log. Try again once it has finished.".to_string())
+        }
+        Err(TryLockError::Error(e)) => Err(format!("Failed to lock the log: {}", e)),
+    }
+}
+
+/// Refuse chunk sizes outside `MIN_CHUNK_SIZE` to `MAX_CHUNK_SIZE`.
+pub fn check_chunk_size(chunk_size: usize) -> Result<(), String> {
+    if !(MIN_CHUNK_SIZE..=MAX_CHUNK_SIZE).contains(&chunk_size) {
+        return Err(format!(
+            "The chunk size must be between {} KiB and {} MiB, not {} bytes.",
+            MIN_CHUNK_SIZE >> 10,
+            MAX_CHUNK_SIZE >> 20,
+            chunk_size
+        ));
+    }
+    Ok(())
+}
+
+/// Appends records to an encrypted log, for services that want a
+/// tamper-evident log without keeping its plaintext on disk. Each record
+/// is written with one `write` call and can be read back on its own as
+/// soon as it is on disk. The log is locked while the writer lives, so a
+/// second writer, in this process or another, is refused.
+pub struct EncryptedLogWriter {
+    file: File,
+    log: Log,
+}
+
+impl EncryptedLogWriter {
+    /// Start a new log at `path`, which must not exist yet, whose records
+    /// hold at most `chunk_size` bytes.
+    pub fn create(path: &Path, password: &str, chunk_size: usize) -> Result<Self, String> {
+        let in_log = |e: String| format!("'{}': {}", path.display(), e);
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| in_log(e.to_string()))?;
+        match lock_for_writing(&file).and_then(|()| Log::create(&mut file, password.as_bytes(), chunk_size)) {
+            Ok(log) => Ok(EncryptedLogWriter { file, log }),
+            Err(e) => {
+                let _ = fs::remove_file(path);
+                Err(in_log(e))
+            }
+        }
+    }
+
+    /// Open the log at `path` to add to it. Only the header is checked
+    /// against the password; the records are skipped over, not read.
+    pub fn open(path: &Path, password: &str) -> Result<Self, String> {
+        let in_log = |e: String| format!("'{}': {}", path.display(), e);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(path)
+            .map_err(|e| in_log(e.to_string()))?;
+        lock_for_writing(&file).map_err(in_log)?;
+        let mut log = Log::open(&mut file, password.as_bytes()).map_err(in_log)?;
+        log.seek_to_end(&mut file).map_err(in_log)?;
+        Ok(EncryptedLogWriter { file, log })
+    }
+
+    /// Open the log at `path`, or start one there with `chunk_size` if
+    /// there is none.
+    pub fn open_or_create(path: &Path, password: &str, chunk_size: usize) -> Result<Self, String> {
+        if path.exists() {
+            Self::open(path, password)
+        } else {
+            Self::create(path, password, chunk_size)
+        }
+    }
+
+    /// Encrypt `record` and add it to the end of the log. Records longer
+    /// than the chunk size are refused.
+    pub fn append(&mut self, record: &[u8]) -> Result<(), String> {
+        if record.len() > self.log.chunk_size {
+            return Err(format!(
+                "A record in this log holds at most {} bytes, not {}.",
+                self.log.chunk_size,
+                record.len()
+            ));
+        }
+        let sealed = self.log.seal_record(record)?;
+        self.file.write_all(&sealed).map_err(|e| e.to_string())
+    }
+
+    /// Wait until every record appended so far is on disk.
+    pub fn sync(&mut self) -> Result<(), String> {
+        self.file.sync_all().map_err(|e| e.to_string())
+    }
+
+    /// The most plaintext one record of this log may hold.
+    pub fn chunk_size(&self) -> usize {
+        self.log.chunk_size
+    }
+
+    /// Records in the log, including ones there before it was opened.
+    pub fn records(&self) -> u64 {
+        self.log.records
+    }
+
+    /// Bytes of plaintext in the log.
+    pub fn plaintext_len(&self) -> u64 {
+        self.log.plaintext_len
+    }
+}
+
+/// Reads the records of an encrypted log in order, checking each one's
+/// tag before handing it out. Iteration stops after the first error,
+/// unless `salvage` was called.
+pub struct EncryptedLogReader {
+    file: BufReader<File>,
+    log: Log,
+    pos: u64,
+    end: u64,
+    failed: bool,
+    salvage: bool,
+}
+
+impl EncryptedLogReader {
+    /// Open the log at `path` and check the password against its header.
+    pub fn open(path: &Path, password: &str) -> Result<Self, String> {
+        let in_log = |e: String| format!("'{}': {}", path.display(), e);
+        let file = File::open(path).map_err(|e| in_log(e.to_string()))?;
+        let end = file.metadata().map_err(|e| in_log(e.to_string()))?.len();
+        let mut file = BufReader::new(file);
+        let log = Log::open(&mut file, password.as_bytes()).map_err(in_log)?;
+        Ok(EncryptedLogReader {
+            file,
+            log,
+            pos: LOG_HEADER_LEN as u64,
+            end,
+            failed: false,
+            salvage: false,
+        })
+    }
+
+    /// Keep going past records whose tag does not match, handing out an
+    /// error for each and going on with the next. A record after one
+    /// whose stored tag was damaged fails too, since its tag is chained to
+    /// the original. Iteration still stops where the records cannot be
+    /// told apart any more: a length that is out of range, or a log cut
+    /// off in the middle of a record.
+    pub fn salvage(&mut self) {
+        self.salvage = true;
+    }
+
+    /// Records read so far.
+    pub fn records(&self) -> u64 {
+        self.log.records
+    }
+
+    /// Bytes of plaintext read so far.
+    pub fn plaintext_len(&self) -> u64 {
+        self.log.plaintext_len
+    }
+
+    fn read_record(&mut self) -> Result<Vec<u8>, String> {
+        let len = read_record_len(&mut self.file, self.log.chunk_size)?;
+        if self.pos + (4 + NONCE_LEN + len + TAG_LEN) as u64 > self.end {
+            return Err(format!(
+                "The log ends in the middle of record {}; a write did not finish.",
+                self.log.records + 1
+            ));
+        }
+        let mut body = Vec::with_capacity(4 + NONCE_LEN + len);
+        body.extend_from_slice(&(len as u32).to_le_bytes());
+        body.resize(4 + NONCE_LEN + len, 0);
+        let mut tag = [0u8; TAG_LEN];
+        self.file.read_exact(&mut body[4..]).map_err(|e| e.to_string())?;
+        self.file.read_exact(&mut tag).map_err(|e| e.to_string())?;
+        let at = self.pos;
+        self.pos += (4 + NONCE_LEN + len + TAG_LEN) as u64;
+        if let Err(e) = self.log.open_record(&mut body, &tag, at) {
+            if self.salvage {
+                self.log.skip_record(len, tag);
+            } else {
+                self.pos = at;
+            }
+            return Err(e);
+        }
+        body.drain(..4 + NONCE_LEN);
+        Ok(body)
+    }
+}
+
+impl Iterator for EncryptedLogReader {
+    type Item = Result<Vec<u8>, String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.pos >= self.end {
+            return None;
+        }
+        let pos = self.pos;
+        let record = self.read_record();
+        // A salvaged record that failed has moved `pos` past itself.
+        self.failed = record.is_err() && !(self.salvage && self.pos > pos);
+        Some(record)
+    }
+}
+
+/// Encrypt bytes of `input` onto the end of the log `writer` has open.
+/// Earlier records are left as they are; only new ones are written.
+///
+/// Without a `range`, the bytes appended are those past the plaintext the
+/// log already holds, so running this after each write to a growing file
+/// keeps the log in step with it. A `range` appends exactly those bytes.
+pub fn append(
+    writer: &mut EncryptedLogWriter,
+    input: &Path,
+    range: Option<Range<u64>>,
+) -> Result<LogSummary, String> {
+    let in_log = |e: String| format!("Failed to write the log: {}", e);
+
+    let mut source = File::open(input).map_err(|e| format!("Failed to read '{}': {}", input.display(), e))?;
+    let input_len = source.metadata().map_err(|e| e.to_string())?.len();
+    let range = match range {
+        Some(range) if range.end > input_len => {
+            return Err(format!(
+                "The range ends at byte {}, past the end of '{}' ({} bytes).",
+                range.end,
+                input.display(),
+                input_len
+            ));
+        }
+        Some(range) => range,
+        None if input_len < writer.plaintext_len() => {
+            return Err(format!(
+                "'{}' is shorter than the log ({} bytes, {} logged); it was probably rotated. Start a new log for it.",
+                input.display(),
+                input_len,
+                writer.plaintext_len()
+            ));
+        }
+        None => writer.plaintext_len()..input_len,
+    };
+
+    source.seek(SeekFrom::Start(range.start)).map_err(|e| e.to_string())?;
+    let mut remaining = range.end - range.start;
+    let mut buffer = vec![0u8; writer.chunk_size()];
+    let records_before = writer.records();
+    while remaining > 0 {
+        let len = remaining.min(buffer.len() as u64) as usize;
+        source
+            .read_exact(&mut buffer[..len])
+            .map_err(|e| format!("Failed to read '{}': {}", input.display(), e))?;
+        writer.append(&buffer[..len]).map_err(in_log)?;
+        remaining -= len as u64;
+    }
+    buffer.fill(0);
+    writer.sync().map_err(in_log)?;
+    Ok(LogSummary {
+        bytes: range.end - range.start,
+        records: writer.records() - records_before,
+        total: writer.plaintext_len(),
+    })
+}
+
+/// Decrypt every record of the log at `log_path` into `output`, checking
+/// each one before any of its plaintext is written.
+pub fn decrypt(log_path: &Path, output: &Path, password: &str) -> Result<LogSummary, String> {
+    let in_log = |e: String| format!("'{}': {}", log_path.display(), e);
+    let mut reader = EncryptedLogReader::open(log_path, password)?;
+    let mut out = File::create(output).map_err(|e| format!("Failed to write '{}': {}", output.display(), e))?;
+    for record in reader.by_ref() {
+        let mut record = record.map_err(in_log)?;
+        out.write_all(&record)
+            .map_err(|e| format!("Failed to write '{}': {}", output.display(), e))?;
+        record.fill(0);
+    }
+    Ok(LogSummary {
+        bytes: reader.plaintext_len(),
+        records: reader.records(),
+        total: reader.plaintext_len(),
+    })
+}
+
+/// Decrypt what can still be read of a damaged log into `output`. A record
+/// that fails its check is written as zeros of the same length, so the
+/// records after it keep their place, and becomes a gap in the returned
+/// map; where the records cannot be told apart any more, the output stops
+/// with a gap of unknown length.
+pub fn salvage(log_path: &Path, output: &Path, password: &str) -> Result<(LogSummary, GapMap), String> {
+    let mut reader = EncryptedLogReader::open(log_path, password)?;
+    reader.salvage();
+    let mut out = File::create(output).map_err(|e| format!("Failed to write '{}': {}", output.display(), e))?;
+    let mut write = |data: &[u8]| {
+        out.write_all(data)
+            .map_err(|e| format!("Failed to write '{}': {}", output.display(), e))
+    };
+    let mut gaps = GapMap::default();
+    loop {
+        let (start, records) = (reader.plaintext_len(), reader.records());
+        let Some(record) = reader.next() else { break };
+        match record {
+            Ok(mut record) => {
+                write(&record)?;
+                record.fill(0);
+            }
+            Err(reason) if reader.records() > records => {
+                let end = reader.plaintext_len();
+                write(&vec![0u8; (end - start) as usize])?;
+                gaps.add(Gap {
+                    file: None,
+                    start,
+                    end: Some(end),
+                    reason,
+                });
+            }
+            Err(reason) => gaps.add(Gap {
+                file: None,
+                start,
+                end: None,
+                reason,
+            }),
+        }
+    }
+    Ok((
+        LogSummary {
+            bytes: reader.plaintext_len() - gaps.bytes(),
+            records: reader.records(),
+            total: reader.plaintext_len(),
+        },
+        gaps,
+    ))
+}
+
+/// Read a byte range such as `1024-4096` (end exclusive) or `1024-` (to the
+/// end of the input, given as `input_len`).
+pub fn parse_range(text: &str, input_len: u64) -> Result<Range<u64>, String> {
+    let invalid = || format!("Invalid range '{}'; expected START-END, such as 0-4096.", text);
+    let (start, end) = text.split_once('-').ok_or_else(invalid)?;
+    let start: u64 = start.trim().parse().map_err(|_| invalid())?;
+    let end = match end.trim() {
+        "" => input_len,
+        end => end.parse().map_err(|_| invalid())?,
+    };
+    if start > end {
+        return Err(invalid());
+    }
+    Ok(start..end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    const PASSWORD: &str = "log password";
+
+    /// A log in `scratch` holding `records`, one record each.
+    fn write_log(scratch: &Scratch, name: &str, records: &[&[u8]]) -> std::path::PathBuf {
+        let path = scratch.dir.join(name);
+        let mut writer = EncryptedLogWriter::create(&path, PASSWORD, MIN_CHUNK_SIZE).unwrap();
+        for record in records {
+            writer.append(record).unwrap();
+        }
+        path
+    }
+
+    fn read_all(path: &Path) -> Vec<Result<Vec<u8>, String>> {
+        EncryptedLogReader::open(path, PASSWORD).unwrap().collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let scratch = Scratch::create().unwrap();
+        let path = write_log(&scratch, "a.log", &[b"first", b"", b"third"]);
+        let mut writer = EncryptedLogWriter::open(&path, PASSWORD).unwrap();
+        assert_eq!((writer.records(), writer.plaintext_len(), writer.chunk_size()), (3, 10, MIN_CHUNK_SIZE));
+        writer.append(&vec![9; MIN_CHUNK_SIZE]).unwrap();
+        assert!(writer.append(&vec![9; MIN_CHUNK_SIZE + 1]).is_err());
+        drop(writer);
+
+        let records: Vec<Vec<u8>> = read_all(&path).into_iter().map(Result::unwrap).collect();
+        assert_eq!(records, [b"first".to_vec(), Vec::new(), b"third".to_vec(), vec![9; MIN_CHUNK_SIZE]]);
+
+        let output = scratch.dir.join("a.txt");
+        let summary = decrypt(&path, &output, PASSWORD).unwrap();
+        assert_eq!((summary.records, summary.bytes), (4, 10 + MIN_CHUNK_SIZE as u64));
+        assert_eq!(fs::read(&output).unwrap(), [b"first".as_slice(), b"third", &vec![9; MIN_CHUNK_SIZE]].concat());
+
+        assert!(EncryptedLogReader::open(&path, "wrong password").is_err());
+        assert!(EncryptedLogWriter::create(&path, PASSWORD, MIN_CHUNK_SIZE).is_err());
+        assert!(EncryptedLogWriter::create(&scratch.dir.join("b.log"), PASSWORD, MIN_CHUNK_SIZE - 1).is_err());
+        assert!(!scratch.dir.join("b.log").exists());
+        scratch.destroy().unwrap();
+    }
+
+    #[test]
+    fn append_follows_a_growing_file() {
+        let scratch = Scratch::create().unwrap();
+        let input = scratch.dir.join("service.log");
+        let path = scratch.dir.join("service.log.fenl");
+        fs::write(&input, b"line one\n").unwrap();
+        let mut writer = EncryptedLogWriter::open_or_create(&path, PASSWORD, MIN_CHUNK_SIZE).unwrap();
+        assert_eq!(append(&mut writer, &input, None).unwrap().bytes, 9);
+        fs::write(&input, b"line one\nline two\n").unwrap();
+        let summary = append(&mut writer, &input, None).unwrap();
+        assert_eq!((summary.bytes, summary.total), (9, 18));
+        // A file shorter than the log has been rotated.
+        fs::write(&input, b"new\n").unwrap();
+        assert!(append(&mut writer, &input, None).is_err());
+        assert!(append(&mut writer, &input, Some(0..5)).is_err());
+        drop(writer);
+        let output = scratch.dir.join("out.txt");
+        decrypt(&path, &output, PASSWORD).unwrap();
+        assert_eq!(fs::read(&output).unwrap(), b"line one\nline two\n");
+        scratch.destroy().unwrap();
+    }
+
+    #[test]
+    fn second_writer_is_refused() {
+        let scratch = Scratch::create().unwrap();
+        let path = write_log(&scratch, "a.log", &[]);
+        let writer = EncryptedLogWriter::open(&path, PASSWORD).unwrap();
+        let error = EncryptedLogWriter::open(&path, PASSWORD).err().unwrap();
+        assert!(error.contains("Another process is writing"), "{}", error);
+        // Reading does not need the lock.
+        assert!(EncryptedLogReader::open(&path, PASSWORD).is_ok());
+        drop(writer);
+        assert!(EncryptedLogWriter::open(&path, PASSWORD).is_ok());
+        scratch.destroy().unwrap();
+    }
+
+    #[test]
+    fn tampering_is_detected_and_salvaged() {
+        let scratch = Scratch::create().unwrap();
+        let path = write_log(&scratch, "a.log", &[b"aaaa", b"bbbb", b"cccc"]);
+        let record_len = 4 + NONCE_LEN + 4 + TAG_LEN;
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[LOG_HEADER_LEN + record_len + 4 + NONCE_LEN + 1] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+
+        let records = read_all(&path);
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].as_deref(), Ok(b"aaaa".as_slice()));
+        assert!(records[1].as_ref().unwrap_err().contains("Record 2 failed authentication"));
+        assert!(decrypt(&path, &scratch.dir.join("out.txt"), PASSWORD).is_err());
+
+        let output = scratch.dir.join("salvaged.txt");
+        let (summary, gaps) = salvage(&path, &output, PASSWORD).unwrap();
+        assert_eq!((summary.records, summary.bytes, summary.total), (3, 8, 12));
+        assert_eq!((gaps.len(), gaps.bytes()), (1, 4));
+        assert_eq!(fs::read(&output).unwrap(), b"aaaa\0\0\0\0cccc");
+        scratch.destroy().unwrap();
+    }
+
+    #[test]
+    fn moved_records_are_detected() {
+        let scratch = Scratch::create().unwrap();
+        let path = write_log(&scratch, "a.log", &[b"aaaa", b"bbbb"]);
+        let record_len = 4 + NONCE_LEN + 4 + TAG_LEN;
+        let bytes = fs::read(&path).unwrap();
+        let (header, records) = bytes.split_at(LOG_HEADER_LEN);
+        let swapped = [header, &records[record_len..], &records[..record_len]].concat();
+        fs::write(&path, &swapped).unwrap();
+        assert!(read_all(&path)[0].is_err());
+
+        // A record from another log with the same password does not fit
+        // this one's chain either.
+        let other = write_log(&scratch, "b.log", &[b"aaaa"]);
+        let spliced = [header, &fs::read(&other).unwrap()[LOG_HEADER_LEN..]].concat();
+        fs::write(&path, &spliced).unwrap();
+        assert!(read_all(&path)[0].is_err());
+        scratch.destroy().unwrap();
+    }
+
+    #[test]
+    fn records_written_again_get_new_nonces() {
+        let scratch = Scratch::create().unwrap();
+        let path = write_log(&scratch, "a.log", &[b"aaaa"]);
+        let first = fs::read(&path).unwrap();
+        // Rolled back to before the record, as by restoring a backup, and
+        // added to again.
+        fs::write(&path, &first[..LOG_HEADER_LEN]).unwrap();
+        EncryptedLogWriter::open(&path, PASSWORD).unwrap().append(b"bbbb").unwrap();
+        let second = fs::read(&path).unwrap();
+        let nonce = LOG_HEADER_LEN + 4..LOG_HEADER_LEN + 4 + NONCE_LEN;
+        assert_ne!(first[nonce.clone()], second[nonce]);
+        assert_eq!(read_all(&path), [Ok(b"bbbb".to_vec())]);
+        scratch.destroy().unwrap();
+    }
+
+    #[test]
+    fn truncated_logs() {
+        let scratch = Scratch::create().unwrap();
+        let path = write_log(&scratch, "a.log", &[b"aaaa", b"bbbb"]);
+        let record_len = 4 + NONCE_LEN + 4 + TAG_LEN;
+        let bytes = fs::read(&path).unwrap();
+        let cut = scratch.dir.join("cut.log");
+        for len in 0..LOG_HEADER_LEN {
+            fs::write(&cut, &bytes[..len]).unwrap();
+            assert!(EncryptedLogReader::open(&cut, PASSWORD).is_err(), "cut to {} bytes", len);
+        }
+        // Each open derives the keys, so only cuts in each part of a record
+        // are tried: its length, its nonce, its ciphertext, its tag, and its
+        // end.
+        let offsets = [0, 2, 4, 10, 4 + NONCE_LEN + 2, record_len - TAG_LEN / 2, record_len, record_len + 3, 2 * record_len];
+        for len in offsets.map(|offset| LOG_HEADER_LEN + offset) {
+            fs::write(&cut, &bytes[..len]).unwrap();
+            let records = read_all(&cut);
+            let whole = (len - LOG_HEADER_LEN) / record_len;
+            // Whole records read back; a partial one is an error, never data.
+            assert!(records[..whole].iter().all(Result::is_ok), "cut to {} bytes", len);
+            let partial = !(len - LOG_HEADER_LEN).is_multiple_of(record_len);
+            assert_eq!(records.len(), whole + usize::from(partial), "cut to {} bytes", len);
+            if partial {
+                assert!(records[whole].is_err(), "cut to {} bytes", len);
+                assert!(EncryptedLogWriter::open(&cut, PASSWORD).is_err(), "cut to {} bytes", len);
+            }
+        }
+        scratch.destroy().unwrap();
+    }
+
+    #[test]
+    fn hostile_headers_and_lengths() {
+        let scratch = Scratch::create().unwrap();
+        let path = write_log(&scratch, "a.log", &[b"aaaa"]);
+        let bytes = fs::read(&path).unwrap();
+        let hostile = scratch.dir.join("hostile.log");
+        let edits: [fn(&mut Vec<u8>); 5] = [
+            |log| log[0] = b'X',
+            |log| log[4] = 9,
+            |log| log[5..9].copy_from_slice(&0u32.to_le_bytes()),
+            |log| log[5..9].copy_from_slice(&u32::MAX.to_le_bytes()),
+            // The chunk size is covered by the header's tag.
+            |log| log[9 + SALT_LEN..13 + SALT_LEN].copy_from_slice(&(MAX_CHUNK_SIZE as u32).to_le_bytes()),
+        ];
+        for (number, edit) in edits.into_iter().enumerate() {
+            let mut log = bytes.clone();
+            edit(&mut log);
+            fs::write(&hostile, &log).unwrap();
+            assert!(EncryptedLogReader::open(&hostile, PASSWORD).is_err(), "edit {}", number);
+        }
+
+        // A record length past the chunk size is refused before anything
+        // that size is allocated.
+        let mut log = bytes.clone();
+        log[LOG_HEADER_LEN..LOG_HEADER_LEN + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        fs::write(&hostile, &log).unwrap();
+        let records = read_all(&hostile);
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].as_ref().unwrap_err(), &format::message(FormatError::Garbled));
+        scratch.destroy().unwrap();
+    }
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("10-20", 100), Ok(10..20));
+        assert_eq!(parse_range(" 10 - ", 100), Ok(10..100));
+        for invalid in ["", "10", "20-10", "a-b", "-5"] {
+            assert!(parse_range(invalid, 100).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,600 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::filter::Filter;
+use crate::format::{self, FormatError, Reader};
+use crate::strip;
+use crate::throttle;
+
+/// Bytes at the start of every folder archive.
+const ARCHIVE_MAGIC: &[u8; 4] = b"FARC";
+
+/// Version number stored right after the magic bytes.
+const ARCHIVE_VERSION: u8 = 1;
+
+/// Entry kinds stored in an archive.
+const KIND_DIRECTORY: u8 = 1;
+const KIND_FILE: u8 = 2;
+const KIND_HARD_LINK: u8 = 3;
+const KIND_SYMLINK: u8 = 4;
+
+/// Counts of what was packed or unpacked, plus anything that was skipped.
+#[derive(Debug, Default)]
+pub struct ArchiveSummary {
+    pub files: usize,
+    pub directories: usize,
+    pub hard_links: usize,
+    pub symlinks: usize,
+    /// Entries skipped because of the filter rules.
+    pub excluded: usize,
+    /// Files whose metadata was stripped before packing
+    /// (`--strip-metadata`).
+    pub stripped: usize,
+    /// Files that kept changing while they were read.
+    pub changed: usize,
+    pub warnings: Vec<String>,
+}
+
+/// Whether decrypted data is a folder archive rather than a single file.
+pub fn is_archive(data: &[u8]) -> bool {
+    data.starts_with(ARCHIVE_MAGIC)
+}
+
+/// Pack every file and folder under `root` that `filter` does not exclude
+/// into a single archive. Excluded folders are not descended into.
+///
+/// Files that share an inode are stored once; later paths are recorded as
+/// hard links to the first one, so the link structure survives a restore
+/// without duplicating data.
+pub fn pack(root: &Path, filter: &Filter) -> Result<(Vec<u8>, ArchiveSummary), String> {
+    let info = fs::metadata(root).map_err(|e| format!("Failed to read folder: {}", e))?;
+    if !info.is_dir() {
+        return Err(format!("'{}' is not a folder.", root.display()));
+    }
+
+    let mut packer = Packer {
+        root: root.to_path_buf(),
+        filter,
+        bytes: Vec::new(),
+        seen_inodes: HashMap::new(),
+        summary: ArchiveSummary::default(),
+    };
+    packer.bytes.extend_from_slice(ARCHIVE_MAGIC);
+    packer.bytes.push(ARCHIVE_VERSION);
+    packer.walk(Path::new(""))?;
+
+    Ok((packer.bytes, packer.summary))
+}
+
+/// Recreate the contents of an archive inside `dest`, which must not
+/// exist yet or be an empty folder.
+///
+/// If the operation is cancelled partway, everything unpacked so far is
+/// removed again.
+pub fn unpack(data: &[u8], dest: &Path) -> Result<ArchiveSummary, String> {
+    let existed = dest.exists();
+    let result = unpack_into(data, dest);
+    if result.is_err() && crate::progress::cancelled() {
+        if existed {
+            // It was empty to begin with.
+            if let Ok(entries) = fs::read_dir(dest) {
+                for entry in entries.flatten() {
+                    let path = entry.path();
+                    let _ = if path.is_dir() && !path.is_symlink() {
+                        fs::remove_dir_all(&path)
+                    } else {
+                        fs::remove_file(&path)
+                    };
+                }
+            }
+        } else {
+            let _ = fs::remove_dir_all(dest);
+        }
+    }
+    result
+}
+
+fn unpack_into(data: &[u8], dest: &Path) -> Result<ArchiveSummary, String> {
+    let mut reader = Reader::new(data);
+    if reader.take(ARCHIVE_MAGIC.len()).map_err(damaged)? != ARCHIVE_MAGIC {
+        return Err("The decrypted data is not a folder archive.".to_string());
+    }
+    let version = reader.u8().map_err(damaged)?;
+    if version != ARCHIVE_VERSION {
+        return Err(format!("Unsupported folder archive version {}.", version));
+    }
+
+    if dest.exists() {
+        let mut entries = fs::read_dir(dest)
+            .map_err(|e| format!("Failed to read '{}': {}", dest.display(), e))?;
+        if entries.next().is_some() {
+            return Err(format!(
+                "'{}' already exists and is not empty.",
+                dest.display()
+            ));
+        }
+    }
+    fs::create_dir_all(dest)
+        .map_err(|e| format!("Failed to create '{}': {}", dest.display(), e))?;
+
+    let mut summary = ArchiveSummary::default();
+    // Folder permissions are applied last so read-only folders can still be filled.
+    let mut folder_modes = Vec::new();
+    // The kind of every entry so far. An archive names each path once, so
+    // a repeated one could only be meant to write through what the first
+    // one created, such as a symlink pointing outside the destination.
+    let mut unpacked: HashMap<PathBuf, u8> = HashMap::new();
+
+    while !reader.is_empty() {
+        let kind = reader.u8().map_err(damaged)?;
+        let relative = safe_relative_path(reader.sized_bytes().map_err(damaged)?)?;
+        let mode = reader.u32().map_err(damaged)?;
+        let target = dest.join(&relative);
+        reject_symlink_parents(dest, &relative)?;
+        if unpacked.insert(relative.clone(), kind).is_some() {
+            return Err(format!(
+                "The archive contains '{}' more than once.",
+                relative.display()
+            ));
+        }
+        // Only a folder made for an earlier entry inside it may already be
+        // there; anything else would be written through or replaced.
+        if let Ok(info) = fs::symlink_metadata(&target)
+            && (kind != KIND_DIRECTORY || !info.is_dir())
+        {
+            return Err(format!("'{}' already exists.", target.display()));
+        }
+
+        if kind != KIND_DIRECTORY
+            && let Some(parent) = target.parent()
+        {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create '{}': {}", parent.display(), e))?;
+        }
+
+        match kind {
+            KIND_DIRECTORY => {
+                fs::create_dir_all(&target)
+                    .map_err(|e| format!("Failed to create '{}': {}", target.display(), e))?;
+                folder_modes.push((target, mode));
+                summary.directories += 1;
+            }
+            KIND_FILE => {
+                let len = reader.len64().map_err(damaged)?;
+                let contents = reader.take(len).map_err(damaged)?;
+                let file = throttle::write_new(&target, contents)
+                    .map_err(|e| format!("Failed to write '{}': {}", target.display(), e))?;
+                set_file_mode(&file, mode);
+                summary.files += 1;
+            }
+            KIND_HARD_LINK => {
+                let original = safe_relative_path(reader.sized_bytes().map_err(damaged)?)?;
+                if unpacked.get(&original) != Some(&KIND_FILE) {
+                    return Err(format!(
+                        "The archive links '{}' to '{}', which is not a file unpacked before it.",
+                        relative.display(),
+                        original.display()
+                    ));
+                }
+                let original = dest.join(original);
+                fs::hard_link(&original, &target)
+                    .map_err(|e| format!("Failed to link '{}': {}", target.display(), e))?;
+                summary.hard_links += 1;
+            }
+            KIND_SYMLINK => {
+                let link_target = reader.sized_bytes().map_err(damaged)?;
+                match create_symlink(link_target, &target) {
+                    Ok(()) => summary.symlinks += 1,
+                    Err(e) => summary.warnings.push(format!(
+                        "Skipped symlink '{}': {}",
+                        target.display(),
+                        e
+                    )),
+                }
+            }
+            _ => return Err(corrupt()),
+        }
+    }
+
+    for (folder, mode) in folder_modes.into_iter().rev() {
+        // Never follow a symlink put in a folder's place.
+        if fs::symlink_metadata(&folder).is_ok_and(|info| info.is_dir()) {
+            set_mode(&folder, mode);
+        }
+    }
+
+    Ok(summary)
+}
+
+/// State carried while walking a folder tree.
+struct Packer<'a> {
+    root: PathBuf,
+    filter: &'a Filter,
+    bytes: Vec<u8>,
+    /// First archive path seen for each (device, inode) pair.
+    seen_inodes: HashMap<(u64, u64), Vec<u8>>,
+    summary: ArchiveSummary,
+}
+
+impl Packer<'_> {
+    /// Add every entry inside the folder at `relative` (relative to the root).
+    fn walk(&mut self, relative: &Path) -> Result<(), String> {
+        let folder = self.root.join(relative);
+        let mut entries: Vec<_> = fs::read_dir(&folder)
+            .map_err(|e| format!("Failed to read '{}': {}", folder.display(), e))?
+            .filter_map(|entry| entry.ok())
+            .collect();
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let child = relative.join(entry.file_name());
+            let full = self.root.join(&child);
+            let info = fs::symlink_metadata(&full)
+                .map_err(|e| format!("Failed to read '{}': {}", full.display(), e))?;
+            let name = path_bytes(&child)?;
+            let mode = file_mode(&info);
+
+            if self.filter.is_excluded(&name, &info) {
+                self.summary.excluded += 1;
+                continue;
+            }
+
+            if info.file_type().is_symlink() {
+                let link_target = fs::read_link(&full)
+                    .map_err(|e| format!("Failed to read link '{}': {}", full.display(), e))?;
+                self.push_header(KIND_SYMLINK, &name, mode);
+                push_sized(&mut self.bytes, &path_bytes(&link_target)?);
+                self.summary.symlinks += 1;
+            } else if info.is_dir() {
+                self.push_header(KIND_DIRECTORY, &name, mode);
+                self.summary.directories += 1;
+                self.walk(&child)?;
+            } else if info.is_file() {
+                if let Some(original) = inode_key(&info).and_then(|key| self.seen_inodes.get(&key))
+                {
+                    let original = original.clone();
+                    self.push_header(KIND_HARD_LINK, &name, mode);
+                    push_sized(&mut self.bytes, &original);
+                    self.summary.hard_links += 1;
+                    continue;
+                }
+
+                let (contents, changed) = throttle::read_consistent(&full)
+                    .map_err(|e| format!("Failed to read '{}': {}", full.display(), e))?;
+                if changed {
+                    self.summary.changed += 1;
+                    self.summary
+                        .warnings
+                        .push(crate::input_changed_warning(&full.display().to_string()));
+                }
+                let (contents, stripped) = strip::strip_if_enabled(contents);
+                if stripped.is_some() {
+                    self.summary.stripped += 1;
+                }
+                self.push_header(KIND_FILE, &name, mode);
+                self.bytes
+                    .extend_from_slice(&(contents.len() as u64).to_le_bytes());
+                self.bytes.extend_from_slice(&contents);
+                self.summary.files += 1;
+
+                if let Some(key) = inode_key(&info) {
+                    self.seen_inodes.insert(key, name);
+                }
+            } else {
+                self.summary.warnings.push(format!(
+                    "Skipped '{}': not a regular file or folder.",
+                    full.display()
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write the fields every entry starts with.
+    fn push_header(&mut self, kind: u8, name: &[u8], mode: u32) {
+        self.bytes.push(kind);
+        push_sized(&mut self.bytes, name);
+        self.bytes.extend_from_slice(&mode.to_le_bytes());
+    }
+}
+
+/// Error for an archive that ends early or has impossible values.
+fn corrupt() -> String {
+    "The folder archive is damaged.".to_string()
+}
+
+/// Report a field that could not be read as damage to the archive, unless
+/// it is too large for this machine, which is worth saying as such.
+fn damaged(error: FormatError) -> String {
+    match error {
+        FormatError::TooLarge(_) => format::message(error),
+        _ => corrupt(),
+    }
+}
+
+/// Append a u32 length followed by the bytes.
+fn push_sized(bytes: &mut Vec<u8>, data: &[u8]) {
+    bytes.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(data);
+}
+
+/// Turn a stored name back into a relative path, refusing anything that
+/// could escape the destination folder.
+pub fn safe_relative_path(bytes: &[u8]) -> Result<PathBuf, String> {
+    let path = path_from_bytes(bytes)?;
+    let is_safe = !bytes.is_empty()
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)));
+    if !is_safe {
+        return Err(format!(
+            "The archive contains an unsafe path '{}'.",
+            path.display()
+        ));
+    }
+    Ok(path)
+}
+
+/// Refuse to write through a symlink that an earlier entry created, which
+/// would let a crafted archive place files outside the destination.
+fn reject_symlink_parents(dest: &Path, relative: &Path) -> Result<(), String> {
+    let mut current = dest.to_path_buf();
+    if let Some(parent) = relative.parent() {
+        for component in parent.components() {
+            current.push(component);
+            let is_symlink = fs::symlink_metadata(&current)
+                .map(|info| info.file_type().is_symlink())
+                .unwrap_or(false);
+            if is_symlink {
+                return Err(format!(
+                    "The archive writes through a symlink at '{}'.",
+                    current.display()
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Identify the underlying file so hard links can be spotted.
+#[cfg(unix)]
+fn inode_key(info: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (info.nlink() > 1).then(|| (info.dev(), info.ino()))
+}
+
+#[cfg(not(unix))]
+fn inode_key(_info: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+/// Permission bits to store for an entry.
+#[cfg(unix)]
+fn file_mode(info: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    info.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(_info: &fs::Metadata) -> u32 {
+    0
+}
+
+/// Apply stored permission bits. Failures are ignored, since the contents
+/// matter more than the exact permissions.
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: u32) {
+    use std::os::unix::fs::PermissionsExt;
+    let _ = fs::set_permissions(path, fs::Permissions::from_mode(mode));
+}
+
+#[cfg(not(unix))]
+fn set_mode(_path: &Path, _mode: u32) {}
+
+/// Apply stored permission bits to a file just written, through its handle
+/// so that nothing put at its path since can be reached.
+#[cfg(unix)]
+fn set_file_mode(file: &fs::File, mode: u32) {
+    use std::os::unix::fs::PermissionsExt;
+    let _ = file.set_permissions(fs::Permissions::from_mode(mode));
+}
+
+#[cfg(not(unix))]
+fn set_file_mode(_file: &fs::File, _mode: u32) {}
+
+/// Recreate a symbolic link.
+#[cfg(unix)]
+fn create_symlink(link_target: &[u8], path: &Path) -> std::io::Result<()> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    std::os::unix::fs::symlink(OsStr::from_bytes(link_target), path)
+}
+
+#[cfg(not(unix))]
+fn create_symlink(_link_target: &[u8], _path: &Path) -> std::io::Result<()> {
+    Err(std::io::Error::new(
+        std::io::ErrorKind::Unsupported,
+        "symlinks are not supported on this platform",
+    ))
+}
+
+/// Store a relative path with `/` separators. Unix names are kept byte for
+/// byte, so names that are not valid UTF-8 still round-trip.
+#[cfg(unix)]
+pub fn path_bytes(path: &Path) -> Result<Vec<u8>, String> {
+    use std::os::unix::ffi::OsStrExt;
+    Ok(path.as_os_str().as_bytes().to_vec())
+}
+
+#[cfg(not(unix))]
+pub fn path_bytes(path: &Path) -> Result<Vec<u8>, String> {
+    let text = path
+        .to_str()
+        .ok_or_else(|| format!("'{}' is not a valid UTF-8 path.", path.display()))?;
+    Ok(text.replace('\\', "/").into_bytes())
+}
+
+/// Turn stored path bytes back into a path.
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf, String> {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    Ok(PathBuf::from(OsStr::from_bytes(bytes)))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> Result<PathBuf, String> {
+    let text = std::str::from_utf8(bytes).map_err(|_| corrupt())?;
+    Ok(PathBuf::from(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A new, empty folder for one test, removed by the test when it passes.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("file_encryptor_archive_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// An archive of the given entries, each already encoded by `entry`.
+    fn archive(entries: &[Vec<u8>]) -> Vec<u8> {
+        let mut bytes = ARCHIVE_MAGIC.to_vec();
+        bytes.push(ARCHIVE_VERSION);
+        entries.iter().for_each(|entry| bytes.extend_from_slice(entry));
+        bytes
+    }
+
+    /// One entry: the header, then what its kind stores after it.
+    fn entry(kind: u8, name: &[u8], rest: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![kind];
+        push_sized(&mut bytes, name);
+        bytes.extend_from_slice(&0o644u32.to_le_bytes());
+        bytes.extend_from_slice(rest);
+        bytes
+    }
+
+    fn file(name: &[u8], contents: &[u8]) -> Vec<u8> {
+        let mut rest = (contents.len() as u64).to_le_bytes().to_vec();
+        rest.extend_from_slice(contents);
+        entry(KIND_FILE, name, &rest)
+    }
+
+    fn sized(data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        push_sized(&mut bytes, data);
+        bytes
+    }
+
+    #[test]
+    fn pack_and_unpack_round_trip() {
+        let dir = test_dir("round_trip");
+        let source = dir.join("source");
+        fs::create_dir_all(source.join("folder/empty")).unwrap();
+        fs::write(source.join("a.txt"), b"alpha").unwrap();
+        fs::write(source.join("folder/b.bin"), [0u8, 1, 2, 255]).unwrap();
+        #[cfg(unix)]
+        {
+            fs::hard_link(source.join("a.txt"), source.join("folder/a-link")).unwrap();
+            std::os::unix::fs::symlink("../a.txt", source.join("folder/a-symlink")).unwrap();
+        }
+        let (bytes, packed) = pack(&source, &Filter::default()).unwrap();
+        assert!(is_archive(&bytes));
+        assert_eq!((packed.files, packed.directories), (2, 2));
+
+        let dest = dir.join("dest");
+        let unpacked = unpack(&bytes, &dest).unwrap();
+        assert_eq!((unpacked.files, unpacked.directories), (2, 2));
+        assert_eq!(fs::read(dest.join("a.txt")).unwrap(), b"alpha");
+        assert_eq!(fs::read(dest.join("folder/b.bin")).unwrap(), [0u8, 1, 2, 255]);
+        assert!(dest.join("folder/empty").is_dir());
+        #[cfg(unix)]
+        {
+            assert_eq!((unpacked.hard_links, unpacked.symlinks), (1, 1));
+            assert_eq!(fs::read(dest.join("folder/a-link")).unwrap(), b"alpha");
+            assert_eq!(fs::read_link(dest.join("folder/a-symlink")).unwrap(), Path::new("../a.txt"));
+        }
+        // The destination has to be empty.
+        assert!(unpack(&bytes, &dest).unwrap_err().contains("not empty"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn truncated_archives_never_unpack_partial_files() {
+        let dir = test_dir("truncated");
+        let contents: Vec<u8> = (0..200).map(|index| index as u8).collect();
+        let bytes = archive(&[
+            entry(KIND_DIRECTORY, b"folder", &[]),
+            file(b"folder/one", &contents),
+            file(b"two", b"second"),
+        ]);
+        for len in 0..bytes.len() {
+            let dest = dir.join(format!("cut-{}", len));
+            if unpack(&bytes[..len], &dest).is_ok() {
+                for (name, expected) in [("folder/one", &contents[..]), ("two", b"second")] {
+                    if let Ok(found) = fs::read(dest.join(name)) {
+                        assert_eq!(found, expected, "cut to {} bytes", len);
+                    }
+                }
+            }
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hostile_entries_are_refused() {
+        let dir = test_dir("hostile");
+        let cases: [(&str, Vec<u8>); 9] = [
+            ("parent", archive(&[file(b"../escape", b"x")])),
+            ("absolute", archive(&[file(b"/tmp/escape", b"x")])),
+            ("empty name", archive(&[file(b"", b"x")])),
+            ("dot", archive(&[file(b"./a/../../escape", b"x")])),
+            ("duplicate", archive(&[file(b"a", b"x"), file(b"a", b"y")])),
+            ("file over folder", archive(&[entry(KIND_DIRECTORY, b"a", &[]), file(b"a", b"x")])),
+            ("link to nothing", archive(&[entry(KIND_HARD_LINK, b"a", &sized(b"missing"))])),
+            ("unknown kind", archive(&[entry(9, b"a", &[])])),
+            ("huge length", archive(&[entry(KIND_FILE, b"a", &u64::MAX.to_le_bytes())])),
+        ];
+        for (what, bytes) in cases {
+            assert!(unpack(&bytes, &dir.join(what)).is_err(), "{}", what);
+        }
+        assert!(!dir.join("escape").exists());
+        let mut version = archive(&[]);
+        version[4] = 2;
+        assert!(unpack(&version, &dir.join("version")).is_err());
+        assert!(unpack(b"FENC", &dir.join("magic")).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_are_not_written_through() {
+        let dir = test_dir("symlinks");
+        let outside = dir.join("outside");
+        fs::create_dir(&outside).unwrap();
+        fs::write(outside.join("target"), b"untouched").unwrap();
+        let link = |name: &[u8]| entry(KIND_SYMLINK, name, &sized(outside.to_str().unwrap().as_bytes()));
+        let file_link = entry(KIND_SYMLINK, b"l", &sized(outside.join("target").to_str().unwrap().as_bytes()));
+
+        let cases: [(&str, Vec<u8>); 4] = [
+            // A file inside a folder that is a symlink.
+            ("through a folder", archive(&[link(b"l"), file(b"l/target", b"overwritten")])),
+            ("deeper", archive(&[link(b"l"), entry(KIND_DIRECTORY, b"l/sub", &[]), file(b"l/sub/x", b"x")])),
+            // A file where a symlink to one was just made.
+            ("onto a symlink", archive(&[file_link.clone(), file(b"l", b"overwritten")])),
+            // A hard link made to the symlink instead of a file.
+            ("link to a symlink", archive(&[file_link, entry(KIND_HARD_LINK, b"h", &sized(b"l"))])),
+        ];
+        for (what, bytes) in cases {
+            assert!(unpack(&bytes, &dir.join(what)).is_err(), "{}", what);
+            assert_eq!(fs::read(outside.join("target")).unwrap(), b"untouched", "{}", what);
+            assert_eq!(fs::read_dir(&outside).unwrap().count(), 1, "{}", what);
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,191 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::OpenError;
+use crate::config::Config;
+use crate::format::FormatError;
+
+/// Longest wait between two attempts, however many have failed.
+const MAX_DELAY_SECONDS: u64 = 300;
+
+/// Failed password attempts on one vault or container, kept in a file
+/// next to it (`FILE.attempts`) so that restarting the tool does not reset
+/// them. This slows down someone guessing at an unlocked workstation; it
+/// does nothing against a copy of the file taken elsewhere.
+pub struct Attempts {
+    path: PathBuf,
+    failures: u32,
+    last_failure: u64,
+    delay_seconds: u64,
+    max_failures: u32,
+    lockout_minutes: u64,
+}
+
+impl Attempts {
+    /// Load the failure count for `target`. Fails if the target is locked
+    /// out; otherwise waits out any delay earned by earlier failures
+    /// before returning, so the caller may try a password.
+    pub fn before_unlock(target: &Path, config: &Config) -> Result<Self, String> {
+        let mut path = target.as_os_str().to_owned();
+        path.push(".attempts");
+        let path = PathBuf::from(path);
+        let (failures, last_failure) = fs::read_to_string(&path)
+            .ok()
+            .and_then(|contents| {
+                let (failures, last) = contents.trim().split_once(' ')?;
+                Some((failures.parse().ok()?, last.parse().ok()?))
+            })
+            .unwrap_or((0, 0));
+        let attempts = Attempts {
+            path,
+            failures,
+            last_failure,
+            delay_seconds: config.unlock_delay_seconds,
+            max_failures: config.unlock_max_failures,
+            lockout_minutes: config.unlock_lockout_minutes,
+        };
+
+        let since_failure = now().saturating_sub(attempts.last_failure);
+        if attempts.max_failures > 0 && attempts.failures >= attempts.max_failures {
+            let lockout = attempts.lockout_minutes * 60;
+            if since_failure < lockout {
+                return Err(format!(
+                    "Locked after {} failed password attempts. Try again in {} minute(s).",
+                    attempts.failures,
+                    (lockout - since_failure).div_ceil(60)
+                ));
+            }
+        }
+        let wait = attempts.delay().saturating_sub(since_failure);
+        if wait > 0 {
+            println!(
+                "Waiting {} second(s) because of earlier failed attempts...",
+                wait
+            );
+            thread::sleep(Duration::from_secs(wait));
+        }
+        Ok(attempts)
+    }
+
+    /// Record how an unlock went. A wrong password adds to the count; a
+    /// right one clears it. Other errors, such as an unreadable file, do
+    /// not count. Returns `result` with its error as a message.
+    pub fn record<T>(mut self, result: Result<T, OpenError>) -> Result<T, String> {
+        match &result {
+            Ok(_) if self.failures > 0 => {
+                let _ = fs::remove_file(&self.path);
+            }
+            Err(OpenError::Format(FormatError::AuthenticationFailed)) => {
+                self.failures += 1;
+                self.last_failure = now();
+                if let Err(e) = self.save() {
+                    eprintln!("Warning: {}", e);
+                }
+            }
+            _ => {}
+        }
+        Ok(result?)
+    }
+
+    /// How long to wait after the last failure: the configured delay,
+    /// doubled for each further failure in a row.
+    fn delay(&self) -> u64 {
+        if self.failures == 0 {
+            return 0;
+        }
+        let doublings = (self.failures - 1).min(16);
+        self.delay_seconds
+            .saturating_mul(1 << doublings)
+            .min(MAX_DELAY_SECONDS)
+    }
+
+    fn save(&self) -> Result<(), String> {
+        let mut options = OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options
+            .open(&self.path)
+            .and_then(|mut file| {
+                writeln!(file, "{} {}", self.failures, self.last_failure)
+            })
+            .map_err(|e| format!("Failed to save '{}': {}", self.path.display(), e))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn failures_are_counted_until_locked_out() {
+        let target = std::env::temp_dir().join(format!("file_encryptor_attempts_{}", std::process::id()));
+        let config = Config {
+            unlock_delay_seconds: 0,
+            unlock_max_failures: 2,
+            ..Config::default()
+        };
+        let wrong = || Err::<(), _>(OpenError::Format(FormatError::AuthenticationFailed));
+
+        // Errors other than a wrong password do not count, even one whose
+        // message reads the same.
+        let attempts = Attempts::before_unlock(&target, &config).unwrap();
+        assert!(attempts.record(Err::<(), _>(OpenError::Other("unreadable".to_string()))).is_err());
+        let attempts = Attempts::before_unlock(&target, &config).unwrap();
+        let lookalike = OpenError::Other(crate::format::message(FormatError::AuthenticationFailed));
+        assert!(attempts.record(Err::<(), _>(lookalike)).is_err());
+        let attempts = Attempts::before_unlock(&target, &config).unwrap();
+        assert_eq!(attempts.failures, 0);
+        assert!(attempts.record(wrong()).is_err());
+
+        // A right password clears the count.
+        let attempts = Attempts::before_unlock(&target, &config).unwrap();
+        assert_eq!(attempts.failures, 1);
+        attempts.record(Ok(())).unwrap();
+        let attempts = Attempts::before_unlock(&target, &config).unwrap();
+        assert_eq!(attempts.failures, 0);
+
+        let _ = attempts.record(wrong());
+        let _ = Attempts::before_unlock(&target, &config).unwrap().record(wrong());
+        let error = Attempts::before_unlock(&target, &config).err().unwrap();
+        assert!(error.contains("Try again in 15 minute(s)"), "{}", error);
+
+        let mut counts = target.into_os_string();
+        counts.push(".attempts");
+        fs::remove_file(counts).unwrap();
+    }
+
+    #[test]
+    fn the_delay_doubles_up_to_a_limit() {
+        let mut attempts = Attempts {
+            path: PathBuf::new(),
+            failures: 0,
+            last_failure: 0,
+            delay_seconds: 2,
+            max_failures: 0,
+            lockout_minutes: 0,
+        };
+        let delays: Vec<u64> = (0..5)
+            .map(|failures| {
+                attempts.failures = failures;
+                attempts.delay()
+            })
+            .collect();
+        assert_eq!(delays, [0, 2, 4, 8, 16]);
+        attempts.failures = u32::MAX;
+        assert_eq!(attempts.delay(), MAX_DELAY_SECONDS);
+    }
+}
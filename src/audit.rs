@@ -0,0 +1,260 @@
+use std::env;
+use std::path::Path;
+
+use crate::checksum::{self, to_hex};
+use crate::config::Config;
+use crate::hooks::HookEvent;
+use crate::policy;
+
+/// Name the events are logged under in syslog.
+const IDENTIFIER: &str = "file_encryptor";
+
+/// Windows Event Log source used when none is configured.
+const DEFAULT_EVENT_SOURCE: &str = "File Encryptor";
+
+/// Sends an audit event to the system log after every encrypt and
+/// decrypt, so log collectors can track use of the tool without parsing
+/// the history file. Events name the user and the result; paths are only
+/// given as SHA-256 hashes, so the log does not reveal file names but a
+/// known path can still be matched.
+pub struct Auditor {
+    /// Send events to syslog or journald (Unix).
+    syslog: bool,
+    /// Event Log source to write events under (Windows), or `None` to not
+    /// write them there.
+    event_source: Option<String>,
+}
+
+impl Auditor {
+    /// Turn auditing on if the config or the organization policy asks
+    /// for it. A source named by the policy wins over the config's.
+    pub fn from_config(config: &Config) -> Self {
+        let policy = policy::get();
+        let event_log = config.audit_event_log || policy.audit_event_log;
+        Auditor {
+            syslog: config.audit_syslog || policy.audit_syslog,
+            event_source: event_log.then(|| {
+                policy
+                    .audit_event_source
+                    .clone()
+                    .or_else(|| config.audit_event_source.clone())
+                    .unwrap_or_else(|| DEFAULT_EVENT_SOURCE.to_string())
+            }),
+        }
+    }
+
+    /// Log a finished operation. Returns a warning if the event could not
+    /// be delivered.
+    pub fn operation_finished(&self, event: &HookEvent) -> Option<String> {
+        if !self.syslog && self.event_source.is_none() {
+            return None;
+        }
+        let record = Record {
+            action: event.action,
+            result: if event.error.is_none() { "success" } else { "failure" },
+            user: user_name(),
+            input_hash: path_hash(event.input),
+            output_hash: path_hash(event.output),
+            error: event.error,
+        };
+        let mut problems = Vec::new();
+        if self.syslog
+            && let Err(e) = send_syslog(&record)
+        {
+            problems.push(format!("Failed to write the audit event to the system log: {}", e));
+        }
+        if let Some(source) = &self.event_source
+            && let Err(e) = send_event_log(source, &record)
+        {
+            problems.push(format!("Failed to write the audit event to the Event Log: {}", e));
+        }
+        (!problems.is_empty()).then(|| problems.join(" "))
+    }
+}
+
+/// One audit event.
+struct Record<'a> {
+    action: &'a str,
+    result: &'a str,
+    user: String,
+    input_hash: String,
+    output_hash: String,
+    error: Option<&'a str>,
+}
+
+impl Record<'_> {
+    /// The event as one line of `key=value` pairs, for plain syslog and
+    /// the Event Log.
+    fn message(&self) -> String {
+        let mut message = format!(
+            "action={} result={} user={} input_sha256={} output_sha256={}",
+            self.action, self.result, self.user, self.input_hash, self.output_hash
+        );
+        if let Some(error) = self.error {
+            message.push_str(&format!(" error={:?}", error));
+        }
+        message
+    }
+}
+
+/// Send the event to journald's native socket, where every value becomes
+/// its own field, or failing that to the classic syslog socket.
+#[cfg(unix)]
+fn send_syslog(record: &Record) -> std::io::Result<()> {
+    use std::os::unix::net::UnixDatagram;
+
+    const JOURNAL_SOCKET: &str = "/run/systemd/journal/socket";
+    #[cfg(target_os = "macos")]
+    const SYSLOG_SOCKET: &str = "/var/run/syslog";
+    #[cfg(not(target_os = "macos"))]
+    const SYSLOG_SOCKET: &str = "/dev/log";
+    const LOG_USER: u8 = 1 << 3;
+    const LOG_WARNING: u8 = 4;
+    const LOG_INFO: u8 = 6;
+
+    let severity = if record.error.is_none() { LOG_INFO } else { LOG_WARNING };
+    let socket = UnixDatagram::unbound()?;
+    if Path::new(JOURNAL_SOCKET).exists() {
+        let mut fields = vec![
+            ("MESSAGE", record.message()),
+            ("PRIORITY", severity.to_string()),
+            ("SYSLOG_IDENTIFIER", IDENTIFIER.to_string()),
+            ("FILE_ENCRYPTOR_ACTION", record.action.to_string()),
+            ("FILE_ENCRYPTOR_RESULT", record.result.to_string()),
+            ("FILE_ENCRYPTOR_USER", record.user.clone()),
+            ("FILE_ENCRYPTOR_INPUT_SHA256", record.input_hash.clone()),
+            ("FILE_ENCRYPTOR_OUTPUT_SHA256", record.output_hash.clone()),
+        ];
+        if let Some(error) = record.error {
+            fields.push(("FILE_ENCRYPTOR_ERROR", error.to_string()));
+        }
+        // Each field is written as NAME, a newline, the value's length as
+        // a 64-bit little-endian number, and the value, so values may hold
+        // newlines.
+        let mut datagram = Vec::new();
+        for (name, value) in fields {
+            datagram.extend_from_slice(name.as_bytes());
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+            datagram.extend_from_slice(value.as_bytes());
+            datagram.push(b'\n');
+        }
+        if socket.send_to(&datagram, JOURNAL_SOCKET).is_ok() {
+            return Ok(());
+        }
+    }
+
+    let line = format!(
+        "<{}>{}[{}]: {}",
+        LOG_USER | severity,
+        IDENTIFIER,
+        std::process::id(),
+        record.message().replace('\n', " ")
+    );
+    socket.send_to(line.as_bytes(), SYSLOG_SOCKET).map(|_| ())
+}
+
+#[cfg(not(unix))]
+fn send_syslog(_record: &Record) -> std::io::Result<()> {
+    Err(std::io::Error::other("audit_syslog is only available on Unix"))
+}
+
+/// Report the event to the Application log under `source`. Successes are
+/// information events with ID 1, failures warnings with ID 2, and the
+/// message is the same `key=value` line syslog gets.
+#[cfg(windows)]
+fn send_event_log(source: &str, record: &Record) -> std::io::Result<()> {
+    use std::ffi::c_void;
+
+    #[link(name = "advapi32")]
+    unsafe extern "system" {
+        fn RegisterEventSourceW(server: *const u16, source: *const u16) -> *mut c_void;
+        fn ReportEventW(
+            log: *mut c_void,
+            kind: u16,
+            category: u16,
+            event_id: u32,
+            user_sid: *mut c_void,
+            string_count: u16,
+            data_size: u32,
+            strings: *const *const u16,
+            data: *const c_void,
+        ) -> i32;
+        fn DeregisterEventSource(log: *mut c_void) -> i32;
+    }
+    const EVENTLOG_WARNING_TYPE: u16 = 0x0002;
+    const EVENTLOG_INFORMATION_TYPE: u16 = 0x0004;
+
+    let wide = |text: &str| text.encode_utf16().chain([0]).collect::<Vec<u16>>();
+    let source = wide(source);
+    let message = wide(&record.message());
+    let (kind, event_id) = match record.error {
+        None => (EVENTLOG_INFORMATION_TYPE, 1),
+        Some(_) => (EVENTLOG_WARNING_TYPE, 2),
+    };
+
+    // SAFETY: both strings are NUL-terminated UTF-16 that outlive the
+    // calls; a null server means the local computer.
+    let log = unsafe { RegisterEventSourceW(std::ptr::null(), source.as_ptr()) };
+    if log.is_null() {
+        return Err(std::io::Error::last_os_error());
+    }
+    let strings = [message.as_ptr()];
+    // SAFETY: `log` is the handle just opened, `strings` holds one valid
+    // string pointer, and no binary data is passed.
+    let reported = unsafe {
+        ReportEventW(
+            log,
+            kind,
+            0,
+            event_id,
+            std::ptr::null_mut(),
+            1,
+            0,
+            strings.as_ptr(),
+            std::ptr::null(),
+        )
+    };
+    let result = if reported == 0 {
+        Err(std::io::Error::last_os_error())
+    } else {
+        Ok(())
+    };
+    // SAFETY: `log` came from RegisterEventSourceW and is closed once.
+    unsafe { DeregisterEventSource(log) };
+    result
+}
+
+#[cfg(not(windows))]
+fn send_event_log(_source: &str, _record: &Record) -> std::io::Result<()> {
+    Err(std::io::Error::other("audit_event_log is only available on Windows"))
+}
+
+/// The name of the user running the tool, with the numeric user ID on
+/// Unix, which cannot be changed through the environment.
+pub fn user_name() -> String {
+    let name = ["USER", "LOGNAME", "USERNAME"]
+        .iter()
+        .find_map(|name| env::var(name).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_else(|| "unknown".to_string());
+    #[cfg(unix)]
+    {
+        unsafe extern "C" {
+            fn getuid() -> u32;
+        }
+        // SAFETY: getuid has no preconditions and cannot fail.
+        let uid = unsafe { getuid() };
+        format!("{}({})", name, uid)
+    }
+    #[cfg(not(unix))]
+    name
+}
+
+/// SHA-256 of the absolute form of `path`, or an empty string for none.
+fn path_hash(path: &str) -> String {
+    if path.is_empty() {
+        return String::new();
+    }
+    let absolute = std::path::absolute(Path::new(path)).unwrap_or_else(|_| Path::new(path).to_path_buf());
+    to_hex(&checksum::sha256(absolute.as_os_str().as_encoded_bytes()))
+}
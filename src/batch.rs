@@ -0,0 +1,258 @@
+use std::collections::HashSet;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::naming::Template;
+use crate::packed;
+use crate::paths;
+
+/// What to do when an output's name is already in use, on disk or by an
+/// earlier input of the same batch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnConflict {
+    /// Add a number to the name (`report-2.pdf.enc`).
+    Rename,
+    /// Leave the input out.
+    Skip,
+    /// Replace the existing file, which `undo` can bring back. Two inputs of
+    /// the same batch sharing a name is still an error.
+    Overwrite,
+}
+
+impl OnConflict {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text {
+            "rename" => Ok(OnConflict::Rename),
+            "skip" => Ok(OnConflict::Skip),
+            "overwrite" => Ok(OnConflict::Overwrite),
+            _ => Err(format!(
+                "'{}' is not a conflict policy; use rename, skip, or overwrite.",
+                text
+            )),
+        }
+    }
+}
+
+/// Where the outputs go and how they are named.
+pub struct Layout<'a> {
+    /// Folder to write every output into, created if missing. Without one,
+    /// each output is written next to its input.
+    pub output_dir: Option<&'a Path>,
+    /// Recreate each input's path, as given, under `output_dir` instead of
+    /// putting every output directly in it.
+    pub keep_structure: bool,
+    pub on_conflict: OnConflict,
+    /// Added to each input's name, such as `.enc`.
+    pub suffix: &'a str,
+    /// Names already inside the archive the outputs are added to, when
+    /// they go into one instead of onto disk. The disk is then not looked
+    /// at.
+    pub existing: Option<&'a HashSet<PathBuf>>,
+    /// Names the outputs with a template, such as
+    /// `{stem}_{date}_{hash8}.enc`, instead of adding the suffix to each
+    /// input's name.
+    pub name_template: Option<&'a Template>,
+}
+
+/// How much one run may take on, so a mistyped path such as `/` is
+/// refused before anything is written instead of starting on the whole
+/// disk. Folders are only walked until a limit is passed.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    pub max_files: Option<u64>,
+    pub max_total_bytes: Option<u64>,
+}
+
+impl Limits {
+    fn check(&self, files: usize, bytes: u64) -> Result<(), String> {
+        if let Some(max) = self.max_files.filter(|&max| files as u64 > max) {
+            return Err(format!(
+                "The paths given hold more than {} file(s), the most --max-files allows. Check the paths, or raise the limit.",
+                max
+            ));
+        }
+        if let Some(max) = self.max_total_bytes.filter(|&max| bytes > max) {
+            return Err(format!(
+                "The files in the paths given come to more than {}, the most --max-total-bytes allows. Check the paths, or raise the limit.",
+                size_text(max)
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// One input and where its output goes.
+pub struct Job {
+    pub input: PathBuf,
+    /// `None` when the input is skipped because its output already exists.
+    pub output: Option<PathBuf>,
+    /// Size of the input, or 0 for an entry inside an archive.
+    pub size: u64,
+}
+
+/// Expand `inputs` into files, folders contributing the files inside them
+/// in name order, and give each an output following `layout`. Every output
+/// is worked out before anything is written, so two inputs cannot end up
+/// overwriting each other halfway through a run. Fails as soon as the
+/// inputs pass `limits`.
+pub fn plan(inputs: &[String], layout: &Layout, limits: &Limits) -> Result<Vec<Job>, String> {
+    if layout.keep_structure && layout.output_dir.is_none() {
+        return Err("Keeping the folder structure needs an output folder.".to_string());
+    }
+    let mut files = Vec::new();
+    let mut total = 0;
+    for input in inputs {
+        collect(Path::new(input), &mut files, &mut total, limits)?;
+    }
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0);
+    let mut taken = HashSet::new();
+    let mut jobs = Vec::with_capacity(files.len());
+    for (index, (input, size)) in files.into_iter().enumerate() {
+        let mut wanted = output_path(&input, layout);
+        if let Some(template) = layout.name_template {
+            wanted.set_file_name(template.render(&input, index + 1, now)?);
+        }
+        let in_batch = taken.contains(&wanted);
+        let output = if !in_batch && !in_use(&wanted, layout) {
+            Some(wanted)
+        } else {
+            match layout.on_conflict {
+                OnConflict::Rename => {
+                    let base = packed::output_base(&input);
+                    // A templated name is numbered as it is, with nothing
+                    // added after it.
+                    let (name, suffix) = match layout.name_template {
+                        Some(_) => (wanted.file_name().unwrap_or_default(), ""),
+                        None => (base.file_name().unwrap_or_default(), layout.suffix),
+                    };
+                    Some(numbered(&wanted, name, suffix, layout, &taken))
+                }
+                OnConflict::Skip => None,
+                OnConflict::Overwrite if in_batch => {
+                    return Err(format!(
+                        "More than one input would be written to '{}'. Use --keep-structure or --on-conflict rename.",
+                        wanted.display()
+                    ));
+                }
+                OnConflict::Overwrite => Some(wanted),
+            }
+        };
+        if let Some(output) = &output {
+            taken.insert(output.clone());
+        }
+        jobs.push(Job { input, output, size });
+    }
+    Ok(jobs)
+}
+
+/// Create the folders `output` will be written into.
+pub fn prepare(output: &Path) -> Result<(), String> {
+    match output.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create folder '{}': {}", parent.display(), e)),
+        _ => Ok(()),
+    }
+}
+
+/// Add `path`, or the files inside it, to `files` with their sizes,
+/// keeping `total` as the sum of the sizes.
+fn collect(path: &Path, files: &mut Vec<(PathBuf, u64)>, total: &mut u64, limits: &Limits) -> Result<(), String> {
+    if packed::split(path).is_some() {
+        files.push((path.to_path_buf(), 0));
+        return limits.check(files.len(), *total);
+    }
+    let info = fs::metadata(path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    if !info.is_dir() {
+        files.push((path.to_path_buf(), info.len()));
+        *total = total.saturating_add(info.len());
+        return limits.check(files.len(), *total);
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)
+        .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    entries.sort();
+    for entry in entries {
+        let is_symlink = fs::symlink_metadata(&entry).is_ok_and(|info| info.file_type().is_symlink());
+        if !is_symlink {
+            collect(&entry, files, total, limits)?;
+        }
+    }
+    Ok(())
+}
+
+fn output_path(input: &Path, layout: &Layout) -> PathBuf {
+    // An entry inside an archive is named after the entry.
+    let input = packed::output_base(input);
+    let input = input.as_ref();
+    let Some(dir) = layout.output_dir else {
+        return paths::with_suffix(input, layout.suffix);
+    };
+    let relative: PathBuf = if layout.keep_structure {
+        // Roots, drive prefixes, and `..` are dropped so everything stays
+        // inside the output folder.
+        input
+            .components()
+            .filter(|component| matches!(component, Component::Normal(_)))
+            .collect()
+    } else {
+        input.file_name().map(PathBuf::from).unwrap_or_default()
+    };
+    dir.join(paths::with_suffix(&relative, layout.suffix))
+}
+
+/// Whether `output` is already on disk, or in the archive the outputs go
+/// into.
+fn in_use(output: &Path, layout: &Layout) -> bool {
+    match layout.existing {
+        Some(names) => names.contains(output),
+        None => output.exists(),
+    }
+}
+
+/// The first of `name-2.ext`, `name-3.ext`, ... (followed by `suffix`),
+/// for the input named `input_name`, that is neither in use nor taken by
+/// the batch.
+fn numbered(
+    wanted: &Path,
+    input_name: &OsStr,
+    suffix: &str,
+    layout: &Layout,
+    taken: &HashSet<PathBuf>,
+) -> PathBuf {
+    let base = Path::new(input_name);
+    let stem = base.file_stem().unwrap_or_default();
+    let mut number = 2;
+    loop {
+        let mut name = OsString::from(stem);
+        name.push(format!("-{}", number));
+        if let Some(extension) = base.extension() {
+            name.push(".");
+            name.push(extension);
+        }
+        name.push(suffix);
+        let candidate = wanted.with_file_name(name);
+        if !taken.contains(&candidate) && !in_use(&candidate, layout) {
+            return candidate;
+        }
+        number += 1;
+    }
+}
+
+/// `bytes` for people, such as `3.2 GiB`.
+pub fn size_text(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", size, UNITS[unit])
+}
@@ -0,0 +1,470 @@
+use std::cmp::Ordering;
+
+/// Odd primes below 2000, used to throw out most prime candidates before
+/// the slower Miller-Rabin test.
+const SMALL_PRIMES_LIMIT: u64 = 2000;
+
+/// Miller-Rabin rounds per candidate. The candidates are random rather
+/// than chosen by an adversary, so this many leaves a negligible chance of
+/// accepting a composite.
+const MILLER_RABIN_ROUNDS: usize = 24;
+
+/// An unsigned integer of any size, stored as 64-bit limbs, least
+/// significant first, with no high zero limbs. Only what the time-lock
+/// puzzle needs is implemented, and none of it runs in constant time.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct BigUint {
+    limbs: Vec<u64>,
+}
+
+impl BigUint {
+    pub fn from_u64(value: u64) -> Self {
+        BigUint::from_limbs(vec![value])
+    }
+
+    fn from_limbs(mut limbs: Vec<u64>) -> Self {
+        while limbs.last() == Some(&0) {
+            limbs.pop();
+        }
+        BigUint { limbs }
+    }
+
+    pub fn from_be_bytes(bytes: &[u8]) -> Self {
+        let limbs = bytes
+            .rchunks(8)
+            .map(|chunk| chunk.iter().fold(0u64, |limb, &byte| limb << 8 | byte as u64))
+            .collect();
+        BigUint::from_limbs(limbs)
+    }
+
+    /// The number as exactly `len` big-endian bytes. High bytes that do not
+    /// fit are dropped, so callers size `len` from the modulus.
+    pub fn to_be_bytes(&self, len: usize) -> Vec<u8> {
+        (0..len)
+            .rev()
+            .map(|i| {
+                let limb = self.limbs.get(i / 8).copied().unwrap_or(0);
+                (limb >> (8 * (i % 8))) as u8
+            })
+            .collect()
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.limbs.is_empty()
+    }
+
+    /// Number of significant bits.
+    pub fn bits(&self) -> u64 {
+        match self.limbs.last() {
+            Some(top) => self.limbs.len() as u64 * 64 - top.leading_zeros() as u64,
+            None => 0,
+        }
+    }
+
+    pub fn bit(&self, index: u64) -> bool {
+        let limb = self.limbs.get((index / 64) as usize).copied().unwrap_or(0);
+        limb >> (index % 64) & 1 == 1
+    }
+
+    pub fn add(&self, other: &BigUint) -> BigUint {
+        let len = self.limbs.len().max(other.limbs.len());
+        let mut limbs = Vec::with_capacity(len + 1);
+        let mut carry = false;
+        for i in 0..len {
+            let a = self.limbs.get(i).copied().unwrap_or(0);
+            let b = other.limbs.get(i).copied().unwrap_or(0);
+            let (sum, overflow1) = a.overflowing_add(b);
+            let (sum, overflow2) = sum.overflowing_add(carry as u64);
+            limbs.push(sum);
+            carry = overflow1 || overflow2;
+        }
+        limbs.push(carry as u64);
+        BigUint::from_limbs(limbs)
+    }
+
+    /// `self - other`. Panics if `other` is larger.
+    pub fn sub(&self, other: &BigUint) -> BigUint {
+        let mut limbs = self.limbs.clone();
+        assert!(!sub_in_place(&mut limbs, &other.limbs), "subtraction underflow");
+        BigUint::from_limbs(limbs)
+    }
+
+    pub fn mul(&self, other: &BigUint) -> BigUint {
+        let mut limbs = vec![0u64; self.limbs.len() + other.limbs.len()];
+        for (i, &a) in self.limbs.iter().enumerate() {
+            let mut carry = 0u128;
+            for (j, &b) in other.limbs.iter().enumerate() {
+                let sum = limbs[i + j] as u128 + a as u128 * b as u128 + carry;
+                limbs[i + j] = sum as u64;
+                carry = sum >> 64;
+            }
+            limbs[i + other.limbs.len()] = carry as u64;
+        }
+        BigUint::from_limbs(limbs)
+    }
+
+    /// `self` shifted right by `shift` bits.
+    pub fn shr(&self, shift: u64) -> BigUint {
+        let limb_shift = (shift / 64) as usize;
+        let bit_shift = shift % 64;
+        let limbs = (limb_shift..self.limbs.len())
+            .map(|i| {
+                let low = self.limbs[i] >> bit_shift;
+                let high = match (bit_shift, self.limbs.get(i + 1)) {
+                    (0, _) | (_, None) => 0,
+                    (_, Some(&next)) => next << (64 - bit_shift),
+                };
+                low | high
+            })
+            .collect();
+        BigUint::from_limbs(limbs)
+    }
+
+    /// `self mod modulus`, by binary long division. Slow, but only used
+    /// where a handful of reductions are needed. Panics if `modulus` is 0.
+    pub fn rem(&self, modulus: &BigUint) -> BigUint {
+        assert!(!modulus.is_zero(), "division by zero");
+        let len = modulus.limbs.len() + 1;
+        let mut remainder = vec![0u64; len];
+        for index in (0..self.bits()).rev() {
+            let mut carry = self.bit(index) as u64;
+            for limb in remainder.iter_mut() {
+                let next = *limb >> 63;
+                *limb = *limb << 1 | carry;
+                carry = next;
+            }
+            if compare(&remainder, &modulus.limbs) != Ordering::Less {
+                sub_in_place(&mut remainder, &modulus.limbs);
+            }
+        }
+        BigUint::from_limbs(remainder)
+    }
+
+    /// `self mod modulus` for a small modulus.
+    pub fn rem_u64(&self, modulus: u64) -> u64 {
+        self.limbs.iter().rev().fold(0u64, |remainder, &limb| {
+            ((((remainder as u128) << 64) | limb as u128) % modulus as u128) as u64
+        })
+    }
+}
+
+impl Ord for BigUint {
+    fn cmp(&self, other: &Self) -> Ordering {
+        compare(&self.limbs, &other.limbs)
+    }
+}
+
+impl PartialOrd for BigUint {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Compare two limb slices as numbers, ignoring high zero limbs.
+fn compare(a: &[u64], b: &[u64]) -> Ordering {
+    let len = a.len().max(b.len());
+    for i in (0..len).rev() {
+        let x = a.get(i).copied().unwrap_or(0);
+        let y = b.get(i).copied().unwrap_or(0);
+        if x != y {
+            return x.cmp(&y);
+        }
+    }
+    Ordering::Equal
+}
+
+/// `a -= b` in place. Returns true if it borrowed past the top of `a`.
+fn sub_in_place(a: &mut [u64], b: &[u64]) -> bool {
+    let mut borrow = false;
+    for (i, limb) in a.iter_mut().enumerate() {
+        let other = b.get(i).copied().unwrap_or(0);
+        let (difference, borrow1) = limb.overflowing_sub(other);
+        let (difference, borrow2) = difference.overflowing_sub(borrow as u64);
+        *limb = difference;
+        borrow = borrow1 || borrow2;
+    }
+    borrow || b.iter().skip(a.len()).any(|&limb| limb != 0)
+}
+
+/// Arithmetic modulo an odd number in Montgomery form, which replaces the
+/// division in each modular multiplication with shifts. Values in
+/// Montgomery form are limb vectors exactly as long as the modulus.
+pub struct Montgomery {
+    modulus: Vec<u64>,
+    /// `-modulus^-1 mod 2^64`.
+    inverse: u64,
+    /// `R^2 mod modulus`, where `R = 2^(64 * limbs)`.
+    r_squared: Vec<u64>,
+}
+
+impl Montgomery {
+    /// Panics if `modulus` is even or 1.
+    pub fn new(modulus: &BigUint) -> Self {
+        assert!(modulus.bit(0) && modulus.bits() > 1, "modulus must be odd and above 1");
+        let limbs = modulus.limbs.clone();
+
+        // Newton's iteration doubles the correct low bits each step:
+        // 1, 2, 4, ... 64 after six steps (starting from 1 correct bit).
+        let mut inverse = 1u64;
+        for _ in 0..6 {
+            inverse = inverse.wrapping_mul(2u64.wrapping_sub(limbs[0].wrapping_mul(inverse)));
+        }
+
+        let mut r_squared = vec![0u64; 2 * limbs.len() + 1];
+        r_squared[2 * limbs.len()] = 1;
+        let mut r_squared = BigUint::from_limbs(r_squared).rem(modulus).limbs;
+        r_squared.resize(limbs.len(), 0);
+
+        Montgomery {
+            inverse: inverse.wrapping_neg(),
+            modulus: limbs,
+            r_squared,
+        }
+    }
+
+    /// Convert `value`, which must be below the modulus, to Montgomery form.
+    pub fn encode(&self, value: &BigUint) -> Vec<u64> {
+        let mut limbs = value.limbs.clone();
+        limbs.resize(self.modulus.len(), 0);
+        self.mul(&limbs, &self.r_squared)
+    }
+
+    /// Convert a value in Montgomery form back to a plain number.
+    pub fn decode(&self, value: &[u64]) -> BigUint {
+        let mut one = vec![0u64; self.modulus.len()];
+        one[0] = 1;
+        BigUint::from_limbs(self.mul(value, &one))
+    }
+
+    /// Montgomery product `a * b / R mod modulus` (coarsely integrated
+    /// operand scanning).
+    pub fn mul(&self, a: &[u64], b: &[u64]) -> Vec<u64> {
+        let n = &self.modulus;
+        let len = n.len();
+        let mut t = vec![0u64; len + 2];
+        for &b_limb in b {
+            let mut carry = 0u128;
+            for j in 0..len {
+                let sum = t[j] as u128 + a[j] as u128 * b_limb as u128 + carry;
+                t[j] = sum as u64;
+                carry = sum >> 64;
+            }
+            let sum = t[len] as u128 + carry;
+            t[len] = sum as u64;
+            t[len + 1] = (sum >> 64) as u64;
+
+            let m = t[0].wrapping_mul(self.inverse);
+            let mut carry = (t[0] as u128 + m as u128 * n[0] as u128) >> 64;
+            for j in 1..len {
+                let sum = t[j] as u128 + m as u128 * n[j] as u128 + carry;
+                t[j - 1] = sum as u64;
+                carry = sum >> 64;
+            }
+            let sum = t[len] as u128 + carry;
+            t[len - 1] = sum as u64;
+            t[len] = t[len + 1] + (sum >> 64) as u64;
+            t[len + 1] = 0;
+        }
+        if t[len] != 0 || compare(&t[..len], n) != Ordering::Less {
+            sub_in_place(&mut t[..=len], n);
+        }
+        t.truncate(len);
+        t
+    }
+
+    /// `base^exponent` with both the base and the result in Montgomery
+    /// form.
+    fn pow_montgomery(&self, base: &[u64], exponent: &BigUint) -> Vec<u64> {
+        let mut result = self.encode(&BigUint::from_u64(1));
+        for index in (0..exponent.bits()).rev() {
+            result = self.mul(&result, &result);
+            if exponent.bit(index) {
+                result = self.mul(&result, base);
+            }
+        }
+        result
+    }
+
+    /// `base^exponent mod modulus`. `base` must be below the modulus.
+    pub fn pow(&self, base: &BigUint, exponent: &BigUint) -> BigUint {
+        let base = self.encode(base);
+        self.decode(&self.pow_montgomery(&base, exponent))
+    }
+}
+
+/// A random prime of exactly `bits` bits whose top two bits are set, so
+/// the product of two such primes has exactly `2 * bits` bits.
+pub fn random_prime(bits: usize) -> Result<BigUint, String> {
+    let small_primes: Vec<u64> = (3..SMALL_PRIMES_LIMIT)
+        .step_by(2)
+        .filter(|&n| (3..n).step_by(2).take_while(|d| d * d <= n).all(|d| n % d != 0))
+        .collect();
+    let mut bytes = vec![0u8; bits.div_ceil(8)];
+    loop {
+        crate::crypto::random_bytes(&mut bytes)?;
+        let excess = bytes.len() * 8 - bits;
+        bytes[0] &= 0xff >> excess;
+        bytes[0] |= 0xc0 >> excess;
+        *bytes.last_mut().expect("at least one byte") |= 1;
+        let candidate = BigUint::from_be_bytes(&bytes);
+        if small_primes.iter().any(|&p| candidate.rem_u64(p) == 0) {
+            continue;
+        }
+        if is_probable_prime(&candidate, &small_primes)? {
+            return Ok(candidate);
+        }
+    }
+}
+
+/// Miller-Rabin test of an odd `candidate` larger than every small prime,
+/// with random bases.
+fn is_probable_prime(candidate: &BigUint, small_primes: &[u64]) -> Result<bool, String> {
+    let one = BigUint::from_u64(1);
+    let minus_one = candidate.sub(&one);
+    let shift = (0..minus_one.bits()).find(|&i| minus_one.bit(i)).unwrap_or(0);
+    let odd_part = minus_one.shr(shift);
+
+    let field = Montgomery::new(candidate);
+    let one_m = field.encode(&one);
+    let minus_one_m = field.encode(&minus_one);
+    let mut bytes = vec![0u8; (candidate.bits() as usize).div_ceil(8)];
+    'rounds: for round in 0..MILLER_RABIN_ROUNDS {
+        // One fixed base keeps the test meaningful even if the random
+        // bases happened to repeat.
+        let base = if round == 0 {
+            BigUint::from_u64(small_primes[0])
+        } else {
+            crate::crypto::random_bytes(&mut bytes)?;
+            BigUint::from_be_bytes(&bytes).rem(&minus_one)
+        };
+        if base.bits() <= 1 {
+            continue;
+        }
+        let mut x = field.pow_montgomery(&field.encode(&base), &odd_part);
+        if x == one_m || x == minus_one_m {
+            continue;
+        }
+        for _ in 1..shift {
+            x = field.mul(&x, &x);
+            if x == minus_one_m {
+                continue 'rounds;
+            }
+        }
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(hex: &str) -> BigUint {
+        let hex = if hex.len() % 2 == 1 { format!("0{}", hex) } else { hex.to_string() };
+        let bytes: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap())
+            .collect();
+        BigUint::from_be_bytes(&bytes)
+    }
+
+    #[test]
+    fn bytes_round_trip() {
+        let value = number("0102030405060708090a0b0c0d0e0f10");
+        assert_eq!(value.to_be_bytes(16), (1..=16).collect::<Vec<u8>>());
+        assert_eq!(value.to_be_bytes(18)[..2], [0, 0]);
+        assert_eq!(BigUint::from_be_bytes(&[0, 0, 0]), BigUint::from_u64(0));
+        assert!(BigUint::from_be_bytes(&[]).is_zero());
+        assert_eq!(value.bits(), 121);
+        assert_eq!(BigUint::from_u64(0).bits(), 0);
+    }
+
+    #[test]
+    fn arithmetic_known_answers() {
+        let a = number("1234567890abcdef1234567890abcdef1234567890abcdef");
+        let b = number("fedcba0987654321fedcba0987654321");
+        assert_eq!(
+            a.mul(&b),
+            number("121fa000a3723a57e68984312c3a8d7ea8d3c8611190a64d96b428606e1e6bf5c24a442fe55618cf")
+        );
+        assert_eq!(a.rem(&b), number("a9b37a7206cd7bc1ccf8e1fb118a5ab"));
+        assert_eq!(a.add(&b), number("1234567890abcdf011111082181111111111108218111110"));
+        assert_eq!(a.sub(&b), number("1234567890abcdee13579c6f09468acd13579c6f09468ace"));
+        assert_eq!(a.shr(70), number("48d159e242af37bc48d159e242af37"));
+        assert!(a.shr(1000).is_zero());
+        assert!(a.sub(&a).is_zero());
+    }
+
+    #[test]
+    fn agrees_with_u128() {
+        let samples = [0u64, 1, 2, 0xffff_ffff, u64::MAX - 1, u64::MAX, 0x8000_0000_0000_0000];
+        for &x in &samples {
+            for &y in &samples {
+                let (bx, by) = (BigUint::from_u64(x), BigUint::from_u64(y));
+                let product = x as u128 * y as u128;
+                assert_eq!(bx.mul(&by).to_be_bytes(16), product.to_be_bytes());
+                let sum = x as u128 + y as u128;
+                assert_eq!(bx.add(&by).to_be_bytes(16), sum.to_be_bytes());
+                if x >= y {
+                    assert_eq!(bx.sub(&by), BigUint::from_u64(x - y));
+                }
+                if y != 0 {
+                    assert_eq!(bx.rem(&by), BigUint::from_u64(x % y));
+                    assert_eq!(bx.rem_u64(y), x % y);
+                }
+                assert_eq!(x.cmp(&y), bx.cmp(&by));
+            }
+        }
+    }
+
+    #[test]
+    fn modular_powers() {
+        let p = number("7fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffffed");
+        let field = Montgomery::new(&p);
+        assert_eq!(
+            field.pow(&BigUint::from_u64(7), &p.sub(&BigUint::from_u64(2))),
+            number("249249249249249249249249249249249249249249249249249249249249248d")
+        );
+        // Fermat: a^(p-1) = 1 for a prime p.
+        assert_eq!(field.pow(&BigUint::from_u64(12345), &p.sub(&BigUint::from_u64(1))), BigUint::from_u64(1));
+        assert!(field.pow(&BigUint::from_u64(0), &BigUint::from_u64(5)).is_zero());
+        assert_eq!(field.pow(&BigUint::from_u64(9), &BigUint::from_u64(0)), BigUint::from_u64(1));
+
+        let mersenne = BigUint::from_be_bytes(&[[1].as_slice(), &[0xff; 65]].concat());
+        assert_eq!(
+            Montgomery::new(&mersenne).pow(&BigUint::from_u64(3), &BigUint::from_u64(12345678901234567890)),
+            number(
+                "1992482a446e6332e2e9d3de666d3b1a5ac9679f598c684fff1514d5ed44813bb8d0ca9eeef2f55e6788bf19abf052289fc0f4bf423595b0a2999dd1cdf50ea5d5"
+            )
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn subtraction_below_zero_panics() {
+        BigUint::from_u64(1).sub(&BigUint::from_u64(2));
+    }
+
+    #[test]
+    #[should_panic(expected = "division by zero")]
+    fn remainder_by_zero_panics() {
+        BigUint::from_u64(1).rem(&BigUint::from_u64(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "modulus must be odd")]
+    fn even_modulus_panics() {
+        Montgomery::new(&BigUint::from_u64(1 << 40));
+    }
+
+    #[test]
+    fn random_primes_have_the_requested_size() {
+        for bits in [64, 127, 256] {
+            let prime = random_prime(bits).unwrap();
+            assert_eq!(prime.bits(), bits as u64);
+            assert!(prime.bit(bits as u64 - 2));
+            let exponent = prime.sub(&BigUint::from_u64(1));
+            assert_eq!(Montgomery::new(&prime).pow(&BigUint::from_u64(2), &exponent), BigUint::from_u64(1));
+        }
+    }
+}
@@ -0,0 +1,85 @@
+use std::alloc::{self, Layout};
+use std::ops::{Deref, DerefMut};
+use std::ptr::NonNull;
+use std::sync::Mutex;
+
+/// Size of every pooled buffer.
+pub const BUFFER_LEN: usize = 64 * 1024;
+
+/// Buffers start on a page boundary, which direct I/O needs and which keeps
+/// wide loads from straddling cache lines.
+pub const BUFFER_ALIGN: usize = 4096;
+
+/// Most idle buffers kept for reuse. Buffers returned beyond this are
+/// freed, so a burst of parallel work does not pin memory afterwards.
+pub const MAX_IDLE: usize = 16;
+
+/// Idle buffers waiting to be reused.
+static IDLE: Mutex<Vec<Block>> = Mutex::new(Vec::new());
+
+/// One aligned allocation of `BUFFER_LEN` bytes.
+struct Block(NonNull<u8>);
+
+// SAFETY: a block is only ever owned by one buffer or by the idle list, so
+// handing it to another thread cannot create shared access.
+unsafe impl Send for Block {}
+
+/// A chunk buffer borrowed from the pool. It goes back to the pool when
+/// dropped, so streaming code can take one per operation without a fresh
+/// allocation each time.
+pub struct Buffer {
+    block: Block,
+}
+
+/// Take a buffer from the pool, or allocate one if none are idle. The
+/// contents are always zero.
+pub fn take() -> Buffer {
+    let idle = IDLE.lock().unwrap_or_else(|e| e.into_inner()).pop();
+    let block = idle.unwrap_or_else(|| {
+        // SAFETY: the layout has a nonzero size.
+        let pointer = unsafe { alloc::alloc_zeroed(layout()) };
+        Block(NonNull::new(pointer).unwrap_or_else(|| alloc::handle_alloc_error(layout())))
+    });
+    Buffer { block }
+}
+
+fn layout() -> Layout {
+    Layout::from_size_align(BUFFER_LEN, BUFFER_ALIGN).expect("buffer layout is valid")
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        // SAFETY: the block is a live allocation of BUFFER_LEN initialized
+        // bytes owned by this buffer.
+        unsafe { std::slice::from_raw_parts(self.block.0.as_ptr(), BUFFER_LEN) }
+    }
+}
+
+impl DerefMut for Buffer {
+    fn deref_mut(&mut self) -> &mut [u8] {
+        // SAFETY: as in `deref`, and `&mut self` makes the access exclusive.
+        unsafe { std::slice::from_raw_parts_mut(self.block.0.as_ptr(), BUFFER_LEN) }
+    }
+}
+
+impl Drop for Buffer {
+    fn drop(&mut self) {
+        // Buffers carry plaintext and keystream, so wipe them before anyone
+        // else can see them. `black_box` keeps the compiler from dropping
+        // the writes as dead before the memory is freed.
+        self.fill(0);
+        std::hint::black_box(&mut **self);
+
+        let block = Block(self.block.0);
+        let mut idle = IDLE.lock().unwrap_or_else(|e| e.into_inner());
+        if idle.len() < MAX_IDLE {
+            idle.push(block);
+        } else {
+            // SAFETY: the block was allocated in `take` with the same layout
+            // and nothing else refers to it.
+            unsafe { alloc::dealloc(block.0.as_ptr(), layout()) };
+        }
+    }
+}
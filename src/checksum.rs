@@ -0,0 +1,317 @@
+pub use file_encryptor_core::sha256::{SHA256_LEN, Sha256, sha256};
+use file_encryptor_core::sha256::INITIAL_STATE;
+
+/// Size of a BLAKE3 digest in bytes.
+pub const BLAKE3_LEN: usize = 32;
+
+/// BLAKE3 splits its input into chunks of this many bytes, hashed as the
+/// leaves of a binary tree.
+const BLAKE3_CHUNK_LEN: usize = 1024;
+
+/// Order the message words are shuffled into between BLAKE3 rounds.
+const BLAKE3_PERMUTATION: [usize; 16] = [2, 6, 3, 10, 7, 0, 4, 13, 1, 11, 12, 5, 9, 14, 15, 8];
+
+// Domain flags of a BLAKE3 compression.
+const CHUNK_START: u32 = 1;
+const CHUNK_END: u32 = 2;
+const PARENT: u32 = 4;
+const ROOT: u32 = 8;
+
+/// Incremental BLAKE3 hasher, following the reference implementation in
+/// the BLAKE3 specification. It keeps one chunk and a chaining value per
+/// level of the tree, so any amount of data hashes in constant memory.
+pub struct Blake3 {
+    chunk: Blake3Chunk,
+    /// Chaining values of finished subtrees still waiting for a sibling.
+    stack: Vec<[u32; 8]>,
+}
+
+impl Blake3 {
+    /// Create a hasher with no data fed into it yet.
+    pub fn new() -> Self {
+        Blake3 {
+            chunk: Blake3Chunk::new(0),
+            stack: Vec::new(),
+        }
+    }
+
+    /// Feed more data into the hash.
+    pub fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            if self.chunk.len() == BLAKE3_CHUNK_LEN {
+                let mut cv = self.chunk.output().chaining_value();
+                let mut total_chunks = self.chunk.counter + 1;
+                // Merge every subtree the new chunk completes: one per
+                // trailing zero bit in the chunk count.
+                while total_chunks & 1 == 0 {
+                    let left = self.stack.pop().expect("a subtree to merge");
+                    cv = parent_output(&left, &cv).chaining_value();
+                    total_chunks >>= 1;
+                }
+                self.stack.push(cv);
+                self.chunk = Blake3Chunk::new(self.chunk.counter + 1);
+            }
+            let take = (BLAKE3_CHUNK_LEN - self.chunk.len()).min(data.len());
+            self.chunk.update(&data[..take]);
+            data = &data[take..];
+        }
+    }
+
+    /// Finish hashing and return the 32 byte digest.
+    pub fn finalize(self) -> [u8; BLAKE3_LEN] {
+        let mut output = self.chunk.output();
+        for left in self.stack.iter().rev() {
+            output = parent_output(left, &output.chaining_value());
+        }
+        let words = blake3_compress(
+            &output.cv,
+            &output.block,
+            output.counter,
+            output.block_len,
+            output.flags | ROOT,
+        );
+        let mut digest = [0u8; BLAKE3_LEN];
+        for (bytes, word) in digest.chunks_exact_mut(4).zip(words) {
+            bytes.copy_from_slice(&word.to_le_bytes());
+        }
+        digest
+    }
+}
+
+/// The chunk currently being filled.
+struct Blake3Chunk {
+    cv: [u32; 8],
+    counter: u64,
+    block: [u8; 64],
+    block_len: usize,
+    blocks_compressed: usize,
+}
+
+impl Blake3Chunk {
+    fn new(counter: u64) -> Self {
+        Blake3Chunk {
+            cv: INITIAL_STATE,
+            counter,
+            block: [0; 64],
+            block_len: 0,
+            blocks_compressed: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        64 * self.blocks_compressed + self.block_len
+    }
+
+    fn start_flag(&self) -> u32 {
+        if self.blocks_compressed == 0 { CHUNK_START } else { 0 }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        while !data.is_empty() {
+            // The last block is kept back: it is compressed differently
+            // depending on whether it ends the chunk.
+            if self.block_len == 64 {
+                let words = blake3_compress(&self.cv, &block_words(&self.block), self.counter, 64, self.start_flag());
+                self.cv.copy_from_slice(&words[..8]);
+                self.blocks_compressed += 1;
+                self.block = [0; 64];
+                self.block_len = 0;
+            }
+            let take = (64 - self.block_len).min(data.len());
+            self.block[self.block_len..self.block_len + take].copy_from_slice(&data[..take]);
+            self.block_len += take;
+            data = &data[take..];
+        }
+    }
+
+    fn output(&self) -> Blake3Output {
+        Blake3Output {
+            cv: self.cv,
+            block: block_words(&self.block),
+            counter: self.counter,
+            block_len: self.block_len as u32,
+            flags: self.start_flag() | CHUNK_END,
+        }
+    }
+}
+
+/// The inputs of a compression not yet run, since the root node is
+/// compressed with an extra flag.
+struct Blake3Output {
+    cv: [u32; 8],
+    block: [u32; 16],
+    counter: u64,
+    block_len: u32,
+    flags: u32,
+}
+
+impl Blake3Output {
+    fn chaining_value(&self) -> [u32; 8] {
+        let words = blake3_compress(&self.cv, &self.block, self.counter, self.block_len, self.flags);
+        words[..8].try_into().expect("eight words")
+    }
+}
+
+fn parent_output(left: &[u32; 8], right: &[u32; 8]) -> Blake3Output {
+    let mut block = [0u32; 16];
+    block[..8].copy_from_slice(left);
+    block[8..].copy_from_slice(right);
+    Blake3Output {
+        cv: INITIAL_STATE,
+        block,
+        counter: 0,
+        block_len: 64,
+        flags: PARENT,
+    }
+}
+
+fn block_words(block: &[u8; 64]) -> [u32; 16] {
+    let mut words = [0u32; 16];
+    for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_le_bytes(bytes.try_into().expect("four bytes"));
+    }
+    words
+}
+
+/// The BLAKE3 compression function: seven rounds of the ChaCha-style
+/// quarter round over the chaining value and the message block.
+fn blake3_compress(cv: &[u32; 8], block: &[u32; 16], counter: u64, block_len: u32, flags: u32) -> [u32; 16] {
+    fn g(state: &mut [u32; 16], a: usize, b: usize, c: usize, d: usize, x: u32, y: u32) {
+        state[a] = state[a].wrapping_add(state[b]).wrapping_add(x);
+        state[d] = (state[d] ^ state[a]).rotate_right(16);
+        state[c] = state[c].wrapping_add(state[d]);
+        state[b] = (state[b] ^ state[c]).rotate_right(12);
+        state[a] = state[a].wrapping_add(state[b]).wrapping_add(y);
+        state[d] = (state[d] ^ state[a]).rotate_right(8);
+        state[c] = state[c].wrapping_add(state[d]);
+        state[b] = (state[b] ^ state[c]).rotate_right(7);
+    }
+
+    let mut state = [
+        cv[0],
+        cv[1],
+        cv[2],
+        cv[3],
+        cv[4],
+        cv[5],
+        cv[6],
+        cv[7],
+        INITIAL_STATE[0],
+        INITIAL_STATE[1],
+        INITIAL_STATE[2],
+        INITIAL_STATE[3],
+        counter as u32,
+        (counter >> 32) as u32,
+        block_len,
+        flags,
+    ];
+    let mut m = *block;
+    for round in 0..7 {
+        g(&mut state, 0, 4, 8, 12, m[0], m[1]);
+        g(&mut state, 1, 5, 9, 13, m[2], m[3]);
+        g(&mut state, 2, 6, 10, 14, m[4], m[5]);
+        g(&mut state, 3, 7, 11, 15, m[6], m[7]);
+        g(&mut state, 0, 5, 10, 15, m[8], m[9]);
+        g(&mut state, 1, 6, 11, 12, m[10], m[11]);
+        g(&mut state, 2, 7, 8, 13, m[12], m[13]);
+        g(&mut state, 3, 4, 9, 14, m[14], m[15]);
+        if round < 6 {
+            m = BLAKE3_PERMUTATION.map(|index| m[index]);
+        }
+    }
+    for i in 0..8 {
+        state[i] ^= state[i + 8];
+        state[i + 8] ^= cv[i];
+    }
+    state
+}
+
+/// Hash a whole buffer with BLAKE3 in one call.
+pub fn blake3(data: &[u8]) -> [u8; BLAKE3_LEN] {
+    let mut hasher = Blake3::new();
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// CRC-32 (the IEEE polynomial, as zip and gzip use it) of `data`. It
+/// only catches accidental damage, never tampering.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc = CRC32_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
+
+/// CRC-32 of every byte value, so `crc32` takes a byte at a time.
+const CRC32_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xedb8_8320 } else { crc >> 1 };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+    table
+};
+
+/// Format bytes as lowercase hexadecimal for display.
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(len: usize) -> Vec<u8> {
+        (0..len).map(|index| (index % 251) as u8).collect()
+    }
+
+    #[test]
+    fn blake3_known_answers() {
+        assert_eq!(
+            to_hex(&blake3(b"")),
+            "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"
+        );
+        assert_eq!(
+            to_hex(&blake3(b"abc")),
+            "6437b3ac38465133ffb63b75273a8db548c558465d79db03fd359c6cd5bd9d85"
+        );
+    }
+
+    #[test]
+    fn blake3_pieces_match_one_call() {
+        // Lengths either side of the chunk size and of whole subtrees.
+        for len in [63, 64, 65, 1023, 1024, 1025, 2048, 2049, 3072, 3073, 4096, 8193] {
+            let data = pattern(len);
+            let whole = blake3(&data);
+            for piece in [1, 7, 64, 1000, 1024] {
+                let mut hasher = Blake3::new();
+                for part in data.chunks(piece) {
+                    hasher.update(part);
+                }
+                assert_eq!(hasher.finalize(), whole, "length {} in pieces of {}", len, piece);
+            }
+        }
+    }
+
+    #[test]
+    fn blake3_tells_lengths_apart() {
+        // The tree shape must not let a chunk and a padded chunk collide.
+        assert_ne!(blake3(&pattern(1024)), blake3(&[pattern(1024), vec![0]].concat()));
+        assert_ne!(blake3(&[0; 64]), blake3(&[0; 65]));
+    }
+
+    #[test]
+    fn crc32_known_answers() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414f_a339);
+    }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::checksum::{self, to_hex};
+
+/// What a refused claim says, so callers can tell it from other errors.
+pub const IN_USE: &str = "is in use by another File Encryptor process";
+
+/// Files this process has claimed, with how many times, and the lock file
+/// held for each. A process may claim a path more than once, such as an
+/// output claimed both by the trash and by the operation writing it.
+static HELD: Mutex<Option<HashMap<PathBuf, (usize, Lock)>>> = Mutex::new(None);
+
+struct Lock {
+    file: File,
+    path: PathBuf,
+}
+
+/// A path claimed by this process until the value is dropped. While it is
+/// held, another instance (the menu, a command, a context-menu entry, or
+/// the daemon) trying to claim the same file is refused, so two processes
+/// never write, replace, or restore the same file at once.
+pub struct Claim {
+    key: Option<PathBuf>,
+}
+
+/// Claim `path` for `action` (such as "encrypt"). Fails if another process
+/// holds it. When no folder for the locks can be created, nothing is
+/// coordinated and the claim always succeeds.
+pub fn claim(path: &Path, action: &str) -> Result<Claim, String> {
+    let key = key(path);
+    let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+    let held = held.get_or_insert_with(HashMap::new);
+    if let Some((count, _)) = held.get_mut(&key) {
+        *count += 1;
+        return Ok(Claim { key: Some(key) });
+    }
+    let Some(dir) = lock_dir() else {
+        return Ok(Claim { key: None });
+    };
+    let lock_path = dir.join(to_hex(&checksum::sha256(key.as_os_str().as_encoded_bytes())[..16]));
+    let file = loop {
+        let Ok(mut file) = OpenOptions::new().read(true).write(true).create(true).truncate(false).open(&lock_path) else {
+            return Ok(Claim { key: None });
+        };
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let mut owner = String::new();
+                let _ = file.read_to_string(&mut owner);
+                let mut lines = owner.lines();
+                let (pid, holder) = (lines.next().unwrap_or("?"), lines.next().unwrap_or("use"));
+                return Err(format!(
+                    "'{}' {} ({}, process {}). Try again once it has finished.",
+                    path.display(),
+                    IN_USE,
+                    holder,
+                    pid
+                ));
+            }
+            Err(TryLockError::Error(_)) => return Ok(Claim { key: None }),
+        }
+        // The holder before us removes the file as it lets go, so the lock
+        // only counts if it is still on the file at that name.
+        if same_file(&file, &lock_path) {
+            let _ = file.set_len(0);
+            let _ = file.seek(SeekFrom::Start(0));
+            let _ = write!(file, "{}\n{}\n{}\n", std::process::id(), action, key.display());
+            break file;
+        }
+    };
+    held.insert(key.clone(), (1, Lock { file, path: lock_path }));
+    Ok(Claim { key: Some(key) })
+}
+
+/// Claim each of `paths`, or none of them.
+pub fn claim_all(paths: &[&Path], action: &str) -> Result<Vec<Claim>, String> {
+    paths.iter().map(|path| claim(path, action)).collect()
+}
+
+impl Drop for Claim {
+    fn drop(&mut self) {
+        let Some(key) = self.key.take() else {
+            return;
+        };
+        let mut held = HELD.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(held) = held.as_mut() else {
+            return;
+        };
+        if let Some((count, _)) = held.get_mut(&key) {
+            *count -= 1;
+            if *count == 0
+                && let Some((_, lock)) = held.remove(&key)
+            {
+                // Removed before unlocking, so nobody can lock a file that is
+                // about to disappear.
+                let _ = fs::remove_file(&lock.path);
+                drop(lock.file);
+            }
+        }
+    }
+}
+
+/// The name a file is claimed under: its absolute path with the folder's
+/// links resolved, and the file's own too if it exists, so two names for
+/// one file are one claim.
+fn key(path: &Path) -> PathBuf {
+    if let Ok(path) = fs::canonicalize(path) {
+        return path;
+    }
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    match (absolute.parent(), absolute.file_name()) {
+        (Some(parent), Some(name)) => fs::canonicalize(parent).map_or(absolute.clone(), |parent| parent.join(name)),
+        _ => absolute,
+    }
+}
+
+/// Where the lock files live: a private folder in `$XDG_RUNTIME_DIR`, or
+/// else one per user in the temporary folder.
+fn lock_dir() -> Option<PathBuf> {
+    let dir = match env::var_os("XDG_RUNTIME_DIR").filter(|dir| !dir.is_empty()) {
+        Some(runtime) => PathBuf::from(runtime).join("file-encryptor-claims"),
+        None => env::temp_dir().join(format!("file-encryptor-claims-{}", crate::audit::user_name())),
+    };
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(&dir).ok()?;
+    Some(dir)
+}
+
+/// Whether the open `file` is the one at `path`.
+fn same_file(file: &File, path: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        match (file.metadata(), fs::metadata(path)) {
+            (Ok(open), Ok(named)) => open.dev() == named.dev() && open.ino() == named.ino(),
+            _ => false,
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        // Windows does not delete a file that is open, so the name cannot
+        // have moved on to another.
+        let _ = (file, path);
+        true
+    }
+}
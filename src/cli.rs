@@ -0,0 +1,3021 @@
+use std::io::{self, BufRead, Read};
+use std::path::Path;
+use std::thread;
+use std::time::{Instant, SystemTime};
+
+use crate::CryptoAction;
+use crate::appendlog;
+use crate::archive;
+use crate::attempts::Attempts;
+use crate::audit::Auditor;
+use crate::batch::{self, OnConflict};
+use crate::checksum;
+use crate::claims;
+use crate::config::Config;
+use crate::container;
+use crate::contacts::{Contact, Contacts};
+use crate::content;
+use crate::cpu;
+use crate::digest::{self, Algorithm};
+use crate::evidence;
+use crate::filter::{self, Filter};
+use crate::format::{self, Cipher};
+use crate::guard;
+use crate::history::{self, History};
+use crate::hooks::{HookEvent, Hooks};
+use crate::i18n::t;
+use crate::mail;
+use crate::manifest;
+use crate::memory;
+use crate::metadata::FileMetadata;
+use crate::migrate::{self, Outcome};
+use crate::naming::Template;
+use crate::policy;
+use crate::recover::{self, Candidates};
+use crate::reencrypt;
+use crate::retry;
+use crate::salvage::GapMap;
+use crate::scratch::{self, Scratch};
+use crate::shell;
+use crate::sidecar;
+use crate::signature::{self, Verdict};
+use crate::store::{self, Change, Repository, Retention};
+use crate::throttle;
+use crate::timelock;
+use crate::notify::Notifier;
+use crate::packed;
+use crate::paths;
+use crate::trash::Trash;
+use crate::tree;
+use crate::tty;
+use crate::tuning;
+use crate::ui;
+use crate::vault::Vault;
+
+/// Usage text shown by `help` and after a usage error.
+const USAGE: &str = "\
+Usage: File-Encryption [COMMAND]
+
+Run with no command to use the interactive menu.
+
+Commands:
+  encrypt PATH... [OPTIONS]         Encrypt each file, and each file inside
+                                    a folder, on its own (default FILE.enc)
+      --output-dir DIR              Write the outputs into DIR, creating it
+                                    if needed
+      --keep-structure              With --output-dir: recreate each
+                                    input's path under DIR
+      --on-conflict POLICY          When an output name is taken: rename
+                                    (the default, as NAME-2.EXT.enc), skip,
+                                    or overwrite
+      --name-template TEMPLATE      Name the outputs from TEMPLATE, such as
+                                    '{stem}_{date}_{hash8}.enc', with
+                                    {stem}, {ext}, {date}, {counter}, and
+                                    {hash} or {hashN} for N hex digits
+      --into-archive FILE           Add the outputs to the zip or tar FILE,
+                                    creating it if needed, instead of
+                                    writing them to disk
+      --max-files N                 Refuse the run if the paths hold more
+                                    than N files
+      --max-total-bytes SIZE        Refuse the run if the files come to
+                                    more than SIZE (such as 20G)
+      --yes                         Do not ask before working through
+                                    folders holding many files
+      --retries N                   Try a file up to N more times when it
+                                    is busy or the network drops, waiting
+                                    longer each time (default 3)
+      --evidence-report NAME        Write an evidence report for chain of
+                                    custody to NAME.json and NAME.txt
+  encrypt-folder FOLDER [OPTIONS]   Encrypt a folder into a single file
+      --output FILE                 Output file (default FOLDER.enc)
+      --exclude PATTERN             Skip entries matching PATTERN
+      --include PATTERN             Keep entries matching PATTERN
+      --exclude-from FILE           Read exclude patterns from FILE
+      --include-from FILE           Read include patterns from FILE
+      --min-size SIZE               Skip files smaller than SIZE (e.g. 10K)
+      --max-size SIZE               Skip files larger than SIZE (e.g. 2G)
+      --newer-than AGE|DATE         Skip files modified before (e.g. 1d)
+      --older-than AGE|DATE         Skip files modified after (e.g. 2024-05-31)
+      --manifest FILE               Write a signed JSON manifest to FILE
+      --evidence-report NAME        Write an evidence report for chain of
+                                    custody to NAME.json and NAME.txt
+  encrypt-tree FOLDER DEST [OPTIONS]
+                                    Encrypt each file under FOLDER into a
+                                    file of its own under DEST
+      --encrypt-names               Encrypt file and folder names too
+  decrypt-tree FOLDER DEST          Decrypt a tree from encrypt-tree
+  migrate FILE... [OPTIONS]         Upgrade encrypted files to the current
+                                    format in place
+      --report FILE                 Text report (default
+                                    migration-report.txt)
+      --manifest FILE               Write a signed JSON manifest to FILE
+  reencrypt FOLDER [OPTIONS]        Find the encrypted files under FOLDER
+                                    whose encryption is outdated or falls
+                                    short of the policy, and upgrade them
+                                    in place
+      --dry-run                     Only list them and what is outdated
+      --report FILE                 Text report (default
+                                    reencrypt-report.txt)
+      --progress FILE               Where finished files are recorded, so
+                                    an interrupted run resumes (default
+                                    reencrypt-progress.txt)
+      --retry-failed                Try files that failed before again
+  recover FILE [OPTIONS]            Search for a forgotten password of one
+                                    of your own files
+      --wordlist FILE               Try every line of FILE
+      --mask MASK                   Try every password matching MASK, where
+                                    ?l ?u ?d ?s ?a stand for a lowercase
+                                    letter, uppercase letter, digit, symbol,
+                                    or any of those (e.g. summer?d?d)
+      --threads N                   Worker threads (default: all cores)
+      --max-candidates N            Refuse larger searches (default
+                                    10000000)
+  vault FILE list                   List the names stored in a vault
+  vault FILE add NAME               Store a secret (read from the terminal)
+  vault FILE get NAME               Print a stored secret
+  vault FILE remove NAME            Delete a stored secret
+  log append LOG FILE [OPTIONS]     Encrypt what FILE gained since the last
+                                    append onto the end of LOG, creating it
+                                    if needed
+      --range START-END             Append bytes START to END instead
+                                    (END may be left out)
+      --chunk-size SIZE             Largest record of a new log, 64K to
+                                    16M (default 1M)
+  log write LOG [--chunk-size SIZE] Encrypt each line read from stdin onto
+                                    the end of LOG as a record of its own
+  log decrypt LOG OUTPUT [--salvage]
+                                    Decrypt a whole log into OUTPUT; with
+                                    --salvage, write zeros for damaged
+                                    records and list them in OUTPUT.gaps
+  store init REPO                   Create an encrypted, deduplicating
+                                    backup repository
+  store backup REPO PATH            Save a file or folder as a new snapshot,
+                                    storing only chunks REPO lacks
+  store snapshots REPO              List the snapshots in REPO
+  store restore REPO ID DEST [--salvage]
+                                    Restore snapshot ID (or 'latest') into
+                                    the empty folder DEST; with --salvage,
+                                    write zeros for damaged chunks and list
+                                    them in DEST.gaps
+  store diff REPO OLD NEW           List files added, removed, or modified
+                                    between two snapshots
+  store prune REPO [OPTIONS]        Remove old snapshots and the chunks only
+                                    they used
+      --keep-last N                 Keep the N newest snapshots (default 1)
+      --keep-within AGE|DATE        Also keep snapshots taken since then
+                                    (e.g. 30d)
+      --dry-run                     Only report what would be removed
+  container create FILE SIZE        Create an encrypted volume of SIZE
+                                    (e.g. 1G)
+  container open FILE [OPTIONS]     Serve the volume as a network block
+                                    device until the client disconnects
+      --socket PATH                 Unix socket to listen on (default
+                                    FILE.sock)
+  timelock lock FILE --until DATE   Encrypt FILE so that it takes until
+                                    about DATE to decrypt (no password)
+      --for AGE                     Instead of --until: lock for AGE from
+                                    now (e.g. 30d)
+      --output FILE                 Output file (default FILE.tlock)
+  timelock open FILE [OPTIONS]      Solve the puzzle in FILE and decrypt it,
+                                    resuming saved progress
+      --output FILE                 Output file (default FILE without
+                                    .tlock)
+  keygen NAME                       Create a signing key: NAME.key (kept
+                                    private, password protected) and
+                                    NAME.pub (given to recipients)
+  sign PATH... --key FILE           Write a detached Ed25519 signature
+                                    PATH.sig for each file (folders are
+                                    signed file by file)
+      --agent                       Instead of --key: sign with a key held
+                                    by the agent in $SSH_AUTH_SOCK
+      --public-key KEY              With --agent: which key to use, if it
+                                    holds several
+  verify-signature PATH... [OPTIONS]
+                                    Check each file (or every file in a
+                                    folder) against its .sig
+      --public-key KEY              Trusted public key: a .pub file or its
+                                    64 hex digits (required)
+  hash FILE... [OPTIONS]            Print the checksum of each file, in the
+                                    format sha256sum and b3sum read
+      --algorithm sha256|blake3     Hash to use (default sha256)
+      --decrypt                     Hash the plaintext inside encrypted
+                                    files instead (asks for the password)
+      --check LIST                  Instead of FILE...: check the files
+                                    named in LIST, a list written by hash,
+                                    sha256sum, or b3sum
+  decrypt-with-key FILE --key FILE  Decrypt a file encrypted for a
+                                    recipient (with --recipient or by the
+                                    policy), with that recipient's private
+                                    key
+      --output PATH                 Output file or folder (default FILE
+                                    without .enc)
+  contact add NAME KEY [--email ADDRESS]
+                                    Remember the public key KEY (a .pub
+                                    file or 64 hex digits) as NAME, for
+                                    --recipient
+  contact list                      List the contacts in the keyring with
+                                    their fingerprints
+  contact verify NAME               Compare NAME's fingerprint with them out
+                                    of band and mark the key as verified
+  contact remove NAME               Forget a contact (by name or address)
+  contact revoke NAME               Mark NAME's key as revoked, so new
+                                    files are not encrypted for it
+  contact import-revocation FILE    Check a revocation file from revoke-key
+                                    and mark that key as revoked
+  revoke-key FILE [OPTIONS]         Write a revocation for the signing key
+                                    FILE, signed by it (default NAME.rev)
+      --reason TEXT                 Why the key is revoked
+      --output FILE                 Where to write it
+  inspect FILE...                   Show the format and recipients of each
+                                    encrypted file without decrypting it,
+                                    flagging revoked keys
+  fingerprint KEY...                Show the short fingerprint of each
+                                    contact or public key, to compare with
+                                    its owner
+  daemon start [OPTIONS]            Run a daemon that keeps the password
+                                    in memory, so files can be encrypted
+                                    and decrypted without retyping it
+      --timeout AGE                 Forget it after AGE unused (default
+                                    15m)
+      --user NAME                   Switch to user NAME once listening
+                                    (when started as root)
+      --metrics ADDR                Also serve its counters over HTTP at
+                                    ADDR/metrics, for Prometheus
+  daemon unlock|lock|status|stop    Give the daemon the password, make it
+                                    forget it, show its state, or stop it
+  daemon stats                      Show files and bytes done, queue depth,
+                                    and errors since the daemon started
+  daemon encrypt FILE [--output FILE]
+                                    Encrypt FILE through the daemon
+                                    (default FILE.enc)
+  daemon decrypt FILE [--output PATH]
+                                    Decrypt FILE through the daemon
+                                    (default FILE without .enc)
+      --socket PATH                 Socket for any daemon command (default
+                                    $XDG_RUNTIME_DIR/file_encryptor.sock)
+  agent start [OPTIONS]             Run a key agent speaking the SSH agent
+                                    protocol, so sign --agent, ssh, and
+                                    ssh-add share the keys it holds
+      --ttl AGE                     Forget keys after AGE unless they were
+                                    added with their own --ttl
+      --user NAME                   Switch to user NAME once listening
+                                    (when started as root)
+  agent add-key FILE [--ttl AGE]    Unlock the signing key FILE and add it
+                                    to the agent, forgotten after AGE
+  agent list                        List the keys the agent holds
+  agent lock|unlock                 Lock the agent with a passphrase, or
+                                    unlock it
+  agent remove-all                  Make the agent forget every key
+      --socket PATH                 Socket for any agent command (default
+                                    $SSH_AUTH_SOCK, or for start
+                                    $XDG_RUNTIME_DIR/file_encryptor_agent.sock)
+  open FILE [OPTIONS]               Decrypt FILE and open it with the
+                                    default application for its type
+      --output PATH                 Output file (default FILE without
+                                    .enc)
+      --delete-after                Decrypt into a private folder instead,
+                                    and wipe the copy once the viewer is
+                                    closed
+  gui [OPTIONS]                     Open a window in the web browser to
+                                    encrypt and decrypt dropped files (in
+                                    builds with --features gui)
+      --port N                      Port to serve it on (default any free
+                                    one)
+      --no-browser                  Only print its address
+  integrate-shell install|uninstall Add or remove Encrypt and Decrypt
+                                    entries in the file manager's context
+                                    menu (Windows Explorer, GNOME Files,
+                                    KDE Dolphin)
+  integrate-shell run encrypt|decrypt FILE...
+                                    What those entries run: ask for the
+                                    password in a dialog and encrypt or
+                                    decrypt each FILE next to itself
+  mail-split PATH [OPTIONS]         Compress, encrypt, and split a file or
+                                    folder into text parts small enough to
+                                    attach to emails
+      --part-size SIZE              Largest part (default 20M)
+      --output-dir DIR              Where to write NAME.partNNN.txt
+                                    (default the current folder)
+  mail-join PART... [--output PATH] Reassemble and decrypt the parts, given
+                                    in any order (default: the original
+                                    name)
+  scratch FILE [--shell PROGRAM]    Open a shell in a private folder kept
+                                    in memory, holding the decrypted
+                                    contents of FILE (empty if FILE does
+                                    not exist); on exit, encrypt the folder
+                                    back to FILE and wipe it
+  sidecar split FILE [OPTIONS]      Move sensitive bytes of FILE into an
+                                    encrypted sidecar, blanking them in
+                                    place so the file keeps its format
+      --range START-END             Bytes to move (repeatable)
+      --dicom-tag GGGG,EEEE         The value of a DICOM element, such as
+                                    0010,0010 for the patient's name
+                                    (repeatable)
+      --sidecar PATH                Sidecar file (default
+                                    FILE.sidecar.enc)
+  sidecar merge FILE [--sidecar PATH]
+                                    Put the bytes from the sidecar back
+                                    into FILE
+  history list [OPTIONS]            Show past operations, newest first
+      --action encrypt|decrypt      Only that kind of operation
+      --failed-only                 Only operations that failed
+      --changed-only                Only encryptions whose input changed
+                                    while it was read
+      --outdated-only               Only decryptions of files whose
+                                    encryption was outdated
+      --since AGE|DATE              Only operations after (e.g. 1d)
+      --until AGE|DATE              Only operations before (e.g. 2024-05-31)
+      --path TEXT                   Only paths containing TEXT
+      --page N                      Show page N of 20 entries (default 1)
+  history search TEXT               Show past operations whose path, note,
+                                    or tags contain TEXT (#tag matches one
+                                    tag exactly)
+  history duplicates [--existing]   List files whose same contents were
+                                    encrypted to more than one output
+      --existing                    Only outputs still on disk
+  undo                              Undo the last operation
+  selftest                          Check the crypto against known answers
+  info --capabilities               Show the CPU features detected and the
+                                    cipher they favor
+  info --policy                     Show the organization policy in force
+  help                              Show this message
+
+Options:
+  --plain                           Plain output for screen readers and
+                                    dumb terminals
+  --legacy-xor                      Allow decrypting and migrating files
+                                    from the old, insecure XOR versions
+  --bwlimit RATE                    Read and write file contents no faster
+                                    than RATE per second (e.g. 10M)
+  --nice                            Run at the lowest CPU and disk priority
+  --recipient NAME                  Also let NAME open new files with their
+                                    private key: a contact's name or
+                                    address, or a public key (repeatable)
+  --allow-revoked                   Let --recipient name a revoked key
+  --max-memory SIZE                 Refuse files too large to encrypt or
+                                    decrypt within SIZE of memory (e.g.
+                                    256M), and use smaller log records
+  --direct-io                       Bypass the page cache when reading and
+                                    writing file contents
+  --adaptive                        Tune the read and write size, and how
+                                    many files of a tree are worked on at
+                                    once, to the measured throughput
+  --totp                            Require a code from an authenticator
+                                    app, as well as the password, to
+                                    decrypt newly encrypted files
+  --strip-metadata                  Remove Exif, author, and similar
+                                    metadata from JPEG, PNG, WebP, and PDF
+                                    files before encrypting them
+  --parity PERCENT                  Append Reed-Solomon parity of PERCENT
+                                    of the size (1 to 100) to new files, so
+                                    damaged blocks are repaired on decrypt
+  --never-modify-source             Refuse to change any file that is read,
+                                    and check afterwards that each one is
+                                    unchanged
+  --progress-json                   Report progress on stderr as one JSON
+                                    object per line (phase, bytes in and
+                                    out, chunk, total)
+  --trace-output FILE               Write a Chrome trace of how long each
+                                    read, key derivation, cipher, and write
+                                    took, for finding what makes a run slow
+  --deterministic-for-testing SEED  Derive salts and nonces from SEED so
+                                    output is byte-for-byte repeatable (for
+                                    tests only; needs FILE_ENCRYPTOR_TESTING=1)
+
+Patterns follow .gitignore rules and are checked in order; the last
+matching pattern decides whether an entry is kept. Ages use s, m, h, d,
+or w; dates are YYYY-MM-DD in UTC.";
+
+/// Whether `option` takes a value, as USAGE shows it: followed by one
+/// space and a placeholder in capitals, such as `--output FILE` or
+/// `--newer-than AGE|DATE`.
+pub fn takes_value(option: &str) -> bool {
+    if !option.starts_with("--") {
+        return false;
+    }
+    USAGE.lines().any(|line| {
+        line.split(' ')
+            .collect::<Vec<_>>()
+            .windows(2)
+            .any(|pair| {
+                pair[0] == option
+                    && !pair[1].is_empty()
+                    && pair[1].starts_with(|c: char| c.is_ascii_uppercase())
+                    && pair[1].chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || "|_-.".contains(c))
+            })
+    })
+}
+
+/// Run a single command given on the command line instead of the menu.
+///
+/// `legacy_xor` allows reading files from the XOR era. It never affects how
+/// new files are written, so commands that only encrypt refuse it.
+pub fn run(config: &Config, args: &[String], legacy_xor: bool) -> Result<(), String> {
+    let (command, rest) = args.split_first().ok_or_else(|| USAGE.to_string())?;
+    match command.as_str() {
+        "encrypt" | "encrypt-folder" if legacy_xor => Err(
+            "--legacy-xor only allows decrypting old files; new files are always written in the current format."
+                .to_string(),
+        ),
+        "encrypt" => encrypt(config, rest),
+        "encrypt-folder" => encrypt_folder(config, rest),
+        "encrypt-tree" if legacy_xor => Err(
+            "--legacy-xor only allows decrypting old files; new files are always written in the current format."
+                .to_string(),
+        ),
+        "encrypt-tree" => encrypt_tree(config, rest),
+        "decrypt-tree" => decrypt_tree(config, rest, legacy_xor),
+        "undo" => undo(config),
+        "selftest" => selftest(),
+        "info" => info(rest),
+        "migrate" => migrate(config, rest, legacy_xor),
+        "reencrypt" => reencrypt(config, rest, legacy_xor),
+        "recover" => recover(rest, legacy_xor),
+        "vault" => vault(config, rest),
+        "store" => store(rest),
+        "log" if legacy_xor => Err(
+            "--legacy-xor only allows decrypting old files; logs are always written in the current format."
+                .to_string(),
+        ),
+        "log" => log(rest),
+        "container" => container(config, rest),
+        "timelock" => timelock(rest),
+        "keygen" => keygen(rest),
+        "sign" => sign(rest),
+        "verify-signature" => verify_signature(rest),
+        "hash" => hash(rest, legacy_xor),
+        "decrypt-with-key" => decrypt_with_key(config, rest),
+        "contact" => contact(config, rest),
+        "fingerprint" => fingerprint(config, rest),
+        "revoke-key" => revoke_key(rest),
+        "inspect" => inspect(config, rest),
+        "daemon" => daemon(config, rest),
+        "agent" => agent(rest),
+        "scratch" => scratch(config, rest),
+        "sidecar" => sidecar(config, rest),
+        "open" => open(config, rest, legacy_xor),
+        "gui" => gui(config, rest),
+        "integrate-shell" => integrate_shell(config, rest),
+        "mail-split" => mail_split(config, rest),
+        "mail-join" => mail_join(config, rest),
+        "history" => history(config, rest),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        other => Err(format!("Unknown command '{}'.\n\n{}", other, USAGE)),
+    }
+}
+
+/// `selftest`
+fn selftest() -> Result<(), String> {
+    let outcomes = crate::selftest::run();
+    let failed = outcomes.iter().filter(|o| o.error.is_some()).count();
+    for outcome in &outcomes {
+        match &outcome.error {
+            None => println!("ok      {}", outcome.name),
+            Some(e) => println!("FAILED  {}: {}", outcome.name, e),
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} self-tests failed.", failed, outcomes.len()));
+    }
+    println!("All {} self-tests passed.", outcomes.len());
+    Ok(())
+}
+
+/// `info --capabilities` or `info --policy`
+fn info(args: &[String]) -> Result<(), String> {
+    if args == ["--policy"] {
+        return info_policy();
+    }
+    if args != ["--capabilities"] {
+        return Err(format!("info needs --capabilities or --policy.\n\n{}", USAGE));
+    }
+    let features = cpu::features();
+    let rows = [
+        ("AES", features.aes, ""),
+        ("Carry-less multiply", features.carryless_multiply, ""),
+        ("AVX2", features.avx2, "used for ChaCha20 and XOR"),
+        ("NEON", features.neon, ""),
+        ("SHA-256", features.sha256, "used for checksums and PBKDF2"),
+    ];
+    println!("CPU features:");
+    for (name, present, note) in rows {
+        let state = if present { "yes" } else { "no" };
+        if present && !note.is_empty() {
+            println!("  {:<21}{} ({})", name, state, note);
+        } else {
+            println!("  {:<21}{}", name, state);
+        }
+    }
+
+    let preferred = cpu::preferred_cipher();
+    println!();
+    println!(
+        "Preferred cipher: {} ({})",
+        preferred.name(),
+        match preferred {
+            Cipher::AesGcm => "hardware AES is available",
+            Cipher::ChaCha20 => "no hardware AES, so ChaCha20 is faster and safer",
+        }
+    );
+    let in_use = cpu::cipher_in_use();
+    let policy = policy::get();
+    let allowed = match preferred {
+        Cipher::AesGcm => policy.allow_aes_gcm,
+        Cipher::ChaCha20 => policy.allow_chacha20,
+    };
+    if in_use == preferred {
+        println!("New files use:    {}", in_use.name());
+    } else if !allowed {
+        println!(
+            "New files use:    {} ({} is not allowed by the organization policy)",
+            in_use.name(),
+            preferred.name()
+        );
+    } else {
+        println!(
+            "New files use:    {} (this version only uses hardware AES on x86_64)",
+            in_use.name()
+        );
+    }
+    Ok(())
+}
+
+/// `info --policy`
+fn info_policy() -> Result<(), String> {
+    let policy = policy::get();
+    let Some(path) = &policy.path else {
+        println!("No organization policy is in force.");
+        return Ok(());
+    };
+    let ciphers: Vec<&str> = [
+        ("chacha20", policy.allow_chacha20),
+        ("aes-gcm", policy.allow_aes_gcm),
+        ("xor", policy.allow_xor),
+    ]
+        .iter()
+        .filter(|(_, allowed)| *allowed)
+        .map(|(name, _)| *name)
+        .collect();
+    println!("Policy file:         {}", path.display());
+    println!(
+        "KDF iterations:      {} (at least {} required)",
+        format::DEFAULT_ITERATIONS.max(policy.min_kdf_iterations),
+        policy.min_kdf_iterations
+    );
+    println!(
+        "Allowed ciphers:     {}",
+        if ciphers.is_empty() { "none".to_string() } else { ciphers.join(", ") }
+    );
+    if policy.required_recipients.is_empty() {
+        println!("Required recipients: none");
+    }
+    for (index, recipient) in policy.required_recipients.iter().enumerate() {
+        let label = if index == 0 { "Required recipients:" } else { "" };
+        println!(
+            "{:<21}{} ({})",
+            label,
+            checksum::to_hex(recipient),
+            signature::fingerprint(recipient)
+        );
+    }
+    Ok(())
+}
+
+/// `encrypt-folder FOLDER [OPTIONS]`
+fn encrypt_folder(config: &Config, args: &[String]) -> Result<(), String> {
+    let mut folder = None;
+    let mut output = None;
+    let mut manifest_path = None;
+    let mut evidence_report = None;
+    let mut filter = Filter::default();
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" | "-o" => output = Some(flag_value(&mut args, arg)?),
+            "--manifest" => manifest_path = Some(flag_value(&mut args, arg)?),
+            "--evidence-report" => evidence_report = Some(flag_value(&mut args, arg)?),
+            "--exclude" => filter.exclude(&flag_value(&mut args, arg)?),
+            "--include" => filter.include(&flag_value(&mut args, arg)?),
+            "--exclude-from" => filter.add_from_file(&flag_value(&mut args, arg)?, false)?,
+            "--include-from" => filter.add_from_file(&flag_value(&mut args, arg)?, true)?,
+            "--min-size" => {
+                filter.min_size = Some(filter::parse_size(&flag_value(&mut args, arg)?)?)
+            }
+            "--max-size" => {
+                filter.max_size = Some(filter::parse_size(&flag_value(&mut args, arg)?)?)
+            }
+            "--newer-than" => {
+                filter.newer_than = Some(filter::parse_time(&flag_value(&mut args, arg)?)?)
+            }
+            "--older-than" => {
+                filter.older_than = Some(filter::parse_time(&flag_value(&mut args, arg)?)?)
+            }
+            flag if flag.starts_with('-') => {
+                return Err(format!("Unknown option '{}'.\n\n{}", flag, USAGE));
+            }
+            _ if folder.is_none() => folder = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument '{}'.\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let folder = folder.ok_or_else(|| format!("encrypt-folder needs a folder.\n\n{}", USAGE))?;
+    let output = output.unwrap_or_else(|| format!("{}.enc", folder.trim_end_matches(['/', '\\'])));
+
+    let password = prompt_password();
+    let trash = Trash::new(config.trash_retention_minutes)?;
+    let started = Instant::now();
+    let started_at = SystemTime::now();
+    let result = trash.protect(&output, || {
+        crate::encrypt_folder(
+            Path::new(&folder),
+            Path::new(&output),
+            &password,
+            &filter,
+            config.preserve_metadata,
+        )
+    });
+
+    let event = HookEvent {
+        action: "encrypt",
+        input: &folder,
+        output: &output,
+        error: result.as_ref().err().map(String::as_str),
+        password: Some(&password),
+    };
+    if let Some(warning) = Hooks::from_config(config).run(&event) {
+        eprintln!("Warning: {}", warning);
+    }
+    if let Some(warning) = Auditor::from_config(config).operation_finished(&event) {
+        eprintln!("Warning: {}", warning);
+    }
+    Notifier::from_config(config).operation_finished(
+        &format!("Encrypting '{}'", folder),
+        event.error,
+        started.elapsed(),
+    );
+    if let Some(manifest_path) = &manifest_path {
+        let entry = manifest::Entry {
+            input: folder.clone(),
+            output: output.clone(),
+            status: if result.is_ok() { "encrypted" } else { "failed" },
+            error: result.as_ref().err().cloned(),
+        };
+        manifest::write(manifest_path, &[entry], password.as_bytes())?;
+        println!("Manifest written to '{}'.", manifest_path);
+    }
+    if let Some(name) = &evidence_report {
+        let record = evidence::Record {
+            action: "encrypt",
+            input: folder.clone(),
+            output: output.clone(),
+            input_sha256: None,
+            started: started_at,
+            finished: SystemTime::now(),
+            error: result.as_ref().err().cloned(),
+        };
+        let (json_path, text_path) = evidence::write(name, &[record])?;
+        println!("Evidence report written to '{}' and '{}'.", json_path, text_path);
+    }
+    let summary = result?;
+
+    println!("Folder encrypted successfully to '{}'.", output);
+    crate::print_archive_summary(&summary);
+    Ok(())
+}
+
+/// `encrypt FILE... [--output-dir DIR [--keep-structure]] [--on-conflict POLICY]`
+/// `[--name-template TEMPLATE] [--into-archive FILE] [--retries N]`
+/// `[--max-files N] [--max-total-bytes SIZE] [--yes]`
+fn encrypt(config: &Config, args: &[String]) -> Result<(), String> {
+    let mut inputs = Vec::new();
+    let mut output_dir = None;
+    let mut name_template = None;
+    let mut into_archive = None;
+    let mut keep_structure = false;
+    let mut on_conflict = OnConflict::Rename;
+    let mut evidence_report = None;
+    let mut retry = retry::Policy::from_config(config);
+    let mut limits = batch::Limits::default();
+    let mut yes = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output-dir" => output_dir = Some(flag_value(&mut args, arg)?),
+            "--into-archive" => into_archive = Some(flag_value(&mut args, arg)?),
+            "--retries" => {
+                retry.retries = flag_value(&mut args, arg)?
+                    .parse()
+                    .map_err(|_| format!("'{}' needs a whole number.", arg))?;
+            }
+            "--max-files" => limits.max_files = Some(parse_count(&flag_value(&mut args, arg)?, arg)?),
+            "--max-total-bytes" => {
+                limits.max_total_bytes = Some(filter::parse_size(&flag_value(&mut args, arg)?)?);
+            }
+            "--yes" => yes = true,
+            "--name-template" => name_template = Some(flag_value(&mut args, arg)?),
+            "--evidence-report" => evidence_report = Some(flag_value(&mut args, arg)?),
+            "--keep-structure" => keep_structure = true,
+            "--on-conflict" => on_conflict = OnConflict::parse(&flag_value(&mut args, arg)?)?,
+            flag if flag.starts_with('-') => {
+                return Err(format!("Unknown option '{}'.
+
+{}", flag, USAGE));
+            }
+            _ => inputs.push(arg.clone()),
+        }
+    }
+    if inputs.is_empty() {
+        return Err(format!("encrypt needs at least one file.
+
+{}", USAGE));
+    }
+    if into_archive.is_some() {
+        if output_dir.is_some() {
+            return Err("--into-archive and --output-dir cannot be used together.".to_string());
+        }
+        if on_conflict == OnConflict::Overwrite {
+            return Err(
+                "Members of an archive cannot be overwritten; use --on-conflict rename or skip.".to_string(),
+            );
+        }
+    }
+    let template = policy::get()
+        .name_template(name_template.as_deref())?
+        .or(config.name_template.as_deref())
+        .map(Template::parse)
+        .transpose()?;
+    let trash = Trash::new(config.trash_retention_minutes)?;
+    // The archive as it was is kept, so `undo` can take back the whole run.
+    let mut archive = None;
+    if let Some(path) = &into_archive {
+        let pending = trash.begin(path)?;
+        match packed::Appender::open(Path::new(path)) {
+            Ok(appender) => archive = Some((appender, pending)),
+            Err(e) => {
+                pending.rollback();
+                return Err(e);
+            }
+        }
+    }
+    let layout = batch::Layout {
+        // Members are named as if the archive were the output folder.
+        output_dir: match &archive {
+            Some(_) => Some(Path::new("")),
+            None => output_dir.as_deref().map(Path::new),
+        },
+        keep_structure,
+        on_conflict,
+        suffix: ".enc",
+        existing: archive.as_ref().map(|(appender, _)| appender.names()),
+        name_template: template.as_ref(),
+    };
+    let planned = batch::plan(&inputs, &layout, &limits)
+        .and_then(|jobs| confirm_large_run(config, &inputs, &jobs, yes).map(|()| jobs));
+    let mut jobs = match planned {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            if let Some((_, pending)) = archive {
+                pending.rollback();
+            }
+            return Err(e);
+        }
+    };
+    if let Some(path) = &into_archive {
+        // The archive itself may be inside a folder being encrypted.
+        let archive_path = std::fs::canonicalize(path).ok();
+        jobs.retain(|job| std::fs::canonicalize(&job.input).ok() != archive_path);
+    }
+
+    let password = prompt_password();
+    let mut history = History::load(config);
+    let (mut encrypted, mut skipped, mut failed) = (0, 0, 0);
+    let mut records = Vec::new();
+    for job in &jobs {
+        let input = paths::display(&job.input);
+        let Some(output_path) = &job.output else {
+            println!("Skipped '{}': its output already exists.", input);
+            skipped += 1;
+            continue;
+        };
+        let started = Instant::now();
+        let started_at = SystemTime::now();
+        let (output, (result, retries)) = match (&mut archive, &into_archive) {
+            (Some((appender, _)), Some(path)) => {
+                let member = packed::Appender::member_name(output_path);
+                let result = retry.run(&input, || {
+                    crate::encrypt_file_into(&job.input, appender, &member, &password, config.preserve_metadata)
+                });
+                (format!("{}!{}", path, member), result)
+            }
+            _ => {
+                let result = retry.run(&input, || {
+                    batch::prepare(output_path).and_then(|()| {
+                        trash.protect(output_path, || {
+                            crate::encrypt_file(&job.input, output_path, &password, config.preserve_metadata)
+                        })
+                    })
+                });
+                (paths::display(output_path), result)
+            }
+        };
+        finish_command(config, "encrypt", &input, &output, result.as_ref().err(), started, &password);
+        records.push(evidence::Record {
+            action: "encrypt",
+            input: input.clone(),
+            output: output.clone(),
+            input_sha256: result.as_ref().ok().map(|report| report.input_checksum),
+            started: started_at,
+            finished: SystemTime::now(),
+            error: result.as_ref().err().cloned(),
+        });
+        let mut entry = history::Entry::now(CryptoAction::Encrypt, job.input.clone(), result.is_ok(), "");
+        // Members of an archive are recorded as the archive they went into.
+        entry.output_path = Some(into_archive.as_ref().map_or_else(|| output_path.clone(), std::path::PathBuf::from));
+        entry.plaintext_sha256 = result.as_ref().ok().map(|report| report.input_checksum);
+        entry.input_changed = result.as_ref().is_ok_and(|report| report.input_changed);
+        entry.retries = retries;
+        if let Err(e) = history.record(entry) {
+            eprintln!("Warning: {}", e);
+        }
+        match result {
+            Ok(report) => {
+                println!("Encrypted '{}' to '{}'.", input, output);
+                for warning in &report.warnings {
+                    eprintln!("Warning: {}", warning);
+                }
+                encrypted += 1;
+            }
+            Err(e) => {
+                eprintln!("Failed to encrypt '{}': {}", input, e);
+                failed += 1;
+            }
+        }
+        if crate::progress::cancelled() {
+            break;
+        }
+    }
+    if let Some((_, pending)) = archive {
+        pending.commit()?;
+    }
+
+    println!("{} file(s) encrypted, {} skipped, {} failed.", encrypted, skipped, failed);
+    if let Some(name) = &evidence_report {
+        let (json_path, text_path) = evidence::write(name, &records)?;
+        println!("Evidence report written to '{}' and '{}'.", json_path, text_path);
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} file(s) could not be encrypted.", failed, jobs.len()));
+    }
+    Ok(())
+}
+
+/// Ask before a run that walks folders and turns out larger than the
+/// `confirm_above_files` or `confirm_above_bytes` setting, in case a path
+/// was mistyped, unless `yes` was given.
+fn confirm_large_run(config: &Config, inputs: &[String], jobs: &[batch::Job], yes: bool) -> Result<(), String> {
+    if yes || !inputs.iter().any(|input| Path::new(input).is_dir()) {
+        return Ok(());
+    }
+    let files = jobs.len() as u64;
+    let bytes: u64 = jobs.iter().map(|job| job.size).sum();
+    let over = |limit: u64, value: u64| limit > 0 && value > limit;
+    if !over(config.confirm_above_files, files) && !over(config.confirm_above_bytes, bytes) {
+        return Ok(());
+    }
+    let answer = tty::prompt(&format!(
+        "The folders given hold {} file(s), {} in all. Encrypt them all? [y/N]: ",
+        files,
+        batch::size_text(bytes)
+    ));
+    if !matches!(answer.to_lowercase().as_str(), "y" | "yes") {
+        return Err("Nothing was encrypted. Add --yes to skip this question.".to_string());
+    }
+    Ok(())
+}
+
+/// `encrypt-tree FOLDER DEST [--encrypt-names]`
+fn encrypt_tree(config: &Config, args: &[String]) -> Result<(), String> {
+    let mut folders = Vec::new();
+    let mut encrypt_names = false;
+    for arg in args {
+        match arg.as_str() {
+            "--encrypt-names" => encrypt_names = true,
+            flag if flag.starts_with('-') => {
+                return Err(format!("Unknown option '{}'.\n\n{}", flag, USAGE));
+            }
+            _ => folders.push(arg.clone()),
+        }
+    }
+    let [source, dest] = folders.as_slice() else {
+        return Err(format!("encrypt-tree needs a folder and a destination.\n\n{}", USAGE));
+    };
+
+    let password = prompt_password();
+    let started = Instant::now();
+    let result = tree::encrypt(config, Path::new(source), Path::new(dest), &password, encrypt_names);
+    finish_command(config, "encrypt", source, dest, result.as_ref().err(), started, &password);
+    let summary = result?;
+    println!("Folder encrypted successfully to '{}'.", dest);
+    print_tree_summary(&summary);
+    Ok(())
+}
+
+/// `decrypt-tree FOLDER DEST`
+fn decrypt_tree(config: &Config, args: &[String], legacy_xor: bool) -> Result<(), String> {
+    if let Some(flag) = args.iter().find(|arg| arg.starts_with('-')) {
+        return Err(format!("Unknown option '{}'.\n\n{}", flag, USAGE));
+    }
+    let [source, dest] = args else {
+        return Err(format!("decrypt-tree needs a folder and a destination.\n\n{}", USAGE));
+    };
+
+    let password = prompt_password();
+    let started = Instant::now();
+    let result = tree::decrypt(config, Path::new(source), Path::new(dest), &password, legacy_xor);
+    finish_command(config, "decrypt", source, dest, result.as_ref().err(), started, &password);
+    let summary = result?;
+    println!("Folder decrypted successfully to '{}'.", dest);
+    print_tree_summary(&summary);
+    Ok(())
+}
+
+/// Run hooks, auditing, and notifications once for a whole tree.
+/// Run the hooks, audit log, and notification for an operation started at
+/// `started` with `password`.
+fn finish_command(
+    config: &Config,
+    action: &'static str,
+    source: &str,
+    dest: &str,
+    error: Option<&String>,
+    started: Instant,
+    password: &str,
+) {
+    let event = HookEvent {
+        action,
+        input: source,
+        output: dest,
+        error: error.map(String::as_str),
+        password: Some(password),
+    };
+    if let Some(warning) = Hooks::from_config(config).run(&event) {
+        eprintln!("Warning: {}", warning);
+    }
+    if let Some(warning) = Auditor::from_config(config).operation_finished(&event) {
+        eprintln!("Warning: {}", warning);
+    }
+    let verb = if action == "encrypt" { "Encrypting" } else { "Decrypting" };
+    Notifier::from_config(config).operation_finished(
+        &format!("{} '{}'", verb, source),
+        event.error,
+        started.elapsed(),
+    );
+}
+
+fn print_tree_summary(summary: &tree::TreeSummary) {
+    println!(
+        "{} file(s) written, {} unchanged, {} folder(s).",
+        summary.files, summary.unchanged, summary.directories
+    );
+    if tuning::enabled() {
+        println!("{}", tuning::describe());
+    }
+    if summary.changed > 0 {
+        println!("{} file(s) kept changing while they were read.", summary.changed);
+    }
+    for warning in &summary.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+}
+
+/// `migrate FILE... [--report FILE] [--manifest FILE]`
+fn migrate(config: &Config, args: &[String], legacy_xor: bool) -> Result<(), String> {
+    let mut files = Vec::new();
+    let mut report = "migration-report.txt".to_string();
+    let mut manifest_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--report" => report = flag_value(&mut args, arg)?,
+            "--manifest" => manifest_path = Some(flag_value(&mut args, arg)?),
+            flag if flag.starts_with('-') => {
+                return Err(format!("Unknown option '{}'.\n\n{}", flag, USAGE));
+            }
+            _ => files.push(arg.clone()),
+        }
+    }
+    if files.is_empty() {
+        return Err(format!("migrate needs at least one file.\n\n{}", USAGE));
+    }
+    guard::refuse("migrate", "rewrites its files in place")?;
+
+    let password = prompt_password();
+    let trash = Trash::new(config.trash_retention_minutes)?;
+    let mut results = Vec::new();
+    for file in files {
+        let result = trash.protect(&file, || migrate::migrate_file(&file, &password, legacy_xor));
+        match &result {
+            Ok(Outcome::Migrated { from, verified, .. }) => {
+                println!("Migrated '{}' from {}.", file, migrate::version_name(*from));
+                if !verified {
+                    eprintln!(
+                        "Warning: '{}' has no checksum, so the password could not be verified.",
+                        file
+                    );
+                }
+            }
+            Ok(Outcome::Current) => println!("'{}' is already in the current format.", file),
+            Err(e) => eprintln!("Failed to migrate '{}': {}", file, e),
+        }
+        results.push((file, result));
+    }
+
+    migrate::write_report(&report, &results)?;
+    println!("Report written to '{}'.", report);
+    if let Some(manifest_path) = &manifest_path {
+        let entries: Vec<_> = results
+            .iter()
+            .map(|(file, result)| manifest::Entry {
+                input: file.clone(),
+                output: file.clone(),
+                status: match result {
+                    Ok(Outcome::Migrated { .. }) => "migrated",
+                    Ok(Outcome::Current) => "current",
+                    Err(_) => "failed",
+                },
+                error: result.as_ref().err().cloned(),
+            })
+            .collect();
+        manifest::write(manifest_path, &entries, password.as_bytes())?;
+        println!("Manifest written to '{}'.", manifest_path);
+    }
+
+    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+    if failed > 0 {
+        return Err(format!("{} of {} file(s) could not be migrated.", failed, results.len()));
+    }
+    Ok(())
+}
+
+/// `reencrypt FOLDER [--report FILE] [--progress FILE] [--dry-run] [--retry-failed]`
+fn reencrypt(config: &Config, args: &[String], legacy_xor: bool) -> Result<(), String> {
+    let mut folder = None;
+    let mut report = "reencrypt-report.txt".to_string();
+    let mut progress_path = "reencrypt-progress.txt".to_string();
+    let mut dry_run = false;
+    let mut retry_failed = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--report" => report = flag_value(&mut args, arg)?,
+            "--progress" => progress_path = flag_value(&mut args, arg)?,
+            "--dry-run" => dry_run = true,
+            "--retry-failed" => retry_failed = true,
+            flag if flag.starts_with('-') => {
+                return Err(format!("Unknown option '{}'.\n\n{}", flag, USAGE));
+            }
+            _ if folder.is_none() => folder = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument '{}'.\n\n{}", arg, USAGE)),
+        }
+    }
+    let folder = folder.ok_or_else(|| format!("reencrypt needs a folder.\n\n{}", USAGE))?;
+    let root = Path::new(&folder);
+
+    let scan = reencrypt::scan(root, legacy_xor)?;
+    println!(
+        "{} encrypted file(s) found, {} to re-encrypt.",
+        scan.encrypted,
+        scan.found.iter().filter(|found| found.outdated.is_ok()).count()
+    );
+    if scan.headerless > 0 {
+        eprintln!(
+            "Warning: {} .enc file(s) have no header and may be from the XOR versions; add --legacy-xor to include them.",
+            scan.headerless
+        );
+    }
+    if dry_run {
+        for found in &scan.found {
+            match &found.outdated {
+                Ok(outdated) => {
+                    println!("{}", paths::display(&found.path));
+                    for weakness in outdated {
+                        println!("  - {}", weakness.describe());
+                    }
+                }
+                Err(e) => eprintln!("Failed to check '{}': {}", paths::display(&found.path), e),
+            }
+        }
+        return Ok(());
+    }
+    guard::refuse("reencrypt", "rewrites its files in place")?;
+
+    let mut progress = reencrypt::Progress::open(Path::new(&progress_path), root)?;
+    if progress.finished() > 0 {
+        println!(
+            "Resuming from '{}': {} file(s) were finished by an earlier run.",
+            progress_path,
+            progress.finished()
+        );
+    }
+    // Files re-encrypted by an earlier run are current and out of the scan
+    // already; failures stay out until --retry-failed.
+    let todo: Vec<_> = scan
+        .found
+        .iter()
+        .filter(|found| progress.get(&found.path).is_none_or(|_| retry_failed))
+        .collect();
+    if todo.len() < scan.found.len() {
+        println!(
+            "{} file(s) that failed before are skipped; add --retry-failed to try them again.",
+            scan.found.len() - todo.len()
+        );
+    }
+
+    let password = if todo.is_empty() { String::new() } else { prompt_password() };
+    let trash = Trash::new(config.trash_retention_minutes)?;
+    let started = Instant::now();
+    for (index, found) in todo.iter().enumerate() {
+        let name = paths::display(&found.path);
+        let (status, detail) = match &found.outdated {
+            Err(e) => ("FAILED", e.clone()),
+            Ok(outdated) => {
+                ui::progress(&format!("Re-encrypting {} of {}: {}", index + 1, todo.len(), name));
+                let result = match found.path.to_str() {
+                    Some(file) => trash.protect(file, || migrate::migrate_file(file, &password, legacy_xor)),
+                    None => Err("The path is not valid Unicode.".to_string()),
+                };
+                ui::end_progress();
+                let codes: Vec<_> = outdated.iter().map(|weakness| weakness.code()).collect();
+                match result {
+                    Ok(Outcome::Migrated { from, plaintext_checksum, .. }) => {
+                        println!("Re-encrypted '{}' ({}).", name, codes.join(", "));
+                        let detail = format!(
+                            "{} ({}) -> version {}, plaintext SHA-256 {}",
+                            migrate::version_name(from),
+                            codes.join(", "),
+                            format::FORMAT_VERSION,
+                            checksum::to_hex(&plaintext_checksum)
+                        );
+                        ("MIGRATED", detail)
+                    }
+                    Ok(Outcome::Current) => ("SKIPPED", format!("already version {}", format::FORMAT_VERSION)),
+                    Err(e) => ("FAILED", e),
+                }
+            }
+        };
+        if status == "FAILED" {
+            eprintln!("Failed to re-encrypt '{}': {}", name, detail);
+        }
+        // A file cut short by Ctrl-C is done again next time.
+        if crate::progress::cancelled() {
+            return Err(format!(
+                "Interrupted. Run the same command again to continue; progress is kept in '{}'.",
+                progress_path
+            ));
+        }
+        progress.record(&found.path, status, &detail)?;
+    }
+
+    let (migrated, failed) = (progress.count("MIGRATED"), progress.count("FAILED"));
+    progress.finish(&report, root)?;
+    println!(
+        "{} file(s) re-encrypted, {} failed, in {:.1}s. Report written to '{}'.",
+        migrated,
+        failed,
+        started.elapsed().as_secs_f64(),
+        report
+    );
+    if failed > 0 {
+        return Err(format!(
+            "{} file(s) could not be re-encrypted; see the report. Those with another password can be done with --retry-failed.",
+            failed
+        ));
+    }
+    Ok(())
+}
+
+/// `recover FILE (--wordlist FILE | --mask MASK) [OPTIONS]`
+fn recover(args: &[String], legacy_xor: bool) -> Result<(), String> {
+    let mut file = None;
+    let mut candidates = None;
+    let mut threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let mut max_candidates = recover::DEFAULT_MAX_CANDIDATES;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--wordlist" => {
+                candidates = Some(Candidates::from_wordlist(&flag_value(&mut args, arg)?)?)
+            }
+            "--mask" => candidates = Some(Candidates::from_mask(&flag_value(&mut args, arg)?)?),
+            "--threads" => threads = parse_count(&flag_value(&mut args, arg)?, arg)? as usize,
+            "--max-candidates" => max_candidates = parse_count(&flag_value(&mut args, arg)?, arg)?,
+            flag if flag.starts_with('-') => {
+                return Err(format!("Unknown option '{}'.\n\n{}", flag, USAGE));
+            }
+            _ if file.is_none() => file = Some(arg.clone()),
+            _ => return Err(format!("Unexpected argument '{}'.\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let file = file.ok_or_else(|| format!("recover needs a file.\n\n{}", USAGE))?;
+    let candidates = candidates
+        .ok_or_else(|| format!("recover needs --wordlist or --mask.\n\n{}", USAGE))?;
+    recover::check_owner(&file)?;
+
+    let count = candidates.count().filter(|&count| count <= max_candidates);
+    let Some(count) = count else {
+        return Err(format!(
+            "That would try more than {} candidates. Narrow the mask or raise --max-candidates.",
+            max_candidates
+        ));
+    };
+
+    println!("Trying {} candidate(s) on {} thread(s).", count, threads);
+    match recover::search(&file, &candidates, threads, legacy_xor)? {
+        Some(password) => {
+            println!("Password found: {}", password);
+            Ok(())
+        }
+        None => Err("None of the candidates matched.".to_string()),
+    }
+}
+
+/// `vault FILE (list | add NAME | get NAME | remove NAME)`
+fn vault(config: &Config, args: &[String]) -> Result<(), String> {
+    let (path, action, name) = match args {
+        [path, action] => (path, action.as_str(), None),
+        [path, action, name] => (path, action.as_str(), Some(name.as_str())),
+        _ => return Err(format!("vault needs a file and an action.\n\n{}", USAGE)),
+    };
+    if !matches!(
+        (action, name),
+        ("list", None) | ("add" | "get" | "remove", Some(_))
+    ) {
+        return Err(format!("Unknown vault action '{}'.\n\n{}", args[1..].join(" "), USAGE));
+    }
+
+    let attempts = Attempts::before_unlock(Path::new(path), config)?;
+    let password = prompt_password();
+    let mut vault = attempts.record(Vault::open(path, &password))?;
+    match (action, name) {
+        ("add", Some(name)) => {
+            vault.add(name, &tty::prompt(&format!("Enter value for '{}': ", name)))?;
+        }
+        ("remove", Some(name)) => vault.remove(name)?,
+        ("get", Some(name)) => {
+            println!("{}", vault.get(name)?);
+            return Ok(());
+        }
+        _ => {
+            for name in vault.names() {
+                println!("{}", name);
+            }
+            return Ok(());
+        }
+    }
+
+    let trash = Trash::new(config.trash_retention_minutes)?;
+    trash.protect(path, || vault.save(path, &password))?;
+    println!("Vault '{}' saved.", path);
+    Ok(())
+}
+
+/// `log (append LOG FILE [--range START-END] [--chunk-size SIZE]
+/// | write LOG [--chunk-size SIZE] | decrypt LOG OUTPUT [--salvage])`
+fn log(args: &[String]) -> Result<(), String> {
+    let (log_path, input, options) = match args {
+        [action, log_path, output, flag] if action == "decrypt" && flag == "--salvage" => {
+            let (summary, gaps) = appendlog::salvage(Path::new(log_path), Path::new(output), &prompt_password())?;
+            println!(
+                "Decrypted {} bytes from {} record(s) to '{}'.",
+                summary.bytes, summary.records, output
+            );
+            return report_gaps(&gaps, Path::new(output));
+        }
+        [action, log_path, output] if action == "decrypt" => {
+            let summary = appendlog::decrypt(Path::new(log_path), Path::new(output), &prompt_password())?;
+            println!(
+                "Decrypted {} bytes from {} record(s) to '{}'.",
+                summary.bytes, summary.records, output
+            );
+            return Ok(());
+        }
+        [action, log_path, input, options @ ..] if action == "append" && !input.starts_with('-') => {
+            (log_path, Some(input), options)
+        }
+        [action, log_path, options @ ..] if action == "write" => (log_path, None, options),
+        _ => return Err(format!("Unknown log action '{}'.\n\n{}", args.join(" "), USAGE)),
+    };
+
+    let mut range = None;
+    let mut chunk_size = None;
+    let mut options = options.iter();
+    while let Some(arg) = options.next() {
+        match (arg.as_str(), input) {
+            ("--range", Some(input)) => {
+                let text = flag_value(&mut options, arg)?;
+                let input_len = std::fs::metadata(input)
+                    .map_err(|e| format!("Failed to read '{}': {}", input, e))?
+                    .len();
+                range = Some(appendlog::parse_range(&text, input_len)?);
+            }
+            ("--chunk-size", _) => {
+                let size = filter::parse_size(&flag_value(&mut options, arg)?)?;
+                let size = usize::try_from(size).unwrap_or(usize::MAX);
+                appendlog::check_chunk_size(size)?;
+                chunk_size = Some(size);
+            }
+            (flag, _) if flag.starts_with('-') => {
+                return Err(format!("Unknown option '{}'.\n\n{}", flag, USAGE));
+            }
+            _ => return Err(format!("Unexpected argument '{}'.\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let password = prompt_password();
+    if !Path::new(log_path).exists() && tty::prompt("Confirm password: ") != password {
+        return Err("The passwords do not match.".to_string());
+    }
+    let mut writer = appendlog::EncryptedLogWriter::open_or_create(
+        Path::new(log_path),
+        &password,
+        chunk_size.unwrap_or_else(memory::log_chunk_size),
+    )?;
+    if let Some(chunk_size) = chunk_size
+        && chunk_size != writer.chunk_size()
+    {
+        return Err(format!(
+            "'{}' already uses a chunk size of {} bytes; --chunk-size only applies to new logs.",
+            log_path,
+            writer.chunk_size()
+        ));
+    }
+
+    let Some(input) = input else {
+        let records_before = writer.records();
+        let mut stdin = io::stdin().lock();
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            if stdin.read_until(b'\n', &mut line).map_err(|e| e.to_string())? == 0 {
+                break;
+            }
+            for record in line.chunks(writer.chunk_size()) {
+                writer.append(record)?;
+            }
+        }
+        writer.sync()?;
+        eprintln!(
+            "Wrote {} record(s) to '{}'.",
+            writer.records() - records_before,
+            log_path
+        );
+        return Ok(());
+    };
+    let summary = appendlog::append(&mut writer, Path::new(input), range)?;
+    println!(
+        "Appended {} bytes in {} record(s) to '{}'; it now holds {} bytes.",
+        summary.bytes, summary.records, log_path, summary.total
+    );
+    Ok(())
+}
+
+/// Say what `--salvage` could not recover and write the gap map for
+/// `output`, failing so scripts notice the output is incomplete.
+fn report_gaps(gaps: &GapMap, output: &Path) -> Result<(), String> {
+    if gaps.is_empty() {
+        return Ok(());
+    }
+    let map = gaps.write(output)?;
+    Err(format!(
+        "{} damaged stretch(es) could not be recovered; {} bytes were filled with zeros. Their byte ranges are listed in '{}'.",
+        gaps.len(),
+        gaps.bytes(),
+        map.display()
+    ))
+}
+
+/// `store (init REPO | backup REPO PATH | snapshots REPO | restore REPO ID DEST
+/// [--salvage] | diff REPO OLD NEW | prune REPO [OPTIONS])`
+fn store(args: &[String]) -> Result<(), String> {
+    if let [action, repo, options @ ..] = args
+        && action == "prune"
+    {
+        return store_prune(repo, options);
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["init", repo] => {
+            let password = prompt_password();
+            if tty::prompt("Confirm password: ") != password {
+                return Err("The passwords do not match.".to_string());
+            }
+            Repository::init(Path::new(repo), &password)?;
+            println!("Created repository '{}'.", repo);
+        }
+        ["backup", repo, source] => {
+            let repository = Repository::open(Path::new(repo), &prompt_password())?;
+            let summary = repository.backup(Path::new(source))?;
+            for warning in &summary.warnings {
+                println!("Warning: {}", warning);
+            }
+            println!(
+                "Snapshot {} saved: {} file(s), {} bytes in {} chunk(s).",
+                summary.snapshot_id, summary.files, summary.bytes, summary.chunks
+            );
+            println!(
+                "{} chunk(s) were new ({} bytes); the rest were already stored.",
+                summary.new_chunks, summary.new_bytes
+            );
+        }
+        ["snapshots", repo] => {
+            let repository = Repository::open(Path::new(repo), &prompt_password())?;
+            let snapshots = repository.snapshots()?;
+            if snapshots.is_empty() {
+                println!("The repository has no snapshots yet.");
+            }
+            for snapshot in snapshots {
+                let bytes: u64 = snapshot.files.iter().map(|file| file.size).sum();
+                println!(
+                    "{}  {}  {} file(s), {} bytes  {}",
+                    snapshot.id,
+                    history::format_date(snapshot.time),
+                    snapshot.files.len(),
+                    bytes,
+                    snapshot.source
+                );
+            }
+        }
+        ["restore", repo, id, dest, options @ ..] if matches!(options, [] | ["--salvage"]) => {
+            let repository = Repository::open(Path::new(repo), &prompt_password())?;
+            let snapshot = repository.find_snapshot(id)?;
+            let mut gaps = GapMap::default();
+            let salvage = !options.is_empty();
+            let files = repository.restore(&snapshot, Path::new(dest), salvage.then_some(&mut gaps))?;
+            println!(
+                "Restored {} file(s) from snapshot {} into '{}'.",
+                files, snapshot.id, dest
+            );
+            report_gaps(&gaps, Path::new(dest))?;
+        }
+        ["diff", repo, old, new] => {
+            let repository = Repository::open(Path::new(repo), &prompt_password())?;
+            let (old, new) = (repository.find_snapshot(old)?, repository.find_snapshot(new)?);
+            let (changes, unchanged) = store::diff(&old, &new);
+            for (change, path) in &changes {
+                let marker = match change {
+                    Change::Added => '+',
+                    Change::Removed => '-',
+                    Change::Modified => 'M',
+                };
+                println!("{} {}", marker, String::from_utf8_lossy(path));
+            }
+            let count = |kind| changes.iter().filter(|(change, _)| *change == kind).count();
+            println!(
+                "{} added, {} removed, {} modified, {} unchanged between {} and {}.",
+                count(Change::Added),
+                count(Change::Removed),
+                count(Change::Modified),
+                unchanged,
+                old.id,
+                new.id
+            );
+        }
+        _ => return Err(format!("Unknown store action.\n\n{}", USAGE)),
+    }
+    Ok(())
+}
+
+/// `store prune REPO [OPTIONS]`
+fn store_prune(repo: &str, args: &[String]) -> Result<(), String> {
+    let mut retention = Retention {
+        keep_last: 1,
+        keep_since: None,
+    };
+    let mut dry_run = false;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--keep-last" => {
+                let value = flag_value(&mut args, arg)?;
+                retention.keep_last = match value.parse() {
+                    Ok(count) if count > 0 => count,
+                    _ => return Err(format!("'--keep-last' must be at least 1, got '{}'.", value)),
+                };
+            }
+            "--keep-within" => {
+                retention.keep_since = Some(history::parse_when(&flag_value(&mut args, arg)?)?)
+            }
+            "--dry-run" => dry_run = true,
+            other => return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE)),
+        }
+    }
+
+    let repository = Repository::open(Path::new(repo), &prompt_password())?;
+    let report = repository.prune(&retention, dry_run)?;
+    let verb = if dry_run { "Would remove" } else { "Removed" };
+    for (id, time) in &report.removed_snapshots {
+        println!("{} snapshot {} from {}", verb, id, history::format_date(*time));
+    }
+    println!(
+        "{} {} snapshot(s) and {} unused chunk(s), {} {} bytes. {} snapshot(s) kept.",
+        verb,
+        report.removed_snapshots.len(),
+        report.removed_chunks,
+        if dry_run { "reclaiming" } else { "reclaimed" },
+        report.reclaimed_bytes,
+        report.kept_snapshots
+    );
+    Ok(())
+}
+
+/// `container (create FILE SIZE | open FILE [--socket PATH])`
+fn container(config: &Config, args: &[String]) -> Result<(), String> {
+    match args {
+        [action, path, size] if action == "create" => {
+            let size = filter::parse_size(size)?;
+            let password = prompt_password();
+            if tty::prompt("Confirm password: ") != password {
+                return Err("The passwords do not match.".to_string());
+            }
+            container::create(Path::new(path), size, &password)?;
+            println!(
+                "Created container '{}' holding {} bytes. Open it with 'container open {}' and format it before first use.",
+                path,
+                size.next_multiple_of(container::BLOCK_LEN as u64),
+                path
+            );
+            Ok(())
+        }
+        [action, path, options @ ..] if action == "open" => {
+            let socket = match options {
+                [] => format!("{}.sock", path),
+                [flag, socket] if flag == "--socket" => socket.clone(),
+                _ => return Err(format!("Unknown container option.\n\n{}", USAGE)),
+            };
+            open_container(config, path, &socket)
+        }
+        _ => Err(format!("container needs 'create' or 'open'.\n\n{}", USAGE)),
+    }
+}
+
+#[cfg(unix)]
+fn open_container(config: &Config, path: &str, socket: &str) -> Result<(), String> {
+    let attempts = Attempts::before_unlock(Path::new(path), config)?;
+    let container =
+        attempts.record(container::Container::open(Path::new(path), &prompt_password()))?;
+    println!("Serving '{}' ({} bytes) on '{}'.", path, container.size(), socket);
+    println!("Attach it as a drive with:");
+    println!("  sudo nbd-client -unix {} /dev/nbd0", socket);
+    println!("The first time, format it (e.g. sudo mkfs.ext4 /dev/nbd0), then mount /dev/nbd0.");
+    println!("To close it, unmount and run 'sudo nbd-client -d /dev/nbd0'.");
+    crate::nbd::serve(&container, Path::new(socket))?;
+    println!("Container '{}' closed.", path);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn open_container(_config: &Config, _path: &str, _socket: &str) -> Result<(), String> {
+    Err("Opening containers needs Unix sockets and is not supported on this system.".to_string())
+}
+
+/// `timelock (lock FILE --until DATE | open FILE) [--output FILE]`
+fn timelock(args: &[String]) -> Result<(), String> {
+    let (action, path, options) = match args {
+        [action, path, options @ ..] if action == "lock" || action == "open" => {
+            (action.as_str(), path, options)
+        }
+        _ => return Err(format!("timelock needs 'lock' or 'open'.\n\n{}", USAGE)),
+    };
+    let mut until = None;
+    let mut output = None;
+    let mut options = options.iter();
+    while let Some(arg) = options.next() {
+        match arg.as_str() {
+            "--until" if action == "lock" => {
+                until = Some(history::parse_when(&flag_value(&mut options, arg)?)?)
+            }
+            "--for" if action == "lock" => {
+                let value = flag_value(&mut options, arg)?;
+                if value.contains('-') {
+                    return Err(format!("'--for' takes a length of time such as 30d, got '{}'.", value));
+                }
+                // parse_when counts ages back from now; mirror that forward.
+                let now = history::parse_when("0s")?;
+                until = Some(2 * now - history::parse_when(&value)?);
+            }
+            "--output" => output = Some(flag_value(&mut options, arg)?),
+            other => return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE)),
+        }
+    }
+    let (data, changed) =
+        throttle::read_consistent(path).map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+
+    if action == "lock" {
+        if changed {
+            eprintln!("Warning: {}", crate::input_changed_warning(path));
+        }
+        let until = until
+            .ok_or_else(|| format!("timelock lock needs --until DATE or --for AGE.\n\n{}", USAGE))?;
+        let output = output.unwrap_or_else(|| format!("{}.tlock", path));
+        println!("Measuring how fast this computer solves the puzzle...");
+        let rate = timelock::squarings_per_second()?;
+        let locked = timelock::lock(&data, until, rate)?;
+        throttle::write(&output, &locked)
+            .map_err(|e| format!("Failed to write '{}': {}", output, e))?;
+        println!(
+            "Locked '{}' into '{}'. Opening it takes about until {} on a computer like this one, working without a break.",
+            path,
+            output,
+            history::format_date(until)
+        );
+        println!("A faster computer or faster code may open it sooner, so leave a margin.");
+        return Ok(());
+    }
+
+    let (puzzle, sealed) = timelock::read_puzzle(&data)?;
+    let output = output.unwrap_or_else(|| {
+        path.strip_suffix(".tlock")
+            .map_or_else(|| format!("{}.dec", path), str::to_string)
+    });
+    println!(
+        "This file was locked until about {}. Solving {} squarings; progress is saved every minute.",
+        history::format_date(puzzle.until),
+        puzzle.squarings
+    );
+    let started = Instant::now();
+    let mut baseline = None;
+    let password = puzzle.solve(Path::new(&format!("{}.progress", path)), &mut |done| {
+        let (first_done, first_time) = *baseline.get_or_insert((done, Instant::now()));
+        let rate = (done - first_done) as f64 / first_time.elapsed().as_secs_f64().max(1e-3);
+        let line = match (puzzle.squarings - done) as f64 / rate {
+            left if rate > 0.0 && left.is_finite() => format!(
+                "{:.1}% done, about {} left",
+                done as f64 * 100.0 / puzzle.squarings as f64,
+                format_duration(left as u64)
+            ),
+            _ => format!("{:.1}% done", done as f64 * 100.0 / puzzle.squarings as f64),
+        };
+        ui::progress(&line);
+    });
+    ui::end_progress();
+    let decrypted = crate::decrypt_bytes(sealed, password.as_bytes(), false)
+        .map_err(|_| "The puzzle was solved, but the contents did not decrypt; the file is damaged.".to_string())?;
+    throttle::write(&output, &decrypted.plaintext)
+        .map_err(|e| format!("Failed to write '{}': {}", output, e))?;
+    println!(
+        "Solved in {} and decrypted to '{}'.",
+        format_duration(started.elapsed().as_secs()),
+        output
+    );
+    Ok(())
+}
+
+/// Format a number of seconds as `3d 4h`, `5h 12m`, or `40s`.
+fn format_duration(seconds: u64) -> String {
+    let (days, hours, minutes) = (seconds / 86_400, seconds / 3_600 % 24, seconds / 60 % 60);
+    if days > 0 {
+        format!("{}d {}h", days, hours)
+    } else if hours > 0 {
+        format!("{}h {}m", hours, minutes)
+    } else if minutes > 0 {
+        format!("{}m {}s", minutes, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    }
+}
+
+/// `keygen NAME`
+fn keygen(args: &[String]) -> Result<(), String> {
+    let [name] = args else {
+        return Err(format!("keygen needs a NAME for the key files.\n\n{}", USAGE));
+    };
+    let password = prompt_password();
+    if tty::prompt("Confirm password: ") != password {
+        return Err("The passwords do not match.".to_string());
+    }
+    let public = signature::generate(name, &password)?;
+    println!("Created '{}.key' (private) and '{}.pub' (public).", name, name);
+    println!("Public key: {}", checksum::to_hex(&public));
+    println!("Fingerprint: {}", signature::fingerprint(&public));
+    println!("Give the public key to recipients so they can check your signatures.");
+    Ok(())
+}
+
+/// `contact (add NAME KEY [--email ADDRESS] | list | verify NAME
+/// | revoke NAME | import-revocation FILE | remove NAME)`
+fn contact(config: &Config, args: &[String]) -> Result<(), String> {
+    let mut contacts = Contacts::load(config)?;
+    match args {
+        [action] if action == "list" => {
+            if contacts.entries().is_empty() {
+                println!("No contacts yet. Add one with 'contact add NAME KEY'.");
+            }
+            for contact in contacts.entries() {
+                println!(
+                    "{:<20} {:<30} {}  {}",
+                    contact.name,
+                    contact.email.as_deref().unwrap_or("-"),
+                    signature::fingerprint(&contact.key),
+                    contact_state(contact)
+                );
+            }
+            Ok(())
+        }
+        [action, name] if action == "verify" => {
+            let contact = contacts
+                .find(name)
+                .ok_or_else(|| format!("No contact is called '{}'.", name))?
+                .clone();
+            println!("Fingerprint of '{}': {}", contact.name, signature::fingerprint(&contact.key));
+            println!("Ask {} to read out the fingerprint of their key ('fingerprint NAME.pub'),", contact.name);
+            println!("in person or over the phone, not through the channel the key came by.");
+            if !matches!(tty::prompt("Do they match exactly? [y/N]: ").to_lowercase().as_str(), "y" | "yes") {
+                return Err(format!("'{}' was not marked as verified.", contact.name));
+            }
+            contacts.verify(name)?;
+            contacts.save()?;
+            println!("Marked '{}' as verified.", contact.name);
+            Ok(())
+        }
+        [action, name] if action == "revoke" => {
+            let name = contacts.revoke(name)?;
+            contacts.save()?;
+            println!("Marked the key of '{}' as revoked. New files can no longer be encrypted for it.", name);
+            Ok(())
+        }
+        [action, file] if action == "import-revocation" => {
+            let (key, reason) = signature::read_revocation(file)?;
+            let name = contacts.revoke_key(key);
+            contacts.save()?;
+            println!("Marked the key of '{}' ({}) as revoked.", name, signature::fingerprint(&key));
+            if !reason.is_empty() {
+                println!("Reason given: {}", reason);
+            }
+            Ok(())
+        }
+        [action, name] if action == "remove" => {
+            let removed = contacts.remove(name)?;
+            contacts.save()?;
+            println!("Removed '{}' from '{}'.", removed.name, contacts.path().display());
+            Ok(())
+        }
+        [action, name, key, options @ ..] if action == "add" => {
+            let mut email = None;
+            let mut options = options.iter();
+            while let Some(arg) = options.next() {
+                match arg.as_str() {
+                    "--email" => email = Some(flag_value(&mut options, arg)?),
+                    other => return Err(format!("Unknown contact option '{}'.\n\n{}", other, USAGE)),
+                }
+            }
+            let key = signature::load_public_key(key)?;
+            contacts.add(name, email.as_deref(), key)?;
+            contacts.save()?;
+            println!("Added '{}' to '{}'.", name, contacts.path().display());
+            println!("Fingerprint: {}", signature::fingerprint(&key));
+            println!("Before encrypting anything sensitive for {}, compare it with them and run 'contact verify {}'.", name, name);
+            Ok(())
+        }
+        _ => Err(format!(
+            "contact needs add, list, verify, revoke, import-revocation, or remove.\n\n{}",
+            USAGE
+        )),
+    }
+}
+
+fn contact_state(contact: &Contact) -> &'static str {
+    match (contact.revoked, contact.verified) {
+        (true, _) => "REVOKED",
+        (false, true) => "verified",
+        (false, false) => "not verified",
+    }
+}
+
+/// `fingerprint KEY...`
+fn fingerprint(config: &Config, args: &[String]) -> Result<(), String> {
+    if args.is_empty() {
+        return Err(format!("fingerprint needs a contact or public key.\n\n{}", USAGE));
+    }
+    for text in args {
+        let (key, contact) = crate::contacts::resolve(config, text)?;
+        let label = match contact {
+            Some(contact) => format!("{} ({})", contact.name, contact_state(&contact)),
+            None => text.clone(),
+        };
+        println!("{}  {}", signature::fingerprint(&key), label);
+    }
+    Ok(())
+}
+
+/// `revoke-key NAME.key [--reason TEXT] [--output FILE]`
+fn revoke_key(args: &[String]) -> Result<(), String> {
+    let mut key = None;
+    let mut reason = String::new();
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--reason" => reason = flag_value(&mut args, arg)?,
+            "--output" => output = Some(flag_value(&mut args, arg)?),
+            other if other.starts_with("--") => {
+                return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE));
+            }
+            _ if key.is_none() => key = Some(arg.clone()),
+            other => return Err(format!("Unexpected argument '{}'.\n\n{}", other, USAGE)),
+        }
+    }
+    let key = key.ok_or_else(|| format!("revoke-key needs the private key file.\n\n{}", USAGE))?;
+    let output = output.unwrap_or_else(|| {
+        key.strip_suffix(".key")
+            .map_or_else(|| format!("{}.rev", key), |name| format!("{}.rev", name))
+    });
+    let seed = signature::load_private_key(&key, &prompt_password())?;
+    signature::write_revocation(&seed, &reason, &output)?;
+    println!("Wrote '{}' revoking {}.", output, signature::fingerprint(&crate::ed25519::public_key(&seed)));
+    println!("Send it to everyone who encrypts for you; they import it with 'contact import-revocation {}'.", output);
+    Ok(())
+}
+
+/// `inspect FILE...`
+fn inspect(config: &Config, args: &[String]) -> Result<(), String> {
+    if args.is_empty() {
+        return Err(format!("inspect needs a file.\n\n{}", USAGE));
+    }
+    // Without a keyring, recipients are shown by fingerprint alone.
+    let contacts = Contacts::load(config).ok();
+    let mut flagged = 0;
+    for (index, path) in args.iter().enumerate() {
+        if index > 0 {
+            println!();
+        }
+        println!("{}", path);
+        let mut header = Vec::new();
+        let longest = format::SEALED_HEADER_LEN + 1 + u8::MAX as usize * format::STANZA_LEN;
+        std::fs::File::open(path)
+            .and_then(|file| file.take(longest as u64).read_to_end(&mut header))
+            .map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+        let version = match format::split_header(&header).map_err(format::message)? {
+            None => {
+                println!("  Format:          no header (XOR, from before version 1; insecure)");
+                continue;
+            }
+            Some((version, _)) if version < format::SEALED_VERSION => {
+                println!("  Format:          version {} (XOR; insecure)", version);
+                continue;
+            }
+            Some((version, _)) => version,
+        };
+        let params = format::parse_sealed_header(&header).map_err(format::message)?;
+        let cipher = match params.cipher {
+            Cipher::ChaCha20 => "ChaCha20 with HMAC-SHA256",
+            Cipher::AesGcm => "AES-256-GCM",
+        };
+        println!("  Format:          version {} ({})", version, cipher);
+        println!("  Key derivation:  PBKDF2-SHA256, {} iterations", params.iterations);
+        if params.stanzas.is_empty() {
+            println!("  Recipients:      none (password only)");
+            continue;
+        }
+        println!("  Recipients:      {}", params.stanzas.len());
+        for stanza in &params.stanzas {
+            let key = crate::recipients::recipient(stanza);
+            let contact = contacts.as_ref().and_then(|contacts| contacts.find_key(&key));
+            let label = contact.map_or_else(|| "unknown key".to_string(), |contact| contact.name.clone());
+            let revoked = contact.is_some_and(|contact| contact.revoked);
+            println!(
+                "    {}  {}{}",
+                signature::fingerprint(&key),
+                label,
+                if revoked { "  REVOKED" } else { "" }
+            );
+            if revoked {
+                flagged += 1;
+            }
+        }
+    }
+    if flagged > 0 {
+        return Err(format!(
+            "{} key slot(s) belong to revoked keys. Re-encrypt those files without them so copies made from now on cannot be opened with those keys.",
+            flagged
+        ));
+    }
+    Ok(())
+}
+
+/// `sign PATH... (--key FILE | --agent [--public-key KEY])`
+fn sign(args: &[String]) -> Result<(), String> {
+    let mut paths = Vec::new();
+    let mut key = None;
+    let mut agent = false;
+    let mut public_key = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--key" => key = Some(flag_value(&mut args, arg)?),
+            "--agent" => agent = true,
+            "--public-key" => public_key = Some(flag_value(&mut args, arg)?),
+            other if other.starts_with("--") => {
+                return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE));
+            }
+            _ => paths.push(arg.clone()),
+        }
+    }
+    if paths.is_empty() {
+        return Err(format!("sign needs at least one file.\n\n{}", USAGE));
+    }
+    let files = match (key, agent) {
+        (Some(_), true) => return Err("Use either --key or --agent, not both.".to_string()),
+        (None, true) => {
+            let files = signature::collect_files(&paths)?;
+            sign_with_agent(&files, public_key.as_deref())?;
+            files
+        }
+        (Some(_), false) if public_key.is_some() => {
+            return Err("--public-key only applies with --agent.".to_string());
+        }
+        (Some(key), false) => {
+            let files = signature::collect_files(&paths)?;
+            let seed = signature::load_private_key(&key, &prompt_password())?;
+            for file in &files {
+                let sig_path = signature::sign_file(file, &seed)?;
+                println!("Signed '{}' -> '{}'", file.display(), sig_path.display());
+            }
+            files
+        }
+        (None, false) => return Err(format!("sign needs --key FILE or --agent.\n\n{}", USAGE)),
+    };
+    println!("Signed {} file(s).", files.len());
+    Ok(())
+}
+
+/// Sign `files` through the agent in `$SSH_AUTH_SOCK`, with the key
+/// `public_key` or else the only Ed25519 key it holds.
+#[cfg(unix)]
+fn sign_with_agent(files: &[std::path::PathBuf], public_key: Option<&str>) -> Result<(), String> {
+    use crate::agent;
+
+    let socket = agent::client_socket(None)?;
+    let public = match public_key {
+        Some(text) => signature::load_public_key(text)?,
+        None => {
+            let keys: Vec<_> = agent::list(&socket)?.into_iter().filter_map(|key| key.public).collect();
+            match keys.as_slice() {
+                [public] => *public,
+                [] => return Err("The agent holds no Ed25519 key. Add one with 'agent add-key'.".to_string()),
+                _ => return Err("The agent holds several keys; choose one with --public-key.".to_string()),
+            }
+        }
+    };
+    for file in files {
+        let sig_path = signature::sign_file_with(file, &public, |data| agent::sign(&socket, &public, data))?;
+        println!("Signed '{}' -> '{}'", file.display(), sig_path.display());
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn sign_with_agent(_files: &[std::path::PathBuf], _public_key: Option<&str>) -> Result<(), String> {
+    Err("The key agent needs Unix sockets and is not supported on this system.".to_string())
+}
+
+/// `verify-signature PATH... --public-key KEY`
+fn verify_signature(args: &[String]) -> Result<(), String> {
+    let mut paths = Vec::new();
+    let mut public_key = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--public-key" => public_key = Some(flag_value(&mut args, arg)?),
+            other if other.starts_with("--") => {
+                return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE));
+            }
+            _ => paths.push(arg.clone()),
+        }
+    }
+    // The key inside a signature file only says who claims to have signed;
+    // trusting it would let anyone re-sign a modified file.
+    let public_key = public_key.ok_or_else(|| {
+        format!("verify-signature needs --public-key with the signer's key.\n\n{}", USAGE)
+    })?;
+    if paths.is_empty() {
+        return Err(format!("verify-signature needs at least one file.\n\n{}", USAGE));
+    }
+
+    let public = signature::load_public_key(&public_key)?;
+    let files = signature::collect_files(&paths)?;
+    let (mut valid, mut failed) = (0, 0);
+    for file in &files {
+        let problem = match signature::verify_file(file, &public) {
+            Ok(Verdict::Valid) => {
+                valid += 1;
+                println!("ok        {}", file.display());
+                continue;
+            }
+            Ok(Verdict::Unsigned) => "no signature file".to_string(),
+            Ok(Verdict::OtherKey) => "signed with a different key".to_string(),
+            Ok(Verdict::Invalid) => "signature does not match; the file was modified".to_string(),
+            Err(e) => e,
+        };
+        failed += 1;
+        println!("FAILED    {}: {}", file.display(), problem);
+    }
+    if failed > 0 {
+        return Err(format!(
+            "{} of {} file(s) failed verification.",
+            failed,
+            files.len()
+        ));
+    }
+    println!("All {} file(s) have valid signatures from {}.", valid, checksum::to_hex(&public));
+    Ok(())
+}
+
+/// `hash FILE... [--algorithm NAME] [--decrypt] [--check LIST]`
+fn hash(args: &[String], legacy_xor: bool) -> Result<(), String> {
+    let mut files = Vec::new();
+    let mut algorithm = Algorithm::Sha256;
+    let mut decrypt = false;
+    let mut check = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--algorithm" => algorithm = Algorithm::parse(&flag_value(&mut args, arg)?)?,
+            "--decrypt" => decrypt = true,
+            "--check" => check = Some(flag_value(&mut args, arg)?),
+            other if other.starts_with("--") => {
+                return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE));
+            }
+            _ => files.push(arg.clone()),
+        }
+    }
+    // Each entry is a file to hash and, when checking, the digest it
+    // should have.
+    let entries: Vec<(String, Option<String>)> = match check {
+        Some(_) if !files.is_empty() => {
+            return Err("Give either files to hash or --check LIST, not both.".to_string());
+        }
+        Some(list) => {
+            let text = std::fs::read_to_string(&list).map_err(|e| format!("Failed to read '{}': {}", list, e))?;
+            let mut entries = Vec::new();
+            for line in text.lines() {
+                if let Some(parsed) = digest::parse_line(line) {
+                    let (expected, path) = parsed.map_err(|e| format!("{}: {}", list, e))?;
+                    entries.push((path, Some(expected)));
+                }
+            }
+            entries
+        }
+        None if files.is_empty() => return Err(format!("hash needs at least one file.\n\n{}", USAGE)),
+        None => files.into_iter().map(|file| (file, None)).collect(),
+    };
+
+    let password = decrypt.then(prompt_password);
+    let digest_of = |path: &str| -> Result<String, String> {
+        let digest = match &password {
+            None => digest::hash_file(Path::new(path), algorithm)
+                .map_err(|e| format!("Failed to read '{}': {}", path, e))?,
+            Some(password) => {
+                let data = throttle::read(path).map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+                let decrypted = crate::decrypt_bytes(&data, password.as_bytes(), legacy_xor)
+                    .map_err(|e| format!("'{}': {}", path, e))?;
+                if archive::is_archive(&decrypted.plaintext) {
+                    return Err(format!("'{}' holds a folder; decrypt it and hash the files inside.", path));
+                }
+                digest::hash_bytes(&decrypted.plaintext, algorithm)
+            }
+        };
+        Ok(checksum::to_hex(&digest))
+    };
+
+    let mut failed = 0;
+    for (path, expected) in &entries {
+        match (digest_of(path), expected) {
+            (Ok(actual), None) => println!("{}  {}", actual, path),
+            (Ok(actual), Some(expected)) if actual == *expected => println!("{}: OK", path),
+            (Ok(_), Some(_)) => {
+                failed += 1;
+                println!("{}: FAILED", path);
+            }
+            (Err(e), _) => {
+                failed += 1;
+                eprintln!("Error: {}", e);
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(format!("{} of {} file(s) could not be hashed or did not match.", failed, entries.len()));
+    }
+    Ok(())
+}
+
+/// `decrypt-with-key FILE --key FILE [--output PATH]`
+fn decrypt_with_key(config: &Config, args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut key = None;
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--key" => key = Some(flag_value(&mut args, arg)?),
+            "--output" => output = Some(flag_value(&mut args, arg)?),
+            other if other.starts_with("--") => {
+                return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE));
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            other => return Err(format!("Unexpected argument '{}'.\n\n{}", other, USAGE)),
+        }
+    }
+    let path = path.ok_or_else(|| format!("decrypt-with-key needs a file.\n\n{}", USAGE))?;
+    let key = key.ok_or_else(|| format!("decrypt-with-key needs --key FILE.\n\n{}", USAGE))?;
+    let output = output.unwrap_or_else(|| {
+        path.strip_suffix(".enc")
+            .map_or_else(|| format!("{}.dec", path), str::to_string)
+    });
+    if Path::new(&output).exists() {
+        return Err(format!("'{}' already exists.", output));
+    }
+
+    let password = prompt_password();
+    let result = write_for_recipient(&path, &key, &password, &output);
+    let event = HookEvent {
+        action: "decrypt",
+        input: &path,
+        output: &output,
+        error: result.as_ref().err().map(String::as_str),
+        password: None,
+    };
+    if let Some(warning) = Auditor::from_config(config).operation_finished(&event) {
+        eprintln!("Warning: {}", warning);
+    }
+    let (mut warnings, metadata) = result?;
+    if config.preserve_metadata {
+        warnings.extend(metadata.restore(&output));
+    }
+    for warning in &warnings {
+        println!("Warning: {}", warning);
+    }
+    println!("Decrypted '{}' -> '{}'", path, output);
+    Ok(())
+}
+
+/// Decrypt `path` with the recipient key in `key` and write it to
+/// `output`, unpacking folders. Returns the warnings from unpacking and
+/// the stored metadata.
+fn write_for_recipient(
+    path: &str,
+    key: &str,
+    password: &str,
+    output: &str,
+) -> Result<(Vec<String>, FileMetadata), String> {
+    let data = throttle::read(path).map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+    let seed = signature::load_private_key(key, password)?;
+    let (plaintext, metadata) = crate::open_sealed_for_recipient(&data, &seed)?;
+    if archive::is_archive(&plaintext) {
+        return Ok((archive::unpack(&plaintext, Path::new(output))?.warnings, metadata));
+    }
+    throttle::write(output, &plaintext).map_err(|e| format!("Failed to write '{}': {}", output, e))?;
+    Ok((Vec::new(), metadata))
+}
+
+/// `daemon (start | unlock | lock | status | stats | stop | encrypt FILE |
+/// decrypt FILE) [OPTIONS]`
+#[cfg(unix)]
+fn daemon(config: &Config, args: &[String]) -> Result<(), String> {
+    use crate::daemon;
+
+    let (action, rest) = args
+        .split_first()
+        .ok_or_else(|| format!("daemon needs an action.\n\n{}", USAGE))?;
+    let mut file = None;
+    let mut output = None;
+    let mut socket = None;
+    let mut timeout = None;
+    let mut user = None;
+    let mut metrics = None;
+    let mut options = rest.iter();
+    while let Some(arg) = options.next() {
+        match arg.as_str() {
+            "--socket" => socket = Some(flag_value(&mut options, arg)?),
+            "--output" => output = Some(flag_value(&mut options, arg)?),
+            "--user" if action == "start" => user = Some(flag_value(&mut options, arg)?),
+            "--metrics" if action == "start" => metrics = Some(flag_value(&mut options, arg)?),
+            "--timeout" if action == "start" => {
+                timeout = Some(parse_duration(arg, &flag_value(&mut options, arg)?)?);
+            }
+            other if other.starts_with("--") => {
+                return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE));
+            }
+            _ if file.is_none() => file = Some(arg.clone()),
+            other => return Err(format!("Unexpected argument '{}'.\n\n{}", other, USAGE)),
+        }
+    }
+    let socket = socket.map_or_else(daemon::default_socket, std::path::PathBuf::from);
+
+    let reply = match (action.as_str(), file) {
+        ("start", None) => {
+            let timeout = timeout.unwrap_or(daemon::DEFAULT_TIMEOUT);
+            println!("Listening on '{}'. Stop with 'daemon stop'.", socket.display());
+            return daemon::serve(config, &socket, timeout, user.as_deref(), metrics.as_deref());
+        }
+        ("unlock", None) => {
+            let password = prompt_password();
+            daemon::request(&socket, &[b"unlock", password.as_bytes()])?
+        }
+        (action @ ("lock" | "status" | "stats" | "stop"), None) => daemon::request(&socket, &[action.as_bytes()])?,
+        (action @ ("encrypt" | "decrypt"), Some(file)) => {
+            let output = output.unwrap_or_else(|| match action {
+                "encrypt" => format!("{}.enc", file),
+                _ => file
+                    .strip_suffix(".enc")
+                    .map_or_else(|| format!("{}.dec", file), str::to_string),
+            });
+            // The daemon has its own working folder, so send full paths.
+            let absolute = |path: &str| {
+                std::path::absolute(path)
+                    .map(|path| path.to_string_lossy().into_owned())
+                    .map_err(|e| format!("Failed to resolve '{}': {}", path, e))
+            };
+            let (file, output) = (absolute(&file)?, absolute(&output)?);
+            daemon::request(&socket, &[action.as_bytes(), file.as_bytes(), output.as_bytes()])?
+        }
+        _ => return Err(format!("Unknown daemon action.\n\n{}", USAGE)),
+    };
+    println!("{}", reply);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn daemon(_config: &Config, _args: &[String]) -> Result<(), String> {
+    Err("The daemon needs Unix sockets and is not supported on this system.".to_string())
+}
+
+/// `agent (start | add-key FILE | list | lock | unlock | remove-all)
+/// [OPTIONS]`
+#[cfg(unix)]
+fn agent(args: &[String]) -> Result<(), String> {
+    use crate::agent;
+
+    let (action, rest) = args
+        .split_first()
+        .ok_or_else(|| format!("agent needs an action.\n\n{}", USAGE))?;
+    let mut file = None;
+    let mut socket = None;
+    let mut ttl = None;
+    let mut user = None;
+    let mut options = rest.iter();
+    while let Some(arg) = options.next() {
+        match arg.as_str() {
+            "--socket" => socket = Some(flag_value(&mut options, arg)?),
+            "--user" if action == "start" => user = Some(flag_value(&mut options, arg)?),
+            "--ttl" if action == "start" || action == "add-key" => {
+                ttl = Some(parse_duration(arg, &flag_value(&mut options, arg)?)?);
+            }
+            other if other.starts_with("--") => {
+                return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE));
+            }
+            _ if file.is_none() => file = Some(arg.clone()),
+            other => return Err(format!("Unexpected argument '{}'.\n\n{}", other, USAGE)),
+        }
+    }
+
+    match (action.as_str(), file) {
+        ("start", None) => {
+            let socket = socket.map_or_else(agent::default_socket, std::path::PathBuf::from);
+            // In the form ssh-agent prints, so `eval` can pick it up.
+            println!("SSH_AUTH_SOCK={}; export SSH_AUTH_SOCK;", socket.display());
+            crate::flush_stdout();
+            agent::serve(&socket, ttl, user.as_deref())
+        }
+        ("add-key", Some(file)) => {
+            let socket = agent::client_socket(socket)?;
+            let seed = signature::load_private_key(&file, &prompt_password())?;
+            agent::add_key(&socket, &seed, &file, ttl)?;
+            match ttl {
+                Some(ttl) => println!("Added '{}' for {}s.", file, ttl.as_secs()),
+                None => println!("Added '{}'.", file),
+            }
+            Ok(())
+        }
+        ("list", None) => {
+            let keys = agent::list(&agent::client_socket(socket)?)?;
+            if keys.is_empty() {
+                println!("The agent holds no keys (or is locked).");
+            }
+            for key in keys {
+                let public = key.public.map_or_else(|| "(not Ed25519)".to_string(), |public| checksum::to_hex(&public));
+                println!("{}  {}  {}", key.fingerprint, public, key.comment);
+            }
+            Ok(())
+        }
+        (action @ ("lock" | "unlock"), None) => {
+            let socket = agent::client_socket(socket)?;
+            let passphrase = tty::prompt("Agent passphrase: ");
+            let locked = action == "lock";
+            agent::set_locked(&socket, locked, &passphrase)?;
+            println!("{}", if locked { "Locked." } else { "Unlocked." });
+            Ok(())
+        }
+        ("remove-all", None) => {
+            agent::remove_all(&agent::client_socket(socket)?)?;
+            println!("Removed every key.");
+            Ok(())
+        }
+        _ => Err(format!("Unknown agent action.\n\n{}", USAGE)),
+    }
+}
+
+#[cfg(not(unix))]
+fn agent(_args: &[String]) -> Result<(), String> {
+    Err("The key agent needs Unix sockets and is not supported on this system.".to_string())
+}
+
+/// `sidecar split FILE [--range START-END]... [--dicom-tag GGGG,EEEE]...
+/// [--sidecar PATH]` or `sidecar merge FILE [--sidecar PATH]`
+fn sidecar(config: &Config, args: &[String]) -> Result<(), String> {
+    let (action, file, options) = match args {
+        [action, file, options @ ..] if matches!(action.as_str(), "split" | "merge") => (action.as_str(), file, options),
+        _ => return Err(format!("sidecar needs split or merge and a file.\n\n{}", USAGE)),
+    };
+    let mut fields = Vec::new();
+    let mut sidecar_path = None;
+    let mut options = options.iter();
+    while let Some(arg) = options.next() {
+        match (arg.as_str(), action) {
+            ("--sidecar", _) => sidecar_path = Some(flag_value(&mut options, arg)?),
+            ("--range", "split") => {
+                let text = flag_value(&mut options, arg)?;
+                let file_len = std::fs::metadata(file)
+                    .map_err(|e| format!("Failed to read '{}': {}", file, e))?
+                    .len();
+                fields.push(sidecar::Field::Range(appendlog::parse_range(&text, file_len)?));
+            }
+            ("--dicom-tag", "split") => {
+                fields.push(sidecar::Field::parse_dicom_tag(&flag_value(&mut options, arg)?)?)
+            }
+            (flag, _) if flag.starts_with('-') => {
+                return Err(format!("Unknown option '{}'.\n\n{}", flag, USAGE));
+            }
+            _ => return Err(format!("Unexpected argument '{}'.\n\n{}", arg, USAGE)),
+        }
+    }
+    guard::refuse("sidecar", "blanks or restores bytes of FILE in place")?;
+    let path = Path::new(file);
+    let sidecar_path = sidecar_path.map(Into::into).unwrap_or_else(|| sidecar::default_path(path));
+
+    let password = prompt_password();
+    let trash = Trash::new(config.trash_retention_minutes)?;
+    let started = Instant::now();
+    let sidecar_shown = paths::display(&sidecar_path);
+    if action == "split" {
+        if tty::prompt("Confirm password: ") != password {
+            return Err("The passwords do not match.".to_string());
+        }
+        let result = trash.protect(path, || sidecar::split(path, &sidecar_path, &fields, &password));
+        finish_command(config, "encrypt", file, &sidecar_shown, result.as_ref().err(), started, &password);
+        let summary = result?;
+        println!(
+            "Moved {} byte(s) in {} range(s) of '{}' into '{}'.",
+            summary.bytes, summary.ranges, file, sidecar_shown
+        );
+    } else {
+        let result = trash.protect(path, || sidecar::merge(path, &sidecar_path, &password));
+        finish_command(config, "decrypt", &sidecar_shown, file, result.as_ref().err(), started, &password);
+        let summary = result?;
+        println!(
+            "Put {} byte(s) in {} range(s) from '{}' back into '{}'.",
+            summary.bytes, summary.ranges, sidecar_shown, file
+        );
+    }
+    Ok(())
+}
+
+/// `scratch FILE [--shell PROGRAM]`
+fn scratch(config: &Config, args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut shell = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--shell" => shell = Some(flag_value(&mut args, arg)?),
+            other if other.starts_with("--") => {
+                return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE));
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            other => return Err(format!("Unexpected argument '{}'.\n\n{}", other, USAGE)),
+        }
+    }
+    let path = path.ok_or_else(|| format!("scratch needs a FILE to keep the folder in.\n\n{}", USAGE))?;
+    // Refuse now rather than after the session, when the work would be
+    // left unencrypted.
+    policy::get().check_encrypt()?;
+
+    let existing = Path::new(&path).exists();
+    if existing {
+        guard::refuse("scratch", "encrypts its work back over FILE")?;
+    }
+    // Held for the whole session, so nothing else changes FILE before the
+    // work is encrypted back over it.
+    let _claim = claims::claim(Path::new(&path), "scratch")?;
+    let password = prompt_password();
+    if password.is_empty() {
+        return Err("Password cannot be empty.".to_string());
+    }
+    if !existing && tty::prompt("Confirm password: ") != password {
+        return Err("The passwords do not match.".to_string());
+    }
+
+    let scratch = Scratch::create()?;
+    let dir = scratch.dir.to_string_lossy().into_owned();
+    let report = |action, input: &str, output: &str, error: Option<&str>| {
+        let event = HookEvent {
+            action,
+            input,
+            output,
+            error,
+            password: Some(&password),
+        };
+        if let Some(warning) = Hooks::from_config(config).run(&event) {
+            eprintln!("Warning: {}", warning);
+        }
+        if let Some(warning) = Auditor::from_config(config).operation_finished(&event) {
+            eprintln!("Warning: {}", warning);
+        }
+    };
+    if existing {
+        // The folder's own attributes are not restored, so it stays
+        // private.
+        let result = crate::decrypt_folder(Path::new(&path), Path::new(&dir), &password, false, false);
+        report("decrypt", &path, &dir, result.as_ref().err().map(String::as_str));
+        if let Err(e) = result {
+            let _ = scratch.destroy();
+            return Err(e);
+        }
+    }
+    if !scratch.in_memory {
+        println!(
+            "Warning: no memory-backed folder is available, so the plaintext is kept on disk until the shell exits."
+        );
+    }
+    println!("Working in '{}'. Exit the shell to encrypt it to '{}'.", dir, path);
+
+    let shell_result = scratch.run_shell(shell.as_deref());
+    let trash = Trash::new(config.trash_retention_minutes)?;
+    let result = trash.protect(&path, || {
+        crate::encrypt_folder(Path::new(&dir), Path::new(&path), &password, &Filter::default(), false)
+    });
+    report("encrypt", &dir, &path, result.as_ref().err().map(String::as_str));
+    let summary = result.map_err(|e| {
+        format!(
+            "{} The plaintext is still in '{}'; encrypt it before deleting it.",
+            e, dir
+        )
+    })?;
+    scratch.destroy()?;
+    println!("Encrypted the scratch folder to '{}' and wiped it.", path);
+    crate::print_archive_summary(&summary);
+    shell_result.map(|_| ())
+}
+
+/// `open FILE [--output PATH | --delete-after]`
+fn open(config: &Config, args: &[String], legacy_xor: bool) -> Result<(), String> {
+    let mut path = None;
+    let mut output = None;
+    let mut delete_after = false;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output = Some(flag_value(&mut args, arg)?),
+            "--delete-after" => delete_after = true,
+            other if other.starts_with("--") => {
+                return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE));
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            other => return Err(format!("Unexpected argument '{}'.\n\n{}", other, USAGE)),
+        }
+    }
+    let path = path.ok_or_else(|| format!("open needs a file.\n\n{}", USAGE))?;
+    if delete_after && output.is_some() {
+        return Err("--delete-after keeps no copy, so it cannot be combined with --output.".to_string());
+    }
+    let default_name = path
+        .strip_suffix(".enc")
+        .map_or_else(|| format!("{}.dec", path), str::to_string);
+
+    let password = prompt_password();
+    let started = Instant::now();
+    let opened = crate::open_file(Path::new(&path), &password, legacy_xor)?;
+    let scratch = if delete_after { Some(Scratch::create()?) } else { None };
+    let output = match (&scratch, output) {
+        (Some(scratch), _) => {
+            let name = Path::new(&default_name).file_name().unwrap_or_default();
+            let mut output = scratch.dir.join(name);
+            // The viewer is picked by extension, and nobody keeps this copy
+            // to care about its name.
+            if output.extension().is_none()
+                && let Some((_, extension)) = content::detect_extension(&opened.decrypted.plaintext)
+            {
+                output = paths::with_suffix(&output, &format!(".{}", extension));
+            }
+            output
+        }
+        (None, Some(output)) => std::path::PathBuf::from(output),
+        (None, None) => crate::suggest_extension(default_name.into(), &opened.decrypted.plaintext),
+    };
+    let output_name = paths::display(&output);
+
+    let result = if scratch.is_some() {
+        crate::decrypt_file(opened, &output, false, &mut crate::confirm_suspicious_output)
+    } else {
+        Trash::new(config.trash_retention_minutes)?.protect(&output, || {
+            crate::decrypt_file(
+                opened,
+                &output,
+                config.preserve_metadata,
+                &mut crate::confirm_suspicious_output,
+            )
+        })
+    };
+    finish_command(config, "decrypt", &path, &output_name, result.as_ref().err(), started, &password);
+    if let Err(e) = result {
+        if let Some(scratch) = scratch {
+            let _ = scratch.destroy();
+        }
+        return Err(e);
+    }
+
+    println!("Decrypted '{}' to '{}'. Opening it...", path, output_name);
+    let viewer = scratch::open_with_default_app(&output);
+    let Some(scratch) = scratch else {
+        return viewer.map(|_| ());
+    };
+    if !scratch.in_memory {
+        println!("Warning: no memory-backed folder is available, so the plaintext is on disk until it is deleted.");
+    }
+    match &viewer {
+        Ok(true) => {}
+        Ok(false) => {
+            tty::prompt("Press Enter once you have closed the file to delete the decrypted copy: ");
+        }
+        Err(e) => eprintln!("Error: {}", e),
+    }
+    scratch.destroy()?;
+    println!("Wiped and deleted the decrypted copy.");
+    viewer.map(|_| ())
+}
+
+/// `gui [--port N] [--no-browser]`
+#[cfg(feature = "gui")]
+fn gui(config: &Config, args: &[String]) -> Result<(), String> {
+    let mut port = 0;
+    let mut open_browser = true;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--port" => {
+                port = u16::try_from(parse_count(&flag_value(&mut args, arg)?, arg)?)
+                    .map_err(|_| "'--port' needs a number up to 65535.".to_string())?
+            }
+            "--no-browser" => open_browser = false,
+            other => return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE)),
+        }
+    }
+    crate::gui::serve(config, port, open_browser)
+}
+
+#[cfg(not(feature = "gui"))]
+fn gui(_config: &Config, _args: &[String]) -> Result<(), String> {
+    Err("This build has no graphical front end. Build it with 'cargo build --release --features gui'.".to_string())
+}
+
+/// `integrate-shell (install | uninstall | run encrypt|decrypt FILE...)`
+fn integrate_shell(config: &Config, args: &[String]) -> Result<(), String> {
+    match args {
+        [action] if action == "install" => {
+            let exe = std::env::current_exe().map_err(|e| format!("Cannot find this program's path: {}", e))?;
+            for entry in shell::install(&exe)? {
+                println!("Added '{}'.", entry);
+            }
+            println!("The entries run '{}', so reinstall them if it moves.", exe.display());
+            Ok(())
+        }
+        [action] if action == "uninstall" => {
+            let removed = shell::uninstall()?;
+            if removed.is_empty() {
+                println!("No context-menu entries were installed.");
+            }
+            for entry in removed {
+                println!("Removed '{}'.", entry);
+            }
+            Ok(())
+        }
+        [action, operation, files @ ..] if action == "run" && !files.is_empty() => {
+            // Started from a file manager, with nowhere to print, so the
+            // outcome is shown in a dialog too.
+            let result = match operation.as_str() {
+                "encrypt" => shell_encrypt(config, files),
+                "decrypt" => shell_decrypt(config, files),
+                other => Err(format!("'{}' is not encrypt or decrypt.", other)),
+            };
+            match &result {
+                Ok(Some(summary)) => shell::show(summary, false),
+                Ok(None) => {}
+                Err(e) => shell::show(e, true),
+            }
+            result.map(|_| ())
+        }
+        _ => Err(format!("integrate-shell needs install, uninstall, or run.\n\n{}", USAGE)),
+    }
+}
+
+/// Encrypt each of `files` next to itself, as `encrypt` does. Returns what
+/// to show, or `None` if the password dialog was cancelled.
+fn shell_encrypt(config: &Config, files: &[String]) -> Result<Option<String>, String> {
+    let template = policy::get()
+        .name_template(None)?
+        .or(config.name_template.as_deref())
+        .map(Template::parse)
+        .transpose()?;
+    let layout = batch::Layout {
+        output_dir: None,
+        keep_structure: false,
+        on_conflict: OnConflict::Rename,
+        suffix: ".enc",
+        existing: None,
+        name_template: template.as_ref(),
+    };
+    let jobs = batch::plan(files, &layout, &batch::Limits::default())?;
+    let Some(password) = shell::ask_password("Password to encrypt with") else {
+        return Ok(None);
+    };
+    let Some(again) = shell::ask_password("Enter the password again") else {
+        return Ok(None);
+    };
+    if password != again {
+        return Err("The passwords do not match, so nothing was encrypted.".to_string());
+    }
+
+    let trash = Trash::new(config.trash_retention_minutes)?;
+    let mut lines = Vec::new();
+    let mut failed = 0;
+    for job in &jobs {
+        let Some(output_path) = &job.output else {
+            continue;
+        };
+        let (input, output) = (paths::display(&job.input), paths::display(output_path));
+        let started = Instant::now();
+        let result = trash.protect(output_path, || {
+            crate::encrypt_file(&job.input, output_path, &password, config.preserve_metadata)
+        });
+        finish_command(config, "encrypt", &input, &output, result.as_ref().err(), started, &password);
+        match result {
+            Ok(report) => {
+                lines.push(format!("Encrypted '{}' to '{}'.", input, output));
+                lines.extend(report.warnings.iter().map(|warning| format!("Warning: {}", warning)));
+            }
+            Err(e) => {
+                lines.push(format!("Failed to encrypt '{}': {}", input, e));
+                failed += 1;
+            }
+        }
+    }
+    shell_summary(lines, failed)
+}
+
+/// Decrypt each of `files` next to itself, to its name without `.enc`
+/// (or with `.dec` added), unpacking encrypted folders. Existing files are
+/// never replaced.
+fn shell_decrypt(config: &Config, files: &[String]) -> Result<Option<String>, String> {
+    let Some(password) = shell::ask_password("Password to decrypt with") else {
+        return Ok(None);
+    };
+    let mut lines = Vec::new();
+    let mut failed = 0;
+    for path in files {
+        let output = path
+            .strip_suffix(".enc")
+            .map_or_else(|| format!("{}.dec", path), str::to_string);
+        let started = Instant::now();
+        let result = if Path::new(&output).exists() {
+            Err(format!("'{}' already exists.", output))
+        } else {
+            crate::open_file(Path::new(path), &password, false).and_then(|opened| {
+                if archive::is_archive(&opened.decrypted.plaintext) {
+                    archive::unpack(&opened.decrypted.plaintext, Path::new(&output)).map(|summary| summary.warnings)
+                } else {
+                    crate::decrypt_file(opened, Path::new(&output), config.preserve_metadata, &mut |reason| {
+                        shell::ask_yes_no(&format!("{} Write it anyway?", reason))
+                    })
+                    .map(|report| report.warnings)
+                }
+            })
+        };
+        finish_command(config, "decrypt", path, &output, result.as_ref().err(), started, &password);
+        match result {
+            Ok(warnings) => {
+                lines.push(format!("Decrypted '{}' to '{}'.", path, output));
+                lines.extend(warnings.iter().map(|warning| format!("Warning: {}", warning)));
+            }
+            Err(e) => {
+                lines.push(format!("Failed to decrypt '{}': {}", path, e));
+                failed += 1;
+            }
+        }
+    }
+    shell_summary(lines, failed)
+}
+
+/// What `integrate-shell run` shows: every line if anything failed, as the
+/// error, or else every line.
+fn shell_summary(lines: Vec<String>, failed: usize) -> Result<Option<String>, String> {
+    let text = lines.join("\n");
+    if failed > 0 {
+        return Err(text);
+    }
+    Ok(Some(text))
+}
+
+/// `mail-split PATH [--part-size SIZE] [--output-dir DIR]`
+fn mail_split(config: &Config, args: &[String]) -> Result<(), String> {
+    let mut path = None;
+    let mut part_size = 20 << 20;
+    let mut output_dir = ".".to_string();
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--part-size" => part_size = filter::parse_size(&flag_value(&mut args, arg)?)?,
+            "--output-dir" => output_dir = flag_value(&mut args, arg)?,
+            other if other.starts_with("--") => {
+                return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE));
+            }
+            _ if path.is_none() => path = Some(arg.clone()),
+            other => return Err(format!("Unexpected argument '{}'.\n\n{}", other, USAGE)),
+        }
+    }
+    let path = path.ok_or_else(|| format!("mail-split needs a file or folder.\n\n{}", USAGE))?;
+    let name = Path::new(&path)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| format!("'{}' has no file name.", path))?;
+
+    let content = if Path::new(&path).is_dir() {
+        let (content, summary) = archive::pack(Path::new(&path), &Filter::default())?;
+        for warning in &summary.warnings {
+            eprintln!("Warning: {}", warning);
+        }
+        content
+    } else {
+        let (content, changed) =
+            throttle::read_consistent(&path).map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+        if changed {
+            eprintln!("Warning: {}", crate::input_changed_warning(&path));
+        }
+        content
+    };
+    let metadata = if config.preserve_metadata {
+        FileMetadata::capture(&path)?
+    } else {
+        FileMetadata::default()
+    };
+    let bundle = mail::bundle(&name, &content)?;
+    let password = prompt_password();
+    let encrypted = crate::encrypt_bytes(&bundle, &metadata.to_bytes(), password.as_bytes())?;
+    let parts = mail::split(&encrypted, part_size)?;
+
+    let part_paths: Vec<_> = (1..=parts.len())
+        .map(|number| Path::new(&output_dir).join(format!("{}.part{:03}.txt", name, number)))
+        .collect();
+    if let Some(existing) = part_paths.iter().find(|part| part.exists()) {
+        return Err(format!("'{}' already exists.", existing.display()));
+    }
+    for (part_path, text) in part_paths.iter().zip(&parts) {
+        std::fs::write(part_path, text).map_err(|e| format!("Failed to write '{}': {}", part_path.display(), e))?;
+        println!("Wrote '{}' ({} bytes).", part_path.display(), text.len());
+    }
+    println!(
+        "Split '{}' ({} bytes, {} compressed and encrypted) into {} part(s). Rebuild it with 'mail-join'.",
+        path,
+        content.len(),
+        encrypted.len(),
+        parts.len()
+    );
+    Ok(())
+}
+
+/// `mail-join PART... [--output PATH]`
+fn mail_join(config: &Config, args: &[String]) -> Result<(), String> {
+    let mut part_paths = Vec::new();
+    let mut output = None;
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--output" => output = Some(flag_value(&mut args, arg)?),
+            other if other.starts_with("--") => {
+                return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE));
+            }
+            _ => part_paths.push(arg.clone()),
+        }
+    }
+    if part_paths.is_empty() {
+        return Err(format!("mail-join needs the parts to join.\n\n{}", USAGE));
+    }
+    let mut parts = Vec::with_capacity(part_paths.len());
+    for part_path in part_paths {
+        let text = std::fs::read_to_string(&part_path).map_err(|e| format!("Failed to read '{}': {}", part_path, e))?;
+        parts.push((part_path, text));
+    }
+    let payload = mail::join(&parts)?;
+
+    let decrypted = crate::decrypt_bytes(&payload, prompt_password().as_bytes(), false)?;
+    let (name, content) = mail::unbundle(&decrypted.plaintext)?;
+    // The stored name only ever picks a file in the current folder.
+    let output = match output {
+        Some(output) => output,
+        None => Path::new(&name)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .ok_or_else(|| "The parts hold no usable file name; give one with --output.".to_string())?,
+    };
+    if Path::new(&output).exists() {
+        return Err(format!("'{}' already exists.", output));
+    }
+    let mut warnings = Vec::new();
+    if archive::is_archive(&content) {
+        warnings = archive::unpack(&content, Path::new(&output))?.warnings;
+    } else {
+        throttle::write(&output, &content).map_err(|e| format!("Failed to write '{}': {}", output, e))?;
+    }
+    if let Some(metadata) = &decrypted.metadata
+        && config.preserve_metadata
+    {
+        warnings.extend(metadata.restore(&output));
+    }
+    for warning in &warnings {
+        println!("Warning: {}", warning);
+    }
+    println!("Joined {} part(s) and decrypted them to '{}'.", parts.len(), output);
+    Ok(())
+}
+
+/// Parse the length of time given to `flag`, such as `15m`.
+fn parse_duration(flag: &str, value: &str) -> Result<std::time::Duration, String> {
+    if value.contains('-') {
+        return Err(format!("'{}' takes a length of time such as 15m, got '{}'.", flag, value));
+    }
+    let seconds = history::parse_when("0s")? - history::parse_when(value)?;
+    Ok(std::time::Duration::from_secs(seconds))
+}
+
+/// `history (list [OPTIONS] | search TEXT | duplicates [--existing])`
+fn history(config: &Config, args: &[String]) -> Result<(), String> {
+    let history = History::load(config);
+    let (matches, page) = match args {
+        [action, rest @ ..] if action == "duplicates" => return history_duplicates(&history, rest),
+        [action, rest @ ..] if action == "list" => {
+            let (filter, page) = parse_history_filter(rest)?;
+            (history.filter(&filter), page)
+        }
+        [action, words @ ..] if action == "search" && !words.is_empty() => {
+            (history.search(&words.join(" ")), 1)
+        }
+        _ => {
+            return Err(format!(
+                "history needs 'list', 'search TEXT', or 'duplicates'.\n\n{}",
+                USAGE
+            ));
+        }
+    };
+
+    if matches.is_empty() {
+        println!("No history entries match.");
+        return Ok(());
+    }
+    let pages = matches.len().div_ceil(history::PAGE_SIZE);
+    let Some(chunk) = matches.chunks(history::PAGE_SIZE).nth(page as usize - 1) else {
+        return Err(format!("There are only {} page(s).", pages));
+    };
+    for (index, entry) in chunk {
+        history::print_entry(index + 1, entry);
+    }
+    if pages > 1 {
+        println!("Page {} of {}.", page, pages);
+    }
+    Ok(())
+}
+
+/// `history duplicates [--existing]`: list plaintexts that were encrypted
+/// to more than one output, so redundant encrypted copies can be found
+/// and removed.
+fn history_duplicates(history: &History, args: &[String]) -> Result<(), String> {
+    let mut existing_only = false;
+    for arg in args {
+        match arg.as_str() {
+            "--existing" => existing_only = true,
+            other => return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE)),
+        }
+    }
+
+    let mut shown = 0;
+    for mut group in history.duplicates() {
+        if existing_only {
+            group.copies.retain(|entry| entry.output_path.as_deref().is_some_and(Path::exists));
+            if group.copies.len() < 2 {
+                continue;
+            }
+        }
+        if shown > 0 {
+            println!();
+        }
+        shown += 1;
+        println!(
+            "{} encrypted to {} outputs:",
+            checksum::to_hex(&group.plaintext_sha256),
+            group.copies.len()
+        );
+        for entry in &group.copies {
+            let output = entry.output_path.as_deref().unwrap_or(Path::new(""));
+            let missing = if output.exists() { "" } else { " (no longer there)" };
+            println!(
+                "  {} {} -> {}{}",
+                history::format_date(entry.timestamp),
+                paths::display(&entry.file_path),
+                paths::display(output),
+                missing
+            );
+        }
+    }
+    if shown == 0 {
+        println!("No plaintext was encrypted to more than one output.");
+    }
+    Ok(())
+}
+
+/// Parse the options of `history list` into a filter and page number.
+fn parse_history_filter(args: &[String]) -> Result<(history::Filter, u64), String> {
+    let mut filter = history::Filter::default();
+    let mut page = 1;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--action" => {
+                let value = flag_value(&mut args, arg)?;
+                let action = CryptoAction::from_name(&value).ok_or_else(|| {
+                    format!("'--action' must be encrypt or decrypt, got '{}'.", value)
+                })?;
+                filter.action = Some(action);
+            }
+            "--failed-only" => filter.success = Some(false),
+            "--changed-only" => filter.input_changed = true,
+            "--outdated-only" => filter.outdated = true,
+            "--since" => filter.since = Some(history::parse_when(&flag_value(&mut args, arg)?)?),
+            "--until" => filter.until = Some(history::parse_when(&flag_value(&mut args, arg)?)?),
+            "--path" => filter.path = Some(flag_value(&mut args, arg)?),
+            "--page" => page = parse_count(&flag_value(&mut args, arg)?, arg)?,
+            other => return Err(format!("Unknown option '{}'.\n\n{}", other, USAGE)),
+        }
+    }
+    Ok((filter, page))
+}
+
+/// Parse a positive whole number given to `flag`.
+fn parse_count(value: &str, flag: &str) -> Result<u64, String> {
+    value
+        .parse::<u64>()
+        .ok()
+        .filter(|&n| n > 0)
+        .ok_or_else(|| format!("'{}' needs a whole number greater than zero.", flag))
+}
+
+/// `undo`
+fn undo(config: &Config) -> Result<(), String> {
+    let trash = Trash::new(config.trash_retention_minutes)?;
+    trash.purge_expired();
+    let message = trash.undo_last()?;
+    println!("{}", message);
+    Ok(())
+}
+
+/// Take the value that follows a flag such as `--output`.
+fn flag_value<'a>(
+    args: &mut impl Iterator<Item = &'a String>,
+    flag: &str,
+) -> Result<String, String> {
+    args.next()
+        .cloned()
+        .ok_or_else(|| format!("'{}' needs a value.", flag))
+}
+
+/// Ask for the password on the terminal.
+fn prompt_password() -> String {
+    tty::prompt(t!("Enter password: "))
+}
@@ -0,0 +1,199 @@
+/// Stored as is, for data that does not get smaller.
+const METHOD_STORED: u8 = 0;
+
+/// LZ77 sequences in the style of LZ4 blocks.
+const METHOD_LZ: u8 = 1;
+
+/// Method byte followed by the original length as a 64-bit little-endian
+/// number.
+const HEADER_LEN: usize = 9;
+
+/// Shortest match worth encoding, and the farthest back one can start.
+const MIN_MATCH: usize = 4;
+const MAX_OFFSET: usize = u16::MAX as usize;
+
+/// The match finder remembers the last position of each hash of four
+/// bytes, in a table of `1 << HASH_BITS` entries.
+const HASH_BITS: u32 = 16;
+
+/// Compress `data`. The result records the method and original length,
+/// and is never more than `HEADER_LEN` bytes longer than the input.
+///
+/// Each sequence is a token byte holding the literal count in its high
+/// four bits and the match length minus four in its low four bits (15
+/// meaning more length bytes follow, each adding up to 255), the literal
+/// bytes, and the match's distance back as a 16-bit little-endian number.
+/// The last sequence has literals only.
+pub fn compress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_LEN + data.len() / 2);
+    out.push(METHOD_LZ);
+    out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let mut anchor = 0;
+    let mut pos = 0;
+    while pos + MIN_MATCH <= data.len() {
+        let word = u32::from_le_bytes(data[pos..pos + 4].try_into().expect("four bytes"));
+        let slot = (word.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize;
+        let candidate = table[slot];
+        table[slot] = pos;
+        if candidate != usize::MAX
+            && pos - candidate <= MAX_OFFSET
+            && data[candidate..candidate + MIN_MATCH] == data[pos..pos + MIN_MATCH]
+        {
+            let mut len = MIN_MATCH;
+            while pos + len < data.len() && data[candidate + len] == data[pos + len] {
+                len += 1;
+            }
+            put_sequence(&mut out, &data[anchor..pos], Some((pos - candidate, len)));
+            pos += len;
+            anchor = pos;
+        } else {
+            pos += 1;
+        }
+    }
+    put_sequence(&mut out, &data[anchor..], None);
+
+    if out.len() >= HEADER_LEN + data.len() {
+        out.clear();
+        out.push(METHOD_STORED);
+        out.extend_from_slice(&(data.len() as u64).to_le_bytes());
+        out.extend_from_slice(data);
+    }
+    out
+}
+
+/// Undo `compress`.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>, String> {
+    let corrupt = || "The compressed data is corrupt.".to_string();
+    let (header, body) = data.split_at_checked(HEADER_LEN).ok_or_else(corrupt)?;
+    let len = u64::from_le_bytes(header[1..].try_into().expect("eight bytes"));
+    let len = usize::try_from(len).map_err(|_| corrupt())?;
+    match header[0] {
+        METHOD_STORED if body.len() == len => return Ok(body.to_vec()),
+        METHOD_STORED => return Err(corrupt()),
+        METHOD_LZ => {}
+        method => return Err(format!("Unknown compression method {}.", method)),
+    }
+
+    // Every sequence takes at least a byte, so this bounds the allocation
+    // whatever the header claims.
+    let mut out = Vec::with_capacity(len.min(body.len().saturating_mul(255)));
+    let mut input = Input(body);
+    loop {
+        let token = input.take(1)?[0];
+        let mut literals = (token >> 4) as usize;
+        if literals == 15 {
+            literals += input.length()?;
+        }
+        if out.len() + literals > len {
+            return Err(corrupt());
+        }
+        out.extend_from_slice(input.take(literals)?);
+        if out.len() == len {
+            break;
+        }
+
+        let offset = u16::from_le_bytes(input.take(2)?.try_into().expect("two bytes")) as usize;
+        let mut match_len = (token & 15) as usize;
+        if match_len == 15 {
+            match_len += input.length()?;
+        }
+        let match_len = match_len + MIN_MATCH;
+        if offset == 0 || offset > out.len() || out.len() + match_len > len {
+            return Err(corrupt());
+        }
+        // Byte by byte, since a match may overlap the bytes it produces.
+        let start = out.len() - offset;
+        for index in start..start + match_len {
+            out.push(out[index]);
+        }
+    }
+    Ok(out)
+}
+
+fn put_sequence(out: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let extra = matched.map_or(0, |(_, len)| len - MIN_MATCH);
+    out.push((literals.len().min(15) as u8) << 4 | extra.min(15) as u8);
+    if literals.len() >= 15 {
+        put_length(out, literals.len() - 15);
+    }
+    out.extend_from_slice(literals);
+    if let Some((offset, _)) = matched {
+        out.extend_from_slice(&(offset as u16).to_le_bytes());
+        if extra >= 15 {
+            put_length(out, extra - 15);
+        }
+    }
+}
+
+fn put_length(out: &mut Vec<u8>, mut length: usize) {
+    while length >= 255 {
+        out.push(255);
+        length -= 255;
+    }
+    out.push(length as u8);
+}
+
+/// The compressed sequences still to be read.
+struct Input<'a>(&'a [u8]);
+
+impl<'a> Input<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        let (taken, rest) = self
+            .0
+            .split_at_checked(count)
+            .ok_or_else(|| "The compressed data is corrupt.".to_string())?;
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    /// An extended length: bytes are added up until one is below 255.
+    fn length(&mut self) -> Result<usize, String> {
+        let mut length = 0usize;
+        loop {
+            let byte = self.take(1)?[0];
+            length += byte as usize;
+            if byte != 255 {
+                return Ok(length);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let repetitive: Vec<u8> = b"the same words again and again, ".repeat(500);
+        let noise: Vec<u8> = (0..5000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+        let long_run = vec![7u8; 70_000];
+        for data in [&b""[..], b"abc", &repetitive, &noise, &long_run] {
+            let compressed = compress(data);
+            assert!(compressed.len() <= data.len() + HEADER_LEN);
+            assert_eq!(decompress(&compressed).unwrap(), data);
+        }
+        assert!(compress(&repetitive).len() < repetitive.len() / 10);
+    }
+
+    #[test]
+    fn corrupt_input_is_refused() {
+        let compressed = compress(&b"hello hello hello hello".repeat(10));
+        for len in 0..compressed.len() {
+            assert!(decompress(&compressed[..len]).is_err(), "cut to {}", len);
+        }
+        let mut longer = compressed.clone();
+        longer[1] += 1;
+        assert!(decompress(&longer).is_err());
+        let mut unknown = compressed;
+        unknown[0] = 9;
+        assert!(decompress(&unknown).is_err());
+        // A header claiming far more than the body can hold.
+        let mut huge = vec![METHOD_LZ];
+        huge.extend_from_slice(&u64::MAX.to_le_bytes());
+        huge.push(0xf0);
+        assert!(decompress(&huge).is_err());
+    }
+}
@@ -0,0 +1,334 @@
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+
+use crate::naming::Template;
+
+/// Settings that can be changed through the config file.
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// How long replaced files are kept in the trash before being purged.
+    pub trash_retention_minutes: u64,
+    /// Print SHA-256 checksums of the input and output after each operation.
+    pub show_checksums: bool,
+    /// Store owner and extended attributes (including ACLs and security
+    /// labels) in encrypted files and restore them on decryption.
+    pub preserve_metadata: bool,
+    /// Remove Exif, author, and similar metadata from JPEG, PNG, WebP, and
+    /// PDF files before encrypting them. Same as passing `--strip-metadata`.
+    pub strip_metadata: bool,
+    /// Refuse to change any file that is read, and check each one is the
+    /// same afterwards. Same as passing `--never-modify-source`.
+    pub never_modify_source: bool,
+    /// Append Reed-Solomon parity of this many percent of the size to new
+    /// encrypted files, or 0 for none. Same as passing `--parity`.
+    pub parity_percent: u8,
+    /// Shell command to run after an operation succeeds.
+    pub hook_on_success: Option<String>,
+    /// Shell command to run after an operation fails.
+    pub hook_on_failure: Option<String>,
+    /// Give the hooks the password on their standard input (see
+    /// `hooks::Hooks`).
+    pub hook_password: bool,
+    /// Show a desktop notification when a long operation finishes.
+    pub notify: bool,
+    /// Only notify about operations that took at least this long.
+    pub notify_after_seconds: u64,
+    /// Language for the interactive prompts (`en` or `es`). When unset,
+    /// the locale environment variables decide.
+    pub language: Option<String>,
+    /// Leave out decorative rules and symbols, for screen readers and dumb
+    /// terminals. Same as passing `--plain`.
+    pub plain: bool,
+    /// File where every operation is recorded between runs, or `None` to
+    /// keep history only for the current session.
+    pub history_file: Option<PathBuf>,
+    /// The keyring of contacts that `--recipient` looks names up in.
+    pub contacts_file: Option<PathBuf>,
+    /// Run at the lowest CPU and disk priority. Same as passing `--nice`.
+    pub nice: bool,
+    /// Read and write file contents without the page cache. Same as
+    /// passing `--direct-io`.
+    pub direct_io: bool,
+    /// Move file contents with io_uring instead of plain read and write
+    /// calls (`io_backend = io_uring`). Only available on Linux.
+    pub io_uring: bool,
+    /// Adjust the piece size of reads and writes, and the number of files
+    /// a tree is worked on at once, to the measured throughput. Same as
+    /// passing `--adaptive`.
+    pub adaptive_io: bool,
+    /// Smallest piece size adaptive tuning may use, in bytes.
+    pub chunk_size_min: u64,
+    /// Largest piece size adaptive tuning may use, in bytes.
+    pub chunk_size_max: u64,
+    /// Most files adaptive tuning may work on at once, or 0 for one per
+    /// CPU.
+    pub max_workers: u64,
+    /// Seconds to wait before the next password attempt on a vault or
+    /// container after a wrong one, doubled for each further failure.
+    pub unlock_delay_seconds: u64,
+    /// Refuse further attempts after this many wrong passwords in a row,
+    /// or 0 to never lock.
+    pub unlock_max_failures: u32,
+    /// How long a lockout lasts.
+    pub unlock_lockout_minutes: u64,
+    /// Send an audit event to syslog or journald for every encrypt and
+    /// decrypt. Only available on Unix.
+    pub audit_syslog: bool,
+    /// Write an audit event to the Windows Event Log for every encrypt
+    /// and decrypt. Only available on Windows.
+    pub audit_event_log: bool,
+    /// Event Log source the events are written under, or `None` for
+    /// `File Encryptor`.
+    pub audit_event_source: Option<String>,
+    /// Template that `encrypt` names its outputs with unless
+    /// `--name-template` gives another, such as `{stem}_{date}.enc`.
+    pub name_template: Option<String>,
+    /// How many times a batch tries a file again after a failure that may
+    /// pass, such as the file being busy, or 0 to never try again.
+    pub retries: u32,
+    /// Seconds to wait before the first retry, doubled for each further
+    /// one.
+    pub retry_delay_seconds: u64,
+    /// Ask before `encrypt` works through folders holding more files than
+    /// this, or 0 to never ask.
+    pub confirm_above_files: u64,
+    /// Ask before `encrypt` works through folders holding more bytes than
+    /// this, or 0 to never ask.
+    pub confirm_above_bytes: u64,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            trash_retention_minutes: 60,
+            show_checksums: false,
+            preserve_metadata: true,
+            strip_metadata: false,
+            never_modify_source: false,
+            parity_percent: 0,
+            hook_on_success: None,
+            hook_on_failure: None,
+            hook_password: false,
+            notify: false,
+            notify_after_seconds: 30,
+            language: None,
+            plain: false,
+            history_file: env::home_dir().map(|home| home.join(".file_encryptor_history")),
+            contacts_file: env::home_dir().map(|home| home.join(".file_encryptor_contacts")),
+            nice: false,
+            direct_io: false,
+            io_uring: false,
+            adaptive_io: false,
+            chunk_size_min: 16 * 1024,
+            chunk_size_max: 4 << 20,
+            max_workers: 0,
+            unlock_delay_seconds: 2,
+            unlock_max_failures: 0,
+            unlock_lockout_minutes: 15,
+            audit_syslog: false,
+            audit_event_log: false,
+            audit_event_source: None,
+            name_template: None,
+            retries: 3,
+            retry_delay_seconds: 1,
+            confirm_above_files: 1000,
+            confirm_above_bytes: 10 << 30,
+        }
+    }
+}
+
+impl Config {
+    /// Load the config file if it exists, falling back to defaults.
+    ///
+    /// The file uses simple `key = value` lines and `#` comments. Problems
+    /// with the file are printed as warnings so a typo never stops the app.
+    pub fn load() -> Self {
+        let mut config = Config::default();
+        let path = match config_path() {
+            Some(path) => path,
+            None => return config,
+        };
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => return config,
+        };
+
+        for (number, line) in contents.lines().enumerate() {
+            if let Err(e) = config.apply_line(line) {
+                println!("Warning: {} line {}: {}", path.display(), number + 1, e);
+            }
+        }
+
+        config
+    }
+
+    /// Apply a single line from the config file.
+    fn apply_line(&mut self, line: &str) -> Result<(), String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| "expected 'key = value'".to_string())?;
+        let key = key.trim();
+        let value = value.trim();
+
+        match key {
+            "trash_retention_minutes" => {
+                self.trash_retention_minutes = parse_number(key, value)?;
+            }
+            "show_checksums" => {
+                self.show_checksums = parse_bool(key, value)?;
+            }
+            "preserve_metadata" => {
+                self.preserve_metadata = parse_bool(key, value)?;
+            }
+            "strip_metadata" => {
+                self.strip_metadata = parse_bool(key, value)?;
+            }
+            "never_modify_source" => {
+                self.never_modify_source = parse_bool(key, value)?;
+            }
+            "parity_percent" => {
+                self.parity_percent = crate::parity::check_percent(parse_number(key, value)?)?;
+            }
+            "hook_on_success" => {
+                self.hook_on_success = parse_command(value);
+            }
+            "hook_on_failure" => {
+                self.hook_on_failure = parse_command(value);
+            }
+            "hook_password" => {
+                self.hook_password = parse_bool(key, value)?;
+            }
+            "notify" => {
+                self.notify = parse_bool(key, value)?;
+            }
+            "notify_after_seconds" => {
+                self.notify_after_seconds = parse_number(key, value)?;
+            }
+            "language" => {
+                self.language = (!value.is_empty()).then(|| value.to_string());
+            }
+            "plain" => {
+                self.plain = parse_bool(key, value)?;
+            }
+            "nice" => {
+                self.nice = parse_bool(key, value)?;
+            }
+            "direct_io" => {
+                self.direct_io = parse_bool(key, value)?;
+            }
+            "io_backend" => {
+                self.io_uring = match value {
+                    "standard" => false,
+                    "io_uring" => true,
+                    _ => {
+                        return Err(format!(
+                            "'{}' must be 'standard' or 'io_uring', not '{}'",
+                            key, value
+                        ));
+                    }
+                };
+            }
+            "adaptive_io" => {
+                self.adaptive_io = parse_bool(key, value)?;
+            }
+            "chunk_size_min" => {
+                self.chunk_size_min = parse_size(key, value)?;
+            }
+            "chunk_size_max" => {
+                self.chunk_size_max = parse_size(key, value)?;
+            }
+            "max_workers" => {
+                self.max_workers = parse_number(key, value)?;
+            }
+            "unlock_delay_seconds" => {
+                self.unlock_delay_seconds = parse_number(key, value)?;
+            }
+            "unlock_max_failures" => {
+                self.unlock_max_failures = u32::try_from(parse_number(key, value)?)
+                    .map_err(|_| format!("'{}' is too large", key))?;
+            }
+            "unlock_lockout_minutes" => {
+                self.unlock_lockout_minutes = parse_number(key, value)?;
+            }
+            "audit_syslog" => {
+                self.audit_syslog = parse_bool(key, value)?;
+            }
+            "audit_event_log" => {
+                self.audit_event_log = parse_bool(key, value)?;
+            }
+            "audit_event_source" => {
+                self.audit_event_source = (!value.is_empty()).then(|| value.to_string());
+            }
+            "name_template" => {
+                if !value.is_empty() {
+                    Template::parse(value)?;
+                }
+                self.name_template = (!value.is_empty()).then(|| value.to_string());
+            }
+            "retries" => {
+                self.retries = u32::try_from(parse_number(key, value)?)
+                    .map_err(|_| format!("'{}' is too large", key))?;
+            }
+            "retry_delay_seconds" => {
+                self.retry_delay_seconds = parse_number(key, value)?;
+            }
+            "confirm_above_files" => {
+                self.confirm_above_files = parse_number(key, value)?;
+            }
+            "confirm_above_bytes" => {
+                self.confirm_above_bytes = parse_size(key, value)?;
+            }
+            "history_file" => {
+                self.history_file = (!value.is_empty()).then(|| PathBuf::from(value));
+            }
+            "contacts_file" => {
+                self.contacts_file = (!value.is_empty()).then(|| PathBuf::from(value));
+            }
+            _ => return Err(format!("unknown setting '{}'", key)),
+        }
+
+        Ok(())
+    }
+}
+
+/// Location of the config file. `FILE_ENCRYPTOR_CONFIG` overrides the
+/// default of `~/.file_encryptor.conf`.
+fn config_path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("FILE_ENCRYPTOR_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    env::home_dir().map(|home| home.join(".file_encryptor.conf"))
+}
+
+/// Parse a whole number setting.
+pub fn parse_number(key: &str, value: &str) -> Result<u64, String> {
+    value
+        .parse()
+        .map_err(|_| format!("'{}' must be a whole number, got '{}'", key, value))
+}
+
+/// Parse a size setting such as `64K` or `4M`.
+fn parse_size(key: &str, value: &str) -> Result<u64, String> {
+    crate::filter::parse_size(value).map_err(|e| format!("'{}': {}", key, e))
+}
+
+/// Parse a true/false setting.
+pub fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "true" | "yes" | "on" | "1" => Ok(true),
+        "false" | "no" | "off" | "0" => Ok(false),
+        _ => Err(format!("'{}' must be true or false, got '{}'", key, value)),
+    }
+}
+
+/// Parse a command setting, where an empty value means no command.
+fn parse_command(value: &str) -> Option<String> {
+    (!value.is_empty()).then(|| value.to_string())
+}
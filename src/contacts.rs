@@ -0,0 +1,242 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::checksum::to_hex;
+use crate::config::Config;
+use crate::ed25519::{self, KEY_LEN};
+use crate::signature;
+use crate::storage;
+
+/// First line of the keyring file.
+const KEYRING_HEADER: &str =
+    "# File Encryptor contacts: name, email, Ed25519 public key, and flags (verified, revoked), separated by tabs";
+
+/// Flag of a contact whose fingerprint the user has checked.
+const VERIFIED: &str = "verified";
+
+/// Flag of a contact whose key must no longer be encrypted for.
+const REVOKED: &str = "revoked";
+
+/// Someone files can be encrypted for, by name instead of by key.
+#[derive(Clone)]
+pub struct Contact {
+    pub name: String,
+    pub email: Option<String>,
+    pub key: [u8; KEY_LEN],
+    /// Whether the user confirmed the key's fingerprint with the contact
+    /// out of band (`contact verify`).
+    pub verified: bool,
+    /// Whether the key was revoked, by `contact revoke` or an imported
+    /// revocation, so new files are not encrypted for it.
+    pub revoked: bool,
+}
+
+/// The local keyring: contacts kept in a text file, one per line. It only
+/// holds public keys, so it is not encrypted.
+pub struct Contacts {
+    path: PathBuf,
+    entries: Vec<Contact>,
+}
+
+impl Contacts {
+    /// Read the keyring set by `contacts_file`, or start an empty one if
+    /// the file does not exist yet.
+    pub fn load(config: &Config) -> Result<Self, String> {
+        let path = config
+            .contacts_file
+            .clone()
+            .ok_or_else(|| "No contacts file is set (contacts_file in the config file).".to_string())?;
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(format!("Failed to read '{}': {}", path.display(), e)),
+        };
+        let mut entries = Vec::new();
+        for (number, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let damaged = || format!("{} line {} is not a contact.", path.display(), number + 1);
+            let mut fields = line.split('\t');
+            // Keyrings from before verification have no flags field.
+            let (Some(name), Some(email), Some(key), flags, None) =
+                (fields.next(), fields.next(), fields.next(), fields.next(), fields.next())
+            else {
+                return Err(damaged());
+            };
+            let key = signature::load_public_key(key).map_err(|_| damaged())?;
+            entries.push(Contact {
+                name: name.to_string(),
+                email: (!email.is_empty()).then(|| email.to_string()),
+                key,
+                verified: flags.is_some_and(|flags| flags.split(',').any(|flag| flag == VERIFIED)),
+                revoked: flags.is_some_and(|flags| flags.split(',').any(|flag| flag == REVOKED)),
+            });
+        }
+        Ok(Contacts { path, entries })
+    }
+
+    /// Every contact, in the order they were added.
+    pub fn entries(&self) -> &[Contact] {
+        &self.entries
+    }
+
+    /// The contact called `text`, or with `text` as their email address.
+    /// Addresses are compared ignoring case.
+    pub fn find(&self, text: &str) -> Option<&Contact> {
+        self.entries.iter().find(|contact| contact.name == text).or_else(|| {
+            self.entries
+                .iter()
+                .find(|contact| contact.email.as_ref().is_some_and(|email| email.eq_ignore_ascii_case(text)))
+        })
+    }
+
+    /// Add a contact, not yet verified. Names and addresses must not be
+    /// taken already.
+    pub fn add(&mut self, name: &str, email: Option<&str>, key: [u8; KEY_LEN]) -> Result<(), String> {
+        if name.is_empty() || name.contains(['\t', '\n', '\r']) || name.starts_with('#') {
+            return Err(format!("'{}' cannot be used as a contact name.", name));
+        }
+        if let Some(email) = email
+            && (!email.contains('@') || email.contains(['\t', '\n', '\r', ' ']))
+        {
+            return Err(format!("'{}' is not an email address.", email));
+        }
+        if ed25519::public_to_x25519(&key).is_none() {
+            return Err("That is not a valid public key.".to_string());
+        }
+        for text in [Some(name), email].into_iter().flatten() {
+            if let Some(existing) = self.find(text) {
+                return Err(format!("'{}' is already used by the contact '{}'.", text, existing.name));
+            }
+        }
+        self.entries.push(Contact {
+            name: name.to_string(),
+            email: email.map(str::to_string),
+            key,
+            verified: false,
+            revoked: false,
+        });
+        Ok(())
+    }
+
+    /// The contact whose public key is `key`.
+    pub fn find_key(&self, key: &[u8; KEY_LEN]) -> Option<&Contact> {
+        self.entries.iter().find(|contact| contact.key == *key)
+    }
+
+    /// Mark the contact called `text` (or with that address) as revoked.
+    /// Returns its name.
+    pub fn revoke(&mut self, text: &str) -> Result<String, String> {
+        let name = self
+            .find(text)
+            .map(|contact| contact.name.clone())
+            .ok_or_else(|| format!("No contact is called '{}'.", text))?;
+        for contact in &mut self.entries {
+            if contact.name == name {
+                contact.revoked = true;
+            }
+        }
+        Ok(name)
+    }
+
+    /// Mark `key` as revoked, as told by a revocation file. A key that is
+    /// not a contact yet is added under a name made from its fingerprint,
+    /// so files encrypted for it are still flagged. Returns the name.
+    pub fn revoke_key(&mut self, key: [u8; KEY_LEN]) -> String {
+        if let Some(contact) = self.entries.iter_mut().find(|contact| contact.key == key) {
+            contact.revoked = true;
+            return contact.name.clone();
+        }
+        let name = format!("revoked-{}", signature::fingerprint(&key).replace(' ', ""));
+        self.entries.push(Contact {
+            name: name.clone(),
+            email: None,
+            key,
+            verified: false,
+            revoked: true,
+        });
+        name
+    }
+
+    /// Remove the contact called `text` (or with that address).
+    pub fn remove(&mut self, text: &str) -> Result<Contact, String> {
+        let name = self
+            .find(text)
+            .map(|contact| contact.name.clone())
+            .ok_or_else(|| format!("No contact is called '{}'.", text))?;
+        let index = self
+            .entries
+            .iter()
+            .position(|contact| contact.name == name)
+            .expect("contact just found");
+        Ok(self.entries.remove(index))
+    }
+
+    /// Mark the contact called `text` (or with that address) as verified.
+    pub fn verify(&mut self, text: &str) -> Result<(), String> {
+        let name = self
+            .find(text)
+            .map(|contact| contact.name.clone())
+            .ok_or_else(|| format!("No contact is called '{}'.", text))?;
+        for contact in &mut self.entries {
+            if contact.name == name {
+                contact.verified = true;
+            }
+        }
+        Ok(())
+    }
+
+    /// Write the keyring back. The new file is written next to the old one
+    /// and renamed over it, so a crash never leaves it half written.
+    pub fn save(&self) -> Result<(), String> {
+        let mut text = format!("{}\n", KEYRING_HEADER);
+        for contact in &self.entries {
+            let flags: Vec<&str> = [(VERIFIED, contact.verified), (REVOKED, contact.revoked)]
+                .iter()
+                .filter(|(_, set)| *set)
+                .map(|(flag, _)| *flag)
+                .collect();
+            text.push_str(&format!(
+                "{}\t{}\t{}\t{}\n",
+                contact.name,
+                contact.email.as_deref().unwrap_or(""),
+                to_hex(&contact.key),
+                flags.join(",")
+            ));
+        }
+        let temp_path = self.path.with_extension("saving");
+        fs::write(&temp_path, text)
+            .and_then(|_| storage::rename(&temp_path, &self.path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&temp_path);
+                format!("Failed to write '{}': {}", self.path.display(), e)
+            })
+    }
+
+    /// Where the keyring is kept.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// The public key meant by `text`: a contact's name or address, 64 hex
+/// digits, or the path of a `.pub` file. The contact is returned too when
+/// there is one, including for a key given directly, so the caller can
+/// show who the key belongs to and whether it was revoked.
+pub fn resolve(config: &Config, text: &str) -> Result<([u8; KEY_LEN], Option<Contact>), String> {
+    let contacts = Contacts::load(config);
+    if let Ok(contacts) = &contacts
+        && let Some(contact) = contacts.find(text)
+    {
+        return Ok((contact.key, Some(contact.clone())));
+    }
+    let key = signature::load_public_key(text).map_err(|_| match &contacts {
+        // A keyring that cannot be read only matters if a name was meant.
+        Err(e) => e.clone(),
+        Ok(_) => format!("'{}' is neither a contact (see 'contact list') nor a public key.", text),
+    })?;
+    let contact = contacts.ok().and_then(|contacts| contacts.find_key(&key).cloned());
+    Ok((key, contact))
+}
@@ -0,0 +1,323 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::OpenError;
+use crate::crypto::{self, KEY_LEN, NONCE_LEN, TAG_LEN};
+
+/// Bytes at the start of every container file.
+const CONTAINER_MAGIC: &[u8; 4] = b"FCNT";
+
+/// Version number stored right after the magic bytes.
+const CONTAINER_VERSION: u8 = 1;
+
+/// Bytes at the start of the decrypted key blob.
+const KEY_MAGIC: &[u8; 4] = b"FCKY";
+
+/// Size of one block of the volume, as the operating system sees it.
+pub const BLOCK_LEN: usize = 4096;
+
+/// Block data starts here. Everything before it is the header: magic,
+/// version, block count, and the key blob, padded with zeros.
+const DATA_OFFSET: u64 = 4096;
+
+/// Offset of the key blob inside the header.
+const KEY_BLOB_OFFSET: usize = CONTAINER_MAGIC.len() + 1 + 8 + 4;
+
+/// Each block is stored with its own random nonce and a tag, so rewriting
+/// a block never reuses keystream and tampering is noticed on read.
+const SLOT_LEN: u64 = (NONCE_LEN + BLOCK_LEN + TAG_LEN) as u64;
+
+/// An encrypted volume of fixed-size blocks kept in a single file. It holds
+/// whatever filesystem the user formats it with; this type only encrypts
+/// and decrypts blocks.
+pub struct Container {
+    file: File,
+    blocks: u64,
+    cipher: [u8; KEY_LEN],
+    mac: [u8; KEY_LEN],
+}
+
+/// Create a container of at least `size` bytes (rounded up to whole
+/// blocks) at `path`, which must not exist. Every block is written
+/// encrypted, so the file does not reveal which parts are in use.
+pub fn create(path: &Path, size: u64, password: &str) -> Result<(), String> {
+    if password.is_empty() {
+        return Err("Password cannot be empty.".to_string());
+    }
+    let blocks = size.div_ceil(BLOCK_LEN as u64);
+    if blocks == 0 {
+        return Err("The container must hold at least one block.".to_string());
+    }
+
+    let mut secret = [0u8; 2 * KEY_LEN];
+    crypto::random_bytes(&mut secret)?;
+    let mut key_blob = KEY_MAGIC.to_vec();
+    key_blob.extend_from_slice(&secret);
+    let key_blob = crate::encrypt_bytes(&key_blob, &[], password.as_bytes())?;
+
+    let mut header = Vec::with_capacity(DATA_OFFSET as usize);
+    header.extend_from_slice(CONTAINER_MAGIC);
+    header.push(CONTAINER_VERSION);
+    header.extend_from_slice(&blocks.to_le_bytes());
+    header.extend_from_slice(&(key_blob.len() as u32).to_le_bytes());
+    header.extend_from_slice(&key_blob);
+    header.resize(DATA_OFFSET as usize, 0);
+
+    let write_error = |e: io::Error| format!("Failed to write '{}': {}", path.display(), e);
+    let file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .map_err(write_error)?;
+    let container = Container::with_secret(file, blocks, &secret);
+    let mut writer = BufWriter::new(&container.file);
+    writer.write_all(&header).map_err(write_error)?;
+    let zeros = [0u8; BLOCK_LEN];
+    for index in 0..blocks {
+        writer
+            .write_all(&container.seal_block(index, &zeros)?)
+            .map_err(write_error)?;
+    }
+    writer.flush().map_err(write_error)?;
+    drop(writer);
+    container.file.sync_all().map_err(write_error)
+}
+
+impl Container {
+    /// Open the container at `path`. Fails if the password is wrong.
+    pub fn open(path: &Path, password: &str) -> Result<Self, OpenError> {
+        let read_error = |e: io::Error| format!("Failed to read '{}': {}", path.display(), e);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .map_err(read_error)?;
+        let mut header = vec![0u8; DATA_OFFSET as usize];
+        file.read_exact(&mut header).map_err(|_| not_a_container(path))?;
+        if !header.starts_with(CONTAINER_MAGIC) {
+            return Err(not_a_container(path).into());
+        }
+        if header[4] != CONTAINER_VERSION {
+            return Err(format!("Unsupported container version {}.", header[4]).into());
+        }
+        let blocks = u64::from_le_bytes(header[5..13].try_into().expect("8 bytes"));
+        let key_len = u32::from_le_bytes(header[13..17].try_into().expect("4 bytes")) as usize;
+        let key_blob = header
+            .get(KEY_BLOB_OFFSET..KEY_BLOB_OFFSET + key_len)
+            .ok_or_else(|| not_a_container(path))?;
+
+        let decrypted = crate::decrypt_bytes(key_blob, password.as_bytes(), false)?;
+        let secret = decrypted
+            .plaintext
+            .strip_prefix(KEY_MAGIC)
+            .filter(|secret| secret.len() == 2 * KEY_LEN)
+            .ok_or_else(|| not_a_container(path))?;
+
+        let expected_len = DATA_OFFSET + blocks * SLOT_LEN;
+        let actual_len = file.metadata().map_err(read_error)?.len();
+        if actual_len != expected_len {
+            return Err(format!(
+                "'{}' should be {} bytes but is {}; the container is damaged.",
+                path.display(),
+                expected_len,
+                actual_len
+            )
+            .into());
+        }
+        Ok(Container::with_secret(file, blocks, secret))
+    }
+
+    fn with_secret(file: File, blocks: u64, secret: &[u8]) -> Self {
+        let mut container = Container {
+            file,
+            blocks,
+            cipher: [0; KEY_LEN],
+            mac: [0; KEY_LEN],
+        };
+        container.cipher.copy_from_slice(&secret[..KEY_LEN]);
+        container.mac.copy_from_slice(&secret[KEY_LEN..]);
+        container
+    }
+
+    /// Size of the volume in bytes.
+    pub fn size(&self) -> u64 {
+        self.blocks * BLOCK_LEN as u64
+    }
+
+    /// Fill `buffer` with the volume's contents starting at `offset`.
+    pub fn read_at(&self, mut offset: u64, mut buffer: &mut [u8]) -> io::Result<()> {
+        self.check_range(offset, buffer.len())?;
+        while !buffer.is_empty() {
+            let index = offset / BLOCK_LEN as u64;
+            let start = (offset % BLOCK_LEN as u64) as usize;
+            let len = buffer.len().min(BLOCK_LEN - start);
+            let block = self.read_block(index)?;
+            buffer[..len].copy_from_slice(&block[start..start + len]);
+            buffer = &mut buffer[len..];
+            offset += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Write `data` to the volume starting at `offset`. Partly covered
+    /// blocks are read, changed, and written back whole.
+    pub fn write_at(&self, mut offset: u64, mut data: &[u8]) -> io::Result<()> {
+        self.check_range(offset, data.len())?;
+        while !data.is_empty() {
+            let index = offset / BLOCK_LEN as u64;
+            let start = (offset % BLOCK_LEN as u64) as usize;
+            let len = data.len().min(BLOCK_LEN - start);
+            let mut block = if len == BLOCK_LEN {
+                vec![0u8; BLOCK_LEN]
+            } else {
+                self.read_block(index)?
+            };
+            block[start..start + len].copy_from_slice(&data[..len]);
+            self.write_block(index, &block)?;
+            data = &data[len..];
+            offset += len as u64;
+        }
+        Ok(())
+    }
+
+    /// Make sure everything written so far is on disk.
+    pub fn flush(&self) -> io::Result<()> {
+        self.file.sync_data()
+    }
+
+    fn check_range(&self, offset: u64, len: usize) -> io::Result<()> {
+        match offset.checked_add(len as u64) {
+            Some(end) if end <= self.size() => Ok(()),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "access past the end of the container",
+            )),
+        }
+    }
+
+    fn read_block(&self, index: u64) -> io::Result<Vec<u8>> {
+        let mut slot = vec![0u8; SLOT_LEN as usize];
+        read_exact_at(&self.file, &mut slot, DATA_OFFSET + index * SLOT_LEN)?;
+        let (body, tag) = slot.split_at(NONCE_LEN + BLOCK_LEN);
+        if !crypto::constant_time_eq(&self.block_tag(index, body), tag) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("block {} of the container was modified or damaged", index),
+            ));
+        }
+        let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+        let mut block = ciphertext.to_vec();
+        crypto::chacha20_xor(
+            &self.cipher,
+            nonce.try_into().expect("nonce length"),
+            0,
+            &mut block,
+        );
+        Ok(block)
+    }
+
+    fn write_block(&self, index: u64, block: &[u8]) -> io::Result<()> {
+        let slot = self.seal_block(index, block).map_err(io::Error::other)?;
+        write_all_at(&self.file, &slot, DATA_OFFSET + index * SLOT_LEN)
+    }
+
+    /// A block as stored: a fresh nonce, the block encrypted with ChaCha20,
+    /// and a tag over the block number, nonce, and ciphertext. Including the
+    /// number stops blocks from being swapped around unnoticed.
+    fn seal_block(&self, index: u64, block: &[u8]) -> Result<Vec<u8>, String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        crypto::random_bytes(&mut nonce)?;
+        let mut slot = Vec::with_capacity(SLOT_LEN as usize);
+        slot.extend_from_slice(&nonce);
+        slot.extend_from_slice(block);
+        crypto::chacha20_xor(&self.cipher, &nonce, 0, &mut slot[NONCE_LEN..]);
+        let tag = self.block_tag(index, &slot);
+        slot.extend_from_slice(&tag);
+        Ok(slot)
+    }
+
+    fn block_tag(&self, index: u64, body: &[u8]) -> [u8; TAG_LEN] {
+        let mut mac = crypto::HmacSha256::new(&self.mac);
+        mac.update(&index.to_le_bytes());
+        mac.update(body);
+        mac.finalize()
+    }
+}
+
+fn not_a_container(path: &Path) -> String {
+    format!("'{}' is not an encrypted container.", path.display())
+}
+
+#[cfg(unix)]
+fn read_exact_at(file: &File, buffer: &mut [u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::read_exact_at(file, buffer, offset)
+}
+
+#[cfg(windows)]
+fn read_exact_at(file: &File, mut buffer: &mut [u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buffer.is_empty() {
+        match file.seek_read(buffer, offset)? {
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            read => {
+                buffer = &mut buffer[read..];
+                offset += read as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &File, data: &[u8], offset: u64) -> io::Result<()> {
+    std::os::unix::fs::FileExt::write_all_at(file, data, offset)
+}
+
+#[cfg(windows)]
+fn write_all_at(file: &File, mut data: &[u8], mut offset: u64) -> io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !data.is_empty() {
+        match file.seek_write(data, offset)? {
+            0 => return Err(io::ErrorKind::WriteZero.into()),
+            written => {
+                data = &data[written..];
+                offset += written as u64;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn changed_or_swapped_blocks_are_noticed() {
+        let path = std::env::temp_dir().join(format!("file_encryptor_container_{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        create(&path, 2 * BLOCK_LEN as u64, "password").unwrap();
+        let container = Container::open(&path, "password").unwrap();
+        container.write_at(0, &[1; 2 * BLOCK_LEN]).unwrap();
+        assert!(container.write_at(1, &[0; 2 * BLOCK_LEN]).is_err());
+
+        // Swap the two blocks on disk: each is intact, but in the wrong place.
+        let mut first = vec![0u8; SLOT_LEN as usize];
+        let mut second = first.clone();
+        read_exact_at(&container.file, &mut first, DATA_OFFSET).unwrap();
+        read_exact_at(&container.file, &mut second, DATA_OFFSET + SLOT_LEN).unwrap();
+        write_all_at(&container.file, &second, DATA_OFFSET).unwrap();
+        write_all_at(&container.file, &first, DATA_OFFSET + SLOT_LEN).unwrap();
+        let mut buffer = [0u8; 1];
+        assert!(container.read_at(0, &mut buffer).is_err());
+
+        // A flipped bit in the ciphertext.
+        write_all_at(&container.file, &first, DATA_OFFSET).unwrap();
+        assert!(container.read_at(0, &mut buffer).is_ok());
+        first[NONCE_LEN] ^= 1;
+        write_all_at(&container.file, &first, DATA_OFFSET).unwrap();
+        assert!(container.read_at(0, &mut buffer).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+/// How much of the data is examined. The start of a file is enough to tell
+/// text, known formats, and random noise apart.
+const SAMPLE_LEN: usize = 64 * 1024;
+
+/// Shorter data says too little to judge.
+const MIN_SAMPLE_LEN: usize = 16;
+
+/// A common file format recognised by the magic bytes at the start of it.
+struct Signature {
+    /// Where the magic bytes sit.
+    offset: usize,
+    magic: &'static [u8],
+    /// What to call the format when telling the user.
+    kind: &'static str,
+    /// Extension files of this format usually have, if any.
+    extension: Option<&'static str>,
+}
+
+const fn sig(
+    offset: usize,
+    magic: &'static [u8],
+    kind: &'static str,
+    extension: Option<&'static str>,
+) -> Signature {
+    Signature {
+        offset,
+        magic,
+        kind,
+        extension,
+    }
+}
+
+/// Signatures of common formats. Many of these formats are compressed, so
+/// apart from the signature they look random.
+const SIGNATURES: &[Signature] = &[
+    sig(0, b"%PDF-", "a PDF document", Some("pdf")),
+    sig(0, b"\x89PNG\r\n\x1a\n", "a PNG image", Some("png")),
+    sig(0, b"\xff\xd8\xff", "a JPEG image", Some("jpg")),
+    sig(0, b"GIF8", "a GIF image", Some("gif")),
+    sig(8, b"WEBP", "a WebP image", Some("webp")),
+    sig(0, b"PK\x03\x04", "a ZIP archive", Some("zip")),
+    sig(0, b"\x1f\x8b", "a gzip archive", Some("gz")),
+    sig(0, b"7z\xbc\xaf\x27\x1c", "a 7-Zip archive", Some("7z")),
+    sig(0, b"Rar!\x1a\x07", "a RAR archive", Some("rar")),
+    sig(0, b"\xfd7zXZ\x00", "an xz archive", Some("xz")),
+    sig(0, b"BZh", "a bzip2 archive", Some("bz2")),
+    sig(257, b"ustar", "a tar archive", Some("tar")),
+    sig(0, b"ID3", "an MP3 audio file", Some("mp3")),
+    sig(4, b"ftyp", "an MP4 video", Some("mp4")),
+    sig(0, b"OggS", "an Ogg audio file", Some("ogg")),
+    sig(
+        0,
+        b"SQLite format 3\x00",
+        "an SQLite database",
+        Some("sqlite"),
+    ),
+    sig(0, b"\x7fELF", "a Linux program", None),
+    sig(0, b"MZ", "a Windows program", Some("exe")),
+    // Folder archives are extracted with "Decrypt folder", not renamed.
+    sig(0, b"FARC", "an encrypted folder", None),
+    sig(0, b"FENC", "an encrypted file", Some("enc")),
+];
+
+/// The signature `data` starts with, if it matches a known format.
+fn find_signature(data: &[u8]) -> Option<&'static Signature> {
+    SIGNATURES.iter().find(|signature| {
+        data.get(signature.offset..)
+            .is_some_and(|rest| rest.starts_with(signature.magic))
+    })
+}
+
+/// Whether `data` starts with the signature of a known format.
+pub fn has_known_signature(data: &[u8]) -> bool {
+    find_signature(data).is_some()
+}
+
+/// Name the format of `data` from its signature and return the extension
+/// such files usually have, so a file decrypted to a name without one can
+/// be given it.
+pub fn detect_extension(data: &[u8]) -> Option<(&'static str, &'static str)> {
+    let signature = find_signature(data)?;
+    Some((signature.kind, signature.extension?))
+}
+
+/// Decide whether decrypted data looks like random noise, which is what a
+/// wrong password produces for files without a checksum. Returns a short
+/// explanation if so.
+pub fn looks_random(data: &[u8]) -> Option<String> {
+    let sample = &data[..data.len().min(SAMPLE_LEN)];
+    if sample.len() < MIN_SAMPLE_LEN || has_known_signature(sample) {
+        return None;
+    }
+
+    // The XOR cipher turns text decrypted with the wrong password into other
+    // 7-bit characters, a few of them control characters real text never
+    // has.
+    let text = text_ratio(sample);
+    if text >= 0.9 {
+        let control = control_ratio(sample);
+        if control >= 0.01 {
+            return Some(format!(
+                "The output looks like scrambled text ({:.1}% control characters), so the password was probably wrong.",
+                control * 100.0
+            ));
+        }
+        return None;
+    }
+
+    // Random bytes come close to the most entropy a sample of this size can
+    // have: 8 bits per byte for long samples, less for short ones.
+    let entropy = entropy(sample);
+    let ceiling = (sample.len().min(256) as f64).log2();
+    if entropy >= 0.85 * ceiling && text < 0.6 {
+        return Some(format!(
+            "The output looks like random data ({:.2} bits of entropy per byte, {:.0}% text), so the password was probably wrong.",
+            entropy,
+            text * 100.0
+        ));
+    }
+    None
+}
+
+/// Shannon entropy of the bytes, in bits per byte.
+fn entropy(sample: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &byte in sample {
+        counts[byte as usize] += 1;
+    }
+    let len = sample.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Fraction of bytes that are printable text, whitespace, or part of a
+/// UTF-8 sequence. Control bytes other than whitespace, zeros among them,
+/// are valid UTF-8 but do not count; otherwise a file of zeros or of
+/// control codes would pass for text.
+fn text_ratio(sample: &[u8]) -> f64 {
+    let controls = sample
+        .iter()
+        .filter(|&&byte| byte.is_ascii_control() && !byte.is_ascii_whitespace())
+        .count();
+    let text = match std::str::from_utf8(sample) {
+        Ok(_) => sample.len() - controls,
+        // A cut through a multi-byte character at the end is still text.
+        Err(e) if e.error_len().is_none() => sample.len() - controls,
+        Err(_) => sample
+            .iter()
+            .filter(|&&byte| byte.is_ascii_graphic() || byte.is_ascii_whitespace())
+            .count(),
+    };
+    text as f64 / sample.len() as f64
+}
+
+/// Fraction of bytes that are control characters other than the ones text
+/// files use: tab, line breaks, form feed, and the escape that starts
+/// terminal color codes.
+fn control_ratio(sample: &[u8]) -> f64 {
+    let control = sample
+        .iter()
+        .filter(|&&byte| {
+            (byte < 0x20 && !matches!(byte, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)) || byte == 0x7f
+        })
+        .count();
+    control as f64 / sample.len() as f64
+}
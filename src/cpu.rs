@@ -0,0 +1,88 @@
+use std::sync::OnceLock;
+
+use file_encryptor_core::aes;
+
+use crate::format::Cipher;
+use crate::policy;
+
+/// Instruction set extensions that matter for encryption speed.
+pub struct Features {
+    /// AES round instructions (AES-NI on x86, the crypto extension on ARM).
+    pub aes: bool,
+    /// Carry-less multiplication, which GCM needs alongside AES to be fast.
+    pub carryless_multiply: bool,
+    /// 256-bit integer vectors (x86), used for XOR and ChaCha20.
+    pub avx2: bool,
+    /// 128-bit vectors (ARM).
+    pub neon: bool,
+    /// SHA-256 instructions, used for checksums and key derivation.
+    pub sha256: bool,
+}
+
+static FEATURES: OnceLock<Features> = OnceLock::new();
+
+/// The features of this CPU, detected on first use.
+pub fn features() -> &'static Features {
+    FEATURES.get_or_init(detect)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn detect() -> Features {
+    Features {
+        aes: std::arch::is_x86_feature_detected!("aes"),
+        carryless_multiply: std::arch::is_x86_feature_detected!("pclmulqdq"),
+        avx2: std::arch::is_x86_feature_detected!("avx2"),
+        neon: false,
+        sha256: std::arch::is_x86_feature_detected!("sha"),
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn detect() -> Features {
+    // The ARM AES extension includes the 64-bit polynomial multiply GCM
+    // needs.
+    let aes = std::arch::is_aarch64_feature_detected!("aes");
+    Features {
+        aes,
+        carryless_multiply: aes,
+        avx2: false,
+        neon: std::arch::is_aarch64_feature_detected!("neon"),
+        sha256: std::arch::is_aarch64_feature_detected!("sha2"),
+    }
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+fn detect() -> Features {
+    Features {
+        aes: false,
+        carryless_multiply: false,
+        avx2: false,
+        neon: false,
+        sha256: false,
+    }
+}
+
+/// The cipher that would be fastest here: AES-GCM when the CPU has
+/// hardware AES and carry-less multiplication, ChaCha20 otherwise, since
+/// AES in software is both slower and open to cache-timing attacks.
+pub fn preferred_cipher() -> Cipher {
+    let features = features();
+    if features.aes && features.carryless_multiply {
+        Cipher::AesGcm
+    } else {
+        Cipher::ChaCha20
+    }
+}
+
+/// The cipher new files are actually written with: AES-GCM where this
+/// build runs it on the CPU's instructions, which is only x86_64 so far,
+/// and ChaCha20 otherwise. The organization policy can rule either out,
+/// and if it allows only AES-GCM that is used even in software.
+pub fn cipher_in_use() -> Cipher {
+    let policy = policy::get();
+    if policy.allow_aes_gcm && (aes::accelerated() || !policy.allow_chacha20) {
+        Cipher::AesGcm
+    } else {
+        Cipher::ChaCha20
+    }
+}
@@ -0,0 +1,71 @@
+use std::sync::Mutex;
+
+pub use file_encryptor_core::crypto::{
+    HmacSha256, KEY_LEN, NONCE_LEN, TAG_LEN, chacha20_xor, constant_time_eq, hmac_sha256, pbkdf2_sha256,
+    xor_in_place,
+};
+
+/// Key of the keystream that stands in for the system's random numbers
+/// under `--deterministic-for-testing`, and how much of it has been used.
+static TEST_STREAM: Mutex<Option<([u8; KEY_LEN], u64)>> = Mutex::new(None);
+
+/// Take every "random" byte for the rest of the run from a ChaCha20
+/// keystream keyed with the SHA-256 of `seed`, so the same inputs always
+/// encrypt to the same bytes. Only for tests: anyone who knows the seed
+/// can predict every salt, nonce, and key.
+pub fn use_test_seed(seed: &[u8]) {
+    *TEST_STREAM.lock().unwrap_or_else(|e| e.into_inner()) = Some((crate::checksum::sha256(seed), 0));
+}
+
+/// Fill `buffer` with random bytes from the operating system, or from the
+/// test seed if one was given.
+pub fn random_bytes(buffer: &mut [u8]) -> Result<(), String> {
+    if let Some((key, used)) = &mut *TEST_STREAM.lock().unwrap_or_else(|e| e.into_inner()) {
+        buffer.fill(0);
+        chacha20_xor(key, &[0; NONCE_LEN], *used, buffer);
+        *used += buffer.len() as u64;
+        return Ok(());
+    }
+    system_random_bytes(buffer)
+}
+
+#[cfg(unix)]
+fn system_random_bytes(buffer: &mut [u8]) -> Result<(), String> {
+    use std::io::Read;
+    std::fs::File::open("/dev/urandom")
+        .and_then(|mut source| source.read_exact(buffer))
+        .map_err(|e| format!("Failed to get random bytes: {}", e))
+}
+
+#[cfg(windows)]
+fn system_random_bytes(buffer: &mut [u8]) -> Result<(), String> {
+    #[link(name = "bcrypt")]
+    unsafe extern "system" {
+        fn BCryptGenRandom(
+            algorithm: *mut std::ffi::c_void,
+            buffer: *mut u8,
+            length: u32,
+            flags: u32,
+        ) -> i32;
+    }
+    const BCRYPT_USE_SYSTEM_PREFERRED_RNG: u32 = 2;
+
+    for chunk in buffer.chunks_mut(u32::MAX as usize) {
+        // SAFETY: the pointer and length describe a valid, writable slice.
+        let status = unsafe {
+            BCryptGenRandom(
+                std::ptr::null_mut(),
+                chunk.as_mut_ptr(),
+                chunk.len() as u32,
+                BCRYPT_USE_SYSTEM_PREFERRED_RNG,
+            )
+        };
+        if status != 0 {
+            return Err(format!(
+                "Failed to get random bytes (status {:#x}).",
+                status
+            ));
+        }
+    }
+    Ok(())
+}
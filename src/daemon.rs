@@ -0,0 +1,375 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, mpsc};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::archive;
+use crate::claims;
+use crate::audit::Auditor;
+use crate::config::Config;
+use crate::format::{self, SALT_LEN, SealParams};
+use crate::hooks::HookEvent;
+use crate::metadata::FileMetadata;
+use crate::metrics::{self, Stats};
+use crate::progress;
+use crate::sandbox;
+use crate::throttle;
+use crate::totp;
+use crate::{Keys, sealing};
+
+/// How long the daemon keeps the password after its last use unless told
+/// otherwise.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(15 * 60);
+
+/// Most fields in one message, and the longest field, so a stray client
+/// cannot make the daemon allocate without limit.
+const MAX_FIELDS: u32 = 8;
+const MAX_FIELD_LEN: u32 = 64 * 1024;
+
+/// The default socket: in `$XDG_RUNTIME_DIR`, which only the user can
+/// enter, or else in the home folder.
+pub fn default_socket() -> PathBuf {
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) => PathBuf::from(dir).join("file_encryptor.sock"),
+        None => env::home_dir()
+            .unwrap_or_default()
+            .join(".file_encryptor.sock"),
+    }
+}
+
+/// What the daemon remembers while unlocked.
+struct Session {
+    /// The cached password, empty while locked.
+    password: Vec<u8>,
+    /// Settings and keys shared by every file encrypted in this session,
+    /// derived on first use.
+    new_files: Option<(SealParams, Keys)>,
+    /// Keys already derived for opening files, by salt and iteration
+    /// count.
+    opened: HashMap<([u8; SALT_LEN], u32), Keys>,
+    /// When the password is forgotten unless used again.
+    expires: Instant,
+}
+
+impl Session {
+    fn is_unlocked(&self) -> bool {
+        !self.password.is_empty()
+    }
+
+    /// Forget the password and every key derived from it.
+    fn lock(&mut self) {
+        self.password.fill(0);
+        self.password.clear();
+        self.new_files = None;
+        self.opened.clear();
+    }
+}
+
+/// Run the daemon on `socket` until a `stop` request, answering one
+/// client at a time. The password given by `unlock` is kept in memory,
+/// with the keys derived from it, until `timeout` passes without a
+/// request that uses it. Once listening it switches to `user`, if given,
+/// and hardens itself (see `sandbox::harden`). With `metrics`, the
+/// counters `stats` reports are also served over HTTP at that address.
+pub fn serve(
+    config: &Config,
+    socket: &Path,
+    timeout: Duration,
+    user: Option<&str>,
+    metrics: Option<&str>,
+) -> Result<(), String> {
+    let listener = sandbox::listen(socket, "daemon")?;
+    // Bound before switching user, so a privileged port still works.
+    let metrics_listener = metrics.map(metrics::bind).transpose()?;
+    if let Some(warning) = sandbox::harden(user, &listener, socket)? {
+        eprintln!("Warning: {}", warning);
+    }
+    let stats = Arc::new(Stats::new());
+    if let Some(metrics_listener) = metrics_listener {
+        metrics::serve_http(metrics_listener, Arc::clone(&stats));
+    }
+
+    let session = Arc::new(Mutex::new(Session {
+        password: Vec::new(),
+        new_files: None,
+        opened: HashMap::new(),
+        expires: Instant::now(),
+    }));
+    let expiry = Arc::clone(&session);
+    let expiry_stats = Arc::clone(&stats);
+    thread::spawn(move || {
+        loop {
+            thread::sleep(Duration::from_secs(1));
+            let mut session = expiry.lock().unwrap_or_else(|e| e.into_inner());
+            if session.is_unlocked() && Instant::now() >= session.expires {
+                session.lock();
+                expiry_stats.set_unlocked(false);
+            }
+        }
+    });
+
+    // Connections are accepted as they come and queued, so the queue
+    // depth can be reported while a request is being worked on.
+    let (queue, queued) = mpsc::channel();
+    let accept_stats = Arc::clone(&stats);
+    let incoming = listener.listener.try_clone().map_err(|e| format!("Failed to listen: {}", e))?;
+    thread::spawn(move || {
+        for stream in incoming.incoming().flatten() {
+            accept_stats.queued();
+            if queue.send(stream).is_err() {
+                break;
+            }
+        }
+    });
+
+    let auditor = Auditor::from_config(config);
+    for mut stream in queued {
+        stats.dequeued();
+        // One client at a time, so one that stops talking must not hold
+        // up the rest.
+        let _ = stream.set_read_timeout(Some(Duration::from_secs(10)));
+        let request = match read_message(&mut stream) {
+            Ok(request) => request,
+            Err(_) => continue,
+        };
+        let stop = request.first().is_some_and(|command| command == b"stop");
+        let reply = {
+            let mut session = session.lock().unwrap_or_else(|e| e.into_inner());
+            let reply = handle(config, &auditor, &mut session, timeout, &stats, &request);
+            stats.set_unlocked(session.is_unlocked());
+            reply
+        };
+        if reply.is_err() {
+            stats.failed();
+        }
+        let (status, message) = match &reply {
+            Ok(message) => ("ok", message.as_str()),
+            Err(message) => ("error", message.as_str()),
+        };
+        let _ = write_message(&mut stream, &[status.as_bytes(), message.as_bytes()]);
+        if stop {
+            break;
+        }
+    }
+    session.lock().unwrap_or_else(|e| e.into_inner()).lock();
+    if !listener.activated {
+        let _ = fs::remove_file(socket);
+    }
+    Ok(())
+}
+
+/// Answer one request.
+fn handle(
+    config: &Config,
+    auditor: &Auditor,
+    session: &mut Session,
+    timeout: Duration,
+    stats: &Stats,
+    request: &[Vec<u8>],
+) -> Result<String, String> {
+    let text = |field: &Vec<u8>| String::from_utf8(field.clone()).map_err(|_| "Paths must be UTF-8.".to_string());
+    match request {
+        [command, password] if command == b"unlock" => {
+            if password.is_empty() {
+                return Err("Password cannot be empty.".to_string());
+            }
+            session.lock();
+            session.password = password.clone();
+            session.expires = Instant::now() + timeout;
+            Ok(format!("Unlocked; the password is forgotten after {}s without use.", timeout.as_secs()))
+        }
+        [command] if command == b"lock" => {
+            session.lock();
+            Ok("Locked.".to_string())
+        }
+        [command] if command == b"status" => Ok(if session.is_unlocked() {
+            format!(
+                "Unlocked; locks in {}s unless used. {} key set(s) cached.",
+                session.expires.saturating_duration_since(Instant::now()).as_secs(),
+                session.opened.len()
+            )
+        } else {
+            "Locked.".to_string()
+        }),
+        [command] if command == b"stats" => Ok(stats.to_prometheus()),
+        [command] if command == b"stop" => Ok("Stopped.".to_string()),
+        [command, input, output] if command == b"encrypt" || command == b"decrypt" => {
+            if !session.is_unlocked() {
+                return Err("The daemon is locked. Run 'daemon unlock' first.".to_string());
+            }
+            session.expires = Instant::now() + timeout;
+            let (input, output) = (text(input)?, text(output)?);
+            let encrypt = command == b"encrypt";
+            let result = if encrypt {
+                encrypt_file(config, session, &input, &output)
+            } else {
+                decrypt_file(config, session, &input, &output)
+            };
+            let event = HookEvent {
+                action: if encrypt { "encrypt" } else { "decrypt" },
+                input: &input,
+                output: &output,
+                error: result.as_ref().err().map(String::as_str),
+                // Only the audit log hears of it; the daemon runs no hooks.
+                password: None,
+            };
+            if let Some(warning) = auditor.operation_finished(&event) {
+                eprintln!("Warning: {}", warning);
+            }
+            let (message, bytes) = result?;
+            stats.file_done(encrypt, bytes);
+            Ok(message)
+        }
+        _ => Err("Unknown request.".to_string()),
+    }
+}
+
+/// Returns what to tell the client and how many bytes of plaintext there
+/// were, as does `decrypt_file`.
+fn encrypt_file(config: &Config, session: &mut Session, input: &str, output: &str) -> Result<(String, u64), String> {
+    if Path::new(input).is_dir() {
+        return Err("The daemon encrypts single files; use encrypt-folder for folders.".to_string());
+    }
+    refuse_existing(output)?;
+    let _claims = claims::claim_all(&[Path::new(input), Path::new(output)], "encrypt")?;
+    let (data, changed) =
+        throttle::read_consistent(input).map_err(|e| format!("Failed to read '{}': {}", input, e))?;
+    let metadata = if config.preserve_metadata {
+        FileMetadata::capture(input)?
+    } else {
+        FileMetadata::default()
+    };
+    if session.new_files.is_none() {
+        if totp::required() && totp::enrolled()?.is_none() {
+            return Err("The first use of --totp shows the authenticator secret in the terminal; encrypt one file directly first.".to_string());
+        }
+        let (params, keys) = crate::new_file_keys(&session.password)?;
+        // Files encrypted now can be opened again without a derivation,
+        // unless their keys are bound to the authenticator secret, which
+        // only opening them directly asks a code for.
+        if !totp::required() {
+            session
+                .opened
+                .insert((params.salt, params.iterations), keys.clone());
+        }
+        session.new_files = Some((params, keys));
+    }
+    let (params, keys) = session.new_files.as_ref().expect("derived above");
+    let encrypted = crate::encrypt_with_keys(&data, &metadata.to_bytes(), params, keys)?;
+    throttle::write(output, &encrypted).map_err(|e| format!("Failed to write '{}': {}", output, e))?;
+    let mut message = format!("Encrypted '{}' -> '{}'", input, output);
+    if changed {
+        message.push_str(&format!("\nWarning: {}", crate::input_changed_warning(input)));
+    }
+    Ok((message, data.len() as u64))
+}
+
+fn decrypt_file(config: &Config, session: &mut Session, input: &str, output: &str) -> Result<(String, u64), String> {
+    refuse_existing(output)?;
+    let _claims = claims::claim_all(&[Path::new(input), Path::new(output)], "decrypt")?;
+    let data = throttle::read(input).map_err(|e| format!("Failed to read '{}': {}", input, e))?;
+    match format::split_header(&data).map_err(format::message)? {
+        Some((version, _)) if version >= format::SEALED_VERSION => {}
+        _ => return Err("The daemon only opens files in the current format; decrypt older files directly.".to_string()),
+    }
+    let sealed = format::split_sealed(&data).map_err(format::message)?;
+    let id = (sealed.params.salt, sealed.params.iterations);
+    let password = &session.password;
+    let keys = session
+        .opened
+        .entry(id)
+        .or_insert_with(|| {
+            progress::deriving_key(sealed.params.iterations, || sealing::derive_keys(password, &sealed.params))
+        });
+    let opened = crate::unseal(&sealed, keys).inspect_err(|_| {
+        // Keys from a wrong password are not worth keeping.
+        session.opened.remove(&id);
+    })?;
+    let (plaintext, metadata) = (opened.plaintext, opened.metadata);
+    if opened.totp_secret.is_some() {
+        return Err("This file needs an authenticator code; decrypt it directly.".to_string());
+    }
+
+    let mut warnings = Vec::new();
+    if archive::is_archive(&plaintext) {
+        warnings = archive::unpack(&plaintext, Path::new(output))?.warnings;
+    } else {
+        throttle::write(output, &plaintext).map_err(|e| format!("Failed to write '{}': {}", output, e))?;
+    }
+    if config.preserve_metadata {
+        warnings.extend(metadata.restore(output));
+    }
+    let mut message = format!("Decrypted '{}' -> '{}'", input, output);
+    for warning in warnings {
+        message.push_str(&format!("\nWarning: {}", warning));
+    }
+    Ok((message, plaintext.len() as u64))
+}
+
+fn refuse_existing(output: &str) -> Result<(), String> {
+    if Path::new(output).exists() {
+        return Err(format!("'{}' already exists.", output));
+    }
+    Ok(())
+}
+
+/// Send one request to the daemon on `socket` and return its answer.
+pub fn request(socket: &Path, fields: &[&[u8]]) -> Result<String, String> {
+    let mut stream = UnixStream::connect(socket).map_err(|e| {
+        format!(
+            "No daemon is listening on '{}' ({}). Start one with 'daemon start'.",
+            socket.display(),
+            e
+        )
+    })?;
+    let reply = write_message(&mut stream, fields)
+        .and_then(|_| read_message(&mut stream))
+        .map_err(|e| format!("The connection to the daemon failed: {}", e))?;
+    match reply.as_slice() {
+        [status, message] => {
+            let message = String::from_utf8_lossy(message).into_owned();
+            if status == b"ok" { Ok(message) } else { Err(message) }
+        }
+        _ => Err("The daemon sent a malformed reply.".to_string()),
+    }
+}
+
+/// Messages are a field count followed by each field with its length,
+/// all lengths as 32-bit little-endian numbers.
+fn write_message(stream: &mut UnixStream, fields: &[&[u8]]) -> io::Result<()> {
+    let mut message = (fields.len() as u32).to_le_bytes().to_vec();
+    for field in fields {
+        message.extend_from_slice(&(field.len() as u32).to_le_bytes());
+        message.extend_from_slice(field);
+    }
+    stream.write_all(&message)
+}
+
+fn read_message(stream: &mut UnixStream) -> io::Result<Vec<Vec<u8>>> {
+    let count = read_u32(stream)?;
+    if count > MAX_FIELDS {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "too many fields"));
+    }
+    let mut fields = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let len = read_u32(stream)?;
+        if len > MAX_FIELD_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "field too long"));
+        }
+        let mut field = vec![0u8; len as usize];
+        stream.read_exact(&mut field)?;
+        fields.push(field);
+    }
+    Ok(fields)
+}
+
+fn read_u32(stream: &mut UnixStream) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    stream.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
@@ -0,0 +1,93 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::buffers;
+use crate::checksum::{Blake3, Sha256};
+
+/// A hash `hash` can compute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+}
+
+impl Algorithm {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "sha256" | "sha-256" => Ok(Algorithm::Sha256),
+            "blake3" => Ok(Algorithm::Blake3),
+            _ => Err(format!("Unknown hash '{}' (known: sha256, blake3).", name)),
+        }
+    }
+}
+
+/// A hash in progress, whichever the algorithm.
+enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<Blake3>),
+}
+
+impl Hasher {
+    fn new(algorithm: Algorithm) -> Self {
+        match algorithm {
+            Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+            Algorithm::Blake3 => Hasher::Blake3(Box::new(Blake3::new())),
+        }
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(data),
+            Hasher::Blake3(hasher) => hasher.update(data),
+        }
+    }
+
+    fn finalize(self) -> [u8; 32] {
+        match self {
+            Hasher::Sha256(hasher) => hasher.finalize(),
+            Hasher::Blake3(hasher) => hasher.finalize(),
+        }
+    }
+}
+
+/// Hash the file at `path`, reading it a pooled buffer at a time so files
+/// of any size hash in constant memory.
+pub fn hash_file(path: &Path, algorithm: Algorithm) -> io::Result<[u8; 32]> {
+    let mut file = File::open(path)?;
+    let mut buffer = buffers::take();
+    let mut hasher = Hasher::new(algorithm);
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => return Ok(hasher.finalize()),
+            Ok(read) => hasher.update(&buffer[..read]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Hash data already in memory.
+pub fn hash_bytes(data: &[u8], algorithm: Algorithm) -> [u8; 32] {
+    let mut hasher = Hasher::new(algorithm);
+    hasher.update(data);
+    hasher.finalize()
+}
+
+/// Split a line of a checksum list in the `sha256sum` / `b3sum` format,
+/// `HEX  PATH` (with `*` before the path in binary mode), into the digest
+/// and the path. Blank lines and `#` comments give `None`.
+pub fn parse_line(line: &str) -> Option<Result<(String, String), String>> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    if line.trim().is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let parsed = line
+        .split_once(' ')
+        .filter(|(hex, _)| hex.len() == 64 && hex.bytes().all(|c| c.is_ascii_hexdigit()))
+        .and_then(|(hex, rest)| {
+            let path = rest.strip_prefix(' ').or_else(|| rest.strip_prefix('*'))?;
+            (!path.is_empty()).then(|| (hex.to_ascii_lowercase(), path.to_string()))
+        });
+    Some(parsed.ok_or_else(|| format!("Not a checksum line: '{}'", line)))
+}
@@ -0,0 +1,790 @@
+use std::sync::OnceLock;
+
+/// Length of a private key seed, a public key, and half a signature.
+pub const KEY_LEN: usize = 32;
+
+/// Length of a signature: the point R followed by the scalar S.
+pub const SIGNATURE_LEN: usize = 64;
+
+// Ed25519 signatures (RFC 8032) and X25519 key agreement (RFC 7748).
+//
+// Field elements are kept as five 51-bit limbs. Scalar multiplication does
+// the same work for every bit of the scalar, so signing does not leak the
+// key through which branches are taken. Scalars modulo the group order are
+// reduced over fixed-width signed limbs, as in TweetNaCl, so the secret
+// scalar and nonce never steer a branch or a loop count either.
+
+/// The public key for a 32-byte seed.
+pub fn public_key(seed: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    let (scalar, _) = expand(seed);
+    scalar_mul_base(&scalar).encode()
+}
+
+/// Sign `message` with the key derived from `seed`.
+pub fn sign(seed: &[u8; KEY_LEN], message: &[u8]) -> [u8; SIGNATURE_LEN] {
+    let (scalar, prefix) = expand(seed);
+    let public = scalar_mul_base(&scalar).encode();
+
+    let mut hash = Sha512::new();
+    hash.update(&prefix);
+    hash.update(message);
+    let r = reduce_scalar(&hash.finalize());
+    let big_r = scalar_mul_base(&r).encode();
+
+    // S = r + k * a mod L, with the product taken limb by limb.
+    let k = challenge(&big_r, &public, message);
+    let mut wide = [0i64; 64];
+    for (limb, &byte) in wide.iter_mut().zip(&r) {
+        *limb = i64::from(byte);
+    }
+    for (i, &k_byte) in k.iter().enumerate() {
+        for (j, &a_byte) in scalar.iter().enumerate() {
+            wide[i + j] += i64::from(k_byte) * i64::from(a_byte);
+        }
+    }
+
+    let mut signature = [0u8; SIGNATURE_LEN];
+    signature[..KEY_LEN].copy_from_slice(&big_r);
+    signature[KEY_LEN..].copy_from_slice(&mod_order(&mut wide));
+    signature
+}
+
+/// Whether `signature` is a valid signature of `message` by `public`.
+pub fn verify(public: &[u8; KEY_LEN], message: &[u8], signature: &[u8; SIGNATURE_LEN]) -> bool {
+    let Some(a) = Point::decode(public) else {
+        return false;
+    };
+    let big_r: [u8; KEY_LEN] = signature[..KEY_LEN].try_into().expect("32 bytes");
+    let Some(r) = Point::decode(&big_r) else {
+        return false;
+    };
+    let s: [u8; KEY_LEN] = signature[KEY_LEN..].try_into().expect("32 bytes");
+    // A second valid S for the same R would make signatures malleable.
+    if !below_order(&s) {
+        return false;
+    }
+    let k = challenge(&big_r, public, message);
+
+    let left = scalar_mul_base(&s);
+    let right = scalar_mul(&a, &k).add(&r);
+    left.encode() == right.encode()
+}
+
+/// Split the hash of a seed into the clamped secret scalar and the prefix
+/// used to derive nonces.
+fn expand(seed: &[u8; KEY_LEN]) -> ([u8; KEY_LEN], [u8; KEY_LEN]) {
+    let mut hash = Sha512::new();
+    hash.update(seed);
+    let digest = hash.finalize();
+    let mut scalar: [u8; KEY_LEN] = digest[..KEY_LEN].try_into().expect("32 bytes");
+    scalar[0] &= 248;
+    scalar[31] &= 127;
+    scalar[31] |= 64;
+    (scalar, digest[KEY_LEN..].try_into().expect("32 bytes"))
+}
+
+/// `SHA-512(R || A || M) mod L`.
+fn challenge(big_r: &[u8; KEY_LEN], public: &[u8; KEY_LEN], message: &[u8]) -> [u8; KEY_LEN] {
+    let mut hash = Sha512::new();
+    hash.update(big_r);
+    hash.update(public);
+    hash.update(message);
+    reduce_scalar(&hash.finalize())
+}
+
+/// The order L of the base point, 2^252 + 27742317777372353535851937790883648493,
+/// little-endian.
+const GROUP_ORDER: [u8; KEY_LEN] = [
+    0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde, 0x14, 0, 0, 0, 0, 0,
+    0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+];
+
+/// A 64-byte little-endian number, such as a SHA-512 digest, modulo L.
+fn reduce_scalar(bytes: &[u8; 64]) -> [u8; KEY_LEN] {
+    let mut wide = [0i64; 64];
+    for (limb, &byte) in wide.iter_mut().zip(bytes) {
+        *limb = i64::from(byte);
+    }
+    mod_order(&mut wide)
+}
+
+/// Reduce 64 signed byte-sized limbs modulo L (TweetNaCl's `modL`). The
+/// work and the memory touched do not depend on the value.
+fn mod_order(x: &mut [i64; 64]) -> [u8; KEY_LEN] {
+    let order = GROUP_ORDER.map(i64::from);
+    // Fold the top 32 limbs down using 2^252 = -(L - 2^252) mod L.
+    for i in (32..64).rev() {
+        let mut carry = 0;
+        for j in i - 32..i - 12 {
+            x[j] += carry - 16 * x[i] * order[j - (i - 32)];
+            carry = (x[j] + 128) >> 8;
+            x[j] -= carry << 8;
+        }
+        x[i - 12] += carry;
+        x[i] = 0;
+    }
+    let mut carry = 0;
+    for j in 0..KEY_LEN {
+        x[j] += carry - (x[31] >> 4) * order[j];
+        carry = x[j] >> 8;
+        x[j] &= 255;
+    }
+    for j in 0..KEY_LEN {
+        x[j] -= carry * order[j];
+    }
+    let mut reduced = [0u8; KEY_LEN];
+    for i in 0..KEY_LEN {
+        x[i + 1] += x[i] >> 8;
+        reduced[i] = (x[i] & 255) as u8;
+    }
+    reduced
+}
+
+/// Whether a little-endian scalar is less than L. Only used on public
+/// values, so it may return early.
+fn below_order(scalar: &[u8; KEY_LEN]) -> bool {
+    for (&byte, &limit) in scalar.iter().zip(&GROUP_ORDER).rev() {
+        if byte != limit {
+            return byte < limit;
+        }
+    }
+    false
+}
+
+/// An element of the field of integers modulo p = 2^255 - 19.
+#[derive(Clone, Copy)]
+struct Fe([u64; 5]);
+
+const LIMB_MASK: u64 = (1 << 51) - 1;
+
+impl Fe {
+    const ZERO: Fe = Fe([0; 5]);
+    const ONE: Fe = Fe([1, 0, 0, 0, 0]);
+
+    fn from_u64(value: u64) -> Fe {
+        Fe([value & LIMB_MASK, value >> 51, 0, 0, 0])
+    }
+
+    /// Load 255 little-endian bits; the top bit is ignored.
+    fn from_bytes(bytes: &[u8; 32]) -> Fe {
+        let load = |at: usize| {
+            let mut word = [0u8; 8];
+            let end = (at + 8).min(32);
+            word[..end - at].copy_from_slice(&bytes[at..end]);
+            u64::from_le_bytes(word)
+        };
+        Fe([
+            load(0) & LIMB_MASK,
+            (load(6) >> 3) & LIMB_MASK,
+            (load(12) >> 6) & LIMB_MASK,
+            (load(19) >> 1) & LIMB_MASK,
+            (load(24) >> 12) & LIMB_MASK,
+        ])
+    }
+
+    /// The unique representative below p, as 32 little-endian bytes.
+    fn to_bytes(self) -> [u8; 32] {
+        let mut limbs = self.carry().carry().0;
+        // Add 19 and see whether that carries out of bit 255: if so, the
+        // value was at least p and p must be subtracted.
+        let mut q = (limbs[0] + 19) >> 51;
+        for limb in &limbs[1..] {
+            q = (limb + q) >> 51;
+        }
+        limbs[0] += 19 * q;
+        for i in 0..4 {
+            limbs[i + 1] += limbs[i] >> 51;
+            limbs[i] &= LIMB_MASK;
+        }
+        limbs[4] &= LIMB_MASK;
+
+        let words = [
+            limbs[0] | limbs[1] << 51,
+            limbs[1] >> 13 | limbs[2] << 38,
+            limbs[2] >> 26 | limbs[3] << 25,
+            limbs[3] >> 39 | limbs[4] << 12,
+        ];
+        let mut bytes = [0u8; 32];
+        for (chunk, word) in bytes.chunks_exact_mut(8).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    /// Bring every limb back to about 51 bits.
+    fn carry(self) -> Fe {
+        let mut limbs = self.0;
+        for i in 0..4 {
+            limbs[i + 1] += limbs[i] >> 51;
+            limbs[i] &= LIMB_MASK;
+        }
+        limbs[0] += 19 * (limbs[4] >> 51);
+        limbs[4] &= LIMB_MASK;
+        Fe(limbs)
+    }
+
+    fn add(self, other: Fe) -> Fe {
+        let mut limbs = self.0;
+        for (limb, other) in limbs.iter_mut().zip(other.0) {
+            *limb += other;
+        }
+        Fe(limbs).carry()
+    }
+
+    fn sub(self, other: Fe) -> Fe {
+        // Add 2p first so no limb goes below zero.
+        const TWO_P: [u64; 5] = [
+            0xf_ffff_ffff_ffda,
+            0xf_ffff_ffff_fffe,
+            0xf_ffff_ffff_fffe,
+            0xf_ffff_ffff_fffe,
+            0xf_ffff_ffff_fffe,
+        ];
+        let mut limbs = self.0;
+        for i in 0..5 {
+            limbs[i] = limbs[i] + TWO_P[i] - other.0[i];
+        }
+        Fe(limbs).carry()
+    }
+
+    fn neg(self) -> Fe {
+        Fe::ZERO.sub(self)
+    }
+
+    fn mul(self, other: Fe) -> Fe {
+        let [a0, a1, a2, a3, a4] = self.0.map(u128::from);
+        let [b0, b1, b2, b3, b4] = other.0.map(u128::from);
+        let (b1_19, b2_19, b3_19, b4_19) = (b1 * 19, b2 * 19, b3 * 19, b4 * 19);
+        let r0 = a0 * b0 + a1 * b4_19 + a2 * b3_19 + a3 * b2_19 + a4 * b1_19;
+        let r1 = a0 * b1 + a1 * b0 + a2 * b4_19 + a3 * b3_19 + a4 * b2_19;
+        let r2 = a0 * b2 + a1 * b1 + a2 * b0 + a3 * b4_19 + a4 * b3_19;
+        let r3 = a0 * b3 + a1 * b2 + a2 * b1 + a3 * b0 + a4 * b4_19;
+        let r4 = a0 * b4 + a1 * b3 + a2 * b2 + a3 * b1 + a4 * b0;
+
+        let mask = LIMB_MASK as u128;
+        let r1 = r1 + (r0 >> 51);
+        let r2 = r2 + (r1 >> 51);
+        let r3 = r3 + (r2 >> 51);
+        let r4 = r4 + (r3 >> 51);
+        let mut limbs = [
+            (r0 & mask) as u64,
+            (r1 & mask) as u64,
+            (r2 & mask) as u64,
+            (r3 & mask) as u64,
+            (r4 & mask) as u64,
+        ];
+        limbs[0] += 19 * (r4 >> 51) as u64;
+        limbs[1] += limbs[0] >> 51;
+        limbs[0] &= LIMB_MASK;
+        Fe(limbs)
+    }
+
+    fn square(self) -> Fe {
+        self.mul(self)
+    }
+
+    /// `self^exponent` for a 255-bit little-endian exponent.
+    fn pow(self, exponent: &[u8; 32]) -> Fe {
+        let mut result = Fe::ONE;
+        for bit in (0..255).rev() {
+            result = result.square();
+            if exponent[bit / 8] >> (bit % 8) & 1 == 1 {
+                result = result.mul(self);
+            }
+        }
+        result
+    }
+
+    fn invert(self) -> Fe {
+        // p - 2 = 2^255 - 21.
+        let mut exponent = [0xff; 32];
+        exponent[0] = 0xeb;
+        exponent[31] = 0x7f;
+        self.pow(&exponent)
+    }
+
+    fn is_negative(self) -> bool {
+        self.to_bytes()[0] & 1 == 1
+    }
+
+    fn equals(self, other: Fe) -> bool {
+        self.to_bytes() == other.to_bytes()
+    }
+
+    /// `other` if `choice` is 1, `self` if it is 0, without branching.
+    fn select(self, other: Fe, choice: u64) -> Fe {
+        let mask = choice.wrapping_neg();
+        let mut limbs = self.0;
+        for (limb, other) in limbs.iter_mut().zip(other.0) {
+            *limb ^= mask & (*limb ^ other);
+        }
+        Fe(limbs)
+    }
+}
+
+/// Curve constants that are cheaper to compute once than to write out.
+struct Constants {
+    d: Fe,
+    two_d: Fe,
+    sqrt_minus_one: Fe,
+    base: Point,
+}
+
+fn constants() -> &'static Constants {
+    static CONSTANTS: OnceLock<Constants> = OnceLock::new();
+    CONSTANTS.get_or_init(|| {
+        let d = Fe::from_u64(121_665).neg().mul(Fe::from_u64(121_666).invert());
+        // 2 is not a square modulo p, so 2^((p-1)/4) squares to -1.
+        let mut exponent = [0xff; 32];
+        exponent[0] = 0xfb;
+        exponent[31] = 0x1f;
+        let sqrt_minus_one = Fe::from_u64(2).pow(&exponent);
+        let mut constants = Constants {
+            d,
+            two_d: d.add(d),
+            sqrt_minus_one,
+            base: Point::IDENTITY,
+        };
+        // The base point has y = 4/5 and a positive x.
+        let y = Fe::from_u64(4).mul(Fe::from_u64(5).invert());
+        constants.base = Point::decode_with(&y.to_bytes(), &constants).expect("valid base point");
+        constants
+    })
+}
+
+/// A point on the twisted Edwards curve in extended coordinates:
+/// x = X/Z, y = Y/Z, and x*y = T/Z.
+#[derive(Clone, Copy)]
+struct Point {
+    x: Fe,
+    y: Fe,
+    z: Fe,
+    t: Fe,
+}
+
+impl Point {
+    const IDENTITY: Point = Point {
+        x: Fe::ZERO,
+        y: Fe::ONE,
+        z: Fe::ONE,
+        t: Fe::ZERO,
+    };
+
+    fn decode(bytes: &[u8; 32]) -> Option<Point> {
+        Point::decode_with(bytes, constants())
+    }
+
+    /// Decompress a point (RFC 8032 section 5.1.3). Fails for encodings
+    /// that are not canonical or not on the curve.
+    fn decode_with(bytes: &[u8; 32], constants: &Constants) -> Option<Point> {
+        let y = Fe::from_bytes(bytes);
+        let sign = bytes[31] >> 7;
+        let mut canonical = *bytes;
+        canonical[31] &= 0x7f;
+        if y.to_bytes() != canonical {
+            return None;
+        }
+
+        let y2 = y.square();
+        let u = y2.sub(Fe::ONE);
+        let v = constants.d.mul(y2).add(Fe::ONE);
+        let v3 = v.square().mul(v);
+        let v7 = v3.square().mul(v);
+        // (p - 5) / 8 = 2^252 - 3.
+        let mut exponent = [0xff; 32];
+        exponent[0] = 0xfd;
+        exponent[31] = 0x0f;
+        let mut x = u.mul(v3).mul(u.mul(v7).pow(&exponent));
+
+        let vx2 = v.mul(x.square());
+        if vx2.equals(u.neg()) {
+            x = x.mul(constants.sqrt_minus_one);
+        } else if !vx2.equals(u) {
+            return None;
+        }
+        if x.equals(Fe::ZERO) && sign == 1 {
+            return None;
+        }
+        if x.is_negative() != (sign == 1) {
+            x = x.neg();
+        }
+        Some(Point {
+            x,
+            y,
+            z: Fe::ONE,
+            t: x.mul(y),
+        })
+    }
+
+    fn encode(&self) -> [u8; 32] {
+        let z_inverse = self.z.invert();
+        let x = self.x.mul(z_inverse);
+        let mut bytes = self.y.mul(z_inverse).to_bytes();
+        bytes[31] |= (x.is_negative() as u8) << 7;
+        bytes
+    }
+
+    /// Point addition ("add-2008-hwcd-3"). The formula is complete, so it
+    /// also doubles.
+    fn add(&self, other: &Point) -> Point {
+        let a = self.y.sub(self.x).mul(other.y.sub(other.x));
+        let b = self.y.add(self.x).mul(other.y.add(other.x));
+        let c = self.t.mul(constants().two_d).mul(other.t);
+        let d = self.z.add(self.z).mul(other.z);
+        let (e, f, g, h) = (b.sub(a), d.sub(c), d.add(c), b.add(a));
+        Point {
+            x: e.mul(f),
+            y: g.mul(h),
+            z: f.mul(g),
+            t: e.mul(h),
+        }
+    }
+
+    fn select(&self, other: &Point, choice: u64) -> Point {
+        Point {
+            x: self.x.select(other.x, choice),
+            y: self.y.select(other.y, choice),
+            z: self.z.select(other.z, choice),
+            t: self.t.select(other.t, choice),
+        }
+    }
+}
+
+/// `scalar * point` for a little-endian scalar, doubling and adding for
+/// every bit and keeping the sum only where the bit is set.
+fn scalar_mul(point: &Point, scalar: &[u8; 32]) -> Point {
+    let mut result = Point::IDENTITY;
+    for bit in (0..256).rev() {
+        result = result.add(&result);
+        let sum = result.add(point);
+        result = result.select(&sum, (scalar[bit / 8] >> (bit % 8) & 1) as u64);
+    }
+    result
+}
+
+fn scalar_mul_base(scalar: &[u8; 32]) -> Point {
+    scalar_mul(&constants().base, scalar)
+}
+
+/// X25519 (RFC 7748): `scalar` times the point with Montgomery
+/// u-coordinate `u`, by the constant-time ladder.
+pub fn x25519(scalar: &[u8; KEY_LEN], u: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    let mut scalar = *scalar;
+    scalar[0] &= 248;
+    scalar[31] &= 127;
+    scalar[31] |= 64;
+
+    let x1 = Fe::from_bytes(u);
+    let (mut x2, mut z2, mut x3, mut z3) = (Fe::ONE, Fe::ZERO, x1, Fe::ONE);
+    let mut swap = 0;
+    for bit in (0..255).rev() {
+        let k = (scalar[bit / 8] >> (bit % 8) & 1) as u64;
+        swap ^= k;
+        (x2, x3) = (x2.select(x3, swap), x3.select(x2, swap));
+        (z2, z3) = (z2.select(z3, swap), z3.select(z2, swap));
+        swap = k;
+
+        let a = x2.add(z2);
+        let aa = a.square();
+        let b = x2.sub(z2);
+        let bb = b.square();
+        let e = aa.sub(bb);
+        let da = x3.sub(z3).mul(a);
+        let cb = x3.add(z3).mul(b);
+        x3 = da.add(cb).square();
+        z3 = x1.mul(da.sub(cb).square());
+        x2 = aa.mul(bb);
+        z2 = e.mul(aa.add(Fe::from_u64(121_665).mul(e)));
+    }
+    x2 = x2.select(x3, swap);
+    z2 = z2.select(z3, swap);
+    x2.mul(z2.invert()).to_bytes()
+}
+
+/// The X25519 secret key for an Ed25519 seed, so one key pair can both
+/// sign and receive files. It is the same clamped scalar Ed25519 signs
+/// with, as in libsodium's key conversion; the matching public key is
+/// `public_to_x25519(&public_key(seed))`.
+pub fn seed_to_x25519(seed: &[u8; KEY_LEN]) -> [u8; KEY_LEN] {
+    expand(seed).0
+}
+
+/// The X25519 public key (Montgomery u = (1 + y) / (1 - y)) matching an
+/// Ed25519 public key, or `None` if it is not a valid point.
+pub fn public_to_x25519(public: &[u8; KEY_LEN]) -> Option<[u8; KEY_LEN]> {
+    Point::decode(public)?;
+    let y = Fe::from_bytes(public);
+    Some(Fe::ONE.add(y).mul(Fe::ONE.sub(y).invert()).to_bytes())
+}
+
+/// SHA-512 of `data` in one call.
+pub fn sha512(data: &[u8]) -> [u8; 64] {
+    let mut hash = Sha512::new();
+    hash.update(data);
+    hash.finalize()
+}
+
+/// SHA-512 (FIPS 180-4), fed piece by piece.
+struct Sha512 {
+    state: [u64; 8],
+    buffer: Vec<u8>,
+    len: u128,
+}
+
+const SHA512_K: [u64; 80] = [
+    0x428a2f98d728ae22, 0x7137449123ef65cd, 0xb5c0fbcfec4d3b2f, 0xe9b5dba58189dbbc,
+    0x3956c25bf348b538, 0x59f111f1b605d019, 0x923f82a4af194f9b, 0xab1c5ed5da6d8118,
+    0xd807aa98a3030242, 0x12835b0145706fbe, 0x243185be4ee4b28c, 0x550c7dc3d5ffb4e2,
+    0x72be5d74f27b896f, 0x80deb1fe3b1696b1, 0x9bdc06a725c71235, 0xc19bf174cf692694,
+    0xe49b69c19ef14ad2, 0xefbe4786384f25e3, 0x0fc19dc68b8cd5b5, 0x240ca1cc77ac9c65,
+    0x2de92c6f592b0275, 0x4a7484aa6ea6e483, 0x5cb0a9dcbd41fbd4, 0x76f988da831153b5,
+    0x983e5152ee66dfab, 0xa831c66d2db43210, 0xb00327c898fb213f, 0xbf597fc7beef0ee4,
+    0xc6e00bf33da88fc2, 0xd5a79147930aa725, 0x06ca6351e003826f, 0x142929670a0e6e70,
+    0x27b70a8546d22ffc, 0x2e1b21385c26c926, 0x4d2c6dfc5ac42aed, 0x53380d139d95b3df,
+    0x650a73548baf63de, 0x766a0abb3c77b2a8, 0x81c2c92e47edaee6, 0x92722c851482353b,
+    0xa2bfe8a14cf10364, 0xa81a664bbc423001, 0xc24b8b70d0f89791, 0xc76c51a30654be30,
+    0xd192e819d6ef5218, 0xd69906245565a910, 0xf40e35855771202a, 0x106aa07032bbd1b8,
+    0x19a4c116b8d2d0c8, 0x1e376c085141ab53, 0x2748774cdf8eeb99, 0x34b0bcb5e19b48a8,
+    0x391c0cb3c5c95a63, 0x4ed8aa4ae3418acb, 0x5b9cca4f7763e373, 0x682e6ff3d6b2b8a3,
+    0x748f82ee5defb2fc, 0x78a5636f43172f60, 0x84c87814a1f0ab72, 0x8cc702081a6439ec,
+    0x90befffa23631e28, 0xa4506cebde82bde9, 0xbef9a3f7b2c67915, 0xc67178f2e372532b,
+    0xca273eceea26619c, 0xd186b8c721c0c207, 0xeada7dd6cde0eb1e, 0xf57d4f7fee6ed178,
+    0x06f067aa72176fba, 0x0a637dc5a2c898a6, 0x113f9804bef90dae, 0x1b710b35131c471b,
+    0x28db77f523047d84, 0x32caab7b40c72493, 0x3c9ebe0a15c9bebc, 0x431d67c49c100d4c,
+    0x4cc5d4becb3e42b6, 0x597f299cfc657e2a, 0x5fcb6fab3ad6faec, 0x6c44198c4a475817,
+];
+
+impl Sha512 {
+    fn new() -> Self {
+        Sha512 {
+            state: [
+                0x6a09e667f3bcc908,
+                0xbb67ae8584caa73b,
+                0x3c6ef372fe94f82b,
+                0xa54ff53a5f1d36f1,
+                0x510e527fade682d1,
+                0x9b05688c2b3e6c1f,
+                0x1f83d9abfb41bd6b,
+                0x5be0cd19137e2179,
+            ],
+            buffer: Vec::with_capacity(128),
+            len: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.len += data.len() as u128;
+        if !self.buffer.is_empty() {
+            let take = data.len().min(128 - self.buffer.len());
+            self.buffer.extend_from_slice(&data[..take]);
+            data = &data[take..];
+            if self.buffer.len() < 128 {
+                return;
+            }
+            let block = std::mem::take(&mut self.buffer);
+            self.compress(&block);
+        }
+        let mut blocks = data.chunks_exact(128);
+        for block in &mut blocks {
+            self.compress(block);
+        }
+        self.buffer.extend_from_slice(blocks.remainder());
+    }
+
+    fn finalize(mut self) -> [u8; 64] {
+        let bits = self.len * 8;
+        let mut padding = vec![0x80u8];
+        padding.resize((239 - self.len as usize % 128) % 128 + 1, 0);
+        padding.extend_from_slice(&bits.to_be_bytes());
+        let len = self.len;
+        self.update(&padding);
+        debug_assert!(self.buffer.is_empty() && self.len == len + padding.len() as u128);
+
+        let mut digest = [0u8; 64];
+        for (chunk, word) in digest.chunks_exact_mut(8).zip(self.state) {
+            chunk.copy_from_slice(&word.to_be_bytes());
+        }
+        digest
+    }
+
+    fn compress(&mut self, block: &[u8]) {
+        let mut w = [0u64; 80];
+        for (i, word) in block.chunks_exact(8).enumerate() {
+            w[i] = u64::from_be_bytes(word.try_into().expect("8 bytes"));
+        }
+        for i in 16..80 {
+            let s0 = w[i - 15].rotate_right(1) ^ w[i - 15].rotate_right(8) ^ (w[i - 15] >> 7);
+            let s1 = w[i - 2].rotate_right(19) ^ w[i - 2].rotate_right(61) ^ (w[i - 2] >> 6);
+            w[i] = w[i - 16]
+                .wrapping_add(s0)
+                .wrapping_add(w[i - 7])
+                .wrapping_add(s1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e, mut f, mut g, mut h] = self.state;
+        for (&k, &word) in SHA512_K.iter().zip(&w) {
+            let s1 = e.rotate_right(14) ^ e.rotate_right(18) ^ e.rotate_right(41);
+            let ch = (e & f) ^ (!e & g);
+            let temp1 = h
+                .wrapping_add(s1)
+                .wrapping_add(ch)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            let s0 = a.rotate_right(28) ^ a.rotate_right(34) ^ a.rotate_right(39);
+            let maj = (a & b) ^ (a & c) ^ (b & c);
+            let temp2 = s0.wrapping_add(maj);
+            h = g;
+            g = f;
+            f = e;
+            e = d.wrapping_add(temp1);
+            d = c;
+            c = b;
+            b = a;
+            a = temp1.wrapping_add(temp2);
+        }
+        for (value, add) in self.state.iter_mut().zip([a, b, c, d, e, f, g, h]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::to_hex;
+
+    fn seed() -> [u8; KEY_LEN] {
+        std::array::from_fn(|index| index as u8)
+    }
+
+    fn key(hex: &str) -> [u8; KEY_LEN] {
+        std::array::from_fn(|index| u8::from_str_radix(&hex[2 * index..2 * index + 2], 16).unwrap())
+    }
+
+    fn message() -> Vec<u8> {
+        (0..300).map(|index| (index % 256) as u8).collect()
+    }
+
+    #[test]
+    fn sign_known_answer() {
+        let public = public_key(&seed());
+        assert_eq!(to_hex(&public), "03a107bff3ce10be1d70dd18e74bc09967e4d6309ba50d5f1ddc8664125531b8");
+        let signature = sign(&seed(), &message());
+        assert_eq!(
+            to_hex(&signature),
+            "47399887fe2c5b7d4eb2fa84b3037a447e34711eae0c990552aebf6f41352a66\
+             a81fea0c877cfa1c0a22693148a4c27e40fbc3edf7a0aa441552a90c0fe45705"
+        );
+        assert!(verify(&public, &message(), &signature));
+    }
+
+    #[test]
+    fn verify_refuses_changes() {
+        let public = public_key(&seed());
+        let signature = sign(&seed(), &message());
+        let mut changed = message();
+        changed[150] ^= 1;
+        assert!(!verify(&public, &changed, &signature));
+        assert!(!verify(&public, &message()[..299], &signature));
+        for index in [0, 31, 32, 63] {
+            let mut damaged = signature;
+            damaged[index] ^= 0x10;
+            assert!(!verify(&public, &message(), &damaged), "flipped byte {}", index);
+        }
+        assert!(!verify(&public_key(&[9; KEY_LEN]), &message(), &signature));
+    }
+
+    #[test]
+    fn verify_refuses_a_malleable_scalar() {
+        // S + L verifies the same equation, and must still be refused.
+        let public = public_key(&seed());
+        let signature = sign(&seed(), &message());
+        let mut malleable = signature;
+        let mut carry = 0;
+        for (byte, &limit) in malleable[KEY_LEN..].iter_mut().zip(&GROUP_ORDER) {
+            let sum = u16::from(*byte) + u16::from(limit) + carry;
+            *byte = sum as u8;
+            carry = sum >> 8;
+        }
+        assert!(!verify(&public, &message(), &malleable));
+        assert!(below_order(&signature[KEY_LEN..].try_into().unwrap()));
+        assert!(!below_order(&GROUP_ORDER));
+    }
+
+    #[test]
+    fn verify_refuses_points_off_the_curve() {
+        let signature = sign(&seed(), &message());
+        // y = 2 has no x on the curve.
+        let mut off_curve = [0u8; KEY_LEN];
+        off_curve[0] = 2;
+        assert!(!verify(&off_curve, &message(), &signature));
+        assert_eq!(public_to_x25519(&off_curve), None);
+        let mut bad_r = signature;
+        bad_r[..KEY_LEN].copy_from_slice(&off_curve);
+        assert!(!verify(&public_key(&seed()), &message(), &bad_r));
+    }
+
+    #[test]
+    fn x25519_known_answer() {
+        // RFC 7748, section 5.2.
+        let scalar = key("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4");
+        let u = key("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c");
+        assert_eq!(to_hex(&x25519(&scalar, &u)), "c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552");
+    }
+
+    #[test]
+    fn converted_keys_agree() {
+        let alice = seed();
+        let bob = [7u8; KEY_LEN];
+        let alice_public = public_to_x25519(&public_key(&alice)).unwrap();
+        let bob_public = public_to_x25519(&public_key(&bob)).unwrap();
+        assert_eq!(to_hex(&alice_public), "4701d08488451f545a409fb58ae3e58581ca40ac3f7f114698cd71deac73ca01");
+        let mut base = [0u8; KEY_LEN];
+        base[0] = 9;
+        assert_eq!(x25519(&seed_to_x25519(&alice), &base), alice_public);
+        assert_eq!(
+            x25519(&seed_to_x25519(&alice), &bob_public),
+            x25519(&seed_to_x25519(&bob), &alice_public)
+        );
+    }
+
+    #[test]
+    fn sha512_known_answers() {
+        assert_eq!(
+            to_hex(&sha512(b"")),
+            "cf83e1357eefb8bdf1542850d66d8007d620e4050b5715dc83f4a921d36ce9ce\
+             47d0d13c5d85f2b0ff8318d2877eec2f63b931bd47417a81a538327af927da3e"
+        );
+        assert_eq!(
+            to_hex(&sha512(
+                b"abcdefghbcdefghicdefghijdefghijkefghijklfghijklmghijklmnhijklmnoijklmnopjklmnopqklmnopqrlmnopqrsmnopqrstnopqrstu"
+            )),
+            "8e959b75dae313da8cf4f72814fc143f8f7779c6eb9f7fa17299aeadb6889018\
+             501d289e4900f7e4331b99dec4b5433ac7d329eeb6dd26545e96e55b874be909"
+        );
+    }
+
+    #[test]
+    fn sha512_pieces_match_one_call() {
+        let data: Vec<u8> = (0..200).map(|index| index as u8).collect();
+        let expected = "986058e9895e2c2ab8f9e8cbdf801db12a44842a56a91d5a4e87b1fc98b29372\
+                        2c4664142e42c3c551ff898646268cd92b84ed230b8c94bed7798d4f27cd7465";
+        assert_eq!(to_hex(&sha512(&data)), expected);
+        for piece in [1, 3, 111, 127, 128, 129] {
+            let mut hash = Sha512::new();
+            for part in data.chunks(piece) {
+                hash.update(part);
+            }
+            assert_eq!(to_hex(&hash.finalize()), expected, "pieces of {}", piece);
+        }
+        // Every length around the padding boundary hashes the same either way.
+        for len in 100..=260 {
+            let mut hash = Sha512::new();
+            hash.update(&data[..len.min(200)]);
+            hash.update(&vec![0; len.saturating_sub(200)]);
+            let mut whole = data[..len.min(200)].to_vec();
+            whole.resize(len, 0);
+            assert_eq!(hash.finalize(), sha512(&whole));
+        }
+    }
+}
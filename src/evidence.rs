@@ -0,0 +1,199 @@
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::checksum::{self, SHA256_LEN, to_hex};
+use crate::cpu;
+use crate::format::{self, Cipher};
+use crate::manifest::json_string;
+use crate::policy;
+
+/// One input handled by the run, for the report.
+pub struct Record {
+    pub action: &'static str,
+    pub input: String,
+    pub output: String,
+    /// SHA-256 of the input as it was read, or `None` for a folder or an
+    /// input that could not be read.
+    pub input_sha256: Option<[u8; SHA256_LEN]>,
+    pub started: SystemTime,
+    pub finished: SystemTime,
+    pub error: Option<String>,
+}
+
+/// What an evidence report says about one record, with the output's size
+/// and SHA-256 read back from disk so they describe what was actually
+/// written.
+struct Item<'a> {
+    record: &'a Record,
+    input: String,
+    input_size: Option<u64>,
+    output: String,
+    output_size: Option<u64>,
+    output_sha256: Option<[u8; SHA256_LEN]>,
+}
+
+/// Write an evidence report for chain-of-custody records: `NAME.json` for
+/// other tools and `NAME.txt` to print and sign. Both name the operator,
+/// the machine, the tool version, and the algorithm, and give each input
+/// and output with its SHA-256, size, and the times the work started and
+/// finished.
+pub fn write(name: &str, records: &[Record]) -> Result<(String, String), String> {
+    let created = SystemTime::now();
+    let operator = crate::audit::user_name();
+    let machine = host_name();
+    let tool = format!("File Encryptor {}", env!("CARGO_PKG_VERSION"));
+    let algorithm = algorithm();
+    let items: Vec<Item> = records.iter().map(Item::new).collect();
+
+    let mut json = String::new();
+    json.push_str("{\n");
+    json.push_str(&format!("  \"report\": \"evidence\",\n  \"created\": {},\n", json_string(&timestamp(created))));
+    json.push_str(&format!("  \"operator\": {},\n", json_string(&operator)));
+    json.push_str(&format!("  \"machine\": {},\n", json_string(&machine)));
+    json.push_str(&format!("  \"tool\": {},\n", json_string(&tool)));
+    json.push_str(&format!("  \"algorithm\": {},\n", json_string(&algorithm)));
+    json.push_str("  \"items\": [\n");
+    for (index, item) in items.iter().enumerate() {
+        json.push_str("    ");
+        json.push_str(&item.json());
+        json.push_str(if index + 1 < items.len() { ",\n" } else { "\n" });
+    }
+    json.push_str("  ]\n}\n");
+
+    let mut text = String::new();
+    text.push_str("EVIDENCE REPORT\n===============\n\n");
+    for (label, value) in [
+        ("Report created", timestamp(created)),
+        ("Operator", operator),
+        ("Machine", machine),
+        ("Tool", tool),
+        ("Algorithm", algorithm),
+    ] {
+        text.push_str(&format!("{:<16}{}\n", format!("{}:", label), value));
+    }
+    for (index, item) in items.iter().enumerate() {
+        text.push_str(&format!("\nItem {} of {}\n", index + 1, items.len()));
+        text.push_str(&item.text());
+    }
+    text.push_str("\n\nHanded over by: ______________________________  Date: ______________\n");
+    text.push_str("\nReceived by:    ______________________________  Date: ______________\n");
+
+    let json_path = format!("{}.json", name);
+    let text_path = format!("{}.txt", name);
+    fs::write(&json_path, json).map_err(|e| format!("Failed to write '{}': {}", json_path, e))?;
+    fs::write(&text_path, text).map_err(|e| format!("Failed to write '{}': {}", text_path, e))?;
+    Ok((json_path, text_path))
+}
+
+impl<'a> Item<'a> {
+    fn new(record: &'a Record) -> Self {
+        let absolute = |path: &str| {
+            std::path::absolute(path).map_or_else(|_| path.to_string(), |path| path.display().to_string())
+        };
+        let written = record.error.is_none().then(|| fs::read(&record.output).ok()).flatten();
+        Item {
+            record,
+            input: absolute(&record.input),
+            input_size: fs::metadata(&record.input)
+                .ok()
+                .filter(|info| info.is_file())
+                .map(|info| info.len()),
+            output: absolute(&record.output),
+            output_size: written.as_ref().map(|data| data.len() as u64),
+            output_sha256: written.as_deref().map(checksum::sha256),
+        }
+    }
+
+    fn json(&self) -> String {
+        let hash = |hash: Option<[u8; SHA256_LEN]>| hash.map_or("null".to_string(), |hash| format!("\"{}\"", to_hex(&hash)));
+        let size = |size: Option<u64>| size.map_or("null".to_string(), |size| size.to_string());
+        let mut json = format!(
+            "{{\"action\": {}, \"input\": {}, \"input_size\": {}, \"input_sha256\": {}, \"output\": {}, \"output_size\": {}, \"output_sha256\": {}, \"started\": {}, \"finished\": {}, \"result\": {}",
+            json_string(self.record.action),
+            json_string(&self.input),
+            size(self.input_size),
+            hash(self.record.input_sha256),
+            json_string(&self.output),
+            size(self.output_size),
+            hash(self.output_sha256),
+            json_string(&timestamp(self.record.started)),
+            json_string(&timestamp(self.record.finished)),
+            json_string(if self.record.error.is_none() { "success" } else { "failure" })
+        );
+        if let Some(error) = &self.record.error {
+            json.push_str(&format!(", \"error\": {}", json_string(error)));
+        }
+        json.push('}');
+        json
+    }
+
+    fn text(&self) -> String {
+        let hash = |hash: Option<[u8; SHA256_LEN]>| hash.map_or("-".to_string(), |hash| to_hex(&hash));
+        let size = |size: Option<u64>| size.map_or("-".to_string(), |size| format!("{} bytes", size));
+        let result = match &self.record.error {
+            None => "success".to_string(),
+            Some(error) => format!("failure: {}", error),
+        };
+        let mut text = String::new();
+        for (label, value) in [
+            ("Action", self.record.action.to_string()),
+            ("Input", self.input.clone()),
+            ("Input size", size(self.input_size)),
+            ("Input SHA-256", hash(self.record.input_sha256)),
+            ("Output", self.output.clone()),
+            ("Output size", size(self.output_size)),
+            ("Output SHA-256", hash(self.output_sha256)),
+            ("Started", timestamp(self.record.started)),
+            ("Finished", timestamp(self.record.finished)),
+            ("Result", result),
+        ] {
+            text.push_str(&format!("  {:<16}{}\n", format!("{}:", label), value));
+        }
+        text
+    }
+}
+
+/// How new files are encrypted, in words.
+fn algorithm() -> String {
+    let iterations = format::DEFAULT_ITERATIONS.max(policy::get().min_kdf_iterations);
+    let (cipher, version) = match cpu::cipher_in_use() {
+        Cipher::ChaCha20 => ("ChaCha20 with HMAC-SHA256", format::FORMAT_VERSION),
+        Cipher::AesGcm => ("AES-256-GCM", format::AES_GCM_VERSION),
+    };
+    format!(
+        "{}, keys from PBKDF2-HMAC-SHA256 ({} iterations), format version {}",
+        cipher, iterations, version
+    )
+}
+
+/// `YYYY-MM-DDTHH:MM:SSZ` in UTC.
+fn timestamp(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |elapsed| elapsed.as_secs());
+    format!(
+        "{}:{:02}Z",
+        crate::history::format_date(seconds).replace(' ', "T"),
+        seconds % 60
+    )
+}
+
+/// The name of this machine.
+fn host_name() -> String {
+    #[cfg(unix)]
+    {
+        unsafe extern "C" {
+            fn gethostname(name: *mut u8, len: usize) -> i32;
+        }
+        let mut name = [0u8; 256];
+        // SAFETY: the buffer is valid for its whole length, and the name is
+        // read only up to the first NUL, or to the end if it was cut short.
+        if unsafe { gethostname(name.as_mut_ptr(), name.len()) } == 0 {
+            let len = name.iter().position(|&byte| byte == 0).unwrap_or(name.len());
+            return String::from_utf8_lossy(&name[..len]).into_owned();
+        }
+    }
+    std::env::var("COMPUTERNAME")
+        .or_else(|_| std::env::var("HOSTNAME"))
+        .or_else(|_| fs::read_to_string(Path::new("/etc/hostname")).map(|name| name.trim().to_string()))
+        .unwrap_or_else(|_| "unknown".to_string())
+}
@@ -0,0 +1,393 @@
+use std::fs;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// One include or exclude pattern.
+#[derive(Debug, Clone)]
+struct Rule {
+    pattern: Vec<u8>,
+    /// The pattern contained a `/`, so it matches the whole relative path
+    /// instead of just the file name.
+    anchored: bool,
+    /// The pattern ended in `/`, so it only matches folders.
+    dir_only: bool,
+    include: bool,
+}
+
+/// Gitignore-style rules that decide which entries a folder operation
+/// skips. Rules are checked in order and the last one that matches wins;
+/// entries no rule matches are included.
+///
+/// Files can also be limited by size and modification time. Those limits
+/// never apply to folders, so matching files deeper down are still found.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    rules: Vec<Rule>,
+    /// Skip files smaller than this many bytes.
+    pub min_size: Option<u64>,
+    /// Skip files larger than this many bytes.
+    pub max_size: Option<u64>,
+    /// Skip files last modified before this time.
+    pub newer_than: Option<SystemTime>,
+    /// Skip files last modified after this time.
+    pub older_than: Option<SystemTime>,
+}
+
+impl Filter {
+    /// Add a pattern that skips matching entries.
+    pub fn exclude(&mut self, pattern: &str) {
+        self.push(pattern, false);
+    }
+
+    /// Add a pattern that keeps matching entries even if an earlier rule
+    /// excluded them.
+    pub fn include(&mut self, pattern: &str) {
+        self.push(pattern, true);
+    }
+
+    /// Add one rule per line of a file. Blank lines and `#` comments are
+    /// ignored, and a leading `!` flips a line to the opposite kind of rule,
+    /// as in `.gitignore`.
+    pub fn add_from_file(&mut self, path: &str, include: bool) -> Result<(), String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read pattern file '{}': {}", path, e))?;
+        for line in contents.lines() {
+            let line = line.trim_end();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.strip_prefix('!') {
+                Some(pattern) => self.push(pattern, !include),
+                None => self.push(line, include),
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the entry at `relative` (using `/` separators) should be
+    /// skipped, based on its path and, for files, its size and age.
+    pub fn is_excluded(&self, relative: &[u8], info: &fs::Metadata) -> bool {
+        if info.is_file() && !self.size_and_age_match(info) {
+            return true;
+        }
+        self.path_excluded(relative, info.is_dir())
+    }
+
+    /// Whether a file is inside the size and modification time limits.
+    fn size_and_age_match(&self, info: &fs::Metadata) -> bool {
+        let size = info.len();
+        if self.min_size.is_some_and(|min| size < min)
+            || self.max_size.is_some_and(|max| size > max)
+        {
+            return false;
+        }
+
+        if self.newer_than.is_none() && self.older_than.is_none() {
+            return true;
+        }
+        let Ok(modified) = info.modified() else {
+            return false;
+        };
+        !(self.newer_than.is_some_and(|bound| modified < bound)
+            || self.older_than.is_some_and(|bound| modified > bound))
+    }
+
+    /// Whether the pattern rules skip the entry at `relative`.
+    fn path_excluded(&self, relative: &[u8], is_dir: bool) -> bool {
+        let name = relative
+            .rsplit(|&byte| byte == b'/')
+            .next()
+            .unwrap_or(relative);
+        let mut excluded = false;
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let subject = if rule.anchored { relative } else { name };
+            if glob_match(&rule.pattern, subject) {
+                excluded = !rule.include;
+            }
+        }
+        excluded
+    }
+
+    /// Parse a pattern into a rule and add it.
+    fn push(&mut self, pattern: &str, include: bool) {
+        let mut pattern = pattern.trim();
+        let dir_only = pattern.len() > 1 && pattern.ends_with('/');
+        if dir_only {
+            pattern = &pattern[..pattern.len() - 1];
+        }
+        let anchored = pattern.contains('/');
+        let pattern = pattern.trim_start_matches('/');
+        if pattern.is_empty() {
+            return;
+        }
+        self.rules.push(Rule {
+            pattern: pattern.as_bytes().to_vec(),
+            anchored,
+            dir_only,
+            include,
+        });
+    }
+}
+
+/// Parse a size such as `500`, `64K`, `10M`, or `2G` (powers of 1024).
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let upper = text.to_ascii_uppercase();
+    let number = upper.trim_end_matches(['B', 'I']);
+    let (digits, multiplier) = match number.chars().last() {
+        Some('K') => (&number[..number.len() - 1], 1u64 << 10),
+        Some('M') => (&number[..number.len() - 1], 1 << 20),
+        Some('G') => (&number[..number.len() - 1], 1 << 30),
+        Some('T') => (&number[..number.len() - 1], 1 << 40),
+        _ => (number, 1),
+    };
+    digits
+        .trim()
+        .parse::<u64>()
+        .ok()
+        .and_then(|value| value.checked_mul(multiplier))
+        .ok_or_else(|| {
+            format!(
+                "'{}' is not a valid size (examples: 500, 64K, 10M, 2G).",
+                text
+            )
+        })
+}
+
+/// Parse a point in time given either as an age relative to now, such as
+/// `30m`, `12h`, `1d`, or `2w`, or as a calendar date `YYYY-MM-DD` (midnight
+/// UTC).
+pub fn parse_time(text: &str) -> Result<SystemTime, String> {
+    let text = text.trim();
+    let invalid = || {
+        format!(
+            "'{}' is not a valid age or date (examples: 12h, 1d, 2w, 2024-05-31).",
+            text
+        )
+    };
+
+    if let Some(days) = parse_date(text) {
+        let seconds = u64::try_from(days).map_err(|_| invalid())? * 86_400;
+        return Ok(UNIX_EPOCH + Duration::from_secs(seconds));
+    }
+
+    let unit = text.chars().last().ok_or_else(invalid)?;
+    let seconds_per_unit = match unit {
+        's' => 1,
+        'm' => 60,
+        'h' => 3_600,
+        'd' => 86_400,
+        'w' => 604_800,
+        _ => return Err(invalid()),
+    };
+    let amount: u64 = text[..text.len() - 1].parse().map_err(|_| invalid())?;
+    let age = amount
+        .checked_mul(seconds_per_unit)
+        .map(Duration::from_secs)
+        .ok_or_else(invalid)?;
+    SystemTime::now().checked_sub(age).ok_or_else(invalid)
+}
+
+/// Parse `YYYY-MM-DD` into days since the Unix epoch.
+fn parse_date(text: &str) -> Option<i64> {
+    let mut parts = text.splitn(3, '-');
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month: i64 = parts.next()?.parse().ok()?;
+    let day: i64 = parts.next()?.parse().ok()?;
+    if text.len() != 10 || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Days-from-civil conversion for the proleptic Gregorian calendar.
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    Some(era * 146_097 + day_of_era - 719_468)
+}
+
+/// Match `text` against a glob. `*` and `?` stay within one path segment,
+/// `**` crosses segments (and `**/` may match nothing), and `[...]` matches
+/// a set of characters, with `!` or `^` to negate it.
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some(b'*') if pattern.get(1) == Some(&b'*') => {
+            let mut rest = &pattern[2..];
+            if rest.first() == Some(&b'/') {
+                rest = &rest[1..];
+                // `**/` matches zero or more whole folders.
+                if glob_match(rest, text) {
+                    return true;
+                }
+                return text
+                    .iter()
+                    .enumerate()
+                    .any(|(i, &byte)| byte == b'/' && glob_match(rest, &text[i + 1..]));
+            }
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..]))
+        }
+        Some(b'*') => {
+            let rest = &pattern[1..];
+            for i in 0..=text.len() {
+                if glob_match(rest, &text[i..]) {
+                    return true;
+                }
+                if text.get(i) == Some(&b'/') {
+                    break;
+                }
+            }
+            false
+        }
+        Some(b'?') => match text.first() {
+            Some(&byte) if byte != b'/' => glob_match(&pattern[1..], &text[1..]),
+            _ => false,
+        },
+        Some(b'[') => match (
+            text.first(),
+            class_match(&pattern[1..], text.first().copied()),
+        ) {
+            (Some(_), Some((true, rest))) => glob_match(rest, &text[1..]),
+            _ => false,
+        },
+        Some(b'\\') if pattern.len() > 1 => {
+            text.first() == Some(&pattern[1]) && glob_match(&pattern[2..], &text[1..])
+        }
+        Some(&literal) => text.first() == Some(&literal) && glob_match(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Match one byte against a `[...]` class. `pattern` starts just after the
+/// `[`. Returns whether the byte matched and the pattern after the `]`, or
+/// `None` if the class is not closed.
+fn class_match(pattern: &[u8], byte: Option<u8>) -> Option<(bool, &[u8])> {
+    let byte = byte?;
+    let (negated, mut rest) = match pattern.first() {
+        Some(b'!') | Some(b'^') => (true, &pattern[1..]),
+        _ => (false, pattern),
+    };
+
+    let mut matched = false;
+    let mut first = true;
+    loop {
+        match rest {
+            [] => return None,
+            [b']', tail @ ..] if !first => {
+                rest = tail;
+                break;
+            }
+            [low, b'-', high, tail @ ..] if *high != b']' => {
+                matched |= (*low..=*high).contains(&byte);
+                rest = tail;
+            }
+            [single, tail @ ..] => {
+                matched |= *single == byte;
+                rest = tail;
+            }
+        }
+        first = false;
+    }
+
+    Some((matched != negated && byte != b'/', rest))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(rules: &[(&str, bool)]) -> Filter {
+        let mut filter = Filter::default();
+        for &(pattern, include) in rules {
+            if include {
+                filter.include(pattern);
+            } else {
+                filter.exclude(pattern);
+            }
+        }
+        filter
+    }
+
+    #[test]
+    fn globs() {
+        for (pattern, text) in [
+            ("*.log", "debug.log"),
+            ("?.txt", "a.txt"),
+            ("[abc].txt", "b.txt"),
+            ("[a-c].txt", "c.txt"),
+            ("[!a-c].txt", "d.txt"),
+            ("a/**/b", "a/b"),
+            ("a/**/b", "a/x/y/b"),
+            ("**/b", "b"),
+            ("a/**", "a/x/y"),
+            ("\\*", "*"),
+        ] {
+            assert!(glob_match(pattern.as_bytes(), text.as_bytes()), "{} should match {}", pattern, text);
+        }
+        for (pattern, text) in [
+            ("*.log", "debug.txt"),
+            ("*", "a/b"),
+            ("?", "/"),
+            ("[abc].txt", "d.txt"),
+            ("[!a-c].txt", "a.txt"),
+            ("[abc", "a"),
+            ("a/**/b", "a/xb"),
+            ("\\*", "x"),
+        ] {
+            assert!(!glob_match(pattern.as_bytes(), text.as_bytes()), "{} should not match {}", pattern, text);
+        }
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        let rules = filter(&[("*.log", false), ("keep.log", true)]);
+        assert!(rules.path_excluded(b"dir/debug.log", false));
+        assert!(!rules.path_excluded(b"dir/keep.log", false));
+        assert!(!rules.path_excluded(b"dir/notes.txt", false));
+
+        let rules = filter(&[("keep.log", true), ("*.log", false)]);
+        assert!(rules.path_excluded(b"keep.log", false));
+    }
+
+    #[test]
+    fn anchored_and_folder_only_rules() {
+        let rules = filter(&[("/build", false), ("cache/", false)]);
+        assert!(rules.path_excluded(b"build", true));
+        assert!(!rules.path_excluded(b"src/build", true));
+        assert!(rules.path_excluded(b"src/cache", true));
+        assert!(!rules.path_excluded(b"src/cache", false));
+
+        let rules = filter(&[("docs/*.md", false)]);
+        assert!(rules.path_excluded(b"docs/a.md", false));
+        assert!(!rules.path_excluded(b"a.md", false));
+        assert!(!rules.path_excluded(b"docs/sub/a.md", false));
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("500"), Ok(500));
+        assert_eq!(parse_size("64K"), Ok(64 << 10));
+        assert_eq!(parse_size("10mb"), Ok(10 << 20));
+        assert_eq!(parse_size("2GiB"), Ok(2 << 30));
+        assert_eq!(parse_size(" 1T "), Ok(1 << 40));
+        for bad in ["", "K", "-1", "1.5M", "12X", "99999999999T"] {
+            assert!(parse_size(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn dates_and_ages() {
+        assert_eq!(parse_time("1970-01-01"), Ok(UNIX_EPOCH));
+        assert_eq!(parse_time("2000-03-01"), Ok(UNIX_EPOCH + Duration::from_secs(951_868_800)));
+        assert_eq!(parse_time("2024-02-29"), Ok(UNIX_EPOCH + Duration::from_secs(1_709_164_800)));
+        let day_ago = parse_time("1d").unwrap();
+        let age = SystemTime::now().duration_since(day_ago).unwrap();
+        assert!(age >= Duration::from_secs(86_400) && age < Duration::from_secs(86_500));
+        for bad in ["", "d", "1y", "2024-13-01", "2024-1-1", "1969-12-31", "99999999999999999w"] {
+            assert!(parse_time(bad).is_err(), "{}", bad);
+        }
+    }
+}
@@ -0,0 +1,17 @@
+pub use file_encryptor_core::format::*;
+
+use crate::i18n::t;
+
+/// The message for `error` in the user's language. The core crate only
+/// has the English text, so errors from it are shown through this.
+pub fn message(error: FormatError) -> String {
+    match error {
+        FormatError::Truncated => t!("The file is too short to be a valid encrypted file.").to_string(),
+        FormatError::UnsupportedVersion(version) => t!("Unsupported file format version {}.", version),
+        FormatError::Garbled => t!("Checksum mismatch: the password is wrong or the file is damaged.").to_string(),
+        FormatError::AuthenticationFailed => {
+            t!("Authentication failed: the password is wrong or the file was modified.").to_string()
+        }
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,165 @@
+use std::fs::{self, File, Metadata};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::SystemTime;
+
+use crate::checksum;
+
+/// Whether source files must be left exactly as they were
+/// (`--never-modify-source`).
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Every file read since the last check, with what it looked like then.
+static SOURCES: Mutex<Vec<Source>> = Mutex::new(Vec::new());
+
+struct Source {
+    path: PathBuf,
+    sha256: [u8; 32],
+    state: State,
+}
+
+/// The parts of a file's metadata an operation could disturb.
+#[derive(PartialEq)]
+struct State {
+    len: u64,
+    modified: Option<SystemTime>,
+    permissions: fs::Permissions,
+    /// Device and inode, so a file replaced by another of the same name and
+    /// contents is noticed too.
+    #[cfg(unix)]
+    identity: (u64, u64),
+}
+
+impl State {
+    fn of(info: &Metadata) -> Self {
+        #[cfg(unix)]
+        use std::os::unix::fs::MetadataExt;
+        State {
+            len: info.len(),
+            modified: info.modified().ok(),
+            permissions: info.permissions(),
+            #[cfg(unix)]
+            identity: (info.dev(), info.ino()),
+        }
+    }
+}
+
+/// Leave every file read in this run untouched: inputs are opened without
+/// updating their access time where the system allows, writing over them
+/// and commands that change them in place are refused, and their contents
+/// and metadata are checked again after each operation.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Refuse `command`, which changes its input in place, when the mode is
+/// on.
+pub fn refuse(command: &str, reason: &str) -> Result<(), String> {
+    if enabled() {
+        return Err(format!("--never-modify-source is on, and {} {}.", command, reason));
+    }
+    Ok(())
+}
+
+/// Open `path` for reading. With the mode on, Linux is asked not to
+/// update its access time, which only works for files the user owns; for
+/// others the file is opened normally.
+pub fn open(path: &Path) -> io::Result<File> {
+    #[cfg(target_os = "linux")]
+    if enabled() {
+        use std::os::unix::fs::OpenOptionsExt;
+        const O_NOATIME: i32 = 0o1000000;
+        match fs::OpenOptions::new().read(true).custom_flags(O_NOATIME).open(path) {
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {}
+            result => return result,
+        }
+    }
+    File::open(path)
+}
+
+/// Remember what `path` looked like when `data` was read from it.
+pub fn record(path: &Path, data: &[u8]) {
+    if !enabled() {
+        return;
+    }
+    let Ok(info) = fs::metadata(path) else {
+        return;
+    };
+    let source = Source {
+        path: std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf()),
+        sha256: checksum::sha256(data),
+        state: State::of(&info),
+    };
+    let mut sources = SOURCES.lock().unwrap_or_else(|e| e.into_inner());
+    sources.retain(|known| known.path != source.path);
+    sources.push(source);
+}
+
+/// Fail if writing `output` would replace a file read in this run.
+pub fn check_output(output: &Path) -> io::Result<()> {
+    if !enabled() {
+        return Ok(());
+    }
+    let Ok(info) = fs::metadata(output) else {
+        return Ok(());
+    };
+    let state = State::of(&info);
+    let output = std::path::absolute(output).unwrap_or_else(|_| output.to_path_buf());
+    let sources = SOURCES.lock().unwrap_or_else(|e| e.into_inner());
+    if sources.iter().any(|source| same_file(source, &output, &state)) {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "'{}' is a source file, and --never-modify-source is on",
+                output.display()
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Check that every file read since the last check still has the contents
+/// and metadata it had then, and forget them. Returns how many were
+/// checked.
+pub fn verify() -> Result<usize, String> {
+    let sources = std::mem::take(&mut *SOURCES.lock().unwrap_or_else(|e| e.into_inner()));
+    for source in &sources {
+        let name = source.path.display();
+        let info = fs::metadata(&source.path)
+            .map_err(|e| format!("Source file '{}' can no longer be read: {}", name, e))?;
+        let data = open(&source.path)
+            .and_then(|mut file| {
+                let mut data = Vec::new();
+                io::Read::read_to_end(&mut file, &mut data).map(|_| data)
+            })
+            .map_err(|e| format!("Source file '{}' can no longer be read: {}", name, e))?;
+        if checksum::sha256(&data) != source.sha256 {
+            return Err(format!("Source file '{}' changed: its SHA-256 is not what was read.", name));
+        }
+        if State::of(&info) != source.state {
+            return Err(format!(
+                "The metadata of source file '{}' changed (size, modification time, permissions, or identity).",
+                name
+            ));
+        }
+    }
+    Ok(sources.len())
+}
+
+/// Whether `output` is `source`, through any name or link.
+#[cfg(unix)]
+fn same_file(source: &Source, _output: &Path, state: &State) -> bool {
+    source.state.identity == state.identity
+}
+
+#[cfg(not(unix))]
+fn same_file(source: &Source, output: &Path, _state: &State) -> bool {
+    let canonical = |path: &Path| fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    canonical(&source.path) == canonical(output)
+}
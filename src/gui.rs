@@ -0,0 +1,528 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use crate::archive;
+use crate::audit::Auditor;
+use crate::checksum::{self, to_hex};
+use crate::config::Config;
+use crate::content;
+use crate::crypto;
+use crate::format;
+use crate::history::{self, History};
+use crate::hooks::{HookEvent, Hooks};
+use crate::i18n::t;
+use crate::manifest::json_string;
+use crate::metadata::FileMetadata;
+use crate::notify::Notifier;
+use crate::paths;
+use crate::progress;
+use crate::scratch::Scratch;
+use crate::strip;
+use crate::totp;
+use crate::{CryptoAction, sealing};
+
+/// The page, with its script and styles, served at the secret address.
+const PAGE: &str = include_str!("gui.html");
+
+/// Longest request line plus headers accepted, so a stray client cannot
+/// make the server read without limit before it has shown the token.
+const MAX_HEAD_LEN: usize = 16 * 1024;
+
+/// Largest file accepted, which is about the most a browser will read into
+/// the buffer the page uploads; anything longer is refused unread.
+const MAX_BODY_LEN: usize = 2 << 30;
+
+/// How long one read or write may wait on the browser, since requests are
+/// served one at a time and a client that stops talking would hold up the
+/// rest.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Entries shown in the history pane, newest first.
+const HISTORY_SHOWN: usize = 50;
+
+/// Serve the graphical front end on `127.0.0.1:port` (any free port for
+/// 0) and open it in the default browser unless `open_browser` is off. It
+/// runs until the page's Quit button is pressed.
+///
+/// The page is a web page so it works wherever there is a browser without
+/// any toolkit to install. Every address includes a random token, so other
+/// users of the machine and other web pages cannot use the server, and
+/// files are sent back to the browser to save rather than written by the
+/// server, since a browser never learns where a dropped file came from.
+pub fn serve(config: &Config, port: u16, open_browser: bool) -> Result<(), String> {
+    let listener = TcpListener::bind(("127.0.0.1", port)).map_err(|e| format!("Failed to listen on port {}: {}", port, e))?;
+    let address = listener.local_addr().map_err(|e| e.to_string())?;
+    let mut token = [0u8; 16];
+    crypto::random_bytes(&mut token)?;
+    let token = to_hex(&token);
+    let url = format!("http://{}/{}/", address, token);
+
+    println!("The graphical front end is at {}", url);
+    println!("Keep this window open while you use it; press Quit on the page when done.");
+    let redirect = match open_browser.then(|| launch_browser(&url)) {
+        Some(Ok(scratch)) => Some(scratch),
+        Some(Err(e)) => {
+            eprintln!("Warning: {} Open the address above in a browser instead.", e);
+            None
+        }
+        None => None,
+    };
+
+    let mut server = Server {
+        config,
+        token,
+        history: History::load(config),
+    };
+    for stream in listener.incoming() {
+        let Ok(mut stream) = stream else {
+            continue;
+        };
+        match server.handle(&mut stream) {
+            Ok(Handled::Quit) => break,
+            Ok(Handled::Continue) => {}
+            Err(e) => eprintln!("Warning: a request to the graphical front end failed: {}", e),
+        }
+    }
+    if let Some(scratch) = redirect {
+        scratch.destroy()?;
+    }
+    println!("Closed the graphical front end.");
+    Ok(())
+}
+
+struct Server<'a> {
+    config: &'a Config,
+    token: String,
+    history: History,
+}
+
+enum Handled {
+    Continue,
+    Quit,
+}
+
+/// The parts of an HTTP request the front end looks at.
+struct Request {
+    method: String,
+    path: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    /// The value of header `name`, percent-decoded, since the page encodes
+    /// names and passwords with `encodeURIComponent`.
+    fn header(&self, name: &str) -> String {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map_or_else(String::new, |(_, value)| paths::percent_decode(value))
+    }
+}
+
+/// A file to hand back to the browser.
+struct Download {
+    name: String,
+    data: Vec<u8>,
+    warnings: Vec<String>,
+}
+
+impl Server<'_> {
+    fn handle(&mut self, stream: &mut TcpStream) -> io::Result<Handled> {
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let Some(mut request) = read_head(&mut reader)? else {
+            return respond(stream, "400 Bad Request", "text/plain; charset=utf-8", &[], b"Bad request.");
+        };
+        let Some(route) = request.path.strip_prefix(&format!("/{}/", self.token)) else {
+            return respond(stream, "404 Not Found", "text/plain; charset=utf-8", &[], b"Not found.");
+        };
+        let route = route.to_string();
+
+        let length = request.header("Content-Length").parse::<usize>().unwrap_or(0);
+        if length > MAX_BODY_LEN {
+            let message = format!("The file is larger than the {} GiB the front end accepts.", MAX_BODY_LEN >> 30);
+            return respond(stream, "413 Content Too Large", "text/plain; charset=utf-8", &[], message.as_bytes());
+        }
+        // Read as it arrives rather than allocated up front, so a length
+        // the client never sends costs nothing.
+        reader.by_ref().take(length as u64).read_to_end(&mut request.body)?;
+        if request.body.len() < length {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        match (request.method.as_str(), route.as_str()) {
+            ("GET", "") => respond(stream, "200 OK", "text/html; charset=utf-8", &[], PAGE.as_bytes()),
+            ("GET", "history") => {
+                let json = self.history_json();
+                respond(stream, "200 OK", "application/json", &[], json.as_bytes())
+            }
+            ("POST", "encrypt") | ("POST", "decrypt") => {
+                let action = if route == "encrypt" { CryptoAction::Encrypt } else { CryptoAction::Decrypt };
+                match self.run(action, &request) {
+                    Ok(download) => {
+                        let warnings = download.warnings.join("\n");
+                        let headers = [
+                            ("X-File-Name", percent_encode(&download.name)),
+                            ("X-Warnings", percent_encode(&warnings)),
+                        ];
+                        respond(stream, "200 OK", "application/octet-stream", &headers, &download.data)
+                    }
+                    Err(e) => respond(stream, "400 Bad Request", "text/plain; charset=utf-8", &[], e.as_bytes()),
+                }
+            }
+            ("POST", "quit") => {
+                respond(stream, "200 OK", "text/plain; charset=utf-8", &[], b"Closed.")?;
+                Ok(Handled::Quit)
+            }
+            _ => respond(stream, "404 Not Found", "text/plain; charset=utf-8", &[], b"Not found."),
+        }
+    }
+
+    /// Encrypt or decrypt the uploaded file, then run the hooks, audit, and
+    /// history for it as the menu does.
+    fn run(&mut self, action: CryptoAction, request: &Request) -> Result<Download, String> {
+        let name = request.header("X-File-Name");
+        let name = Path::new(&name)
+            .file_name()
+            .map_or_else(|| "file".to_string(), |name| name.to_string_lossy().into_owned());
+        let password = request.header("X-Password");
+        let started = Instant::now();
+        let result = if password.is_empty() {
+            Err(t!("Password cannot be empty.").to_string())
+        } else {
+            match action {
+                CryptoAction::Encrypt => encrypt(&name, &request.body, &password),
+                CryptoAction::Decrypt => decrypt(&name, &request.body, &password, &request.header("X-Code")),
+            }
+        };
+
+        let output = result.as_ref().map_or_else(|_| String::new(), |(download, _)| download.name.clone());
+        let error = result.as_ref().err().map(String::as_str);
+        let event = HookEvent {
+            action: action.name(),
+            input: &name,
+            output: &output,
+            error,
+            password: Some(&password),
+        };
+        let mut warnings = Vec::new();
+        warnings.extend(Hooks::from_config(self.config).run(&event));
+        warnings.extend(Auditor::from_config(self.config).operation_finished(&event));
+
+        let mut entry = history::Entry::now(action, PathBuf::from(&name), result.is_ok(), "");
+        if let Ok((download, plaintext_sha256)) = &result {
+            entry.output_path = Some(PathBuf::from(&download.name));
+            entry.plaintext_sha256 = Some(*plaintext_sha256);
+        }
+        if let Err(e) = self.history.record(entry) {
+            warnings.push(e);
+        }
+        let verb = match action {
+            CryptoAction::Encrypt => "Encrypted",
+            CryptoAction::Decrypt => "Decrypted",
+        };
+        match &result {
+            Ok((download, _)) => println!("{} '{}' to '{}'.", verb, name, download.name),
+            Err(e) => eprintln!("Failed to {} '{}': {}", action.name(), name, e),
+        }
+        let description = match action {
+            CryptoAction::Encrypt => t!("Encrypting '{}'", name),
+            CryptoAction::Decrypt => t!("Decrypting '{}'", name),
+        };
+        Notifier::from_config(self.config).operation_finished(&description, error, started.elapsed());
+
+        result.map(|(mut download, _)| {
+            download.warnings.extend(warnings);
+            download
+        })
+    }
+
+    /// The newest history entries, for the history pane.
+    fn history_json(&self) -> String {
+        let entries: Vec<String> = self
+            .history
+            .entries()
+            .iter()
+            .rev()
+            .take(HISTORY_SHOWN)
+            .map(|entry| {
+                format!(
+                    "{{\"date\": {}, \"action\": {}, \"file\": {}, \"output\": {}, \"success\": {}}}",
+                    json_string(&history::format_date(entry.timestamp)),
+                    json_string(entry.action.name()),
+                    json_string(&paths::display(&entry.file_path)),
+                    json_string(&entry.output_path.as_deref().map(paths::display).unwrap_or_default()),
+                    entry.success
+                )
+            })
+            .collect();
+        format!("[{}]", entries.join(", "))
+    }
+}
+
+/// Encrypt a dropped file, returning the file to save and the SHA-256 of
+/// what was encrypted.
+fn encrypt(name: &str, data: &[u8], password: &str) -> Result<(Download, [u8; checksum::SHA256_LEN]), String> {
+    if totp::required() && totp::enrolled()?.is_none() {
+        return Err("The first use of --totp shows the authenticator secret in the terminal, so encrypt one file from the menu first.".to_string());
+    }
+    let plaintext_sha256 = checksum::sha256(data);
+    let (data, stripped) = strip::strip_if_enabled(data.to_vec());
+    let encrypted = crate::encrypt_bytes(&data, &FileMetadata::default().to_bytes(), password.as_bytes())?;
+    let mut warnings = Vec::new();
+    if let Some(kind) = stripped {
+        warnings.push(t!("Removed the metadata from this {} file before encrypting it.", kind).to_string());
+    }
+    let download = Download {
+        name: format!("{}.enc", name),
+        data: encrypted,
+        warnings,
+    };
+    Ok((download, plaintext_sha256))
+}
+
+/// Decrypt a dropped file in the current format, checking the
+/// authenticator `code` if the file needs one.
+fn decrypt(name: &str, data: &[u8], password: &str, code: &str) -> Result<(Download, [u8; checksum::SHA256_LEN]), String> {
+    match format::split_header(data).map_err(format::message)? {
+        Some((version, _)) if version >= format::SEALED_VERSION => {}
+        _ => {
+            return Err("Only files in the current format can be opened here; decrypt older files from the menu.".to_string());
+        }
+    }
+    let sealed = format::split_sealed(data).map_err(format::message)?;
+    let keys = progress::deriving_key(sealed.params.iterations, || {
+        sealing::derive_keys(password.as_bytes(), &sealed.params)
+    });
+    let opened = crate::unseal(&sealed, &keys)?;
+    if let Some(secret) = &opened.totp_secret {
+        if code.trim().is_empty() {
+            return Err("This file also needs the 6-digit code from your authenticator app.".to_string());
+        }
+        totp::check_code(secret, code)?;
+    }
+    let plaintext = opened.plaintext;
+    if archive::is_archive(&plaintext) {
+        return Err(t!("This file contains an encrypted folder. Use 'Decrypt folder' to extract it.").to_string());
+    }
+
+    let mut output = name
+        .strip_suffix(".enc")
+        .map_or_else(|| format!("{}.dec", name), str::to_string);
+    if Path::new(&output).extension().is_none()
+        && let Some((_, extension)) = content::detect_extension(&plaintext)
+    {
+        output = format!("{}.{}", output, extension);
+    }
+    let plaintext_sha256 = checksum::sha256(&plaintext);
+    let download = Download {
+        name: output,
+        data: plaintext,
+        warnings: Vec::new(),
+    };
+    Ok((download, plaintext_sha256))
+}
+
+/// Read the request line and headers, leaving the body to the caller.
+/// Returns `None` for anything that is not a well-formed request.
+fn read_head(reader: &mut impl BufRead) -> io::Result<Option<Request>> {
+    let mut read = 0;
+    let mut lines = Vec::new();
+    loop {
+        let mut line = String::new();
+        let len = reader.take((MAX_HEAD_LEN - read) as u64).read_line(&mut line)?;
+        read += len;
+        if len == 0 || read >= MAX_HEAD_LEN {
+            return Ok(None);
+        }
+        let line = line.trim_end_matches(['\r', '\n']).to_string();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+    let Some((request_line, header_lines)) = lines.split_first() else {
+        return Ok(None);
+    };
+    let mut parts = request_line.split(' ');
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return Ok(None);
+    };
+    let path = target.split('?').next().unwrap_or_default().to_string();
+    let headers = header_lines
+        .iter()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+    Ok(Some(Request {
+        method: method.to_string(),
+        path,
+        headers,
+        body: Vec::new(),
+    }))
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    headers: &[(&str, String)],
+    body: &[u8],
+) -> io::Result<Handled> {
+    let mut head = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\nCache-Control: no-store\r\nReferrer-Policy: no-referrer\r\nX-Content-Type-Options: nosniff\r\nContent-Security-Policy: default-src 'none'; script-src 'unsafe-inline'; style-src 'unsafe-inline'; connect-src 'self'\r\n",
+        status,
+        content_type,
+        body.len()
+    );
+    for (name, value) in headers {
+        head.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    head.push_str("\r\n");
+    stream.write_all(head.as_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()?;
+    Ok(Handled::Continue)
+}
+
+/// Encode `text` so it fits in a header, for `decodeURIComponent` to undo.
+fn percent_encode(text: &str) -> String {
+    let mut encoded = String::with_capacity(text.len());
+    for byte in text.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-_.!~*'()".contains(&byte) {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Open `url` in the default browser without waiting for it. Returns the
+/// folder holding the page the browser was pointed at, to be destroyed
+/// once the server stops.
+fn launch_browser(url: &str) -> Result<Scratch, String> {
+    // Other users can read the arguments of a running program, so the
+    // browser is given a page only this user can read, which forwards it
+    // to the address with the token.
+    let scratch = Scratch::create()?;
+    let page = scratch.dir.join("open.html");
+    let forward = format!("<!DOCTYPE html>\n<meta http-equiv=\"refresh\" content=\"0; url={}\">\n", url);
+    match fs::write(&page, forward)
+        .map_err(|e| format!("Failed to write '{}': {}", page.display(), e))
+        .and_then(|()| open_in_browser(&page))
+    {
+        Ok(()) => Ok(scratch),
+        Err(e) => {
+            let _ = scratch.destroy();
+            Err(e)
+        }
+    }
+}
+
+fn open_in_browser(page: &Path) -> Result<(), String> {
+    #[cfg(target_os = "macos")]
+    let mut command = Command::new("open");
+    #[cfg(windows)]
+    let mut command = {
+        use std::os::windows::process::CommandExt;
+        // `start` takes its first quoted argument as a window title.
+        let mut command = Command::new("cmd");
+        command.raw_arg("/C start \"\"");
+        command
+    };
+    #[cfg(not(any(target_os = "macos", windows)))]
+    let mut command = Command::new("xdg-open");
+
+    let program = format!("{:?}", command.get_program());
+    let status = command
+        .arg(page)
+        .status()
+        .map_err(|e| format!("Failed to start {}: {}", program, e))?;
+    if !status.success() {
+        return Err(format!("{} could not open the browser ({}).", program, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send `request` to a server with the token "t0ken", and have it
+    /// handle it. Returns the server's result and the client's end.
+    fn handle_one(request: &[u8]) -> (io::Result<Handled>, TcpStream) {
+        let config = Config {
+            history_file: None,
+            ..Config::default()
+        };
+        let mut server = Server {
+            config: &config,
+            token: "t0ken".to_string(),
+            history: History::load(&config),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(request).unwrap();
+        client.shutdown(std::net::Shutdown::Write).unwrap();
+        let (mut stream, _) = listener.accept().unwrap();
+        (server.handle(&mut stream), client)
+    }
+
+    /// Send `request` to a server with the token "t0ken" and return the
+    /// status line of the response and whether the server quit.
+    fn exchange(request: &[u8]) -> (String, bool) {
+        let (handled, client) = handle_one(request);
+        let quit = matches!(handled.unwrap(), Handled::Quit);
+        let mut response = String::new();
+        BufReader::new(client).read_line(&mut response).unwrap();
+        (response.trim_end().to_string(), quit)
+    }
+
+    #[test]
+    fn requests_need_the_token() {
+        assert_eq!(exchange(b"GET /t0ken/ HTTP/1.1\r\n\r\n"), ("HTTP/1.1 200 OK".to_string(), false));
+        assert_eq!(exchange(b"GET /t0ken/history HTTP/1.1\r\n\r\n").0, "HTTP/1.1 200 OK");
+        assert_eq!(exchange(b"GET / HTTP/1.1\r\n\r\n").0, "HTTP/1.1 404 Not Found");
+        assert_eq!(exchange(b"POST /guess/quit HTTP/1.1\r\n\r\n"), ("HTTP/1.1 404 Not Found".to_string(), false));
+        assert_eq!(exchange(b"GET /t0ken/other HTTP/1.1\r\n\r\n").0, "HTTP/1.1 404 Not Found");
+        assert_eq!(exchange(b"\r\n\r\n").0, "HTTP/1.1 400 Bad Request");
+        let too_large = format!("POST /t0ken/encrypt HTTP/1.1\r\nContent-Length: {}\r\n\r\n", MAX_BODY_LEN + 1);
+        assert_eq!(exchange(too_large.as_bytes()).0, "HTTP/1.1 413 Content Too Large");
+        // A body shorter than its stated length is an error, found without
+        // setting aside room for the length first.
+        let short = format!("POST /t0ken/encrypt HTTP/1.1\r\nContent-Length: {}\r\n\r\nbody", MAX_BODY_LEN);
+        let error = handle_one(short.as_bytes()).0.err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(exchange(b"POST /t0ken/quit HTTP/1.1\r\n\r\n"), ("HTTP/1.1 200 OK".to_string(), true));
+    }
+
+    #[test]
+    fn heads_are_parsed_and_limited() {
+        let mut head: &[u8] = b"POST /t0ken/encrypt?x=1 HTTP/1.1\r\nX-File-Name: a%20b%C3%A9.txt\r\nBroken line\r\n\r\nbody";
+        let request = read_head(&mut head).unwrap().unwrap();
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("POST", "/t0ken/encrypt"));
+        assert_eq!(request.header("x-file-name"), "a bé.txt");
+        assert_eq!(request.header("Missing"), "");
+        assert_eq!(head, b"body");
+
+        let endless = format!("GET / HTTP/1.1\r\nX: {}\r\n\r\n", "a".repeat(MAX_HEAD_LEN));
+        assert!(read_head(&mut endless.as_bytes()).unwrap().is_none());
+        assert!(read_head(&mut &b"GET / HTTP/1.1\r\n"[..]).unwrap().is_none());
+    }
+
+    #[test]
+    fn percent_encoding_round_trips() {
+        for text in ["plain", "with space", "100%", "é/ü\n", ""] {
+            assert_eq!(paths::percent_decode(&percent_encode(text)), text);
+        }
+        assert_eq!(percent_encode("a b"), "a%20b");
+        assert_eq!(paths::percent_decode("%zz%4"), "%zz%4");
+    }
+}
@@ -0,0 +1,489 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::CryptoAction;
+use crate::checksum::{self, SHA256_LEN};
+use crate::config::Config;
+use crate::i18n::t;
+use crate::paths;
+use crate::ui;
+
+/// One recorded operation.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// When the operation finished, in seconds since 1970 UTC.
+    pub timestamp: u64,
+    pub action: CryptoAction,
+    pub file_path: PathBuf,
+    pub success: bool,
+    /// Free-text note the user attached, or empty.
+    pub note: String,
+    /// Tags the user attached, without the leading `#`.
+    pub tags: Vec<String>,
+    /// Where the result was written, if recorded.
+    pub output_path: Option<PathBuf>,
+    /// SHA-256 of the plaintext, for file operations that succeeded.
+    pub plaintext_sha256: Option<[u8; SHA256_LEN]>,
+    /// Whether the input kept changing while it was encrypted, so the
+    /// output is suspect. Only set on successful operations.
+    pub input_changed: bool,
+    /// What was outdated about a decrypted file's encryption, by the short
+    /// names from `migrate::Weakness::code`, so files needing an upgrade
+    /// can be found later.
+    pub outdated: Vec<String>,
+    /// How many times the operation was tried again after a transient
+    /// failure before it finished.
+    pub retries: u32,
+}
+
+/// Fewest characters of a path `print_entry` shows, however narrow the
+/// terminal.
+const MIN_PATH_WIDTH: usize = 12;
+
+/// Entries shown per page when listing history.
+pub const PAGE_SIZE: usize = 20;
+
+/// Which entries to show. Every condition that is set must hold.
+#[derive(Debug, Default)]
+pub struct Filter {
+    pub action: Option<CryptoAction>,
+    /// Only successful (`Some(true)`) or failed (`Some(false)`) operations.
+    pub success: Option<bool>,
+    /// Only operations whose input kept changing while it was encrypted.
+    pub input_changed: bool,
+    /// Only decryptions of files whose encryption was outdated.
+    pub outdated: bool,
+    /// Only operations at or after this time, in seconds since 1970 UTC.
+    pub since: Option<u64>,
+    /// Only operations before this time.
+    pub until: Option<u64>,
+    /// Only paths containing this text, ignoring case.
+    pub path: Option<String>,
+}
+
+impl Filter {
+    /// Parse a filter typed at the menu, such as `decrypt failed since:1w
+    /// reports`. Words are `encrypt`, `decrypt`, `ok`, `failed`,
+    /// `since:WHEN`, and `until:WHEN`; anything else must appear in the path.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut filter = Filter::default();
+        let mut path_words = Vec::new();
+        for word in text.split_whitespace() {
+            match word {
+                "encrypt" => filter.action = Some(CryptoAction::Encrypt),
+                "decrypt" => filter.action = Some(CryptoAction::Decrypt),
+                "ok" => filter.success = Some(true),
+                "failed" => filter.success = Some(false),
+                "changed" => filter.input_changed = true,
+                "outdated" => filter.outdated = true,
+                _ => {
+                    if let Some(when) = word.strip_prefix("since:") {
+                        filter.since = Some(parse_when(when)?);
+                    } else if let Some(when) = word.strip_prefix("until:") {
+                        filter.until = Some(parse_when(when)?);
+                    } else {
+                        path_words.push(word);
+                    }
+                }
+            }
+        }
+        if !path_words.is_empty() {
+            filter.path = Some(path_words.join(" "));
+        }
+        Ok(filter)
+    }
+
+    /// Whether `entry` passes every condition.
+    fn matches(&self, entry: &Entry) -> bool {
+        self.action.is_none_or(|action| entry.action == action)
+            && self.success.is_none_or(|success| entry.success == success)
+            && (!self.input_changed || entry.input_changed)
+            && (!self.outdated || !entry.outdated.is_empty())
+            && self.since.is_none_or(|since| entry.timestamp >= since)
+            && self.until.is_none_or(|until| entry.timestamp < until)
+            && self.path.as_ref().is_none_or(|path| {
+                paths::display(&entry.file_path)
+                    .to_lowercase()
+                    .contains(&path.to_lowercase())
+            })
+    }
+}
+
+/// Operations from this run and earlier ones. Each entry is appended to the
+/// history file as soon as it is recorded, so nothing is lost if the
+/// program is killed.
+pub struct History {
+    path: Option<PathBuf>,
+    entries: Vec<Entry>,
+    /// Index of the first entry recorded in this run.
+    session_start: usize,
+}
+
+impl History {
+    /// Read the history file named in the config. A missing file is an
+    /// empty history, and lines that cannot be read are skipped.
+    pub fn load(config: &Config) -> Self {
+        let entries: Vec<Entry> = config
+            .history_file
+            .as_ref()
+            .and_then(|path| fs::read(path).ok())
+            .map(|contents| {
+                String::from_utf8_lossy(&contents)
+                    .lines()
+                    .filter_map(parse_line)
+                    .collect()
+            })
+            .unwrap_or_default();
+        History {
+            path: config.history_file.clone(),
+            session_start: entries.len(),
+            entries,
+        }
+    }
+
+    /// Add an entry and append it to the history file.
+    pub fn record(&mut self, entry: Entry) -> Result<(), String> {
+        let line = format_line(&entry);
+        self.entries.push(entry);
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let mut options = OpenOptions::new();
+        options.create(true).append(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options
+            .open(path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|e| format!("Failed to save history to '{}': {}", path.display(), e))
+    }
+
+    /// Every entry, oldest first.
+    pub fn entries(&self) -> &[Entry] {
+        &self.entries
+    }
+
+    /// The entries recorded in this run.
+    pub fn session(&self) -> &[Entry] {
+        &self.entries[self.session_start..]
+    }
+
+    /// Entries that pass `filter`, newest first, with their positions in
+    /// the full history.
+    pub fn filter(&self, filter: &Filter) -> Vec<(usize, &Entry)> {
+        self.entries
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, entry)| filter.matches(entry))
+            .collect()
+    }
+
+    /// Entries whose path, note, or tags contain `query`, ignoring case. A
+    /// query starting with `#` only matches that exact tag.
+    pub fn search(&self, query: &str) -> Vec<(usize, &Entry)> {
+        let query = query.to_lowercase();
+        self.entries
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| match query.strip_prefix('#') {
+                Some(tag) => entry.tags.iter().any(|t| t.to_lowercase() == tag),
+                None => {
+                    paths::display(&entry.file_path).to_lowercase().contains(&query)
+                        || entry.note.to_lowercase().contains(&query)
+                        || entry.tags.iter().any(|t| t.to_lowercase().contains(&query))
+                }
+            })
+            .collect()
+    }
+
+    /// Plaintexts that were encrypted successfully to more than one
+    /// output, newest first. Each output is listed once, with the latest
+    /// entry that wrote it.
+    pub fn duplicates(&self) -> Vec<Duplicate<'_>> {
+        let mut groups: Vec<Duplicate> = Vec::new();
+        for entry in self.entries.iter().rev() {
+            let (Some(hash), Some(output)) = (entry.plaintext_sha256, &entry.output_path) else {
+                continue;
+            };
+            if entry.action != CryptoAction::Encrypt || !entry.success {
+                continue;
+            }
+            match groups.iter_mut().find(|group| group.plaintext_sha256 == hash) {
+                Some(group) => {
+                    let output = paths::simplify(output);
+                    if !group.copies.iter().any(|copy| same_output(copy, &output)) {
+                        group.copies.push(entry);
+                    }
+                }
+                None => groups.push(Duplicate {
+                    plaintext_sha256: hash,
+                    copies: vec![entry],
+                }),
+            }
+        }
+        groups.retain(|group| group.copies.len() > 1);
+        groups
+    }
+}
+
+/// Whether `entry` wrote to `output`, however either path was spelled.
+fn same_output(entry: &Entry, output: &Path) -> bool {
+    entry
+        .output_path
+        .as_deref()
+        .is_some_and(|path| paths::simplify(path) == output)
+}
+
+/// One plaintext and the encrypt operations that wrote it to different
+/// outputs, newest first.
+#[derive(Debug)]
+pub struct Duplicate<'a> {
+    pub plaintext_sha256: [u8; SHA256_LEN],
+    pub copies: Vec<&'a Entry>,
+}
+
+impl Entry {
+    /// Create an entry stamped with the current time. `label` is what the
+    /// user typed to describe the operation, as read by `parse_label`.
+    pub fn now(action: CryptoAction, file_path: PathBuf, success: bool, label: &str) -> Self {
+        let (note, tags) = parse_label(label);
+        Entry {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or(0),
+            action,
+            file_path,
+            success,
+            note,
+            tags,
+            output_path: None,
+            plaintext_sha256: None,
+            input_changed: false,
+            outdated: Vec::new(),
+            retries: 0,
+        }
+    }
+}
+
+/// Print one entry, numbered as shown to the user, with its note and tags
+/// on a second line.
+pub fn print_entry(number: usize, entry: &Entry) {
+    let action_str = match entry.action {
+        CryptoAction::Encrypt => t!("Encrypt"),
+        CryptoAction::Decrypt => t!("Decrypt"),
+    };
+    let status_str = if entry.input_changed {
+        t!("Success, but the input changed while it was read")
+    } else if entry.success {
+        t!("Success")
+    } else {
+        t!("Failed")
+    };
+    let prefix = format!("{}. {} [{}] ", number, format_date(entry.timestamp), action_str);
+    let suffix = format!("{} {}", ui::arrow(), status_str);
+    // Long paths are cut in the middle so each entry stays on one line;
+    // on very narrow terminals the path keeps at least a few characters.
+    let room = ui::width().saturating_sub(prefix.chars().count() + suffix.chars().count() + 1);
+    println!(
+        "{}{}{}",
+        prefix,
+        ui::truncate_middle(&paths::display(&entry.file_path), room.max(MIN_PATH_WIDTH)),
+        suffix
+    );
+
+    let mut label = entry.note.clone();
+    for tag in &entry.tags {
+        if !label.is_empty() {
+            label.push(' ');
+        }
+        label.push('#');
+        label.push_str(tag);
+    }
+    if !label.is_empty() {
+        println!("   {}", label);
+    }
+    if !entry.outdated.is_empty() {
+        println!("   {}", t!("Outdated encryption ({}); upgrade with 'migrate'.", entry.outdated.join(", ")));
+    }
+    if entry.retries > 0 {
+        println!("   {}", t!("Tried again {} time(s) after transient failures.", entry.retries));
+    }
+}
+
+/// Parse an age such as `1d` or a date such as `2024-05-31` into seconds
+/// since 1970 UTC.
+pub fn parse_when(text: &str) -> Result<u64, String> {
+    let time = crate::filter::parse_time(text)?;
+    Ok(time
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0))
+}
+
+/// Split what the user typed to describe an operation, such as
+/// `Q3 payroll export #finance #q3`, into the note and the tags.
+pub fn parse_label(text: &str) -> (String, Vec<String>) {
+    let mut words = Vec::new();
+    let mut tags = Vec::new();
+    for word in text.split_whitespace() {
+        match word.strip_prefix('#').filter(|tag| !tag.is_empty()) {
+            // Commas separate tags in the history file, so they split
+            // tags here too.
+            Some(tag) => tags.extend(
+                tag.split(',')
+                    .filter(|tag| !tag.is_empty())
+                    .map(str::to_string),
+            ),
+            _ => words.push(word),
+        }
+    }
+    (words.join(" "), tags)
+}
+
+/// Format a timestamp as `YYYY-MM-DD HH:MM` in UTC.
+pub fn format_date(timestamp: u64) -> String {
+    let (year, month, day) = civil_date(timestamp);
+    let minutes = timestamp % 86_400 / 60;
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        minutes / 60,
+        minutes % 60
+    )
+}
+
+/// The UTC year, month, and day of a timestamp.
+pub fn civil_date(timestamp: u64) -> (i64, i64, i64) {
+    let days = (timestamp / 86_400) as i64;
+
+    // Civil-from-days conversion for the proleptic Gregorian calendar, the
+    // inverse of the one `filter::parse_time` uses.
+    let shifted = days + 719_468;
+    let era = shifted.div_euclid(146_097);
+    let day_of_era = shifted - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+/// Write an entry as one tab-separated line: time, action, status (`ok`,
+/// `failed`, or `changed` for a success whose input changed), path, tags,
+/// note, output path, and plaintext SHA-256 (empty if unknown).
+fn format_line(entry: &Entry) -> String {
+    format!(
+        "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+        entry.timestamp,
+        entry.action.name(),
+        match (entry.success, entry.input_changed) {
+            (true, false) => "ok",
+            (true, true) => "changed",
+            (false, _) => "failed",
+        },
+        paths::encode(&entry.file_path),
+        escape(&entry.tags.join(",")),
+        escape(&entry.note),
+        entry.output_path.as_deref().map(paths::encode).unwrap_or_default(),
+        entry.plaintext_sha256.map(|hash| checksum::to_hex(&hash)).unwrap_or_default(),
+        entry.outdated.join(","),
+        entry.retries
+    )
+}
+
+/// Read a line written by `format_line`. Lines written before the output
+/// path and plaintext hash were recorded end after the note, those from
+/// before outdated encryption was tracked after the hash, and those from
+/// before retries were counted after the outdated list.
+fn parse_line(line: &str) -> Option<Entry> {
+    let mut fields = line.split('\t');
+    let timestamp = fields.next()?.parse().ok()?;
+    let action = CryptoAction::from_name(fields.next()?)?;
+    let (success, input_changed) = match fields.next()? {
+        "ok" => (true, false),
+        "changed" => (true, true),
+        "failed" => (false, false),
+        _ => return None,
+    };
+    let file_path = paths::decode(fields.next()?);
+    let tags = unescape(fields.next()?);
+    let note = unescape(fields.next()?);
+    let output_path = fields.next().filter(|field| !field.is_empty()).map(paths::decode);
+    let plaintext_sha256 = fields.next().and_then(parse_hex);
+    let outdated = fields
+        .next()
+        .map(|field| field.split(',').filter(|code| !code.is_empty()).map(str::to_string).collect())
+        .unwrap_or_default();
+    let retries = fields.next().and_then(|field| field.parse().ok()).unwrap_or(0);
+    Some(Entry {
+        timestamp,
+        action,
+        file_path,
+        success,
+        note,
+        tags: tags
+            .split(',')
+            .filter(|tag| !tag.is_empty())
+            .map(str::to_string)
+            .collect(),
+        output_path,
+        plaintext_sha256,
+        input_changed,
+        outdated,
+        retries,
+    })
+}
+
+/// Read a SHA-256 written by `checksum::to_hex`.
+fn parse_hex(text: &str) -> Option<[u8; SHA256_LEN]> {
+    if text.len() != 2 * SHA256_LEN {
+        return None;
+    }
+    let mut hash = [0u8; SHA256_LEN];
+    for (index, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(text.get(2 * index..2 * index + 2)?, 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// Escape the characters that separate fields and lines.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+/// Undo `escape`.
+fn unescape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
@@ -0,0 +1,187 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+use crate::config::Config;
+
+/// First line of what a hook given the password reads on its standard
+/// input. The number goes up if the layout ever changes.
+const SECRETS_HEADER: &str = "file-encryptor-secrets 1";
+
+/// Details of a finished operation that a hook command can refer to.
+pub struct HookEvent<'a> {
+    /// `encrypt` or `decrypt`.
+    pub action: &'a str,
+    pub input: &'a str,
+    pub output: &'a str,
+    /// The error message if the operation failed.
+    pub error: Option<&'a str>,
+    /// The password the operation used, handed to hooks only when the
+    /// `hook_password` setting is on.
+    pub password: Option<&'a str>,
+}
+
+/// Commands from the config file that run after each operation.
+///
+/// Placeholders `{action}`, `{input}`, `{output}`, `{status}`, and `{error}`
+/// are replaced with shell-quoted values, so paths with spaces or quotes
+/// stay intact. The same values are also available to the command as the
+/// `FILE_ENCRYPTOR_*` environment variables. On Windows no quoting is safe
+/// from cmd, so a placeholder becomes a reference to its variable instead,
+/// expanded only once the command line has been parsed.
+///
+/// With `hook_password` on, the password is written to the command's
+/// standard input instead, never to its arguments or environment, where
+/// other users could see it in the process list:
+///
+/// ```text
+/// file-encryptor-secrets 1
+/// password PASSWORD
+///
+/// ```
+///
+/// The header comes first, then `name value` lines, then an empty line.
+/// Lines with names the hook does not know should be skipped, so more can
+/// be added later. `FILE_ENCRYPTOR_SECRETS=stdin` tells the command to
+/// expect them.
+pub struct Hooks {
+    on_success: Option<String>,
+    on_failure: Option<String>,
+    give_password: bool,
+}
+
+impl Hooks {
+    /// Read the hook commands from the config.
+    pub fn from_config(config: &Config) -> Self {
+        Hooks {
+            on_success: config.hook_on_success.clone(),
+            on_failure: config.hook_on_failure.clone(),
+            give_password: config.hook_password,
+        }
+    }
+
+    /// Run the hook that matches the outcome of `event`, if one is set, and
+    /// wait for it to finish. Returns a warning if the hook could not be
+    /// started or exited unsuccessfully.
+    pub fn run(&self, event: &HookEvent) -> Option<String> {
+        let template = match event.error {
+            None => self.on_success.as_deref()?,
+            Some(_) => self.on_failure.as_deref()?,
+        };
+        let status = if event.error.is_none() {
+            "success"
+        } else {
+            "failure"
+        };
+        let error = event.error.unwrap_or("");
+
+        let command_line = expand_placeholders(
+            template,
+            &[
+                ("action", event.action),
+                ("input", event.input),
+                ("output", event.output),
+                ("status", status),
+                ("error", error),
+            ],
+        );
+
+        let mut command = shell_command(&command_line);
+        command
+            .env("FILE_ENCRYPTOR_ACTION", event.action)
+            .env("FILE_ENCRYPTOR_INPUT", event.input)
+            .env("FILE_ENCRYPTOR_OUTPUT", event.output)
+            .env("FILE_ENCRYPTOR_STATUS", status)
+            .env("FILE_ENCRYPTOR_ERROR", error);
+        if self.give_password {
+            command.env("FILE_ENCRYPTOR_SECRETS", "stdin").stdin(Stdio::piped());
+        }
+        let result = command.spawn().and_then(|mut child| {
+            if let Some(mut stdin) = child.stdin.take() {
+                let mut secrets = format!("{}\n", SECRETS_HEADER).into_bytes();
+                if let Some(password) = event.password {
+                    secrets.extend_from_slice(b"password ");
+                    secrets.extend_from_slice(password.as_bytes());
+                    secrets.push(b'\n');
+                }
+                secrets.push(b'\n');
+                // A hook that never reads them is not an error.
+                let _ = stdin.write_all(&secrets);
+                secrets.fill(0);
+            }
+            child.wait()
+        });
+
+        match result {
+            Ok(exit) if exit.success() => None,
+            Ok(exit) => Some(format!("Hook command exited with {}.", exit)),
+            Err(e) => Some(format!("Failed to run hook command: {}", e)),
+        }
+    }
+}
+
+/// Replace each `{name}` in `template` with its value as `placeholder`
+/// gives it, in a single pass, so text inside a value is never expanded
+/// again.
+fn expand_placeholders(template: &str, values: &[(&str, &str)]) -> String {
+    let mut expanded = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 1..];
+        let known = after.find('}').and_then(|end| {
+            let name = &after[..end];
+            values
+                .iter()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| (end, *value))
+        });
+        match known {
+            Some((end, value)) => {
+                expanded.push_str(&placeholder(&after[..end], value));
+                rest = &after[end + 1..];
+            }
+            None => {
+                expanded.push('{');
+                rest = after;
+            }
+        }
+    }
+    expanded.push_str(rest);
+    expanded
+}
+
+/// Build a command that runs `command_line` through the platform shell.
+#[cfg(unix)]
+fn shell_command(command_line: &str) -> Command {
+    let mut command = Command::new("sh");
+    command.arg("-c").arg(command_line);
+    command
+}
+
+/// cmd is started with delayed expansion (`/V:ON`) for the `!NAME!`
+/// references `placeholder` writes, and is given the command line as it
+/// is, since it does not follow the quoting rules other programs do.
+#[cfg(windows)]
+fn shell_command(command_line: &str) -> Command {
+    use std::os::windows::process::CommandExt;
+    let mut command = Command::new("cmd");
+    command.args(["/D", "/V:ON", "/S", "/C"]).raw_arg(format!("\"{}\"", command_line));
+    command
+}
+
+/// What the placeholder `{name}` for `value` turns into: the value quoted
+/// so the shell passes it through as a single argument.
+#[cfg(unix)]
+fn placeholder(_name: &str, value: &str) -> String {
+    format!("'{}'", value.replace('\'', "'\\''"))
+}
+
+/// On Windows, a reference to the value's `FILE_ENCRYPTOR_*` variable.
+/// cmd expands `%` references and acts on `&`, `|`, `<`, `>`, `^`, and
+/// quotes in a line before running it, and no escaping holds up against
+/// all of them; `!NAME!` is only expanded after that, so nothing in the
+/// value is read as part of the command.
+#[cfg(windows)]
+fn placeholder(name: &str, _value: &str) -> String {
+    format!("\"!FILE_ENCRYPTOR_{}!\"", name.to_ascii_uppercase())
+}
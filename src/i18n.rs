@@ -0,0 +1,389 @@
+use std::collections::HashMap;
+use std::env;
+use std::fmt::Display;
+use std::sync::OnceLock;
+
+/// Languages the interactive prompts can be shown in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Language {
+    English,
+    Spanish,
+}
+
+/// Language picked at startup. Until `init` runs, messages are English.
+static LANGUAGE: OnceLock<Language> = OnceLock::new();
+
+/// Lookup table for the selected catalog, built on first use.
+static CATALOG: OnceLock<HashMap<&'static str, &'static str>> = OnceLock::new();
+
+/// Translate a message, optionally filling its `{}` placeholders in order.
+///
+/// Messages are looked up by their English text, gettext style, so the
+/// English text doubles as the key and as the fallback when a catalog has
+/// no entry.
+macro_rules! t {
+    ($msgid:literal) => {
+        $crate::i18n::translate($msgid)
+    };
+    ($msgid:literal, $($arg:expr),+ $(,)?) => {
+        $crate::i18n::fill($crate::i18n::translate($msgid), &[$(&$arg as &dyn std::fmt::Display),+])
+    };
+}
+pub(crate) use t;
+
+/// Pick the language from the `language` setting if given, otherwise from
+/// the usual locale environment variables.
+pub fn init(setting: Option<&str>) {
+    let from_env = || {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+    };
+    let code = setting.map(str::to_string).or_else(from_env);
+    let language = match code.as_deref().map(str::to_ascii_lowercase) {
+        Some(code) if code.starts_with("es") => Language::Spanish,
+        _ => Language::English,
+    };
+    let _ = LANGUAGE.set(language);
+}
+
+/// Return the translation of `msgid` in the selected language.
+pub fn translate(msgid: &'static str) -> &'static str {
+    let language = LANGUAGE.get().copied().unwrap_or(Language::English);
+    if language == Language::English {
+        return msgid;
+    }
+    let catalog = CATALOG.get_or_init(|| SPANISH.iter().copied().collect());
+    catalog.get(msgid).copied().unwrap_or(msgid)
+}
+
+/// Replace each `{}` in `message` with the next argument.
+pub fn fill(message: &str, args: &[&dyn Display]) -> String {
+    let mut filled = String::with_capacity(message.len());
+    let mut args = args.iter();
+    let mut pieces = message.split("{}");
+    if let Some(first) = pieces.next() {
+        filled.push_str(first);
+    }
+    for piece in pieces {
+        if let Some(arg) = args.next() {
+            filled.push_str(&arg.to_string());
+        }
+        filled.push_str(piece);
+    }
+    filled
+}
+
+/// Spanish catalog, keyed by the English message.
+const SPANISH: &[(&str, &str)] = &[
+    // Startup and menu
+    (
+        "Welcome to the Rust File Encryptor.",
+        "Bienvenido al Cifrador de Archivos en Rust.",
+    ),
+    (
+        "Note: This is a simple learning project and is not meant for real security.",
+        "Nota: este es un proyecto de aprendizaje sencillo y no está pensado para seguridad real.",
+    ),
+    (
+        "================ File Encryptor ================",
+        "============== Cifrador de Archivos ==============",
+    ),
+    ("1) Encrypt file", "1) Cifrar archivo"),
+    ("2) Decrypt file", "2) Descifrar archivo"),
+    ("3) Encrypt folder", "3) Cifrar carpeta"),
+    ("4) Decrypt folder", "4) Descifrar carpeta"),
+    ("5) Preview decryption", "5) Vista previa del descifrado"),
+    ("6) Show history", "6) Mostrar historial"),
+    ("7) Undo last operation", "7) Deshacer la última operación"),
+    ("8) Quit", "8) Salir"),
+    ("Enter your choice: ", "Elija una opción: "),
+    ("Goodbye!", "¡Adiós!"),
+    (
+        "Invalid choice. Please enter a number from 1 to 8.",
+        "Opción no válida. Introduzca un número del 1 al 8.",
+    ),
+    // Prompts
+    ("--- Encrypt File ---", "--- Cifrar archivo ---"),
+    ("--- Decrypt File ---", "--- Descifrar archivo ---"),
+    ("--- Encrypt Folder ---", "--- Cifrar carpeta ---"),
+    ("--- Decrypt Folder ---", "--- Descifrar carpeta ---"),
+    (
+        "--- Preview Decryption ---",
+        "--- Vista previa del descifrado ---",
+    ),
+    ("Enter input file path: ", "Ruta del archivo de entrada: "),
+    ("Enter folder path: ", "Ruta de la carpeta: "),
+    (
+        "Enter input file path (leave blank to list '{}'): ",
+        "Ruta del archivo de entrada (déjela en blanco para ver '{}'): ",
+    ),
+    (
+        "Enter input file path (relative to '{}'): ",
+        "Ruta del archivo de entrada (relativa a '{}'): ",
+    ),
+    (
+        "Enter folder path (relative to '{}'): ",
+        "Ruta de la carpeta (relativa a '{}'): ",
+    ),
+    (
+        "Enter output path (leave blank for '{}'): ",
+        "Ruta de salida (déjela en blanco para usar '{}'): ",
+    ),
+    (
+        "Files to encrypt (such as 1 3 5-7, or all; leave blank to cancel): ",
+        "Archivos a cifrar (por ejemplo 1 3 5-7, o all; déjelo en blanco para cancelar): ",
+    ),
+    (
+        "Enter output file path (leave blank for default .enc): ",
+        "Ruta del archivo de salida (déjela en blanco para usar .enc): ",
+    ),
+    (
+        "Enter output file path (leave blank for default .dec): ",
+        "Ruta del archivo de salida (déjela en blanco para usar .dec): ",
+    ),
+    (
+        "Enter output folder path (leave blank for default .dec): ",
+        "Ruta de la carpeta de salida (déjela en blanco para usar .dec): ",
+    ),
+    (
+        "Patterns to exclude, separated by spaces (leave blank for none): ",
+        "Patrones a excluir, separados por espacios (déjelo en blanco para ninguno): ",
+    ),
+    ("Enter password: ", "Contraseña: "),
+    (
+        "How many kilobytes to preview (leave blank for {}): ",
+        "¿Cuántos kilobytes mostrar? (déjelo en blanco para {}): ",
+    ),
+    (
+        "Please enter a whole number greater than zero.",
+        "Introduzca un número entero mayor que cero.",
+    ),
+    (
+        "Show as (t)ext or (h)ex? [t]: ",
+        "¿Mostrar como (t)exto o (h)exadecimal? [t]: ",
+    ),
+    // Results
+    (
+        "{} source file(s) checked: unchanged.",
+        "{} archivo(s) de origen comprobados: sin cambios.",
+    ),
+    (
+        "Encrypted {} of {} selected file(s).",
+        "Se cifraron {} de {} archivo(s) seleccionados.",
+    ),
+    ("Failed to read folder: {}", "No se pudo leer la carpeta: {}"),
+    (
+        "There are no files directly in this folder. Use 'Encrypt folder' for its subfolders.",
+        "No hay archivos directamente en esta carpeta. Use 'Cifrar carpeta' para sus subcarpetas.",
+    ),
+    (
+        "'{}' is not a number or range from 1 to {}.",
+        "'{}' no es un número ni un intervalo del 1 al {}.",
+    ),
+    (
+        "File encrypted successfully to '{}'.",
+        "Archivo cifrado correctamente en '{}'.",
+    ),
+    (
+        "File decrypted successfully to '{}'.",
+        "Archivo descifrado correctamente en '{}'.",
+    ),
+    (
+        "Folder encrypted successfully to '{}'.",
+        "Carpeta cifrada correctamente en '{}'.",
+    ),
+    (
+        "Folder decrypted successfully to '{}'.",
+        "Carpeta descifrada correctamente en '{}'.",
+    ),
+    ("Encryption failed: {}", "Error al cifrar: {}"),
+    ("Decryption failed: {}", "Error al descifrar: {}"),
+    ("Preview failed: {}", "Error en la vista previa: {}"),
+    ("Undo failed: {}", "No se pudo deshacer: {}"),
+    ("Error: {}", "Error: {}"),
+    ("Warning: {}", "Aviso: {}"),
+    (
+        "Checksum verified: the output matches the original file.",
+        "Suma de verificación correcta: el resultado coincide con el archivo original.",
+    ),
+    (
+        "Note: this file was written by an older version and has no stored checksum, so the output could not be verified.",
+        "Nota: este archivo fue creado por una versión anterior y no tiene suma de verificación, así que el resultado no se pudo comprobar.",
+    ),
+    ("SHA-256 of input:  {}", "SHA-256 de la entrada: {}"),
+    ("SHA-256 of output: {}", "SHA-256 de la salida:  {}"),
+    (
+        "(Showing the first {} byte(s).)",
+        "(Se muestran los primeros {} byte(s).)",
+    ),
+    (
+        "{} file(s), {} folder(s), {} hard link(s), {} symlink(s).",
+        "{} archivo(s), {} carpeta(s), {} enlace(s) duro(s), {} enlace(s) simbólico(s).",
+    ),
+    (
+        "{} entr(ies) skipped by the filters.",
+        "{} elemento(s) omitido(s) por los filtros.",
+    ),
+    (
+        "Removed the metadata from {} file(s) before encrypting them.",
+        "Se quitaron los metadatos de {} archivo(s) antes de cifrarlos.",
+    ),
+    (
+        "Removed the metadata from this {} file before encrypting it.",
+        "Se quitaron los metadatos de este archivo {} antes de cifrarlo.",
+    ),
+    (
+        "Write it anyway? [y/N]: ",
+        "¿Escribirlo de todos modos? [s/N]: ",
+    ),
+    (
+        "The output looks like {}. Save it as '{}' instead? [Y/n]: ",
+        "El resultado parece ser {}. ¿Guardarlo como '{}'? [S/n]: ",
+    ),
+    (
+        "Nothing was written because the output looked wrong.",
+        "No se escribió nada porque el resultado parecía incorrecto.",
+    ),
+    ("The operation was cancelled.", "La operación se canceló."),
+    ("Encrypting '{}'", "Cifrando '{}'"),
+    ("Decrypting '{}'", "Descifrando '{}'"),
+    // History
+    (
+        "No history yet. Try encrypting or decrypting a file first.",
+        "Aún no hay historial. Cifre o descifre un archivo primero.",
+    ),
+    ("--- History ---", "--- Historial ---"),
+    (
+        "Filter with encrypt, decrypt, ok, failed, changed, outdated, since:WHEN, until:WHEN, or text in the path.",
+        "Filtre con encrypt, decrypt, ok, failed, changed, outdated, since:CUÁNDO, until:CUÁNDO o texto de la ruta.",
+    ),
+    (
+        "Filter (leave blank for all): ",
+        "Filtro (deje en blanco para todo): ",
+    ),
+    ("No entries match the filter.", "Ninguna entrada coincide con el filtro."),
+    (
+        "Page {} of {}. Press Enter for more or q to stop: ",
+        "Página {} de {}. Pulse Intro para ver más o q para parar: ",
+    ),
+    (
+        "Note or #tags for the history (leave blank for none): ",
+        "Nota o #etiquetas para el historial (deje en blanco para ninguna): ",
+    ),
+    ("Encrypt", "Cifrar"),
+    ("Decrypt", "Descifrar"),
+    ("Success", "Correcto"),
+    ("Failed", "Fallido"),
+    (
+        "{} file(s) kept changing while they were read.",
+        "{} archivo(s) siguieron cambiando mientras se leían.",
+    ),
+    (
+        "Success, but the input changed while it was read",
+        "Correcto, pero la entrada cambió mientras se leía",
+    ),
+    (
+        "'{}' kept changing while it was read, so its encrypted copy may mix old and new contents. Encrypt it again once it is no longer being written to.",
+        "'{}' siguió cambiando mientras se leía, así que su copia cifrada puede mezclar contenido antiguo y nuevo. Vuelva a cifrarlo cuando ya no se esté escribiendo.",
+    ),
+    ("Summary this session:", "Resumen de esta sesión:"),
+    ("Encrypted: {} file(s)", "Cifrados: {} archivo(s)"),
+    ("Decrypted: {} file(s)", "Descifrados: {} archivo(s)"),
+    // Errors
+    (
+        "Failed to read input file: {}",
+        "No se pudo leer el archivo de entrada: {}",
+    ),
+    (
+        "Failed to write output file: {}",
+        "No se pudo escribir el archivo de salida: {}",
+    ),
+    (
+        "Password cannot be empty.",
+        "La contraseña no puede estar vacía.",
+    ),
+    (
+        "The file is too short to be a valid encrypted file.",
+        "El archivo es demasiado corto para ser un archivo cifrado válido.",
+    ),
+    (
+        "Checksum mismatch: the password is wrong or the file is damaged.",
+        "La suma de verificación no coincide: la contraseña es incorrecta o el archivo está dañado.",
+    ),
+    (
+        "Authentication failed: the password is wrong or the file was modified.",
+        "Error de autenticación: la contraseña es incorrecta o el archivo fue modificado.",
+    ),
+    (
+        "This file uses the old XOR cipher, which is not secure. Run with --legacy-xor to decrypt it, or upgrade it with 'migrate --legacy-xor FILE'.",
+        "Este archivo usa el antiguo cifrado XOR, que no es seguro. Ejecute con --legacy-xor para descifrarlo, o actualícelo con 'migrate --legacy-xor ARCHIVO'.",
+    ),
+    ("This file uses outdated encryption:", "Este archivo usa un cifrado anticuado:"),
+    (
+        "cipher: XOR ({}), which gives no real protection; anyone who had a copy may have read or changed it",
+        "cifrado: XOR ({}), que no ofrece protección real; cualquiera que tuviera una copia pudo leerlo o modificarlo",
+    ),
+    (
+        "key derivation: {} PBKDF2 iterations, fewer than the {} new files get, so the password is quicker to guess",
+        "derivación de clave: {} iteraciones de PBKDF2, menos que las {} de los archivos nuevos, así que la contraseña se adivina antes",
+    ),
+    (
+        "recipients: not encrypted for every recipient the organization policy requires",
+        "destinatarios: no está cifrado para todos los destinatarios que exige la política de la organización",
+    ),
+    ("Upgrade it with '{}'.", "Actualícelo con '{}'."),
+    ("Migrate it to the current format now? [y/N]: ", "¿Migrarlo ahora al formato actual? [s/N]: "),
+    ("Migrated '{}' to format version {}.", "'{}' migrado a la versión {} del formato."),
+    ("Migration failed: {}", "Error al migrar: {}"),
+    ("Migration failed: the path is not valid Unicode.", "Error al migrar: la ruta no es Unicode válido."),
+    (
+        "Outdated encryption ({}); upgrade with 'migrate'.",
+        "Cifrado anticuado ({}); actualícelo con 'migrate'.",
+    ),
+    (
+        "Tried again {} time(s) after transient failures.",
+        "Reintentado {} vez/veces tras fallos pasajeros.",
+    ),
+    (
+        "WARNING: --legacy-xor is on. Files from the old XOR versions can be decrypted, but their contents were never really protected. New files are always written in the current format.",
+        "AVISO: --legacy-xor está activado. Los archivos de las antiguas versiones XOR se pueden descifrar, pero su contenido nunca estuvo realmente protegido. Los archivos nuevos siempre se escriben en el formato actual.",
+    ),
+    (
+        "Unsupported file format version {}.",
+        "Versión de formato de archivo no compatible: {}.",
+    ),
+    (
+        "This file contains an encrypted folder. Use 'Decrypt folder' to extract it.",
+        "Este archivo contiene una carpeta cifrada. Use 'Descifrar carpeta' para extraerla.",
+    ),
+    (
+        "This file contains a single file, not a folder. Use 'Decrypt file' instead.",
+        "Este archivo contiene un solo archivo, no una carpeta. Use 'Descifrar archivo'.",
+    ),
+    (
+        "Add this secret to your authenticator app:",
+        "Añada este secreto a su aplicación de autenticación:",
+    ),
+    (
+        "Enter the code it shows to confirm: ",
+        "Introduzca el código que muestra para confirmar: ",
+    ),
+    (
+        "The code did not match, so nothing was encrypted. Check the secret and your clock and try again.",
+        "El código no coincide, así que no se cifró nada. Compruebe el secreto y el reloj e inténtelo de nuevo.",
+    ),
+    (
+        "Keep a copy of this secret. Files encrypted with --totp cannot be opened without it; it is kept in '{}'.",
+        "Guarde una copia de este secreto. Los archivos cifrados con --totp no se pueden abrir sin él; se guarda en '{}'.",
+    ),
+    (
+        "Enter the 6-digit code from your authenticator app: ",
+        "Introduzca el código de 6 dígitos de su aplicación de autenticación: ",
+    ),
+    (
+        "The authenticator code is wrong or has expired.",
+        "El código de autenticación es incorrecto o ha caducado.",
+    ),
+];
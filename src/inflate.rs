@@ -0,0 +1,448 @@
+use crate::checksum;
+
+/// Longest Huffman code in a deflate stream.
+const MAX_BITS: usize = 15;
+
+/// Base lengths of length codes 257 to 285, and how many extra bits follow.
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+
+/// Base distances of distance codes 0 to 29, and how many extra bits follow.
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145,
+    8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13];
+
+/// Order the code length code lengths are stored in, in a dynamic block.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+fn damaged() -> String {
+    "The compressed data is damaged.".to_string()
+}
+
+/// Bits read from the least significant end of each byte, as deflate
+/// stores them.
+struct Bits<'a> {
+    data: &'a [u8],
+    position: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn take(&mut self, wanted: u32) -> Result<u32, String> {
+        while self.count < wanted {
+            let byte = *self.data.get(self.position).ok_or_else(damaged)?;
+            self.position += 1;
+            self.buffer |= (byte as u32) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1u64 << wanted) - 1) as u32;
+        self.buffer >>= wanted;
+        self.count -= wanted;
+        Ok(value)
+    }
+
+    /// Drop the bits left in the current byte, before a stored block.
+    fn align(&mut self) {
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical Huffman code: how many codes there are of each length, and
+/// the symbols in code order.
+struct Huffman {
+    counts: [u16; MAX_BITS + 1],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// Build the code from the length of each symbol's code (0 for unused
+    /// symbols). Codes that cannot be decoded are refused; incomplete ones
+    /// are allowed, as deflate does for a single distance code.
+    fn new(lengths: &[u8]) -> Result<Self, String> {
+        let mut counts = [0u16; MAX_BITS + 1];
+        for &length in lengths {
+            counts[length as usize] += 1;
+        }
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = left * 2 - count as i32;
+            if left < 0 {
+                return Err(damaged());
+            }
+        }
+        let mut offsets = [0u16; MAX_BITS + 2];
+        for length in 1..=MAX_BITS {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0u16; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(Huffman { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+        // Codes are read a bit at a time, most significant first.
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for length in 1..=MAX_BITS {
+            code |= bits.take(1)? as i32;
+            let count = self.counts[length] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(damaged())
+    }
+}
+
+/// Decompress a raw deflate stream (RFC 1951), as found in zip entries.
+/// Returns the data and how many bytes of `data` the stream took. Output
+/// beyond `limit` bytes is refused, so a small archive cannot expand into
+/// more than memory can hold.
+pub fn inflate(data: &[u8], limit: u64) -> Result<(Vec<u8>, usize), String> {
+    let mut bits = Bits {
+        data,
+        position: 0,
+        buffer: 0,
+        count: 0,
+    };
+    let mut out = Vec::new();
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => {
+                bits.align();
+                let start = bits.position;
+                let header = data.get(start..start + 4).ok_or_else(damaged)?;
+                let len = u16::from_le_bytes([header[0], header[1]]);
+                let complement = u16::from_le_bytes([header[2], header[3]]);
+                if len != !complement {
+                    return Err(damaged());
+                }
+                let stored = data.get(start + 4..start + 4 + len as usize).ok_or_else(damaged)?;
+                out.extend_from_slice(stored);
+                bits.position = start + 4 + len as usize;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].fill(8);
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                lengths[280..].fill(8);
+                let literals = Huffman::new(&lengths)?;
+                let distances = Huffman::new(&[5; 30])?;
+                inflate_block(&mut bits, &mut out, &literals, &distances, limit)?;
+            }
+            2 => {
+                let (literals, distances) = read_dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, &mut out, &literals, &distances, limit)?;
+            }
+            _ => return Err(damaged()),
+        }
+        if out.len() as u64 > limit {
+            return Err("The compressed data expands to more than expected.".to_string());
+        }
+        if last {
+            return Ok((out, bits.position));
+        }
+    }
+}
+
+fn read_dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+    let literal_count = bits.take(5)? as usize + 257;
+    let distance_count = bits.take(5)? as usize + 1;
+    let code_length_count = bits.take(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        return Err(damaged());
+    }
+    let mut code_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[symbol] = bits.take(3)? as u8;
+    }
+    let code_lengths = Huffman::new(&code_lengths)?;
+
+    let mut lengths = vec![0u8; literal_count + distance_count];
+    let mut index = 0;
+    while index < lengths.len() {
+        let symbol = code_lengths.decode(bits)?;
+        let (value, repeat) = match symbol {
+            0..=15 => (symbol as u8, 1),
+            16 => (*lengths[..index].last().ok_or_else(damaged)?, 3 + bits.take(2)? as usize),
+            17 => (0, 3 + bits.take(3)? as usize),
+            _ => (0, 11 + bits.take(7)? as usize),
+        };
+        let end = index + repeat;
+        lengths.get_mut(index..end).ok_or_else(damaged)?.fill(value);
+        index = end;
+    }
+    // A block without an end-of-block code could never finish.
+    if lengths[256] == 0 {
+        return Err(damaged());
+    }
+    let (literals, distances) = lengths.split_at(literal_count);
+    Ok((Huffman::new(literals)?, Huffman::new(distances)?))
+}
+
+fn inflate_block(
+    bits: &mut Bits,
+    out: &mut Vec<u8>,
+    literals: &Huffman,
+    distances: &Huffman,
+    limit: u64,
+) -> Result<(), String> {
+    loop {
+        let symbol = literals.decode(bits)? as usize;
+        match symbol {
+            0..=255 => out.push(symbol as u8),
+            256 => return Ok(()),
+            _ => {
+                let code = symbol - 257;
+                if code >= LENGTH_BASE.len() {
+                    return Err(damaged());
+                }
+                let length = LENGTH_BASE[code] as usize + bits.take(LENGTH_EXTRA[code] as u32)? as usize;
+                let code = distances.decode(bits)? as usize;
+                if code >= DISTANCE_BASE.len() {
+                    return Err(damaged());
+                }
+                let distance = DISTANCE_BASE[code] as usize + bits.take(DISTANCE_EXTRA[code] as u32)? as usize;
+                if distance > out.len() {
+                    return Err(damaged());
+                }
+                // Byte by byte, since a match may overlap what it copies.
+                let start = out.len() - distance;
+                for offset in 0..length {
+                    out.push(out[start + offset]);
+                }
+                if out.len() as u64 > limit {
+                    return Err("The compressed data expands to more than expected.".to_string());
+                }
+            }
+        }
+    }
+}
+
+/// Decompress a gzip file (RFC 1952), all its members one after another,
+/// checking each one's CRC-32 and length.
+pub fn gunzip(data: &[u8], limit: u64) -> Result<Vec<u8>, String> {
+    const FHCRC: u8 = 0x02;
+    const FEXTRA: u8 = 0x04;
+    const FNAME: u8 = 0x08;
+    const FCOMMENT: u8 = 0x10;
+
+    let mut out = Vec::new();
+    let mut rest = data;
+    while !rest.is_empty() {
+        if rest.len() < 18 || rest[..3] != [0x1f, 0x8b, 8] {
+            return Err("Not a gzip file.".to_string());
+        }
+        let flags = rest[3];
+        let mut position = 10;
+        if flags & FEXTRA != 0 {
+            let len = rest.get(position..position + 2).ok_or_else(damaged)?;
+            position += 2 + u16::from_le_bytes([len[0], len[1]]) as usize;
+        }
+        for flag in [FNAME, FCOMMENT] {
+            if flags & flag != 0 {
+                let end = rest.get(position..).ok_or_else(damaged)?.iter().position(|&byte| byte == 0);
+                position += end.ok_or_else(damaged)? + 1;
+            }
+        }
+        if flags & FHCRC != 0 {
+            position += 2;
+        }
+        let stream = rest.get(position..).ok_or_else(damaged)?;
+        let (member, used) = inflate(stream, limit.saturating_sub(out.len() as u64))?;
+        let trailer = stream.get(used..used + 8).ok_or_else(damaged)?;
+        let crc = u32::from_le_bytes(trailer[..4].try_into().expect("four bytes"));
+        let len = u32::from_le_bytes(trailer[4..].try_into().expect("four bytes"));
+        if checksum::crc32(&member) != crc || member.len() as u32 != len {
+            return Err(damaged());
+        }
+        out.extend_from_slice(&member);
+        rest = &stream[used + 8..];
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|index| u8::from_str_radix(&hex[index..index + 2], 16).unwrap()).collect()
+    }
+
+    /// "hello hello hello hello, deflate!" with the fixed codes, from zlib.
+    const FIXED: &str = "cb48cdc9c957c8402775145252d372124b521501";
+
+    /// `dynamic_text()` with codes of its own, from zlib.
+    const DYNAMIC: &str = "8dd4310ec2301044d19e53ec11d81908701c13b088e2381505b7475c80effe574fa3694b7fc631f61a251e9f5eb6658ed7bbd6adf4b8b77d5e0fed572416c2c2589cb03863316171c1e28ac58dc5065059359935d935193659369936d93619375957acab81cdb2ae5857ac2bd615eb8a75c5ba625db3ae59d70397c0ba665db3ae59d7ac6bd6f55fdd2f";
+
+    /// "gzip member" as gzip writes it.
+    const GZIP: &str = "1f8b08000000000002034bafca2c50c84dcd4d4a2d02002ee308a10b000000";
+
+    fn dynamic_text() -> Vec<u8> {
+        (0..40).flat_map(|line| format!("line {} of a dynamic huffman block\n", line).into_bytes()).collect()
+    }
+
+    /// Writes a deflate stream by hand, for streams no compressor makes.
+    #[derive(Default)]
+    struct Writer {
+        out: Vec<u8>,
+        count: u32,
+    }
+
+    impl Writer {
+        fn bits(&mut self, value: u32, count: u32) {
+            for bit in 0..count {
+                if self.count.is_multiple_of(8) {
+                    self.out.push(0);
+                }
+                *self.out.last_mut().unwrap() |= ((value >> bit & 1) as u8) << (self.count % 8);
+                self.count += 1;
+            }
+        }
+
+        /// A Huffman code, which goes most significant bit first.
+        fn code(&mut self, code: u32, len: u32) {
+            for bit in (0..len).rev() {
+                self.bits(code >> bit & 1, 1);
+            }
+        }
+
+        /// Symbol `symbol` of the fixed literal/length code.
+        fn fixed(&mut self, symbol: u32) {
+            match symbol {
+                0..=143 => self.code(0x30 + symbol, 8),
+                144..=255 => self.code(0x190 + symbol - 144, 9),
+                256..=279 => self.code(symbol - 256, 7),
+                _ => self.code(0xc0 + symbol - 280, 8),
+            }
+        }
+    }
+
+    #[test]
+    fn known_streams() {
+        let fixed = bytes(FIXED);
+        assert_eq!(inflate(&fixed, 1000).unwrap(), (b"hello hello hello hello, deflate!".to_vec(), fixed.len()));
+        let dynamic = bytes(DYNAMIC);
+        assert_eq!(inflate(&dynamic, 10_000).unwrap(), (dynamic_text(), dynamic.len()));
+        // Whatever follows the stream is left for the caller.
+        let mut followed = fixed.clone();
+        followed.extend_from_slice(b"trailer");
+        assert_eq!(inflate(&followed, 1000).unwrap().1, fixed.len());
+    }
+
+    #[test]
+    fn stored_blocks() {
+        let mut stream = vec![0x00, 3, 0, !3, 0xff];
+        stream.extend_from_slice(b"abc");
+        stream.extend_from_slice(&[0x01, 0, 0, 0xff, 0xff]);
+        assert_eq!(inflate(&stream, 100).unwrap(), (b"abc".to_vec(), stream.len()));
+
+        let mut mismatch = stream.clone();
+        mismatch[3] ^= 1;
+        assert!(inflate(&mismatch, 100).is_err());
+        // A length running past the end of the input.
+        assert!(inflate(&[0x01, 10, 0, !10, 0xff, b'a'], 100).is_err());
+    }
+
+    #[test]
+    fn truncated_streams_are_refused() {
+        for stream in [bytes(FIXED), bytes(DYNAMIC)] {
+            for len in 0..stream.len() {
+                assert!(inflate(&stream[..len], 10_000).is_err(), "cut to {} bytes", len);
+            }
+        }
+    }
+
+    #[test]
+    fn hostile_streams_are_refused() {
+        // Block type 3 does not exist.
+        assert!(inflate(&[0x07], 100).is_err());
+
+        // A match before anything has been written.
+        let mut early = Writer::default();
+        early.bits(1, 1);
+        early.bits(1, 2);
+        early.fixed(257);
+        early.code(0, 5);
+        early.fixed(256);
+        assert!(inflate(&early.out, 100).is_err());
+
+        // Length symbols 286 and 287 are in the fixed code but mean nothing.
+        let mut unused = Writer::default();
+        unused.bits(1, 1);
+        unused.bits(1, 2);
+        unused.fixed(b'a' as u32);
+        unused.fixed(286);
+        assert!(inflate(&unused.out, 100).is_err());
+
+        // Code lengths that claim more codes than there is room for.
+        assert!(Huffman::new(&[1, 1, 1]).is_err());
+    }
+
+    #[test]
+    fn output_is_limited() {
+        let dynamic = bytes(DYNAMIC);
+        let len = dynamic_text().len() as u64;
+        assert!(inflate(&dynamic, len).is_ok());
+        assert!(inflate(&dynamic, len - 1).is_err());
+
+        // A few kilobytes that would expand to over a megabyte.
+        let mut bomb = Writer::default();
+        bomb.bits(1, 1);
+        bomb.bits(1, 2);
+        bomb.fixed(0);
+        for _ in 0..5000 {
+            bomb.fixed(285);
+            bomb.code(0, 5);
+        }
+        bomb.fixed(256);
+        assert_eq!(inflate(&bomb.out, 2 << 20).unwrap().0.len(), 1 + 5000 * 258);
+        assert!(inflate(&bomb.out, 1 << 20).is_err());
+    }
+
+    #[test]
+    fn gzip_members() {
+        let gzip = bytes(GZIP);
+        assert_eq!(gunzip(&gzip, 100).unwrap(), b"gzip member");
+        let twice = [gzip.clone(), gzip.clone()].concat();
+        assert_eq!(gunzip(&twice, 100).unwrap(), b"gzip membergzip member");
+        // The limit covers every member together.
+        assert!(gunzip(&twice, 15).is_err());
+    }
+
+    #[test]
+    fn damaged_gzip_is_refused() {
+        let gzip = bytes(GZIP);
+        assert_eq!(gunzip(b"plain text, not compressed", 100).unwrap_err(), "Not a gzip file.");
+        for len in 1..gzip.len() {
+            assert!(gunzip(&gzip[..len], 100).is_err(), "cut to {} bytes", len);
+        }
+        let crc_at = gzip.len() - 8;
+        for position in [crc_at, gzip.len() - 4] {
+            let mut damaged = gzip.clone();
+            damaged[position] ^= 1;
+            assert!(gunzip(&damaged, 100).is_err(), "flipped byte {}", position);
+        }
+        // A file name that never ends.
+        let mut unterminated = gzip[..10].to_vec();
+        unterminated[3] = 0x08;
+        unterminated.extend_from_slice(&[b'x'; 20]);
+        assert!(gunzip(&unterminated, 100).is_err());
+    }
+}
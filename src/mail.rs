@@ -0,0 +1,239 @@
+use crate::base64;
+use crate::checksum::{self, to_hex};
+use crate::compress;
+
+/// First and last lines of every part.
+const BEGIN: &str = "-----BEGIN FILE ENCRYPTOR PART-----";
+const END: &str = "-----END FILE ENCRYPTOR PART-----";
+
+/// Bytes encoded on each base64 line, giving 76 characters, the longest
+/// line MIME allows, and the line's length with its newline.
+const LINE_BYTES: usize = 57;
+const LINE_LEN: usize = 4 * LINE_BYTES / 3 + 1;
+
+/// Most parts in one set, so part numbers keep three digits.
+pub const MAX_PARTS: usize = 999;
+
+/// Compress `content` together with its file name, ready to encrypt.
+pub fn bundle(name: &str, content: &[u8]) -> Result<Vec<u8>, String> {
+    let name_len = u16::try_from(name.len()).map_err(|_| format!("The name '{}' is too long.", name))?;
+    let mut plain = Vec::with_capacity(2 + name.len() + content.len());
+    plain.extend_from_slice(&name_len.to_le_bytes());
+    plain.extend_from_slice(name.as_bytes());
+    plain.extend_from_slice(content);
+    Ok(compress::compress(&plain))
+}
+
+/// Undo `bundle`, returning the file name and the content.
+pub fn unbundle(data: &[u8]) -> Result<(String, Vec<u8>), String> {
+    let plain = compress::decompress(data)?;
+    let damaged = || "The decrypted data is not a mail-split bundle.".to_string();
+    let (len, rest) = plain.split_first_chunk::<2>().ok_or_else(damaged)?;
+    let (name, content) = rest
+        .split_at_checked(u16::from_le_bytes(*len) as usize)
+        .ok_or_else(damaged)?;
+    let name = String::from_utf8(name.to_vec()).map_err(|_| damaged())?;
+    Ok((name, content.to_vec()))
+}
+
+/// Split `payload` into text parts of at most `part_size` bytes each, for
+/// sending through mail systems that limit attachment sizes.
+///
+/// Each part is plain ASCII: a header naming the set every part belongs
+/// to, the part's number, the size of the whole payload, and the SHA-256
+/// of the part's bytes, then those bytes in base64.
+pub fn split(payload: &[u8], part_size: u64) -> Result<Vec<String>, String> {
+    let mut set = [0u8; 8];
+    crate::crypto::random_bytes(&mut set)?;
+    let set = to_hex(&set);
+
+    // The header is longest with three-digit part numbers.
+    let overhead = header(&set, MAX_PARTS, MAX_PARTS, payload.len(), &[0; checksum::SHA256_LEN]).len()
+        + END.len()
+        + 1;
+    let lines = part_size.saturating_sub(overhead as u64) / LINE_LEN as u64;
+    if lines == 0 {
+        return Err(format!("The part size must be at least {} bytes.", overhead + LINE_LEN));
+    }
+    let chunk_len = usize::try_from(lines).unwrap_or(usize::MAX).saturating_mul(LINE_BYTES);
+    let count = payload.len().div_ceil(chunk_len).max(1);
+    if count > MAX_PARTS {
+        return Err(format!(
+            "That would take {} parts; at most {} are supported. Choose a larger part size.",
+            count, MAX_PARTS
+        ));
+    }
+
+    let mut parts = Vec::with_capacity(count);
+    for number in 1..=count {
+        let chunk = payload.chunks(chunk_len).nth(number - 1).unwrap_or_default();
+        let mut text = header(&set, number, count, payload.len(), &checksum::sha256(chunk));
+        for line in chunk.chunks(LINE_BYTES) {
+            text.push_str(&base64::encode(line));
+            text.push('\n');
+        }
+        text.push_str(END);
+        text.push('\n');
+        parts.push(text);
+    }
+    Ok(parts)
+}
+
+fn header(set: &str, number: usize, count: usize, total: usize, hash: &[u8]) -> String {
+    format!(
+        "{}\nSet: {}\nPart: {}/{}\nTotal-Size: {}\nPart-SHA256: {}\n\n",
+        BEGIN,
+        set,
+        number,
+        count,
+        total,
+        to_hex(hash)
+    )
+}
+
+/// One part as read back.
+struct Part {
+    set: String,
+    number: usize,
+    count: usize,
+    total: usize,
+    bytes: Vec<u8>,
+}
+
+/// Put the payload back together from `parts`, given as (name, text)
+/// pairs in any order. Every part of one set must be there exactly once
+/// and unchanged; the names are only used in errors.
+pub fn join(parts: &[(String, String)]) -> Result<Vec<u8>, String> {
+    let mut parsed = Vec::with_capacity(parts.len());
+    for (name, text) in parts {
+        parsed.push(parse(text).map_err(|e| format!("'{}': {}", name, e))?);
+    }
+    let first = parsed.first().ok_or_else(|| "No parts given.".to_string())?;
+    let (set, count, total) = (first.set.clone(), first.count, first.total);
+    if let Some(index) = parsed.iter().position(|part| part.set != set) {
+        return Err(format!(
+            "'{}' belongs to a different set than '{}'.",
+            parts[index].0, parts[0].0
+        ));
+    }
+
+    let mut ordered: Vec<Option<&Part>> = vec![None; count];
+    for (index, part) in parsed.iter().enumerate() {
+        if part.count != count || part.total != total {
+            return Err(format!("'{}' disagrees with the other parts about the set.", parts[index].0));
+        }
+        if ordered[part.number - 1].replace(part).is_some() {
+            return Err(format!("Part {} was given twice.", part.number));
+        }
+    }
+    let missing: Vec<String> = (1..=count)
+        .filter(|number| ordered[number - 1].is_none())
+        .map(|number| number.to_string())
+        .collect();
+    if !missing.is_empty() {
+        return Err(format!("Missing part(s) {} of {}.", missing.join(", "), count));
+    }
+
+    let payload: Vec<u8> = ordered.into_iter().flatten().flat_map(|part| part.bytes.iter().copied()).collect();
+    if payload.len() != total {
+        return Err(format!("The parts hold {} bytes, but the set should have {}.", payload.len(), total));
+    }
+    Ok(payload)
+}
+
+/// Read one part, ignoring anything around the BEGIN and END lines, such
+/// as mail headers or a quoted reply.
+fn parse(text: &str) -> Result<Part, String> {
+    let start = text
+        .find(BEGIN)
+        .ok_or_else(|| "not a File Encryptor part.".to_string())?;
+    let text = &text[start + BEGIN.len()..];
+    let end = text.find(END).ok_or_else(|| "the part is cut short.".to_string())?;
+    let (head, body) = text[..end]
+        .split_once("\n\n")
+        .or_else(|| text[..end].split_once("\r\n\r\n"))
+        .ok_or_else(|| "the part has no header.".to_string())?;
+
+    let (mut set, mut position, mut total, mut hash) = (None, None, None, None);
+    for line in head.lines() {
+        match line.split_once(':') {
+            Some(("Set", value)) => set = Some(value.trim().to_string()),
+            Some(("Part", value)) => {
+                position = value
+                    .trim()
+                    .split_once('/')
+                    .and_then(|(number, count)| Some((number.parse::<usize>().ok()?, count.parse::<usize>().ok()?)))
+            }
+            Some(("Total-Size", value)) => total = value.trim().parse::<usize>().ok(),
+            Some(("Part-SHA256", value)) => hash = Some(value.trim().to_ascii_lowercase()),
+            _ => {}
+        }
+    }
+    let malformed = || "the part header is damaged.".to_string();
+    let (number, count) = position.ok_or_else(malformed)?;
+    if number == 0 || number > count || count > MAX_PARTS {
+        return Err(malformed());
+    }
+    let bytes = base64::decode(body).ok_or_else(|| "the part's contents are damaged.".to_string())?;
+    if Some(to_hex(&checksum::sha256(&bytes))) != hash {
+        return Err(format!("part {} of {} does not match its checksum; it was damaged in transit.", number, count));
+    }
+    Ok(Part {
+        set: set.ok_or_else(malformed)?,
+        number,
+        count,
+        total: total.ok_or_else(malformed)?,
+        bytes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn named(parts: &[String]) -> Vec<(String, String)> {
+        parts
+            .iter()
+            .enumerate()
+            .map(|(index, text)| (format!("part{}", index + 1), text.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn split_and_join_round_trip() {
+        let payload: Vec<u8> = (0..10_000u32).map(|i| (i * 7) as u8).collect();
+        let parts = split(&payload, 2000).unwrap();
+        assert!(parts.len() > 5);
+        assert!(parts.iter().all(|part| part.len() <= 2000 && part.is_ascii()));
+
+        // Any order works, and so does text around each part.
+        let mut shuffled = named(&parts);
+        shuffled.reverse();
+        shuffled[0].1 = format!("Subject: part\r\n\r\n> quoted\n{}\nThanks!\n", shuffled[0].1);
+        assert_eq!(join(&shuffled).unwrap(), payload);
+        assert_eq!(join(&named(&split(b"", 2000).unwrap())).unwrap(), b"");
+
+        let bundle = bundle("report.pdf", &payload).unwrap();
+        assert_eq!(unbundle(&bundle).unwrap(), ("report.pdf".to_string(), payload));
+        assert!(split(b"data", 100).is_err());
+    }
+
+    #[test]
+    fn damaged_or_mixed_sets_are_refused() {
+        let parts = named(&split(&[5; 3000], 1000).unwrap());
+        assert!(join(&parts[1..]).unwrap_err().contains("Missing part(s) 1 of"));
+        let mut twice = parts.clone();
+        twice.push(parts[0].clone());
+        assert!(join(&twice).unwrap_err().contains("given twice"));
+
+        let mut damaged = parts.clone();
+        damaged[1].1 = damaged[1].1.replacen("BQUF", "BQUG", 1);
+        assert!(join(&damaged).unwrap_err().contains("does not match its checksum"));
+
+        let mut mixed = parts.clone();
+        mixed[0] = named(&split(&[5; 3000], 1000).unwrap())[0].clone();
+        assert!(join(&mixed).unwrap_err().contains("different set"));
+        assert!(join(&[]).is_err());
+        assert!(join(&[("note".to_string(), "hello".to_string())]).is_err());
+    }
+}
@@ -1,44 +1,277 @@
+#[cfg(unix)]
+mod agent;
+mod appendlog;
+mod archive;
+mod attempts;
+mod audit;
+mod base32;
+mod base64;
+mod batch;
+mod bigint;
+mod buffers;
+mod checksum;
+mod claims;
+mod cli;
+mod compress;
+mod config;
+mod contacts;
+mod container;
+mod content;
+mod cpu;
+mod crypto;
+#[cfg(unix)]
+mod daemon;
+mod digest;
+mod evidence;
+mod ed25519;
+mod filter;
+mod format;
+mod guard;
+#[cfg(feature = "gui")]
+mod gui;
+mod history;
+mod hooks;
+mod i18n;
+mod inflate;
+mod mail;
+mod manifest;
+mod memory;
+mod metadata;
+#[cfg(unix)]
+mod metrics;
+mod migrate;
+mod naming;
+#[cfg(unix)]
+mod nbd;
+mod packed;
+mod parity;
+mod paths;
+mod policy;
+mod priority;
+mod progress;
+mod recipients;
+mod recover;
+mod reencrypt;
+mod retry;
+mod salvage;
+#[cfg(unix)]
+mod sandbox;
+mod scratch;
+mod notify;
+mod selftest;
+mod shell;
+mod sidecar;
+mod signature;
+mod storage;
+mod store;
+mod strip;
+mod throttle;
+mod timelock;
+mod trace;
+mod tree;
+mod totp;
+mod trash;
+mod tty;
+mod tuning;
+mod ui;
+#[cfg(target_os = "linux")]
+mod uring;
+mod vault;
+
 use std::collections::HashMap;
-use std::io::{self, Write};
+use std::env;
+use std::fmt;
+use std::io::{self, BufRead, Read, Seek, SeekFrom, Write};
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use archive::ArchiveSummary;
+use checksum::SHA256_LEN;
+use config::Config;
+use filter::Filter;
+use file_encryptor_core::aes;
+use file_encryptor_core::seal::{self as sealing, Keys};
+use format::{Cipher, FORMAT_VERSION, FormatError, HEADER_LEN, METADATA_LEN_FIELD, SEALED_VERSION, SealParams};
+use history::History;
+use audit::Auditor;
+use hooks::{HookEvent, Hooks};
+use i18n::t;
+use metadata::FileMetadata;
+use notify::Notifier;
+use progress::Phase;
+use trash::Trash;
+
+/// How much of a file the preview shows when the user does not choose.
+const DEFAULT_PREVIEW_KB: u64 = 4;
+
+/// Exit status of a command that finished but encrypted at least one file
+/// that kept changing while it was read (see `throttle::read_consistent`).
+const EXIT_INPUT_CHANGED: i32 = 3;
+
+/// Environment variable that must be `1` for `--deterministic-for-testing`
+/// to be accepted.
+const TESTING_VARIABLE: &str = "FILE_ENCRYPTOR_TESTING";
+
+/// Smallest run of repeated key the legacy XOR cipher works on at a time.
+const XOR_BLOCK_LEN: usize = 4096;
+
+
+/// What an encrypt or decrypt operation produced besides the output file.
+struct OperationReport {
+    /// SHA-256 of the input file.
+    input_checksum: [u8; SHA256_LEN],
+    /// SHA-256 of the output file.
+    output_checksum: [u8; SHA256_LEN],
+    /// Whether the output matched a checksum stored inside the input file.
+    verified: bool,
+    /// Problems that did not stop the operation, such as attributes that
+    /// could not be restored.
+    warnings: Vec<String>,
+    /// Format of the input if its metadata was stripped before encryption
+    /// (`--strip-metadata`).
+    stripped: Option<&'static str>,
+    /// Whether the input kept changing while it was read, so the output
+    /// may mix old and new contents.
+    input_changed: bool,
+}
 
 /// Represents what kind of action the user took.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum CryptoAction {
     Encrypt,
     Decrypt,
 }
 
-/// Stores a single history entry for this session.
-#[derive(Debug, Clone)]
-struct HistoryEntry {
-    file_path: String,
-    action: CryptoAction,
-    success: bool,
+impl CryptoAction {
+    /// Lowercase name used when describing the action to other programs.
+    fn name(self) -> &'static str {
+        match self {
+            CryptoAction::Encrypt => "encrypt",
+            CryptoAction::Decrypt => "decrypt",
+        }
+    }
+
+    /// Parse a name written by `name`.
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "encrypt" => Some(CryptoAction::Encrypt),
+            "decrypt" => Some(CryptoAction::Decrypt),
+            _ => None,
+        }
+    }
+}
+
+/// Folders used earlier in this menu session, offered again at the path
+/// prompts so repeated work needs fewer keystrokes. Nothing is saved.
+#[derive(Default)]
+struct SessionDefaults {
+    /// Folder the last input came from.
+    input_dir: Option<PathBuf>,
+    /// Folder the last output went to.
+    output_dir: Option<PathBuf>,
+}
+
+impl SessionDefaults {
+    /// Ask for an input path. Once an input folder is known, `with_dir`
+    /// builds the prompt naming it, and relative paths found in it are taken
+    /// from there.
+    fn read_input(&self, prompt: &str, with_dir: impl Fn(String) -> String) -> PathBuf {
+        let Some(dir) = &self.input_dir else {
+            print!("{}", prompt);
+            flush_stdout();
+            return read_path();
+        };
+        print!("{}", with_dir(paths::display(dir)));
+        flush_stdout();
+        let path = read_path();
+        let in_dir = dir.join(&path);
+        if !path.as_os_str().is_empty() && path.is_relative() && in_dir.exists() { in_dir } else { path }
+    }
+
+    /// Ask for an output path, offering `input` with `suffix` added, placed
+    /// in the last output folder if there is one.
+    fn read_output(&self, input: &Path, suffix: &str, prompt: &str) -> PathBuf {
+        // Trailing separators are dropped by taking the components.
+        let input = packed::output_base(input);
+        let input = input.components().as_path();
+        let default = match (&self.output_dir, input.file_name()) {
+            (Some(dir), Some(name)) => dir.join(paths::with_suffix(Path::new(name), suffix)),
+            _ => paths::with_suffix(input, suffix),
+        };
+        if self.output_dir.is_some() {
+            print!("{}", t!("Enter output path (leave blank for '{}'): ", paths::display(&default)));
+        } else {
+            print!("{}", prompt);
+        }
+        flush_stdout();
+        let path = read_path();
+        if path.as_os_str().is_empty() { default } else { path }
+    }
+
+    /// Remember the folders of a finished operation.
+    fn remember(&mut self, input: &Path, output: &Path) {
+        let folder = |path: &Path| {
+            path.components()
+                .as_path()
+                .parent()
+                .filter(|parent| !parent.as_os_str().is_empty())
+                .map(Path::to_path_buf)
+        };
+        self.input_dir = folder(input);
+        self.output_dir = folder(output);
+    }
 }
 
 /// Main application struct that holds the history.
 struct FileCryptoApp {
-    history: Vec<HistoryEntry>,
+    history: History,
+    trash: Trash,
+    show_checksums: bool,
+    preserve_metadata: bool,
+    hooks: Hooks,
+    notifier: Notifier,
+    auditor: Auditor,
+    retry: retry::Policy,
+    /// Whether files from the XOR era may be decrypted (`--legacy-xor`).
+    legacy_xor: bool,
+    recent: SessionDefaults,
 }
 
 impl FileCryptoApp {
     /// Create a new instance of the app.
-    fn new() -> Self {
-        FileCryptoApp { history: Vec::new() }
+    fn new(config: &Config, legacy_xor: bool) -> Result<Self, String> {
+        let trash = Trash::new(config.trash_retention_minutes)?;
+        trash.purge_expired();
+        Ok(FileCryptoApp {
+            history: History::load(config),
+            trash,
+            show_checksums: config.show_checksums,
+            preserve_metadata: config.preserve_metadata,
+            hooks: Hooks::from_config(config),
+            notifier: Notifier::from_config(config),
+            auditor: Auditor::from_config(config),
+            retry: retry::Policy::from_config(config),
+            legacy_xor,
+            recent: SessionDefaults::default(),
+        })
     }
 
     /// Main loop that keeps the program running until the user quits.
     fn run(&mut self) {
         loop {
             println!();
-            println!("================ File Encryptor ================");
-            println!("1) Encrypt file");
-            println!("2) Decrypt file");
-            println!("3) Show history");
-            println!("4) Quit");
-            println!("=======================================================");
-            print!("Enter your choice: ");
+            ui::heading(t!("================ File Encryptor ================"));
+            println!("{}", t!("1) Encrypt file"));
+            println!("{}", t!("2) Decrypt file"));
+            println!("{}", t!("3) Encrypt folder"));
+            println!("{}", t!("4) Decrypt folder"));
+            println!("{}", t!("5) Preview decryption"));
+            println!("{}", t!("6) Show history"));
+            println!("{}", t!("7) Undo last operation"));
+            println!("{}", t!("8) Quit"));
+            ui::rule();
+            print!("{}", t!("Enter your choice: "));
             flush_stdout();
 
             let choice = read_line_trimmed();
@@ -46,117 +279,516 @@ impl FileCryptoApp {
             match choice.as_str() {
                 "1" => self.handle_encrypt(),
                 "2" => self.handle_decrypt(),
-                "3" => self.show_history(),
-                "4" => {
-                    println!("Goodbye!");
+                "3" => self.handle_encrypt_folder(),
+                "4" => self.handle_decrypt_folder(),
+                "5" => self.handle_preview(),
+                "6" => self.show_history(),
+                "7" => self.handle_undo(),
+                "8" => {
+                    println!("{}", t!("Goodbye!"));
                     break;
                 }
                 _ => {
-                    println!("Invalid choice. Please enter 1, 2, 3, or 4.");
+                    println!("{}", t!("Invalid choice. Please enter a number from 1 to 8."));
                 }
             }
         }
     }
 
-    /// Handle the "Encrypt file" menu option.
+    /// Handle the "Encrypt file" menu option. Given a folder instead of a
+    /// file, it lists the files in it to pick several from.
     fn handle_encrypt(&mut self) {
         println!();
-        println!("--- Encrypt File ---");
-        print!("Enter input file path: ");
-        flush_stdout();
-        let input_path = read_line_trimmed();
+        ui::heading(t!("--- Encrypt File ---"));
+        let mut input_path = self.recent.read_input(t!("Enter input file path: "), |dir| {
+            t!("Enter input file path (leave blank to list '{}'): ", dir)
+        });
+        if input_path.as_os_str().is_empty()
+            && let Some(dir) = &self.recent.input_dir
+        {
+            input_path = dir.clone();
+        }
+        if input_path.is_dir() {
+            self.handle_encrypt_selection(&input_path);
+            return;
+        }
+        let output_path = self.recent.read_output(
+            &input_path,
+            ".enc",
+            t!("Enter output file path (leave blank for default .enc): "),
+        );
 
-        print!("Enter output file path (leave blank for default .enc): ");
-        flush_stdout();
-        let mut output_path = read_line_trimmed();
-        if output_path.is_empty() {
-            output_path = format!("{}.enc", input_path);
+        let password = tty::prompt(t!("Enter password: "));
+        let label = read_label();
+        self.encrypt_and_record(input_path, output_path, &password, &label);
+    }
+
+    /// List the files directly in `folder`, let the user pick any number of
+    /// them, and encrypt each next to itself with one password.
+    fn handle_encrypt_selection(&mut self, folder: &Path) {
+        let mut files: Vec<PathBuf> = match fs::read_dir(folder) {
+            Ok(entries) => entries
+                .filter_map(Result::ok)
+                .filter(|entry| entry.file_type().is_ok_and(|kind| kind.is_file()))
+                .map(|entry| entry.path())
+                .collect(),
+            Err(e) => {
+                println!("{}", t!("Failed to read folder: {}", e));
+                return;
+            }
+        };
+        if files.is_empty() {
+            println!("{}", t!("There are no files directly in this folder. Use 'Encrypt folder' for its subfolders."));
+            return;
         }
+        files.sort();
 
-        print!("Enter password: ");
+        let number_width = files.len().to_string().len();
+        for (index, file) in files.iter().enumerate() {
+            let name = file.file_name().map(Path::new).unwrap_or(file);
+            let size = fs::metadata(file).map(|info| info.len()).unwrap_or(0);
+            let prefix = format!("  {:>number_width$}) ", index + 1);
+            let suffix = format!(" ({} bytes)", size);
+            let room = ui::width().saturating_sub(prefix.len() + suffix.len() + 1);
+            println!(
+                "{}{}{}",
+                prefix,
+                ui::truncate_middle(&name.display().to_string(), room.max(12)),
+                suffix
+            );
+        }
+        print!("{}", t!("Files to encrypt (such as 1 3 5-7, or all; leave blank to cancel): "));
         flush_stdout();
-        let password = read_line_trimmed();
+        let selection = read_line_trimmed();
+        if selection.is_empty() {
+            return;
+        }
+        let chosen = match parse_selection(&selection, files.len()) {
+            Ok(chosen) => chosen,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
 
-        let result = encrypt_file(&input_path, &output_path, &password);
+        let password = tty::prompt(t!("Enter password: "));
+        let label = read_label();
+        let mut encrypted = 0;
+        for &index in &chosen {
+            let input_path = files[index].clone();
+            let output_path = paths::with_suffix(&input_path, ".enc");
+            println!();
+            if self.encrypt_and_record(input_path, output_path, &password, &label) {
+                encrypted += 1;
+            }
+            if progress::cancelled() {
+                break;
+            }
+        }
+        println!();
+        println!("{}", t!("Encrypted {} of {} selected file(s).", encrypted, chosen.len()));
+        if encrypted > 0 {
+            self.recent.input_dir = Some(folder.to_path_buf());
+            self.recent.output_dir = Some(folder.to_path_buf());
+        }
+    }
+
+    /// Encrypt one file, show the result, and run the hooks and history
+    /// for it. Returns whether it worked.
+    fn encrypt_and_record(&mut self, input_path: PathBuf, output_path: PathBuf, password: &str, label: &str) -> bool {
+        let started = Instant::now();
+        let (result, retries) = self.retry.run(&paths::display(&input_path), || {
+            self.trash.protect(&output_path, || {
+                encrypt_file(&input_path, &output_path, password, self.preserve_metadata)
+            })
+        });
 
         let success = result.is_ok();
-        if let Err(e) = result {
-            println!("Encryption failed: {}", e);
-        } else {
-            println!("File encrypted successfully to '{}'.", output_path);
+        let error = result.as_ref().err().cloned();
+        let plaintext_sha256 = result.as_ref().ok().map(|report| report.input_checksum);
+        let input_changed = result.as_ref().is_ok_and(|report| report.input_changed);
+        match result {
+            Ok(report) => {
+                println!("{}", t!("File encrypted successfully to '{}'.", paths::display(&output_path)));
+                self.print_report(&report);
+            }
+            Err(e) => println!("{}", t!("Encryption failed: {}", e)),
         }
 
-        self.add_history_entry(input_path, CryptoAction::Encrypt, success);
+        self.finish_operation(
+            CryptoAction::Encrypt,
+            &input_path,
+            &output_path,
+            error.as_deref(),
+            started.elapsed(),
+            password,
+        );
+        if success {
+            self.recent.remember(&input_path, &output_path);
+        }
+        let mut entry = history::Entry::now(CryptoAction::Encrypt, input_path, success, label);
+        entry.output_path = Some(output_path);
+        entry.plaintext_sha256 = plaintext_sha256;
+        entry.input_changed = input_changed;
+        entry.retries = retries;
+        self.add_history_entry(entry);
+        success
     }
 
     /// Handle the "Decrypt file" menu option.
     fn handle_decrypt(&mut self) {
         println!();
-        println!("--- Decrypt File ---");
-        print!("Enter input file path: ");
-        flush_stdout();
-        let input_path = read_line_trimmed();
+        ui::heading(t!("--- Decrypt File ---"));
+        let input_path = self.recent.read_input(t!("Enter input file path: "), |dir| {
+            t!("Enter input file path (relative to '{}'): ", dir)
+        });
+        let mut output_path = self.recent.read_output(
+            &input_path,
+            ".dec",
+            t!("Enter output file path (leave blank for default .dec): "),
+        );
 
-        print!("Enter output file path (leave blank for default .dec): ");
-        flush_stdout();
-        let mut output_path = read_line_trimmed();
-        if output_path.is_empty() {
-            output_path = format!("{}.dec", input_path);
+        let password = tty::prompt(t!("Enter password: "));
+
+        let started = Instant::now();
+        let result = open_file(&input_path, &password, self.legacy_xor).and_then(|opened| {
+            output_path = suggest_extension(output_path.clone(), &opened.decrypted.plaintext);
+            self.trash.protect(&output_path, || {
+                decrypt_file(
+                    opened,
+                    &output_path,
+                    self.preserve_metadata,
+                    &mut confirm_suspicious_output,
+                )
+            })
+        });
+
+        let success = result.is_ok();
+        let error = result.as_ref().err().cloned();
+        match result {
+            Ok(report) => {
+                println!("{}", t!("File decrypted successfully to '{}'.", paths::display(&output_path)));
+                if report.verified {
+                    println!("{}", t!("Checksum verified: the output matches the original file."));
+                } else {
+                    println!("{}", t!("Note: this file was written by an older version and has no stored checksum, so the output could not be verified."));
+                }
+                self.print_report(&report);
+            }
+            Err(e) => println!("{}", t!("Decryption failed: {}", e)),
+        }
+
+        self.finish_operation(
+            CryptoAction::Decrypt,
+            &input_path,
+            &output_path,
+            error.as_deref(),
+            started.elapsed(),
+            &password,
+        );
+        if success {
+            self.recent.remember(&input_path, &output_path);
+        }
+        let mut entry = history::Entry::now(CryptoAction::Decrypt, input_path, success, "");
+        if success {
+            entry.outdated = self.offer_migration(&entry.file_path, &password);
         }
+        self.add_history_entry(entry);
+    }
 
-        print!("Enter password: ");
+    /// After `input_path` was decrypted, offer to upgrade it in place if
+    /// its encryption is outdated (the warning saying what is outdated has
+    /// been shown already). Returns the short names of what was outdated,
+    /// for the history.
+    fn offer_migration(&self, input_path: &Path, password: &str) -> Vec<String> {
+        let outdated = migrate::file_weaknesses(input_path).unwrap_or_default();
+        if outdated.is_empty() {
+            return Vec::new();
+        }
+        let codes = outdated.iter().map(|weakness| weakness.code().to_string()).collect();
+        // Accept the Spanish "s" too, matching the translated prompt.
+        if !matches!(tty::prompt(t!("Migrate it to the current format now? [y/N]: ")).to_lowercase().as_str(), "y" | "s") {
+            return codes;
+        }
+        let Some(path) = input_path.to_str() else {
+            println!("{}", t!("Migration failed: the path is not valid Unicode."));
+            return codes;
+        };
+        match self.trash.protect(input_path, || migrate::migrate_file(path, password, self.legacy_xor)) {
+            Ok(_) => println!("{}", t!("Migrated '{}' to format version {}.", path, FORMAT_VERSION)),
+            Err(e) => println!("{}", t!("Migration failed: {}", e)),
+        }
+        codes
+    }
+
+    /// Handle the "Encrypt folder" menu option.
+    fn handle_encrypt_folder(&mut self) {
+        println!();
+        ui::heading(t!("--- Encrypt Folder ---"));
+        let input_path = self.recent.read_input(t!("Enter folder path: "), |dir| {
+            t!("Enter folder path (relative to '{}'): ", dir)
+        });
+        let output_path = self.recent.read_output(
+            &input_path,
+            ".enc",
+            t!("Enter output file path (leave blank for default .enc): "),
+        );
+
+        print!("{}", t!("Patterns to exclude, separated by spaces (leave blank for none): "));
         flush_stdout();
-        let password = read_line_trimmed();
+        let mut filter = Filter::default();
+        for pattern in read_line_trimmed().split_whitespace() {
+            filter.exclude(pattern);
+        }
+
+        let password = tty::prompt(t!("Enter password: "));
+        let label = read_label();
 
-        let result = decrypt_file(&input_path, &output_path, &password);
+        let started = Instant::now();
+        let result = self.trash.protect(&output_path, || {
+            encrypt_folder(&input_path, &output_path, &password, &filter, self.preserve_metadata)
+        });
 
         let success = result.is_ok();
-        if let Err(e) = result {
-            println!("Decryption failed: {}", e);
-        } else {
-            println!("File decrypted successfully to '{}'.", output_path);
+        let error = result.as_ref().err().cloned();
+        let input_changed = result.as_ref().is_ok_and(|summary| summary.changed > 0);
+        match result {
+            Ok(summary) => {
+                println!("{}", t!("Folder encrypted successfully to '{}'.", paths::display(&output_path)));
+                print_archive_summary(&summary);
+            }
+            Err(e) => println!("{}", t!("Encryption failed: {}", e)),
+        }
+
+        self.finish_operation(
+            CryptoAction::Encrypt,
+            &input_path,
+            &output_path,
+            error.as_deref(),
+            started.elapsed(),
+            &password,
+        );
+        if success {
+            self.recent.remember(&input_path, &output_path);
+        }
+        let mut entry = history::Entry::now(CryptoAction::Encrypt, input_path, success, &label);
+        entry.input_changed = input_changed;
+        self.add_history_entry(entry);
+    }
+
+    /// Handle the "Decrypt folder" menu option. The output folder must be
+    /// new or empty, so nothing is overwritten and there is nothing to undo.
+    fn handle_decrypt_folder(&mut self) {
+        println!();
+        ui::heading(t!("--- Decrypt Folder ---"));
+        let input_path = self.recent.read_input(t!("Enter input file path: "), |dir| {
+            t!("Enter input file path (relative to '{}'): ", dir)
+        });
+        let output_path = self.recent.read_output(
+            &input_path,
+            ".dec",
+            t!("Enter output folder path (leave blank for default .dec): "),
+        );
+
+        let password = tty::prompt(t!("Enter password: "));
+
+        let started = Instant::now();
+        let result = decrypt_folder(
+            &input_path,
+            &output_path,
+            &password,
+            self.preserve_metadata,
+            self.legacy_xor,
+        );
+
+        let success = result.is_ok();
+        let error = result.as_ref().err().cloned();
+        match result {
+            Ok(summary) => {
+                println!("{}", t!("Folder decrypted successfully to '{}'.", paths::display(&output_path)));
+                print_archive_summary(&summary);
+            }
+            Err(e) => println!("{}", t!("Decryption failed: {}", e)),
+        }
+
+        self.finish_operation(
+            CryptoAction::Decrypt,
+            &input_path,
+            &output_path,
+            error.as_deref(),
+            started.elapsed(),
+            &password,
+        );
+        if success {
+            self.recent.remember(&input_path, &output_path);
+        }
+        let mut entry = history::Entry::now(CryptoAction::Decrypt, input_path, success, "");
+        if success {
+            entry.outdated = self.offer_migration(&entry.file_path, &password);
+        }
+        self.add_history_entry(entry);
+    }
+
+    /// Handle the "Preview decryption" menu option. Nothing is written to
+    /// disk, so this is not recorded in the history.
+    fn handle_preview(&self) {
+        println!();
+        ui::heading(t!("--- Preview Decryption ---"));
+        let input_path = self.recent.read_input(t!("Enter input file path: "), |dir| {
+            t!("Enter input file path (relative to '{}'): ", dir)
+        });
+
+        let password = tty::prompt(t!("Enter password: "));
+
+        print!("{}", t!("How many kilobytes to preview (leave blank for {}): ", DEFAULT_PREVIEW_KB));
+        flush_stdout();
+        let kilobytes = match read_line_trimmed().as_str() {
+            "" => DEFAULT_PREVIEW_KB,
+            text => match text.parse::<u64>() {
+                Ok(kb) if kb > 0 => kb,
+                _ => {
+                    println!("{}", t!("Please enter a whole number greater than zero."));
+                    return;
+                }
+            },
+        };
+
+        print!("{}", t!("Show as (t)ext or (h)ex? [t]: "));
+        flush_stdout();
+        let as_hex = read_line_trimmed().eq_ignore_ascii_case("h");
+
+        let max_bytes = kilobytes.saturating_mul(1024);
+        match preview_file(&input_path, &password, max_bytes, self.legacy_xor) {
+            Ok((data, verified)) => {
+                println!();
+                if as_hex {
+                    print_hex_dump(&data);
+                } else {
+                    println!("{}", printable_text(&data));
+                }
+                println!();
+                println!("{}", t!("(Showing the first {} byte(s).)", data.len()));
+                if !verified && let Some(reason) = content::looks_random(&data) {
+                    println!("{}", t!("Warning: {}", reason));
+                }
+            }
+            Err(e) => println!("{}", t!("Preview failed: {}", e)),
+        }
+    }
+
+    /// Handle the "Undo last operation" menu option.
+    fn handle_undo(&self) {
+        println!();
+        match self.trash.undo_last() {
+            Ok(message) => println!("{}", message),
+            Err(e) => println!("{}", t!("Undo failed: {}", e)),
         }
+    }
 
-        self.add_history_entry(input_path, CryptoAction::Decrypt, success);
+    /// Print any warnings from an operation, and the input and output
+    /// checksums if the user asked for them.
+    fn print_report(&self, report: &OperationReport) {
+        if let Some(kind) = report.stripped {
+            println!("{}", t!("Removed the metadata from this {} file before encrypting it.", kind));
+        }
+        for warning in &report.warnings {
+            println!("{}", t!("Warning: {}", warning));
+        }
+        if self.show_checksums {
+            println!("{}", t!("SHA-256 of input:  {}", checksum::to_hex(&report.input_checksum)));
+            println!("{}", t!("SHA-256 of output: {}", checksum::to_hex(&report.output_checksum)));
+        }
     }
 
-    /// Add a new entry to the in memory history list.
-    fn add_history_entry(&mut self, path: String, action: CryptoAction, success: bool) {
-        let entry = HistoryEntry {
-            file_path: path,
-            action,
-            success,
+    /// Run the configured hook for a finished operation, show any problem
+    /// with it, send the audit event if auditing is on, and send a desktop
+    /// notification if the operation was long. The hook is given
+    /// `password` if the `hook_password` setting is on.
+    fn finish_operation(
+        &self,
+        action: CryptoAction,
+        input: &Path,
+        output: &Path,
+        error: Option<&str>,
+        elapsed: Duration,
+        password: &str,
+    ) {
+        let (input, output) = (paths::display(input), paths::display(output));
+        let event = HookEvent {
+            action: action.name(),
+            input: &input,
+            output: &output,
+            error,
+            password: Some(password),
         };
-        self.history.push(entry);
+        if let Some(warning) = self.hooks.run(&event) {
+            println!("{}", t!("Warning: {}", warning));
+        }
+        if let Some(warning) = self.auditor.operation_finished(&event) {
+            println!("{}", t!("Warning: {}", warning));
+        }
+
+        let description = match action {
+            CryptoAction::Encrypt => t!("Encrypting '{}'", input),
+            CryptoAction::Decrypt => t!("Decrypting '{}'", input),
+        };
+        self.notifier.operation_finished(&description, error, elapsed);
+        if guard::enabled() {
+            match guard::verify() {
+                Ok(count) => println!("{}", t!("{} source file(s) checked: unchanged.", count)),
+                Err(e) => println!("{}", t!("Error: {}", e)),
+            }
+        }
     }
 
-    /// Display all history entries for this session and a small summary.
+    /// Record an operation in the history.
+    fn add_history_entry(&mut self, entry: history::Entry) {
+        if let Err(e) = self.history.record(entry) {
+            println!("{}", t!("Warning: {}", e));
+        }
+    }
+
+    /// Display the history entries matching a filter the user types, newest
+    /// first and a page at a time, and a small summary of this session.
     fn show_history(&self) {
         println!();
-        if self.history.is_empty() {
-            println!("No history yet. Try encrypting or decrypting a file first.");
+        if self.history.entries().is_empty() {
+            println!("{}", t!("No history yet. Try encrypting or decrypting a file first."));
             return;
         }
 
-        println!("--- History ---");
-        for (index, entry) in self.history.iter().enumerate() {
-            let action_str = match entry.action {
-                CryptoAction::Encrypt => "Encrypt",
-                CryptoAction::Decrypt => "Decrypt",
-            };
-            let status_str = if entry.success { "Success" } else { "Failed" };
+        println!("{}", t!("Filter with encrypt, decrypt, ok, failed, changed, outdated, since:WHEN, until:WHEN, or text in the path."));
+        print!("{}", t!("Filter (leave blank for all): "));
+        flush_stdout();
+        let filter = match history::Filter::parse(&read_line_trimmed()) {
+            Ok(filter) => filter,
+            Err(e) => {
+                println!("{}", e);
+                return;
+            }
+        };
 
-            println!(
-                "{}. [{}] {} -> {}",
-                index + 1,
-                action_str,
-                entry.file_path,
-                status_str
-            );
+        let entries = self.history.filter(&filter);
+        ui::heading(t!("--- History ---"));
+        if entries.is_empty() {
+            println!("{}", t!("No entries match the filter."));
+        }
+        let pages = entries.len().div_ceil(history::PAGE_SIZE);
+        for (page, chunk) in entries.chunks(history::PAGE_SIZE).enumerate() {
+            for (index, entry) in chunk {
+                history::print_entry(index + 1, entry);
+            }
+            if page + 1 < pages {
+                print!("{}", t!("Page {} of {}. Press Enter for more or q to stop: ", page + 1, pages));
+                flush_stdout();
+                if read_line_trimmed().eq_ignore_ascii_case("q") {
+                    break;
+                }
+            }
         }
 
         let mut summary: HashMap<&str, usize> = HashMap::new();
-        for entry in &self.history {
+        for entry in self.history.session() {
             let key = match entry.action {
                 CryptoAction::Encrypt => "encrypt",
                 CryptoAction::Decrypt => "decrypt",
@@ -165,71 +797,916 @@ impl FileCryptoApp {
         }
 
         println!();
-        println!("Summary this session:");
+        println!("{}", t!("Summary this session:"));
         println!(
-            "Encrypted: {} file(s)",
-            summary.get("encrypt").cloned().unwrap_or(0)
+            "{}",
+            t!("Encrypted: {} file(s)", summary.get("encrypt").cloned().unwrap_or(0))
         );
         println!(
-            "Decrypted: {} file(s)",
-            summary.get("decrypt").cloned().unwrap_or(0)
+            "{}",
+            t!("Decrypted: {} file(s)", summary.get("decrypt").cloned().unwrap_or(0))
         );
     }
 }
 
+/// Result of decrypting the contents of an encrypted file in memory.
+struct Decrypted {
+    plaintext: Vec<u8>,
+    /// Stored attributes, for format versions that have a metadata block.
+    metadata: Option<FileMetadata>,
+    /// Whether the plaintext matched the checksum stored in the file.
+    verified: bool,
+    /// What is outdated about how the file was encrypted, such as the
+    /// insecure XOR cipher.
+    outdated: Vec<migrate::Weakness>,
+}
+
+/// Why encrypted data did not decrypt. Errors from the format, among them
+/// a wrong password, keep their type, so callers such as `Attempts` can
+/// tell them apart without reading a message that may be translated.
+#[derive(Debug)]
+enum OpenError {
+    Format(FormatError),
+    Other(String),
+}
+
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OpenError::Format(error) => f.write_str(&format::message(error.clone())),
+            OpenError::Other(message) => f.write_str(message),
+        }
+    }
+}
+
+impl From<FormatError> for OpenError {
+    fn from(error: FormatError) -> Self {
+        OpenError::Format(error)
+    }
+}
+
+impl From<String> for OpenError {
+    fn from(message: String) -> Self {
+        OpenError::Other(message)
+    }
+}
+
+impl From<OpenError> for String {
+    fn from(error: OpenError) -> String {
+        error.to_string()
+    }
+}
+
 /// Helper function that encrypts a file by reading it into memory,
-/// running XOR over all bytes with a key derived from the password,
-/// and writing out the result.
-fn encrypt_file(input_path: &str, output_path: &str, password: &str) -> Result<(), String> {
-    let data = fs::read(input_path).map_err(|e| format!("Failed to read input file: {}", e))?;
+/// encrypting it with keys derived from the password, and writing out the
+/// result.
+///
+/// The file's owner and extended attributes are stored in the metadata
+/// block unless `preserve_metadata` is off.
+fn encrypt_file(
+    input_path: &Path,
+    output_path: &Path,
+    password: &str,
+    preserve_metadata: bool,
+) -> Result<OperationReport, String> {
+    let _claims = claims::claim_all(&[input_path, output_path], "encrypt")?;
+    let sealed = seal_file(input_path, password, preserve_metadata)?;
+
+    check_cancelled()?;
+    progress::phase(Phase::Writing);
+    throttle::write(output_path, &sealed.encrypted)
+        .map_err(|e| t!("Failed to write output file: {}", e))?;
+    progress::phase(Phase::Done);
+    Ok(sealed.report)
+}
+
+/// Encrypt a file as `encrypt_file` does, adding the result to `archive`
+/// as the member `member` instead of writing a file of its own.
+fn encrypt_file_into(
+    input_path: &Path,
+    archive: &mut packed::Appender,
+    member: &str,
+    password: &str,
+    preserve_metadata: bool,
+) -> Result<OperationReport, String> {
+    let _claim = claims::claim(input_path, "encrypt")?;
+    let sealed = seal_file(input_path, password, preserve_metadata)?;
+
+    check_cancelled()?;
+    progress::phase(Phase::Writing);
+    archive.add(member, &sealed.encrypted)?;
+    progress::phase(Phase::Done);
+    Ok(sealed.report)
+}
+
+/// A file that has been read and encrypted in memory but not yet written
+/// anywhere.
+struct SealedFile {
+    encrypted: Vec<u8>,
+    report: OperationReport,
+    /// Keeps the operation going until the file has been written.
+    _operation: progress::Operation,
+}
+
+/// Read `input_path` and encrypt it in memory.
+fn seal_file(input_path: &Path, password: &str, preserve_metadata: bool) -> Result<SealedFile, String> {
+    memory::check_file(input_path)?;
+    let operation = progress::begin(Phase::Reading, fs::metadata(input_path).ok().map(|info| info.len()));
+    // An entry inside a zip or tar goes from the archive straight into the
+    // cipher, and has no owner or attributes of its own to keep.
+    let entry = packed::split(input_path);
+    let (data, input_changed) = match &entry {
+        Some((archive, name)) => (packed::read(archive, name)?, false),
+        None => throttle::read_consistent(input_path).map_err(|e| t!("Failed to read input file: {}", e))?,
+    };
     let key_bytes = password.as_bytes();
     if key_bytes.is_empty() {
-        return Err("Password cannot be empty.".to_string());
+        return Err(t!("Password cannot be empty.").to_string());
     }
 
-    let encrypted = xor_with_key(&data, key_bytes);
+    let metadata = if preserve_metadata && entry.is_none() {
+        FileMetadata::capture(input_path)?
+    } else {
+        FileMetadata::default()
+    };
 
-    fs::write(output_path, encrypted)
-        .map_err(|e| format!("Failed to write output file: {}", e))?;
+    check_cancelled()?;
+    progress::phase(Phase::Encrypting);
+    let input_checksum = checksum::sha256(&data);
+    let (data, stripped) = strip::strip_if_enabled(data);
+    let encrypted = parity::protect_if_enabled(encrypt_bytes(&data, &metadata.to_bytes(), key_bytes)?);
 
-    Ok(())
+    let mut warnings = Vec::new();
+    if input_changed {
+        warnings.push(input_changed_warning(&paths::display(input_path)));
+    }
+    Ok(SealedFile {
+        report: OperationReport {
+            input_checksum,
+            output_checksum: checksum::sha256(&encrypted),
+            verified: false,
+            warnings,
+            stripped,
+            input_changed,
+        },
+        encrypted,
+        _operation: operation,
+    })
 }
 
-/// Helper function that decrypts a file. Since XOR is symmetric,
-/// we can use the same operation for decryption.
-fn decrypt_file(input_path: &str, output_path: &str, password: &str) -> Result<(), String> {
-    let data = fs::read(input_path).map_err(|e| format!("Failed to read input file: {}", e))?;
+/// An encrypted file that has been read and decrypted in memory but not yet
+/// written anywhere.
+struct OpenedFile {
+    /// SHA-256 of the encrypted file.
+    input_checksum: [u8; SHA256_LEN],
+    decrypted: Decrypted,
+    /// Keeps the operation going, and the input claimed, until the file
+    /// has been written.
+    _operation: progress::Operation,
+    _claim: claims::Claim,
+}
+
+/// Read and decrypt a file without writing anything, so the output name can
+/// still be chosen from what it contains. Files from the XOR era are only
+/// accepted with `legacy_xor`.
+fn open_file(input_path: &Path, password: &str, legacy_xor: bool) -> Result<OpenedFile, String> {
+    let claim = claims::claim(input_path, "decrypt")?;
+    memory::check_file(input_path)?;
+    let operation = progress::begin(Phase::Reading, fs::metadata(input_path).ok().map(|info| info.len()));
+    let data = throttle::read(input_path).map_err(|e| t!("Failed to read input file: {}", e))?;
     let key_bytes = password.as_bytes();
     if key_bytes.is_empty() {
-        return Err("Password cannot be empty.".to_string());
+        return Err(t!("Password cannot be empty.").to_string());
+    }
+    progress::phase(Phase::Decrypting);
+
+    Ok(OpenedFile {
+        input_checksum: checksum::sha256(&data),
+        decrypted: decrypt_bytes(&data, key_bytes, legacy_xor)?,
+        _operation: operation,
+        _claim: claim,
+    })
+}
+
+/// Helper function that writes a file opened by `open_file` to
+/// `output_path`.
+///
+/// Stored attributes are restored onto the output if `preserve_metadata`
+/// is on.
+///
+/// Files without a checksum cannot prove the password was right, so if
+/// the output looks like random data, `confirm` is asked with the reason
+/// before anything is written.
+fn decrypt_file(
+    opened: OpenedFile,
+    output_path: &Path,
+    preserve_metadata: bool,
+    confirm: &mut dyn FnMut(&str) -> bool,
+) -> Result<OperationReport, String> {
+    let _claim = claims::claim(output_path, "decrypt")?;
+    let decrypted = opened.decrypted;
+    check_cancelled()?;
+    if !decrypted.verified
+        && let Some(reason) = content::looks_random(&decrypted.plaintext)
+        && !confirm(&reason)
+    {
+        return Err(t!("Nothing was written because the output looked wrong.").to_string());
     }
 
-    let decrypted = xor_with_key(&data, key_bytes);
+    progress::phase(Phase::Writing);
+    throttle::write(output_path, &decrypted.plaintext)
+        .map_err(|e| t!("Failed to write output file: {}", e))?;
+    progress::phase(Phase::Done);
+
+    let mut warnings = match &decrypted.metadata {
+        Some(metadata) if preserve_metadata => metadata.restore(output_path),
+        _ => Vec::new(),
+    };
+    if !decrypted.outdated.is_empty() {
+        warnings.push(migrate::warning(&decrypted.outdated));
+    }
+    if archive::is_archive(&decrypted.plaintext) {
+        warnings.push(t!("This file contains an encrypted folder. Use 'Decrypt folder' to extract it.").to_string());
+    }
 
-    fs::write(output_path, decrypted)
-        .map_err(|e| format!("Failed to write output file: {}", e))?;
+    Ok(OperationReport {
+        input_checksum: opened.input_checksum,
+        output_checksum: checksum::sha256(&decrypted.plaintext),
+        verified: decrypted.verified,
+        warnings,
+        stripped: None,
+        input_changed: false,
+    })
+}
 
+/// Stop with an error if the operation in progress has been cancelled.
+fn check_cancelled() -> Result<(), String> {
+    if progress::cancelled() {
+        return Err(t!("The operation was cancelled.").to_string());
+    }
     Ok(())
 }
 
+/// If `output_path` has no extension and `plaintext` starts with the
+/// signature of a known format, offer to add the usual extension so the
+/// file opens in the right program. Returns the path to write.
+fn suggest_extension(output_path: PathBuf, plaintext: &[u8]) -> PathBuf {
+    if output_path.extension().is_some() {
+        return output_path;
+    }
+    let Some((kind, extension)) = content::detect_extension(plaintext) else {
+        return output_path;
+    };
+    let suggested = paths::with_suffix(&output_path, &format!(".{}", extension));
+    match tty::prompt(&t!(
+        "The output looks like {}. Save it as '{}' instead? [Y/n]: ",
+        kind,
+        paths::display(&suggested)
+    ))
+        .to_lowercase()
+        .as_str()
+    {
+        "n" | "no" => output_path,
+        _ => suggested,
+    }
+}
+
+/// Pack a folder into an archive and encrypt it into a single file,
+/// skipping entries that `filter` excludes.
+fn encrypt_folder(
+    input_path: &Path,
+    output_path: &Path,
+    password: &str,
+    filter: &Filter,
+    preserve_metadata: bool,
+) -> Result<ArchiveSummary, String> {
+    let key_bytes = password.as_bytes();
+    if key_bytes.is_empty() {
+        return Err(t!("Password cannot be empty.").to_string());
+    }
+
+    let _claims = claims::claim_all(&[input_path, output_path], "encrypt")?;
+    let _operation = progress::begin(Phase::Reading, None);
+    let (data, summary) = {
+        let _span = trace::span("pack");
+        archive::pack(input_path, filter)?
+    };
+    memory::check_contents(data.len() as u64, &format!("The packed folder '{}'", input_path.display()))?;
+
+    let metadata = if preserve_metadata {
+        FileMetadata::capture(input_path)?
+    } else {
+        FileMetadata::default()
+    };
+
+    progress::phase(Phase::Encrypting);
+    let encrypted = parity::protect_if_enabled(encrypt_bytes(&data, &metadata.to_bytes(), key_bytes)?);
+
+    check_cancelled()?;
+    progress::phase(Phase::Writing);
+    throttle::write(output_path, &encrypted)
+        .map_err(|e| t!("Failed to write output file: {}", e))?;
+    progress::phase(Phase::Done);
+
+    Ok(summary)
+}
+
+/// Decrypt a file made by `encrypt_folder` and unpack it into a new folder.
+fn decrypt_folder(
+    input_path: &Path,
+    output_path: &Path,
+    password: &str,
+    preserve_metadata: bool,
+    legacy_xor: bool,
+) -> Result<ArchiveSummary, String> {
+    let _claims = claims::claim_all(&[input_path, output_path], "decrypt")?;
+    memory::check_file(input_path)?;
+    let _operation = progress::begin(Phase::Reading, fs::metadata(input_path).ok().map(|info| info.len()));
+    let data = throttle::read(input_path).map_err(|e| t!("Failed to read input file: {}", e))?;
+    let key_bytes = password.as_bytes();
+    if key_bytes.is_empty() {
+        return Err(t!("Password cannot be empty.").to_string());
+    }
+
+    progress::phase(Phase::Decrypting);
+    let decrypted = decrypt_bytes(&data, key_bytes, legacy_xor)?;
+    if !archive::is_archive(&decrypted.plaintext) {
+        return Err(t!("This file contains a single file, not a folder. Use 'Decrypt file' instead.").to_string());
+    }
+
+    check_cancelled()?;
+    progress::phase(Phase::Writing);
+    let mut summary = {
+        let _span = trace::span("unpack");
+        archive::unpack(&decrypted.plaintext, output_path)?
+    };
+    progress::phase(Phase::Done);
+    if let Some(metadata) = &decrypted.metadata
+        && preserve_metadata
+    {
+        summary.warnings.extend(metadata.restore(output_path));
+    }
+    if !decrypted.outdated.is_empty() {
+        summary.warnings.push(migrate::warning(&decrypted.outdated));
+    }
+
+    Ok(summary)
+}
+
+/// Print what a folder operation packed or unpacked.
+fn print_archive_summary(summary: &ArchiveSummary) {
+    println!(
+        "{}",
+        t!(
+            "{} file(s), {} folder(s), {} hard link(s), {} symlink(s).",
+            summary.files,
+            summary.directories,
+            summary.hard_links,
+            summary.symlinks
+        )
+    );
+    if summary.excluded > 0 {
+        println!("{}", t!("{} entr(ies) skipped by the filters.", summary.excluded));
+    }
+    if summary.stripped > 0 {
+        println!("{}", t!("Removed the metadata from {} file(s) before encrypting them.", summary.stripped));
+    }
+    if summary.changed > 0 {
+        println!("{}", t!("{} file(s) kept changing while they were read.", summary.changed));
+    }
+    for warning in &summary.warnings {
+        println!("{}", t!("Warning: {}", warning));
+    }
+}
+
+/// Build the encrypted form of `data` in the current format, with a fresh
+/// random salt and nonce.
+///
+/// The organization policy decides the minimum key derivation cost and
+/// which recipients the keys are also wrapped for, besides any chosen
+/// with `--recipient`.
+fn encrypt_bytes(data: &[u8], metadata: &[u8], password: &[u8]) -> Result<Vec<u8>, String> {
+    let (params, keys) = new_file_keys(password)?;
+    encrypt_with_keys(data, metadata, &params, &keys)
+}
+
+/// Pick a fresh salt, derive the keys for new files from `password`, and
+/// wrap them for the recipients the policy requires and those chosen with
+/// `--recipient`. Several files can be
+/// encrypted with the result by `encrypt_with_keys`, paying for the key
+/// derivation once.
+fn new_file_keys(password: &[u8]) -> Result<(SealParams, Keys), String> {
+    let policy = policy::get();
+    policy.check_encrypt()?;
+    let mut params = SealParams {
+        iterations: format::DEFAULT_ITERATIONS.max(policy.min_kdf_iterations),
+        salt: [0; format::SALT_LEN],
+        nonce: [0; crypto::NONCE_LEN],
+        stanzas: Vec::new(),
+        cipher: cpu::cipher_in_use(),
+    };
+    crypto::random_bytes(&mut params.salt)?;
+    let keys = progress::deriving_key(params.iterations, || sealing::derive_keys(password, &params));
+    let keys = match totp::secret_for_new_files()? {
+        Some(secret) => totp::bind_keys(&keys, &secret),
+        None => keys,
+    };
+    let mut wrapped = [0u8; recipients::WRAPPED_LEN];
+    wrapped[..crypto::KEY_LEN].copy_from_slice(&keys.cipher);
+    wrapped[crypto::KEY_LEN..].copy_from_slice(&keys.mac);
+    let mut chosen: Vec<&[u8; ed25519::KEY_LEN]> = policy.required_recipients.iter().collect();
+    for recipient in recipients::chosen() {
+        if !chosen.contains(&recipient) {
+            chosen.push(recipient);
+        }
+    }
+    if chosen.len() > u8::MAX as usize {
+        return Err(format!("Files can be encrypted for at most {} recipients.", u8::MAX));
+    }
+    for recipient in chosen {
+        params.stanzas.push(recipients::wrap(&wrapped, recipient)?);
+    }
+    Ok((params, keys))
+}
+
+/// Encrypt with keys from `new_file_keys` and a fresh random nonce.
+fn encrypt_with_keys(
+    data: &[u8],
+    metadata: &[u8],
+    params: &SealParams,
+    keys: &Keys,
+) -> Result<Vec<u8>, String> {
+    memory::check_contents(data.len() as u64, "The data to encrypt")?;
+    if data.len() as u64 + metadata.len() as u64 > format::MAX_PAYLOAD_LEN {
+        return Err("Files larger than 256 GiB cannot be encrypted.".to_string());
+    }
+    let mut params = params.clone();
+    let payload_len = (SHA256_LEN + METADATA_LEN_FIELD + metadata.len() + data.len()) as u64;
+    if params.cipher == Cipher::AesGcm && payload_len > aes::GCM_MAX_LEN {
+        // One GCM nonce covers just under 64 GiB; the keys are the same
+        // for ChaCha20.
+        if !policy::get().allow_chacha20 {
+            return Err("The organization policy allows only AES-256-GCM, which cannot encrypt files larger than 64 GiB.".to_string());
+        }
+        params.cipher = Cipher::ChaCha20;
+    }
+    crypto::random_bytes(&mut params.nonce)?;
+    let _span = trace::span("cipher").with("bytes", data.len() as u64);
+    Ok(sealing::seal_with_keys(data, metadata, keys, &params))
+}
+
+/// Reverse `encrypt_bytes`, for any format version. When the data has a
+/// header, the tag or stored checksum is checked. Data from before the
+/// header existed is decrypted as-is. Files from the XOR era (versions 1
+/// and 2 and headerless files) are refused unless `legacy_xor` is set.
+///
+/// Parity appended by `--parity` is left out first, after rebuilding any
+/// blocks found damaged.
+fn decrypt_bytes(data: &[u8], key_bytes: &[u8], legacy_xor: bool) -> Result<Decrypted, OpenError> {
+    let (data, repair) = parity::repair(data)?;
+    let data = &*data;
+    if repair.repaired > 0 {
+        eprintln!("Repaired {} damaged block(s) of the encrypted data from its parity.", repair.repaired);
+    }
+    if repair.checksums_lost > 0 {
+        eprintln!(
+            "Warning: {} parity checksum(s) are damaged in both copies, so damage to the blocks they cover may not have been repaired.",
+            repair.checksums_lost
+        );
+    }
+    let header = format::split_header(data)?;
+    if let Some((version, _)) = header
+        && version >= SEALED_VERSION
+    {
+        let (plaintext, metadata) = open_sealed(data, key_bytes)?;
+        return Ok(Decrypted {
+            plaintext,
+            metadata: Some(metadata),
+            verified: true,
+            outdated: migrate::weaknesses(data),
+        });
+    }
+
+    if !legacy_xor {
+        return Err(t!("This file uses the old XOR cipher, which is not secure. Run with --legacy-xor to decrypt it, or upgrade it with 'migrate --legacy-xor FILE'.").to_string().into());
+    }
+    policy::get().check_legacy_xor()?;
+    match header {
+        Some((version, payload)) => {
+            let (plaintext, metadata) = open_payload(version, payload, key_bytes)?;
+            Ok(Decrypted {
+                plaintext,
+                metadata,
+                verified: true,
+                outdated: migrate::weaknesses(data),
+            })
+        }
+        None => Ok(Decrypted {
+            plaintext: xor_with_key(data, key_bytes),
+            metadata: None,
+            verified: false,
+            outdated: migrate::weaknesses(data),
+        }),
+    }
+}
+
+/// The warning shown when a file to encrypt kept changing while it was
+/// read (see `throttle::read_consistent`).
+fn input_changed_warning(path: &str) -> String {
+    t!("'{}' kept changing while it was read, so its encrypted copy may mix old and new contents. Encrypt it again once it is no longer being written to.", path).to_string()
+}
+
+/// Check the tag of a sealed file, then decrypt it and return the
+/// plaintext and its metadata.
+fn open_sealed(data: &[u8], password: &[u8]) -> Result<(Vec<u8>, FileMetadata), OpenError> {
+    let sealed = format::split_sealed(data)?;
+    let keys = progress::deriving_key(sealed.params.iterations, || sealing::derive_keys(password, &sealed.params));
+    open_sealed_with_keys(&sealed, &keys)
+}
+
+/// Open a sealed file with the private key of one of its recipients
+/// instead of the password.
+fn open_sealed_for_recipient(
+    data: &[u8],
+    seed: &[u8; ed25519::KEY_LEN],
+) -> Result<(Vec<u8>, FileMetadata), String> {
+    let sealed = format::split_sealed(data).map_err(format::message)?;
+    if sealed.params.stanzas.is_empty() {
+        return Err("This file was not encrypted for any recipient keys. Decrypt it with its password.".to_string());
+    }
+    let wrapped = sealed
+        .params
+        .stanzas
+        .iter()
+        .find_map(|stanza| recipients::unwrap(stanza, seed))
+        .ok_or_else(|| "This file was not encrypted for this key.".to_string())?;
+    let mut keys = Keys {
+        cipher: [0; crypto::KEY_LEN],
+        mac: [0; crypto::KEY_LEN],
+    };
+    keys.cipher.copy_from_slice(&wrapped[..crypto::KEY_LEN]);
+    keys.mac.copy_from_slice(&wrapped[crypto::KEY_LEN..]);
+    Ok(open_sealed_with_keys(&sealed, &keys)?)
+}
+
+/// Check the tag of a split sealed file with `keys`, then decrypt it and
+/// ask for the authenticator code if the file needs one.
+fn open_sealed_with_keys(
+    sealed: &format::Sealed<'_>,
+    keys: &Keys,
+) -> Result<(Vec<u8>, FileMetadata), OpenError> {
+    let opened = unseal(sealed, keys)?;
+    if let Some(secret) = &opened.totp_secret {
+        totp::check(secret)?;
+    }
+    Ok((opened.plaintext, opened.metadata))
+}
+
+/// A sealed file opened by `unseal`.
+struct Opened {
+    plaintext: Vec<u8>,
+    metadata: FileMetadata,
+    /// The authenticator secret to check a current code against before
+    /// the plaintext is used, for files encrypted with `--totp`.
+    totp_secret: Option<Vec<u8>>,
+}
+
+/// Check the tag of a split sealed file with `keys` and decrypt it,
+/// leaving any authenticator code check to the caller.
+fn unseal(sealed: &format::Sealed<'_>, keys: &Keys) -> Result<Opened, OpenError> {
+    let (keys, totp_secret) = {
+        let _span = trace::span("mac").with("bytes", sealed.authenticated.len() as u64);
+        matching_keys(sealed, keys)?
+    };
+    memory::check_contents(sealed.ciphertext.len() as u64, "This file")?;
+    let unsealed = {
+        let _span = trace::span("cipher").with("bytes", sealed.ciphertext.len() as u64);
+        sealing::decrypt(sealed, &keys)?
+    };
+    let metadata = FileMetadata::from_bytes(&unsealed.metadata)?;
+    Ok(Opened {
+        plaintext: unsealed.plaintext,
+        metadata,
+        totp_secret,
+    })
+}
+
+/// The keys the tag of `sealed` matches: `keys` from the password, or for
+/// a file encrypted with `--totp`, `keys` bound to this user's
+/// authenticator secret, which is returned with them.
+fn matching_keys(sealed: &format::Sealed<'_>, keys: &Keys) -> Result<(Keys, Option<Vec<u8>>), OpenError> {
+    if sealing::check_tag(sealed, keys).is_ok() {
+        return Ok((keys.clone(), None));
+    }
+    if let Some(secret) = totp::enrolled()? {
+        let bound = totp::bind_keys(keys, &secret);
+        if sealing::check_tag(sealed, &bound).is_ok() {
+            return Ok((bound, Some(secret)));
+        }
+    }
+    Err(FormatError::AuthenticationFailed.into())
+}
+
+/// Decrypt the payload of an XOR-era file with a header, check the stored checksum,
+/// and return the plaintext along with the metadata block if the version
+/// has one.
+fn open_payload(
+    version: u8,
+    payload: &[u8],
+    key_bytes: &[u8],
+) -> Result<(Vec<u8>, Option<FileMetadata>), OpenError> {
+    let decrypted = xor_with_key(payload, key_bytes);
+    let parts = format::parse_payload(version, &decrypted)?;
+    if checksum::sha256(parts.plaintext)[..] != parts.checksum[..] {
+        return Err(FormatError::Garbled.into());
+    }
+
+    let metadata = if version >= 2 {
+        Some(FileMetadata::from_bytes(parts.metadata)?)
+    } else {
+        None
+    };
+    Ok((parts.plaintext.to_vec(), metadata))
+}
+
+/// Decrypt only the first `max_bytes` of a file and return them, so the
+/// user can check the file and password without reading the whole file.
+/// Also returns whether the password was confirmed.
+fn preview_file(
+    input_path: &Path,
+    password: &str,
+    max_bytes: u64,
+    legacy_xor: bool,
+) -> Result<(Vec<u8>, bool), String> {
+    let key_bytes = password.as_bytes();
+    if key_bytes.is_empty() {
+        return Err(t!("Password cannot be empty.").to_string());
+    }
+
+    let read_error = |e: io::Error| t!("Failed to read input file: {}", e);
+    let mut file = fs::File::open(input_path).map_err(read_error)?;
+
+    // Read just enough to find where the plaintext starts.
+    let mut prefix = Vec::new();
+    (&mut file)
+        .take((HEADER_LEN + SHA256_LEN + METADATA_LEN_FIELD) as u64)
+        .read_to_end(&mut prefix)
+        .map_err(read_error)?;
+
+    let header = format::split_header(&prefix).map_err(format::message)?;
+    if let Some((version, _)) = header
+        && version >= SEALED_VERSION
+    {
+        return Ok((preview_sealed(file, key_bytes, max_bytes)?, true));
+    }
+    if !legacy_xor {
+        return Err(t!("This file uses the old XOR cipher, which is not secure. Run with --legacy-xor to decrypt it, or upgrade it with 'migrate --legacy-xor FILE'.").to_string());
+    }
+    policy::get().check_legacy_xor()?;
+
+    let (payload_start, skip) = match header {
+        Some((version, payload)) => {
+            let mut skip = SHA256_LEN;
+            if version >= 2 {
+                let len_field = payload.get(SHA256_LEN..).unwrap_or_default();
+                let len_field = xor_with_key_from(len_field, key_bytes, SHA256_LEN);
+                skip += METADATA_LEN_FIELD + format::metadata_len(&len_field).map_err(format::message)?;
+            }
+            (HEADER_LEN, skip)
+        }
+        None => (0, 0),
+    };
+
+    file.seek(SeekFrom::Start((payload_start + skip) as u64))
+        .map_err(read_error)?;
+    let mut data = Vec::new();
+    file.take(max_bytes)
+        .read_to_end(&mut data)
+        .map_err(read_error)?;
+
+    // Only sealed files are checked before previewing.
+    Ok((xor_with_key_from(&data, key_bytes, skip), false))
+}
+
+/// Preview a sealed file. The tag is checked by streaming through the
+/// whole file, but only the requested part is decrypted and kept.
+fn preview_sealed(mut file: fs::File, password: &[u8], max_bytes: u64) -> Result<Vec<u8>, OpenError> {
+    let read_error = |e: io::Error| t!("Failed to read input file: {}", e);
+    let file_len = file.metadata().map_err(read_error)?.len();
+
+    // Headers after version 3 carry recipient stanzas after the fixed
+    // part, with their count in the first byte after it.
+    let mut header = vec![0u8; format::SEALED_HEADER_LEN];
+    file.seek(SeekFrom::Start(0)).map_err(read_error)?;
+    file.read_exact(&mut header).map_err(read_error)?;
+    if header[format::FILE_MAGIC.len()] != format::SEALED_VERSION {
+        let mut count = [0u8; 1];
+        file.read_exact(&mut count).map_err(read_error)?;
+        header.push(count[0]);
+        let mut stanzas = vec![0u8; count[0] as usize * format::STANZA_LEN];
+        file.read_exact(&mut stanzas).map_err(read_error)?;
+        header.extend_from_slice(&stanzas);
+    }
+    let params = format::parse_sealed_header(&header)?;
+    let header_len = header.len() as u64;
+    let body_len = file_len
+        .checked_sub(header_len + params.cipher.tag_len() as u64)
+        .ok_or(FormatError::Truncated)?;
+    let keys = progress::deriving_key(params.iterations, || sealing::derive_keys(password, &params));
+    // A file encrypted with --totp is tagged with the keys bound to this
+    // user's authenticator secret, so both tags are worked out in one pass.
+    let bound = totp::enrolled()?.map(|secret| (totp::bind_keys(&keys, &secret), secret));
+
+    let mut tagger = sealing::Tagger::new(&params, &keys, &header);
+    let mut bound_tagger = bound.as_ref().map(|(keys, _)| sealing::Tagger::new(&params, keys, &header));
+    let mut update = |data: &[u8]| {
+        tagger.update(data);
+        if let Some(bound_tagger) = &mut bound_tagger {
+            bound_tagger.update(data);
+        }
+    };
+    let mut remaining = body_len;
+    let mut buffer = buffers::take();
+    while remaining > 0 {
+        let take = remaining.min(buffer.len() as u64) as usize;
+        file.read_exact(&mut buffer[..take]).map_err(read_error)?;
+        update(&buffer[..take]);
+        remaining -= take as u64;
+    }
+    let mut tag = vec![0u8; params.cipher.tag_len()];
+    file.read_exact(&mut tag).map_err(read_error)?;
+    let (keys, bound_secret) = if tagger.matches(&tag) {
+        (keys, None)
+    } else {
+        match (bound, bound_tagger.map(|bound_tagger| bound_tagger.matches(&tag))) {
+            (Some((keys, secret)), Some(true)) => (keys, Some(secret)),
+            _ => return Err(FormatError::AuthenticationFailed.into()),
+        }
+    };
+    if let Some(secret) = &bound_secret {
+        totp::check(secret)?;
+    }
+
+    // Find where the plaintext starts, then decrypt just the part asked for.
+    let mut len_field = [0u8; METADATA_LEN_FIELD];
+    let len_position = SHA256_LEN as u64;
+    file.seek(SeekFrom::Start(header_len + len_position))
+        .map_err(read_error)?;
+    file.read_exact(&mut len_field).map_err(read_error)?;
+    sealing::xor_keystream(&params, &keys, len_position, &mut len_field);
+    let skip = len_position + (METADATA_LEN_FIELD + format::metadata_len(&len_field)?) as u64;
+
+    let available = body_len.checked_sub(skip).ok_or(FormatError::Garbled)?;
+    file.seek(SeekFrom::Start(header_len + skip))
+        .map_err(read_error)?;
+    let mut data = vec![0u8; available.min(max_bytes) as usize];
+    file.read_exact(&mut data).map_err(read_error)?;
+    sealing::xor_keystream(&params, &keys, skip, &mut data);
+    Ok(data)
+}
+
+/// Print bytes as a classic hex dump: offset, sixteen hex bytes, and the
+/// printable ASCII characters.
+fn print_hex_dump(data: &[u8]) {
+    for (row, chunk) in data.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|byte| format!("{:02x}", byte)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&byte| {
+                if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        println!("{:08x}  {:<47}  {}", row * 16, hex.join(" "), ascii);
+    }
+}
+
+/// `data` as text that is safe to print: control characters other than
+/// line breaks and tabs, such as the escape that starts terminal commands,
+/// are shown as `\x1b` instead of being passed to the terminal.
+fn printable_text(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len());
+    for c in String::from_utf8_lossy(data).chars() {
+        if c.is_control() && c != '\n' && c != '\t' {
+            text.push_str(&format!("\\x{:02x}", c as u32));
+        } else {
+            text.push(c);
+        }
+    }
+    text
+}
+
 /// Core XOR function that applies the key bytes repeatedly across the data.
 fn xor_with_key(data: &[u8], key: &[u8]) -> Vec<u8> {
-    data.iter()
-        .enumerate()
-        .map(|(i, byte)| {
-            let key_byte = key[i % key.len()];
-            byte ^ key_byte
-        })
-        .collect()
+    xor_with_key_from(data, key, 0)
+}
+
+/// XOR data that starts `offset` bytes into a longer stream, so the key
+/// lines up the same way it would if the whole stream were processed.
+fn xor_with_key_from(data: &[u8], key: &[u8], offset: usize) -> Vec<u8> {
+    // Repeat the key into a block of whole copies, starting where `offset`
+    // falls, so the data can be XORed a block at a time with wide
+    // instructions instead of byte by byte.
+    let copies = XOR_BLOCK_LEN.div_ceil(key.len());
+    let block: Vec<u8> = key
+        .iter()
+        .cycle()
+        .skip(offset % key.len())
+        .take(key.len() * copies)
+        .copied()
+        .collect();
+
+    let mut output = data.to_vec();
+    for chunk in output.chunks_mut(block.len()) {
+        crypto::xor_in_place(chunk, &block);
+    }
+    output
+}
+
+/// Show why decrypted output looks wrong and ask whether to write it
+/// anyway.
+fn confirm_suspicious_output(reason: &str) -> bool {
+    println!("{}", t!("Warning: {}", reason));
+    // Accept the Spanish "s" too, matching the translated prompt.
+    matches!(tty::prompt(t!("Write it anyway? [y/N]: ")).to_lowercase().as_str(), "y" | "s")
+}
+
+/// Ask for an optional note and `#tags` to remember an operation by.
+fn read_label() -> String {
+    print!("{}", t!("Note or #tags for the history (leave blank for none): "));
+    flush_stdout();
+    read_line_trimmed()
+}
+
+/// Read which of `count` numbered items the user picked, such as `1 3 5-7`
+/// or `all`, as indexes from 0, in order and without repeats.
+fn parse_selection(text: &str, count: usize) -> Result<Vec<usize>, String> {
+    if text.eq_ignore_ascii_case("all") || text == "*" {
+        return Ok((0..count).collect());
+    }
+    let invalid = |part: &str| t!("'{}' is not a number or range from 1 to {}.", part, count);
+    let mut chosen = vec![false; count];
+    for part in text.split([' ', ',']).filter(|part| !part.is_empty()) {
+        let number = |text: &str| {
+            text.trim()
+                .parse::<usize>()
+                .ok()
+                .filter(|number| (1..=count).contains(number))
+                .ok_or_else(|| invalid(part))
+        };
+        let (first, last) = match part.split_once('-') {
+            Some((first, last)) => (number(first)?, number(last)?),
+            None => (number(part)?, number(part)?),
+        };
+        if first > last {
+            return Err(invalid(part));
+        }
+        chosen[first - 1..last].fill(true);
+    }
+    Ok((0..count).filter(|&index| chosen[index]).collect())
 }
 
 /// Read a line from stdin, trim whitespace, and return it as a String.
+/// Bytes that are not valid UTF-8 become replacement characters.
 fn read_line_trimmed() -> String {
-    let mut input = String::new();
+    String::from_utf8_lossy(&read_line_bytes()).trim().to_string()
+}
+
+/// Read a line from stdin as a path, trimming whitespace around it. Unlike
+/// `read_line_trimmed`, names that are not valid Unicode come through
+/// intact on Unix, and a `content://` or `file://` URI pasted from Android
+/// becomes the path it names.
+fn read_path() -> PathBuf {
+    let line = read_line_bytes();
+    if let Ok(text) = std::str::from_utf8(line.trim_ascii()) {
+        match storage::resolve(text) {
+            Ok(Some(path)) => return PathBuf::from(path),
+            Ok(None) => {}
+            // Used as typed, so the error for the missing file follows.
+            Err(e) => eprintln!("Warning: {}", e),
+        }
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStringExt;
+        PathBuf::from(std::ffi::OsString::from_vec(line.trim_ascii().to_vec()))
+    }
+    #[cfg(not(unix))]
+    {
+        PathBuf::from(String::from_utf8_lossy(line.trim_ascii()).into_owned())
+    }
+}
+
+fn read_line_bytes() -> Vec<u8> {
+    let mut line = Vec::new();
     io::stdin()
-        .read_line(&mut input)
+        .lock()
+        .read_until(b'\n', &mut line)
         .expect("Failed to read line from stdin.");
-    input.trim().to_string()
+    line
 }
 
 /// Ensure that printed prompts appear before the user types input.
@@ -237,10 +1714,291 @@ fn flush_stdout() {
     io::stdout().flush().expect("Failed to flush stdout.");
 }
 
+/// Remove `--bwlimit RATE` (or `--bwlimit=RATE`) from the arguments and
+/// return the rate in bytes per second. Rates use the same suffixes as
+/// sizes, so `10M` is 10 MiB per second.
+fn take_bwlimit(args: &mut Vec<String>) -> Result<Option<u64>, String> {
+    let Some(value) = take_value(args, "--bwlimit")? else {
+        return Ok(None);
+    };
+    match filter::parse_size(&value)? {
+        0 => Err("'--bwlimit' must be greater than zero.".to_string()),
+        limit => Ok(Some(limit)),
+    }
+}
+
+/// Remove `--max-memory SIZE` from the arguments and keep file contents
+/// within it for the rest of the run.
+fn take_max_memory(args: &mut Vec<String>) -> Result<(), String> {
+    let Some(value) = take_value(args, "--max-memory")? else {
+        return Ok(());
+    };
+    memory::set_limit(filter::parse_size(&value)?)
+}
+
+/// Remove `--parity PERCENT` from the arguments and add that much parity
+/// to every file encrypted in this run, or `parity_percent` from the
+/// config when the flag is not given.
+fn take_parity(args: &mut Vec<String>, config: &Config) -> Result<(), String> {
+    let percent = match take_value(args, "--parity")? {
+        Some(value) => config::parse_number("--parity", value.trim_end_matches('%'))?,
+        None => config.parity_percent as u64,
+    };
+    parity::set_percent(percent)
+}
+
+/// Remove every `--recipient NAME` from the arguments and wrap the keys of
+/// new files for each one too. A name is looked up among the contacts
+/// first, then read as a public key. Each recipient is shown with its
+/// fingerprint, with a warning for keys nobody has verified. Revoked keys
+/// are refused unless `--allow-revoked` is given too.
+fn take_recipients(args: &mut Vec<String>, config: &Config) -> Result<(), String> {
+    let allow_revoked = take_flag(args, "--allow-revoked");
+    let mut chosen = Vec::new();
+    while let Some(name) = take_value(args, "--recipient")? {
+        let (key, contact) = contacts::resolve(config, &name)?;
+        if ed25519::public_to_x25519(&key).is_none() {
+            return Err(format!("'{}' is not a valid public key.", name));
+        }
+        let fingerprint = signature::fingerprint(&key);
+        match contact {
+            Some(contact) if contact.revoked && !allow_revoked => {
+                return Err(format!(
+                    "The key of '{}' ({}) has been revoked. Ask them for their new key, or add --allow-revoked to encrypt for it anyway.",
+                    contact.name, fingerprint
+                ));
+            }
+            Some(contact) => {
+                let email = contact.email.map(|email| format!(" <{}>", email)).unwrap_or_default();
+                eprintln!("Recipient: {}{} ({})", contact.name, email, fingerprint);
+                if contact.revoked {
+                    eprintln!("Warning: the key of '{}' has been revoked.", contact.name);
+                } else if !contact.verified {
+                    eprintln!(
+                        "Warning: the key of '{}' has not been verified. Compare its fingerprint with them in person or by phone, then run 'contact verify {}'.",
+                        contact.name, contact.name
+                    );
+                }
+            }
+            None => {
+                eprintln!("Recipient: {} ({})", name, fingerprint);
+                eprintln!("Warning: '{}' is not a contact, so its key has not been verified.", name);
+            }
+        }
+        if !chosen.contains(&key) {
+            chosen.push(key);
+        }
+    }
+    recipients::choose(chosen);
+    Ok(())
+}
+
+/// Where in `args` an option matching `matches` is, passing over every
+/// argument that is the value of the option before it, so that an output
+/// file named like a global flag is left alone.
+fn option_position(args: &[String], matches: impl Fn(&str) -> bool) -> Option<usize> {
+    let mut index = 0;
+    while index < args.len() {
+        if matches(&args[index]) {
+            return Some(index);
+        }
+        index += if cli::takes_value(&args[index]) { 2 } else { 1 };
+    }
+    None
+}
+
+/// Remove a global switch such as `--plain` from `args`, however often it
+/// is given, and return whether it was.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let mut found = false;
+    while let Some(index) = option_position(args, |arg| arg == flag) {
+        args.remove(index);
+        found = true;
+    }
+    found
+}
+
+/// Remove a global `flag VALUE` or `flag=VALUE` from `args` and return the
+/// value.
+fn take_value(args: &mut Vec<String>, flag: &str) -> Result<Option<String>, String> {
+    let joined = format!("{}=", flag);
+    let Some(index) = option_position(args, |arg| arg == flag || arg.starts_with(&joined)) else {
+        return Ok(None);
+    };
+    let arg = args.remove(index);
+    match arg.strip_prefix(&joined) {
+        Some(value) => Ok(Some(value.to_string())),
+        None if index < args.len() => Ok(Some(args.remove(index))),
+        None => Err(format!("'{}' needs a value.", flag)),
+    }
+}
+
+/// Turn on `--deterministic-for-testing SEED`, which must be confirmed
+/// through the environment so it cannot be left on by accident.
+fn take_test_seed(args: &mut Vec<String>) -> Result<(), String> {
+    let Some(seed) = take_value(args, "--deterministic-for-testing")? else {
+        return Ok(());
+    };
+    if env::var_os(TESTING_VARIABLE).is_none_or(|value| value != "1") {
+        return Err(format!(
+            "--deterministic-for-testing makes every file predictable to anyone who knows the seed. Set {}=1 to confirm this is a test.",
+            TESTING_VARIABLE
+        ));
+    }
+    if seed.is_empty() {
+        return Err("'--deterministic-for-testing' needs a seed.".to_string());
+    }
+    crypto::use_test_seed(seed.as_bytes());
+    eprintln!("WARNING: --deterministic-for-testing is on. Salts, nonces, and keys come from the seed, so nothing written in this run is secure. Never use it for real data.");
+    Ok(())
+}
+
 /// Entry point of the program.
 fn main() {
-    println!("Welcome to the Rust File Encryptor.");
-    println!("Note: This is a simple learning project and is not meant for real security.");
-    let mut app = FileCryptoApp::new();
+    let config = Config::load();
+    i18n::init(config.language.as_deref());
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let plain_flag = take_flag(&mut args, "--plain");
+    ui::init(plain_flag || config.plain);
+    let legacy_xor = take_flag(&mut args, "--legacy-xor");
+    let nice_flag = take_flag(&mut args, "--nice");
+    if (nice_flag || config.nice)
+        && let Err(e) = priority::lower()
+    {
+        eprintln!("Warning: {}", e);
+    }
+    if take_flag(&mut args, "--direct-io") || config.direct_io {
+        throttle::enable_direct_io();
+    }
+    if take_flag(&mut args, "--adaptive") || config.adaptive_io {
+        tuning::enable(tuning::Bounds::from_config(&config));
+    }
+    if take_flag(&mut args, "--strip-metadata") || config.strip_metadata {
+        strip::enable();
+    }
+    if take_flag(&mut args, "--totp") {
+        totp::require_for_new_files();
+    }
+    if take_flag(&mut args, "--never-modify-source") || config.never_modify_source {
+        guard::enable();
+    }
+    if take_flag(&mut args, "--progress-json") {
+        progress::report_as_json();
+    }
+    progress::cancel_on_interrupt();
+    if config.io_uring
+        && let Err(e) = throttle::enable_io_uring()
+    {
+        eprintln!("Warning: {}", e);
+    }
+    match take_bwlimit(&mut args) {
+        Ok(Some(limit)) => throttle::init(limit),
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+    if let Err(e) = take_test_seed(&mut args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = take_max_memory(&mut args) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = take_parity(&mut args, &config) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    if let Err(e) = take_recipients(&mut args, &config) {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    match take_value(&mut args, "--trace-output") {
+        Ok(Some(path)) => {
+            if let Err(e) = trace::start(Path::new(&path)) {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+        Ok(None) => {}
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    }
+
+    // Files handed over by Android apps (a Termux widget or share menu)
+    // arrive as URIs rather than paths.
+    for arg in &mut args {
+        match storage::resolve(arg) {
+            Ok(Some(path)) => *arg = path,
+            Ok(None) => {}
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Detect CPU features once, before any crypto runs, so every later
+    // check is a cached lookup.
+    cpu::features();
+
+    // `selftest` reports every result itself; everything else refuses to
+    // run on a build whose crypto gives wrong answers.
+    if args.first().map(String::as_str) != Some("selftest")
+        && let Err(e) = selftest::verify()
+    {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+
+    // The organization policy applies to every command. A policy that
+    // cannot be read is an error rather than no policy, so it cannot be
+    // bypassed by breaking the file.
+    if let Err(e) = policy::load() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+    if legacy_xor && let Err(e) = policy::get().check_legacy_xor() {
+        eprintln!("Error: {}", e);
+        std::process::exit(1);
+    }
+
+    if !args.is_empty() {
+        if let Err(e) = cli::run(&config, &args, legacy_xor) {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+        if guard::enabled() {
+            match guard::verify() {
+                Ok(count) => eprintln!("{} source file(s) checked: unchanged.", count),
+                Err(e) => {
+                    eprintln!("Error: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        if throttle::input_changed() {
+            std::process::exit(EXIT_INPUT_CHANGED);
+        }
+        return;
+    }
+
+    println!("{}", t!("Welcome to the Rust File Encryptor."));
+    println!("{}", t!("Note: This is a simple learning project and is not meant for real security."));
+    if legacy_xor {
+        eprintln!();
+        eprintln!("{}", t!("WARNING: --legacy-xor is on. Files from the old XOR versions can be decrypted, but their contents were never really protected. New files are always written in the current format."));
+    }
+    let mut app = match FileCryptoApp::new(&config, legacy_xor) {
+        Ok(app) => app,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            std::process::exit(1);
+        }
+    };
     app.run();
 }
\ No newline at end of file
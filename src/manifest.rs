@@ -0,0 +1,108 @@
+use std::fs;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::checksum;
+use crate::crypto;
+use crate::format::{DEFAULT_ITERATIONS, SALT_LEN};
+
+/// The line that starts the MAC section. The tag covers every byte of the
+/// manifest before it.
+const MAC_LINE: &str = "  \"mac\": {\n";
+
+/// What happened to one input of a batch.
+pub struct Entry {
+    pub input: String,
+    pub output: String,
+    /// Short status word, such as `encrypted`, `migrated`, or `failed`.
+    pub status: &'static str,
+    pub error: Option<String>,
+}
+
+/// Write a JSON manifest listing each input with its output, the output's
+/// size and SHA-256, and the status, so other tools can check the batch.
+///
+/// The manifest ends with an HMAC-SHA256 tag keyed from `password` with
+/// PBKDF2 and a fresh salt, so anyone with the password can confirm it was
+/// written by this run and not edited since.
+pub fn write(path: &str, entries: &[Entry], password: &[u8]) -> Result<(), String> {
+    let created = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+
+    let mut manifest = String::new();
+    manifest.push_str("{\n");
+    manifest.push_str("  \"tool\": \"File Encryptor\",\n");
+    manifest.push_str(&format!("  \"created\": {},\n", created));
+    manifest.push_str("  \"entries\": [\n");
+    for (index, entry) in entries.iter().enumerate() {
+        manifest.push_str("    ");
+        manifest.push_str(&entry_json(entry));
+        manifest.push_str(if index + 1 < entries.len() { ",\n" } else { "\n" });
+    }
+    manifest.push_str("  ],\n");
+
+    let mut salt = [0u8; SALT_LEN];
+    crypto::random_bytes(&mut salt)?;
+    let mut key = [0u8; crypto::KEY_LEN];
+    crypto::pbkdf2_sha256(password, &salt, DEFAULT_ITERATIONS, &mut key);
+    let tag = crypto::hmac_sha256(&key, manifest.as_bytes());
+
+    manifest.push_str(MAC_LINE);
+    manifest.push_str("    \"algorithm\": \"HMAC-SHA256\",\n");
+    manifest.push_str("    \"kdf\": \"PBKDF2-HMAC-SHA256\",\n");
+    manifest.push_str(&format!("    \"iterations\": {},\n", DEFAULT_ITERATIONS));
+    manifest.push_str(&format!("    \"salt\": \"{}\",\n", checksum::to_hex(&salt)));
+    manifest.push_str(&format!(
+        "    \"covers\": {},\n",
+        json_string("every byte before the line starting with \"mac\"")
+    ));
+    manifest.push_str(&format!("    \"tag\": \"{}\"\n", checksum::to_hex(&tag)));
+    manifest.push_str("  }\n}\n");
+
+    fs::write(path, manifest).map_err(|e| format!("Failed to write manifest '{}': {}", path, e))
+}
+
+/// One entry as a single line of JSON. The output's size and SHA-256 are
+/// read from disk, so they describe what was actually written.
+fn entry_json(entry: &Entry) -> String {
+    let mut json = format!(
+        "{{\"input\": {}, \"output\": {}, \"status\": {}",
+        json_string(&entry.input),
+        json_string(&entry.output),
+        json_string(entry.status)
+    );
+    if entry.error.is_none()
+        && let Ok(data) = fs::read(&entry.output)
+    {
+        json.push_str(&format!(
+            ", \"size\": {}, \"sha256\": \"{}\"",
+            data.len(),
+            checksum::to_hex(&checksum::sha256(&data))
+        ));
+    }
+    if let Some(error) = &entry.error {
+        json.push_str(&format!(", \"error\": {}", json_string(error)));
+    }
+    json.push('}');
+    json
+}
+
+/// Quote and escape text as a JSON string.
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
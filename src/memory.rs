@@ -0,0 +1,114 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::appendlog;
+use crate::buffers;
+
+/// The most memory file contents may take (`--max-memory`), or 0 for no
+/// limit (the default).
+static LIMIT: AtomicU64 = AtomicU64::new(0);
+
+/// Copies of a file held at once while it is encrypted or decrypted: the
+/// data read, the payload run through the cipher, and the sealed result
+/// (or, when decrypting, the ciphertext, its decryption, and the
+/// plaintext taken from it).
+const FILE_COPIES: u64 = 3;
+
+/// Copies of a record an encrypted log holds at once: the plaintext read
+/// and the sealed record.
+const RECORD_COPIES: u64 = 2;
+
+/// Memory taken whatever the file: the pool of I/O buffers at its fullest,
+/// plus room for headers, metadata, and the rest of the program.
+const BASE: u64 = (buffers::MAX_IDLE * buffers::BUFFER_LEN) as u64 + (4 << 20);
+
+/// Keep file contents under `bytes` for the rest of the run. Fails if even
+/// an empty file would not fit.
+pub fn set_limit(bytes: u64) -> Result<(), String> {
+    if bytes <= BASE {
+        return Err(format!(
+            "'--max-memory' must be more than {}, which the tool needs before reading any file.",
+            mib(BASE)
+        ));
+    }
+    LIMIT.store(bytes, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Whether `--max-memory` is in force.
+pub fn is_limited() -> bool {
+    limit().is_some()
+}
+
+fn limit() -> Option<u64> {
+    match LIMIT.load(Ordering::Relaxed) {
+        0 => None,
+        limit => Some(limit),
+    }
+}
+
+/// Fail with a clear error, before anything is read, if encrypting or
+/// decrypting `path` whole would go over the limit.
+pub fn check_file(path: &Path) -> Result<(), String> {
+    if limit().is_none() {
+        return Ok(());
+    }
+    match std::fs::metadata(path) {
+        Ok(info) if info.is_file() => check_contents(info.len(), &format!("'{}'", path.display())),
+        // Reading it reports the problem.
+        _ => Ok(()),
+    }
+}
+
+/// Fail if `len` bytes of contents, described by `what`, cannot be
+/// encrypted or decrypted within the limit. Catches what `check_file`
+/// cannot see coming, such as a folder packed into one archive.
+pub fn check_contents(len: u64, what: &str) -> Result<(), String> {
+    let Some(limit) = limit() else {
+        return Ok(());
+    };
+    let needed = len.saturating_mul(FILE_COPIES).saturating_add(BASE);
+    if needed > limit {
+        return Err(format!(
+            "{} needs about {} of memory, more than the {} allowed by --max-memory. Files are encrypted whole, so split it or raise the limit.",
+            what,
+            mib(needed),
+            mib(limit)
+        ));
+    }
+    Ok(())
+}
+
+/// Chunk size for a new encrypted log: `appendlog::DEFAULT_CHUNK_SIZE`, or
+/// less if that would go over the limit.
+pub fn log_chunk_size() -> usize {
+    let Some(limit) = limit() else {
+        return appendlog::DEFAULT_CHUNK_SIZE;
+    };
+    let fits = ((limit - BASE) / RECORD_COPIES) as usize;
+    // Whole multiples of the smallest size, to keep sizes round.
+    let fits = fits / appendlog::MIN_CHUNK_SIZE * appendlog::MIN_CHUNK_SIZE;
+    appendlog::DEFAULT_CHUNK_SIZE.min(fits).max(appendlog::MIN_CHUNK_SIZE)
+}
+
+/// Fail if records of `chunk_size` bytes cannot be handled within the
+/// limit, as with a log created elsewhere with large chunks.
+pub fn check_log_chunk_size(chunk_size: usize) -> Result<(), String> {
+    let Some(limit) = limit() else {
+        return Ok(());
+    };
+    let needed = (chunk_size as u64).saturating_mul(RECORD_COPIES).saturating_add(BASE);
+    if needed > limit {
+        return Err(format!(
+            "Records of {} KiB need about {} of memory, more than the {} allowed by --max-memory.",
+            chunk_size >> 10,
+            mib(needed),
+            mib(limit)
+        ));
+    }
+    Ok(())
+}
+
+fn mib(bytes: u64) -> String {
+    format!("{:.1} MiB", bytes as f64 / (1 << 20) as f64)
+}
@@ -0,0 +1,252 @@
+use std::path::Path;
+
+use crate::format::{self, FormatError, Reader};
+
+/// Record tag for the owning user and group.
+const TAG_OWNER: u8 = 1;
+
+/// Record tag for one extended attribute. ACLs and security labels are
+/// stored by the OS as extended attributes, so they travel this way too.
+const TAG_XATTR: u8 = 2;
+
+/// An extended attribute as a raw name and value.
+type Xattr = (Vec<u8>, Vec<u8>);
+
+/// File attributes that are carried inside the encrypted file so they can be
+/// put back on the decrypted output.
+#[derive(Debug, Clone, Default)]
+pub struct FileMetadata {
+    owner: Option<(u32, u32)>,
+    xattrs: Vec<Xattr>,
+}
+
+impl FileMetadata {
+    /// Read the ownership and extended attributes of a file. Platforms
+    /// without support simply produce empty metadata.
+    pub fn capture(path: impl AsRef<Path>) -> Result<Self, String> {
+        let path = path.as_ref();
+        let mut metadata = FileMetadata::default();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::MetadataExt;
+            let info = std::fs::metadata(path)
+                .map_err(|e| format!("Failed to read file attributes: {}", e))?;
+            metadata.owner = Some((info.uid(), info.gid()));
+        }
+
+        metadata.xattrs = xattr::list(path)?;
+        Ok(metadata)
+    }
+
+    /// Apply the stored attributes to `path`. Anything that cannot be
+    /// restored (for example because it needs more privileges) is returned
+    /// as a warning instead of failing the whole operation.
+    pub fn restore(&self, path: impl AsRef<Path>) -> Vec<String> {
+        let path = path.as_ref();
+        let mut warnings = Vec::new();
+
+        #[cfg(unix)]
+        if let Some((uid, gid)) = self.owner {
+            use std::os::unix::fs::MetadataExt;
+            let current = std::fs::metadata(path).map(|info| (info.uid(), info.gid()));
+            if current.ok() != Some((uid, gid))
+                && let Err(e) = std::os::unix::fs::chown(path, Some(uid), Some(gid))
+            {
+                warnings.push(format!("Could not restore owner {}:{}: {}", uid, gid, e));
+            }
+        }
+
+        for (name, value) in &self.xattrs {
+            if let Err(e) = xattr::set(path, name, value) {
+                warnings.push(format!(
+                    "Could not restore attribute '{}': {}",
+                    String::from_utf8_lossy(name),
+                    e
+                ));
+            }
+        }
+
+        warnings
+    }
+
+    /// Serialize the metadata as a list of tagged, length-prefixed records.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        if let Some((uid, gid)) = self.owner {
+            let mut body = Vec::with_capacity(8);
+            body.extend_from_slice(&uid.to_le_bytes());
+            body.extend_from_slice(&gid.to_le_bytes());
+            format::push_record(&mut bytes, TAG_OWNER, &body);
+        }
+
+        for (name, value) in &self.xattrs {
+            let mut body = Vec::with_capacity(2 + name.len() + value.len());
+            body.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            body.extend_from_slice(name);
+            body.extend_from_slice(value);
+            format::push_record(&mut bytes, TAG_XATTR, &body);
+        }
+
+        bytes
+    }
+
+    /// Parse metadata written by `to_bytes`. Unknown record tags are
+    /// skipped so newer files still open.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FormatError> {
+        let mut metadata = FileMetadata::default();
+
+        for (tag, body) in format::read_records(bytes)? {
+            match tag {
+                TAG_OWNER => {
+                    let body: [u8; 8] = body.try_into().map_err(|_| FormatError::BadRecord)?;
+                    let mut reader = Reader::new(&body);
+                    metadata.owner = Some((reader.u32()?, reader.u32()?));
+                }
+                TAG_XATTR => {
+                    let mut reader = Reader::new(body);
+                    let name = reader
+                        .u16()
+                        .and_then(|len| reader.take(len.into()))
+                        .map_err(|_| FormatError::BadRecord)?;
+                    metadata.xattrs.push((name.to_vec(), reader.rest().to_vec()));
+                }
+                _ => {}
+            }
+        }
+
+        Ok(metadata)
+    }
+}
+
+/// Extended attribute access through the C library on Linux.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+mod xattr {
+    use std::ffi::{CString, c_char, c_int, c_void};
+    use std::io;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    unsafe extern "C" {
+        fn llistxattr(path: *const c_char, list: *mut c_char, size: usize) -> isize;
+        fn lgetxattr(
+            path: *const c_char,
+            name: *const c_char,
+            value: *mut c_void,
+            size: usize,
+        ) -> isize;
+        fn lsetxattr(
+            path: *const c_char,
+            name: *const c_char,
+            value: *const c_void,
+            size: usize,
+            flags: c_int,
+        ) -> c_int;
+    }
+
+    /// Convert a path or name into a C string.
+    fn c_string(bytes: &[u8]) -> io::Result<CString> {
+        CString::new(bytes)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "contains a NUL byte"))
+    }
+
+    /// Return every extended attribute name and value on `path`.
+    pub fn list(path: &Path) -> Result<Vec<super::Xattr>, String> {
+        let c_path = c_string(path.as_os_str().as_bytes()).map_err(|e| format!("Invalid path: {}", e))?;
+
+        // SAFETY: `c_path` is a valid C string and a null buffer asks for the size.
+        let size = unsafe { llistxattr(c_path.as_ptr(), std::ptr::null_mut(), 0) };
+        if size < 0 {
+            let error = io::Error::last_os_error();
+            // Filesystems without xattr support simply have nothing to keep.
+            if error.raw_os_error() == Some(95) {
+                return Ok(Vec::new());
+            }
+            return Err(format!("Failed to list extended attributes: {}", error));
+        }
+
+        let mut names = vec![0u8; size as usize];
+        // SAFETY: `names` has room for `names.len()` bytes.
+        let size = unsafe { llistxattr(c_path.as_ptr(), names.as_mut_ptr().cast(), names.len()) };
+        if size < 0 {
+            return Err(format!(
+                "Failed to list extended attributes: {}",
+                io::Error::last_os_error()
+            ));
+        }
+        names.truncate(size as usize);
+
+        let mut attributes = Vec::new();
+        for name in names
+            .split(|&byte| byte == 0)
+            .filter(|name| !name.is_empty())
+        {
+            let c_name = c_string(name).map_err(|e| format!("Invalid attribute name: {}", e))?;
+            // SAFETY: both strings are valid and a null buffer asks for the size.
+            let size =
+                unsafe { lgetxattr(c_path.as_ptr(), c_name.as_ptr(), std::ptr::null_mut(), 0) };
+            if size < 0 {
+                continue;
+            }
+            let mut value = vec![0u8; size as usize];
+            // SAFETY: `value` has room for `value.len()` bytes.
+            let size = unsafe {
+                lgetxattr(
+                    c_path.as_ptr(),
+                    c_name.as_ptr(),
+                    value.as_mut_ptr().cast(),
+                    value.len(),
+                )
+            };
+            if size < 0 {
+                continue;
+            }
+            value.truncate(size as usize);
+            attributes.push((name.to_vec(), value));
+        }
+
+        Ok(attributes)
+    }
+
+    /// Set one extended attribute on `path`.
+    pub fn set(path: &Path, name: &[u8], value: &[u8]) -> io::Result<()> {
+        let c_path = c_string(path.as_os_str().as_bytes())?;
+        let c_name = c_string(name)?;
+        // SAFETY: both strings are valid and `value` is readable for its length.
+        let result = unsafe {
+            lsetxattr(
+                c_path.as_ptr(),
+                c_name.as_ptr(),
+                value.as_ptr().cast(),
+                value.len(),
+                0,
+            )
+        };
+        if result == 0 {
+            Ok(())
+        } else {
+            Err(io::Error::last_os_error())
+        }
+    }
+}
+
+/// Platforms without extended attribute support keep nothing.
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+mod xattr {
+    use std::io;
+    use std::path::Path;
+
+    /// Return every extended attribute name and value on `path`.
+    pub fn list(_path: &Path) -> Result<Vec<super::Xattr>, String> {
+        Ok(Vec::new())
+    }
+
+    /// Set one extended attribute on `path`.
+    pub fn set(_path: &Path, _name: &[u8], _value: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "extended attributes are not supported on this platform",
+        ))
+    }
+}
@@ -0,0 +1,163 @@
+use std::fmt::Write as _;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Counters a long-running service keeps for monitoring, read by the
+/// `stats` request and the optional `/metrics` endpoint while it works.
+pub struct Stats {
+    started: Instant,
+    files_encrypted: AtomicU64,
+    files_decrypted: AtomicU64,
+    bytes_encrypted: AtomicU64,
+    bytes_decrypted: AtomicU64,
+    /// Requests of any kind that failed.
+    errors: AtomicU64,
+    /// Requests accepted but not yet answered.
+    queue_depth: AtomicU64,
+    unlocked: AtomicBool,
+}
+
+impl Stats {
+    pub fn new() -> Self {
+        Stats {
+            started: Instant::now(),
+            files_encrypted: AtomicU64::new(0),
+            files_decrypted: AtomicU64::new(0),
+            bytes_encrypted: AtomicU64::new(0),
+            bytes_decrypted: AtomicU64::new(0),
+            errors: AtomicU64::new(0),
+            queue_depth: AtomicU64::new(0),
+            unlocked: AtomicBool::new(false),
+        }
+    }
+
+    /// Count a file of `bytes` bytes of plaintext that was encrypted or
+    /// decrypted.
+    pub fn file_done(&self, encrypted: bool, bytes: u64) {
+        let (files, total) = if encrypted {
+            (&self.files_encrypted, &self.bytes_encrypted)
+        } else {
+            (&self.files_decrypted, &self.bytes_decrypted)
+        };
+        files.fetch_add(1, Ordering::Relaxed);
+        total.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    pub fn failed(&self) {
+        self.errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn queued(&self) {
+        self.queue_depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dequeued(&self) {
+        self.queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn set_unlocked(&self, unlocked: bool) {
+        self.unlocked.store(unlocked, Ordering::Relaxed);
+    }
+
+    /// The counters in the Prometheus text format.
+    pub fn to_prometheus(&self) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let metrics = [
+            (
+                "file_encryptor_files_total",
+                "counter",
+                "Files encrypted or decrypted.",
+                vec![
+                    ("{action=\"encrypt\"}", load(&self.files_encrypted)),
+                    ("{action=\"decrypt\"}", load(&self.files_decrypted)),
+                ],
+            ),
+            (
+                "file_encryptor_bytes_total",
+                "counter",
+                "Bytes of plaintext encrypted or decrypted.",
+                vec![
+                    ("{action=\"encrypt\"}", load(&self.bytes_encrypted)),
+                    ("{action=\"decrypt\"}", load(&self.bytes_decrypted)),
+                ],
+            ),
+            (
+                "file_encryptor_errors_total",
+                "counter",
+                "Requests that failed.",
+                vec![("", load(&self.errors))],
+            ),
+            (
+                "file_encryptor_queue_depth",
+                "gauge",
+                "Requests accepted but not yet answered.",
+                vec![("", load(&self.queue_depth))],
+            ),
+            (
+                "file_encryptor_unlocked",
+                "gauge",
+                "Whether the password is held (1) or not (0).",
+                vec![("", self.unlocked.load(Ordering::Relaxed) as u64)],
+            ),
+            (
+                "file_encryptor_uptime_seconds",
+                "gauge",
+                "Seconds since the service started.",
+                vec![("", self.started.elapsed().as_secs())],
+            ),
+        ];
+        let mut text = String::new();
+        for (name, kind, help, samples) in metrics {
+            let _ = writeln!(text, "# HELP {} {}", name, help);
+            let _ = writeln!(text, "# TYPE {} {}", name, kind);
+            for (labels, value) in samples {
+                let _ = writeln!(text, "{}{} {}", name, labels, value);
+            }
+        }
+        text
+    }
+}
+
+/// Listen for metrics requests on `address`, such as `127.0.0.1:9464`.
+/// The endpoint has no authentication, so it should only listen where the
+/// monitoring system can reach it; the counters reveal no file names.
+pub fn bind(address: &str) -> Result<TcpListener, String> {
+    TcpListener::bind(address).map_err(|e| format!("Failed to listen for metrics on '{}': {}", address, e))
+}
+
+/// Answer `GET /metrics` on `listener` with `stats` in the Prometheus text
+/// format, from a thread of its own.
+pub fn serve_http(listener: TcpListener, stats: Arc<Stats>) {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(5)));
+            // The request line is all that matters; headers are read only
+            // so the client is not cut off while still sending them.
+            let mut request = Vec::new();
+            let mut buffer = [0u8; 1024];
+            while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < 8192 {
+                match stream.read(&mut buffer) {
+                    Ok(0) | Err(_) => break,
+                    Ok(read) => request.extend_from_slice(&buffer[..read]),
+                }
+            }
+            let line = request.split(|&byte| byte == b'\r').next().unwrap_or_default();
+            let (status, body) = match line.strip_prefix(b"GET /metrics ") {
+                Some(_) => ("200 OK", stats.to_prometheus()),
+                None => ("404 Not Found", "Only GET /metrics is served.\n".to_string()),
+            };
+            let response = format!(
+                "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+}
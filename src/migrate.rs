@@ -0,0 +1,247 @@
+use std::fs::{self, File};
+use std::io::Read;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::checksum;
+use crate::claims;
+use crate::content;
+use crate::format::{self, FORMAT_VERSION, SEALED_VERSION};
+use crate::i18n::t;
+use crate::parity;
+use crate::policy;
+use crate::storage;
+use crate::throttle;
+
+/// What happened to one file during a migration.
+pub enum Outcome {
+    /// The file was rewritten in the current format.
+    Migrated {
+        /// The version it was in before, or `None` for headerless files.
+        from: Option<u8>,
+        /// SHA-256 of the plaintext, which is the same before and after.
+        plaintext_checksum: [u8; checksum::SHA256_LEN],
+        /// Whether the old file had a checksum or tag confirming the
+        /// password. Headerless files have neither.
+        verified: bool,
+    },
+    /// The file was already in the current format, met the policy, and had
+    /// nothing outdated.
+    Current,
+}
+
+/// Something about an encrypted file that falls short of what new files
+/// get, and that `migrate_file` fixes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weakness {
+    /// The XOR cipher, in format `version` (`None` for headerless files).
+    XorCipher(Option<u8>),
+    /// Keys derived with `iterations` rounds of PBKDF2, fewer than the
+    /// `current` ones new files get.
+    LowKdfCost { iterations: u32, current: u32 },
+    /// Not encrypted for every recipient the organization policy requires.
+    MissingRecipient,
+}
+
+impl Weakness {
+    /// Short name kept in the history.
+    pub fn code(self) -> &'static str {
+        match self {
+            Weakness::XorCipher(_) => "xor",
+            Weakness::LowKdfCost { .. } => "kdf",
+            Weakness::MissingRecipient => "recipient",
+        }
+    }
+
+    /// One line saying what is outdated and why it matters.
+    pub fn describe(self) -> String {
+        match self {
+            Weakness::XorCipher(version) => t!(
+                "cipher: XOR ({}), which gives no real protection; anyone who had a copy may have read or changed it",
+                version_name(version)
+            ),
+            Weakness::LowKdfCost { iterations, current } => t!(
+                "key derivation: {} PBKDF2 iterations, fewer than the {} new files get, so the password is quicker to guess",
+                iterations,
+                current
+            ),
+            Weakness::MissingRecipient => {
+                t!("recipients: not encrypted for every recipient the organization policy requires").to_string()
+            }
+        }
+    }
+}
+
+/// What is outdated about the encrypted `data`, judged from its header
+/// alone, so no password is needed.
+pub fn weaknesses(data: &[u8]) -> Vec<Weakness> {
+    let header = match format::split_header(data) {
+        Ok(header) => header,
+        // Decrypting reports a damaged header.
+        Err(_) => return Vec::new(),
+    };
+    match header {
+        None => vec![Weakness::XorCipher(None)],
+        Some((version, _)) if version < SEALED_VERSION => vec![Weakness::XorCipher(Some(version))],
+        Some(_) => {
+            let current = format::DEFAULT_ITERATIONS.max(policy::get().min_kdf_iterations);
+            match format::parse_sealed_header(data) {
+                Ok(params) if params.iterations < current => vec![Weakness::LowKdfCost {
+                    iterations: params.iterations,
+                    current,
+                }],
+                // The iterations are enough, so what the policy misses is a
+                // recipient.
+                Ok(_) if !policy::get().is_met_by(data) => vec![Weakness::MissingRecipient],
+                _ => Vec::new(),
+            }
+        }
+    }
+}
+
+/// `weaknesses` of the file at `path`, reading only its header.
+pub fn file_weaknesses(path: &Path) -> Result<Vec<Weakness>, String> {
+    Ok(weaknesses(&read_header(path)?))
+}
+
+/// The start of the file at `path`, as much as the longest header can
+/// take, which is all `weaknesses` and the policy look at.
+pub fn read_header(path: &Path) -> Result<Vec<u8>, String> {
+    let longest = format::SEALED_HEADER_LEN + 1 + u8::MAX as usize * format::STANZA_LEN;
+    let mut header = Vec::new();
+    File::open(path)
+        .and_then(|file| file.take(longest as u64).read_to_end(&mut header))
+        .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    Ok(header)
+}
+
+/// A warning listing `outdated`, one weakness per line, ending with the
+/// command that upgrades the file.
+pub fn warning(outdated: &[Weakness]) -> String {
+    let mut text = t!("This file uses outdated encryption:").to_string();
+    for weakness in outdated {
+        text.push_str(&format!("\n  - {}", weakness.describe()));
+    }
+    let xor = outdated.iter().any(|weakness| matches!(weakness, Weakness::XorCipher(_)));
+    text.push('\n');
+    text.push_str(&t!("Upgrade it with '{}'.", if xor { "migrate --legacy-xor FILE" } else { "migrate FILE" }));
+    text
+}
+
+/// Rewrite one encrypted file in the current format and to the current
+/// policy, in place. The file is decrypted with `password`, re-encrypted
+/// with the same metadata, checked by decrypting the result, and only then
+/// written over the original. Files from the XOR era are only read with `legacy_xor`.
+pub fn migrate_file(path: &str, password: &str, legacy_xor: bool) -> Result<Outcome, String> {
+    let _claim = claims::claim(Path::new(path), "migrate")?;
+    let data = throttle::read(path).map_err(|e| format!("Failed to read input file: {}", e))?;
+    let key_bytes = password.as_bytes();
+    if key_bytes.is_empty() {
+        return Err("Password cannot be empty.".to_string());
+    }
+
+    // Files in the current format are rewritten only when they fall short
+    // of the organization policy, e.g. after its minimum KDF cost was
+    // raised or a required recipient added, or of what new files get.
+    let from = format::split_header(&data).map_err(format::message)?.map(|(version, _)| version);
+    if from.is_some_and(|version| version >= FORMAT_VERSION)
+        && policy::get().is_met_by(&data)
+        && weaknesses(&data).is_empty()
+    {
+        return Ok(Outcome::Current);
+    }
+
+    let decrypted = crate::decrypt_bytes(&data, key_bytes, legacy_xor)?;
+    if !decrypted.verified
+        && let Some(reason) = content::looks_random(&decrypted.plaintext)
+    {
+        return Err(reason);
+    }
+    let metadata = decrypted
+        .metadata
+        .map(|metadata| metadata.to_bytes())
+        .unwrap_or_default();
+    let migrated = crate::encrypt_bytes(&decrypted.plaintext, &metadata, key_bytes)?;
+    // A file that had parity keeps as much of it.
+    let migrated = match parity::percent_of(&data) {
+        Some(percent) => parity::protect(migrated, percent),
+        None => parity::protect_if_enabled(migrated),
+    };
+
+    let check = crate::decrypt_bytes(&migrated, key_bytes, false)?;
+    if check.plaintext != decrypted.plaintext {
+        return Err("The re-encrypted file did not decrypt to the same contents.".to_string());
+    }
+
+    // Write next to the original and rename over it, so a crash never
+    // leaves a half-written file behind.
+    let temp_path = format!("{}.migrating", path);
+    throttle::write(&temp_path, &migrated)
+        .and_then(|_| storage::rename(&temp_path, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&temp_path);
+            format!("Failed to write output file: {}", e)
+        })?;
+
+    Ok(Outcome::Migrated {
+        from,
+        plaintext_checksum: checksum::sha256(&decrypted.plaintext),
+        verified: decrypted.verified,
+    })
+}
+
+/// Describe a version for people: headerless files have none.
+pub fn version_name(version: Option<u8>) -> String {
+    match version {
+        Some(version) => format!("version {}", version),
+        None => "legacy (no header)".to_string(),
+    }
+}
+
+/// Write a plain-text report listing what happened to each file.
+pub fn write_report(
+    path: &str,
+    results: &[(String, Result<Outcome, String>)],
+) -> Result<(), String> {
+    let started = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0);
+
+    let mut report = String::new();
+    report.push_str("File Encryptor migration report\n");
+    report.push_str(&format!(
+        "Written at: {} (seconds since 1970 UTC)\n",
+        started
+    ));
+    report.push_str(&format!("Target format: version {}\n\n", FORMAT_VERSION));
+
+    for (file, outcome) in results {
+        let line = match outcome {
+            Ok(Outcome::Migrated {
+                from,
+                plaintext_checksum,
+                verified,
+            }) => format!(
+                "MIGRATED  {}: {} -> version {}, plaintext SHA-256 {}{}",
+                file,
+                version_name(*from),
+                FORMAT_VERSION,
+                checksum::to_hex(plaintext_checksum),
+                if *verified {
+                    ""
+                } else {
+                    " (password could not be verified)"
+                }
+            ),
+            Ok(Outcome::Current) => {
+                format!("SKIPPED   {}: already version {}", file, FORMAT_VERSION)
+            }
+            Err(e) => format!("FAILED    {}: {}", file, e),
+        };
+        report.push_str(&line);
+        report.push('\n');
+    }
+
+    fs::write(path, report).map_err(|e| format!("Failed to write report '{}': {}", path, e))
+}
@@ -0,0 +1,137 @@
+use std::path::Path;
+
+use crate::checksum;
+use crate::digest::{self, Algorithm};
+use crate::history;
+use crate::packed;
+
+/// One piece of a name template.
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Text(String),
+    /// The input's name without its last extension.
+    Stem,
+    /// The input's last extension, without the dot.
+    Extension,
+    /// The UTC date of the run, as `YYYY-MM-DD`.
+    Date,
+    /// The input's position in the run, from 1.
+    Counter,
+    /// The first this many hex digits of the SHA-256 of the input.
+    Hash(usize),
+}
+
+/// How outputs are named, such as `{stem}_{date}_{hash8}.enc`, so an
+/// organization can follow one naming convention across every run.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    parts: Vec<Part>,
+    text: String,
+}
+
+impl Template {
+    /// Read a template. Variables are written in braces: `{stem}`, `{ext}`,
+    /// `{date}`, `{counter}`, `{hash}` (all 64 hex digits), and `{hash8}`
+    /// or any other length up to 64; `{{` and `}}` stand for a brace.
+    pub fn parse(text: &str) -> Result<Self, String> {
+        if text.contains(['/', '\\']) {
+            return Err(format!(
+                "The name template '{}' cannot contain folders; it only names the file.",
+                text
+            ));
+        }
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = text;
+        while let Some(at) = rest.find(['{', '}']) {
+            literal.push_str(&rest[..at]);
+            let brace = &rest[at..];
+            if brace.starts_with("{{") || brace.starts_with("}}") {
+                literal.push_str(&brace[..1]);
+                rest = &brace[2..];
+                continue;
+            }
+            let end = match brace.strip_prefix('{').and_then(|inner| inner.find('}')) {
+                Some(end) => end,
+                None => return Err(format!("The name template '{}' has an unmatched brace.", text)),
+            };
+            let name = &brace[1..1 + end];
+            let part = match name {
+                "stem" => Part::Stem,
+                "ext" => Part::Extension,
+                "date" => Part::Date,
+                "counter" => Part::Counter,
+                "hash" => Part::Hash(64),
+                _ => match name.strip_prefix("hash").and_then(|digits| digits.parse().ok()) {
+                    Some(len @ 1..=64) => Part::Hash(len),
+                    _ => {
+                        return Err(format!(
+                            "Unknown variable '{{{}}}' in the name template; use {{stem}}, {{ext}}, {{date}}, {{counter}}, or {{hash8}}.",
+                            name
+                        ));
+                    }
+                },
+            };
+            if !literal.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut literal)));
+            }
+            parts.push(part);
+            rest = &brace[end + 2..];
+        }
+        literal.push_str(rest);
+        if !literal.is_empty() {
+            parts.push(Part::Text(literal));
+        }
+        if parts.is_empty() {
+            return Err("The name template is empty.".to_string());
+        }
+        Ok(Template {
+            parts,
+            text: text.to_string(),
+        })
+    }
+
+    /// The name of the output for `input`, the `counter`th input of a run
+    /// started at `timestamp`. The input is only read when the template
+    /// has a hash in it.
+    pub fn render(&self, input: &Path, counter: usize, timestamp: u64) -> Result<String, String> {
+        let base = packed::output_base(input);
+        let file_name = Path::new(base.file_name().unwrap_or_default());
+        let mut hash = None;
+        let mut name = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => name.push_str(text),
+                Part::Stem => name.push_str(&file_name.file_stem().unwrap_or_default().to_string_lossy()),
+                Part::Extension => name.push_str(&file_name.extension().unwrap_or_default().to_string_lossy()),
+                Part::Date => name.push_str(&history::format_date(timestamp)[..10]),
+                Part::Counter => name.push_str(&counter.to_string()),
+                Part::Hash(len) => {
+                    if hash.is_none() {
+                        hash = Some(content_hash(input)?);
+                    }
+                    name.push_str(&hash.as_deref().unwrap_or_default()[..*len]);
+                }
+            }
+        }
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(format!(
+                "The name template '{}' gives no usable name for '{}'.",
+                self.text,
+                input.display()
+            ));
+        }
+        Ok(name)
+    }
+}
+
+/// SHA-256 of the contents of `input` in hex, reading a file in pieces or
+/// an archive entry out of its archive.
+fn content_hash(input: &Path) -> Result<String, String> {
+    let hash = match packed::split(input) {
+        Some((archive, entry)) => checksum::sha256(&packed::read(&archive, &entry)?),
+        None => digest::hash_file(input, Algorithm::Sha256)
+            .map_err(|e| format!("Failed to read '{}': {}", input.display(), e))?,
+    };
+    Ok(checksum::to_hex(&hash))
+}
@@ -0,0 +1,321 @@
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use crate::container::Container;
+use crate::sandbox;
+
+// Constants from the NBD protocol (doc/proto.md in the nbd project). Only
+// the fixed newstyle handshake is spoken, which every current client uses.
+const NBD_MAGIC: u64 = 0x4e42_444d_4147_4943; // "NBDMAGIC"
+const IHAVEOPT: u64 = 0x4948_4156_454f_5054; // "IHAVEOPT"
+const OPTION_REPLY_MAGIC: u64 = 0x0003_e889_0455_65a9;
+const REQUEST_MAGIC: u32 = 0x2560_9513;
+const SIMPLE_REPLY_MAGIC: u32 = 0x6744_6698;
+
+const FLAG_FIXED_NEWSTYLE: u16 = 1 << 0;
+const FLAG_NO_ZEROES: u16 = 1 << 1;
+const CLIENT_NO_ZEROES: u32 = 1 << 1;
+
+const TRANSMISSION_HAS_FLAGS: u16 = 1 << 0;
+const TRANSMISSION_SEND_FLUSH: u16 = 1 << 2;
+
+const OPT_EXPORT_NAME: u32 = 1;
+const OPT_ABORT: u32 = 2;
+const OPT_LIST: u32 = 3;
+const OPT_INFO: u32 = 6;
+const OPT_GO: u32 = 7;
+
+const REP_ACK: u32 = 1;
+const REP_SERVER: u32 = 2;
+const REP_INFO: u32 = 3;
+const REP_ERR_UNSUP: u32 = (1 << 31) + 1;
+const INFO_EXPORT: u16 = 0;
+
+const CMD_READ: u16 = 0;
+const CMD_WRITE: u16 = 1;
+const CMD_DISC: u16 = 2;
+const CMD_FLUSH: u16 = 3;
+
+const EIO: u32 = 5;
+const EINVAL: u32 = 22;
+
+/// Largest read or write request accepted, well above what the kernel
+/// client sends, so a misbehaving client cannot make us allocate gigabytes.
+const MAX_REQUEST_LEN: u32 = 32 * 1024 * 1024;
+
+/// Serve `container` as a network block device on a Unix socket at
+/// `socket_path` until the client disconnects. The socket is removed
+/// afterwards. One client is served, since two clients writing the same
+/// filesystem would corrupt it.
+pub fn serve(container: &Container, socket_path: &Path) -> Result<(), String> {
+    // Only this user may connect, since a client reads and writes the
+    // decrypted blocks.
+    let listener = sandbox::listen(socket_path, "container")?;
+    let result = listener
+        .listener
+        .accept()
+        .map_err(|e| e.to_string())
+        .and_then(|(stream, _)| serve_client(container, stream).map_err(|e| e.to_string()));
+    if !listener.activated {
+        let _ = std::fs::remove_file(socket_path);
+    }
+    container
+        .flush()
+        .map_err(|e| format!("Failed to flush the container: {}", e))?;
+    result.map_err(|e| format!("The connection failed: {}", e))
+}
+
+fn serve_client(container: &Container, mut stream: UnixStream) -> io::Result<()> {
+    if handshake(container, &mut stream)? {
+        transmit(container, &mut stream)?;
+    }
+    Ok(())
+}
+
+/// Negotiate options. Returns false if the client gave up before choosing
+/// the export.
+fn handshake(container: &Container, stream: &mut UnixStream) -> io::Result<bool> {
+    let mut greeting = Vec::with_capacity(18);
+    greeting.extend_from_slice(&NBD_MAGIC.to_be_bytes());
+    greeting.extend_from_slice(&IHAVEOPT.to_be_bytes());
+    greeting.extend_from_slice(&(FLAG_FIXED_NEWSTYLE | FLAG_NO_ZEROES).to_be_bytes());
+    stream.write_all(&greeting)?;
+    let client_flags = read_u32(stream)?;
+
+    loop {
+        if read_u64(stream)? != IHAVEOPT {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad option magic"));
+        }
+        let option = read_u32(stream)?;
+        let len = read_u32(stream)?;
+        if len > 4096 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "option too long"));
+        }
+        let mut data = vec![0u8; len as usize];
+        stream.read_exact(&mut data)?;
+
+        match option {
+            OPT_EXPORT_NAME => {
+                // The old way to finish: no reply header, just the export.
+                let mut reply = Vec::with_capacity(10 + 124);
+                reply.extend_from_slice(&container.size().to_be_bytes());
+                reply.extend_from_slice(&transmission_flags().to_be_bytes());
+                if client_flags & CLIENT_NO_ZEROES == 0 {
+                    reply.resize(reply.len() + 124, 0);
+                }
+                stream.write_all(&reply)?;
+                return Ok(true);
+            }
+            OPT_INFO | OPT_GO => {
+                let mut info = Vec::with_capacity(12);
+                info.extend_from_slice(&INFO_EXPORT.to_be_bytes());
+                info.extend_from_slice(&container.size().to_be_bytes());
+                info.extend_from_slice(&transmission_flags().to_be_bytes());
+                option_reply(stream, option, REP_INFO, &info)?;
+                option_reply(stream, option, REP_ACK, &[])?;
+                if option == OPT_GO {
+                    return Ok(true);
+                }
+            }
+            OPT_LIST => {
+                // A single export with the default (empty) name.
+                option_reply(stream, option, REP_SERVER, &0u32.to_be_bytes())?;
+                option_reply(stream, option, REP_ACK, &[])?;
+            }
+            OPT_ABORT => {
+                option_reply(stream, option, REP_ACK, &[])?;
+                return Ok(false);
+            }
+            _ => option_reply(stream, option, REP_ERR_UNSUP, &[])?,
+        }
+    }
+}
+
+fn transmission_flags() -> u16 {
+    TRANSMISSION_HAS_FLAGS | TRANSMISSION_SEND_FLUSH
+}
+
+fn option_reply(stream: &mut UnixStream, option: u32, kind: u32, data: &[u8]) -> io::Result<()> {
+    let mut reply = Vec::with_capacity(20 + data.len());
+    reply.extend_from_slice(&OPTION_REPLY_MAGIC.to_be_bytes());
+    reply.extend_from_slice(&option.to_be_bytes());
+    reply.extend_from_slice(&kind.to_be_bytes());
+    reply.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    reply.extend_from_slice(data);
+    stream.write_all(&reply)
+}
+
+/// Answer read, write, and flush requests until the client disconnects.
+/// Failures of single requests are reported to the client as I/O errors,
+/// which the kernel passes on to the filesystem.
+fn transmit(container: &Container, stream: &mut UnixStream) -> io::Result<()> {
+    let mut header = [0u8; 28];
+    loop {
+        match stream.read_exact(&mut header) {
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            result => result?,
+        }
+        let magic = u32::from_be_bytes(header[0..4].try_into().expect("4 bytes"));
+        let command = u16::from_be_bytes(header[6..8].try_into().expect("2 bytes"));
+        let handle = &header[8..16];
+        let offset = u64::from_be_bytes(header[16..24].try_into().expect("8 bytes"));
+        let len = u32::from_be_bytes(header[24..28].try_into().expect("4 bytes"));
+        if magic != REQUEST_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "bad request magic"));
+        }
+        if len > MAX_REQUEST_LEN {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "request too long"));
+        }
+
+        match command {
+            CMD_READ => {
+                let mut data = vec![0u8; len as usize];
+                match container.read_at(offset, &mut data) {
+                    Ok(()) => simple_reply(stream, 0, handle, &data)?,
+                    Err(e) => simple_reply(stream, error_code(&e), handle, &[])?,
+                }
+            }
+            CMD_WRITE => {
+                let mut data = vec![0u8; len as usize];
+                stream.read_exact(&mut data)?;
+                let error = container.write_at(offset, &data).err();
+                simple_reply(stream, error.as_ref().map_or(0, error_code), handle, &[])?;
+            }
+            CMD_FLUSH => {
+                let error = container.flush().err();
+                simple_reply(stream, error.as_ref().map_or(0, error_code), handle, &[])?;
+            }
+            CMD_DISC => return Ok(()),
+            _ => simple_reply(stream, EINVAL, handle, &[])?,
+        }
+    }
+}
+
+fn simple_reply(stream: &mut UnixStream, error: u32, handle: &[u8], data: &[u8]) -> io::Result<()> {
+    let mut reply = Vec::with_capacity(16 + data.len());
+    reply.extend_from_slice(&SIMPLE_REPLY_MAGIC.to_be_bytes());
+    reply.extend_from_slice(&error.to_be_bytes());
+    reply.extend_from_slice(handle);
+    reply.extend_from_slice(data);
+    stream.write_all(&reply)
+}
+
+fn error_code(error: &io::Error) -> u32 {
+    match error.kind() {
+        io::ErrorKind::InvalidInput => EINVAL,
+        _ => EIO,
+    }
+}
+
+fn read_u32(stream: &mut UnixStream) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    stream.read_exact(&mut bytes)?;
+    Ok(u32::from_be_bytes(bytes))
+}
+
+fn read_u64(stream: &mut UnixStream) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    stream.read_exact(&mut bytes)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::container;
+    use crate::format::FormatError;
+
+    fn request(stream: &mut UnixStream, command: u16, offset: u64, len: u32, data: &[u8]) -> (u32, Vec<u8>) {
+        let mut header = Vec::with_capacity(28 + data.len());
+        header.extend_from_slice(&REQUEST_MAGIC.to_be_bytes());
+        header.extend_from_slice(&0u16.to_be_bytes());
+        header.extend_from_slice(&command.to_be_bytes());
+        header.extend_from_slice(b"handle!!");
+        header.extend_from_slice(&offset.to_be_bytes());
+        header.extend_from_slice(&len.to_be_bytes());
+        header.extend_from_slice(data);
+        stream.write_all(&header).unwrap();
+
+        let mut reply = [0u8; 16];
+        stream.read_exact(&mut reply).unwrap();
+        assert_eq!(reply[0..4], SIMPLE_REPLY_MAGIC.to_be_bytes());
+        assert_eq!(&reply[8..16], b"handle!!");
+        let error = u32::from_be_bytes(reply[4..8].try_into().unwrap());
+        let mut body = vec![0u8; if command == CMD_READ && error == 0 { len as usize } else { 0 }];
+        stream.read_exact(&mut body).unwrap();
+        (error, body)
+    }
+
+    #[test]
+    fn a_client_can_negotiate_write_and_read() {
+        let dir = std::env::temp_dir().join(format!("file_encryptor_nbd_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("volume");
+        container::create(&path, 3 * container::BLOCK_LEN as u64, "password").unwrap();
+        let volume = Container::open(&path, "password").unwrap();
+        let (mut client, server) = UnixStream::pair().unwrap();
+
+        std::thread::scope(|scope| {
+            let served = scope.spawn(|| serve_client(&volume, server));
+
+            let mut greeting = [0u8; 18];
+            client.read_exact(&mut greeting).unwrap();
+            assert_eq!(greeting[0..8], NBD_MAGIC.to_be_bytes());
+            assert_eq!(greeting[8..16], IHAVEOPT.to_be_bytes());
+            client.write_all(&(CLIENT_NO_ZEROES | 1).to_be_bytes()).unwrap();
+
+            // Ask for the default export: an empty name and no info requests.
+            let mut option = IHAVEOPT.to_be_bytes().to_vec();
+            option.extend_from_slice(&OPT_GO.to_be_bytes());
+            option.extend_from_slice(&6u32.to_be_bytes());
+            option.extend_from_slice(&[0; 6]);
+            client.write_all(&option).unwrap();
+            let mut info = [0u8; 20 + 12];
+            client.read_exact(&mut info).unwrap();
+            assert_eq!(info[12..16], REP_INFO.to_be_bytes());
+            assert_eq!(info[22..30], volume.size().to_be_bytes());
+            let mut ack = [0u8; 20];
+            client.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[12..16], REP_ACK.to_be_bytes());
+
+            // A write across a block boundary reads back, and its
+            // neighbours are still zero.
+            let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+            assert_eq!(request(&mut client, CMD_WRITE, 4000, 5000, &data).0, 0);
+            let (error, read) = request(&mut client, CMD_READ, 3990, 5020, &[]);
+            assert_eq!(error, 0);
+            assert_eq!(read[..10], [0; 10]);
+            assert_eq!(read[10..5010], data[..]);
+            assert_eq!(read[5010..], [0; 10]);
+
+            assert_eq!(request(&mut client, CMD_FLUSH, 0, 0, &[]).0, 0);
+            assert_eq!(request(&mut client, CMD_READ, volume.size() - 1, 2, &[]).0, EINVAL);
+            assert_eq!(request(&mut client, 99, 0, 0, &[]).0, EINVAL);
+            request_disconnect(&mut client);
+            served.join().unwrap().unwrap();
+        });
+
+        // What was written is still there after reopening.
+        let reopened = Container::open(&path, "password").unwrap();
+        let mut buffer = [0u8; 3];
+        reopened.read_at(4000, &mut buffer).unwrap();
+        assert_eq!(buffer, [0, 1, 2]);
+        // Told apart by its type, so a wrong password counts against the
+        // unlock attempts.
+        assert!(matches!(
+            Container::open(&path, "wrong"),
+            Err(crate::OpenError::Format(FormatError::AuthenticationFailed))
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn request_disconnect(stream: &mut UnixStream) {
+        let mut header = REQUEST_MAGIC.to_be_bytes().to_vec();
+        header.extend_from_slice(&0u16.to_be_bytes());
+        header.extend_from_slice(&CMD_DISC.to_be_bytes());
+        header.extend_from_slice(&[0; 20]);
+        stream.write_all(&header).unwrap();
+    }
+}
@@ -0,0 +1,88 @@
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+use crate::config::Config;
+
+/// Title used for every notification.
+const TITLE: &str = "File Encryptor";
+
+/// Sends a desktop notification when a long operation finishes, so users
+/// who switched to another window know it is done.
+pub struct Notifier {
+    enabled: bool,
+    min_duration: Duration,
+}
+
+impl Notifier {
+    /// Read the notification settings from the config.
+    pub fn from_config(config: &Config) -> Self {
+        Notifier {
+            enabled: config.notify,
+            min_duration: Duration::from_secs(config.notify_after_seconds),
+        }
+    }
+
+    /// Notify about a finished operation if notifications are on and it
+    /// took at least the configured time. `description` names what was
+    /// processed, such as "Encrypting 'report.pdf'".
+    pub fn operation_finished(&self, description: &str, error: Option<&str>, elapsed: Duration) {
+        if !self.enabled || elapsed < self.min_duration {
+            return;
+        }
+        let body = match error {
+            None => format!("{} finished after {}s.", description, elapsed.as_secs()),
+            Some(e) => format!("{} failed: {}", description, e),
+        };
+        // Notifications are a convenience, so a missing tool is not an error.
+        let _ = send(TITLE, &body);
+    }
+}
+
+/// Show a notification with the platform's own tool.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn send(title: &str, body: &str) -> std::io::Result<()> {
+    run_quietly(Command::new("notify-send").arg(title).arg(body))
+}
+
+#[cfg(target_os = "macos")]
+fn send(title: &str, body: &str) -> std::io::Result<()> {
+    let quote = |text: &str| text.replace('\\', "\\\\").replace('"', "\\\"");
+    let script = format!(
+        "display notification \"{}\" with title \"{}\"",
+        quote(body),
+        quote(title)
+    );
+    run_quietly(Command::new("osascript").arg("-e").arg(script))
+}
+
+#[cfg(windows)]
+fn send(title: &str, body: &str) -> std::io::Result<()> {
+    let quote = |text: &str| text.replace('\'', "''");
+    let script = format!(
+        "Add-Type -AssemblyName System.Windows.Forms; \
+         $n = New-Object System.Windows.Forms.NotifyIcon; \
+         $n.Icon = [System.Drawing.SystemIcons]::Information; \
+         $n.Visible = $true; \
+         $n.ShowBalloonTip(5000, '{}', '{}', 'Info'); \
+         Start-Sleep -Seconds 6; $n.Dispose()",
+        quote(title),
+        quote(body)
+    );
+    let mut command = Command::new("powershell");
+    command.args(["-NoProfile", "-WindowStyle", "Hidden", "-Command", &script]);
+    command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map(|_| ())
+}
+
+/// Run a notification command without letting it print into the menu.
+#[cfg(not(windows))]
+fn run_quietly(command: &mut Command) -> std::io::Result<()> {
+    command
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|_| ())
+}
@@ -0,0 +1,914 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::checksum;
+use crate::guard;
+use crate::history;
+use crate::inflate;
+use crate::memory;
+use crate::progress;
+use crate::throttle;
+
+/// Separates an archive from the entry inside it, as in
+/// `archive.zip!docs/report.pdf`.
+const SEPARATOR: char = '!';
+
+/// Archive names entries can be read from.
+const EXTENSIONS: [&str; 4] = [".zip", ".tar", ".tar.gz", ".tgz"];
+
+const ZIP_END: &[u8; 4] = b"PK\x05\x06";
+const ZIP64_END: &[u8; 4] = b"PK\x06\x06";
+const ZIP64_LOCATOR: &[u8; 4] = b"PK\x06\x07";
+const ZIP_CENTRAL: &[u8; 4] = b"PK\x01\x02";
+const ZIP_LOCAL: &[u8; 4] = b"PK\x03\x04";
+
+/// Size of a tar header and of the blocks entries are padded to.
+const TAR_BLOCK: u64 = 512;
+
+/// Split `path` into an archive and the name of an entry inside it, when
+/// it names one: the part before a `!` is an existing `.zip`, `.tar`,
+/// `.tar.gz`, or `.tgz` file and `path` itself does not exist. Files
+/// whose names contain `!` are therefore still read as they are.
+pub fn split(path: &Path) -> Option<(PathBuf, String)> {
+    let text = path.to_str()?;
+    if !text.contains(SEPARATOR) || path.exists() {
+        return None;
+    }
+    text.match_indices(SEPARATOR).find_map(|(index, _)| {
+        let (archive, entry) = (&text[..index], &text[index + 1..]);
+        let lower = archive.to_ascii_lowercase();
+        let known = EXTENSIONS.iter().any(|extension| lower.ends_with(extension));
+        (known && !entry.is_empty() && Path::new(archive).is_file())
+            .then(|| (PathBuf::from(archive), entry.to_string()))
+    })
+}
+
+/// The path outputs for `path` are named after: for an entry inside an
+/// archive, a file with the entry's name next to the archive, so
+/// `backup.zip!docs/report.pdf` encrypts to `report.pdf.enc`; otherwise
+/// `path` itself.
+pub fn output_base(path: &Path) -> Cow<'_, Path> {
+    match split(path) {
+        Some((archive, entry)) => {
+            let name = entry.trim_end_matches('/').rsplit('/').next().unwrap_or(&entry);
+            Cow::Owned(archive.with_file_name(name))
+        }
+        None => Cow::Borrowed(path),
+    }
+}
+
+/// Read the entry called `entry` out of `archive` into memory, without
+/// writing it anywhere. Zip entries are checked against their CRC-32.
+pub fn read(archive: &Path, entry: &str) -> Result<Vec<u8>, String> {
+    let wanted = normalize(entry);
+    let what = format!("'{}{}{}'", archive.display(), SEPARATOR, entry);
+    let in_archive = |e: String| format!("{}: {}", what, e);
+    let lower = archive.to_string_lossy().to_ascii_lowercase();
+    let data = if lower.ends_with(".zip") {
+        let mut file = guard::open(archive).map_err(|e| in_archive(e.to_string()))?;
+        read_zip(&mut file, wanted, &what)
+    } else if lower.ends_with(".tar") {
+        let mut file = guard::open(archive).map_err(|e| in_archive(e.to_string()))?;
+        read_tar(&mut file, wanted, &what)
+    } else {
+        // Compressed tar files cannot be skipped through, so the whole
+        // stream is decompressed, still only in memory.
+        let compressed = throttle::read(archive).map_err(|e| in_archive(e.to_string()))?;
+        let tar = inflate::gunzip(&compressed, u64::MAX).map_err(in_archive)?;
+        read_tar(&mut Cursor::new(tar), wanted, &what)
+    }?;
+    progress::read(data.len());
+    Ok(data)
+}
+
+/// Entry names as archives store them: relative, with `/` between folders.
+fn normalize(name: &str) -> &str {
+    let mut name = name.trim_start_matches('/');
+    while let Some(rest) = name.strip_prefix("./") {
+        name = rest;
+    }
+    name
+}
+
+fn not_found(what: &str) -> String {
+    format!("{} does not exist; name the entry as the archive lists it, such as docs/report.pdf.", what)
+}
+
+fn damaged(what: &str) -> String {
+    format!("{} could not be read: the archive is damaged.", what)
+}
+
+fn read_at(file: &mut (impl Read + Seek), offset: u64, len: usize) -> io::Result<Vec<u8>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut buffer = vec![0u8; len];
+    file.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> u64 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]]) as u64
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> u64 {
+    u32::from_le_bytes(bytes[at..at + 4].try_into().expect("four bytes")) as u64
+}
+
+fn u64_at(bytes: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(bytes[at..at + 8].try_into().expect("eight bytes"))
+}
+
+/// A zip's central directory, found through the end record.
+struct ZipDirectory {
+    count: u64,
+    /// Where the directory starts, which is also where the entries' data
+    /// ends.
+    at: u64,
+    bytes: Vec<u8>,
+    /// The zip's comment, kept when members are added.
+    comment: Vec<u8>,
+}
+
+/// One entry of the central directory.
+struct CentralRecord<'a> {
+    header: &'a [u8],
+    name: &'a [u8],
+    extra: &'a [u8],
+}
+
+fn zip_directory(file: &mut File, what: &str) -> Result<ZipDirectory, String> {
+    let io_error = |e: io::Error| format!("{}: {}", what, e);
+    let len = file.metadata().map_err(io_error)?.len();
+    // The end record is 22 bytes plus a comment of up to 64 KiB.
+    let tail_len = len.min(22 + u16::MAX as u64);
+    let tail = read_at(file, len - tail_len, tail_len as usize).map_err(io_error)?;
+    let end = (0..tail.len().saturating_sub(21))
+        .rev()
+        .find(|&at| &tail[at..at + 4] == ZIP_END)
+        .ok_or_else(|| format!("{}: the archive is not a zip file.", what))?;
+    let record = &tail[end..];
+    let comment = record.get(22..22 + u16_at(record, 20) as usize).unwrap_or(&record[22..]).to_vec();
+    let (mut count, mut directory_len, mut directory_at) = (u16_at(record, 10), u32_at(record, 12), u32_at(record, 16));
+    if count == u16::MAX as u64 || directory_len == u32::MAX as u64 || directory_at == u32::MAX as u64 {
+        // Zip64: a locator in front of the end record gives the real one.
+        let locator_at = (len - tail_len + end as u64).checked_sub(20).ok_or_else(|| damaged(what))?;
+        let locator = read_at(file, locator_at, 20).map_err(io_error)?;
+        if &locator[..4] != ZIP64_LOCATOR {
+            return Err(damaged(what));
+        }
+        let record = read_at(file, u64_at(&locator, 8), 56).map_err(io_error)?;
+        if &record[..4] != ZIP64_END {
+            return Err(damaged(what));
+        }
+        (count, directory_len, directory_at) = (u64_at(&record, 32), u64_at(&record, 40), u64_at(&record, 48));
+    }
+    if directory_at.saturating_add(directory_len) > len {
+        return Err(damaged(what));
+    }
+    Ok(ZipDirectory {
+        count,
+        at: directory_at,
+        bytes: read_at(file, directory_at, directory_len as usize).map_err(io_error)?,
+        comment,
+    })
+}
+
+fn central_records<'a>(directory: &'a ZipDirectory, what: &str) -> Result<Vec<CentralRecord<'a>>, String> {
+    let bytes = &directory.bytes;
+    let mut records = Vec::new();
+    let mut at = 0;
+    for _ in 0..directory.count {
+        let header = bytes.get(at..at + 46).ok_or_else(|| damaged(what))?;
+        if &header[..4] != ZIP_CENTRAL {
+            return Err(damaged(what));
+        }
+        let (name_len, extra_len, comment_len) = (
+            u16_at(header, 28) as usize,
+            u16_at(header, 30) as usize,
+            u16_at(header, 32) as usize,
+        );
+        let name = bytes.get(at + 46..at + 46 + name_len).ok_or_else(|| damaged(what))?;
+        let extra = bytes
+            .get(at + 46 + name_len..at + 46 + name_len + extra_len)
+            .ok_or_else(|| damaged(what))?;
+        at += 46 + name_len + extra_len + comment_len;
+        records.push(CentralRecord { header, name, extra });
+    }
+    Ok(records)
+}
+
+/// Find `wanted` through the zip's central directory at its end, then read
+/// and decompress only that entry.
+fn read_zip(file: &mut File, wanted: &str, what: &str) -> Result<Vec<u8>, String> {
+    let io_error = |e: io::Error| format!("{}: {}", what, e);
+    let len = file.metadata().map_err(io_error)?.len();
+    let directory = zip_directory(file, what)?;
+    for CentralRecord { header, name, extra } in central_records(&directory, what)? {
+        if normalize(&String::from_utf8_lossy(name)) != wanted {
+            if name.strip_suffix(b"/") == Some(wanted.as_bytes()) {
+                return Err(format!("{} is a folder; name a file inside it.", what));
+            }
+            continue;
+        }
+
+        let (flags, method, crc) = (u16_at(header, 8), u16_at(header, 10), u32_at(header, 16) as u32);
+        let (mut compressed_len, mut size, mut offset) = (u32_at(header, 20), u32_at(header, 24), u32_at(header, 42));
+        // Zip64 sizes and offset, in this order, for each one that is full.
+        let mut fields = zip64_fields(extra).into_iter();
+        for value in [&mut size, &mut compressed_len, &mut offset] {
+            if *value == u32::MAX as u64 {
+                *value = fields.next().ok_or_else(|| damaged(what))?;
+            }
+        }
+        if flags & 1 != 0 {
+            return Err(format!(
+                "{} is encrypted by the zip itself; extract it with the zip's own password instead.",
+                what
+            ));
+        }
+        if method != 0 && method != 8 {
+            return Err(format!(
+                "{} is compressed with a method this tool cannot read (zip method {}); only stored and deflated entries are supported.",
+                what, method
+            ));
+        }
+        memory::check_contents(size, what)?;
+
+        let local = read_at(file, offset, 30).map_err(io_error)?;
+        if &local[..4] != ZIP_LOCAL {
+            return Err(damaged(what));
+        }
+        let data_at = offset + 30 + u16_at(&local, 26) + u16_at(&local, 28);
+        if data_at.saturating_add(compressed_len) > len {
+            return Err(damaged(what));
+        }
+        let compressed = read_at(file, data_at, compressed_len as usize).map_err(io_error)?;
+        let data = match method {
+            0 => compressed,
+            _ => inflate::inflate(&compressed, size).map_err(|e| format!("{}: {}", what, e))?.0,
+        };
+        if data.len() as u64 != size || checksum::crc32(&data) != crc {
+            return Err(format!("{} does not match its checksum in the archive; the archive is damaged.", what));
+        }
+        return Ok(data);
+    }
+    Err(not_found(what))
+}
+
+/// The values of the Zip64 extra field (ID 1) of an entry, in order.
+fn zip64_fields(mut extra: &[u8]) -> Vec<u64> {
+    while extra.len() >= 4 {
+        let (id, len) = (u16_at(extra, 0), u16_at(extra, 2) as usize);
+        let Some(body) = extra.get(4..4 + len) else {
+            break;
+        };
+        if id == 1 {
+            return body.chunks_exact(8).map(|value| u64_at(value, 0)).collect();
+        }
+        extra = &extra[4 + len..];
+    }
+    Vec::new()
+}
+
+/// An entry found by walking a tar's headers.
+struct TarEntry {
+    name: String,
+    /// The type flag, such as `b'0'` for a file or `b'5'` for a folder.
+    kind: u8,
+    data_at: u64,
+    size: u64,
+}
+
+/// Walk the tar's headers, skipping over every entry's contents. GNU long
+/// names and pax paths are understood. Returns the entries and where the
+/// end-of-archive marker starts, which is where new members go.
+fn tar_entries(reader: &mut (impl Read + Seek), what: &str) -> Result<(Vec<TarEntry>, u64), String> {
+    let io_error = |e: io::Error| format!("{}: {}", what, e);
+    let total = reader.seek(SeekFrom::End(0)).map_err(io_error)?;
+    reader.seek(SeekFrom::Start(0)).map_err(io_error)?;
+    let mut entries = Vec::new();
+    let mut long_name: Option<String> = None;
+    loop {
+        let header_at = reader.stream_position().map_err(io_error)?;
+        let mut header = [0u8; TAR_BLOCK as usize];
+        match reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok((entries, header_at)),
+            Err(e) => return Err(io_error(e)),
+        }
+        if header.iter().all(|&byte| byte == 0) {
+            return Ok((entries, header_at));
+        }
+        let stored_sum = parse_octal(&header[148..156]).ok_or_else(|| damaged(what))?;
+        let sum: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(at, &byte)| if (148..156).contains(&at) { b' ' as u64 } else { byte as u64 })
+            .sum();
+        if sum != stored_sum {
+            return Err(damaged(what));
+        }
+        let size = parse_size(&header[124..136]).ok_or_else(|| damaged(what))?;
+        let data_at = header_at + TAR_BLOCK;
+        if data_at.saturating_add(size) > total {
+            return Err(damaged(what));
+        }
+        let padded = size.div_ceil(TAR_BLOCK) * TAR_BLOCK;
+        let name = long_name.take().unwrap_or_else(|| {
+            let field = |bytes: &[u8]| {
+                let end = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+                String::from_utf8_lossy(&bytes[..end]).into_owned()
+            };
+            let (name, prefix) = (field(&header[..100]), field(&header[345..500]));
+            if &header[257..262] == b"ustar" && !prefix.is_empty() {
+                format!("{}/{}", prefix, name)
+            } else {
+                name
+            }
+        });
+
+        match header[156] {
+            // The next entry's name (GNU) or its attributes (pax).
+            kind @ (b'L' | b'x') => {
+                memory::check_contents(size, what)?;
+                let mut body = vec![0u8; size as usize];
+                reader.read_exact(&mut body).map_err(io_error)?;
+                long_name = match kind {
+                    b'L' => Some(String::from_utf8_lossy(body.split(|&byte| byte == 0).next().unwrap_or(&[])).into_owned()),
+                    _ => pax_path(&body),
+                };
+            }
+            kind => entries.push(TarEntry { name, kind, data_at, size }),
+        }
+        reader.seek(SeekFrom::Start(data_at + padded)).map_err(io_error)?;
+    }
+}
+
+/// Find `wanted` among the tar's entries and read only its contents.
+fn read_tar(reader: &mut (impl Read + Seek), wanted: &str, what: &str) -> Result<Vec<u8>, String> {
+    let (entries, _) = tar_entries(reader, what)?;
+    for entry in entries {
+        match entry.kind {
+            b'0' | 0 | b'7' if normalize(&entry.name) == wanted => {
+                memory::check_contents(entry.size, what)?;
+                let mut data = vec![0u8; entry.size as usize];
+                reader.seek(SeekFrom::Start(entry.data_at)).map_err(|e| format!("{}: {}", what, e))?;
+                reader.read_exact(&mut data).map_err(|e| format!("{}: {}", what, e))?;
+                return Ok(data);
+            }
+            b'5' if normalize(&entry.name).trim_end_matches('/') == wanted => {
+                return Err(format!("{} is a folder; name a file inside it.", what));
+            }
+            _ => {}
+        }
+    }
+    Err(not_found(what))
+}
+
+/// A number stored in octal text, padded with spaces or NULs.
+fn parse_octal(field: &[u8]) -> Option<u64> {
+    let text = std::str::from_utf8(field).ok()?.trim_matches([' ', '\0']);
+    if text.is_empty() {
+        return Some(0);
+    }
+    u64::from_str_radix(text, 8).ok()
+}
+
+/// An entry size: octal, or big-endian binary when the first bit is set,
+/// as GNU tar writes sizes of 8 GiB and more.
+fn parse_size(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 == 0 {
+        return parse_octal(field);
+    }
+    field[1..].iter().try_fold(0u64, |size, &byte| size.checked_mul(256).map(|size| size | byte as u64))
+}
+
+/// The `path` attribute of a pax header, made of records such as
+/// `27 path=docs/a long name.pdf\n`.
+fn pax_path(mut body: &[u8]) -> Option<String> {
+    let mut path = None;
+    while let Some(space) = body.iter().position(|&byte| byte == b' ') {
+        let len: usize = std::str::from_utf8(&body[..space]).ok()?.parse().ok()?;
+        let record = body.get(space + 1..len)?;
+        let record = record.strip_suffix(b"\n").unwrap_or(record);
+        if let Some(value) = record.strip_prefix(b"path=") {
+            path = Some(String::from_utf8_lossy(value).into_owned());
+        }
+        body = &body[len..];
+    }
+    path
+}
+
+/// Archive names members can be added to. Compressed tar files would have
+/// to be rewritten whole, so they are left out.
+const WRITABLE: [&str; 2] = [".zip", ".tar"];
+
+/// Permissions recorded for added members: readable by everyone, written
+/// by the owner.
+const MEMBER_MODE: u32 = 0o644;
+
+enum Kind {
+    Zip,
+    Tar,
+}
+
+/// A zip or tar that encrypted files are added to as members, created if
+/// missing. The central directory or end marker is written again after
+/// every member, so a run that stops partway still leaves a complete
+/// archive with everything added so far.
+pub struct Appender {
+    file: File,
+    kind: Kind,
+    /// Where the next member goes: the start of the zip's central
+    /// directory, or of the tar's end marker.
+    end: u64,
+    /// The zip's central directory records, old and new.
+    directory: Vec<u8>,
+    count: u64,
+    comment: Vec<u8>,
+    names: HashSet<PathBuf>,
+    what: String,
+}
+
+impl Appender {
+    /// Open `path` to add members to, creating an empty archive there if
+    /// it does not exist yet. Whether it is a zip or a tar is decided by
+    /// its extension.
+    pub fn open(path: &Path) -> Result<Self, String> {
+        let what = format!("'{}'", path.display());
+        let lower = path.to_string_lossy().to_ascii_lowercase();
+        let kind = if lower.ends_with(".zip") {
+            Kind::Zip
+        } else if lower.ends_with(".tar") {
+            Kind::Tar
+        } else {
+            return Err(format!(
+                "{}: encrypted files can only be added to a {} archive.",
+                what,
+                WRITABLE.join(" or ")
+            ));
+        };
+        guard::check_output(path).map_err(|e| format!("{}: {}", what, e))?;
+        let io_error = |e: io::Error| format!("{}: {}", what, e);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)
+            .map_err(io_error)?;
+        let empty = file.metadata().map_err(io_error)?.len() == 0;
+        let (mut end, mut directory, mut count, mut comment, mut names) = (0, Vec::new(), 0, Vec::new(), Vec::new());
+        match kind {
+            _ if empty => {}
+            Kind::Zip => {
+                let zip = zip_directory(&mut file, &what)?;
+                names = central_records(&zip, &what)?
+                    .iter()
+                    .map(|record| String::from_utf8_lossy(record.name).into_owned())
+                    .collect();
+                (end, count) = (zip.at, zip.count);
+                (directory, comment) = (zip.bytes, zip.comment);
+            }
+            Kind::Tar => {
+                let (entries, at) = tar_entries(&mut file, &what)?;
+                end = at;
+                names = entries.into_iter().map(|entry| entry.name).collect();
+            }
+        }
+        let names = names
+            .iter()
+            .map(|name| PathBuf::from(normalize(name).trim_end_matches('/')))
+            .collect();
+        let mut appender = Appender {
+            file,
+            kind,
+            end,
+            directory,
+            count,
+            comment,
+            names,
+            what,
+        };
+        if empty {
+            // A new archive is complete from the start.
+            appender.finish().map_err(|e| format!("{}: {}", appender.what, e))?;
+        }
+        Ok(appender)
+    }
+
+    /// Names of the members already in the archive.
+    pub fn names(&self) -> &HashSet<PathBuf> {
+        &self.names
+    }
+
+    /// `path` in the form archives store names in: relative, with `/`
+    /// between folders.
+    pub fn member_name(path: &Path) -> String {
+        path.components()
+            .filter_map(|component| match component {
+                Component::Normal(part) => Some(part.to_string_lossy()),
+                _ => None,
+            })
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Add `data` as a regular file called `name`, then complete the
+    /// archive again.
+    pub fn add(&mut self, name: &str, data: &[u8]) -> Result<(), String> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0);
+        let records = self.directory.len();
+        let member = match self.kind {
+            Kind::Zip => self.zip_member(name, data, now)?,
+            Kind::Tar => tar_member(name, data.len() as u64, now),
+        };
+        // Tar contents are padded to whole blocks.
+        let padding = match self.kind {
+            Kind::Zip => 0,
+            Kind::Tar => (data.len() as u64).next_multiple_of(TAR_BLOCK) - data.len() as u64,
+        };
+        let file = &mut self.file;
+        let written = file
+            .seek(SeekFrom::Start(self.end))
+            .and_then(|_| file.write_all(&member))
+            .and_then(|()| file.write_all(data))
+            .and_then(|()| file.write_all(&vec![0u8; padding as usize]));
+        if let Err(e) = written {
+            // Left out of the directory, so the member can be added again.
+            self.directory.truncate(records);
+            return Err(format!("{}: {}", self.what, e));
+        }
+        self.end += member.len() as u64 + data.len() as u64 + padding;
+        self.count += 1;
+        self.names.insert(PathBuf::from(name));
+        progress::wrote(data.len());
+        self.finish().map_err(|e| format!("{}: {}", self.what, e))
+    }
+
+    /// The local header of a stored (uncompressed) zip member, adding its
+    /// record to the central directory. Encrypted data does not compress,
+    /// so nothing is gained by deflating it.
+    fn zip_member(&mut self, name: &str, data: &[u8], now: u64) -> Result<Vec<u8>, String> {
+        if name.len() > u16::MAX as usize {
+            return Err(format!("{}: the name '{}' is too long for a zip.", self.what, name));
+        }
+        let size = data.len() as u64;
+        let crc = checksum::crc32(data);
+        let (time, date) = dos_time(now);
+        let large = size >= u32::MAX as u64;
+        let far = self.end >= u32::MAX as u64;
+        let version: u16 = if large || far { 45 } else { 20 };
+        let size32 = if large { u32::MAX } else { size as u32 };
+        // Bit 11: the name is UTF-8.
+        let flags: u16 = 0x0800;
+
+        let mut local = Vec::with_capacity(30 + name.len() + 20);
+        local.extend_from_slice(ZIP_LOCAL);
+        for value in [version, flags, 0, time, date] {
+            local.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [crc, size32, size32] {
+            local.extend_from_slice(&value.to_le_bytes());
+        }
+        local.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        local.extend_from_slice(&(if large { 20u16 } else { 0 }).to_le_bytes());
+        local.extend_from_slice(name.as_bytes());
+        if large {
+            for value in [1u16, 16] {
+                local.extend_from_slice(&value.to_le_bytes());
+            }
+            local.extend_from_slice(&size.to_le_bytes());
+            local.extend_from_slice(&size.to_le_bytes());
+        }
+
+        // Zip64 sizes and offset, in this order, for each one that is full.
+        let mut extra = Vec::new();
+        if large {
+            extra.extend_from_slice(&size.to_le_bytes());
+            extra.extend_from_slice(&size.to_le_bytes());
+        }
+        if far {
+            extra.extend_from_slice(&self.end.to_le_bytes());
+        }
+        if !extra.is_empty() {
+            let fields = extra.len() as u16;
+            extra.splice(0..0, [1u16, fields].iter().flat_map(|value| value.to_le_bytes()));
+        }
+        let record = &mut self.directory;
+        record.extend_from_slice(ZIP_CENTRAL);
+        // Made by Unix, so the permissions below are read.
+        for value in [0x0300 | version, version, flags, 0, time, date] {
+            record.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [crc, size32, size32] {
+            record.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [name.len() as u16, extra.len() as u16, 0, 0, 0] {
+            record.extend_from_slice(&value.to_le_bytes());
+        }
+        record.extend_from_slice(&((0o100000 | MEMBER_MODE) << 16).to_le_bytes());
+        record.extend_from_slice(&(if far { u32::MAX } else { self.end as u32 }).to_le_bytes());
+        record.extend_from_slice(name.as_bytes());
+        record.extend_from_slice(&extra);
+        Ok(local)
+    }
+
+    /// Write what comes after the members, the zip's central directory and
+    /// end record or the tar's end marker, and cut off anything left
+    /// beyond it.
+    fn finish(&mut self) -> io::Result<()> {
+        let mut tail = Vec::new();
+        match self.kind {
+            Kind::Zip => {
+                let (at, len) = (self.end, self.directory.len() as u64);
+                tail.extend_from_slice(&self.directory);
+                let zip64 = self.count >= u16::MAX as u64 || at >= u32::MAX as u64 || len >= u32::MAX as u64;
+                if zip64 {
+                    let record_at = at + len;
+                    tail.extend_from_slice(ZIP64_END);
+                    tail.extend_from_slice(&44u64.to_le_bytes());
+                    tail.extend_from_slice(&[45, 0, 45, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+                    for value in [self.count, self.count, len, at] {
+                        tail.extend_from_slice(&value.to_le_bytes());
+                    }
+                    tail.extend_from_slice(ZIP64_LOCATOR);
+                    tail.extend_from_slice(&0u32.to_le_bytes());
+                    tail.extend_from_slice(&record_at.to_le_bytes());
+                    tail.extend_from_slice(&1u32.to_le_bytes());
+                }
+                let count = self.count.min(u16::MAX as u64) as u16;
+                tail.extend_from_slice(ZIP_END);
+                for value in [0, 0, count, count] {
+                    tail.extend_from_slice(&value.to_le_bytes());
+                }
+                for value in [len, at] {
+                    tail.extend_from_slice(&(if zip64 { u32::MAX } else { value as u32 }).to_le_bytes());
+                }
+                tail.extend_from_slice(&(self.comment.len() as u16).to_le_bytes());
+                tail.extend_from_slice(&self.comment);
+            }
+            Kind::Tar => tail.resize(2 * TAR_BLOCK as usize, 0),
+        }
+        self.file.seek(SeekFrom::Start(self.end))?;
+        self.file.write_all(&tail)?;
+        self.file.set_len(self.end + tail.len() as u64)?;
+        self.file.sync_all()
+    }
+}
+
+/// The ustar header (after a GNU long name entry, when the name does not
+/// fit) of a regular file of `size` bytes called `name`.
+fn tar_member(name: &str, size: u64, now: u64) -> Vec<u8> {
+    let mut member = Vec::new();
+    // A name of up to 255 bytes can be split into a prefix and a name at a
+    // folder boundary; longer ones go in an entry of their own.
+    let split = (name.len() > 100)
+        .then(|| {
+            name.match_indices('/')
+                .map(|(at, _)| at)
+                .find(|&at| at <= 155 && name.len() - at - 1 <= 100 && at + 1 < name.len())
+        })
+        .flatten();
+    let (prefix, short) = match split {
+        Some(at) => (&name[..at], &name[at + 1..]),
+        None => ("", name),
+    };
+    if short.len() > 100 {
+        let mut long = name.as_bytes().to_vec();
+        long.push(0);
+        member.extend_from_slice(&tar_header("././@LongLink", "", b'L', long.len() as u64, now));
+        let padded = long.len().next_multiple_of(TAR_BLOCK as usize);
+        long.resize(padded, 0);
+        member.extend_from_slice(&long);
+    }
+    member.extend_from_slice(&tar_header(short, prefix, b'0', size, now));
+    member
+}
+
+fn tar_header(name: &str, prefix: &str, kind: u8, size: u64, now: u64) -> [u8; TAR_BLOCK as usize] {
+    let mut header = [0u8; TAR_BLOCK as usize];
+    let name = &name.as_bytes()[..name.len().min(100)];
+    header[..name.len()].copy_from_slice(name);
+    header[100..108].copy_from_slice(format!("{:07o}\0", MEMBER_MODE).as_bytes());
+    header[108..116].copy_from_slice(b"0000000\0");
+    header[116..124].copy_from_slice(b"0000000\0");
+    if size < 1 << 33 {
+        header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+    } else {
+        // Big-endian binary with the first bit set, as GNU tar writes
+        // sizes of 8 GiB and more.
+        header[124] = 0x80;
+        header[128..136].copy_from_slice(&size.to_be_bytes());
+    }
+    header[136..148].copy_from_slice(format!("{:011o}\0", now.min(0o77777777777)).as_bytes());
+    header[148..156].fill(b' ');
+    header[156] = kind;
+    header[257..265].copy_from_slice(b"ustar\x0000");
+    header[345..345 + prefix.len()].copy_from_slice(prefix.as_bytes());
+    let sum: u32 = header.iter().map(|&byte| byte as u32).sum();
+    header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    header
+}
+
+/// A Unix time as the MS-DOS time and date zip stores, in UTC. Dates
+/// before 1980 cannot be written and become 1980-01-01.
+fn dos_time(timestamp: u64) -> (u16, u16) {
+    let (year, month, day) = history::civil_date(timestamp);
+    if year < 1980 {
+        return (0, 1 << 5 | 1);
+    }
+    let seconds = timestamp % 86_400;
+    let time = (seconds / 3600) << 11 | (seconds % 3600 / 60) << 5 | (seconds % 60 / 2);
+    let date = ((year - 1980).min(127) as u64) << 9 | (month as u64) << 5 | day as u64;
+    (time as u16, date as u16)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    /// A zip from Python's zipfile: a `docs/` folder, `docs/notes.txt`
+    /// deflated ("notes " 40 times) and `plain.txt` stored ("stored").
+    const ZIP: &str = "504b03041400000000000000210000000000000000000000000005000000646f63732f504b0304140000000800ed38505d51e105250b000000f00000000e000000646f63732f6e6f7465732e747874cbcb2f492d56c81b312400504b0304140000000000ed38505d0bf94356060000000600000009000000706c61696e2e74787473746f726564504b0102140314000000000000002100000000000000000000000000050000000000000000000000800100000000646f63732f504b01021403140000000800ed38505d51e105250b000000f00000000e0000000000000000000000800123000000646f63732f6e6f7465732e747874504b01021403140000000000ed38505d0bf94356060000000600000009000000000000000000000080015a000000706c61696e2e747874504b05060000000003000300a6000000870000000000";
+
+    /// Where the central directory of `ZIP` starts, and its second and
+    /// third records.
+    const CENTRAL: usize = 0x87;
+    const NOTES_RECORD: usize = CENTRAL + 46 + 5;
+    const PLAIN_RECORD: usize = NOTES_RECORD + 46 + 14;
+    /// Where the end record of `ZIP` starts.
+    const END: usize = ZIP.len() / 2 - 22;
+
+    /// A change to `ZIP` and the entry to read from the result.
+    type Edit = (&'static str, fn(&mut Vec<u8>));
+
+    fn zip() -> Vec<u8> {
+        (0..ZIP.len()).step_by(2).map(|index| u8::from_str_radix(&ZIP[index..index + 2], 16).unwrap()).collect()
+    }
+
+    /// Read `entry` from an archive with the given bytes, saved under
+    /// `name` in `scratch`.
+    fn read_from(scratch: &Scratch, name: &str, bytes: &[u8], entry: &str) -> Result<Vec<u8>, String> {
+        let path = scratch.dir.join(name);
+        std::fs::write(&path, bytes).unwrap();
+        read(&path, entry)
+    }
+
+    /// Store the checksum of a tar header again after it was changed.
+    fn reseal(header: &mut [u8]) {
+        header[148..156].fill(b' ');
+        let sum: u32 = header.iter().map(|&byte| byte as u32).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+    }
+
+    #[test]
+    fn reads_entries_of_a_foreign_zip() {
+        let scratch = Scratch::create().unwrap();
+        let zip = zip();
+        assert_eq!(read_from(&scratch, "a.zip", &zip, "docs/notes.txt").unwrap(), b"notes ".repeat(40));
+        assert_eq!(read_from(&scratch, "a.zip", &zip, "./plain.txt").unwrap(), b"stored");
+        assert!(read_from(&scratch, "a.zip", &zip, "docs").unwrap_err().contains("is a folder"));
+        assert!(read_from(&scratch, "a.zip", &zip, "missing.txt").unwrap_err().contains("does not exist"));
+
+        let path = scratch.dir.join("a.zip");
+        let (archive, entry) = split(&scratch.dir.join("a.zip!docs/notes.txt")).unwrap();
+        assert_eq!((archive, entry.as_str()), (path.clone(), "docs/notes.txt"));
+        assert_eq!(output_base(&scratch.dir.join("a.zip!docs/notes.txt")), scratch.dir.join("notes.txt"));
+        assert_eq!(split(&scratch.dir.join("b.zip!docs/notes.txt")), None);
+        scratch.destroy().unwrap();
+    }
+
+    #[test]
+    fn truncated_zips_are_refused() {
+        let scratch = Scratch::create().unwrap();
+        let zip = zip();
+        for len in 0..zip.len() {
+            assert!(read_from(&scratch, "a.zip", &zip[..len], "plain.txt").is_err(), "cut to {} bytes", len);
+        }
+        scratch.destroy().unwrap();
+    }
+
+    #[test]
+    fn hostile_zips_are_refused() {
+        let scratch = Scratch::create().unwrap();
+        let edits: [Edit; 9] = [
+            // The directory said to be past the end of the file.
+            ("plain.txt", |zip| zip[END + 16..END + 20].copy_from_slice(&0xffff_0000u32.to_le_bytes())),
+            // A Zip64 count with no Zip64 record behind it.
+            ("plain.txt", |zip| zip[END + 10..END + 12].copy_from_slice(&[0xff, 0xff])),
+            // A name running past the directory.
+            ("plain.txt", |zip| zip[CENTRAL + 28..CENTRAL + 30].copy_from_slice(&[0xff, 0xff])),
+            // More entries than there are records.
+            ("plain.txt", |zip| zip[END + 10] = 9),
+            // Local header past the end of the file.
+            ("plain.txt", |zip| zip[PLAIN_RECORD + 42..PLAIN_RECORD + 46].copy_from_slice(&0x7fff_0000u32.to_le_bytes())),
+            // Compressed data running past the end of the file.
+            ("docs/notes.txt", |zip| zip[NOTES_RECORD + 20..NOTES_RECORD + 24].copy_from_slice(&0x7fff_0000u32.to_le_bytes())),
+            // A size that does not match what the data expands to.
+            ("docs/notes.txt", |zip| zip[NOTES_RECORD + 24] = 0xef),
+            // Contents that no longer match the CRC-32.
+            ("plain.txt", |zip| {
+                let at = zip.windows(6).position(|window| window == b"stored").unwrap();
+                zip[at] = b'S';
+            }),
+            // A compression method this reader does not know.
+            ("plain.txt", |zip| zip[PLAIN_RECORD + 10] = 12),
+        ];
+        for (number, (entry, edit)) in edits.into_iter().enumerate() {
+            let mut hostile = zip();
+            edit(&mut hostile);
+            assert!(read_from(&scratch, "a.zip", &hostile, entry).is_err(), "edit {}", number);
+        }
+        let mut encrypted = zip();
+        encrypted[PLAIN_RECORD + 8] |= 1;
+        assert!(read_from(&scratch, "a.zip", &encrypted, "plain.txt").unwrap_err().contains("encrypted by the zip"));
+        scratch.destroy().unwrap();
+    }
+
+    #[test]
+    fn appended_members_read_back() {
+        let scratch = Scratch::create().unwrap();
+        let long_name = format!("{}/{}", "folder".repeat(20), "n".repeat(90));
+        let longer_name = "x".repeat(150);
+        let large: Vec<u8> = (0..5000).map(|index| index as u8).collect();
+        for name in ["out.zip", "out.tar"] {
+            let path = scratch.dir.join(name);
+            let mut appender = Appender::open(&path).unwrap();
+            appender.add("a.txt.enc", b"first").unwrap();
+            appender.add(&long_name, &large).unwrap();
+            drop(appender);
+
+            // Opened again, the members already there are kept.
+            let mut appender = Appender::open(&path).unwrap();
+            assert_eq!(appender.names().len(), 2);
+            assert!(appender.names().contains(Path::new("a.txt.enc")));
+            appender.add(&longer_name, b"").unwrap();
+            drop(appender);
+
+            assert_eq!(read(&path, "a.txt.enc").unwrap(), b"first", "{}", name);
+            assert_eq!(read(&path, &long_name).unwrap(), large, "{}", name);
+            assert_eq!(read(&path, &longer_name).unwrap(), b"", "{}", name);
+        }
+        assert!(Appender::open(&scratch.dir.join("out.tar.gz")).is_err());
+        scratch.destroy().unwrap();
+    }
+
+    #[test]
+    fn damaged_tars_are_refused() {
+        let scratch = Scratch::create().unwrap();
+        let mut tar = tar_member("file.bin", 1000, 0);
+        tar.extend_from_slice(&[7; 1000]);
+        tar.resize(3 * TAR_BLOCK as usize, 0);
+        tar.extend_from_slice(&[0; 2 * TAR_BLOCK as usize]);
+        assert_eq!(read_from(&scratch, "a.tar", &tar, "file.bin").unwrap(), vec![7; 1000]);
+
+        // Cut anywhere before the end of the contents, the entry is lost.
+        for len in (0..TAR_BLOCK as usize + 1000).step_by(50) {
+            assert!(read_from(&scratch, "a.tar", &tar[..len], "file.bin").is_err(), "cut to {} bytes", len);
+        }
+
+        let mut bad_sum = tar.clone();
+        bad_sum[0] = b'F';
+        assert!(read_from(&scratch, "a.tar", &bad_sum, "file.bin").unwrap_err().contains("damaged"));
+
+        let sizes: [&[u8]; 3] = [b"77777777777\0", b"not a size!\0", &[0x80, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]];
+        for size in sizes {
+            let mut hostile = tar.clone();
+            hostile[124..136].copy_from_slice(size);
+            reseal(&mut hostile[..TAR_BLOCK as usize]);
+            assert!(read_from(&scratch, "a.tar", &hostile, "file.bin").unwrap_err().contains("damaged"));
+        }
+        scratch.destroy().unwrap();
+    }
+
+    #[test]
+    fn tar_fields() {
+        assert_eq!(parse_octal(b"0000644\0"), Some(0o644));
+        assert_eq!(parse_octal(b"  \0\0"), Some(0));
+        assert_eq!(parse_octal(b"0009\0"), None);
+        assert_eq!(parse_size(&[0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0]), Some(256));
+        assert_eq!(parse_size(&[0x80, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff]), None);
+        let header = tar_header("big", "", b'0', 10 << 30, 0);
+        assert_eq!(parse_size(&header[124..136]), Some(10 << 30));
+
+        assert_eq!(pax_path(b"12 path=abc\n"), Some("abc".to_string()));
+        assert_eq!(pax_path(b"20 mtime=1.5\n12 path=abc\n"), None);
+        assert_eq!(pax_path(b"13 mtime=1.5\n12 path=abc\n"), Some("abc".to_string()));
+        for hostile in [&b"99999999999999999999999 path=x\n"[..], b"99 path=x\n", b"0 path=x\n", b"x path=y\n"] {
+            assert_eq!(pax_path(hostile), None);
+        }
+    }
+
+    #[test]
+    fn dos_times() {
+        let (time, date) = dos_time(1_709_210_096);
+        assert_eq!(time, 12 << 11 | 34 << 5 | 28);
+        assert_eq!(date, 44 << 9 | 2 << 5 | 29);
+        assert_eq!(dos_time(0), (0, 1 << 5 | 1));
+    }
+}
@@ -0,0 +1,609 @@
+use std::borrow::Cow;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use crate::checksum;
+
+/// How much parity new files get, as a percentage of their size, or 0 for
+/// none (`--parity PERCENT`).
+static PERCENT: AtomicU8 = AtomicU8::new(0);
+
+const MAGIC: &[u8; 4] = b"FPAR";
+const VERSION: u8 = 1;
+const FOOTER_LEN: usize = 32;
+/// Bytes per block: a few sectors of a disc or flash drive, so a sector
+/// that cannot be read spoils one block and no more.
+const BLOCK_SIZE: usize = 4096;
+/// Most data and parity blocks one group may have together. Each block
+/// needs its own value in GF(256), and 255 of them are usable.
+const MAX_GROUP: usize = 255;
+/// Checksums in one segment of the table, 256 bytes with the CRC-32 of the
+/// segment that ends it. A damaged segment is told apart by that CRC and
+/// read from the other copy of the table instead, so damage to both
+/// copies loses only the checksums of the segments hit in each.
+const SEGMENT_ENTRIES: usize = 63;
+
+/// Add parity of `percent` percent to every file encrypted in this run.
+pub fn set_percent(percent: u64) -> Result<(), String> {
+    PERCENT.store(check_percent(percent)?, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Refuse a redundancy above 100%, which the code has no room for.
+pub fn check_percent(percent: u64) -> Result<u8, String> {
+    match u8::try_from(percent) {
+        Ok(percent @ 0..=100) => Ok(percent),
+        _ => Err(format!("The parity must be between 0 and 100 percent, got {}.", percent)),
+    }
+}
+
+/// `encrypted` followed by parity blocks if `--parity` is on.
+pub fn protect_if_enabled(encrypted: Vec<u8>) -> Vec<u8> {
+    match PERCENT.load(Ordering::Relaxed) {
+        0 => encrypted,
+        percent => protect(encrypted, percent),
+    }
+}
+
+/// Append Reed-Solomon parity of about `percent` percent to `data`, so that
+/// blocks damaged on flaky media can be rebuilt when it is read back.
+///
+/// The data is cut into blocks of `BLOCK_SIZE` bytes, and block `i` goes
+/// into group `i % groups`, so a run of bad sectors is spread over many
+/// groups instead of spoiling one. Each group gets enough parity blocks
+/// that any that many of its blocks can be lost. After the data come a
+/// copy of the footer, the parity blocks (interleaved the same way), two
+/// copies of a table with a CRC-32 of every data and parity block, and the
+/// footer, which gives the length of the data so the trailer can be found
+/// and left out again.
+pub fn protect(mut data: Vec<u8>, percent: u8) -> Vec<u8> {
+    let layout = Layout::new(data.len() as u64, percent);
+    let mut parity = vec![0u8; layout.parity_blocks * BLOCK_SIZE];
+    for (group, positions) in layout.positions().iter().enumerate() {
+        let members = layout.members(group);
+        for (column, &index) in members.iter().enumerate() {
+            let block = data_block(&data, index);
+            for (row, &position) in positions.iter().enumerate() {
+                let out = &mut parity[position * BLOCK_SIZE..(position + 1) * BLOCK_SIZE];
+                mul_add(out, block, cauchy(members.len(), row, column));
+            }
+        }
+    }
+
+    let crcs: Vec<u32> = data.chunks(BLOCK_SIZE).chain(parity.chunks(BLOCK_SIZE)).map(checksum::crc32).collect();
+    let table = encode_table(&crcs);
+    let footer = layout.footer();
+    data.reserve(layout.trailer_len().unwrap_or(0));
+    data.extend_from_slice(&footer);
+    data.extend_from_slice(&parity);
+    data.extend_from_slice(&table);
+    data.extend_from_slice(&table);
+    data.extend_from_slice(&footer);
+    data
+}
+
+/// The redundancy `data` was protected with, if it has parity.
+pub fn percent_of(data: &[u8]) -> Option<u8> {
+    find(data).map(|layout| layout.percent)
+}
+
+/// What `repair` found.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Repair {
+    /// Damaged data blocks rebuilt from the parity.
+    pub repaired: usize,
+    /// Checksums damaged in both copies of the table. The blocks they
+    /// cover are rebuilt from the parity when there is enough of it, and
+    /// otherwise left as they are.
+    pub checksums_lost: usize,
+}
+
+/// `data` without its parity trailer, with any damaged blocks rebuilt.
+/// Data without a trailer comes back unchanged.
+///
+/// Fails if a group has more damaged blocks than it has parity for.
+pub fn repair(data: &[u8]) -> Result<(Cow<'_, [u8]>, Repair), String> {
+    let Some(layout) = find(data) else {
+        return Ok((Cow::Borrowed(data), Repair::default()));
+    };
+    // `find` checked that all of this fits within `data`.
+    let stored = &data[..layout.data_len as usize];
+    let parity = &data[stored.len() + FOOTER_LEN..][..layout.parity_blocks * BLOCK_SIZE];
+    let entries = layout.data_blocks + layout.parity_blocks;
+    let tables = &data[stored.len() + FOOTER_LEN + parity.len()..data.len() - FOOTER_LEN];
+    let (first, second) = tables.split_at(tables.len() / 2);
+    let crcs = decode_table(first, second, entries);
+    let mut report = Repair {
+        repaired: 0,
+        checksums_lost: crcs.iter().filter(|crc| crc.is_none()).count(),
+    };
+    // Whether a block matches its checksum, or `None` if that was lost.
+    let intact = |index: usize, block: &[u8]| crcs[index].map(|crc| checksum::crc32(block) == crc);
+    let parity_block = |position: usize| &parity[position * BLOCK_SIZE..(position + 1) * BLOCK_SIZE];
+
+    let mut output: Option<Vec<u8>> = None;
+    for (group, positions) in layout.positions().iter().enumerate() {
+        let members = layout.members(group);
+        let mut damaged = Vec::new();
+        let mut unknown = Vec::new();
+        for (column, &index) in members.iter().enumerate() {
+            match intact(index, data_block(stored, index)) {
+                Some(true) => {}
+                Some(false) => damaged.push(column),
+                None => unknown.push(column),
+            }
+        }
+        let good_parity: Vec<(usize, usize)> = positions
+            .iter()
+            .copied()
+            .enumerate()
+            .filter(|&(_, position)| intact(layout.data_blocks + position, parity_block(position)) == Some(true))
+            .collect();
+        // Blocks without a checksum are rebuilt too when the parity is
+        // enough for them all, and otherwise trusted.
+        let mut erased = damaged;
+        if erased.len() + unknown.len() <= good_parity.len() {
+            erased.extend(unknown);
+        }
+        if erased.is_empty() {
+            continue;
+        }
+        if good_parity.len() < erased.len() {
+            let first = members[erased[0]] as u64 * BLOCK_SIZE as u64;
+            return Err(format!(
+                "{} block(s) of the encrypted data are damaged (the first at byte {}), but only {} parity block(s) that could replace them are intact, so the file cannot be repaired.",
+                erased.len(),
+                first,
+                good_parity.len()
+            ));
+        }
+        let good_parity = &good_parity[..erased.len()];
+
+        // Each intact parity block, less what the intact data blocks put
+        // into it, is a sum of the erased blocks over the matching rows of
+        // the Cauchy matrix. Solving that system gives the erased blocks.
+        let mut sums: Vec<Vec<u8>> = Vec::with_capacity(erased.len());
+        for &(row, position) in good_parity {
+            let mut sum = parity_block(position).to_vec();
+            for (column, &index) in members.iter().enumerate() {
+                if !erased.contains(&column) {
+                    mul_add(&mut sum, data_block(stored, index), cauchy(members.len(), row, column));
+                }
+            }
+            sums.push(sum);
+        }
+        let matrix: Vec<Vec<u8>> = good_parity
+            .iter()
+            .map(|&(row, _)| erased.iter().map(|&column| cauchy(members.len(), row, column)).collect())
+            .collect();
+        let solution = invert(matrix);
+        for (i, &column) in erased.iter().enumerate() {
+            let mut rebuilt = vec![0u8; BLOCK_SIZE];
+            for (j, sum) in sums.iter().enumerate() {
+                mul_add(&mut rebuilt, sum, solution[i][j]);
+            }
+            let start = members[column] * BLOCK_SIZE;
+            let block = data_block(stored, members[column]);
+            let rebuilt = &rebuilt[..block.len()];
+            if rebuilt != block {
+                let output = output.get_or_insert_with(|| stored.to_vec());
+                output[start..start + block.len()].copy_from_slice(rebuilt);
+                report.repaired += 1;
+            }
+        }
+    }
+    let data = match output {
+        Some(output) => Cow::Owned(output),
+        None => Cow::Borrowed(stored),
+    };
+    Ok((data, report))
+}
+
+/// Block `index` of `data`; the last one may be short.
+fn data_block(data: &[u8], index: usize) -> &[u8] {
+    &data[index * BLOCK_SIZE..((index + 1) * BLOCK_SIZE).min(data.len())]
+}
+
+/// The checksums as stored: segments of up to `SEGMENT_ENTRIES`, each
+/// followed by a CRC-32 of the segment.
+fn encode_table(crcs: &[u32]) -> Vec<u8> {
+    let mut table = Vec::with_capacity(table_len(crcs.len()).unwrap_or(0));
+    for segment in crcs.chunks(SEGMENT_ENTRIES) {
+        let start = table.len();
+        for crc in segment {
+            table.extend_from_slice(&crc.to_le_bytes());
+        }
+        let check = checksum::crc32(&table[start..]);
+        table.extend_from_slice(&check.to_le_bytes());
+    }
+    table
+}
+
+/// The `entries` checksums from two copies of the table, each segment
+/// taken from whichever copy has it intact, or `None` for the checksums of
+/// a segment damaged in both.
+fn decode_table(first: &[u8], second: &[u8], entries: usize) -> Vec<Option<u32>> {
+    let mut crcs = Vec::with_capacity(entries);
+    let mut at = 0;
+    for start in (0..entries).step_by(SEGMENT_ENTRIES) {
+        let count = (entries - start).min(SEGMENT_ENTRIES);
+        let len = count * 4;
+        let segment = [first, second]
+            .into_iter()
+            .map(|copy| &copy[at..at + len + 4])
+            .find(|segment| segment[len..] == checksum::crc32(&segment[..len]).to_le_bytes());
+        for entry in 0..count {
+            crcs.push(segment.map(|segment| {
+                u32::from_le_bytes([
+                    segment[entry * 4],
+                    segment[entry * 4 + 1],
+                    segment[entry * 4 + 2],
+                    segment[entry * 4 + 3],
+                ])
+            }));
+        }
+        at += len + 4;
+    }
+    crcs
+}
+
+/// Bytes one copy of the table takes for `entries` checksums.
+fn table_len(entries: usize) -> Option<usize> {
+    entries.checked_mul(4)?.checked_add(entries.div_ceil(SEGMENT_ENTRIES) * 4)
+}
+
+/// How the blocks of a protected file are arranged.
+#[derive(Debug, Clone, Copy)]
+struct Layout {
+    data_len: u64,
+    percent: u8,
+    data_blocks: usize,
+    groups: usize,
+    parity_blocks: usize,
+}
+
+impl Layout {
+    /// The layout for `data_len` bytes of data, which must be no more than
+    /// is in memory.
+    fn new(data_len: u64, percent: u8) -> Self {
+        let data_blocks = data_len.div_ceil(BLOCK_SIZE as u64) as usize;
+        // The largest group whose parity still fits beside it.
+        let mut most = MAX_GROUP * 100 / (100 + percent as usize);
+        while most + parity_for(most, percent) > MAX_GROUP {
+            most -= 1;
+        }
+        let groups = data_blocks.div_ceil(most).max(1);
+        let mut layout = Layout {
+            data_len,
+            percent,
+            data_blocks,
+            groups,
+            parity_blocks: 0,
+        };
+        layout.parity_blocks = (0..groups).map(|group| layout.parity_count(group)).sum();
+        layout
+    }
+
+    /// The data blocks in `group`: every `groups`th block from its number.
+    fn members(&self, group: usize) -> Vec<usize> {
+        (group..self.data_blocks).step_by(self.groups).collect()
+    }
+
+    fn parity_count(&self, group: usize) -> usize {
+        let members = self.data_blocks / self.groups + usize::from(group < self.data_blocks % self.groups);
+        parity_for(members, self.percent)
+    }
+
+    /// Where the parity blocks of each group are among all parity blocks,
+    /// in row order. Row `r` of every group comes before row `r + 1` of
+    /// any.
+    fn positions(&self) -> Vec<Vec<usize>> {
+        let counts: Vec<usize> = (0..self.groups).map(|group| self.parity_count(group)).collect();
+        let mut positions: Vec<Vec<usize>> = counts.iter().map(|&count| Vec::with_capacity(count)).collect();
+        let mut position = 0;
+        for row in 0..counts.iter().copied().max().unwrap_or(0) {
+            for (group, &count) in counts.iter().enumerate() {
+                if row < count {
+                    positions[group].push(position);
+                    position += 1;
+                }
+            }
+        }
+        positions
+    }
+
+    /// Bytes the trailer adds after the data, or `None` if that is more
+    /// than this machine can count.
+    fn trailer_len(&self) -> Option<usize> {
+        let table = table_len(self.data_blocks.checked_add(self.parity_blocks)?)?;
+        self.parity_blocks
+            .checked_mul(BLOCK_SIZE)?
+            .checked_add(table.checked_mul(2)?)?
+            .checked_add(2 * FOOTER_LEN)
+    }
+
+    fn footer(&self) -> [u8; FOOTER_LEN] {
+        let mut footer = [0u8; FOOTER_LEN];
+        footer[..4].copy_from_slice(MAGIC);
+        footer[4] = VERSION;
+        footer[5] = self.percent;
+        footer[8..12].copy_from_slice(&(BLOCK_SIZE as u32).to_le_bytes());
+        footer[12..20].copy_from_slice(&self.data_len.to_le_bytes());
+        let crc = checksum::crc32(&footer[..FOOTER_LEN - 4]);
+        footer[FOOTER_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        footer
+    }
+
+    /// Read a footer, if `bytes` starts with an intact one that fits a
+    /// file of `file_len` bytes. The CRC only catches damage, so every
+    /// value is checked before it is used.
+    fn parse(bytes: &[u8], file_len: usize) -> Option<Self> {
+        let footer: &[u8; FOOTER_LEN] = bytes.get(..FOOTER_LEN)?.try_into().ok()?;
+        let crc = u32::from_le_bytes(footer[FOOTER_LEN - 4..].try_into().ok()?);
+        if &footer[..4] != MAGIC || footer[4] != VERSION || checksum::crc32(&footer[..FOOTER_LEN - 4]) != crc {
+            return None;
+        }
+        let percent = footer[5];
+        let block_size = u32::from_le_bytes(footer[8..12].try_into().ok()?);
+        let data_len = u64::from_le_bytes(footer[12..20].try_into().ok()?);
+        if !(1..=100).contains(&percent) || block_size as usize != BLOCK_SIZE || data_len > file_len as u64 {
+            return None;
+        }
+        let layout = Layout::new(data_len, percent);
+        let trailer_len = layout.trailer_len()?;
+        (data_len as usize).checked_add(trailer_len).filter(|&len| len == file_len)?;
+        Some(layout)
+    }
+}
+
+/// Parity blocks for a group of `members` data blocks: `percent` percent,
+/// rounded up, and at least one.
+fn parity_for(members: usize, percent: u8) -> usize {
+    (members * percent as usize).div_ceil(100).max(1)
+}
+
+/// The layout of the trailer on `data`, if it has one. The footer at the
+/// end is tried first; if it is damaged, the copy straight after the data
+/// is searched for.
+fn find(data: &[u8]) -> Option<Layout> {
+    if let Some(layout) = data
+        .len()
+        .checked_sub(FOOTER_LEN)
+        .and_then(|at| Layout::parse(&data[at..], data.len()))
+    {
+        return Some(layout);
+    }
+    let end = data.len().checked_sub(2 * FOOTER_LEN)?;
+    (0..=end).rev().find_map(|at| {
+        if &data[at..at + 4] != MAGIC {
+            return None;
+        }
+        Layout::parse(&data[at..], data.len()).filter(|layout| layout.data_len == at as u64)
+    })
+}
+
+/// The coefficient of data block `column` in parity block `row` of a group
+/// with `members` data blocks: 1 / (x + y) with x = `members + row` and
+/// y = `column`. Every square part of such a matrix can be inverted, so any
+/// damaged blocks can be solved for with as many intact parity blocks.
+fn cauchy(members: usize, row: usize, column: usize) -> u8 {
+    inverse((members + row) as u8 ^ column as u8)
+}
+
+/// Invert a square matrix over GF(256) by Gauss-Jordan elimination. Parts
+/// of a Cauchy matrix always have an inverse.
+fn invert(mut matrix: Vec<Vec<u8>>) -> Vec<Vec<u8>> {
+    let size = matrix.len();
+    let mut result: Vec<Vec<u8>> = (0..size)
+        .map(|row| (0..size).map(|column| u8::from(row == column)).collect())
+        .collect();
+    for column in 0..size {
+        let pivot = (column..size).find(|&row| matrix[row][column] != 0).unwrap_or(column);
+        matrix.swap(column, pivot);
+        result.swap(column, pivot);
+        let scale = inverse(matrix[column][column]);
+        for value in matrix[column].iter_mut().chain(result[column].iter_mut()) {
+            *value = mul(*value, scale);
+        }
+        for row in 0..size {
+            let factor = matrix[row][column];
+            if row == column || factor == 0 {
+                continue;
+            }
+            for k in 0..size {
+                matrix[row][k] ^= mul(factor, matrix[column][k]);
+                result[row][k] ^= mul(factor, result[column][k]);
+            }
+        }
+    }
+    result
+}
+
+/// Add `factor` times `source` to `out`, byte by byte, in GF(256). A
+/// short `source`, such as the last block of the data, counts as padded
+/// with zeros.
+fn mul_add(out: &mut [u8], source: &[u8], factor: u8) {
+    if factor == 0 {
+        return;
+    }
+    let mut row = [0u8; 256];
+    for (value, product) in row.iter_mut().enumerate() {
+        *product = mul(value as u8, factor);
+    }
+    for (out, &byte) in out.iter_mut().zip(source) {
+        *out ^= row[byte as usize];
+    }
+}
+
+fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    EXP[LOG[a as usize] as usize + LOG[b as usize] as usize]
+}
+
+fn inverse(a: u8) -> u8 {
+    EXP[255 - LOG[a as usize] as usize]
+}
+
+/// Powers of 2 in GF(256) with the polynomial x^8 + x^4 + x^3 + x^2 + 1,
+/// twice over so a sum of two logarithms needs no reduction.
+const EXP: [u8; 512] = {
+    let mut table = [0u8; 512];
+    let mut value: u32 = 1;
+    let mut index = 0;
+    while index < 512 {
+        table[index] = value as u8;
+        value <<= 1;
+        if value & 0x100 != 0 {
+            value ^= 0x11d;
+        }
+        index += 1;
+    }
+    table
+};
+
+/// Logarithms to base 2 in GF(256), the reverse of `EXP`.
+const LOG: [u8; 256] = {
+    let mut table = [0u8; 256];
+    let mut index = 0;
+    while index < 255 {
+        table[EXP[index] as usize] = index as u8;
+        index += 1;
+    }
+    table
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Data that differs from block to block, so a block rebuilt from the
+    /// wrong place would show.
+    fn sample(len: usize) -> Vec<u8> {
+        (0..len).map(|index| (index * 7 + index / BLOCK_SIZE) as u8).collect()
+    }
+
+    /// A footer for `data_len` bytes, changed by `edit` and then given a
+    /// correct CRC again, as a forger would.
+    fn forged_footer(data_len: u64, edit: impl FnOnce(&mut [u8; FOOTER_LEN])) -> [u8; FOOTER_LEN] {
+        let mut footer = Layout::new(data_len, 10).footer();
+        edit(&mut footer);
+        let crc = checksum::crc32(&footer[..FOOTER_LEN - 4]);
+        footer[FOOTER_LEN - 4..].copy_from_slice(&crc.to_le_bytes());
+        footer
+    }
+
+    #[test]
+    fn field_arithmetic() {
+        for a in 1..=255u8 {
+            assert_eq!(mul(a, inverse(a)), 1);
+            assert_eq!(mul(a, 1), a);
+            assert_eq!(mul(a, 0), 0);
+        }
+    }
+
+    #[test]
+    fn round_trip_without_damage() {
+        for len in [0, 1, BLOCK_SIZE - 1, BLOCK_SIZE, BLOCK_SIZE + 1, 300 * BLOCK_SIZE + 5] {
+            let data = sample(len);
+            let protected = protect(data.clone(), 10);
+            assert_eq!(percent_of(&protected), Some(10));
+            let (repaired, report) = repair(&protected).unwrap();
+            assert_eq!(&repaired[..], &data[..], "length {}", len);
+            assert_eq!(report, Repair::default());
+        }
+        // Data without a trailer passes through untouched.
+        let plain = sample(5000);
+        assert_eq!(percent_of(&plain), None);
+        assert!(matches!(repair(&plain).unwrap().0, Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn repairs_a_burst_of_damage() {
+        // 300 blocks at 10% make two groups of 150 with 15 parity blocks
+        // each; a burst of 20 blocks costs each group 10.
+        let data = sample(300 * BLOCK_SIZE + 100);
+        let mut protected = protect(data.clone(), 10);
+        for byte in &mut protected[40 * BLOCK_SIZE + 3..60 * BLOCK_SIZE + 3] {
+            *byte ^= 0x5a;
+        }
+        // The footer at the end is lost too; the copy after the data is used.
+        let len = protected.len();
+        protected[len - 5] ^= 1;
+        let (repaired, report) = repair(&protected).unwrap();
+        assert_eq!(&repaired[..], &data[..]);
+        assert_eq!(report.repaired, 21);
+        assert_eq!(report.checksums_lost, 0);
+    }
+
+    #[test]
+    fn repairs_with_one_copy_of_the_table_damaged() {
+        let data = sample(20 * BLOCK_SIZE);
+        let mut protected = protect(data.clone(), 20);
+        let layout = find(&protected).unwrap();
+        let table = table_len(layout.data_blocks + layout.parity_blocks).unwrap();
+        let first_table = protected.len() - FOOTER_LEN - 2 * table;
+        protected[first_table + 1] ^= 0xff;
+        protected[BLOCK_SIZE * 3] ^= 0xff;
+        let (repaired, report) = repair(&protected).unwrap();
+        assert_eq!(&repaired[..], &data[..]);
+        assert_eq!(report, Repair { repaired: 1, checksums_lost: 0 });
+
+        // With the same segment hit in both copies its checksums are lost,
+        // and with no parity known to be good the blocks are trusted.
+        protected[BLOCK_SIZE * 3] ^= 0xff;
+        protected[first_table + table + 1] ^= 0xff;
+        let (repaired, report) = repair(&protected).unwrap();
+        assert_eq!(&repaired[..], &data[..]);
+        assert_eq!(report, Repair { repaired: 0, checksums_lost: layout.data_blocks + layout.parity_blocks });
+    }
+
+    #[test]
+    fn refuses_more_damage_than_parity() {
+        let data = sample(300 * BLOCK_SIZE);
+        let mut protected = protect(data, 10);
+        for byte in &mut protected[..40 * BLOCK_SIZE] {
+            *byte = !*byte;
+        }
+        assert!(repair(&protected).is_err());
+    }
+
+    #[test]
+    fn truncated_trailers_are_not_trusted() {
+        let protected = protect(sample(100), 50);
+        for len in 0..protected.len() {
+            assert_eq!(percent_of(&protected[..len]), None, "cut to {} bytes", len);
+        }
+    }
+
+    #[test]
+    fn hostile_footers_are_ignored() {
+        let data = sample(2 * BLOCK_SIZE);
+        let protected = protect(data.clone(), 10);
+        let at = protected.len() - FOOTER_LEN;
+        let edits: [fn(&mut [u8; FOOTER_LEN]); 7] = [
+            |footer| footer[4] = VERSION + 1,
+            |footer| footer[5] = 0,
+            |footer| footer[5] = 101,
+            |footer| footer[8..12].copy_from_slice(&512u32.to_le_bytes()),
+            |footer| footer[12..20].copy_from_slice(&u64::MAX.to_le_bytes()),
+            |footer| footer[12..20].copy_from_slice(&(u64::MAX / BLOCK_SIZE as u64).to_le_bytes()),
+            |footer| footer[12..20].copy_from_slice(&(2 * BLOCK_SIZE as u64 + 1).to_le_bytes()),
+        ];
+        for (number, edit) in edits.into_iter().enumerate() {
+            // Forge both footers so neither can be fallen back on.
+            let forged = forged_footer(data.len() as u64, edit);
+            let mut hostile = protected.clone();
+            hostile[at..].copy_from_slice(&forged);
+            hostile[data.len()..data.len() + FOOTER_LEN].copy_from_slice(&forged);
+            assert_eq!(percent_of(&hostile), None, "edit {}", number);
+            let (repaired, report) = repair(&hostile).unwrap();
+            assert_eq!(repaired.len(), hostile.len(), "edit {}", number);
+            assert_eq!(report, Repair::default());
+        }
+        // A lone footer claiming more data than there is.
+        let mut lone = sample(10);
+        lone.extend_from_slice(&forged_footer(1 << 40, |_| {}));
+        assert_eq!(percent_of(&lone), None);
+    }
+}
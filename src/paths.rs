@@ -0,0 +1,182 @@
+use std::borrow::Cow;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+/// `path` without the `\\?\` prefix Windows uses for extended-length
+/// paths, where the path means the same without it: `\\?\C:\dir` is
+/// `C:\dir` and `\\?\UNC\server\share` is `\\server\share`. The standard
+/// library adds the prefix itself when opening a long path, so it only
+/// gets in the way of showing and comparing paths.
+pub fn simplify(path: &Path) -> Cow<'_, Path> {
+    #[cfg(windows)]
+    if let Some(text) = path.to_str() {
+        if let Some(rest) = text.strip_prefix(r"\\?\UNC\") {
+            return Cow::Owned(PathBuf::from(format!(r"\\{}", rest)));
+        }
+        if let Some(rest) = text.strip_prefix(r"\\?\")
+            && rest.as_bytes().get(1) == Some(&b':')
+        {
+            return Cow::Owned(PathBuf::from(rest));
+        }
+    }
+    Cow::Borrowed(path)
+}
+
+/// Show `path` to the user. Names that are not valid Unicode are shown
+/// with replacement characters; use `encode` to store a path exactly.
+pub fn display(path: &Path) -> String {
+    simplify(path).to_string_lossy().into_owned()
+}
+
+/// `path` with `suffix` added to its last component, as in `report.pdf`
+/// to `report.pdf.enc`, without going through a `String`.
+pub fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.as_os_str());
+    name.push(suffix);
+    PathBuf::from(name)
+}
+
+/// Write `path` as one line of text that `decode` turns back into exactly
+/// the same path. Backslashes, tabs, and line breaks are escaped as `\\`,
+/// `\t`, and `\n`, and whatever is not valid Unicode as `\xHH` for each
+/// byte on Unix or `\uHHHH` for each unpaired surrogate on Windows.
+pub fn encode(path: &Path) -> String {
+    let mut text = String::with_capacity(path.as_os_str().len());
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        for chunk in path.as_os_str().as_bytes().utf8_chunks() {
+            push_escaped(&mut text, chunk.valid());
+            for byte in chunk.invalid() {
+                text.push_str(&format!("\\x{:02x}", byte));
+            }
+        }
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        for unit in char::decode_utf16(path.as_os_str().encode_wide()) {
+            match unit {
+                Ok(c) => push_escaped(&mut text, c.encode_utf8(&mut [0; 4])),
+                Err(e) => text.push_str(&format!("\\u{:04x}", e.unpaired_surrogate())),
+            }
+        }
+    }
+    #[cfg(not(any(unix, windows)))]
+    push_escaped(&mut text, &path.to_string_lossy());
+    text
+}
+
+fn push_escaped(text: &mut String, part: &str) {
+    for c in part.chars() {
+        match c {
+            '\\' => text.push_str("\\\\"),
+            '\t' => text.push_str("\\t"),
+            '\n' => text.push_str("\\n"),
+            _ => text.push(c),
+        }
+    }
+}
+
+/// Undo `encode`. Any other character after a backslash stands for
+/// itself.
+pub fn decode(text: &str) -> PathBuf {
+    let mut units = Vec::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '\\' => match chars.next() {
+                Some('t') => '\t',
+                Some('n') => '\n',
+                Some(RAW_ESCAPE) => {
+                    let digits: String = chars.by_ref().take(RAW_DIGITS).collect();
+                    match u16::from_str_radix(&digits, 16) {
+                        Ok(unit) => push_unit(&mut units, unit),
+                        Err(_) => push_str(&mut units, &format!("{}{}", RAW_ESCAPE, digits)),
+                    }
+                    continue;
+                }
+                Some(other) => other,
+                None => '\\',
+            },
+            c => c,
+        };
+        push_str(&mut units, c.encode_utf8(&mut [0; 4]));
+    }
+    from_units(units)
+}
+
+// The units a path is made of: bytes on Unix, UTF-16 code units on
+// Windows, and how `encode` writes one that is not valid Unicode.
+
+#[cfg(not(windows))]
+const RAW_ESCAPE: char = 'x';
+#[cfg(not(windows))]
+const RAW_DIGITS: usize = 2;
+#[cfg(windows)]
+const RAW_ESCAPE: char = 'u';
+#[cfg(windows)]
+const RAW_DIGITS: usize = 4;
+
+#[cfg(not(windows))]
+fn push_str(units: &mut Vec<u8>, text: &str) {
+    units.extend_from_slice(text.as_bytes());
+}
+
+#[cfg(not(windows))]
+fn push_unit(units: &mut Vec<u8>, unit: u16) {
+    units.push(unit as u8);
+}
+
+#[cfg(unix)]
+fn from_units(units: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(units))
+}
+
+#[cfg(not(any(unix, windows)))]
+fn from_units(units: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&units).into_owned())
+}
+
+#[cfg(windows)]
+fn push_str(units: &mut Vec<u16>, text: &str) {
+    units.extend(text.encode_utf16());
+}
+
+#[cfg(windows)]
+fn push_unit(units: &mut Vec<u16>, unit: u16) {
+    units.push(unit);
+}
+
+#[cfg(windows)]
+fn from_units(units: Vec<u16>) -> PathBuf {
+    use std::os::windows::ffi::OsStringExt;
+    PathBuf::from(OsString::from_wide(&units))
+}
+
+/// Undo percent-encoding, as done by `encodeURIComponent` and in URIs.
+/// Escapes for bytes that are not valid UTF-8 become replacement
+/// characters.
+pub fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], hex) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
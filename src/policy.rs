@@ -0,0 +1,311 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use crate::config::{parse_bool, parse_number};
+use crate::ed25519;
+use crate::format::{self, MAX_ITERATIONS, SEALED_VERSION};
+use crate::naming::Template;
+use crate::signature;
+
+/// Rules set by an administrator for every user of this machine. Unlike
+/// the config file, the policy cannot be overridden by the user: a policy
+/// file that cannot be read stops the tool instead of being skipped.
+#[derive(Debug, Clone)]
+pub struct Policy {
+    /// Where the policy was loaded from, or `None` when there is none.
+    pub path: Option<PathBuf>,
+    /// New files are derived with at least this many PBKDF2 iterations.
+    pub min_kdf_iterations: u32,
+    /// Whether new files may be written with ChaCha20. Encrypted logs,
+    /// containers and backups always use it.
+    pub allow_chacha20: bool,
+    /// Whether new files may be written with AES-256-GCM.
+    pub allow_aes_gcm: bool,
+    /// Whether files from the XOR era may be read.
+    pub allow_xor: bool,
+    /// Public keys every new file is also encrypted for, so the
+    /// organization can always recover its data.
+    pub required_recipients: Vec<[u8; ed25519::KEY_LEN]>,
+    /// Send audit events to the system log whatever the user's config
+    /// says.
+    pub audit_syslog: bool,
+    /// Write audit events to the Windows Event Log whatever the user's
+    /// config says.
+    pub audit_event_log: bool,
+    /// Event Log source to use, overriding the user's config.
+    pub audit_event_source: Option<String>,
+    /// Template every output of `encrypt` is named with.
+    pub name_template: Option<String>,
+}
+
+/// What applies without a policy file: everything is allowed.
+const NO_POLICY: Policy = Policy {
+    path: None,
+    min_kdf_iterations: 0,
+    allow_chacha20: true,
+    allow_aes_gcm: true,
+    allow_xor: true,
+    required_recipients: Vec::new(),
+    audit_syslog: false,
+    audit_event_log: false,
+    audit_event_source: None,
+    name_template: None,
+};
+
+/// The policy loaded at startup.
+static POLICY: OnceLock<Policy> = OnceLock::new();
+
+/// Returned by `get` until a policy is loaded, e.g. while the self-tests
+/// run.
+static DEFAULT: Policy = NO_POLICY;
+
+/// Read the policy file, if there is one, and make it apply for the rest
+/// of the run.
+pub fn load() -> Result<(), String> {
+    let path = policy_path();
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(format!("Failed to read the policy file '{}': {}", path.display(), e)),
+    };
+
+    let mut policy = Policy {
+        path: Some(path.clone()),
+        ..NO_POLICY
+    };
+    for (number, line) in contents.lines().enumerate() {
+        policy
+            .apply_line(line)
+            .map_err(|e| format!("{} line {}: {}", path.display(), number + 1, e))?;
+    }
+    let _ = POLICY.set(policy);
+    Ok(())
+}
+
+/// The policy in force. Without a policy file everything is allowed.
+pub fn get() -> &'static Policy {
+    POLICY.get().unwrap_or(&DEFAULT)
+}
+
+impl Policy {
+    /// Apply a single line from the policy file.
+    fn apply_line(&mut self, line: &str) -> Result<(), String> {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| "expected 'key = value'".to_string())?;
+        let key = key.trim();
+        let value = value.trim();
+
+        match key {
+            "min_kdf_iterations" => {
+                let iterations = parse_number(key, value)?;
+                if iterations > MAX_ITERATIONS as u64 {
+                    return Err(format!("'{}' cannot be more than {}", key, MAX_ITERATIONS));
+                }
+                self.min_kdf_iterations = iterations as u32;
+            }
+            "allowed_ciphers" => {
+                self.allow_chacha20 = false;
+                self.allow_aes_gcm = false;
+                self.allow_xor = false;
+                if value.trim().is_empty() {
+                    return Err(format!("'{}' names no cipher (known: chacha20, aes-gcm, xor)", key));
+                }
+                for cipher in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                    match cipher {
+                        "chacha20" => self.allow_chacha20 = true,
+                        "aes-gcm" => self.allow_aes_gcm = true,
+                        "xor" => self.allow_xor = true,
+                        _ => {
+                            return Err(format!(
+                                "unknown cipher '{}' in '{}' (known: chacha20, aes-gcm, xor)",
+                                cipher, key
+                            ));
+                        }
+                    }
+                }
+            }
+            "forbid_legacy_xor" => {
+                if parse_bool(key, value)? {
+                    self.allow_xor = false;
+                }
+            }
+            "required_recipients" => {
+                for recipient in value.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+                    let key = signature::load_public_key(recipient)?;
+                    if ed25519::public_to_x25519(&key).is_none() {
+                        return Err(format!("'{}' is not a valid public key", recipient));
+                    }
+                    if !self.required_recipients.contains(&key) {
+                        self.required_recipients.push(key);
+                    }
+                }
+                if self.required_recipients.len() > u8::MAX as usize {
+                    return Err(format!("at most {} recipients are supported", u8::MAX));
+                }
+            }
+            "audit_syslog" => {
+                self.audit_syslog = parse_bool(key, value)?;
+            }
+            "audit_event_log" => {
+                self.audit_event_log = parse_bool(key, value)?;
+            }
+            "audit_event_source" => {
+                self.audit_event_source = (!value.is_empty()).then(|| value.to_string());
+            }
+            "name_template" => {
+                if !value.is_empty() {
+                    Template::parse(value)?;
+                }
+                self.name_template = (!value.is_empty()).then(|| value.to_string());
+            }
+            _ => return Err(format!("unknown setting '{}'", key)),
+        }
+
+        Ok(())
+    }
+
+    /// The template outputs are named with: the policy's when it sets one,
+    /// refusing a different `requested` one, or else `requested`.
+    pub fn name_template<'a>(&'a self, requested: Option<&'a str>) -> Result<Option<&'a str>, String> {
+        match (&self.name_template, requested) {
+            (Some(required), Some(requested)) if requested != required => Err(self.refusal(&format!(
+                "requires outputs to be named with the template '{}'",
+                required
+            ))),
+            (Some(required), _) => Ok(Some(required)),
+            (None, requested) => Ok(requested),
+        }
+    }
+
+    /// Refuse to write a new file if the policy allows no cipher this tool
+    /// can write.
+    pub fn check_encrypt(&self) -> Result<(), String> {
+        if self.allow_chacha20 || self.allow_aes_gcm {
+            return Ok(());
+        }
+        Err(self.refusal("allows neither ChaCha20 nor AES-256-GCM, so no new files can be written"))
+    }
+
+    /// Refuse to write something only ChaCha20 can encrypt, such as an
+    /// encrypted log, if the policy does not allow it.
+    pub fn check_chacha20(&self, what: &str) -> Result<(), String> {
+        if self.allow_chacha20 {
+            return Ok(());
+        }
+        Err(self.refusal(&format!("does not allow the ChaCha20 cipher, which {} are written with", what)))
+    }
+
+    /// Refuse to read a file from the XOR era if the policy forbids it.
+    pub fn check_legacy_xor(&self) -> Result<(), String> {
+        if self.allow_xor {
+            return Ok(());
+        }
+        Err(self.refusal("forbids the old XOR cipher, so files from before version 3 cannot be read"))
+    }
+
+    /// Whether the encrypted file `data` already meets the policy: it is
+    /// sealed, derived with enough iterations, and encrypted for every
+    /// required recipient. `migrate` rewrites files that do not.
+    pub fn is_met_by(&self, data: &[u8]) -> bool {
+        let Ok(Some((version, _))) = format::split_header(data) else {
+            return false;
+        };
+        if version < SEALED_VERSION {
+            return false;
+        }
+        let Ok(params) = format::parse_sealed_header(data) else {
+            return false;
+        };
+        params.iterations >= self.min_kdf_iterations
+            && self.required_recipients.iter().all(|required| {
+                params
+                    .stanzas
+                    .iter()
+                    .any(|stanza| crate::recipients::recipient(stanza) == *required)
+            })
+    }
+
+    fn refusal(&self, reason: &str) -> String {
+        let path = self.path.as_ref().map(|path| path.display().to_string()).unwrap_or_default();
+        format!("The organization policy ({}) {}.", path, reason)
+    }
+}
+
+/// Location of the policy file. It is fixed, with no environment override,
+/// so users cannot point the tool at a policy of their own.
+fn policy_path() -> PathBuf {
+    // Not read from %ProgramData%, which any user can set for their own
+    // processes.
+    #[cfg(windows)]
+    {
+        PathBuf::from("C:\\ProgramData\\File Encryptor\\policy.conf")
+    }
+    #[cfg(not(windows))]
+    {
+        PathBuf::from("/etc/file_encryptor/policy.conf")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::to_hex;
+
+    fn policy(lines: &[&str]) -> Result<Policy, String> {
+        let mut policy = NO_POLICY;
+        for line in lines {
+            policy.apply_line(line)?;
+        }
+        Ok(policy)
+    }
+
+    #[test]
+    fn settings_are_parsed() {
+        let recipient = ed25519::public_key(&[5; ed25519::KEY_LEN]);
+        let policy = policy(&[
+            "# comment",
+            "",
+            "min_kdf_iterations = 300000",
+            "allowed_ciphers = chacha20",
+            &format!("required_recipients = {}, {0}", to_hex(&recipient)),
+        ])
+        .unwrap();
+        assert_eq!(policy.min_kdf_iterations, 300_000);
+        assert!(policy.allow_chacha20 && !policy.allow_aes_gcm && !policy.allow_xor);
+        assert_eq!(policy.required_recipients, [recipient]);
+        assert!(policy.check_encrypt().is_ok());
+        assert!(policy.check_legacy_xor().is_err());
+
+        let xor_only = self::policy(&["allowed_ciphers = xor"]).unwrap();
+        assert!(xor_only.check_encrypt().is_err());
+        let aes_only = self::policy(&["allowed_ciphers = aes-gcm"]).unwrap();
+        assert!(aes_only.allow_aes_gcm && !aes_only.allow_chacha20);
+        assert!(aes_only.check_encrypt().is_ok());
+        assert!(aes_only.check_chacha20("encrypted logs").is_err());
+        assert!(xor_only.check_legacy_xor().is_ok());
+        assert!(self::policy(&["forbid_legacy_xor = yes"]).unwrap().check_legacy_xor().is_err());
+    }
+
+    #[test]
+    fn mistakes_are_refused() {
+        for line in [
+            "allowed_ciphers = chacha20, aes",
+            "allowed_ciphers =",
+            "min_kdf_iterations = many",
+            &format!("min_kdf_iterations = {}", MAX_ITERATIONS as u64 + 1),
+            "required_recipients = 00",
+            "unknown = 1",
+            "no equals sign",
+        ] {
+            assert!(policy(&[line]).is_err(), "{}", line);
+        }
+    }
+}
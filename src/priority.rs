@@ -0,0 +1,103 @@
+/// Drop this process to the lowest CPU and disk priority, so a scheduled
+/// job stays out of the way of people using the machine (`--nice`).
+/// Fails with the reason if either priority could not be lowered.
+#[cfg(unix)]
+pub fn lower() -> Result<(), String> {
+    unsafe extern "C" {
+        fn setpriority(which: i32, who: u32, priority: i32) -> i32;
+    }
+    const PRIO_PROCESS: i32 = 0;
+    const LOWEST_PRIORITY: i32 = 19;
+
+    // SAFETY: setpriority only reads its integer arguments; who = 0 means
+    // this process.
+    if unsafe { setpriority(PRIO_PROCESS, 0, LOWEST_PRIORITY) } != 0 {
+        return Err(format!(
+            "Failed to lower the CPU priority: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    lower_io()
+}
+
+/// Put this process in the idle disk scheduling class, like `ionice -c 3`.
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn lower_io() -> Result<(), String> {
+    unsafe extern "C" {
+        fn syscall(number: i64, ...) -> i64;
+    }
+    #[cfg(target_arch = "x86_64")]
+    const SYS_IOPRIO_SET: i64 = 251;
+    #[cfg(target_arch = "aarch64")]
+    const SYS_IOPRIO_SET: i64 = 30;
+    const IOPRIO_WHO_PROCESS: i64 = 1;
+    const IOPRIO_CLASS_IDLE: i64 = 3;
+    const IOPRIO_CLASS_SHIFT: i64 = 13;
+
+    // SAFETY: ioprio_set takes three integers and touches no memory of
+    // ours; who = 0 means this process.
+    let result = unsafe {
+        syscall(
+            SYS_IOPRIO_SET,
+            IOPRIO_WHO_PROCESS,
+            0i64,
+            IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+        )
+    };
+    if result != 0 {
+        return Err(format!(
+            "Failed to lower the disk priority: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+/// Ask the kernel to throttle this process's disk access.
+#[cfg(target_os = "macos")]
+fn lower_io() -> Result<(), String> {
+    unsafe extern "C" {
+        fn setiopolicy_np(io_type: i32, scope: i32, policy: i32) -> i32;
+    }
+    const IOPOL_TYPE_DISK: i32 = 0;
+    const IOPOL_SCOPE_PROCESS: i32 = 0;
+    const IOPOL_THROTTLE: i32 = 3;
+
+    // SAFETY: setiopolicy_np only reads its integer arguments.
+    if unsafe { setiopolicy_np(IOPOL_TYPE_DISK, IOPOL_SCOPE_PROCESS, IOPOL_THROTTLE) } != 0 {
+        return Err(format!(
+            "Failed to lower the disk priority: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(all(
+    unix,
+    not(target_os = "macos"),
+    not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))
+))]
+fn lower_io() -> Result<(), String> {
+    Err("Lowering the disk priority is not supported on this system.".to_string())
+}
+
+/// Enter background mode, which lowers both CPU and disk priority.
+#[cfg(windows)]
+pub fn lower() -> Result<(), String> {
+    unsafe extern "system" {
+        fn GetCurrentProcess() -> *mut std::ffi::c_void;
+        fn SetPriorityClass(process: *mut std::ffi::c_void, class: u32) -> i32;
+    }
+    const PROCESS_MODE_BACKGROUND_BEGIN: u32 = 0x0010_0000;
+
+    // SAFETY: GetCurrentProcess returns a pseudo handle that is always
+    // valid for the calling process.
+    if unsafe { SetPriorityClass(GetCurrentProcess(), PROCESS_MODE_BACKGROUND_BEGIN) } == 0 {
+        return Err(format!(
+            "Failed to enter background priority: {}",
+            std::io::Error::last_os_error()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,283 @@
+use std::io::{self, Write};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crate::trace;
+
+/// Stage an operation has reached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Reading,
+    /// Running the password through PBKDF2, which can take a while and
+    /// moves no bytes.
+    DerivingKey,
+    Encrypting,
+    Decrypting,
+    Writing,
+    Done,
+}
+
+impl Phase {
+    /// Lowercase name used when describing progress to other programs.
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Reading => "reading",
+            Phase::DerivingKey => "deriving_key",
+            Phase::Encrypting => "encrypting",
+            Phase::Decrypting => "decrypting",
+            Phase::Writing => "writing",
+            Phase::Done => "done",
+        }
+    }
+}
+
+/// Where an operation stands, sent to every listener each time a chunk of
+/// file contents is read or written and each time the phase changes.
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub phase: Phase,
+    /// Bytes of file contents read since the operation began.
+    pub bytes_in: u64,
+    /// Bytes of file contents written since the operation began.
+    pub bytes_out: u64,
+    /// Number of chunks read or written so far.
+    pub chunk: u64,
+    /// Size of the input, when it is known up front.
+    pub total: Option<u64>,
+}
+
+type Listener = Box<dyn Fn(&Event) + Send>;
+
+/// Functions called with every event. A wrapper that wants a channel
+/// subscribes a closure owning the `Sender`.
+static LISTENERS: Mutex<Vec<Listener>> = Mutex::new(Vec::new());
+
+/// Whether anyone is listening, so the I/O path can skip the lock when
+/// nobody is (the usual case).
+static LISTENING: AtomicBool = AtomicBool::new(false);
+
+/// Whether an operation is under way, for the interrupt handler, which
+/// cannot take a lock.
+static ACTIVE: AtomicBool = AtomicBool::new(false);
+
+/// Set by `cancel` and cleared when the next operation begins.
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+static CURRENT: Mutex<Event> = Mutex::new(Event {
+    phase: Phase::Done,
+    bytes_in: 0,
+    bytes_out: 0,
+    chunk: 0,
+    total: None,
+});
+
+/// Call `listener` with every progress event from now on, on the thread
+/// doing the work, so it should return quickly.
+pub fn subscribe(listener: impl Fn(&Event) + Send + 'static) {
+    LISTENERS.lock().unwrap_or_else(|e| e.into_inner()).push(Box::new(listener));
+    LISTENING.store(true, Ordering::Relaxed);
+}
+
+/// An operation in progress. Dropping it ends the operation, telling the
+/// listeners it is done if it stopped early on an error. Its span holds
+/// every stage of the operation in a trace.
+pub struct Operation {
+    _span: trace::Span,
+}
+
+impl Drop for Operation {
+    fn drop(&mut self) {
+        phase(Phase::Done);
+        ACTIVE.store(false, Ordering::Relaxed);
+    }
+}
+
+/// Start counting a new operation whose input holds `total` bytes. It
+/// lasts until the returned value is dropped.
+pub fn begin(phase: Phase, total: Option<u64>) -> Operation {
+    CANCELLED.store(false, Ordering::Relaxed);
+    ACTIVE.store(true, Ordering::Relaxed);
+    let mut span = trace::span("operation");
+    if let Some(total) = total {
+        span = span.with("total", total);
+    }
+    if !LISTENING.load(Ordering::Relaxed) {
+        return Operation { _span: span };
+    }
+    let event = Event {
+        phase,
+        bytes_in: 0,
+        bytes_out: 0,
+        chunk: 0,
+        total,
+    };
+    *CURRENT.lock().unwrap_or_else(|e| e.into_inner()) = event;
+    notify(&event);
+    Operation { _span: span }
+}
+
+/// Ask the operation in progress to stop. It gives up at the next chunk
+/// it reads or writes, or before its next phase, and removes any output it
+/// had only partly written. Safe to call from any thread.
+pub fn cancel() {
+    if ACTIVE.load(Ordering::Relaxed) {
+        CANCELLED.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Whether the operation in progress has been asked to stop.
+pub fn cancelled() -> bool {
+    CANCELLED.load(Ordering::Relaxed)
+}
+
+/// Fail with an error if the operation in progress has been asked to stop,
+/// for the I/O path to call between chunks.
+pub fn check() -> io::Result<()> {
+    if cancelled() {
+        return Err(io::Error::new(io::ErrorKind::Interrupted, "cancelled"));
+    }
+    Ok(())
+}
+
+/// Make Ctrl-C (and SIGTERM on Unix) cancel the operation in progress, so
+/// its partial output is cleaned up, instead of killing the process.
+/// Outside an operation they still end the process as usual.
+pub fn cancel_on_interrupt() {
+    #[cfg(unix)]
+    {
+        const SIGINT: i32 = 2;
+        const SIGTERM: i32 = 15;
+        const SIG_DFL: usize = 0;
+        unsafe extern "C" {
+            fn signal(signum: i32, handler: usize) -> usize;
+            fn raise(signum: i32) -> i32;
+        }
+        extern "C" fn handler(signum: i32) {
+            if ACTIVE.load(Ordering::Relaxed) {
+                cancel();
+                return;
+            }
+            // SAFETY: signal and raise are async-signal-safe; the default
+            // handler then ends the process as if this one was never set.
+            unsafe {
+                signal(signum, SIG_DFL);
+                raise(signum);
+            }
+        }
+        // SAFETY: the handler only touches atomics and calls
+        // async-signal-safe functions.
+        unsafe {
+            signal(SIGINT, handler as extern "C" fn(i32) as usize);
+            signal(SIGTERM, handler as extern "C" fn(i32) as usize);
+        }
+    }
+    #[cfg(windows)]
+    {
+        unsafe extern "system" {
+            fn SetConsoleCtrlHandler(handler: Option<unsafe extern "system" fn(u32) -> i32>, add: i32) -> i32;
+        }
+        unsafe extern "system" fn handler(_event: u32) -> i32 {
+            // Returning false passes the event on to the default handler,
+            // which ends the process.
+            if ACTIVE.load(Ordering::Relaxed) {
+                cancel();
+                return 1;
+            }
+            0
+        }
+        // SAFETY: the handler only touches atomics.
+        unsafe {
+            SetConsoleCtrlHandler(Some(handler), 1);
+        }
+    }
+}
+
+/// Move to `phase` and return the phase before it. Outside an operation,
+/// such as while the self-test derives keys at startup, nothing changes.
+pub fn phase(phase: Phase) -> Phase {
+    let mut previous = phase;
+    update(|event| {
+        previous = event.phase;
+        event.phase = phase;
+    });
+    previous
+}
+
+/// Run `derive`, the key derivation with `iterations` rounds, as the
+/// `DerivingKey` phase, and trace it.
+pub fn deriving_key<T>(iterations: u32, derive: impl FnOnce() -> T) -> T {
+    let previous = phase(Phase::DerivingKey);
+    let result = {
+        let _span = trace::span("kdf").with("iterations", iterations.into());
+        derive()
+    };
+    phase(previous);
+    result
+}
+
+/// Count `bytes` of file contents read.
+pub fn read(bytes: usize) {
+    update(|event| {
+        event.bytes_in += bytes as u64;
+        event.chunk += 1;
+    });
+}
+
+/// Count `bytes` of file contents written.
+pub fn wrote(bytes: usize) {
+    update(|event| {
+        event.bytes_out += bytes as u64;
+        event.chunk += 1;
+    });
+}
+
+/// Print every event as one line of JSON on standard error
+/// (`--progress-json`), for wrappers that draw their own progress bar.
+/// Byte counts are sent at most ten times a second; phase changes always.
+pub fn report_as_json() {
+    let last: Mutex<Option<(Phase, Instant)>> = Mutex::new(None);
+    subscribe(move |event| {
+        let mut last = last.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some((phase, at)) = *last
+            && phase == event.phase
+            && at.elapsed() < Duration::from_millis(100)
+        {
+            return;
+        }
+        *last = Some((event.phase, Instant::now()));
+        let total = event.total.map_or("null".to_string(), |total| total.to_string());
+        let _ = writeln!(
+            std::io::stderr(),
+            "{{\"phase\": \"{}\", \"bytes_in\": {}, \"bytes_out\": {}, \"chunk\": {}, \"total\": {}}}",
+            event.phase.name(),
+            event.bytes_in,
+            event.bytes_out,
+            event.chunk,
+            total
+        );
+    });
+}
+
+/// Apply `change` to the operation in progress, if there is one, and tell
+/// the listeners.
+fn update(change: impl FnOnce(&mut Event)) {
+    if !LISTENING.load(Ordering::Relaxed) {
+        return;
+    }
+    let event = {
+        let mut current = CURRENT.lock().unwrap_or_else(|e| e.into_inner());
+        if current.phase == Phase::Done {
+            return;
+        }
+        change(&mut current);
+        *current
+    };
+    notify(&event);
+}
+
+fn notify(event: &Event) {
+    for listener in LISTENERS.lock().unwrap_or_else(|e| e.into_inner()).iter() {
+        listener(event);
+    }
+}
@@ -0,0 +1,129 @@
+use std::sync::OnceLock;
+
+use crate::crypto::{self, KEY_LEN, NONCE_LEN, TAG_LEN};
+use crate::ed25519;
+use crate::format::STANZA_LEN;
+
+/// Length of the file keys wrapped in a stanza: the cipher key followed by
+/// the MAC key.
+pub const WRAPPED_LEN: usize = 2 * KEY_LEN;
+
+/// Recipients chosen with `--recipient` for every file encrypted in this
+/// run, on top of any the policy requires.
+static CHOSEN: OnceLock<Vec<[u8; ed25519::KEY_LEN]>> = OnceLock::new();
+
+/// Also wrap the keys of every new file for `recipients`.
+pub fn choose(recipients: Vec<[u8; ed25519::KEY_LEN]>) {
+    let _ = CHOSEN.set(recipients);
+}
+
+/// The recipients chosen with `--recipient`.
+pub fn chosen() -> &'static [[u8; ed25519::KEY_LEN]] {
+    CHOSEN.get().map_or(&[], Vec::as_slice)
+}
+
+/// Wrap `keys` for the holder of the Ed25519 key `recipient`, so they can
+/// open the file with their private key instead of the password.
+///
+/// A fresh X25519 key is agreed with the recipient's key converted to its
+/// X25519 form, and the shared secret encrypts and authenticates the file
+/// keys. The stanza holds the recipient's key, the ephemeral public key,
+/// the wrapped keys, and the tag.
+pub fn wrap(keys: &[u8; WRAPPED_LEN], recipient: &[u8; ed25519::KEY_LEN]) -> Result<[u8; STANZA_LEN], String> {
+    let recipient_x = ed25519::public_to_x25519(recipient)
+        .ok_or_else(|| "A required recipient is not a valid public key.".to_string())?;
+    let mut ephemeral = [0u8; ed25519::KEY_LEN];
+    crypto::random_bytes(&mut ephemeral)?;
+    let ephemeral_public = ed25519::x25519(&ephemeral, &BASE_POINT);
+    let shared = ed25519::x25519(&ephemeral, &recipient_x);
+    if shared == [0; ed25519::KEY_LEN] {
+        return Err("A required recipient is not a valid public key.".to_string());
+    }
+    let (cipher, mac) = stanza_keys(&shared, &ephemeral_public, recipient);
+
+    let mut stanza = [0u8; STANZA_LEN];
+    stanza[..32].copy_from_slice(recipient);
+    stanza[32..64].copy_from_slice(&ephemeral_public);
+    stanza[64..64 + WRAPPED_LEN].copy_from_slice(keys);
+    crypto::chacha20_xor(&cipher, &[0; NONCE_LEN], 0, &mut stanza[64..64 + WRAPPED_LEN]);
+    let tag = crypto::hmac_sha256(&mac, &stanza[..64 + WRAPPED_LEN]);
+    stanza[64 + WRAPPED_LEN..].copy_from_slice(&tag);
+    Ok(stanza)
+}
+
+/// The recipient's Ed25519 public key named in a stanza.
+pub fn recipient(stanza: &[u8; STANZA_LEN]) -> [u8; ed25519::KEY_LEN] {
+    stanza[..32].try_into().expect("key length")
+}
+
+/// Unwrap the file keys from `stanza` with the private key `seed`, or
+/// `None` if the stanza was not made for it or has been changed.
+pub fn unwrap(stanza: &[u8; STANZA_LEN], seed: &[u8; ed25519::KEY_LEN]) -> Option<[u8; WRAPPED_LEN]> {
+    let public = ed25519::public_key(seed);
+    if recipient(stanza) != public {
+        return None;
+    }
+    let ephemeral_public: [u8; 32] = stanza[32..64].try_into().expect("key length");
+    let shared = ed25519::x25519(&ed25519::seed_to_x25519(seed), &ephemeral_public);
+    if shared == [0; ed25519::KEY_LEN] {
+        return None;
+    }
+    let (cipher, mac) = stanza_keys(&shared, &ephemeral_public, &public);
+    let tag = crypto::hmac_sha256(&mac, &stanza[..64 + WRAPPED_LEN]);
+    if !crypto::constant_time_eq(&tag, &stanza[64 + WRAPPED_LEN..64 + WRAPPED_LEN + TAG_LEN]) {
+        return None;
+    }
+    let mut keys = [0u8; WRAPPED_LEN];
+    keys.copy_from_slice(&stanza[64..64 + WRAPPED_LEN]);
+    crypto::chacha20_xor(&cipher, &[0; NONCE_LEN], 0, &mut keys);
+    Some(keys)
+}
+
+/// The X25519 base point, u = 9.
+const BASE_POINT: [u8; 32] = {
+    let mut point = [0u8; 32];
+    point[0] = 9;
+    point
+};
+
+/// Cipher and MAC keys for one stanza, bound to both public keys so a
+/// stanza cannot be moved to another recipient.
+fn stanza_keys(
+    shared: &[u8; 32],
+    ephemeral_public: &[u8; 32],
+    recipient: &[u8; 32],
+) -> ([u8; KEY_LEN], [u8; KEY_LEN]) {
+    let derive = |label: &[u8]| {
+        let mut input = label.to_vec();
+        input.extend_from_slice(ephemeral_public);
+        input.extend_from_slice(recipient);
+        crypto::hmac_sha256(shared, &input)
+    };
+    (derive(b"cipher"), derive(b"mac"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_recipient_can_unwrap() {
+        let keys: [u8; WRAPPED_LEN] = std::array::from_fn(|index| index as u8);
+        let seed = [3u8; ed25519::KEY_LEN];
+        let stanza = wrap(&keys, &ed25519::public_key(&seed)).unwrap();
+        assert_eq!(recipient(&stanza), ed25519::public_key(&seed));
+        assert_eq!(unwrap(&stanza, &seed), Some(keys));
+        assert_eq!(unwrap(&stanza, &[4; ed25519::KEY_LEN]), None);
+
+        // Any change to the stanza is noticed.
+        for index in [32, 64, 64 + WRAPPED_LEN] {
+            let mut changed = stanza;
+            changed[index] ^= 1;
+            assert_eq!(unwrap(&changed, &seed), None, "changed byte {}", index);
+        }
+        // Claiming another recipient does not help them.
+        let mut moved = stanza;
+        moved[..32].copy_from_slice(&ed25519::public_key(&[4; ed25519::KEY_LEN]));
+        assert_eq!(unwrap(&moved, &[4; ed25519::KEY_LEN]), None);
+    }
+}
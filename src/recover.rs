@@ -0,0 +1,277 @@
+use std::fs;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::format::{self, SEALED_VERSION, Sealed};
+use crate::sealing;
+use crate::storage;
+use crate::totp;
+use crate::ui;
+
+/// Most candidates tried unless the user raises the limit.
+pub const DEFAULT_MAX_CANDIDATES: u64 = 10_000_000;
+
+/// Characters a mask placeholder such as `?d` stands for.
+const LOWER: &[u8] = b"abcdefghijklmnopqrstuvwxyz";
+const UPPER: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+const DIGITS: &[u8] = b"0123456789";
+const SYMBOLS: &[u8] = b" !\"#$%&'()*+,-./:;<=>?@[\\]^_`{|}~";
+
+/// Where password guesses come from.
+pub enum Candidates {
+    /// Every line of a wordlist.
+    Words(Vec<String>),
+    /// Every password matching a mask. Each position holds the characters
+    /// allowed there.
+    Mask(Vec<Vec<u8>>),
+}
+
+impl Candidates {
+    /// Read a wordlist, one candidate per line.
+    pub fn from_wordlist(path: &str) -> Result<Self, String> {
+        let contents =
+            fs::read(path).map_err(|e| format!("Failed to read wordlist '{}': {}", path, e))?;
+        let words = String::from_utf8_lossy(&contents)
+            .lines()
+            .map(|line| line.trim_end_matches('\r').to_string())
+            .filter(|line| !line.is_empty())
+            .collect();
+        Ok(Candidates::Words(words))
+    }
+
+    /// Parse a mask such as `summer?d?d`. `?l`, `?u`, `?d`, and `?s` stand
+    /// for a lowercase letter, uppercase letter, digit, or symbol, `?a` for
+    /// any of those, and `??` for a literal `?`. Other characters match
+    /// themselves.
+    pub fn from_mask(mask: &str) -> Result<Self, String> {
+        let mut positions = Vec::new();
+        let mut bytes = mask.bytes();
+        while let Some(byte) = bytes.next() {
+            if byte != b'?' {
+                positions.push(vec![byte]);
+                continue;
+            }
+            let set = match bytes.next() {
+                Some(b'l') => LOWER.to_vec(),
+                Some(b'u') => UPPER.to_vec(),
+                Some(b'd') => DIGITS.to_vec(),
+                Some(b's') => SYMBOLS.to_vec(),
+                Some(b'a') => [LOWER, UPPER, DIGITS, SYMBOLS].concat(),
+                Some(b'?') => vec![b'?'],
+                other => {
+                    return Err(format!(
+                        "Unknown mask placeholder '?{}'. Use ?l, ?u, ?d, ?s, ?a, or ??.",
+                        other.map(char::from).unwrap_or(' ')
+                    ));
+                }
+            };
+            positions.push(set);
+        }
+        if positions.is_empty() {
+            return Err("The mask is empty.".to_string());
+        }
+        Ok(Candidates::Mask(positions))
+    }
+
+    /// How many candidates there are, or `None` if the count overflows.
+    pub fn count(&self) -> Option<u64> {
+        match self {
+            Candidates::Words(words) => Some(words.len() as u64),
+            Candidates::Mask(positions) => positions
+                .iter()
+                .try_fold(1u64, |total, set| total.checked_mul(set.len() as u64)),
+        }
+    }
+
+    /// The candidate with the given index, from 0 to `count() - 1`.
+    fn get(&self, mut index: u64) -> Vec<u8> {
+        match self {
+            Candidates::Words(words) => words[index as usize].as_bytes().to_vec(),
+            Candidates::Mask(positions) => {
+                let mut candidate = vec![0u8; positions.len()];
+                for (slot, set) in candidate.iter_mut().zip(positions).rev() {
+                    let radix = set.len() as u64;
+                    *slot = set[(index % radix) as usize];
+                    index /= radix;
+                }
+                candidate
+            }
+        }
+    }
+}
+
+/// Checks password guesses against one encrypted file without writing
+/// anything.
+enum Verifier<'a> {
+    /// XOR-era file with a stored checksum.
+    Xor { version: u8, payload: &'a [u8] },
+    /// Sealed file; a guess is right when the tag matches, with the keys
+    /// from the guess or, for a file encrypted with `--totp`, with them
+    /// bound to this user's authenticator secret.
+    Sealed(Sealed<'a>, Option<Vec<u8>>),
+}
+
+impl<'a> Verifier<'a> {
+    fn new(data: &'a [u8], legacy_xor: bool) -> Result<Self, String> {
+        match format::split_header(data).map_err(format::message)? {
+            Some((version, _)) if version >= SEALED_VERSION => {
+                Ok(Verifier::Sealed(format::split_sealed(data).map_err(format::message)?, totp::enrolled()?))
+            }
+            Some(_) if !legacy_xor => Err(
+                "This file uses the old XOR cipher. Run with --legacy-xor to search for its password."
+                    .to_string(),
+            ),
+            Some((version, payload)) => Ok(Verifier::Xor { version, payload }),
+            None => Err(
+                "This file has no header and no checksum, so a guessed password cannot be confirmed."
+                    .to_string(),
+            ),
+        }
+    }
+
+    fn matches(&self, password: &[u8]) -> bool {
+        if password.is_empty() {
+            return false;
+        }
+        match self {
+            Verifier::Xor { version, payload } => {
+                crate::open_payload(*version, payload, password).is_ok()
+            }
+            Verifier::Sealed(sealed, totp_secret) => {
+                let keys = sealing::derive_keys(password, &sealed.params);
+                let matches = |keys: &crate::Keys| sealing::check_tag(sealed, keys).is_ok();
+                matches(&keys) || totp_secret.as_ref().is_some_and(|secret| matches(&totp::bind_keys(&keys, secret)))
+            }
+        }
+    }
+}
+
+/// Make sure `path` belongs to the person running the tool, so this only
+/// helps people get back into their own files.
+#[cfg(unix)]
+pub fn check_owner(path: &str) -> Result<(), String> {
+    let info = fs::metadata(path).map_err(|e| format!("Failed to read input file: {}", e))?;
+    if !storage::owned_by_user(&info) {
+        return Err(format!(
+            "'{}' belongs to another user. Password recovery only works on your own files.",
+            path
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn check_owner(path: &str) -> Result<(), String> {
+    fs::metadata(path)
+        .map(|_| ())
+        .map_err(|e| format!("Failed to read input file: {}", e))
+}
+
+/// Try every candidate against `path` on `threads` threads, printing the
+/// progress and rate as it goes. Returns the password if one matched.
+pub fn search(
+    path: &str,
+    candidates: &Candidates,
+    threads: usize,
+    legacy_xor: bool,
+) -> Result<Option<String>, String> {
+    let data = fs::read(path).map_err(|e| format!("Failed to read input file: {}", e))?;
+    let verifier = Verifier::new(&data, legacy_xor)?;
+    let total = candidates.count().unwrap_or(u64::MAX);
+    let threads = threads.max(1) as u64;
+
+    let tried = AtomicU64::new(0);
+    let done = AtomicBool::new(false);
+    let started = Instant::now();
+
+    let (found, progress_shown) = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|worker| {
+                let (verifier, tried, done) = (&verifier, &tried, &done);
+                scope.spawn(move || {
+                    let mut index = worker;
+                    while index < total && !done.load(Ordering::Relaxed) {
+                        let candidate = candidates.get(index);
+                        tried.fetch_add(1, Ordering::Relaxed);
+                        if verifier.matches(&candidate) {
+                            done.store(true, Ordering::Relaxed);
+                            return Some(candidate);
+                        }
+                        index += threads;
+                    }
+                    None
+                })
+            })
+            .collect();
+
+        let shown = report_progress(&tried, &done, total, started, &workers);
+        let found = workers
+            .into_iter()
+            .filter_map(|worker| worker.join().ok().flatten())
+            .next();
+        (found, shown)
+    });
+
+    finish_progress(tried.load(Ordering::Relaxed), started, progress_shown);
+    Ok(found.map(|password| String::from_utf8_lossy(&password).into_owned()))
+}
+
+/// Print how far the search is until every worker has stopped. Returns
+/// whether anything was printed.
+fn report_progress<T>(
+    tried: &AtomicU64,
+    done: &AtomicBool,
+    total: u64,
+    started: Instant,
+    workers: &[thread::ScopedJoinHandle<'_, T>],
+) -> bool {
+    // Plain mode writes whole lines, so it reports less often.
+    let interval = if ui::is_plain() {
+        Duration::from_secs(10)
+    } else {
+        Duration::from_secs(1)
+    };
+    let mut last = Instant::now();
+    let mut shown = false;
+    while !workers.iter().all(|worker| worker.is_finished()) {
+        thread::sleep(Duration::from_millis(50));
+        if done.load(Ordering::Relaxed) || last.elapsed() < interval {
+            continue;
+        }
+        last = Instant::now();
+        shown = true;
+        let count = tried.load(Ordering::Relaxed);
+        let line = format!(
+            "Tried {} of {} candidates ({}/s)",
+            count,
+            total,
+            rate(count, started)
+        );
+        ui::progress(&line);
+    }
+    shown
+}
+
+/// End the progress display with a final count.
+fn finish_progress(tried: u64, started: Instant, progress_shown: bool) {
+    if progress_shown {
+        ui::end_progress();
+    }
+    eprintln!(
+        "Tried {} candidate(s) in {:.1}s ({}/s).",
+        tried,
+        started.elapsed().as_secs_f64(),
+        rate(tried, started)
+    );
+}
+
+/// Candidates per second so far.
+fn rate(tried: u64, started: Instant) -> u64 {
+    let seconds = started.elapsed().as_secs_f64();
+    if seconds > 0.0 {
+        (tried as f64 / seconds) as u64
+    } else {
+        0
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::format::{self, FORMAT_VERSION};
+use crate::migrate::{self, Weakness};
+use crate::paths;
+
+/// First words of a progress file, followed by the folder it is for.
+const PROGRESS_HEADER: &str = "# File Encryptor re-encryption progress for ";
+
+/// An encrypted file found by `scan`, with what is outdated about it, or
+/// why its header could not be read.
+pub struct Found {
+    pub path: PathBuf,
+    pub outdated: Result<Vec<Weakness>, String>,
+}
+
+/// What `scan` saw under a folder.
+pub struct Scan {
+    /// Files needing re-encryption, and files that could not be checked,
+    /// in name order.
+    pub found: Vec<Found>,
+    /// Encrypted files seen, outdated or not.
+    pub encrypted: usize,
+    /// `.enc` files without a header, left alone without `--legacy-xor`
+    /// since they cannot be told apart from other files.
+    pub headerless: usize,
+}
+
+/// Look at every file under `root`, reading only headers, and find the
+/// encrypted ones whose cipher or key derivation is outdated or that fall
+/// short of the organization policy. Headerless `.enc` files count as XOR
+/// files with `legacy_xor`. Symbolic links are not followed.
+pub fn scan(root: &Path, legacy_xor: bool) -> Result<Scan, String> {
+    let mut scan = Scan {
+        found: Vec::new(),
+        encrypted: 0,
+        headerless: 0,
+    };
+    walk(root, legacy_xor, &mut scan)?;
+    Ok(scan)
+}
+
+fn walk(folder: &Path, legacy_xor: bool, scan: &mut Scan) -> Result<(), String> {
+    let mut entries: Vec<PathBuf> = fs::read_dir(folder)
+        .map_err(|e| format!("Failed to read '{}': {}", folder.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    entries.sort();
+    for path in entries {
+        let Ok(info) = fs::symlink_metadata(&path) else {
+            continue;
+        };
+        if info.is_dir() {
+            walk(&path, legacy_xor, scan)?;
+            continue;
+        }
+        // Left over from a migration that was cut short.
+        if !info.is_file() || path.extension().is_some_and(|ext| ext == "migrating") {
+            continue;
+        }
+        let outdated = match migrate::read_header(&path) {
+            Ok(header) => match format::split_header(&header) {
+                Ok(Some(_)) => Ok(migrate::weaknesses(&header)),
+                Ok(None) if path.extension().is_some_and(|ext| ext == "enc") => {
+                    if !legacy_xor {
+                        scan.headerless += 1;
+                        continue;
+                    }
+                    Ok(vec![Weakness::XorCipher(None)])
+                }
+                Ok(None) => continue,
+                Err(e) => Err(format::message(e)),
+            },
+            Err(e) => Err(e),
+        };
+        scan.encrypted += 1;
+        if outdated.as_ref().is_ok_and(|outdated| outdated.is_empty()) {
+            continue;
+        }
+        scan.found.push(Found { path, outdated });
+    }
+    Ok(())
+}
+
+/// How one file of a re-encryption ended, as kept in the progress file
+/// and written to the report.
+pub struct Record {
+    /// `MIGRATED`, `SKIPPED`, or `FAILED`.
+    pub status: String,
+    pub detail: String,
+}
+
+/// The progress file of a re-encryption: one line per file finished, added
+/// as soon as it is, so a run that was interrupted, or that failed on files
+/// with another password, picks up where it stopped when run again.
+pub struct Progress {
+    path: PathBuf,
+    /// Results by path, including those of earlier runs.
+    records: HashMap<PathBuf, Record>,
+    /// Paths in the order they finished.
+    order: Vec<PathBuf>,
+}
+
+impl Progress {
+    /// Open the progress file at `path` for a re-encryption of `root`,
+    /// reading what earlier runs finished, or start one.
+    pub fn open(path: &Path, root: &Path) -> Result<Self, String> {
+        let header = format!("{}{}", PROGRESS_HEADER, paths::encode(root));
+        let mut progress = Progress {
+            path: path.to_path_buf(),
+            records: HashMap::new(),
+            order: Vec::new(),
+        };
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                fs::write(path, format!("{}\n", header))
+                    .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+                return Ok(progress);
+            }
+            Err(e) => return Err(format!("Failed to read '{}': {}", path.display(), e)),
+        };
+        let mut lines = contents.lines();
+        if lines.next() != Some(header.as_str()) {
+            return Err(format!(
+                "'{}' holds the progress of another re-encryption. Finish that one, or give another file with --progress.",
+                path.display()
+            ));
+        }
+        for line in lines {
+            // A line cut short by a crash before its path ended is
+            // dropped, so that file is looked at again.
+            let mut fields = line.splitn(3, '\t');
+            if let (Some(status), Some(file), Some(detail)) = (fields.next(), fields.next(), fields.next()) {
+                progress.insert(paths::decode(file), status.to_string(), detail.to_string());
+            }
+        }
+        Ok(progress)
+    }
+
+    /// What an earlier run recorded for `path`.
+    pub fn get(&self, path: &Path) -> Option<&Record> {
+        self.records.get(path)
+    }
+
+    /// Record the result for `path` and add it to the file straight away.
+    pub fn record(&mut self, path: &Path, status: &str, detail: &str) -> Result<(), String> {
+        let line = format!("{}\t{}\t{}\n", status, paths::encode(path), detail.replace(['\t', '\n'], " "));
+        OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .and_then(|mut file| file.write_all(line.as_bytes()))
+            .map_err(|e| format!("Failed to write '{}': {}", self.path.display(), e))?;
+        self.insert(path.to_path_buf(), status.to_string(), detail.to_string());
+        Ok(())
+    }
+
+    fn insert(&mut self, path: PathBuf, status: String, detail: String) {
+        if !self.records.contains_key(&path) {
+            self.order.push(path.clone());
+        }
+        self.records.insert(path, Record { status, detail });
+    }
+
+    /// Number of files recorded, by this run and earlier ones.
+    pub fn finished(&self) -> usize {
+        self.order.len()
+    }
+
+    /// Count of files with `status`.
+    pub fn count(&self, status: &str) -> usize {
+        self.records.values().filter(|record| record.status == status).count()
+    }
+
+    /// Write a plain-text report of every file, from this run and earlier
+    /// ones, to `report`. The progress file is removed once no file has
+    /// failed; until then it is kept, so `--retry-failed` knows which files
+    /// to try again and the next report still covers every run.
+    pub fn finish(self, report: &str, root: &Path) -> Result<(), String> {
+        let written = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or(0);
+        let mut text = String::new();
+        text.push_str("File Encryptor re-encryption report\n");
+        text.push_str(&format!("Folder: {}\n", paths::display(root)));
+        text.push_str(&format!("Written at: {} (seconds since 1970 UTC)\n", written));
+        text.push_str(&format!("Target format: version {}\n\n", FORMAT_VERSION));
+        for path in &self.order {
+            let record = &self.records[path];
+            text.push_str(&format!("{:<10}{}: {}\n", record.status, paths::display(path), record.detail));
+        }
+        fs::write(report, text).map_err(|e| format!("Failed to write report '{}': {}", report, e))?;
+        if self.count("FAILED") == 0 {
+            let _ = fs::remove_file(&self.path);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,93 @@
+use std::io::{self, ErrorKind};
+use std::thread;
+use std::time::Duration;
+
+use crate::claims;
+use crate::config::Config;
+use crate::progress;
+
+/// Longest wait between two tries, however many have failed.
+const MAX_DELAY_SECONDS: u64 = 60;
+
+/// How often, and how patiently, a batch tries a file again after a
+/// failure that may go away on its own, such as the file being busy or a
+/// network share dropping out for a moment.
+#[derive(Debug, Clone, Copy)]
+pub struct Policy {
+    /// Tries after the first, or 0 to never try again.
+    pub retries: u32,
+    /// Wait before the first retry, doubled for each one after it.
+    pub delay_seconds: u64,
+}
+
+impl Policy {
+    pub fn from_config(config: &Config) -> Self {
+        Policy {
+            retries: config.retries,
+            delay_seconds: config.retry_delay_seconds,
+        }
+    }
+
+    /// Run `operation` on `what`, trying again after each transient
+    /// failure until it works, fails for good, or the retries run out.
+    /// Returns its last result and how many retries it took.
+    pub fn run<T>(&self, what: &str, mut operation: impl FnMut() -> Result<T, String>) -> (Result<T, String>, u32) {
+        let mut retries = 0;
+        loop {
+            let result = operation();
+            match &result {
+                Err(e) if retries < self.retries && is_transient(e) && !progress::cancelled() => {
+                    let wait = self.delay_seconds.saturating_mul(1 << retries.min(16)).min(MAX_DELAY_SECONDS);
+                    eprintln!(
+                        "'{}' failed ({}); trying again in {} second(s) ({} of {}).",
+                        what,
+                        e,
+                        wait,
+                        retries + 1,
+                        self.retries
+                    );
+                    thread::sleep(Duration::from_secs(wait));
+                    retries += 1;
+                }
+                _ => return (result, retries),
+            }
+        }
+    }
+}
+
+/// Whether `error` may go away if the operation is tried again: another
+/// File Encryptor process has the file, or the system reported it busy or
+/// the network interrupted. Errors such as a wrong password, a missing
+/// file, or a refused permission are permanent.
+pub fn is_transient(error: &str) -> bool {
+    if error.contains(claims::IN_USE) {
+        return true;
+    }
+    // Errors from the system end with `(os error N)`.
+    let Some(code) = error
+        .rfind("(os error ")
+        .and_then(|at| error[at + "(os error ".len()..].split(')').next())
+        .and_then(|code| code.parse::<i32>().ok())
+    else {
+        return false;
+    };
+    // ERROR_SHARING_VIOLATION and ERROR_LOCK_VIOLATION: another program
+    // has the file open.
+    if cfg!(windows) && matches!(code, 32 | 33) {
+        return true;
+    }
+    matches!(
+        io::Error::from_raw_os_error(code).kind(),
+        ErrorKind::ResourceBusy
+            | ErrorKind::ExecutableFileBusy
+            | ErrorKind::WouldBlock
+            | ErrorKind::Interrupted
+            | ErrorKind::TimedOut
+            | ErrorKind::ConnectionReset
+            | ErrorKind::ConnectionAborted
+            | ErrorKind::NetworkDown
+            | ErrorKind::NetworkUnreachable
+            | ErrorKind::HostUnreachable
+            | ErrorKind::StaleNetworkFileHandle
+    )
+}
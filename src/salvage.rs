@@ -0,0 +1,61 @@
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A stretch of an output that could not be recovered and was filled with
+/// zeros, or left out at the end.
+pub struct Gap {
+    /// The restored file it is in, when one run writes several.
+    pub file: Option<PathBuf>,
+    pub start: u64,
+    /// `None` when everything from `start` on is missing and its length
+    /// cannot be told.
+    pub end: Option<u64>,
+    pub reason: String,
+}
+
+/// Where a salvaged output has holes, written next to it as `OUTPUT.gaps`
+/// so the user knows which parts of the rescued data not to trust.
+#[derive(Default)]
+pub struct GapMap {
+    gaps: Vec<Gap>,
+}
+
+impl GapMap {
+    pub fn add(&mut self, gap: Gap) {
+        self.gaps.push(gap);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.gaps.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.gaps.len()
+    }
+
+    /// Bytes filled with zeros, leaving out gaps of unknown length.
+    pub fn bytes(&self) -> u64 {
+        self.gaps.iter().filter_map(|gap| Some(gap.end? - gap.start)).sum()
+    }
+
+    /// The map for `output`: one line per gap, `START-END` (end exclusive,
+    /// or left out when unknown) and the reason, each after the file's
+    /// path when there are several files, separated by tabs.
+    pub fn write(&self, output: &Path) -> Result<PathBuf, String> {
+        let mut text = String::from("# Byte ranges filled with zeros because they could not be recovered.\n");
+        for gap in &self.gaps {
+            if let Some(file) = &gap.file {
+                let _ = write!(text, "{}\t", file.display());
+            }
+            let end = gap.end.map(|end| end.to_string()).unwrap_or_default();
+            let _ = writeln!(text, "{}-{}\t{}", gap.start, end, gap.reason);
+        }
+        let mut path = output.as_os_str().to_owned();
+        path.push(".gaps");
+        let path = PathBuf::from(path);
+        fs::write(&path, text)
+            .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+        Ok(path)
+    }
+}
@@ -0,0 +1,357 @@
+use std::env;
+use std::ffi::{CString, c_char, c_int};
+use std::fs;
+use std::os::fd::FromRawFd;
+use std::os::unix::fs::PermissionsExt;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::Path;
+
+/// First file descriptor systemd passes to an activated service.
+const SD_LISTEN_FDS_START: c_int = 3;
+
+/// The socket a service listens on.
+pub struct Listener {
+    pub listener: UnixListener,
+    /// Whether systemd opened the socket and passed it in. It then owns
+    /// the socket file, so the service must not remove it.
+    pub activated: bool,
+}
+
+/// Listen on `socket`, or take over the socket systemd passed in when the
+/// service was started by socket activation. `service` names the service
+/// in errors.
+pub fn listen(socket: &Path, service: &str) -> Result<Listener, String> {
+    if let Some(listener) = activated_listener()? {
+        return Ok(Listener {
+            listener,
+            activated: true,
+        });
+    }
+    if UnixStream::connect(socket).is_ok() {
+        return Err(format!("A {} is already listening on '{}'.", service, socket.display()));
+    }
+    // A socket left behind by a service that did not shut down cleanly.
+    let _ = fs::remove_file(socket);
+    let listener = bind_private(socket)
+        .map_err(|e| format!("Failed to listen on '{}': {}", socket.display(), e))?;
+    fs::set_permissions(socket, fs::Permissions::from_mode(0o600))
+        .map_err(|e| format!("Failed to restrict '{}': {}", socket.display(), e))?;
+    Ok(Listener {
+        listener,
+        activated: false,
+    })
+}
+
+/// Bind `socket` under a umask that leaves other users no access, so
+/// there is no moment before it is restricted when they could connect.
+fn bind_private(socket: &Path) -> std::io::Result<UnixListener> {
+    #[cfg(target_os = "linux")]
+    type Mode = u32;
+    #[cfg(not(target_os = "linux"))]
+    type Mode = u16;
+    unsafe extern "C" {
+        fn umask(mask: Mode) -> Mode;
+    }
+    // SAFETY: umask only swaps the process's file creation mask.
+    let previous = unsafe { umask(0o177) };
+    let listener = UnixListener::bind(socket);
+    // SAFETY: as above, putting the old mask back.
+    unsafe { umask(previous) };
+    listener
+}
+
+/// The listening socket passed by systemd, following sd_listen_fds(3):
+/// `LISTEN_PID` names this process and `LISTEN_FDS` counts the sockets,
+/// which start at descriptor 3. Only one socket is expected.
+fn activated_listener() -> Result<Option<UnixListener>, String> {
+    let Some(pid) = env::var("LISTEN_PID").ok().and_then(|pid| pid.parse::<u32>().ok()) else {
+        return Ok(None);
+    };
+    if pid != std::process::id() {
+        return Ok(None);
+    }
+    match env::var("LISTEN_FDS").ok().and_then(|count| count.parse::<u32>().ok()) {
+        None | Some(0) => Ok(None),
+        // SAFETY: systemd hands descriptor 3 to this process as a listening
+        // socket, and nothing else in the process has taken ownership of it.
+        Some(1) => Ok(Some(unsafe { UnixListener::from_raw_fd(SD_LISTEN_FDS_START) })),
+        Some(count) => Err(format!(
+            "systemd passed {} sockets; the socket unit must have exactly one ListenStream.",
+            count
+        )),
+    }
+}
+
+/// Harden a service once its socket is open and before it starts any
+/// threads: switch to `user` if given, which needs root. On Linux the
+/// process is then barred from gaining privileges, and a seccomp filter
+/// refuses system calls a service never needs, such as running programs,
+/// tracing other processes, or mounting file systems. The service may
+/// still start threads, which inherit the filter. Returns a warning if the
+/// filter could not be applied.
+pub fn harden(user: Option<&str>, listener: &Listener, socket: &Path) -> Result<Option<String>, String> {
+    if let Some(user) = user {
+        let (uid, gid) = lookup_user(user)?;
+        // The socket was created as root; hand it over so the user's
+        // clients can still connect.
+        if !listener.activated {
+            std::os::unix::fs::chown(socket, Some(uid), Some(gid))
+                .map_err(|e| format!("Failed to hand '{}' to '{}': {}", socket.display(), user, e))?;
+        }
+        switch_user(user, uid, gid)?;
+    }
+    Ok(restrict_syscalls().err())
+}
+
+/// Look up the user and primary group IDs of `name`.
+fn lookup_user(name: &str) -> Result<(u32, u32), String> {
+    /// The leading fields of `struct passwd`, which are laid out the same
+    /// on every Unix.
+    #[repr(C)]
+    struct Passwd {
+        name: *const c_char,
+        password: *const c_char,
+        uid: u32,
+        gid: u32,
+    }
+    unsafe extern "C" {
+        fn getpwnam(name: *const c_char) -> *const Passwd;
+    }
+
+    let c_name = CString::new(name).map_err(|_| format!("'{}' is not a valid user name.", name))?;
+    // SAFETY: `c_name` is NUL-terminated; the returned entry is read at
+    // once, before any other call could reuse its storage.
+    let entry = unsafe { getpwnam(c_name.as_ptr()) };
+    if entry.is_null() {
+        return Err(format!("No user named '{}'.", name));
+    }
+    // SAFETY: a non-null result points to a valid passwd entry.
+    let entry = unsafe { &*entry };
+    Ok((entry.uid, entry.gid))
+}
+
+/// Give up root for `uid` and `gid`, dropping supplementary groups first
+/// since that needs root too.
+fn switch_user(name: &str, uid: u32, gid: u32) -> Result<(), String> {
+    unsafe extern "C" {
+        #[cfg(target_os = "linux")]
+        fn setgroups(count: usize, groups: *const u32) -> c_int;
+        #[cfg(not(target_os = "linux"))]
+        fn setgroups(count: c_int, groups: *const u32) -> c_int;
+        fn setgid(gid: u32) -> c_int;
+        fn setuid(uid: u32) -> c_int;
+    }
+
+    let failed = |step: &str| format!("Failed to switch to user '{}' ({}): {}", name, step, std::io::Error::last_os_error());
+    // SAFETY: these calls only change the process's credentials; the
+    // group list is the one valid `gid`.
+    unsafe {
+        if setgroups(1, &gid) != 0 {
+            return Err(failed("setgroups"));
+        }
+        if setgid(gid) != 0 {
+            return Err(failed("setgid"));
+        }
+        if setuid(uid) != 0 {
+            return Err(failed("setuid"));
+        }
+        // With the saved ID dropped too, root cannot be taken back.
+        if uid != 0 && setuid(0) == 0 {
+            return Err(format!("Switched to user '{}' but could still regain root.", name));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn restrict_syscalls() -> Result<(), String> {
+    #[repr(C)]
+    struct SockFilter {
+        code: u16,
+        jt: u8,
+        jf: u8,
+        k: u32,
+    }
+    #[repr(C)]
+    struct SockFprog {
+        len: u16,
+        filter: *const SockFilter,
+    }
+    unsafe extern "C" {
+        fn prctl(option: c_int, arg2: usize, arg3: usize, arg4: usize, arg5: usize) -> c_int;
+    }
+
+    const PR_SET_SECCOMP: c_int = 22;
+    const PR_SET_NO_NEW_PRIVS: c_int = 38;
+    const SECCOMP_MODE_FILTER: usize = 2;
+    const SECCOMP_RET_KILL_PROCESS: u32 = 0x8000_0000;
+    const SECCOMP_RET_ERRNO: u32 = 0x0005_0000;
+    const SECCOMP_RET_ALLOW: u32 = 0x7fff_0000;
+    const EPERM: u32 = 1;
+    const ENOSYS: u32 = 38;
+    const CLONE_THREAD: u32 = 0x0001_0000;
+    const BPF_LD_W_ABS: u16 = 0x20;
+    const BPF_JEQ_K: u16 = 0x15;
+    const BPF_JSET_K: u16 = 0x45;
+    #[cfg(target_arch = "x86_64")]
+    const BPF_JGE_K: u16 = 0x35;
+    const BPF_RET_K: u16 = 0x06;
+    // Offsets into struct seccomp_data.
+    const SYSCALL_NR: u32 = 0;
+    const ARCH: u32 = 4;
+    // The low half of the first argument, on both little-endian targets.
+    const ARG0: u32 = 16;
+
+    #[cfg(target_arch = "x86_64")]
+    const AUDIT_ARCH: u32 = 0xc000_003e;
+    #[cfg(target_arch = "aarch64")]
+    const AUDIT_ARCH: u32 = 0xc000_00b7;
+    #[cfg(target_arch = "x86_64")]
+    const CLONE: u32 = 56;
+    #[cfg(target_arch = "aarch64")]
+    const CLONE: u32 = 220;
+    const CLONE3: u32 = 435;
+
+    // execve, execveat, fork and vfork (x86_64 only), ptrace, process_vm_readv,
+    // process_vm_writev, mount, umount2, pivot_root, chroot, unshare,
+    // setns, init_module, finit_module, delete_module, kexec_load,
+    // kexec_file_load, reboot, swapon, swapoff, bpf, perf_event_open,
+    // userfaultfd, personality, open_by_handle_at, keyctl, add_key,
+    // request_key, setuid, setgid, setreuid, setregid, setresuid,
+    // setresgid, setgroups.
+    #[cfg(target_arch = "x86_64")]
+    const DENIED: &[u32] = &[
+        59, 322, 57, 58, 101, 310, 311, 165, 166, 155, 161, 272, 308, 175, 313, 176, 246, 320, 169, 167, 168, 321,
+        298, 323, 135, 304, 250, 248, 249, 105, 106, 113, 114, 117, 119, 116,
+    ];
+    #[cfg(target_arch = "aarch64")]
+    const DENIED: &[u32] = &[
+        221, 281, 117, 270, 271, 40, 39, 41, 51, 97, 268, 105, 273, 106, 104, 294, 142, 224, 225, 280, 241, 282, 92,
+        265, 219, 217, 218, 146, 144, 145, 143, 147, 149, 159,
+    ];
+
+    let op = |code, k| SockFilter { code, jt: 0, jf: 0, k };
+    let mut program = vec![
+        op(BPF_LD_W_ABS, ARCH),
+        SockFilter {
+            code: BPF_JEQ_K,
+            jt: 1,
+            jf: 0,
+            k: AUDIT_ARCH,
+        },
+        op(BPF_RET_K, SECCOMP_RET_KILL_PROCESS),
+        op(BPF_LD_W_ABS, SYSCALL_NR),
+    ];
+    // The x32 ABI shares the x86_64 arch value but sets this bit in the
+    // call number, so it would slip past the numbers below.
+    #[cfg(target_arch = "x86_64")]
+    program.push(SockFilter {
+        code: BPF_JGE_K,
+        jt: 0,
+        jf: 1,
+        k: 0x4000_0000,
+    });
+    #[cfg(target_arch = "x86_64")]
+    program.push(op(BPF_RET_K, SECCOMP_RET_ERRNO | EPERM));
+    for &number in DENIED {
+        program.push(SockFilter {
+            code: BPF_JEQ_K,
+            jt: 0,
+            jf: 1,
+            k: number,
+        });
+        program.push(op(BPF_RET_K, SECCOMP_RET_ERRNO | EPERM));
+    }
+    // clone3 passes its flags in memory the filter cannot read. Claiming it
+    // does not exist makes the C library fall back to clone.
+    program.push(SockFilter {
+        code: BPF_JEQ_K,
+        jt: 0,
+        jf: 1,
+        k: CLONE3,
+    });
+    program.push(op(BPF_RET_K, SECCOMP_RET_ERRNO | ENOSYS));
+    // clone may start a thread, but not a new process: that is how fork
+    // and posix_spawn reach the kernel.
+    program.push(SockFilter {
+        code: BPF_JEQ_K,
+        jt: 0,
+        jf: 3,
+        k: CLONE,
+    });
+    program.push(op(BPF_LD_W_ABS, ARG0));
+    program.push(SockFilter {
+        code: BPF_JSET_K,
+        jt: 1,
+        jf: 0,
+        k: CLONE_THREAD,
+    });
+    program.push(op(BPF_RET_K, SECCOMP_RET_ERRNO | EPERM));
+    program.push(op(BPF_RET_K, SECCOMP_RET_ALLOW));
+    let prog = SockFprog {
+        len: program.len() as u16,
+        filter: program.as_ptr(),
+    };
+
+    let failed = |what: &str| format!("Failed to {}: {}", what, std::io::Error::last_os_error());
+    // SAFETY: prctl is given the documented arguments, and `prog` points to
+    // a filter that stays alive until the kernel has copied it.
+    unsafe {
+        if prctl(PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) != 0 {
+            return Err(failed("forbid gaining privileges"));
+        }
+        if prctl(PR_SET_SECCOMP, SECCOMP_MODE_FILTER, &prog as *const SockFprog as usize, 0, 0) != 0 {
+            return Err(failed("apply the seccomp filter"));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64"))))]
+fn restrict_syscalls() -> Result<(), String> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::scratch::Scratch;
+
+    #[test]
+    fn sockets_are_private_to_the_user() {
+        let scratch = Scratch::create().unwrap();
+        let socket = scratch.dir.join("test.sock");
+        let listener = listen(&socket, "test").unwrap();
+        assert!(!listener.activated);
+        assert_eq!(fs::metadata(&socket).unwrap().permissions().mode() & 0o777, 0o600);
+        assert!(listen(&socket, "test").err().unwrap().contains("already listening"));
+        drop(listener);
+        // A socket left behind is replaced.
+        assert!(listen(&socket, "test").is_ok());
+        scratch.destroy().unwrap();
+    }
+
+    #[cfg(all(target_os = "linux", any(target_arch = "x86_64", target_arch = "aarch64")))]
+    #[test]
+    fn filter_allows_threads_but_not_processes() {
+        unsafe extern "C" {
+            fn fork() -> c_int;
+            fn _exit(status: c_int) -> !;
+        }
+        // The filter binds only this thread and the ones it starts, so the
+        // rest of the test run is unaffected.
+        std::thread::spawn(|| {
+            restrict_syscalls().unwrap();
+            assert_eq!(std::thread::spawn(|| 7).join().unwrap(), 7);
+            // SAFETY: a child, should one be started, exits at once.
+            let pid = unsafe { fork() };
+            if pid == 0 {
+                unsafe { _exit(0) };
+            }
+            assert_eq!(pid, -1);
+            assert_eq!(std::io::Error::last_os_error().raw_os_error(), Some(1), "EPERM");
+        })
+        .join()
+        .unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+/// A private folder for working on decrypted files, kept in memory where
+/// the system allows.
+pub struct Scratch {
+    pub dir: PathBuf,
+    /// Whether the folder is on a memory-backed file system, so the
+    /// plaintext never reaches the disk.
+    pub in_memory: bool,
+}
+
+impl Scratch {
+    /// Create an empty scratch folder only the user can enter.
+    pub fn create() -> Result<Self, String> {
+        let (parent, in_memory) = memory_backed_dir().map_or_else(|| (env::temp_dir(), false), |dir| (dir, true));
+        let mut suffix = [0u8; 8];
+        crate::crypto::random_bytes(&mut suffix)?;
+        let dir = parent.join(format!(
+            "file_encryptor-scratch-{}",
+            crate::checksum::to_hex(&suffix)
+        ));
+        let mut builder = fs::DirBuilder::new();
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            builder.mode(0o700);
+        }
+        builder
+            .create(&dir)
+            .map_err(|e| format!("Failed to create '{}': {}", dir.display(), e))?;
+        Ok(Scratch { dir, in_memory })
+    }
+
+    /// Run an interactive shell inside the folder and wait for it to exit.
+    /// `shell` overrides `$SHELL` (`%COMSPEC%` on Windows).
+    pub fn run_shell(&self, shell: Option<&str>) -> Result<ExitStatus, String> {
+        let shell = shell.map(str::to_string).unwrap_or_else(default_shell);
+        // A relative path names a program from where the tool was run, not
+        // from inside the folder.
+        let program = match Path::new(&shell).components().count() {
+            1 => PathBuf::from(&shell),
+            _ => std::path::absolute(&shell).map_err(|e| format!("Failed to resolve '{}': {}", shell, e))?,
+        };
+        let mut command = Command::new(program);
+        command
+            .current_dir(&self.dir)
+            .env("FILE_ENCRYPTOR_SCRATCH", &self.dir);
+        #[cfg(unix)]
+        let _ignored = IgnoreInterrupts::new(&mut command);
+        command
+            .status()
+            .map_err(|e| format!("Failed to start '{}': {}", shell, e))
+    }
+
+    /// Overwrite every file in the folder with zeros and delete it all, so
+    /// the plaintext cannot be read back from freed memory or disk blocks.
+    pub fn destroy(self) -> Result<(), String> {
+        wipe(&self.dir).map_err(|e| format!("Failed to wipe '{}': {}", self.dir.display(), e))?;
+        fs::remove_dir_all(&self.dir).map_err(|e| format!("Failed to remove '{}': {}", self.dir.display(), e))
+    }
+}
+
+/// Open `path` with the application the desktop uses for its type and
+/// return whether this waited for that application to be closed, as `open
+/// -W` does on macOS and `start /WAIT` on Windows. `xdg-open` hands the
+/// file over and returns at once.
+pub fn open_with_default_app(path: &Path) -> Result<bool, String> {
+    #[cfg(target_os = "macos")]
+    let (mut command, waits) = {
+        let mut command = Command::new("open");
+        command.arg("-W");
+        (command, true)
+    };
+    #[cfg(windows)]
+    let (mut command, waits) = {
+        use std::os::windows::process::CommandExt;
+        // `start` takes its first quoted argument as a window title.
+        let mut command = Command::new("cmd");
+        command.raw_arg("/C start \"\" /WAIT");
+        (command, true)
+    };
+    #[cfg(not(any(target_os = "macos", windows)))]
+    let (mut command, waits) = (Command::new("xdg-open"), false);
+
+    let program = format!("{:?}", command.get_program());
+    let status = command
+        .arg(path)
+        .status()
+        .map_err(|e| format!("Failed to start {}: {}", program, e))?;
+    if !status.success() {
+        return Err(format!("{} could not open '{}' ({}).", program, path.display(), status));
+    }
+    Ok(waits)
+}
+
+/// A file system that lives in memory, if the system has one the user can
+/// write to: `/dev/shm` on Linux, or the runtime folder systemd mounts as
+/// tmpfs.
+fn memory_backed_dir() -> Option<PathBuf> {
+    #[cfg(target_os = "linux")]
+    {
+        let shm = Path::new("/dev/shm");
+        if shm.is_dir() {
+            return Some(shm.to_path_buf());
+        }
+        env::var_os("XDG_RUNTIME_DIR")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_dir())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        None
+    }
+}
+
+fn default_shell() -> String {
+    #[cfg(windows)]
+    {
+        env::var("COMSPEC").unwrap_or_else(|_| "cmd.exe".to_string())
+    }
+    #[cfg(not(windows))]
+    {
+        env::var("SHELL")
+            .ok()
+            .filter(|shell| !shell.is_empty())
+            .unwrap_or_else(|| "/bin/sh".to_string())
+    }
+}
+
+/// Overwrite every regular file under `dir` with zeros. Symbolic links are
+/// not followed, so nothing outside the folder is touched.
+fn wipe(dir: &Path) -> io::Result<()> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let kind = entry.file_type()?;
+        if kind.is_dir() {
+            wipe(&entry.path())?;
+        } else if kind.is_file() {
+            let mut file = OpenOptions::new().write(true).open(entry.path())?;
+            let mut remaining = file.metadata()?.len();
+            let zeros = [0u8; 64 * 1024];
+            while remaining > 0 {
+                let chunk = remaining.min(zeros.len() as u64) as usize;
+                file.write_all(&zeros[..chunk])?;
+                remaining -= chunk as u64;
+            }
+            file.sync_all()?;
+        }
+    }
+    Ok(())
+}
+
+/// Keeps Ctrl-C and Ctrl-\ typed in the shell from killing this process,
+/// which would leave the plaintext behind, while the shell itself still
+/// gets them, as system(3) does.
+#[cfg(unix)]
+struct IgnoreInterrupts;
+
+#[cfg(unix)]
+const SIGINT: i32 = 2;
+#[cfg(unix)]
+const SIGQUIT: i32 = 3;
+#[cfg(unix)]
+const SIG_DFL: usize = 0;
+#[cfg(unix)]
+const SIG_IGN: usize = 1;
+
+#[cfg(unix)]
+unsafe extern "C" {
+    fn signal(signum: i32, handler: usize) -> usize;
+}
+
+#[cfg(unix)]
+impl IgnoreInterrupts {
+    fn new(command: &mut Command) -> Self {
+        use std::os::unix::process::CommandExt;
+
+        // SAFETY: signal is async-signal-safe, so it may run between fork
+        // and exec; it gives the shell the default handlers back.
+        unsafe {
+            command.pre_exec(|| {
+                signal(SIGINT, SIG_DFL);
+                signal(SIGQUIT, SIG_DFL);
+                Ok(())
+            });
+        }
+        // SAFETY: ignoring these signals has no preconditions.
+        unsafe {
+            signal(SIGINT, SIG_IGN);
+            signal(SIGQUIT, SIG_IGN);
+        }
+        IgnoreInterrupts
+    }
+}
+
+#[cfg(unix)]
+impl Drop for IgnoreInterrupts {
+    fn drop(&mut self) {
+        // SAFETY: restoring the default handlers has no preconditions.
+        unsafe {
+            signal(SIGINT, SIG_DFL);
+            signal(SIGQUIT, SIG_DFL);
+        }
+    }
+}
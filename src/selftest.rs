@@ -0,0 +1,413 @@
+use file_encryptor_core::aes::Aes256Gcm;
+
+use crate::checksum::{self, to_hex};
+use crate::crypto;
+use crate::ed25519;
+use crate::format::{self, SEALED_VERSION};
+use crate::metadata::FileMetadata;
+use crate::sealing;
+
+/// SHA-256 known answers: input and expected digest in hex. The first
+/// three are the FIPS 180-2 examples.
+pub const SHA256_VECTORS: &[(&[u8], &str)] = &[
+    (
+        b"",
+        "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+    ),
+    (
+        b"abc",
+        "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad",
+    ),
+    (
+        b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq",
+        "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1",
+    ),
+];
+
+/// BLAKE3 known answers from the official test vectors: input length
+/// (the input is bytes 0, 1, ... 250 repeating) and expected digest in
+/// hex. The lengths cover one block, one chunk, and the tree above it.
+pub const BLAKE3_VECTORS: &[(usize, &str)] = &[
+    (0, "af1349b9f5f9a1a6a0404dea36dcc9499bcb25c9adc112b7cc9a93cae41f3262"),
+    (1, "2d3adedff11b61f14c886e35afa036736dcd87a74d27b5c1510225d0f592e213"),
+    (1024, "42214739f095a406f3fc83deb889744ac00df831c10daa55189b5d121c855af7"),
+    (1025, "d00278ae47eb27b34faecf67b4fe263f82d5412916c1ffd97c8cb7fb814b8444"),
+    (2048, "e776b6028c7cd22a4d0ba182a8bf62205d2ef576467e838ed6f2529b85fba24a"),
+];
+
+/// HMAC-SHA256 known answers: key, message, and expected tag in hex
+/// (RFC 4231 test case 2).
+pub const HMAC_VECTORS: &[(&[u8], &[u8], &str)] = &[(
+    b"Jefe",
+    b"what do ya want for nothing?",
+    "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843",
+)];
+
+/// PBKDF2-HMAC-SHA256 known answers: password, salt, iterations, and the
+/// expected derived key in hex.
+pub const PBKDF2_VECTORS: &[(&[u8], &[u8], u32, &str)] = &[
+    (
+        b"password",
+        b"salt",
+        1,
+        "120fb6cffcf8b32c43e7225256c4f837a86548c92ccc35480805987cb70be17b",
+    ),
+    (
+        b"password",
+        b"salt",
+        2,
+        "ae4d0c95af6b46d32d0adff928f06dd02a303f8ef3c251dfd6e2d85a95474c43",
+    ),
+    (
+        b"passwordPASSWORDpassword",
+        b"saltSALTsaltSALTsaltSALTsaltSALTsalt",
+        4096,
+        "348c89dbcbd32b2f32d814b8116e84cf2b17347ebc1800181c4e2a1fb8dd53e1c635518c7dac47e9",
+    ),
+];
+
+/// ChaCha20 known answers: key, nonce, block counter, plaintext, and the
+/// expected ciphertext in hex (RFC 8439 section 2.4.2).
+pub const CHACHA20_VECTORS: &[(&str, &str, u32, &[u8], &str)] = &[(
+    "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f",
+    "000000000000004a00000000",
+    1,
+    b"Ladies and Gentlemen of the class of '99: If I could offer you only one tip for the future, sunscreen would be it.",
+    "6e2e359a2568f98041ba0728dd0d6981e97e7aec1d4360c20a27afccfd9fae0b\
+     f91b65c5524733ab8f593dabcd62b3571639d624e65152ab8f530c359f0861d8\
+     07ca0dbf500d6a6156a38e088a22b65e52bc514d16ccf806818ce91ab7793736\
+     5af90bbf74a35be6b40b8eedf2785e42874d",
+)];
+
+/// AES-256-GCM known answers: key, nonce, associated data, plaintext, and
+/// the expected ciphertext followed by the tag in hex (test case 16 of
+/// McGrew and Viega's GCM specification).
+pub const AES_GCM_VECTORS: &[(&str, &str, &str, &str, &str)] = &[(
+    "feffe9928665731c6d6a8f9467308308feffe9928665731c6d6a8f9467308308",
+    "cafebabefacedbaddecaf888",
+    "feedfacedeadbeeffeedfacedeadbeefabaddad2",
+    "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72\
+     1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b39",
+    "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa\
+     8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662\
+     76fc6ece0f4e1768cddf8853bb2d551b",
+)];
+
+/// SHA-512 known answers: input and expected digest in hex (FIPS 180-2).
+pub const SHA512_VECTORS: &[(&[u8], &str)] = &[(
+    b"abc",
+    "ddaf35a193617abacc417349ae20413112e6fa4e89a97ea20a9eeee64b55d39a\
+     2192992a274fc1a836ba3c23a3feebbd454d4423643ce80e2a9ac94fa54ca49f",
+)];
+
+/// Ed25519 known answers: private key seed, public key, message, and the
+/// expected signature in hex (RFC 8032 section 7.1, tests 1 and 2).
+pub const ED25519_VECTORS: &[(&str, &str, &[u8], &str)] = &[
+    (
+        "9d61b19deffd5a60ba844af492ec2cc44449c5697b326919703bac031cae7f60",
+        "d75a980182b10ab7d54bfed3c964073a0ee172f3daa62325af021a68f707511a",
+        b"",
+        "e5564300c360ac729086e2cc806e828a84877f1eb8e5d974d873e065224901555\
+         fb8821590a33bacc61e39701cf9b46bd25bf5f0595bbe24655141438e7a100b",
+    ),
+    (
+        "4ccd089b28ff96da9db6c346ec114e0f5b8a319f35aba624da8cf6ed4fb8a6fb",
+        "3d4017c3e843895a92b70aa74d1b7ebc9c982ccf2ec4968cc0cd55f12af4660c",
+        b"\x72",
+        "92a009a9f0d4cab8720e820b5f642540a2b27b5416503f8fb3762223ebdb69da\
+         085ac1e43e15996e458f3613d0f11d8c387b2eaeb4302aeeb00d291612bb0c00",
+    ),
+];
+
+/// X25519 known answers: scalar, u-coordinate, and the expected result in
+/// hex (RFC 7748 section 5.2, the first test and one round of the
+/// iterated test).
+pub const X25519_VECTORS: &[(&str, &str, &str)] = &[
+    (
+        "a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4",
+        "e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c",
+        "c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552",
+    ),
+    (
+        "0900000000000000000000000000000000000000000000000000000000000000",
+        "0900000000000000000000000000000000000000000000000000000000000000",
+        "422c8e7a6227d7bca1350b3e2bb7279f7897b87bb6854b783c60e80311ae3079",
+    ),
+];
+
+/// XOR cipher known answers: plaintext, key, and expected output in hex.
+pub const XOR_VECTORS: &[(&[u8], &[u8], &str)] = &[
+    (b"hello", b"key", "030015070a"),
+    (b"\x00\x00\x00\x00", b"\xff\x01", "ff01ff01"),
+];
+
+/// File format known answers: format version, plaintext, password, and the
+/// complete encrypted file in hex. Version 0 stands for headerless files
+/// from before the format had a header. Sealed versions are also encrypted
+/// again with the salt and nonce from their header and must come out the
+/// same.
+pub const FORMAT_VECTORS: &[(u8, &[u8], &[u8], &str)] = &[
+    (0, b"hello", b"key", "030015070a"),
+    (
+        1,
+        b"hello",
+        b"key",
+        "46454e4301479734d13ac9c86b5f835e53aedc9bf57e6f753966cc272718614a\
+         09f6f2f341110e091504",
+    ),
+    (
+        2,
+        b"hello",
+        b"key",
+        "46454e4302479734d13ac9c86b5f835e53aedc9bf57e6f753966cc272718614a\
+         09f6f2f341796b6579030015070a",
+    ),
+    (
+        3,
+        b"hello",
+        b"key",
+        "46454e430302000000000102030405060708090a0b0c0d0e0f00010203040506\
+         0708090a0be6c2047e1a28908a3245cbe04648d23c78af727495fd875b375768\
+         d2efc3042128fd895d26f1e7c4f5d370f4ac33c64909f5a6d34f5ffc9e586f21\
+         9db1cfbaab38653acebee3d2303b",
+    ),
+    (
+        5,
+        b"hello",
+        b"key",
+        "46454e430502000000000102030405060708090a0b0c0d0e0f00010203040506\
+         0708090a0b00ec4d47791d152b3cbac355d047a365515d15c6fa781dca4a6fa9\
+         701eb939dceaef82f7d6564447de08b7199165614c7ec41731f2158cfe55a2",
+    ),
+];
+
+/// Metadata known answers: a metadata block in hex (an owner of
+/// 1000:1000 and the extended attribute `user.test=abc`), a plaintext, a
+/// password, and the complete version 3 file holding both, in hex. They pin
+/// every fixed-width field of the format, so a 32-bit or big-endian build
+/// that laid one out differently would fail here instead of writing files
+/// other machines cannot read.
+pub const METADATA_VECTORS: &[(&str, &[u8], &[u8], &str)] = &[(
+    "0108000000e8030000e8030000020e0000000900757365722e74657374616263",
+    b"hello",
+    b"key",
+    "46454e430302000000000102030405060708090a0b0c0d0e0f00010203040506\
+     0708090a0be6c2047e1a28908a3245cbe04648d23c78af727495fd875b375768\
+     d2efc3042108fd895d4f9c8ba89a27bacac9aa57b1e78c3e34ad4c3a1557e316\
+     cb0ea00a898bdd099695404d82469f444fbdc50c69bb08017a0dcf55c7bcd2a7\
+     43c1484a0972490600a7b7f5ed53",
+)];
+
+/// The outcome of one known-answer test.
+pub struct Outcome {
+    pub name: String,
+    pub error: Option<String>,
+}
+
+/// Run every known-answer test and report each result.
+pub fn run() -> Vec<Outcome> {
+    let mut outcomes = Vec::new();
+    let mut record = |name: String, error: Option<String>| outcomes.push(Outcome { name, error });
+
+    for (index, (input, expected)) in SHA256_VECTORS.iter().enumerate() {
+        let actual = to_hex(&checksum::sha256(input));
+        record(
+            format!("SHA-256 #{}", index + 1),
+            mismatch(expected, &actual),
+        );
+    }
+
+    for (index, (len, expected)) in BLAKE3_VECTORS.iter().enumerate() {
+        let input: Vec<u8> = (0..*len).map(|i| (i % 251) as u8).collect();
+        let actual = to_hex(&checksum::blake3(&input));
+        record(format!("BLAKE3 #{}", index + 1), mismatch(expected, &actual));
+    }
+
+    for (index, (key, message, expected)) in HMAC_VECTORS.iter().enumerate() {
+        let actual = to_hex(&crypto::hmac_sha256(key, message));
+        record(
+            format!("HMAC-SHA256 #{}", index + 1),
+            mismatch(expected, &actual),
+        );
+    }
+
+    for (index, (password, salt, iterations, expected)) in PBKDF2_VECTORS.iter().enumerate() {
+        let mut derived = vec![0u8; expected.len() / 2];
+        crypto::pbkdf2_sha256(password, salt, *iterations, &mut derived);
+        record(
+            format!("PBKDF2-HMAC-SHA256 #{}", index + 1),
+            mismatch(expected, &to_hex(&derived)),
+        );
+    }
+
+    for (index, (key, nonce, counter, plaintext, expected)) in CHACHA20_VECTORS.iter().enumerate() {
+        let mut key_bytes = [0u8; crypto::KEY_LEN];
+        key_bytes.copy_from_slice(&from_hex(key));
+        let mut nonce_bytes = [0u8; crypto::NONCE_LEN];
+        nonce_bytes.copy_from_slice(&from_hex(nonce));
+        let mut data = plaintext.to_vec();
+        crypto::chacha20_xor(&key_bytes, &nonce_bytes, *counter as u64 * 64, &mut data);
+        record(
+            format!("ChaCha20 #{}", index + 1),
+            mismatch(&to_hex(&from_hex(expected)), &to_hex(&data)),
+        );
+
+        // The same, in two pieces split inside a block, as when previewing
+        // part of a file.
+        let mut pieces = plaintext.to_vec();
+        let (first, second) = pieces.split_at_mut(7);
+        crypto::chacha20_xor(&key_bytes, &nonce_bytes, *counter as u64 * 64, first);
+        crypto::chacha20_xor(&key_bytes, &nonce_bytes, *counter as u64 * 64 + 7, second);
+        record(
+            format!("ChaCha20 #{} in pieces", index + 1),
+            mismatch(&to_hex(&from_hex(expected)), &to_hex(&pieces)),
+        );
+    }
+
+    for (index, (key, nonce, aad, plaintext, expected)) in AES_GCM_VECTORS.iter().enumerate() {
+        let mut key_bytes = [0u8; crypto::KEY_LEN];
+        key_bytes.copy_from_slice(&from_hex(key));
+        let mut nonce_bytes = [0u8; crypto::NONCE_LEN];
+        nonce_bytes.copy_from_slice(&from_hex(nonce));
+        let aes = Aes256Gcm::new(&key_bytes);
+        let mut data = from_hex(plaintext);
+        aes.xor_keystream(&nonce_bytes, 0, &mut data);
+        let tag = aes.tag(&nonce_bytes, &from_hex(aad), &data);
+        data.extend_from_slice(&tag);
+        record(
+            format!("AES-256-GCM #{}", index + 1),
+            mismatch(&to_hex(&from_hex(expected)), &to_hex(&data)),
+        );
+    }
+
+    for (index, (input, expected)) in SHA512_VECTORS.iter().enumerate() {
+        let actual = to_hex(&ed25519::sha512(input));
+        record(
+            format!("SHA-512 #{}", index + 1),
+            mismatch(&to_hex(&from_hex(expected)), &actual),
+        );
+    }
+
+    for (index, (seed, public, message, expected)) in ED25519_VECTORS.iter().enumerate() {
+        let mut seed_bytes = [0u8; ed25519::KEY_LEN];
+        seed_bytes.copy_from_slice(&from_hex(seed));
+        let signature = ed25519::sign(&seed_bytes, message);
+        let mut error = mismatch(public, &to_hex(&ed25519::public_key(&seed_bytes)))
+            .or_else(|| mismatch(&to_hex(&from_hex(expected)), &to_hex(&signature)));
+        if error.is_none() {
+            let mut public_bytes = [0u8; ed25519::KEY_LEN];
+            public_bytes.copy_from_slice(&from_hex(public));
+            let mut forged = signature;
+            forged[0] ^= 1;
+            if !ed25519::verify(&public_bytes, message, &signature) {
+                error = Some("rejected a valid signature".to_string());
+            } else if ed25519::verify(&public_bytes, message, &forged) {
+                error = Some("accepted a modified signature".to_string());
+            }
+        }
+        record(format!("Ed25519 #{}", index + 1), error);
+    }
+
+    for (index, (scalar, u, expected)) in X25519_VECTORS.iter().enumerate() {
+        let mut scalar_bytes = [0u8; ed25519::KEY_LEN];
+        scalar_bytes.copy_from_slice(&from_hex(scalar));
+        let mut u_bytes = [0u8; ed25519::KEY_LEN];
+        u_bytes.copy_from_slice(&from_hex(u));
+        let actual = to_hex(&ed25519::x25519(&scalar_bytes, &u_bytes));
+        record(format!("X25519 #{}", index + 1), mismatch(expected, &actual));
+    }
+
+    for (index, (plaintext, key, expected)) in XOR_VECTORS.iter().enumerate() {
+        let actual = to_hex(&crate::xor_with_key(plaintext, key));
+        record(format!("XOR #{}", index + 1), mismatch(expected, &actual));
+    }
+
+    for (version, plaintext, password, encrypted) in FORMAT_VECTORS {
+        let encrypted = from_hex(encrypted);
+        let mut error = None;
+        if *version >= SEALED_VERSION {
+            error = match format::parse_sealed_header(&encrypted) {
+                Ok(params) => {
+                    let actual = to_hex(&sealing::seal(plaintext, &[], password, &params));
+                    mismatch(&to_hex(&encrypted), &actual)
+                }
+                Err(e) => Some(format::message(e)),
+            };
+        }
+        if error.is_none() {
+            error = match crate::decrypt_bytes(&encrypted, password, true) {
+                Ok(decrypted) if decrypted.plaintext == *plaintext => None,
+                Ok(_) => Some("decrypted to the wrong plaintext".to_string()),
+                Err(e) => Some(e.into()),
+            };
+        }
+        if error.is_none() && *version > 0 && crate::decrypt_bytes(&encrypted, b"wrong", true).is_ok() {
+            error = Some("accepted a wrong password".to_string());
+        }
+        record(format!("Format version {}", version), error);
+    }
+
+    for (index, (metadata, plaintext, password, encrypted)) in METADATA_VECTORS.iter().enumerate() {
+        let metadata = from_hex(metadata);
+        let encrypted = from_hex(encrypted);
+        let error = match FileMetadata::from_bytes(&metadata) {
+            Ok(parsed) => mismatch(&to_hex(&metadata), &to_hex(&parsed.to_bytes())),
+            Err(e) => Some(format::message(e)),
+        };
+        let error = error.or_else(|| match format::parse_sealed_header(&encrypted) {
+            Ok(params) => {
+                let actual = to_hex(&sealing::seal(plaintext, &metadata, password, &params));
+                mismatch(&to_hex(&encrypted), &actual)
+            }
+            Err(e) => Some(format::message(e)),
+        });
+        let error = error.or_else(|| match crate::decrypt_bytes(&encrypted, password, false) {
+            Ok(decrypted) if decrypted.plaintext != *plaintext => Some("decrypted to the wrong plaintext".to_string()),
+            Ok(decrypted) => {
+                let stored = decrypted.metadata.map(|stored| stored.to_bytes()).unwrap_or_default();
+                mismatch(&to_hex(&metadata), &to_hex(&stored))
+            }
+            Err(e) => Some(e.into()),
+        });
+        record(format!("Metadata block #{}", index + 1), error);
+    }
+
+    outcomes
+}
+
+/// Run the tests and return an error naming the first one that failed, so
+/// the tool can refuse to work when its crypto is broken.
+pub fn verify() -> Result<(), String> {
+    match run().into_iter().find(|outcome| outcome.error.is_some()) {
+        None => Ok(()),
+        Some(outcome) => Err(format!(
+            "Self-test '{}' failed: {}. This build produces wrong results and will not be used.",
+            outcome.name,
+            outcome.error.unwrap_or_default()
+        )),
+    }
+}
+
+/// Describe the difference between two hex strings, if any.
+fn mismatch(expected: &str, actual: &str) -> Option<String> {
+    (expected != actual).then(|| format!("expected {}, got {}", expected, actual))
+}
+
+/// Decode a hex string, ignoring anything that is not a hex digit.
+fn from_hex(text: &str) -> Vec<u8> {
+    let digits: Vec<u8> = text.bytes().filter(u8::is_ascii_hexdigit).collect();
+    digits
+        .chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).unwrap_or("00"), 16).unwrap_or(0))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn every_known_answer_passes() {
+        for outcome in super::run() {
+            assert!(outcome.error.is_none(), "{}: {:?}", outcome.name, outcome.error);
+        }
+    }
+}
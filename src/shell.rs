@@ -0,0 +1,289 @@
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::tty;
+
+/// Title of the menu entries and dialogs.
+const APP_NAME: &str = "File Encryptor";
+
+/// Add "Encrypt with File Encryptor" and "Decrypt with File Encryptor" to
+/// the file manager's context menu, running `exe integrate-shell run`.
+/// Returns what was added, to show the user.
+pub fn install(exe: &Path) -> Result<Vec<String>, String> {
+    #[cfg(windows)]
+    {
+        let exe = exe.display().to_string();
+        let mut added = Vec::new();
+        for (key, label, action) in windows_keys() {
+            reg("add", key, &["/ve", "/d", &format!("{} with {}", label, APP_NAME), "/f"])?;
+            reg("add", key, &["/v", "Icon", "/d", &exe, "/f"])?;
+            let command = format!("\"{}\" integrate-shell run {} \"%1\"", exe, action);
+            reg("add", &format!("{}\\command", key), &["/ve", "/d", &command, "/f"])?;
+            added.push(format!("HKEY_CURRENT_USER\\{}", key));
+        }
+        Ok(added)
+    }
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        let mut added = Vec::new();
+        let service_menu = service_menu_path()?;
+        write_executable(&service_menu, &service_menu_contents(exe))?;
+        added.push(service_menu.display().to_string());
+        for (path, action) in nautilus_scripts()? {
+            write_executable(&path, &format!("#!/bin/sh\nexec {} integrate-shell run {} \"$@\"\n", shell_quote(exe), action))?;
+            added.push(path.display().to_string());
+        }
+        Ok(added)
+    }
+    #[cfg(not(any(windows, all(unix, not(target_os = "macos")))))]
+    {
+        let _ = exe;
+        Err("Context-menu entries can only be installed on Windows and Linux.".to_string())
+    }
+}
+
+/// Remove the entries `install` added. Returns what was removed.
+pub fn uninstall() -> Result<Vec<String>, String> {
+    #[cfg(windows)]
+    {
+        let mut removed = Vec::new();
+        for (key, _, _) in windows_keys() {
+            // `reg delete` fails when the key is already gone.
+            if reg("delete", key, &["/f"]).is_ok() {
+                removed.push(format!("HKEY_CURRENT_USER\\{}", key));
+            }
+        }
+        Ok(removed)
+    }
+    #[cfg(all(unix, not(target_os = "macos")))]
+    {
+        let mut removed = Vec::new();
+        let mut paths = vec![service_menu_path()?];
+        paths.extend(nautilus_scripts()?.into_iter().map(|(path, _)| path));
+        for path in paths {
+            if path.exists() {
+                std::fs::remove_file(&path).map_err(|e| format!("Failed to remove '{}': {}", path.display(), e))?;
+                removed.push(path.display().to_string());
+            }
+        }
+        Ok(removed)
+    }
+    #[cfg(not(any(windows, all(unix, not(target_os = "macos")))))]
+    {
+        Err("Context-menu entries can only be installed on Windows and Linux.".to_string())
+    }
+}
+
+/// Ask for a password in a small dialog, falling back to the terminal when
+/// no dialog program is available. Returns `None` if the dialog was
+/// cancelled.
+pub fn ask_password(prompt: &str) -> Option<String> {
+    #[cfg(windows)]
+    let answer = powershell(&format!(
+        "$c = Get-Credential -UserName '{}' -Message '{}'; if ($c) {{ $c.GetNetworkCredential().Password }} else {{ exit 1 }}",
+        ps_quote(APP_NAME),
+        ps_quote(prompt)
+    ));
+    #[cfg(not(windows))]
+    let answer = dialog(&[
+        &["zenity", "--password", "--title", APP_NAME],
+        &["kdialog", "--title", APP_NAME, "--password", prompt],
+    ]);
+    match answer {
+        Dialog::Answered(text) => Some(text.trim_end_matches(['\r', '\n']).to_string()),
+        Dialog::Cancelled => None,
+        Dialog::Unavailable => Some(tty::prompt(&format!("{}: ", prompt))),
+    }
+}
+
+/// Ask a yes-or-no question, where the answer is no unless chosen.
+pub fn ask_yes_no(question: &str) -> bool {
+    #[cfg(windows)]
+    let answer = powershell(&format!(
+        "Add-Type -AssemblyName System.Windows.Forms; if ([System.Windows.Forms.MessageBox]::Show('{}', '{}', 'YesNo', 'Warning', 'Button2') -ne 'Yes') {{ exit 1 }}",
+        ps_quote(question),
+        ps_quote(APP_NAME)
+    ));
+    #[cfg(not(windows))]
+    let answer = dialog(&[
+        &["zenity", "--question", "--title", APP_NAME, "--default-cancel", "--text", question],
+        &["kdialog", "--title", APP_NAME, "--warningyesno", question],
+    ]);
+    match answer {
+        Dialog::Answered(_) => true,
+        Dialog::Cancelled => false,
+        Dialog::Unavailable => {
+            matches!(tty::prompt(&format!("{} [y/N]: ", question)).to_lowercase().as_str(), "y" | "yes")
+        }
+    }
+}
+
+/// Show the outcome of an action, as an error if `failed`.
+pub fn show(message: &str, failed: bool) {
+    #[cfg(windows)]
+    let answer = powershell(&format!(
+        "Add-Type -AssemblyName System.Windows.Forms; [void][System.Windows.Forms.MessageBox]::Show('{}', '{}', 'OK', '{}')",
+        ps_quote(message),
+        ps_quote(APP_NAME),
+        if failed { "Error" } else { "Information" }
+    ));
+    #[cfg(not(windows))]
+    let answer = dialog(&[
+        &["zenity", if failed { "--error" } else { "--info" }, "--title", APP_NAME, "--no-markup", "--text", message],
+        &["kdialog", "--title", APP_NAME, if failed { "--error" } else { "--msgbox" }, message],
+    ]);
+    if let Dialog::Unavailable = answer {
+        if failed {
+            eprintln!("{}", message);
+        } else {
+            println!("{}", message);
+        }
+    }
+}
+
+enum Dialog {
+    /// The dialog was accepted, with what it printed.
+    Answered(String),
+    Cancelled,
+    /// None of the dialog programs could be started.
+    Unavailable,
+}
+
+/// Run the first of `commands` whose program is installed.
+#[cfg(not(windows))]
+fn dialog(commands: &[&[&str]]) -> Dialog {
+    for command in commands {
+        let Some((program, args)) = command.split_first() else {
+            continue;
+        };
+        let Ok(output) = Command::new(program).args(args).stdin(Stdio::null()).stderr(Stdio::null()).output() else {
+            continue;
+        };
+        return if output.status.success() {
+            Dialog::Answered(String::from_utf8_lossy(&output.stdout).into_owned())
+        } else {
+            Dialog::Cancelled
+        };
+    }
+    Dialog::Unavailable
+}
+
+#[cfg(windows)]
+fn powershell(script: &str) -> Dialog {
+    let output = Command::new("powershell")
+        .args(["-NoProfile", "-Command", script])
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output();
+    match output {
+        Ok(output) if output.status.success() => Dialog::Answered(String::from_utf8_lossy(&output.stdout).into_owned()),
+        Ok(_) => Dialog::Cancelled,
+        Err(_) => Dialog::Unavailable,
+    }
+}
+
+/// Quote `text` for a single-quoted PowerShell string.
+#[cfg(windows)]
+fn ps_quote(text: &str) -> String {
+    text.replace('\'', "''")
+}
+
+/// The registry keys under `HKEY_CURRENT_USER`, with their label and
+/// action: encrypting is offered for every file, decrypting for `.enc`
+/// files.
+#[cfg(windows)]
+fn windows_keys() -> [(&'static str, &'static str, &'static str); 2] {
+    [
+        ("Software\\Classes\\*\\shell\\FileEncryptorEncrypt", "Encrypt", "encrypt"),
+        ("Software\\Classes\\SystemFileAssociations\\.enc\\shell\\FileEncryptorDecrypt", "Decrypt", "decrypt"),
+    ]
+}
+
+/// Run `reg OPERATION HKCU\KEY ARGS...`.
+#[cfg(windows)]
+fn reg(operation: &str, key: &str, args: &[&str]) -> Result<(), String> {
+    let key = format!("HKCU\\{}", key);
+    let status = Command::new("reg")
+        .arg(operation)
+        .arg(&key)
+        .args(args)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map_err(|e| format!("Failed to start reg: {}", e))?;
+    if !status.success() {
+        return Err(format!("reg {} '{}' failed ({}).", operation, key, status));
+    }
+    Ok(())
+}
+
+/// `~/.local/share`, or `$XDG_DATA_HOME`.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn data_home() -> Result<PathBuf, String> {
+    match std::env::var_os("XDG_DATA_HOME").filter(|dir| !dir.is_empty()) {
+        Some(dir) => Ok(PathBuf::from(dir)),
+        None => std::env::home_dir()
+            .map(|home| home.join(".local/share"))
+            .ok_or_else(|| "Cannot find the home folder.".to_string()),
+    }
+}
+
+/// The service menu KDE's Dolphin reads, whose actions appear in the
+/// context menu of every file.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn service_menu_path() -> Result<PathBuf, String> {
+    Ok(data_home()?.join("kio/servicemenus/file-encryptor.desktop"))
+}
+
+#[cfg(all(unix, not(target_os = "macos")))]
+fn service_menu_contents(exe: &Path) -> String {
+    // Exec arguments are quoted with double quotes, inside which `"`, `` ` ``,
+    // `$`, and `\` need a backslash.
+    let mut quoted = String::from("\"");
+    for c in exe.display().to_string().chars() {
+        if matches!(c, '"' | '`' | '$' | '\\') {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    format!(
+        "[Desktop Entry]\nType=Service\nMimeType=application/octet-stream;all/allfiles;\nX-KDE-ServiceTypes=KonqPopupMenu/Plugin\nActions=encrypt;decrypt;\n\n\
+         [Desktop Action encrypt]\nName=Encrypt with {app}\nIcon=document-encrypt\nExec={exe} integrate-shell run encrypt %F\n\n\
+         [Desktop Action decrypt]\nName=Decrypt with {app}\nIcon=document-decrypt\nExec={exe} integrate-shell run decrypt %F\n",
+        app = APP_NAME,
+        exe = quoted
+    )
+}
+
+/// The scripts GNOME Files (Nautilus) lists under Scripts in its context
+/// menu, with the action each runs.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn nautilus_scripts() -> Result<[(PathBuf, &'static str); 2], String> {
+    let dir = data_home()?.join("nautilus/scripts");
+    Ok([
+        (dir.join(format!("Encrypt with {}", APP_NAME)), "encrypt"),
+        (dir.join(format!("Decrypt with {}", APP_NAME)), "decrypt"),
+    ])
+}
+
+/// Quote `path` for a POSIX shell.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn shell_quote(path: &Path) -> String {
+    format!("'{}'", path.display().to_string().replace('\'', "'\\''"))
+}
+
+/// Write `contents` to `path`, creating its folder, and make it
+/// executable, as both Nautilus scripts and newer KDE service menus must
+/// be.
+#[cfg(all(unix, not(target_os = "macos")))]
+fn write_executable(path: &Path, contents: &str) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent).map_err(|e| format!("Failed to create '{}': {}", parent.display(), e))?;
+    }
+    std::fs::write(path, contents).map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o755))
+        .map_err(|e| format!("Failed to make '{}' executable: {}", path.display(), e))
+}
@@ -0,0 +1,347 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use crate::checksum::{self, SHA256_LEN};
+use crate::format;
+use crate::memory;
+use crate::storage;
+use crate::throttle;
+
+/// Bytes at the start of a decrypted sidecar, so an ordinary encrypted file
+/// is not mistaken for one.
+const SIDECAR_MAGIC: &[u8; 4] = b"FSCR";
+
+/// Record tags inside a sidecar: the SHA-256 of the file as it was left
+/// after splitting, then one record per range, holding its offset (eight
+/// bytes, little-endian) followed by the bytes that were taken out.
+const TAG_SPLIT_SHA256: u8 = 1;
+const TAG_RANGE: u8 = 2;
+
+/// DICOM tags with a meaning of their own while walking a file.
+const TRANSFER_SYNTAX: (u16, u16) = (0x0002, 0x0010);
+const PIXEL_DATA: (u16, u16) = (0x7fe0, 0x0010);
+const ITEM: (u16, u16) = (0xfffe, 0xe000);
+const ITEM_DELIMITER: (u16, u16) = (0xfffe, 0xe00d);
+const SEQUENCE_DELIMITER: (u16, u16) = (0xfffe, 0xe0dd);
+
+/// Length of a DICOM value whose end is marked by a delimiter instead.
+const UNDEFINED_LENGTH: usize = 0xffff_ffff;
+
+/// Part of a file to move into the sidecar.
+pub enum Field {
+    /// Bytes `start` to `end` (exclusive).
+    Range(Range<u64>),
+    /// The value of every DICOM data element with this group and element
+    /// number, such as `(0x0010, 0x0010)` for the patient's name.
+    Dicom(u16, u16),
+}
+
+impl Field {
+    /// Read a DICOM tag written as `GGGG,EEEE` in hexadecimal, with or
+    /// without parentheses.
+    pub fn parse_dicom_tag(text: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid DICOM tag '{}'; expected GGGG,EEEE, such as 0010,0010.", text);
+        let inner = text.trim().trim_start_matches('(').trim_end_matches(')');
+        let (group, element) = inner.split_once(',').ok_or_else(invalid)?;
+        let parse = |part: &str| u16::from_str_radix(part.trim(), 16).map_err(|_| invalid());
+        Ok(Field::Dicom(parse(group)?, parse(element)?))
+    }
+}
+
+/// What `split` or `merge` moved.
+pub struct Summary {
+    pub ranges: usize,
+    pub bytes: u64,
+}
+
+/// Where the sidecar of `path` goes unless another is named:
+/// `scan.dcm.sidecar.enc` for `scan.dcm`.
+pub fn default_path(path: &Path) -> PathBuf {
+    crate::paths::with_suffix(path, ".sidecar.enc")
+}
+
+/// Move the bytes `fields` select out of the file at `path` into an
+/// encrypted sidecar at `sidecar`, and blank them in the file, which keeps
+/// its size and structure so the tools that read its format still can.
+/// Text is blanked with spaces and anything else with zeros. The sidecar
+/// is written before the file is touched.
+pub fn split(path: &Path, sidecar: &Path, fields: &[Field], password: &str) -> Result<Summary, String> {
+    if password.is_empty() {
+        return Err("Password cannot be empty.".to_string());
+    }
+    if fields.is_empty() {
+        return Err("Name at least one --range or --dicom-tag to move into the sidecar.".to_string());
+    }
+    memory::check_file(path)?;
+    let mut data = throttle::read(path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+
+    let mut ranges = Vec::new();
+    let wanted: Vec<(u16, u16)> = fields
+        .iter()
+        .filter_map(|field| match field {
+            Field::Dicom(group, element) => Some((*group, *element)),
+            Field::Range(_) => None,
+        })
+        .collect();
+    if !wanted.is_empty() {
+        ranges.extend(dicom_values(&data, &wanted)?);
+    }
+    for field in fields {
+        if let Field::Range(range) = field {
+            if range.end > data.len() as u64 {
+                return Err(format!(
+                    "The range {}-{} goes past the end of '{}', which is {} bytes long.",
+                    range.start,
+                    range.end,
+                    path.display(),
+                    data.len()
+                ));
+            }
+            ranges.push(range.start as usize..range.end as usize);
+        }
+    }
+    let ranges = merge_overlapping(ranges);
+
+    let mut plaintext = SIDECAR_MAGIC.to_vec();
+    let mut bytes = 0;
+    for range in &ranges {
+        let mut body = (range.start as u64).to_le_bytes().to_vec();
+        body.extend_from_slice(&data[range.clone()]);
+        format::push_record(&mut plaintext, TAG_RANGE, &body);
+        bytes += range.len() as u64;
+        let fill = if is_text(&data[range.clone()]) { b' ' } else { 0 };
+        data[range.clone()].fill(fill);
+    }
+    format::push_record(&mut plaintext, TAG_SPLIT_SHA256, &checksum::sha256(&data));
+    let encrypted = crate::encrypt_bytes(&plaintext, &[], password.as_bytes())?;
+
+    write_replacing(sidecar, &encrypted)?;
+    write_replacing(path, &data)?;
+    Ok(Summary {
+        ranges: ranges.len(),
+        bytes,
+    })
+}
+
+/// Put the bytes kept in `sidecar` back into the file at `path`. The file
+/// must be exactly as `split` left it; one that was changed since is
+/// refused rather than patched at offsets that may no longer mean the
+/// same.
+pub fn merge(path: &Path, sidecar: &Path, password: &str) -> Result<Summary, String> {
+    if password.is_empty() {
+        return Err("Password cannot be empty.".to_string());
+    }
+    let encrypted =
+        throttle::read(sidecar).map_err(|e| format!("Failed to read sidecar '{}': {}", sidecar.display(), e))?;
+    let decrypted = crate::decrypt_bytes(&encrypted, password.as_bytes(), false)?;
+    let records = decrypted
+        .plaintext
+        .strip_prefix(SIDECAR_MAGIC)
+        .ok_or_else(|| format!("'{}' is an encrypted file but not a sidecar.", sidecar.display()))?;
+
+    memory::check_file(path)?;
+    let mut data = throttle::read(path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    let mut ranges = Vec::new();
+    let mut split_sha256 = None;
+    for (tag, body) in format::read_records(records).map_err(format::message)? {
+        match tag {
+            TAG_RANGE if body.len() >= 8 => {
+                let start = u64::from_le_bytes(body[..8].try_into().expect("eight bytes"));
+                ranges.push((start, &body[8..]));
+            }
+            TAG_SPLIT_SHA256 if body.len() == SHA256_LEN => split_sha256 = Some(body),
+            _ => return Err("The sidecar's contents are damaged.".to_string()),
+        }
+    }
+    if split_sha256 != Some(&checksum::sha256(&data)[..]) {
+        return Err(format!(
+            "'{}' has changed since its sidecar was made, so the bytes cannot be put back safely.",
+            path.display()
+        ));
+    }
+
+    let mut bytes = 0;
+    for (start, original) in &ranges {
+        let start = *start as usize;
+        data.get_mut(start..start + original.len())
+            .ok_or_else(|| "The sidecar's contents are damaged.".to_string())?
+            .copy_from_slice(original);
+        bytes += original.len() as u64;
+    }
+    write_replacing(path, &data)?;
+    Ok(Summary {
+        ranges: ranges.len(),
+        bytes,
+    })
+}
+
+/// Write `data` next to `path` and rename it over the file, so a crash
+/// never leaves a half-written file behind.
+fn write_replacing(path: &Path, data: &[u8]) -> Result<(), String> {
+    let temp_path = crate::paths::with_suffix(path, ".saving");
+    throttle::write(&temp_path, data)
+        .and_then(|_| storage::rename(&temp_path, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&temp_path);
+            format!("Failed to write '{}': {}", path.display(), e)
+        })
+}
+
+/// Sort `ranges` and join those that overlap or touch, so no byte is kept
+/// twice.
+fn merge_overlapping(mut ranges: Vec<Range<usize>>) -> Vec<Range<usize>> {
+    ranges.retain(|range| !range.is_empty());
+    ranges.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+    merged
+}
+
+/// Whether `bytes` read as text, perhaps padded with NULs, so blanking
+/// them with spaces keeps them valid where a text value is expected.
+fn is_text(bytes: &[u8]) -> bool {
+    bytes.iter().any(|&byte| byte != 0)
+        && bytes.iter().all(|&byte| byte == 0 || byte == b'\t' || byte == b'\n' || byte == b'\r' || (0x20..0x7f).contains(&byte))
+}
+
+/// Where the values of the `wanted` data elements are in a DICOM Part 10
+/// file, at any depth. Every tag must occur at least once.
+fn dicom_values(data: &[u8], wanted: &[(u16, u16)]) -> Result<Vec<Range<usize>>, String> {
+    if data.get(128..132) != Some(b"DICM") {
+        return Err("--dicom-tag needs a DICOM file, which starts with a 128-byte preamble and 'DICM'.".to_string());
+    }
+    let mut walker = Dicom {
+        data,
+        wanted,
+        explicit: true,
+        past_meta: false,
+        syntax: Vec::new(),
+        found: Vec::new(),
+    };
+    walker.elements(132, data.len(), false)?;
+    for &(group, element) in wanted {
+        if !walker.found.iter().any(|(tag, _)| *tag == (group, element)) {
+            return Err(format!("The DICOM file has no element ({:04X},{:04X}).", group, element));
+        }
+    }
+    Ok(walker.found.into_iter().map(|(_, range)| range).collect())
+}
+
+/// Walks the data elements of a DICOM file, little-endian with explicit
+/// or implicit value representations, recording where the wanted values
+/// are.
+struct Dicom<'a> {
+    data: &'a [u8],
+    wanted: &'a [(u16, u16)],
+    /// Whether each element states its value representation. The file
+    /// meta group always does; the rest follows the transfer syntax.
+    explicit: bool,
+    past_meta: bool,
+    syntax: Vec<u8>,
+    found: Vec<((u16, u16), Range<usize>)>,
+}
+
+impl Dicom<'_> {
+    fn damaged() -> String {
+        "The DICOM file is damaged.".to_string()
+    }
+
+    fn u16_at(&self, at: usize) -> Result<u16, String> {
+        let bytes = self.data.get(at..at + 2).ok_or_else(Self::damaged)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32_at(&self, at: usize) -> Result<usize, String> {
+        let bytes = self.data.get(at..at + 4).ok_or_else(Self::damaged)?;
+        Ok(u32::from_le_bytes(bytes.try_into().expect("four bytes")) as usize)
+    }
+
+    fn tag_at(&self, at: usize) -> Result<(u16, u16), String> {
+        Ok((self.u16_at(at)?, self.u16_at(at + 2)?))
+    }
+
+    /// Walk the elements from `at` up to `end`, or, `in_item` of undefined
+    /// length, up to its delimiter. Returns where the walk stopped.
+    fn elements(&mut self, mut at: usize, end: usize, in_item: bool) -> Result<usize, String> {
+        while at < end {
+            let tag = self.tag_at(at)?;
+            if tag == ITEM_DELIMITER && in_item {
+                return Ok(at + 8);
+            }
+            if tag.0 != 0x0002 && !self.past_meta {
+                self.past_meta = true;
+                let syntax = String::from_utf8_lossy(&self.syntax);
+                match syntax.trim_end_matches(['\0', ' ']) {
+                    "1.2.840.10008.1.2" => self.explicit = false,
+                    "1.2.840.10008.1.2.2" | "1.2.840.10008.1.2.1.99" => {
+                        return Err(
+                            "The DICOM file is big-endian or deflated, which --dicom-tag cannot read.".to_string(),
+                        );
+                    }
+                    _ => {}
+                }
+            }
+            let (vr, len, value_at) = if self.explicit || tag.0 == 0x0002 {
+                let vr = self.data.get(at + 4..at + 6).ok_or_else(Self::damaged)?;
+                let long = matches!(
+                    vr,
+                    b"OB" | b"OD" | b"OF" | b"OL" | b"OV" | b"OW" | b"SQ" | b"SV" | b"UC" | b"UN" | b"UR" | b"UT" | b"UV"
+                );
+                if long {
+                    (Some(vr), self.u32_at(at + 8)?, at + 12)
+                } else {
+                    (Some(vr), self.u16_at(at + 6)? as usize, at + 8)
+                }
+            } else {
+                (None, self.u32_at(at + 4)?, at + 8)
+            };
+            if len == UNDEFINED_LENGTH {
+                // A sequence, or pixel data split into fragments, which are
+                // not elements.
+                at = self.items(value_at, end, tag != PIXEL_DATA)?;
+                continue;
+            }
+            let value_end = value_at.checked_add(len).filter(|&value_end| value_end <= end).ok_or_else(Self::damaged)?;
+            if self.wanted.contains(&tag) {
+                self.found.push((tag, value_at..value_end));
+            }
+            if tag == TRANSFER_SYNTAX {
+                self.syntax = self.data[value_at..value_end].to_vec();
+            }
+            if vr == Some(b"SQ") {
+                self.items(value_at, value_end, true)?;
+            }
+            at = value_end;
+        }
+        Ok(at)
+    }
+
+    /// Walk the items of a sequence from `at` up to `end` or its
+    /// delimiter, looking inside them when they hold `elements`. Returns
+    /// where the sequence ended.
+    fn items(&mut self, mut at: usize, end: usize, elements: bool) -> Result<usize, String> {
+        while at < end {
+            let (tag, len) = (self.tag_at(at)?, self.u32_at(at + 4)?);
+            at += 8;
+            match tag {
+                SEQUENCE_DELIMITER => return Ok(at),
+                ITEM if len == UNDEFINED_LENGTH => at = self.elements(at, end, true)?,
+                ITEM => {
+                    let item_end = at.checked_add(len).filter(|&item_end| item_end <= end).ok_or_else(Self::damaged)?;
+                    if elements {
+                        self.elements(at, item_end, false)?;
+                    }
+                    at = item_end;
+                }
+                _ => return Err(Self::damaged()),
+            }
+        }
+        Ok(at)
+    }
+}
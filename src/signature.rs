@@ -0,0 +1,282 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::checksum::to_hex;
+use crate::ed25519::{self, KEY_LEN, SIGNATURE_LEN};
+use crate::throttle;
+
+/// Bytes at the start of a decrypted private key file.
+const KEY_MAGIC: &[u8; 4] = b"FSKY";
+
+/// First line of a public key file.
+const PUBLIC_KEY_HEADER: &str = "# File Encryptor Ed25519 public key";
+
+/// First line of a signature file.
+const SIGNATURE_HEADER: &str = "File Encryptor detached signature";
+
+/// First line of a revocation file.
+const REVOCATION_HEADER: &str = "File Encryptor key revocation";
+
+/// Extension of the signature file written next to each signed file.
+pub const SIGNATURE_EXTENSION: &str = "sig";
+
+/// Create a signing key pair: `NAME.key`, the private key encrypted with
+/// `password`, and `NAME.pub`, the public key to give to whoever checks
+/// the signatures. Neither file may exist yet. Returns the public key.
+pub fn generate(name: &str, password: &str) -> Result<[u8; KEY_LEN], String> {
+    if password.is_empty() {
+        return Err("Password cannot be empty.".to_string());
+    }
+    let mut seed = [0u8; KEY_LEN];
+    crate::crypto::random_bytes(&mut seed)?;
+    let public = ed25519::public_key(&seed);
+
+    let mut private = KEY_MAGIC.to_vec();
+    private.extend_from_slice(&seed);
+    let private = crate::encrypt_bytes(&private, &[], password.as_bytes())?;
+    let public_text = format!("{}\n{}\n", PUBLIC_KEY_HEADER, to_hex(&public));
+
+    let private_path = format!("{}.key", name);
+    let public_path = format!("{}.pub", name);
+    write_new(&private_path, &private, true)?;
+    if let Err(e) = write_new(&public_path, public_text.as_bytes(), false) {
+        let _ = fs::remove_file(&private_path);
+        return Err(e);
+    }
+    Ok(public)
+}
+
+/// Decrypt the private key file at `path`.
+pub fn load_private_key(path: &str, password: &str) -> Result<[u8; KEY_LEN], String> {
+    let data = fs::read(path).map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+    let decrypted = crate::decrypt_bytes(&data, password.as_bytes(), false)?;
+    decrypted
+        .plaintext
+        .strip_prefix(KEY_MAGIC)
+        .and_then(|seed| seed.try_into().ok())
+        .ok_or_else(|| format!("'{}' is not a signing key.", path))
+}
+
+/// Read a public key given either as 64 hex digits or as the path of a
+/// public key file.
+pub fn load_public_key(text: &str) -> Result<[u8; KEY_LEN], String> {
+    if let Some(key) = parse_key(text) {
+        return Ok(key);
+    }
+    let contents =
+        fs::read_to_string(text).map_err(|e| format!("Failed to read '{}': {}", text, e))?;
+    contents
+        .lines()
+        .map(str::trim)
+        .find(|line| !line.is_empty() && !line.starts_with('#'))
+        .and_then(parse_key)
+        .ok_or_else(|| format!("'{}' is not a public key.", text))
+}
+
+/// A short fingerprint of `key` for people to compare out of band, such as
+/// over the phone: the first 80 bits of its SHA-256, as five groups of
+/// four hex digits.
+pub fn fingerprint(key: &[u8; KEY_LEN]) -> String {
+    let digest = to_hex(&crate::checksum::sha256(key)[..10]).to_uppercase();
+    let groups: Vec<&str> = (0..digest.len()).step_by(4).map(|i| &digest[i..i + 4]).collect();
+    groups.join(" ")
+}
+
+/// Sign the file at `path`, writing the signature to `PATH.sig`.
+pub fn sign_file(path: &Path, seed: &[u8; KEY_LEN]) -> Result<PathBuf, String> {
+    sign_file_with(path, &ed25519::public_key(seed), |data| Ok(ed25519::sign(seed, data)))
+}
+
+/// Sign the file at `path` with the key `public`, having `sign` make the
+/// signature, e.g. through a key agent holding the private key.
+pub fn sign_file_with(
+    path: &Path,
+    public: &[u8; KEY_LEN],
+    sign: impl Fn(&[u8]) -> Result<[u8; SIGNATURE_LEN], String>,
+) -> Result<PathBuf, String> {
+    let data = throttle::read(path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    let signature = sign(&data)?;
+    let text = format!(
+        "{}\nAlgorithm: Ed25519\nPublic key: {}\nSignature: {}\n",
+        SIGNATURE_HEADER,
+        to_hex(public),
+        to_hex(&signature)
+    );
+    let sig_path = signature_path(path);
+    fs::write(&sig_path, text)
+        .map_err(|e| format!("Failed to write '{}': {}", sig_path.display(), e))?;
+    Ok(sig_path)
+}
+
+/// Write a revocation for the key with private `seed` to `path`, signed by
+/// that key so anyone can check it came from its owner. `reason` is kept
+/// on one line.
+pub fn write_revocation(seed: &[u8; KEY_LEN], reason: &str, path: &str) -> Result<(), String> {
+    let public = ed25519::public_key(seed);
+    let reason = reason.replace(['\r', '\n'], " ");
+    let signature = ed25519::sign(seed, &revocation_message(&public, &reason));
+    let text = format!(
+        "{}\nAlgorithm: Ed25519\nPublic key: {}\nReason: {}\nSignature: {}\n",
+        REVOCATION_HEADER,
+        to_hex(&public),
+        reason,
+        to_hex(&signature)
+    );
+    write_new(path, text.as_bytes(), false)
+}
+
+/// Read the revocation file at `path` and check its signature. Returns the
+/// revoked key and the reason given.
+pub fn read_revocation(path: &str) -> Result<([u8; KEY_LEN], String), String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read '{}': {}", path, e))?;
+    let not_revocation = || format!("'{}' is not a revocation file.", path);
+    let mut lines = text.lines().map(str::trim);
+    if lines.next() != Some(REVOCATION_HEADER) {
+        return Err(not_revocation());
+    }
+    let (mut public, mut reason, mut signature) = (None, String::new(), None);
+    for line in lines {
+        match line.split_once(':') {
+            Some(("Algorithm", value)) if value.trim() != "Ed25519" => return Err(not_revocation()),
+            Some(("Public key", value)) => public = parse_key(value.trim()),
+            Some(("Reason", value)) => reason = value.trim().to_string(),
+            Some(("Signature", value)) => {
+                signature = from_hex(value.trim()).and_then(|bytes| <[u8; SIGNATURE_LEN]>::try_from(bytes).ok())
+            }
+            _ => {}
+        }
+    }
+    let (Some(public), Some(signature)) = (public, signature) else {
+        return Err(not_revocation());
+    };
+    if !ed25519::verify(&public, &revocation_message(&public, &reason), &signature) {
+        return Err(format!("The signature in '{}' does not match; it was not made by the key's owner.", path));
+    }
+    Ok((public, reason))
+}
+
+/// What a revocation signs: its header, the key, and the reason.
+fn revocation_message(public: &[u8; KEY_LEN], reason: &str) -> Vec<u8> {
+    format!("{}\n{}\n{}\n", REVOCATION_HEADER, to_hex(public), reason).into_bytes()
+}
+
+/// The result of checking one file.
+pub enum Verdict {
+    Valid,
+    /// There is no `.sig` file next to it.
+    Unsigned,
+    /// The signature file names a different key than the one trusted.
+    OtherKey,
+    /// The signature does not match the file's contents.
+    Invalid,
+}
+
+/// Check the file at `path` against its `PATH.sig` and the trusted
+/// `public` key.
+pub fn verify_file(path: &Path, public: &[u8; KEY_LEN]) -> Result<Verdict, String> {
+    let sig_path = signature_path(path);
+    let text = match fs::read_to_string(&sig_path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Verdict::Unsigned),
+        Err(e) => return Err(format!("Failed to read '{}': {}", sig_path.display(), e)),
+    };
+    let (signer, signature) =
+        parse_signature(&text).ok_or_else(|| format!("'{}' is not a signature file.", sig_path.display()))?;
+    if signer != *public {
+        return Ok(Verdict::OtherKey);
+    }
+    let data = throttle::read(path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    Ok(if ed25519::verify(public, &data, &signature) {
+        Verdict::Valid
+    } else {
+        Verdict::Invalid
+    })
+}
+
+/// Every file to sign or check under `paths`: files as given, and the
+/// files inside folders, recursively and in name order. Signature files
+/// themselves are left out.
+pub fn collect_files(paths: &[String]) -> Result<Vec<PathBuf>, String> {
+    let mut files = Vec::new();
+    for path in paths {
+        collect(Path::new(path), &mut files)?;
+    }
+    Ok(files)
+}
+
+fn collect(path: &Path, files: &mut Vec<PathBuf>) -> Result<(), String> {
+    let info = fs::metadata(path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    if !info.is_dir() {
+        files.push(path.to_path_buf());
+        return Ok(());
+    }
+    let mut entries: Vec<PathBuf> = fs::read_dir(path)
+        .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    entries.sort();
+    for entry in entries {
+        let is_signature = entry.extension().is_some_and(|ext| ext == SIGNATURE_EXTENSION);
+        let is_symlink = fs::symlink_metadata(&entry).is_ok_and(|info| info.file_type().is_symlink());
+        if !is_signature && !is_symlink {
+            collect(&entry, files)?;
+        }
+    }
+    Ok(())
+}
+
+fn signature_path(path: &Path) -> PathBuf {
+    let mut sig_path = path.as_os_str().to_owned();
+    sig_path.push(".");
+    sig_path.push(SIGNATURE_EXTENSION);
+    PathBuf::from(sig_path)
+}
+
+fn parse_signature(text: &str) -> Option<([u8; KEY_LEN], [u8; SIGNATURE_LEN])> {
+    let mut lines = text.lines().map(str::trim);
+    if lines.next()? != SIGNATURE_HEADER {
+        return None;
+    }
+    let mut public = None;
+    let mut signature = None;
+    for line in lines {
+        match line.split_once(':') {
+            Some(("Algorithm", value)) if value.trim() != "Ed25519" => return None,
+            Some(("Public key", value)) => public = parse_key(value.trim()),
+            Some(("Signature", value)) => signature = from_hex(value.trim())?.try_into().ok(),
+            _ => {}
+        }
+    }
+    Some((public?, signature?))
+}
+
+fn parse_key(text: &str) -> Option<[u8; KEY_LEN]> {
+    from_hex(text)?.try_into().ok()
+}
+
+fn from_hex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(text.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn write_new(path: &str, data: &[u8], private: bool) -> Result<(), String> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    if private {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    #[cfg(not(unix))]
+    let _ = private;
+    options
+        .open(path)
+        .and_then(|mut file| file.write_all(data))
+        .map_err(|e| format!("Failed to create '{}': {}", path, e))
+}
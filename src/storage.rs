@@ -0,0 +1,145 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::paths;
+
+/// Where Android mounts the user's own shared storage ("primary" in a
+/// document ID).
+const PRIMARY_STORAGE: &str = "/storage/emulated/0";
+
+/// Turn a `content://` or `file://` URI into the path it names, for files
+/// handed over by Termux widgets, share menus, and file managers on
+/// Android. Returns `Ok(None)` for anything that is not a URI, so plain
+/// paths pass through untouched.
+pub fn resolve(text: &str) -> Result<Option<String>, String> {
+    if let Some(rest) = text.strip_prefix("file://") {
+        // `file:///sdcard/a` or `file://localhost/sdcard/a`.
+        let path = rest.strip_prefix("localhost").unwrap_or(rest);
+        return Ok(Some(paths::percent_decode(path)));
+    }
+    let Some(rest) = text.strip_prefix("content://") else {
+        return Ok(None);
+    };
+    let unsupported = || {
+        format!(
+            "'{}' does not name a file this tool can open. Share it to Termux instead (it is saved under ~/downloads), or run termux-setup-storage and give the path under ~/storage.",
+            text
+        )
+    };
+    let (authority, rest) = rest.split_once('/').ok_or_else(unsupported)?;
+    // A document picked inside a granted folder is
+    // `tree/<folder>/document/<file>`; the file is what was meant.
+    let id = match rest.rsplit_once("document/") {
+        Some((_, id)) => id,
+        None => rest.strip_prefix("tree/").ok_or_else(unsupported)?,
+    };
+    let id = paths::percent_decode(id.split(['?', '#']).next().unwrap_or(id));
+    match authority {
+        // `primary:Documents/a.txt` or, on an SD card, `1A2B-3C4D:a.txt`.
+        "com.android.externalstorage.documents" => {
+            let (volume, relative) = id.split_once(':').ok_or_else(unsupported)?;
+            let root = match volume {
+                "primary" => PRIMARY_STORAGE.to_string(),
+                _ => format!("/storage/{}", volume),
+            };
+            Ok(Some(match relative {
+                "" => root,
+                _ => format!("{}/{}", root, relative),
+            }))
+        }
+        // Only downloads that expose their path; the rest are numbered.
+        "com.android.providers.downloads.documents" => match id.strip_prefix("raw:") {
+            Some(path) => Ok(Some(path.to_string())),
+            None => Err(unsupported()),
+        },
+        // Termux names its own files by their full path.
+        "com.termux.documents" if id.starts_with('/') => Ok(Some(id)),
+        _ => Err(unsupported()),
+    }
+}
+
+/// Whether the file `info` was read from belongs to the user running the
+/// tool, going by the effective user ID as the kernel does for access.
+#[cfg(unix)]
+pub fn owned_by_user(info: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    unsafe extern "C" {
+        fn geteuid() -> u32;
+    }
+
+    // SAFETY: geteuid has no preconditions and cannot fail.
+    info.uid() == unsafe { geteuid() }
+}
+
+/// Move `from` to `to`, replacing `to`. A rename is tried first; where that
+/// is refused, as between Termux's home and Android's shared storage
+/// (different mounts) or on storage that does not support renaming, the
+/// file is copied next to `to`, read back and compared with the original,
+/// and only then put in place and `from` removed. A copy that does not
+/// match is deleted and reported, and `from` is kept.
+pub fn rename(from: impl AsRef<Path>, to: impl AsRef<Path>) -> io::Result<()> {
+    let (from, to) = (from.as_ref(), to.as_ref());
+    if fs::rename(from, to).is_ok() {
+        return Ok(());
+    }
+    // Next to `to`, so putting it in place is a rename within one folder.
+    let copy = paths::with_suffix(to, ".copying");
+    let result = copy_verified(from, &copy).and_then(|_| match fs::rename(&copy, to) {
+        Ok(()) => Ok(()),
+        // Storage that cannot rename at all gets written over in place.
+        Err(_) => copy_verified(&copy, to),
+    });
+    let _ = fs::remove_file(&copy);
+    result?;
+    fs::remove_file(from)
+}
+
+/// Copy `from` to `to`, flush it to storage, and fail unless reading `to`
+/// back gives exactly what `from` holds.
+fn copy_verified(from: &Path, to: &Path) -> io::Result<()> {
+    // Written without copying permissions, which shared storage refuses.
+    let mut target = File::create(to)?;
+    io::copy(&mut File::open(from)?, &mut target)?;
+    target.sync_all()?;
+    drop(target);
+    if !same_contents(from, to)? {
+        let _ = fs::remove_file(to);
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("the copy at '{}' does not match the original", to.display()),
+        ));
+    }
+    Ok(())
+}
+
+fn same_contents(a: &Path, b: &Path) -> io::Result<bool> {
+    let mut a = File::open(a)?;
+    let mut b = File::open(b)?;
+    let mut chunk_a = vec![0u8; 64 * 1024];
+    let mut chunk_b = vec![0u8; 64 * 1024];
+    loop {
+        let read = read_full(&mut a, &mut chunk_a)?;
+        if read_full(&mut b, &mut chunk_b)? != read || chunk_a[..read] != chunk_b[..read] {
+            return Ok(false);
+        }
+        if read == 0 {
+            return Ok(true);
+        }
+    }
+}
+
+/// Fill `buffer` as far as the input allows and return how much was read.
+fn read_full(input: &mut impl Read, buffer: &mut [u8]) -> io::Result<usize> {
+    let mut filled = 0;
+    while filled < buffer.len() {
+        match input.read(&mut buffer[filled..]) {
+            Ok(0) => break,
+            Ok(read) => filled += read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(filled)
+}
@@ -0,0 +1,994 @@
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::archive;
+use crate::checksum::{self, SHA256_LEN};
+use crate::crypto::{self, KEY_LEN, NONCE_LEN, TAG_LEN};
+use crate::format;
+use crate::salvage::{Gap, GapMap};
+use crate::storage;
+use crate::throttle;
+
+/// Bytes at the start of the decrypted key file.
+const KEY_MAGIC: &[u8; 4] = b"FKEY";
+
+/// Bytes at the start of a decrypted snapshot.
+const SNAPSHOT_MAGIC: &[u8; 4] = b"FSNP";
+
+/// Layout of a repository folder.
+const KEY_FILE: &str = "key";
+const LOCK_FILE: &str = "lock";
+const CHUNKS_DIR: &str = "chunks";
+const SNAPSHOTS_DIR: &str = "snapshots";
+
+/// Chunks are never cut shorter than this, except at the end of a file.
+const MIN_CHUNK_LEN: usize = 64 * 1024;
+
+/// Chunks are always cut at this length, even without a boundary.
+const MAX_CHUNK_LEN: usize = 1024 * 1024;
+
+/// A boundary falls where the top bits of the rolling hash are all zero.
+/// 18 bits puts one every 256 KiB on average after the minimum.
+const BOUNDARY_BITS: u32 = 18;
+
+/// Record tags inside a snapshot. Each file is a path record followed by
+/// its size and its chunk list.
+const TAG_TIME: u8 = 1;
+const TAG_SOURCE: u8 = 2;
+const TAG_PATH: u8 = 3;
+const TAG_SIZE: u8 = 4;
+const TAG_CHUNKS: u8 = 5;
+
+/// Identifies a chunk: a keyed hash of its plaintext, so equal chunks get
+/// equal ids without the ids revealing anything to someone without the
+/// password.
+pub type ChunkId = [u8; SHA256_LEN];
+
+/// Keys stored, encrypted with the password, in the repository's key file.
+/// Chunks use these instead of the password directly, so each one does not
+/// need its own slow key derivation.
+struct Keys {
+    cipher: [u8; KEY_LEN],
+    mac: [u8; KEY_LEN],
+    id: [u8; KEY_LEN],
+}
+
+/// A folder of encrypted, deduplicated chunks plus the snapshots that list
+/// which chunks make up each backed up file.
+pub struct Repository {
+    root: PathBuf,
+    keys: Keys,
+    gear: [u64; 256],
+}
+
+/// One backup run: every file under the source, as it was at `time`.
+pub struct Snapshot {
+    pub id: String,
+    pub time: u64,
+    pub source: String,
+    pub files: Vec<FileRecipe>,
+}
+
+/// How to rebuild one file: its path relative to the source and its
+/// chunks in order.
+pub struct FileRecipe {
+    pub path: Vec<u8>,
+    pub size: u64,
+    pub chunks: Vec<ChunkId>,
+}
+
+/// What a backup stored.
+#[derive(Default)]
+pub struct BackupSummary {
+    pub snapshot_id: String,
+    pub files: usize,
+    pub bytes: u64,
+    pub chunks: usize,
+    /// Chunks that were not in the repository yet, and their size before
+    /// encryption.
+    pub new_chunks: usize,
+    pub new_bytes: u64,
+    pub warnings: Vec<String>,
+}
+
+/// Which snapshots `prune` keeps. A snapshot is kept if either rule keeps
+/// it.
+pub struct Retention {
+    /// Keep this many of the newest snapshots. Always at least one.
+    pub keep_last: usize,
+    /// Keep every snapshot taken at or after this time (seconds since
+    /// 1970).
+    pub keep_since: Option<u64>,
+}
+
+/// What `prune` removed, or would remove in a dry run.
+#[derive(Default)]
+pub struct PruneReport {
+    /// Id and time of each snapshot removed, oldest first.
+    pub removed_snapshots: Vec<(String, u64)>,
+    pub kept_snapshots: usize,
+    /// Chunks no kept snapshot refers to.
+    pub removed_chunks: usize,
+    /// Space on disk freed by removing those snapshots and chunks.
+    pub reclaimed_bytes: u64,
+}
+
+/// How a file differs between two snapshots.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    Added,
+    Removed,
+    Modified,
+}
+
+/// Held while a backup or prune changes the repository, so a prune cannot
+/// delete chunks that a backup has written but not yet recorded in a
+/// snapshot. The lock file is removed when this is dropped.
+struct Lock {
+    path: PathBuf,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+impl Repository {
+    /// Create an empty repository at `root`, which must not exist yet or be
+    /// an empty folder.
+    pub fn init(root: &Path, password: &str) -> Result<Self, String> {
+        if password.is_empty() {
+            return Err("Password cannot be empty.".to_string());
+        }
+        if root.exists()
+            && fs::read_dir(root)
+                .map_err(|e| format!("Failed to read '{}': {}", root.display(), e))?
+                .next()
+                .is_some()
+        {
+            return Err(format!("'{}' already exists and is not empty.", root.display()));
+        }
+
+        let mut secret = [0u8; 3 * KEY_LEN];
+        crypto::random_bytes(&mut secret)?;
+        for dir in [CHUNKS_DIR, SNAPSHOTS_DIR] {
+            let path = root.join(dir);
+            fs::create_dir_all(&path)
+                .map_err(|e| format!("Failed to create '{}': {}", path.display(), e))?;
+        }
+        let mut key_file = KEY_MAGIC.to_vec();
+        key_file.extend_from_slice(&secret);
+        let encrypted = crate::encrypt_bytes(&key_file, &[], password.as_bytes())?;
+        fs::write(root.join(KEY_FILE), encrypted)
+            .map_err(|e| format!("Failed to write the key file: {}", e))?;
+
+        Ok(Repository::with_secret(root, &secret))
+    }
+
+    /// Open the repository at `root`. Fails if the password is wrong.
+    pub fn open(root: &Path, password: &str) -> Result<Self, String> {
+        let data = fs::read(root.join(KEY_FILE)).map_err(|e| {
+            format!("'{}' is not a repository: {}", root.display(), e)
+        })?;
+        let decrypted = crate::decrypt_bytes(&data, password.as_bytes(), false)?;
+        let secret = decrypted
+            .plaintext
+            .strip_prefix(KEY_MAGIC)
+            .filter(|secret| secret.len() == 3 * KEY_LEN)
+            .ok_or_else(|| format!("'{}' does not hold a repository key.", root.display()))?;
+        Ok(Repository::with_secret(root, secret))
+    }
+
+    fn with_secret(root: &Path, secret: &[u8]) -> Self {
+        let mut keys = Keys {
+            cipher: [0; KEY_LEN],
+            mac: [0; KEY_LEN],
+            id: [0; KEY_LEN],
+        };
+        keys.cipher.copy_from_slice(&secret[..KEY_LEN]);
+        keys.mac.copy_from_slice(&secret[KEY_LEN..2 * KEY_LEN]);
+        keys.id.copy_from_slice(&secret[2 * KEY_LEN..]);
+        let gear = gear_table(&keys.id);
+        Repository {
+            root: root.to_path_buf(),
+            keys,
+            gear,
+        }
+    }
+
+    /// Save every file under `source` (or `source` itself, if it is a file)
+    /// as a new snapshot. Only chunks the repository does not already have
+    /// are encrypted and written.
+    pub fn backup(&self, source: &Path) -> Result<BackupSummary, String> {
+        let _lock = self.lock()?;
+        let info = fs::metadata(source)
+            .map_err(|e| format!("Failed to read '{}': {}", source.display(), e))?;
+        let mut summary = BackupSummary::default();
+        let mut files = Vec::new();
+        if info.is_dir() {
+            self.collect(source, Path::new(""), &mut files, &mut summary)?;
+        } else {
+            let name = source.file_name().map(Path::new).unwrap_or(source);
+            files.push(self.store_file(source, name, &mut summary)?);
+        }
+
+        let mut id = [0u8; 4];
+        crypto::random_bytes(&mut id)?;
+        let snapshot = Snapshot {
+            id: checksum::to_hex(&id),
+            time: now(),
+            source: source.display().to_string(),
+            files,
+        };
+        self.write_snapshot(&snapshot)?;
+        summary.snapshot_id = snapshot.id;
+        Ok(summary)
+    }
+
+    /// Add the files in the folder at `relative` (under `root`), in name
+    /// order. Symlinks and special files are skipped with a warning.
+    fn collect(
+        &self,
+        root: &Path,
+        relative: &Path,
+        files: &mut Vec<FileRecipe>,
+        summary: &mut BackupSummary,
+    ) -> Result<(), String> {
+        let folder = root.join(relative);
+        let mut entries: Vec<_> = fs::read_dir(&folder)
+            .map_err(|e| format!("Failed to read '{}': {}", folder.display(), e))?
+            .filter_map(|entry| entry.ok())
+            .collect();
+        entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in entries {
+            let child = relative.join(entry.file_name());
+            let full = root.join(&child);
+            let info = fs::symlink_metadata(&full)
+                .map_err(|e| format!("Failed to read '{}': {}", full.display(), e))?;
+            if info.is_dir() {
+                self.collect(root, &child, files, summary)?;
+            } else if info.is_file() {
+                files.push(self.store_file(&full, &child, summary)?);
+            } else {
+                summary.warnings.push(format!(
+                    "Skipped '{}': not a regular file or folder.",
+                    full.display()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Split one file into chunks and store the ones that are new.
+    fn store_file(
+        &self,
+        path: &Path,
+        relative: &Path,
+        summary: &mut BackupSummary,
+    ) -> Result<FileRecipe, String> {
+        let (contents, changed) = throttle::read_consistent(path)
+            .map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+        if changed {
+            summary
+                .warnings
+                .push(crate::input_changed_warning(&path.display().to_string()));
+        }
+        let mut chunks = Vec::new();
+        let mut rest = contents.as_slice();
+        while !rest.is_empty() {
+            let (chunk, tail) = rest.split_at(self.cut_point(rest));
+            let id = crypto::hmac_sha256(&self.keys.id, chunk);
+            if self.write_chunk(&id, chunk)? {
+                summary.new_chunks += 1;
+                summary.new_bytes += chunk.len() as u64;
+            }
+            summary.chunks += 1;
+            chunks.push(id);
+            rest = tail;
+        }
+        summary.files += 1;
+        summary.bytes += contents.len() as u64;
+        Ok(FileRecipe {
+            path: archive::path_bytes(relative)?,
+            size: contents.len() as u64,
+            chunks,
+        })
+    }
+
+    /// Length of the next chunk of `data`. A gear hash rolls over the bytes
+    /// and the chunk ends where it hits a boundary, so an insertion early
+    /// in a file only changes the chunks around it, not every later one.
+    fn cut_point(&self, data: &[u8]) -> usize {
+        if data.len() <= MIN_CHUNK_LEN {
+            return data.len();
+        }
+        let end = data.len().min(MAX_CHUNK_LEN);
+        let mut hash = 0u64;
+        for (index, &byte) in data[..end].iter().enumerate().skip(MIN_CHUNK_LEN) {
+            hash = (hash << 1).wrapping_add(self.gear[byte as usize]);
+            if hash >> (64 - BOUNDARY_BITS) == 0 {
+                return index + 1;
+            }
+        }
+        end
+    }
+
+    /// Encrypt and write a chunk unless the repository already has it.
+    /// Returns whether it was new.
+    fn write_chunk(&self, id: &ChunkId, data: &[u8]) -> Result<bool, String> {
+        let path = self.chunk_path(id);
+        if path.exists() {
+            return Ok(false);
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create '{}': {}", parent.display(), e))?;
+        }
+        write_atomically(&path, &self.seal(data)?)?;
+        Ok(true)
+    }
+
+    /// Decrypt a chunk and check that it is the one `id` names.
+    fn read_chunk(&self, id: &ChunkId) -> Result<Vec<u8>, String> {
+        let path = self.chunk_path(id);
+        let data = throttle::read(&path)
+            .map_err(|e| format!("Failed to read chunk {}: {}", checksum::to_hex(id), e))?;
+        let plaintext = self.open_sealed(&data).ok_or_else(|| {
+            format!("Chunk {} is damaged.", checksum::to_hex(id))
+        })?;
+        if !crypto::constant_time_eq(&crypto::hmac_sha256(&self.keys.id, &plaintext), id) {
+            return Err(format!("Chunk {} is damaged.", checksum::to_hex(id)));
+        }
+        Ok(plaintext)
+    }
+
+    /// The contents of `file`, restored to `target`, failing at the first
+    /// chunk that cannot be read.
+    fn rebuild_file(&self, file: &FileRecipe, target: &Path) -> Result<Vec<u8>, String> {
+        let size = usize::try_from(file.size).map_err(|_| format::message(format::FormatError::TooLarge(file.size)))?;
+        let mut contents = Vec::with_capacity(size);
+        for (index, id) in file.chunks.iter().enumerate() {
+            let chunk = self.read_chunk(id).map_err(|e| {
+                let end = self.chunk_len(id).map_or(String::new(), |len| (contents.len() + len).to_string());
+                format!(
+                    "'{}': chunk {} of {}, bytes {}-{}: {} Add --salvage to restore the rest.",
+                    target.display(),
+                    index + 1,
+                    file.chunks.len(),
+                    contents.len(),
+                    end,
+                    e
+                )
+            })?;
+            contents.extend_from_slice(&chunk);
+        }
+        if contents.len() as u64 != file.size {
+            return Err(format!(
+                "'{}' came out the wrong size; the snapshot is damaged.",
+                target.display()
+            ));
+        }
+        Ok(contents)
+    }
+
+    /// The contents of `file` with every chunk that cannot be read written
+    /// as zeros and added to `gaps`. A missing chunk's length is worked
+    /// out from the file's size when it is the only one unknown; past a
+    /// chunk whose length cannot be told, nothing can be placed.
+    fn salvage_file(&self, file: &FileRecipe, target: &Path, gaps: &mut GapMap) -> Result<Vec<u8>, String> {
+        let chunks: Vec<_> = file
+            .chunks
+            .iter()
+            .map(|id| self.read_chunk(id).map_err(|e| (e, self.chunk_len(id))))
+            .collect();
+        let lens: Vec<Option<usize>> = chunks
+            .iter()
+            .map(|chunk| match chunk {
+                Ok(data) => Some(data.len()),
+                Err((_, len)) => *len,
+            })
+            .collect();
+        let known: u64 = lens.iter().flatten().map(|&len| len as u64).sum();
+        let unknown = lens.iter().filter(|len| len.is_none()).count();
+        let inferred = (unknown == 1 && known <= file.size).then(|| (file.size - known) as usize);
+
+        let mut contents = Vec::new();
+        let mut gap = |start: usize, end: Option<usize>, reason: String| {
+            gaps.add(Gap {
+                file: Some(target.to_path_buf()),
+                start: start as u64,
+                end: end.map(|end| end as u64),
+                reason,
+            })
+        };
+        for (chunk, len) in chunks.into_iter().zip(lens) {
+            match (chunk, len.or(inferred)) {
+                (Ok(data), _) => contents.extend_from_slice(&data),
+                (Err((e, _)), Some(len)) => {
+                    gap(contents.len(), Some(contents.len() + len), e);
+                    contents.resize(contents.len() + len, 0);
+                }
+                (Err((e, _)), None) => {
+                    gap(contents.len(), None, format!("{} Its length is unknown, so the rest cannot be placed.", e));
+                    return Ok(contents);
+                }
+            }
+        }
+        if contents.len() as u64 != file.size {
+            gap(
+                contents.len().min(file.size as usize),
+                None,
+                format!("The file came out {} bytes instead of {}; the snapshot is damaged.", contents.len(), file.size),
+            );
+        }
+        Ok(contents)
+    }
+
+    /// Plaintext length of the stored chunk `id`, going by the size of its
+    /// file, or `None` if it is missing.
+    fn chunk_len(&self, id: &ChunkId) -> Option<usize> {
+        let len = fs::metadata(self.chunk_path(id)).ok()?.len() as usize;
+        len.checked_sub(NONCE_LEN + TAG_LEN)
+    }
+
+    /// Chunks are spread over 256 folders by the first byte of their id,
+    /// so no single folder grows huge.
+    fn chunk_path(&self, id: &ChunkId) -> PathBuf {
+        let hex = checksum::to_hex(id);
+        self.root.join(CHUNKS_DIR).join(&hex[..2]).join(hex)
+    }
+
+    /// Every snapshot in the repository, oldest first.
+    pub fn snapshots(&self) -> Result<Vec<Snapshot>, String> {
+        let folder = self.root.join(SNAPSHOTS_DIR);
+        let entries = fs::read_dir(&folder)
+            .map_err(|e| format!("Failed to read '{}': {}", folder.display(), e))?;
+        let mut snapshots = Vec::new();
+        for entry in entries.filter_map(|entry| entry.ok()) {
+            let id = entry.file_name().to_string_lossy().into_owned();
+            // Skip leftovers of an interrupted write, such as `id.saving`.
+            if id.contains('.') {
+                continue;
+            }
+            snapshots.push(self.read_snapshot(&id)?);
+        }
+        snapshots.sort_by(|a, b| a.time.cmp(&b.time).then_with(|| a.id.cmp(&b.id)));
+        Ok(snapshots)
+    }
+
+    /// The snapshot with the given id, or the newest one for `latest`.
+    pub fn find_snapshot(&self, id: &str) -> Result<Snapshot, String> {
+        if id == "latest" {
+            return self
+                .snapshots()?
+                .pop()
+                .ok_or_else(|| "The repository has no snapshots yet.".to_string());
+        }
+        let is_id = !id.is_empty() && id.chars().all(|c| c.is_ascii_hexdigit());
+        if !is_id || !self.root.join(SNAPSHOTS_DIR).join(id).is_file() {
+            return Err(format!("There is no snapshot '{}'.", id));
+        }
+        self.read_snapshot(id)
+    }
+
+    /// Rebuild every file of a snapshot inside `dest`, which must not exist
+    /// yet or be an empty folder. Returns the number of files written.
+    ///
+    /// A damaged or missing chunk is an error naming where in which file it
+    /// belongs, unless `gaps` is given: the chunk is then written as zeros
+    /// and recorded there, so the rest of the backup is still rescued.
+    pub fn restore(&self, snapshot: &Snapshot, dest: &Path, mut gaps: Option<&mut GapMap>) -> Result<usize, String> {
+        if dest.exists()
+            && fs::read_dir(dest)
+                .map_err(|e| format!("Failed to read '{}': {}", dest.display(), e))?
+                .next()
+                .is_some()
+        {
+            return Err(format!("'{}' already exists and is not empty.", dest.display()));
+        }
+
+        for file in &snapshot.files {
+            let target = dest.join(archive::safe_relative_path(&file.path)?);
+            if let Some(parent) = target.parent() {
+                fs::create_dir_all(parent)
+                    .map_err(|e| format!("Failed to create '{}': {}", parent.display(), e))?;
+            }
+            let contents = match gaps.as_deref_mut() {
+                Some(gaps) => self.salvage_file(file, &target, gaps)?,
+                None => self.rebuild_file(file, &target)?,
+            };
+            throttle::write(&target, &contents)
+                .map_err(|e| format!("Failed to write '{}': {}", target.display(), e))?;
+        }
+        Ok(snapshot.files.len())
+    }
+
+    /// Remove the snapshots `retention` does not keep, then delete every
+    /// chunk the remaining snapshots do not use. With `dry_run`, only
+    /// report what would be removed.
+    pub fn prune(&self, retention: &Retention, dry_run: bool) -> Result<PruneReport, String> {
+        let _lock = self.lock()?;
+        let snapshots = self.snapshots()?;
+        let newest_kept = snapshots.len().saturating_sub(retention.keep_last.max(1));
+        let (removed, kept): (Vec<_>, Vec<_>) =
+            snapshots.into_iter().enumerate().partition(|(index, snapshot)| {
+                *index < newest_kept
+                    && retention.keep_since.is_none_or(|since| snapshot.time < since)
+            });
+
+        let mut report = PruneReport {
+            kept_snapshots: kept.len(),
+            ..PruneReport::default()
+        };
+        // Snapshots go first, so an interrupted prune never leaves a
+        // snapshot pointing at a deleted chunk.
+        for (_, snapshot) in removed {
+            let path = self.root.join(SNAPSHOTS_DIR).join(&snapshot.id);
+            report.reclaimed_bytes += file_len(&path);
+            if !dry_run {
+                fs::remove_file(&path)
+                    .map_err(|e| format!("Failed to remove snapshot '{}': {}", snapshot.id, e))?;
+            }
+            report.removed_snapshots.push((snapshot.id, snapshot.time));
+        }
+
+        let used: HashSet<String> = kept
+            .iter()
+            .flat_map(|(_, snapshot)| &snapshot.files)
+            .flat_map(|file| &file.chunks)
+            .map(|id| checksum::to_hex(id))
+            .collect();
+        for path in self.chunk_files()? {
+            let name = path.file_name().map(|name| name.to_string_lossy().into_owned());
+            if name.is_some_and(|name| used.contains(&name)) {
+                continue;
+            }
+            report.reclaimed_bytes += file_len(&path);
+            report.removed_chunks += 1;
+            if !dry_run {
+                fs::remove_file(&path)
+                    .map_err(|e| format!("Failed to remove '{}': {}", path.display(), e))?;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Every file in the chunk folders, including leftovers of interrupted
+    /// writes.
+    fn chunk_files(&self) -> Result<Vec<PathBuf>, String> {
+        let read = |folder: &Path| {
+            fs::read_dir(folder)
+                .map_err(|e| format!("Failed to read '{}': {}", folder.display(), e))
+        };
+        let mut files = Vec::new();
+        for bucket in read(&self.root.join(CHUNKS_DIR))?.filter_map(|entry| entry.ok()) {
+            for entry in read(&bucket.path())?.filter_map(|entry| entry.ok()) {
+                files.push(entry.path());
+            }
+        }
+        Ok(files)
+    }
+
+    /// Take the repository lock, or fail if another backup or prune holds
+    /// it.
+    fn lock(&self) -> Result<Lock, String> {
+        let path = self.root.join(LOCK_FILE);
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(_) => Ok(Lock { path }),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Err(format!(
+                "The repository is in use by another backup or prune. If none is running, delete '{}'.",
+                path.display()
+            )),
+            Err(e) => Err(format!("Failed to lock the repository: {}", e)),
+        }
+    }
+
+    fn write_snapshot(&self, snapshot: &Snapshot) -> Result<(), String> {
+        let mut plaintext = SNAPSHOT_MAGIC.to_vec();
+        format::push_record(&mut plaintext, TAG_TIME, &snapshot.time.to_le_bytes());
+        format::push_record(&mut plaintext, TAG_SOURCE, snapshot.source.as_bytes());
+        for file in &snapshot.files {
+            format::push_record(&mut plaintext, TAG_PATH, &file.path);
+            format::push_record(&mut plaintext, TAG_SIZE, &file.size.to_le_bytes());
+            format::push_record(&mut plaintext, TAG_CHUNKS, &file.chunks.concat());
+        }
+        let path = self.root.join(SNAPSHOTS_DIR).join(&snapshot.id);
+        write_atomically(&path, &self.seal(&plaintext)?)
+    }
+
+    fn read_snapshot(&self, id: &str) -> Result<Snapshot, String> {
+        let damaged = || format!("Snapshot '{}' is damaged.", id);
+        let path = self.root.join(SNAPSHOTS_DIR).join(id);
+        let data = fs::read(&path)
+            .map_err(|e| format!("Failed to read snapshot '{}': {}", id, e))?;
+        let plaintext = self.open_sealed(&data).ok_or_else(damaged)?;
+        let records = plaintext.strip_prefix(SNAPSHOT_MAGIC).ok_or_else(damaged)?;
+
+        let mut snapshot = Snapshot {
+            id: id.to_string(),
+            time: 0,
+            source: String::new(),
+            files: Vec::new(),
+        };
+        let mut records = format::read_records(records).map_err(format::message)?.into_iter();
+        while let Some((tag, body)) = records.next() {
+            match tag {
+                TAG_TIME => snapshot.time = read_u64(body).ok_or_else(damaged)?,
+                TAG_SOURCE => snapshot.source = String::from_utf8_lossy(body).into_owned(),
+                TAG_PATH => {
+                    let (Some((TAG_SIZE, size)), Some((TAG_CHUNKS, chunks))) =
+                        (records.next(), records.next())
+                    else {
+                        return Err(damaged());
+                    };
+                    if chunks.len() % SHA256_LEN != 0 {
+                        return Err(damaged());
+                    }
+                    snapshot.files.push(FileRecipe {
+                        path: body.to_vec(),
+                        size: read_u64(size).ok_or_else(damaged)?,
+                        chunks: chunks
+                            .chunks_exact(SHA256_LEN)
+                            .map(|id| id.try_into().expect("chunk ids are 32 bytes"))
+                            .collect(),
+                    });
+                }
+                _ => return Err(damaged()),
+            }
+        }
+        Ok(snapshot)
+    }
+
+    /// Encrypt repository data: a random nonce, the data encrypted with
+    /// ChaCha20, and an HMAC over both.
+    fn seal(&self, data: &[u8]) -> Result<Vec<u8>, String> {
+        let mut nonce = [0u8; NONCE_LEN];
+        crypto::random_bytes(&mut nonce)?;
+        let mut sealed = Vec::with_capacity(NONCE_LEN + data.len() + TAG_LEN);
+        sealed.extend_from_slice(&nonce);
+        sealed.extend_from_slice(data);
+        crypto::chacha20_xor(&self.keys.cipher, &nonce, 0, &mut sealed[NONCE_LEN..]);
+        let tag = crypto::hmac_sha256(&self.keys.mac, &sealed);
+        sealed.extend_from_slice(&tag);
+        Ok(sealed)
+    }
+
+    /// Reverse `seal`, or `None` if the tag does not match.
+    fn open_sealed(&self, data: &[u8]) -> Option<Vec<u8>> {
+        if data.len() < NONCE_LEN + TAG_LEN {
+            return None;
+        }
+        let (body, tag) = data.split_at(data.len() - TAG_LEN);
+        if !crypto::constant_time_eq(&crypto::hmac_sha256(&self.keys.mac, body), tag) {
+            return None;
+        }
+        let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+        let mut plaintext = ciphertext.to_vec();
+        crypto::chacha20_xor(
+            &self.keys.cipher,
+            nonce.try_into().expect("nonce length checked"),
+            0,
+            &mut plaintext,
+        );
+        Some(plaintext)
+    }
+}
+
+/// Compare two snapshots by their recipes, without reading any chunk.
+/// Chunk ids are keyed hashes of the contents, so a file whose size and
+/// chunk list match is unchanged. Returns the changed paths in order and
+/// the number of unchanged files.
+pub fn diff<'a>(old: &'a Snapshot, new: &'a Snapshot) -> (Vec<(Change, &'a [u8])>, usize) {
+    let recipes = |snapshot: &'a Snapshot| -> BTreeMap<&'a [u8], &'a FileRecipe> {
+        snapshot
+            .files
+            .iter()
+            .map(|file| (file.path.as_slice(), file))
+            .collect()
+    };
+    let (old_files, new_files) = (recipes(old), recipes(new));
+
+    let mut changes = Vec::new();
+    let mut unchanged = 0;
+    for (path, file) in &new_files {
+        match old_files.get(path) {
+            None => changes.push((Change::Added, *path)),
+            Some(before) if before.size != file.size || before.chunks != file.chunks => {
+                changes.push((Change::Modified, *path))
+            }
+            Some(_) => unchanged += 1,
+        }
+    }
+    for path in old_files.keys() {
+        if !new_files.contains_key(path) {
+            changes.push((Change::Removed, *path));
+        }
+    }
+    changes.sort_by_key(|(_, path)| *path);
+    (changes, unchanged)
+}
+
+/// The gear table for the rolling hash: 256 pseudo-random values derived
+/// from the repository's id key. Keying it keeps chunk boundaries, and
+/// therefore chunk sizes, from revealing which known files are stored.
+fn gear_table(id_key: &[u8; KEY_LEN]) -> [u64; 256] {
+    let seed = crypto::hmac_sha256(id_key, b"gear");
+    let mut state = u64::from_le_bytes(seed[..8].try_into().expect("8 bytes"));
+    let mut table = [0u64; 256];
+    for value in &mut table {
+        // SplitMix64.
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        *value = z ^ (z >> 31);
+    }
+    table
+}
+
+/// Write next to `path` and rename into place, so an interrupted backup
+/// never leaves a half-written chunk or snapshot under its real name.
+fn write_atomically(path: &Path, data: &[u8]) -> Result<(), String> {
+    let temp_path = path.with_extension("saving");
+    throttle::write(&temp_path, data)
+        .and_then(|_| storage::rename(&temp_path, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&temp_path);
+            format!("Failed to write '{}': {}", path.display(), e)
+        })
+}
+
+/// Size of a file on disk, or 0 if it cannot be read.
+fn file_len(path: &Path) -> u64 {
+    fs::metadata(path).map_or(0, |info| info.len())
+}
+
+fn read_u64(bytes: &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A new, empty folder for one test, removed by the test when it passes.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("file_encryptor_store_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A repository in `dir` with a fixed secret, so tests need no slow key
+    /// derivation.
+    fn repository(dir: &Path) -> Repository {
+        let root = dir.join("repo");
+        for sub in [CHUNKS_DIR, SNAPSHOTS_DIR] {
+            fs::create_dir_all(root.join(sub)).unwrap();
+        }
+        Repository::with_secret(&root, &[7; 3 * KEY_LEN])
+    }
+
+    /// Bytes that do not repeat, so chunking behaves as on real files.
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed * 2 + 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state as u8
+            })
+            .collect()
+    }
+
+    fn write_source(dir: &Path, large: &[u8]) -> PathBuf {
+        let source = dir.join("source");
+        fs::create_dir_all(source.join("nested/deeper")).unwrap();
+        fs::write(source.join("small.txt"), b"small").unwrap();
+        fs::write(source.join("empty"), b"").unwrap();
+        fs::write(source.join("nested/deeper/large.bin"), large).unwrap();
+        source
+    }
+
+    #[test]
+    fn init_and_open() {
+        let dir = test_dir("init");
+        let root = dir.join("repo");
+        assert!(Repository::init(&root, "").is_err());
+        let created = Repository::init(&root, "password").unwrap();
+        let opened = Repository::open(&root, "password").unwrap();
+        assert_eq!(created.keys.id, opened.keys.id);
+        assert!(Repository::open(&root, "wrong").is_err());
+        assert!(Repository::init(&root, "password").is_err());
+        assert!(Repository::open(&dir, "password").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn backup_and_restore_round_trip() {
+        let dir = test_dir("round_trip");
+        let repository = repository(&dir);
+        let large = noise(3 * MAX_CHUNK_LEN, 1);
+        let source = write_source(&dir, &large);
+
+        let first = repository.backup(&source).unwrap();
+        assert_eq!((first.files, first.bytes), (3, 5 + large.len() as u64));
+        assert!(first.chunks >= 4 && first.new_chunks == first.chunks);
+        let snapshot = repository.find_snapshot(&first.snapshot_id).unwrap();
+        let dest = dir.join("restored");
+        assert_eq!(repository.restore(&snapshot, &dest, None).unwrap(), 3);
+        assert_eq!(fs::read(dest.join("small.txt")).unwrap(), b"small");
+        assert_eq!(fs::read(dest.join("empty")).unwrap(), b"");
+        assert_eq!(fs::read(dest.join("nested/deeper/large.bin")).unwrap(), large);
+        // Restoring over files that are there already is refused.
+        assert!(repository.restore(&snapshot, &dest, None).is_err());
+
+        // Nothing changed, so nothing new is stored.
+        let second = repository.backup(&source).unwrap();
+        assert_eq!(second.new_chunks, 0);
+
+        // A byte inserted near the start changes only the chunks around it.
+        let mut edited = large.clone();
+        edited.insert(100, b'!');
+        fs::write(source.join("nested/deeper/large.bin"), &edited).unwrap();
+        let third = repository.backup(&source).unwrap();
+        assert!(third.new_chunks < third.chunks / 2, "{} of {} chunks new", third.new_chunks, third.chunks);
+        let edited_snapshot = repository.find_snapshot(&third.snapshot_id).unwrap();
+        let (changes, unchanged) = diff(&snapshot, &edited_snapshot);
+        assert_eq!(unchanged, 2);
+        assert!(changes == [(Change::Modified, b"nested/deeper/large.bin".as_slice())]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chunks_stay_within_bounds() {
+        let dir = test_dir("bounds");
+        let repository = repository(&dir);
+        let data = noise(4 * MAX_CHUNK_LEN, 2);
+        let mut rest = data.as_slice();
+        while !rest.is_empty() {
+            let len = repository.cut_point(rest);
+            assert!(len <= MAX_CHUNK_LEN);
+            assert!(len >= MIN_CHUNK_LEN || len == rest.len());
+            rest = &rest[len..];
+        }
+        // Data that never hits a boundary is still cut.
+        assert_eq!(repository.cut_point(&vec![0; 2 * MAX_CHUNK_LEN]), MAX_CHUNK_LEN);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn damaged_chunks_are_salvaged() {
+        let dir = test_dir("salvage");
+        let repository = repository(&dir);
+        let large = noise(3 * MAX_CHUNK_LEN, 3);
+        let source = write_source(&dir, &large);
+        let snapshot = repository.find_snapshot(&repository.backup(&source).unwrap().snapshot_id).unwrap();
+        let file = snapshot.files.iter().find(|file| file.path == b"nested/deeper/large.bin").unwrap();
+
+        // Damage the second chunk and delete the fourth.
+        let second = repository.chunk_path(&file.chunks[1]);
+        let mut bytes = fs::read(&second).unwrap();
+        bytes[NONCE_LEN + 10] ^= 1;
+        fs::write(&second, &bytes).unwrap();
+        fs::remove_file(repository.chunk_path(&file.chunks[3])).unwrap();
+
+        let error = repository.restore(&snapshot, &dir.join("strict"), None).unwrap_err();
+        assert!(error.contains("chunk 2 of") && error.contains("--salvage"), "{}", error);
+
+        let mut gaps = GapMap::default();
+        let dest = dir.join("salvaged");
+        repository.restore(&snapshot, &dest, Some(&mut gaps)).unwrap();
+        let restored = fs::read(dest.join("nested/deeper/large.bin")).unwrap();
+        assert_eq!(restored.len(), large.len());
+        assert_eq!(gaps.len(), 2);
+
+        // Everything outside the two gaps came back; the gaps are zeros.
+        let lens: Vec<usize> = file.chunks.iter().map(|id| repository.chunk_len(id).unwrap_or(0)).collect();
+        let second_at = lens[0];
+        let fourth_at = lens[..3].iter().sum::<usize>();
+        let fourth_len = large.len() - lens.iter().sum::<usize>();
+        for (start, end) in [(second_at, second_at + lens[1]), (fourth_at, fourth_at + fourth_len)] {
+            assert!(restored[start..end].iter().all(|&byte| byte == 0));
+        }
+        assert_eq!(restored[..second_at], large[..second_at]);
+        assert_eq!(restored[second_at + lens[1]..fourth_at], large[second_at + lens[1]..fourth_at]);
+        assert_eq!(restored[fourth_at + fourth_len..], large[fourth_at + fourth_len..]);
+        assert_eq!(gaps.bytes(), (lens[1] + fourth_len) as u64);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn hostile_snapshots_are_refused() {
+        let dir = test_dir("hostile");
+        let repository = repository(&dir);
+        let save = |id: &str, plaintext: &[u8]| {
+            let path = repository.root.join(SNAPSHOTS_DIR).join(id);
+            fs::write(path, repository.seal(plaintext).unwrap()).unwrap();
+        };
+        let file = |path: &[u8], size: u64, chunks: &[u8]| {
+            let mut plaintext = SNAPSHOT_MAGIC.to_vec();
+            format::push_record(&mut plaintext, TAG_TIME, &1u64.to_le_bytes());
+            format::push_record(&mut plaintext, TAG_PATH, path);
+            format::push_record(&mut plaintext, TAG_SIZE, &size.to_le_bytes());
+            format::push_record(&mut plaintext, TAG_CHUNKS, chunks);
+            plaintext
+        };
+
+        // Paths that would be written outside the destination.
+        for (id, path) in [("a1", &b"../escape"[..]), ("a2", b"/etc/passwd"), ("a3", b"")] {
+            save(id, &file(path, 0, &[]));
+            let snapshot = repository.find_snapshot(id).unwrap();
+            assert!(repository.restore(&snapshot, &dir.join(id), None).is_err(), "{}", id);
+            assert!(!dir.join("escape").exists());
+        }
+
+        save("b1", &file(b"name", 0, &[0; 33]));
+        save("b2", &b"FSNQ"[..]);
+        let mut missing_size = SNAPSHOT_MAGIC.to_vec();
+        format::push_record(&mut missing_size, TAG_PATH, b"name");
+        save("b3", &missing_size);
+        let mut unknown = SNAPSHOT_MAGIC.to_vec();
+        format::push_record(&mut unknown, 99, b"");
+        save("b4", &unknown);
+        for id in ["b1", "b2", "b3", "b4"] {
+            assert!(repository.find_snapshot(id).is_err(), "{}", id);
+        }
+
+        // A size that does not match the chunks.
+        save("c1", &file(b"name", 10, &[]));
+        let snapshot = repository.find_snapshot("c1").unwrap();
+        assert!(repository.restore(&snapshot, &dir.join("c1"), None).is_err());
+
+        // A snapshot sealed with other keys, or changed on disk.
+        let path = repository.root.join(SNAPSHOTS_DIR).join("c1");
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[NONCE_LEN] ^= 1;
+        fs::write(&path, &bytes).unwrap();
+        assert!(repository.find_snapshot("c1").is_err());
+        fs::write(&path, &bytes[..NONCE_LEN + TAG_LEN - 1]).unwrap();
+        assert!(repository.find_snapshot("c1").is_err());
+        assert!(repository.find_snapshot("../key").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn prune_keeps_what_is_used() {
+        let dir = test_dir("prune");
+        let repository = repository(&dir);
+        let source = dir.join("file.bin");
+        fs::write(&source, noise(MAX_CHUNK_LEN, 4)).unwrap();
+        // Dated long ago, so it is the older of two taken in one second.
+        let mut old = repository.find_snapshot(&repository.backup(&source).unwrap().snapshot_id).unwrap();
+        old.time = 1;
+        repository.write_snapshot(&old).unwrap();
+        fs::write(&source, noise(MAX_CHUNK_LEN, 5)).unwrap();
+        let kept = repository.backup(&source).unwrap();
+
+        let retention = Retention { keep_last: 1, keep_since: None };
+        let dry = repository.prune(&retention, true).unwrap();
+        assert_eq!(repository.snapshots().unwrap().len(), 2);
+        let report = repository.prune(&retention, false).unwrap();
+        assert_eq!((report.removed_snapshots.len(), report.kept_snapshots), (1, 1));
+        assert_eq!(report.removed_chunks, dry.removed_chunks);
+        assert!(report.removed_chunks > 0);
+
+        let snapshot = repository.find_snapshot("latest").unwrap();
+        assert_eq!(snapshot.id, kept.snapshot_id);
+        repository.restore(&snapshot, &dir.join("restored"), None).unwrap();
+        assert_eq!(fs::read(dir.join("restored/file.bin")).unwrap(), noise(MAX_CHUNK_LEN, 5));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
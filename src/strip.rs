@@ -0,0 +1,275 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether files are stripped of metadata before encryption
+/// (`--strip-metadata`).
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Strip every file encrypted in this run of the metadata its format
+/// carries, such as camera details, GPS position, or author.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// If `--strip-metadata` is on and `data` is a JPEG, PNG, WebP, or PDF
+/// file carrying metadata, return it without the metadata and the name of
+/// the format. Anything else, including files too damaged to walk safely,
+/// comes back unchanged with `None`.
+pub fn strip_if_enabled(data: Vec<u8>) -> (Vec<u8>, Option<&'static str>) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return (data, None);
+    }
+    let stripped = if data.starts_with(b"\xff\xd8") {
+        strip_jpeg(&data).map(|data| (data, "JPEG"))
+    } else if data.starts_with(PNG_SIGNATURE) {
+        strip_png(&data).map(|data| (data, "PNG"))
+    } else if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        strip_webp(&data).map(|data| (data, "WebP"))
+    } else if data.starts_with(b"%PDF-") {
+        strip_pdf(&data).map(|data| (data, "PDF"))
+    } else {
+        None
+    };
+    match stripped {
+        Some((data, kind)) => (data, Some(kind)),
+        None => (data, None),
+    }
+}
+
+/// Drop the JPEG segments that hold metadata: APP1 (Exif and XMP), APP12,
+/// APP13 (Photoshop and IPTC), comments, and APP2 apart from the ICC
+/// colour profile. Everything from the first scan on is copied as is.
+fn strip_jpeg(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..2]);
+    let mut pos = 2;
+    let mut removed = false;
+    loop {
+        if *data.get(pos)? != 0xff {
+            return None;
+        }
+        // Any number of 0xFF fill bytes may come before a marker.
+        let mut start = pos;
+        while *data.get(start + 1)? == 0xff {
+            start += 1;
+        }
+        let marker = data[start + 1];
+        match marker {
+            // Start of scan, after which compressed image data follows, or
+            // end of image.
+            0xda | 0xd9 => {
+                out.extend_from_slice(&data[pos..]);
+                break;
+            }
+            0x01 | 0xd0..=0xd7 => {
+                out.extend_from_slice(&data[pos..start + 2]);
+                pos = start + 2;
+            }
+            _ => {
+                let len = u16::from_be_bytes([*data.get(start + 2)?, *data.get(start + 3)?]) as usize;
+                if len < 2 {
+                    return None;
+                }
+                let segment = data.get(start..start + 2 + len)?;
+                let body = &segment[4..];
+                let metadata = match marker {
+                    0xe1 | 0xec | 0xed | 0xfe => true,
+                    0xe2 => !body.starts_with(b"ICC_PROFILE\0"),
+                    _ => false,
+                };
+                if metadata {
+                    removed = true;
+                } else {
+                    out.extend_from_slice(&data[pos..start + 2 + len]);
+                }
+                pos = start + 2 + len;
+            }
+        }
+    }
+    removed.then_some(out)
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// PNG chunks that hold metadata: text (often the author, software, or
+/// a description), Exif, and the time of last change.
+const PNG_METADATA_CHUNKS: [&[u8; 4]; 5] = [b"tEXt", b"zTXt", b"iTXt", b"eXIf", b"tIME"];
+
+/// Drop the metadata chunks. Each chunk carries its own CRC, so the rest
+/// are copied unchanged.
+fn strip_png(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(PNG_SIGNATURE);
+    let mut pos = PNG_SIGNATURE.len();
+    let mut removed = false;
+    loop {
+        let len = u32::from_be_bytes(data.get(pos..pos + 4)?.try_into().ok()?) as usize;
+        let kind = data.get(pos + 4..pos + 8)?;
+        // Length, type, contents, and CRC.
+        let end = pos.checked_add(12 + len)?;
+        let chunk = data.get(pos..end)?;
+        if PNG_METADATA_CHUNKS.iter().any(|metadata| kind == *metadata) {
+            removed = true;
+        } else {
+            out.extend_from_slice(chunk);
+        }
+        pos = end;
+        if kind == b"IEND" {
+            out.extend_from_slice(&data[pos..]);
+            break;
+        }
+    }
+    removed.then_some(out)
+}
+
+/// Flags in the first byte of a WebP VP8X chunk saying which metadata
+/// chunks follow.
+const WEBP_EXIF_FLAG: u8 = 0x08;
+const WEBP_XMP_FLAG: u8 = 0x04;
+
+/// Drop the EXIF and XMP chunks of a WebP file, clear the flags that
+/// announce them, and correct the RIFF size.
+fn strip_webp(data: &[u8]) -> Option<Vec<u8>> {
+    let riff_len = u32::from_le_bytes(data.get(4..8)?.try_into().ok()?) as usize;
+    let end = data.len().min(riff_len.checked_add(8)?);
+    let mut out = Vec::with_capacity(data.len());
+    out.extend_from_slice(&data[..12]);
+    let mut pos = 12;
+    let mut removed = false;
+    while pos < end {
+        let kind = data.get(pos..pos + 4)?;
+        let len = u32::from_le_bytes(data.get(pos + 4..pos + 8)?.try_into().ok()?) as usize;
+        // Chunks are padded to an even length.
+        let next = pos.checked_add(8 + len + len % 2)?.min(end);
+        let chunk = data.get(pos..next)?;
+        if kind == b"EXIF" || kind == b"XMP " {
+            removed = true;
+        } else {
+            let start = out.len();
+            out.extend_from_slice(chunk);
+            if kind == b"VP8X" && len > 0 {
+                out[start + 8] &= !(WEBP_EXIF_FLAG | WEBP_XMP_FLAG);
+            }
+        }
+        pos = next;
+    }
+    if !removed {
+        return None;
+    }
+    let riff_len = u32::try_from(out.len() - 8).ok()?;
+    out[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    out.extend_from_slice(&data[end..]);
+    Some(out)
+}
+
+/// Blank the strings of every document information dictionary (title,
+/// author, creator, producer, dates, and any custom entries) and every
+/// XMP packet. Bytes are overwritten with spaces rather than removed, so
+/// the offsets in the cross-reference table stay right.
+///
+/// Only what is stored uncompressed can be found this way; information
+/// kept inside compressed object streams is left alone.
+fn strip_pdf(data: &[u8]) -> Option<Vec<u8>> {
+    let mut out = data.to_vec();
+    let mut removed = false;
+
+    for pos in find_all(data, b"/Info") {
+        let Some((number, generation)) = parse_reference(&data[pos + 5..]) else {
+            continue;
+        };
+        let header = format!("{} {} obj", number, generation);
+        for start in find_all(data, header.as_bytes()) {
+            if start > 0 && data[start - 1].is_ascii_digit() {
+                continue;
+            }
+            let body = start + header.len();
+            let Some(len) = find(&data[body..], b"endobj") else {
+                continue;
+            };
+            removed |= blank_strings(&mut out[body..body + len]);
+        }
+    }
+
+    for start in find_all(data, b"<x:xmpmeta") {
+        let closing = b"</x:xmpmeta>";
+        if let Some(len) = find(&data[start..], closing) {
+            let packet = &mut out[start..start + len + closing.len()];
+            packet.fill(b' ');
+            removed = true;
+        }
+    }
+    removed.then_some(out)
+}
+
+/// Overwrite the contents of every literal `(...)` and hex `<...>` string
+/// in `object` with spaces. Returns whether any string had contents.
+fn blank_strings(object: &mut [u8]) -> bool {
+    let mut blanked = false;
+    let mut pos = 0;
+    while pos < object.len() {
+        match object[pos] {
+            b'<' if object.get(pos + 1) == Some(&b'<') => pos += 2,
+            b'<' => {
+                let start = pos + 1;
+                while pos < object.len() && object[pos] != b'>' {
+                    pos += 1;
+                }
+                blanked |= fill_spaces(&mut object[start..pos]);
+                pos += 1;
+            }
+            b'(' => {
+                let start = pos + 1;
+                let mut depth = 1;
+                pos += 1;
+                while pos < object.len() {
+                    match object[pos] {
+                        b'\\' => pos += 1,
+                        b'(' => depth += 1,
+                        b')' => {
+                            depth -= 1;
+                            if depth == 0 {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                    pos += 1;
+                }
+                let end = pos.min(object.len());
+                blanked |= fill_spaces(&mut object[start..end]);
+                pos += 1;
+            }
+            _ => pos += 1,
+        }
+    }
+    blanked
+}
+
+/// Overwrite `bytes` with spaces. Returns whether anything changed.
+fn fill_spaces(bytes: &mut [u8]) -> bool {
+    let changed = bytes.iter().any(|&byte| byte != b' ');
+    bytes.fill(b' ');
+    changed
+}
+
+/// Read an indirect reference such as ` 12 0 R`.
+fn parse_reference(text: &[u8]) -> Option<(u64, u64)> {
+    let text = String::from_utf8_lossy(&text[..text.len().min(32)]);
+    let mut words = text.split_ascii_whitespace();
+    let number = words.next()?.parse().ok()?;
+    let generation = words.next()?.parse().ok()?;
+    words.next()?.starts_with('R').then_some((number, generation))
+}
+
+/// Position of the first `needle` in `haystack`.
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Positions of every `needle` in `haystack`.
+fn find_all<'a>(haystack: &'a [u8], needle: &'a [u8]) -> impl Iterator<Item = usize> + 'a {
+    haystack
+        .windows(needle.len())
+        .enumerate()
+        .filter(move |(_, window)| *window == needle)
+        .map(|(pos, _)| pos)
+}
@@ -0,0 +1,387 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+#[cfg(target_os = "linux")]
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::buffers;
+use crate::progress;
+use crate::trace;
+use crate::tuning;
+
+/// Budget shared by every read and write, or `None` when there is no limit
+/// (the default).
+static BUCKET: Mutex<Option<Bucket>> = Mutex::new(None);
+
+/// Whether file contents bypass the operating system's page cache
+/// (`--direct-io`).
+static DIRECT: AtomicBool = AtomicBool::new(false);
+
+/// Whether file contents move through io_uring (`io_backend = io_uring`).
+#[cfg(target_os = "linux")]
+static URING: AtomicBool = AtomicBool::new(false);
+
+/// Whether any file read by `read_consistent` was still changing after
+/// the last attempt, for the exit status.
+static INPUT_CHANGED: AtomicBool = AtomicBool::new(false);
+
+/// Times `read_consistent` reads a file that changed while it was read.
+const CONSISTENT_READ_ATTEMPTS: usize = 3;
+
+/// Token bucket refilled at the allowed rate. It holds at most one piece,
+/// so time spent idle (for example waiting at the menu) does not turn
+/// into a burst later.
+struct Bucket {
+    bytes_per_second: f64,
+    available: f64,
+    last: Instant,
+}
+
+/// Limit reading and writing file contents to `bytes_per_second`, counted
+/// together, for the rest of the run (`--bwlimit`).
+pub fn init(bytes_per_second: u64) {
+    let mut bucket = BUCKET.lock().unwrap_or_else(|e| e.into_inner());
+    *bucket = Some(Bucket {
+        bytes_per_second: bytes_per_second as f64,
+        available: 0.0,
+        last: Instant::now(),
+    });
+}
+
+/// Read and write file contents without going through the page cache for
+/// the rest of the run (`--direct-io`), so encrypting a large backup image
+/// does not push other programs' data out of memory.
+pub fn enable_direct_io() {
+    DIRECT.store(true, Ordering::Relaxed);
+}
+
+/// Move file contents through io_uring for the rest of the run, keeping
+/// several pieces of each file in flight instead of waiting on one system
+/// call per piece. Fails, leaving plain I/O in place, if the kernel does
+/// not offer io_uring.
+#[cfg(target_os = "linux")]
+pub fn enable_io_uring() -> Result<(), String> {
+    crate::uring::probe()
+        .map_err(|e| format!("io_uring is not available, using plain I/O: {}", e))?;
+    URING.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn enable_io_uring() -> Result<(), String> {
+    Err("io_uring is only available on Linux, using plain I/O.".to_string())
+}
+
+/// Read a whole file, like `fs::read`, but no faster than the limit. Each
+/// piece is read straight into the returned buffer, so nothing is copied
+/// twice.
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let path = path.as_ref();
+    let data = if DIRECT.load(Ordering::Relaxed) {
+        read_direct(path)?
+    } else {
+        read_buffered(path)?
+    };
+    crate::guard::record(path, &data);
+    Ok(data)
+}
+
+fn read_buffered(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = crate::guard::open(path)?;
+    let mut data = vec![0u8; expected_len(&file)?];
+    let mut filled = 0;
+    #[cfg(target_os = "linux")]
+    if URING.load(Ordering::Relaxed) {
+        let span = trace::span("read");
+        filled = crate::uring::read(&file, &mut data, &wait)?;
+        drop(span.with("bytes", filled as u64));
+        progress::read(filled);
+        tuning::moved(filled);
+        // io_uring reads at explicit offsets, so the handle has not moved.
+        file.seek(SeekFrom::Start(filled as u64))?;
+    }
+    loop {
+        progress::check()?;
+        // Files are read in pieces so the rate can be kept even; with
+        // --adaptive their size follows the measured speed.
+        let chunk_len = tuning::chunk_len();
+        if filled == data.len() {
+            // The file is longer than it was, or its size was unknown.
+            data.resize(filled + chunk_len, 0);
+        }
+        let end = (filled + chunk_len).min(data.len());
+        let span = trace::span("read");
+        let started = Instant::now();
+        let result = file.read(&mut data[filled..end]);
+        let took = started.elapsed();
+        drop(span.with("bytes", *result.as_ref().unwrap_or(&0) as u64));
+        match result {
+            Ok(0) => {
+                data.truncate(filled);
+                return Ok(data);
+            }
+            Ok(read) => {
+                tuning::chunk_moved(read, took);
+                wait(read);
+                progress::read(read);
+                filled += read;
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Size of the file about to be read whole, or 0 if it is not known. A
+/// file too large for this machine's address space, as a 5 GiB file on a
+/// 32-bit NAS, is refused rather than read into a truncated buffer.
+fn expected_len(file: &File) -> io::Result<usize> {
+    let len = file.metadata().map_or(0, |info| info.len());
+    usize::try_from(len).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::OutOfMemory,
+            format!("{} bytes is more than a {}-bit system can hold in memory", len, usize::BITS),
+        )
+    })
+}
+
+/// Read a whole file to encrypt it, as `read` does, and check that its
+/// size and modification time are the same after reading as before and
+/// that as many bytes were read as it holds. A file that changed, such as
+/// a log being written to or a download still in progress, is read again
+/// after a short pause, up to `CONSISTENT_READ_ATTEMPTS` times.
+///
+/// The flag is true if the file was still changing on the last attempt:
+/// the data may then mix old and new contents, and the caller should
+/// warn that its output is suspect.
+pub fn read_consistent(path: impl AsRef<Path>) -> io::Result<(Vec<u8>, bool)> {
+    let path = path.as_ref();
+    let state = |path: &Path| {
+        let info = std::fs::metadata(path)?;
+        Ok::<_, io::Error>((info.len(), info.modified().ok()))
+    };
+    let mut attempt = 1;
+    loop {
+        let before = state(path)?;
+        let data = read(path)?;
+        let after = state(path)?;
+        let changed = before != after || data.len() as u64 != after.0;
+        if !changed {
+            return Ok((data, false));
+        }
+        if attempt == CONSISTENT_READ_ATTEMPTS {
+            INPUT_CHANGED.store(true, Ordering::Relaxed);
+            return Ok((data, true));
+        }
+        attempt += 1;
+        thread::sleep(Duration::from_millis(200));
+    }
+}
+
+/// Whether `read_consistent` gave up on any file in this run.
+pub fn input_changed() -> bool {
+    INPUT_CHANGED.load(Ordering::Relaxed)
+}
+
+/// Write a whole file, like `fs::write`, but no faster than the limit. If
+/// the operation is cancelled partway, the partly written file is removed.
+pub fn write(path: impl AsRef<Path>, data: &[u8]) -> io::Result<()> {
+    let path = path.as_ref();
+    crate::guard::check_output(path)?;
+    progress::check()?;
+    let result = if DIRECT.load(Ordering::Relaxed) {
+        write_direct(path, data)
+    } else {
+        write_buffered(path, data)
+    };
+    if result.is_err() && progress::cancelled() {
+        let _ = std::fs::remove_file(path);
+    }
+    result
+}
+
+/// Write a new file, like `write`, but fail if anything already exists
+/// at `path`, a symlink included, instead of writing through it. The open
+/// handle is returned so its permissions can be set without going through
+/// the path again. The page cache is always used.
+pub fn write_new(path: impl AsRef<Path>, data: &[u8]) -> io::Result<File> {
+    let path = path.as_ref();
+    crate::guard::check_output(path)?;
+    progress::check()?;
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        #[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "arm")))]
+        const NOFOLLOW_FLAG: i32 = 0o100000;
+        #[cfg(all(target_os = "linux", not(any(target_arch = "aarch64", target_arch = "arm"))))]
+        const NOFOLLOW_FLAG: i32 = 0o400000;
+        #[cfg(not(target_os = "linux"))]
+        const NOFOLLOW_FLAG: i32 = 0x100;
+        options.custom_flags(NOFOLLOW_FLAG);
+    }
+    let mut file = options.open(path)?;
+    if let Err(e) = write_to(&mut file, data) {
+        drop(file);
+        let _ = std::fs::remove_file(path);
+        return Err(e);
+    }
+    Ok(file)
+}
+
+fn write_buffered(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = File::create(path)?;
+    write_to(&mut file, data)
+}
+
+/// Write `data` to an open file, a piece at a time within the limit.
+fn write_to(file: &mut File, data: &[u8]) -> io::Result<()> {
+    #[cfg(target_os = "linux")]
+    if URING.load(Ordering::Relaxed) {
+        let _span = trace::span("write").with("bytes", data.len() as u64);
+        crate::uring::write(file, data, &wait)?;
+        progress::wrote(data.len());
+        tuning::moved(data.len());
+        return Ok(());
+    }
+    let mut rest = data;
+    while !rest.is_empty() {
+        let (chunk, after) = rest.split_at(tuning::chunk_len().min(rest.len()));
+        rest = after;
+        progress::check()?;
+        wait(chunk.len());
+        let span = trace::span("write").with("bytes", chunk.len() as u64);
+        let started = Instant::now();
+        file.write_all(chunk)?;
+        tuning::chunk_moved(chunk.len(), started.elapsed());
+        drop(span);
+        progress::wrote(chunk.len());
+    }
+    file.flush()
+}
+
+/// `read` without the page cache. Unbuffered reads must land in aligned
+/// memory, so each piece goes through a pooled buffer.
+fn read_direct(path: &Path) -> io::Result<Vec<u8>> {
+    let mut file = open_direct(path, OpenOptions::new().read(true))?;
+    let mut data = Vec::with_capacity(expected_len(&file)?);
+    let mut buffer = buffers::take();
+    loop {
+        progress::check()?;
+        let span = trace::span("read");
+        let result = file.read(&mut buffer);
+        drop(span.with("bytes", *result.as_ref().unwrap_or(&0) as u64));
+        match result {
+            Ok(0) => return Ok(data),
+            Ok(read) => {
+                wait(read);
+                progress::read(read);
+                tuning::moved(read);
+                data.extend_from_slice(&buffer[..read]);
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// `write` without the page cache. Unbuffered writes must be whole blocks
+/// from aligned memory, so full pieces are copied into a pooled buffer and
+/// the short tail is appended afterwards through a normal handle.
+fn write_direct(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = open_direct(path, OpenOptions::new().write(true).create(true).truncate(true))?;
+    let mut buffer = buffers::take();
+    let mut pieces = data.chunks_exact(buffers::BUFFER_LEN);
+    for piece in &mut pieces {
+        progress::check()?;
+        wait(piece.len());
+        buffer.copy_from_slice(piece);
+        let span = trace::span("write").with("bytes", piece.len() as u64);
+        file.write_all(&buffer)?;
+        drop(span);
+        progress::wrote(piece.len());
+        tuning::moved(piece.len());
+    }
+    file.sync_data()?;
+    drop(file);
+
+    let tail = pieces.remainder();
+    if !tail.is_empty() {
+        wait(tail.len());
+        let _span = trace::span("write").with("bytes", tail.len() as u64);
+        OpenOptions::new().append(true).open(path)?.write_all(tail)?;
+        progress::wrote(tail.len());
+    }
+    Ok(())
+}
+
+/// Open a file so its contents bypass the page cache. Filesystems that do
+/// not support this (such as tmpfs) get a normal handle instead.
+fn open_direct(path: &Path, options: &mut OpenOptions) -> io::Result<File> {
+    #[cfg(any(target_os = "linux", windows))]
+    {
+        #[cfg(target_os = "linux")]
+        use std::os::unix::fs::OpenOptionsExt;
+        #[cfg(windows)]
+        use std::os::windows::fs::OpenOptionsExt;
+
+        #[cfg(all(target_os = "linux", any(target_arch = "aarch64", target_arch = "arm")))]
+        const DIRECT_FLAG: u32 = 0o200000;
+        #[cfg(all(target_os = "linux", not(any(target_arch = "aarch64", target_arch = "arm"))))]
+        const DIRECT_FLAG: u32 = 0o40000;
+        #[cfg(windows)]
+        const DIRECT_FLAG: u32 = 0x2000_0000; // FILE_FLAG_NO_BUFFERING
+
+        let mut direct = options.clone();
+        direct.custom_flags(DIRECT_FLAG as _);
+        match direct.open(path) {
+            Err(e) if e.kind() == io::ErrorKind::InvalidInput => options.open(path),
+            result => result,
+        }
+    }
+    #[cfg(target_os = "macos")]
+    {
+        use std::os::fd::AsRawFd;
+        unsafe extern "C" {
+            fn fcntl(fd: i32, command: i32, ...) -> i32;
+        }
+        const F_NOCACHE: i32 = 48;
+
+        let file = options.open(path)?;
+        // SAFETY: the descriptor is open for as long as `file` lives, and
+        // F_NOCACHE only takes an integer argument.
+        unsafe { fcntl(file.as_raw_fd(), F_NOCACHE, 1) };
+        Ok(file)
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+    {
+        options.open(path)
+    }
+}
+
+/// Take `bytes` from the budget, sleeping until the rate allows them.
+fn wait(bytes: usize) {
+    let mut bucket = BUCKET.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(bucket) = bucket.as_mut() else {
+        return;
+    };
+
+    let now = Instant::now();
+    let refill = now.duration_since(bucket.last).as_secs_f64() * bucket.bytes_per_second;
+    bucket.available = (bucket.available + refill).min(tuning::chunk_len() as f64);
+    bucket.last = now;
+    bucket.available -= bytes as f64;
+    if bucket.available < 0.0 {
+        thread::sleep(Duration::from_secs_f64(
+            -bucket.available / bucket.bytes_per_second,
+        ));
+        // The sleep paid off the debt; refilling starts again from here.
+        bucket.available = 0.0;
+        bucket.last = Instant::now();
+    }
+}
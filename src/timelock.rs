@@ -0,0 +1,231 @@
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use crate::bigint::{self, BigUint, Montgomery};
+use crate::checksum::{self, to_hex};
+use crate::storage;
+
+/// Bytes at the start of every time-locked file.
+const TIMELOCK_MAGIC: &[u8; 4] = b"FTLK";
+
+/// Version number stored right after the magic bytes.
+const TIMELOCK_VERSION: u8 = 1;
+
+/// Size of each secret prime. Factoring their product skips the puzzle, so
+/// it gets the usual RSA size.
+const PRIME_BITS: usize = 1024;
+
+/// Length of the modulus and base as stored.
+const MODULUS_LEN: usize = 2 * PRIME_BITS / 8;
+
+/// Magic, version, target date, squaring count, modulus, and base.
+const HEADER_LEN: usize = TIMELOCK_MAGIC.len() + 1 + 8 + 8 + 2 * MODULUS_LEN;
+
+/// How long to measure the squaring speed for.
+const CALIBRATION_TIME: Duration = Duration::from_secs(1);
+
+/// Squarings between looks at the clock while solving.
+const BATCH: u64 = 4096;
+
+/// How often to save progress while solving.
+const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+/// A time-lock puzzle (Rivest, Shamir, and Wagner, 1996). The key is
+/// `base^(2^squarings) mod modulus`. Whoever made the puzzle knows the
+/// factors of the modulus and can compute that almost at once; everyone
+/// else has to square `squarings` times in a row, and each squaring needs
+/// the result of the one before, so more computers do not help.
+pub struct Puzzle {
+    /// When the puzzle was meant to become solvable, seconds since 1970.
+    pub until: u64,
+    pub squarings: u64,
+    modulus: BigUint,
+    base: BigUint,
+}
+
+/// Measure how many squarings per second this computer manages, which is
+/// what turns a date into a puzzle size.
+pub fn squarings_per_second() -> Result<u64, String> {
+    let mut bytes = [0u8; MODULUS_LEN];
+    crate::crypto::random_bytes(&mut bytes)?;
+    bytes[0] |= 0x80;
+    bytes[MODULUS_LEN - 1] |= 1;
+    let field = Montgomery::new(&BigUint::from_be_bytes(&bytes));
+    bytes[0] &= 0x7f;
+    let mut value = field.encode(&BigUint::from_be_bytes(&bytes));
+
+    let started = Instant::now();
+    let mut count = 0u64;
+    while started.elapsed() < CALIBRATION_TIME {
+        for _ in 0..BATCH {
+            value = field.mul(&value, &value);
+        }
+        count += BATCH;
+    }
+    Ok((count as f64 / started.elapsed().as_secs_f64()) as u64)
+}
+
+/// Encrypt `data` so it takes about `until - now` seconds of sequential
+/// work at `rate` squarings per second to decrypt. Returns the file
+/// contents: the puzzle followed by `data` encrypted with its solution.
+pub fn lock(data: &[u8], until: u64, rate: u64) -> Result<Vec<u8>, String> {
+    let seconds = until.saturating_sub(now());
+    if seconds == 0 {
+        return Err("The date must be in the future.".to_string());
+    }
+    let squarings = seconds
+        .checked_mul(rate)
+        .ok_or_else(|| "That date is too far away.".to_string())?;
+
+    let one = BigUint::from_u64(1);
+    let p = bigint::random_prime(PRIME_BITS)?;
+    let q = loop {
+        let q = bigint::random_prime(PRIME_BITS)?;
+        if q != p {
+            break q;
+        }
+    };
+    let modulus = p.mul(&q);
+    let totient = p.sub(&one).mul(&q.sub(&one));
+
+    let mut bytes = [0u8; MODULUS_LEN];
+    crate::crypto::random_bytes(&mut bytes)?;
+    let base = BigUint::from_be_bytes(&bytes)
+        .rem(&modulus.sub(&BigUint::from_u64(3)))
+        .add(&BigUint::from_u64(2));
+
+    // The shortcut: 2^squarings mod the totient, then one exponentiation.
+    let mut exponent = one.clone();
+    for index in (0..64 - squarings.leading_zeros() as u64).rev() {
+        exponent = exponent.mul(&exponent).rem(&totient);
+        if squarings >> index & 1 == 1 {
+            exponent = exponent.add(&exponent).rem(&totient);
+        }
+    }
+    let solution = Montgomery::new(&modulus).pow(&base, &exponent);
+
+    let puzzle = Puzzle {
+        until,
+        squarings,
+        modulus,
+        base,
+    };
+    let mut output = puzzle.header();
+    output.extend_from_slice(&crate::encrypt_bytes(
+        data,
+        &[],
+        puzzle.key(&solution).as_bytes(),
+    )?);
+    Ok(output)
+}
+
+/// Split a time-locked file into its puzzle and the encrypted contents.
+pub fn read_puzzle(data: &[u8]) -> Result<(Puzzle, &[u8]), String> {
+    if data.len() < HEADER_LEN || !data.starts_with(TIMELOCK_MAGIC) {
+        return Err("This is not a time-locked file.".to_string());
+    }
+    if data[4] != TIMELOCK_VERSION {
+        return Err(format!("Unsupported time-lock version {}.", data[4]));
+    }
+    let u64_at = |at: usize| u64::from_le_bytes(data[at..at + 8].try_into().expect("8 bytes"));
+    let modulus_at = 21;
+    let base_at = modulus_at + MODULUS_LEN;
+    let puzzle = Puzzle {
+        until: u64_at(5),
+        squarings: u64_at(13),
+        modulus: BigUint::from_be_bytes(&data[modulus_at..base_at]),
+        base: BigUint::from_be_bytes(&data[base_at..HEADER_LEN]),
+    };
+    if !puzzle.modulus.bit(0) || puzzle.modulus.bits() < 2 || puzzle.base >= puzzle.modulus {
+        return Err("The time-lock puzzle is damaged.".to_string());
+    }
+    Ok((puzzle, &data[HEADER_LEN..]))
+}
+
+impl Puzzle {
+    fn header(&self) -> Vec<u8> {
+        let mut header = Vec::with_capacity(HEADER_LEN);
+        header.extend_from_slice(TIMELOCK_MAGIC);
+        header.push(TIMELOCK_VERSION);
+        header.extend_from_slice(&self.until.to_le_bytes());
+        header.extend_from_slice(&self.squarings.to_le_bytes());
+        header.extend_from_slice(&self.modulus.to_be_bytes(MODULUS_LEN));
+        header.extend_from_slice(&self.base.to_be_bytes(MODULUS_LEN));
+        header
+    }
+
+    /// The password the contents were encrypted with, derived from the
+    /// solution and bound to this puzzle.
+    fn key(&self, solution: &BigUint) -> String {
+        let mut input = self.header();
+        input.extend_from_slice(&solution.to_be_bytes(MODULUS_LEN));
+        to_hex(&checksum::sha256(&input))
+    }
+
+    /// Work through the puzzle and return the password. Progress is saved
+    /// to `checkpoint` every minute and picked up from there on the next
+    /// run, so the work survives restarts; the file is removed at the end.
+    /// `progress` is called about once a second with the squarings done.
+    pub fn solve(&self, checkpoint: &Path, progress: &mut dyn FnMut(u64)) -> String {
+        let field = Montgomery::new(&self.modulus);
+        let id = to_hex(&checksum::sha256(&self.header()));
+        let (mut done, mut value) = match load_checkpoint(checkpoint, &id, &self.modulus) {
+            Some((done, value)) if done <= self.squarings => (done, field.encode(&value)),
+            _ => (0, field.encode(&self.base)),
+        };
+
+        let mut last_progress = Instant::now();
+        let mut last_checkpoint = Instant::now();
+        while done < self.squarings {
+            for _ in 0..BATCH.min(self.squarings - done) {
+                value = field.mul(&value, &value);
+                done += 1;
+            }
+            if last_progress.elapsed() >= Duration::from_secs(1) {
+                last_progress = Instant::now();
+                progress(done);
+            }
+            if last_checkpoint.elapsed() >= CHECKPOINT_INTERVAL {
+                last_checkpoint = Instant::now();
+                let state = format!(
+                    "{} {} {}\n",
+                    id,
+                    done,
+                    to_hex(&field.decode(&value).to_be_bytes(MODULUS_LEN))
+                );
+                let temp_path = checkpoint.with_extension("saving");
+                if let Err(e) = fs::write(&temp_path, state).and_then(|_| storage::rename(&temp_path, checkpoint)) {
+                    eprintln!("Warning: failed to save progress to '{}': {}", checkpoint.display(), e);
+                }
+            }
+        }
+        let _ = fs::remove_file(checkpoint);
+        self.key(&field.decode(&value))
+    }
+}
+
+/// Saved progress for the puzzle with `id`: squarings done and the value
+/// reached. Anything unreadable or belonging to another puzzle is ignored.
+fn load_checkpoint(path: &Path, id: &str, modulus: &BigUint) -> Option<(u64, BigUint)> {
+    let contents = fs::read_to_string(path).ok()?;
+    let mut fields = contents.split_whitespace();
+    if fields.next()? != id {
+        return None;
+    }
+    let done = fields.next()?.parse().ok()?;
+    let hex = fields.next()?;
+    let bytes = (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect::<Option<Vec<u8>>>()?;
+    let value = BigUint::from_be_bytes(&bytes);
+    (value < *modulus).then_some((done, value))
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
@@ -0,0 +1,339 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::Keys;
+use crate::base32;
+use crate::crypto;
+use crate::i18n::t;
+use crate::storage;
+use crate::tty;
+
+/// Length of a new TOTP secret. RFC 4226 recommends 160 bits.
+const SECRET_LEN: usize = 20;
+
+/// Codes change every this many seconds.
+const TIME_STEP: u64 = 30;
+
+/// Codes from this many steps before or after the current one are still
+/// accepted, to allow for clock drift and slow typing.
+const ALLOWED_DRIFT: u64 = 1;
+
+/// Input to the HMAC that turns the secret into the share mixed into the
+/// keys of a file, so the share is never the secret itself.
+const KEY_SHARE_LABEL: &[u8] = b"File Encryptor TOTP key share";
+
+/// Whether new files require a code to decrypt (`--totp`).
+static REQUIRED: AtomicBool = AtomicBool::new(false);
+
+/// The secret a code was last accepted for in this run.
+static CONFIRMED: Mutex<Option<Vec<u8>>> = Mutex::new(None);
+
+/// Make every file encrypted in this run require a code from an
+/// authenticator app, in addition to the password, to decrypt.
+pub fn require_for_new_files() {
+    REQUIRED.store(true, Ordering::Relaxed);
+}
+
+/// Whether `--totp` is on.
+pub fn required() -> bool {
+    REQUIRED.load(Ordering::Relaxed)
+}
+
+/// Where the secret of the user running the tool is kept, in base32.
+fn enrollment_path() -> PathBuf {
+    env::home_dir().unwrap_or_default().join(".file_encryptor_totp")
+}
+
+/// The authenticator secret of the user running the tool, or `None` if
+/// they have not used `--totp` yet.
+pub fn enrolled() -> Result<Option<Vec<u8>>, String> {
+    let path = enrollment_path();
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("Failed to read '{}': {}", path.display(), e)),
+    };
+    check_private(&path)?;
+    let encoded: String = text.split_whitespace().collect();
+    match base32::decode(&encoded) {
+        Some(secret) if !secret.is_empty() => Ok(Some(secret)),
+        _ => Err(format!("'{}' does not hold an authenticator secret.", path.display())),
+    }
+}
+
+/// If `--totp` is on, the secret to bind new files to with `bind_keys`.
+///
+/// There is one secret per user. The first time `--totp` is used it is
+/// created, shown so it can be added to an authenticator app, and kept in
+/// `~/.file_encryptor_totp` once a code from the app confirms it.
+pub fn secret_for_new_files() -> Result<Option<Vec<u8>>, String> {
+    if !required() {
+        return Ok(None);
+    }
+    if let Some(secret) = enrolled()? {
+        return Ok(Some(secret));
+    }
+    let mut secret = vec![0u8; SECRET_LEN];
+    crypto::random_bytes(&mut secret)?;
+    let encoded = base32::encode(&secret);
+
+    println!("{}", t!("Add this secret to your authenticator app:"));
+    let groups: Vec<&str> = encoded
+        .as_bytes()
+        .chunks(4)
+        .map(|group| std::str::from_utf8(group).expect("base32 is ASCII"))
+        .collect();
+    println!("  {}", groups.join(" "));
+    let account = ["USER", "LOGNAME", "USERNAME"]
+        .iter()
+        .find_map(|name| env::var(name).ok().filter(|value| !value.is_empty()))
+        .unwrap_or_else(|| "File Encryptor".to_string());
+    println!(
+        "  otpauth://totp/File%20Encryptor:{}?secret={}&issuer=File%20Encryptor",
+        percent_encode(&account),
+        encoded
+    );
+    if !verify(&secret, &tty::prompt(t!("Enter the code it shows to confirm: ")), now()) {
+        return Err(t!("The code did not match, so nothing was encrypted. Check the secret and your clock and try again.").to_string());
+    }
+
+    let path = enrollment_path();
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    options
+        .open(&path)
+        .and_then(|mut file| writeln!(file, "{}", encoded))
+        .map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+    println!(
+        "{}",
+        t!("Keep a copy of this secret. Files encrypted with --totp cannot be opened without it; it is kept in '{}'.", path.display())
+    );
+    Ok(Some(secret))
+}
+
+/// Bind `keys` derived from a password to an authenticator secret, so a
+/// file sealed with the result cannot be opened with the password alone.
+/// Each key is replaced by an HMAC, keyed with it, of a share derived from
+/// the secret.
+///
+/// The code itself changes every 30 seconds and cannot go into a key, so
+/// what the file needs besides the password is the secret: it is in the
+/// authenticator app and in this user's `~/.file_encryptor_totp`, and the
+/// tool asks for a current code before it uses the secret to decrypt.
+pub fn bind_keys(keys: &Keys, secret: &[u8]) -> Keys {
+    let share = crypto::hmac_sha256(secret, KEY_SHARE_LABEL);
+    Keys {
+        cipher: crypto::hmac_sha256(&keys.cipher, &share),
+        mac: crypto::hmac_sha256(&keys.mac, &share),
+    }
+}
+
+/// Refuse an enrollment file that others can read or replace.
+#[cfg(unix)]
+fn check_private(path: &Path) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let info = fs::symlink_metadata(path).map_err(|e| format!("Failed to read '{}': {}", path.display(), e))?;
+    if !info.is_file() || !storage::owned_by_user(&info) || info.permissions().mode() & 0o077 != 0 {
+        return Err(format!(
+            "'{}' is not a file only you can read, so the authenticator secret in it is not trusted. Make it yours with mode 600.",
+            path.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_private(path: &Path) -> Result<(), String> {
+    match fs::symlink_metadata(path) {
+        Ok(info) if info.is_file() => Ok(()),
+        Ok(_) => Err(format!("'{}' is not a file.", path.display())),
+        Err(e) => Err(format!("Failed to read '{}': {}", path.display(), e)),
+    }
+}
+
+/// Ask for the current code for `secret` and check it. Once a code for a
+/// secret has been accepted, later files with the same secret in this run,
+/// such as the rest of a folder encrypted with `--totp`, do not ask again.
+pub fn check(secret: &[u8]) -> Result<(), String> {
+    let mut confirmed = CONFIRMED.lock().unwrap_or_else(|e| e.into_inner());
+    if confirmed.as_deref() == Some(secret) {
+        return Ok(());
+    }
+    check_code(secret, &tty::prompt(t!("Enter the 6-digit code from your authenticator app: ")))?;
+    *confirmed = Some(secret.to_vec());
+    Ok(())
+}
+
+/// Check a code for `secret` that was entered somewhere other than the
+/// terminal.
+pub fn check_code(secret: &[u8], code: &str) -> Result<(), String> {
+    if verify(secret, code, now()) {
+        Ok(())
+    } else {
+        Err(t!("The authenticator code is wrong or has expired.").to_string())
+    }
+}
+
+/// Whether `code` is a valid TOTP code (RFC 6238, HMAC-SHA1, 6 digits,
+/// 30-second steps) for `secret` at `time`.
+fn verify(secret: &[u8], code: &str, time: u64) -> bool {
+    let code: String = code.chars().filter(|c| !c.is_whitespace()).collect();
+    let Ok(code) = code.parse::<u32>() else {
+        return false;
+    };
+    let step = time / TIME_STEP;
+    let mut matched = false;
+    // Check every step in the window, so timing does not reveal which one
+    // matched.
+    for counter in step.saturating_sub(ALLOWED_DRIFT)..=step + ALLOWED_DRIFT {
+        matched |= hotp(secret, counter) == code;
+    }
+    matched
+}
+
+/// The HOTP value for `counter` (RFC 4226): HMAC-SHA1 of the counter,
+/// dynamically truncated to six digits.
+fn hotp(secret: &[u8], counter: u64) -> u32 {
+    let mac = hmac_sha1(secret, &counter.to_be_bytes());
+    let offset = (mac[19] & 0x0f) as usize;
+    let value = u32::from_be_bytes([mac[offset], mac[offset + 1], mac[offset + 2], mac[offset + 3]])
+        & 0x7fff_ffff;
+    value % 1_000_000
+}
+
+/// HMAC-SHA1. SHA-1 is only used here, because it is what authenticator
+/// apps compute; HMAC-SHA1 is still sound for this even though plain SHA-1
+/// collisions are not.
+fn hmac_sha1(key: &[u8], message: &[u8]) -> [u8; 20] {
+    let mut block = [0u8; 64];
+    if key.len() > 64 {
+        block[..20].copy_from_slice(&sha1(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+    let mut inner = block.map(|byte| byte ^ 0x36).to_vec();
+    inner.extend_from_slice(message);
+    let mut outer = block.map(|byte| byte ^ 0x5c).to_vec();
+    outer.extend_from_slice(&sha1(&inner));
+    sha1(&outer)
+}
+
+/// SHA-1 (FIPS 180-4) of a short message.
+fn sha1(message: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x6745_2301, 0xefcd_ab89, 0x98ba_dcfe, 0x1032_5476, 0xc3d2_e1f0];
+    let mut padded = message.to_vec();
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    padded.extend_from_slice(&((message.len() as u64) * 8).to_be_bytes());
+
+    for block in padded.chunks_exact(64) {
+        let mut w = [0u32; 80];
+        for (i, word) in block.chunks_exact(4).enumerate() {
+            w[i] = u32::from_be_bytes([word[0], word[1], word[2], word[3]]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (i, &word) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19 => ((b & c) | (!b & d), 0x5a82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ed9_eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1b_bcdc),
+                _ => (b ^ c ^ d, 0xca62_c1d6),
+            };
+            let temp = a
+                .rotate_left(5)
+                .wrapping_add(f)
+                .wrapping_add(e)
+                .wrapping_add(k)
+                .wrapping_add(word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, add) in state.iter_mut().zip([a, b, c, d, e]) {
+            *value = value.wrapping_add(add);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (out, value) in digest.chunks_exact_mut(4).zip(state) {
+        out.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+/// Escape text for the label part of an `otpauth://` URI.
+fn percent_encode(text: &str) -> String {
+    text.bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{:02X}", byte),
+        })
+        .collect()
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn hotp_matches_rfc_4226() {
+        let expected = [755224, 287082, 359152, 969429, 338314, 254676, 287922, 162583, 399871, 520489];
+        for (counter, code) in expected.into_iter().enumerate() {
+            assert_eq!(hotp(RFC_SECRET, counter as u64), code, "counter {}", counter);
+        }
+        assert_eq!(
+            sha1(b"abc").map(|byte| format!("{:02x}", byte)).concat(),
+            "a9993e364706816aba3e25717850c26c9cd0d89d"
+        );
+    }
+
+    #[test]
+    fn totp_matches_rfc_6238() {
+        // The RFC's eight-digit codes, cut to the six digits apps show.
+        for (time, code) in [
+            (59, "287082"),
+            (1111111109, "081804"),
+            (1111111111, "050471"),
+            (1234567890, "005924"),
+            (2000000000, "279037"),
+            (20000000000, "353130"),
+        ] {
+            assert!(verify(RFC_SECRET, code, time), "{}", time);
+        }
+        assert!(verify(RFC_SECRET, "287 082", 59));
+        assert!(verify(RFC_SECRET, "081804", 1111111109 + TIME_STEP));
+        assert!(!verify(RFC_SECRET, "081804", 1111111109 + 10 * TIME_STEP));
+        assert!(!verify(RFC_SECRET, "28708x", 59));
+        assert!(!verify(b"another secret", "287082", 59));
+    }
+
+}
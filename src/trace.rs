@@ -0,0 +1,191 @@
+use std::cell::Cell;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Instant;
+
+use crate::manifest::json_string;
+
+/// Whether spans are being recorded (`--trace-output`), so the I/O path
+/// pays only for an atomic load when they are not (the usual case).
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static TRACE: Mutex<Option<Trace>> = Mutex::new(None);
+
+/// Number given to the next thread that records a span.
+static NEXT_THREAD: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// This thread's number in the trace, once it has one.
+    static THREAD: Cell<u64> = const { Cell::new(0) };
+    /// Spans open on this thread, so the trace is written out when the
+    /// outermost one ends.
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// The file being written and the events not yet in it.
+struct Trace {
+    file: File,
+    start: Instant,
+    pending: String,
+    /// Whether the file already holds an event, so the next needs a comma.
+    written: bool,
+}
+
+/// Record a span for each stage of every operation from now on and write
+/// them to `path` as a Chrome trace (the JSON array format), which
+/// `chrome://tracing`, Perfetto, and speedscope open as a timeline or a
+/// flame graph.
+pub fn start(path: &Path) -> Result<(), String> {
+    let mut file = File::create(path).map_err(|e| format!("Failed to create '{}': {}", path.display(), e))?;
+    file.write_all(b"[]").map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+    let mut trace = Trace {
+        file,
+        start: Instant::now(),
+        pending: String::new(),
+        written: false,
+    };
+    trace.pending.push_str(&format!(
+        "{{\"name\": \"process_name\", \"ph\": \"M\", \"pid\": {}, \"args\": {{\"name\": \"File Encryptor\"}}}}",
+        std::process::id()
+    ));
+    trace.flush();
+    *TRACE.lock().unwrap_or_else(|e| e.into_inner()) = Some(trace);
+    ENABLED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// A stage being timed. It ends, and is recorded, when dropped.
+pub struct Span {
+    /// `None` when tracing is off.
+    start: Option<Instant>,
+    name: &'static str,
+    args: String,
+}
+
+/// Start timing `name`, such as "read" for one chunk read from a file.
+pub fn span(name: &'static str) -> Span {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return Span {
+            start: None,
+            name,
+            args: String::new(),
+        };
+    }
+    DEPTH.with(|depth| depth.set(depth.get() + 1));
+    Span {
+        start: Some(Instant::now()),
+        name,
+        args: String::new(),
+    }
+}
+
+impl Span {
+    /// Attach a number shown with the span, such as the bytes it moved.
+    pub fn with(mut self, key: &str, value: u64) -> Self {
+        if self.start.is_some() {
+            self.separate();
+            let _ = write!(self.args, "\"{}\": {}", key, value);
+        }
+        self
+    }
+
+    /// Attach text shown with the span, such as the file it worked on.
+    pub fn with_text(mut self, key: &str, value: &str) -> Self {
+        if self.start.is_some() {
+            self.separate();
+            let _ = write!(self.args, "\"{}\": {}", key, json_string(value));
+        }
+        self
+    }
+
+    fn separate(&mut self) {
+        if !self.args.is_empty() {
+            self.args.push_str(", ");
+        }
+    }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        let Some(start) = self.start else {
+            return;
+        };
+        let end = Instant::now();
+        let outermost = DEPTH.with(|depth| {
+            depth.set(depth.get() - 1);
+            depth.get() == 0
+        });
+        let (thread, named) = thread_number();
+        let mut guard = TRACE.lock().unwrap_or_else(|e| e.into_inner());
+        let Some(trace) = guard.as_mut() else {
+            return;
+        };
+        let pid = std::process::id();
+        if named && let Some(name) = std::thread::current().name() {
+            trace.event(&format!(
+                "{{\"name\": \"thread_name\", \"ph\": \"M\", \"pid\": {}, \"tid\": {}, \"args\": {{\"name\": {}}}}}",
+                pid,
+                thread,
+                json_string(name)
+            ));
+        }
+        // Chrome traces count microseconds.
+        let micros = |at: Instant| at.duration_since(trace.start).as_nanos() as f64 / 1000.0;
+        let event = format!(
+            "{{\"name\": \"{}\", \"ph\": \"X\", \"ts\": {:.3}, \"dur\": {:.3}, \"pid\": {}, \"tid\": {}, \"args\": {{{}}}}}",
+            self.name,
+            micros(start),
+            micros(end) - micros(start),
+            pid,
+            thread,
+            self.args
+        );
+        trace.event(&event);
+        if outermost {
+            trace.flush();
+        }
+    }
+}
+
+impl Trace {
+    fn event(&mut self, event: &str) {
+        if !self.pending.is_empty() {
+            self.pending.push_str(",\n");
+        }
+        self.pending.push_str(event);
+    }
+
+    /// Append the pending events before the closing bracket, so the file
+    /// is a complete trace after every flush, even if the process is
+    /// killed later.
+    fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        let mut text = String::from(if self.written { ",\n" } else { "\n" });
+        text.push_str(&self.pending);
+        text.push_str("\n]");
+        self.pending.clear();
+        // A trace that cannot be written is not worth stopping the
+        // operation for.
+        if self.file.seek(SeekFrom::End(-1)).is_ok() && self.file.write_all(text.as_bytes()).is_ok() {
+            self.written = true;
+        }
+    }
+}
+
+/// This thread's number in the trace, and whether it was just given one.
+fn thread_number() -> (u64, bool) {
+    THREAD.with(|thread| match thread.get() {
+        0 => {
+            let number = NEXT_THREAD.fetch_add(1, Ordering::Relaxed);
+            thread.set(number);
+            (number, true)
+        }
+        number => (number, false),
+    })
+}
@@ -0,0 +1,247 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::claims::{self, Claim};
+use crate::paths;
+use crate::storage;
+
+/// Name of the file inside a trash slot that records what the operation did.
+const JOURNAL_FILE: &str = "operation";
+
+/// Name of the file inside a trash slot that holds the replaced original.
+const ORIGINAL_FILE: &str = "original";
+
+/// Temporary holding area for files that an operation replaced, so the
+/// last operation can be undone for a while after it ran.
+pub struct Trash {
+    dir: PathBuf,
+    retention: Duration,
+}
+
+/// An operation that has saved any existing output but has not yet
+/// been recorded. Call `commit` once the new output is written, or
+/// `rollback` if the operation failed.
+pub struct PendingOperation {
+    slot: PathBuf,
+    output_path: PathBuf,
+    replaced: bool,
+    /// Size and modification time of the replaced file, to tell whether the
+    /// operation got as far as touching it.
+    original_state: Option<(u64, Option<SystemTime>)>,
+    /// Held until the operation is recorded or rolled back, so no other
+    /// process writes the output meanwhile and has its work undone by the
+    /// rollback.
+    _claim: Claim,
+}
+
+impl Trash {
+    /// Create a trash that keeps entries for the given number of minutes.
+    ///
+    /// It lives in the home folder rather than the shared temporary folder,
+    /// since it holds copies of replaced files and its journals name the
+    /// files `undo` removes and overwrites. Fails when there is no home
+    /// folder, rather than keeping the trash in whatever folder the tool
+    /// was started from.
+    pub fn new(retention_minutes: u64) -> Result<Self, String> {
+        let home = env::home_dir().ok_or("Cannot find your home folder to keep the trash in.")?;
+        Ok(Trash {
+            dir: home.join(".file_encryptor_trash"),
+            retention: Duration::from_secs(retention_minutes.saturating_mul(60)),
+        })
+    }
+
+    /// Delete every slot that is older than the retention window.
+    pub fn purge_expired(&self) {
+        if check_private(&self.dir).is_err() {
+            return;
+        }
+        for (slot, created) in self.slots() {
+            if !self.is_fresh(created) {
+                let _ = fs::remove_dir_all(slot);
+            }
+        }
+    }
+
+    /// Start an operation that will write `output_path`. If a file already
+    /// exists there it is copied into the trash so it can be restored later.
+    /// Copying rather than moving keeps the file readable when the output
+    /// path is also the input path.
+    pub fn begin(&self, output_path: impl AsRef<Path>) -> Result<PendingOperation, String> {
+        let output_path = std::path::absolute(output_path)
+            .map_err(|e| format!("Failed to resolve output path: {}", e))?;
+        let claim = claims::claim(&output_path, "write")?;
+
+        create_private_dir(&self.dir).map_err(|e| format!("Failed to create trash folder: {}", e))?;
+        check_private(&self.dir)?;
+        let slot = self.dir.join(format!("{:020}", now_nanos()));
+        create_private_dir(&slot).map_err(|e| format!("Failed to create trash slot: {}", e))?;
+
+        let replaced = output_path.is_file();
+        let original_state = file_state(&output_path);
+        if replaced {
+            fs::copy(&output_path, slot.join(ORIGINAL_FILE))
+                .map_err(|e| format!("Failed to copy existing output to trash: {}", e))?;
+        }
+
+        Ok(PendingOperation {
+            slot,
+            output_path,
+            replaced,
+            original_state,
+            _claim: claim,
+        })
+    }
+
+    /// Run an operation that writes `output_path`, keeping any file it
+    /// replaces so the operation can be undone. If the operation fails, the
+    /// original is put back straight away.
+    pub fn protect<T, F>(&self, output_path: impl AsRef<Path>, operation: F) -> Result<T, String>
+    where
+        F: FnOnce() -> Result<T, String>,
+    {
+        let pending = self.begin(output_path)?;
+        match operation() {
+            Ok(value) => {
+                pending.commit()?;
+                Ok(value)
+            }
+            Err(e) => {
+                pending.rollback();
+                Err(e)
+            }
+        }
+    }
+
+    /// Undo the most recent operation that is still inside the retention
+    /// window. Returns a short description of what was restored.
+    pub fn undo_last(&self) -> Result<String, String> {
+        if !self.dir.exists() {
+            return Err("Nothing to undo.".to_string());
+        }
+        check_private(&self.dir)?;
+        let (slot, created) = self
+            .slots()
+            .into_iter()
+            .filter(|(slot, _)| slot.join(JOURNAL_FILE).is_file())
+            .max_by_key(|(_, created)| *created)
+            .ok_or_else(|| "Nothing to undo.".to_string())?;
+
+        if !self.is_fresh(created) {
+            return Err("The last operation is older than the trash retention window.".to_string());
+        }
+
+        let journal = fs::read_to_string(slot.join(JOURNAL_FILE))
+            .map_err(|e| format!("Failed to read trash journal: {}", e))?;
+        let output_path = paths::decode(journal.trim_end_matches('\n'));
+
+        let _claim = claims::claim(&output_path, "undo")?;
+        if output_path.is_file() {
+            fs::remove_file(&output_path)
+                .map_err(|e| format!("Failed to remove '{}': {}", output_path.display(), e))?;
+        }
+
+        let original = slot.join(ORIGINAL_FILE);
+        let message = if original.is_file() {
+            storage::rename(&original, &output_path)
+                .map_err(|e| format!("Failed to restore '{}': {}", output_path.display(), e))?;
+            format!("Restored the previous '{}'.", paths::display(&output_path))
+        } else {
+            format!("Removed '{}'.", paths::display(&output_path))
+        };
+
+        let _ = fs::remove_dir_all(&slot);
+        Ok(message)
+    }
+
+    /// List every slot directory together with the time it was created.
+    fn slots(&self) -> Vec<(PathBuf, u128)> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return Vec::new(),
+        };
+
+        entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let created = entry.file_name().to_str()?.parse::<u128>().ok()?;
+                Some((entry.path(), created))
+            })
+            .collect()
+    }
+
+    /// Whether a slot created at `created` (nanoseconds) is still kept.
+    fn is_fresh(&self, created: u128) -> bool {
+        now_nanos().saturating_sub(created) <= self.retention.as_nanos()
+    }
+}
+
+impl PendingOperation {
+    /// Record the finished operation so it can be undone later.
+    pub fn commit(self) -> Result<(), String> {
+        let journal = format!("{}\n", paths::encode(&self.output_path));
+        fs::write(self.slot.join(JOURNAL_FILE), journal)
+            .map_err(|e| format!("Failed to write trash journal: {}", e))
+    }
+
+    /// Put back whatever `begin` saved and forget the operation.
+    pub fn rollback(self) {
+        // A file the operation never wrote to is left alone, so a write that
+        // was refused does not disturb it either.
+        if self.replaced && file_state(&self.output_path) != self.original_state {
+            let _ = fs::copy(self.slot.join(ORIGINAL_FILE), &self.output_path);
+        }
+        let _ = fs::remove_dir_all(&self.slot);
+    }
+}
+
+fn file_state(path: &Path) -> Option<(u64, Option<SystemTime>)> {
+    fs::metadata(path).ok().map(|info| (info.len(), info.modified().ok()))
+}
+
+/// Create a directory that only the current user can read.
+fn create_private_dir(path: &Path) -> std::io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+    builder.create(path)
+}
+
+/// Refuse a trash folder that is a symlink, belongs to another user, or
+/// that others may enter, since whoever can write to it decides what
+/// `undo` removes and puts back.
+#[cfg(unix)]
+fn check_private(dir: &Path) -> Result<(), String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let info = fs::symlink_metadata(dir).map_err(|e| format!("Failed to read '{}': {}", dir.display(), e))?;
+    if !info.is_dir() || !storage::owned_by_user(&info) || info.permissions().mode() & 0o077 != 0 {
+        return Err(format!(
+            "The trash folder '{}' is not a folder only you can use, so it is not trusted. Remove it, or make it yours with mode 700.",
+            dir.display()
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_private(dir: &Path) -> Result<(), String> {
+    match fs::symlink_metadata(dir) {
+        Ok(info) if info.is_dir() => Ok(()),
+        Ok(_) => Err(format!("The trash folder '{}' is not a folder.", dir.display())),
+        Err(e) => Err(format!("Failed to read '{}': {}", dir.display(), e)),
+    }
+}
+
+/// Current time as nanoseconds since the Unix epoch.
+fn now_nanos() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or(0)
+}
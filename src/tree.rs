@@ -0,0 +1,501 @@
+use std::collections::HashMap;
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use crate::base32;
+use crate::claims;
+use crate::config::Config;
+use crate::crypto::{self, KEY_LEN, NONCE_LEN};
+use crate::format::{self, SALT_LEN, SealParams};
+use crate::memory;
+use crate::metadata::FileMetadata;
+use crate::paths;
+use crate::policy;
+use crate::progress::{self, Phase};
+use crate::strip;
+use crate::throttle;
+use crate::trace;
+use crate::tuning;
+use crate::{Keys, sealing};
+
+/// File at the top of a tree with encrypted names. It holds the salt the
+/// name keys are derived with and a value that shows whether a password
+/// is the one the names were encrypted with.
+pub const NAMES_FILE: &str = ".file_encryptor_names";
+
+const NAMES_MAGIC: &[u8; 4] = b"FENM";
+const NAMES_VERSION: u8 = 1;
+const NAMES_FILE_LEN: usize = NAMES_MAGIC.len() + 1 + 4 + SALT_LEN + crypto::TAG_LEN;
+
+/// What the check value in `NAMES_FILE` is a MAC of.
+const CHECK_MESSAGE: &[u8] = b"file encryptor names";
+
+/// Length of the synthetic IV in front of each encrypted name.
+const SIV_LEN: usize = 16;
+
+/// Longest name, once escaped by `paths::encode`, whose encrypted form
+/// still fits in the 255 characters file systems allow.
+const MAX_NAME_LEN: usize = 255 * 5 / 8 - SIV_LEN;
+
+/// What a tree operation did.
+#[derive(Debug, Default)]
+pub struct TreeSummary {
+    pub files: usize,
+    pub directories: usize,
+    /// Files skipped because the output was already newer than the input.
+    pub unchanged: usize,
+    /// Files that kept changing while they were read.
+    pub changed: usize,
+    pub warnings: Vec<String>,
+}
+
+/// Keys for file names. They come from the password and the salt in
+/// `NAMES_FILE`, so a name encrypts to the same text on every run and a
+/// tree can be brought up to date in place.
+struct NameKeys {
+    cipher: [u8; KEY_LEN],
+    mac: [u8; KEY_LEN],
+}
+
+impl Drop for NameKeys {
+    fn drop(&mut self) {
+        self.cipher.fill(0);
+        self.mac.fill(0);
+    }
+}
+
+impl NameKeys {
+    /// Derive the keys for the tree at `root` from `password`, checking it
+    /// against `NAMES_FILE`. With `create`, a tree without one gets a new
+    /// one; otherwise it has no encrypted names and `None` is returned.
+    fn load(root: &Path, password: &[u8], create: bool) -> Result<Option<Self>, String> {
+        let path = root.join(NAMES_FILE);
+        let contents = match fs::read(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && create => {
+                return Self::create(&path, password).map(Some);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(format!("Failed to read '{}': {}", path.display(), e)),
+        };
+
+        let damaged = || format!("'{}' is damaged.", path.display());
+        if contents.len() != NAMES_FILE_LEN || !contents.starts_with(NAMES_MAGIC) {
+            return Err(damaged());
+        }
+        if contents[4] != NAMES_VERSION {
+            return Err(format!("'{}' was written by a newer version.", path.display()));
+        }
+        let iterations = u32::from_le_bytes(contents[5..9].try_into().expect("four bytes"));
+        if iterations == 0 || iterations > format::MAX_ITERATIONS {
+            return Err(damaged());
+        }
+        let salt = &contents[9..9 + SALT_LEN];
+        let keys = Self::derive(password, salt, iterations);
+        let check = crypto::hmac_sha256(&keys.mac, CHECK_MESSAGE);
+        if !crypto::constant_time_eq(&check, &contents[9 + SALT_LEN..]) {
+            return Err("Wrong password for this tree's file names.".to_string());
+        }
+        Ok(Some(keys))
+    }
+
+    fn create(path: &Path, password: &[u8]) -> Result<Self, String> {
+        let iterations = format::DEFAULT_ITERATIONS.max(policy::get().min_kdf_iterations);
+        let mut salt = [0u8; SALT_LEN];
+        crypto::random_bytes(&mut salt)?;
+        let keys = Self::derive(password, &salt, iterations);
+
+        let mut contents = Vec::with_capacity(NAMES_FILE_LEN);
+        contents.extend_from_slice(NAMES_MAGIC);
+        contents.push(NAMES_VERSION);
+        contents.extend_from_slice(&iterations.to_le_bytes());
+        contents.extend_from_slice(&salt);
+        contents.extend_from_slice(&crypto::hmac_sha256(&keys.mac, CHECK_MESSAGE));
+        fs::write(path, contents).map_err(|e| format!("Failed to write '{}': {}", path.display(), e))?;
+        Ok(keys)
+    }
+
+    fn derive(password: &[u8], salt: &[u8], iterations: u32) -> Self {
+        let mut derived = [0u8; 2 * KEY_LEN];
+        crypto::pbkdf2_sha256(password, salt, iterations, &mut derived);
+        let mut keys = NameKeys {
+            cipher: [0; KEY_LEN],
+            mac: [0; KEY_LEN],
+        };
+        keys.cipher.copy_from_slice(&derived[..KEY_LEN]);
+        keys.mac.copy_from_slice(&derived[KEY_LEN..]);
+        derived.fill(0);
+        keys
+    }
+
+    /// Encrypt `name`, found in the folder `parent` (relative to the top
+    /// of the tree), into lowercase base32.
+    ///
+    /// The nonce is a MAC of the parent and the name (a synthetic IV), so
+    /// the same name in the same folder always gives the same text, the
+    /// same name in different folders gives different text, and a renamed
+    /// or moved file is caught when decrypting.
+    fn encrypt(&self, parent: &Path, name: &OsStr) -> Result<String, String> {
+        let mut plain = paths::encode(Path::new(name)).into_bytes();
+        if plain.len() > MAX_NAME_LEN {
+            return Err(format!(
+                "The name '{}' is too long to encrypt (at most {} bytes).",
+                name.to_string_lossy(),
+                MAX_NAME_LEN
+            ));
+        }
+        let siv = self.siv(parent, &plain);
+        crypto::chacha20_xor(&self.cipher, &nonce(&siv), 0, &mut plain);
+        let mut encrypted = siv.to_vec();
+        encrypted.extend_from_slice(&plain);
+        Ok(base32::encode(&encrypted).to_ascii_lowercase())
+    }
+
+    /// Undo `encrypt`, or `None` if `name` was not encrypted with these
+    /// keys in this folder.
+    fn decrypt(&self, parent: &Path, name: &OsStr) -> Option<OsString> {
+        let encrypted = base32::decode(name.to_str()?)?;
+        if encrypted.len() <= SIV_LEN {
+            return None;
+        }
+        let (siv, ciphertext) = encrypted.split_at(SIV_LEN);
+        let mut plain = ciphertext.to_vec();
+        crypto::chacha20_xor(&self.cipher, &nonce(siv), 0, &mut plain);
+        if !crypto::constant_time_eq(&self.siv(parent, &plain), siv) {
+            return None;
+        }
+        let name = paths::decode(&String::from_utf8(plain).ok()?).into_os_string();
+        // A name is one component; anything else would escape the folder.
+        let valid = Path::new(&name).components().count() == 1
+            && matches!(Path::new(&name).components().next(), Some(std::path::Component::Normal(_)));
+        valid.then_some(name)
+    }
+
+    fn siv(&self, parent: &Path, name: &[u8]) -> [u8; SIV_LEN] {
+        let parent = paths::encode(parent);
+        let mut mac = crypto::HmacSha256::new(&self.mac);
+        mac.update(&(parent.len() as u64).to_le_bytes());
+        mac.update(parent.as_bytes());
+        mac.update(name);
+        let mut siv = [0u8; SIV_LEN];
+        siv.copy_from_slice(&mac.finalize()[..SIV_LEN]);
+        siv
+    }
+}
+
+fn nonce(siv: &[u8]) -> [u8; NONCE_LEN] {
+    siv[..NONCE_LEN].try_into().expect("the SIV is longer than a nonce")
+}
+
+/// Encrypt every file under `source` into a file of its own under `dest`,
+/// keeping the folder structure. Files are named `NAME.enc`, or with
+/// `encrypt_names`, every file and folder name is encrypted too.
+///
+/// Running it again updates the tree: files whose output is newer than
+/// the input are skipped. Outputs whose input is gone are left alone.
+/// Folders are created first; with `--adaptive` several files may then be
+/// encrypted at once.
+pub fn encrypt(
+    config: &Config,
+    source: &Path,
+    dest: &Path,
+    password: &str,
+    encrypt_names: bool,
+) -> Result<TreeSummary, String> {
+    if !source.is_dir() {
+        return Err(format!("'{}' is not a folder.", source.display()));
+    }
+    let absolute = |path: &Path| std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    if absolute(dest).starts_with(absolute(source)) {
+        return Err("The output folder cannot be inside the folder being encrypted.".to_string());
+    }
+    fs::create_dir_all(dest).map_err(|e| format!("Failed to create '{}': {}", dest.display(), e))?;
+
+    let names = NameKeys::load(dest, password.as_bytes(), encrypt_names)?;
+    if names.is_some() && !encrypt_names {
+        return Err(format!(
+            "'{}' has encrypted file names; add --encrypt-names to update it.",
+            dest.display()
+        ));
+    }
+    let (params, keys) = crate::new_file_keys(password.as_bytes())?;
+    let mut encryptor = Encryptor {
+        config,
+        names,
+        params,
+        keys,
+        files: Vec::new(),
+        summary: TreeSummary::default(),
+    };
+    let _operation = progress::begin(Phase::Reading, None);
+    encryptor.walk(source, dest, Path::new(""))?;
+    let files = std::mem::take(&mut encryptor.files);
+    let changed = tuning::run(&files, |(input, output)| encryptor.encrypt_file(input, output))?;
+    let mut summary = encryptor.summary;
+    summary.files += files.len();
+    for warning in changed.into_iter().flatten() {
+        summary.changed += 1;
+        summary.warnings.push(warning);
+    }
+    Ok(summary)
+}
+
+struct Encryptor<'a> {
+    config: &'a Config,
+    names: Option<NameKeys>,
+    params: SealParams,
+    keys: Keys,
+    /// Files to encrypt, with their outputs, found by `walk`.
+    files: Vec<(PathBuf, PathBuf)>,
+    summary: TreeSummary,
+}
+
+impl Encryptor<'_> {
+    /// Create the folders of `from` under `to` and list the files in it
+    /// that need encrypting. `relative` is where `from` is in the tree,
+    /// which encrypted names depend on.
+    fn walk(&mut self, from: &Path, to: &Path, relative: &Path) -> Result<(), String> {
+        for (name, path, kind) in read_dir_sorted(from)? {
+            if kind.is_dir() {
+                let target = to.join(self.output_name(relative, &name, false)?);
+                fs::create_dir_all(&target)
+                    .map_err(|e| format!("Failed to create '{}': {}", target.display(), e))?;
+                self.summary.directories += 1;
+                self.walk(&path, &target, &relative.join(&name))?;
+            } else if kind.is_file() {
+                let target = to.join(self.output_name(relative, &name, true)?);
+                if is_up_to_date(&path, &target) {
+                    self.summary.unchanged += 1;
+                    continue;
+                }
+                self.files.push((path, target));
+            } else {
+                self.summary.warnings.push(format!(
+                    "Skipped '{}': not a regular file or folder.",
+                    path.display()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn output_name(&self, parent: &Path, name: &OsStr, is_file: bool) -> Result<OsString, String> {
+        match &self.names {
+            Some(names) => names.encrypt(parent, name).map(OsString::from),
+            None if is_file => Ok(paths::with_suffix(Path::new(name), ".enc").into_os_string()),
+            None => Ok(name.to_os_string()),
+        }
+    }
+
+    /// Encrypt one file. Returns a warning if it kept changing while it was
+    /// read.
+    fn encrypt_file(&self, input: &Path, output: &Path) -> Result<Option<String>, String> {
+        let _claims = claims::claim_all(&[input, output], "encrypt")?;
+        memory::check_file(input)?;
+        let _span = trace::span("file").with_text("path", &input.display().to_string());
+        progress::phase(Phase::Reading);
+        let (data, changed) = throttle::read_consistent(input)
+            .map_err(|e| format!("Failed to read '{}': {}", input.display(), e))?;
+        let warning = changed.then(|| crate::input_changed_warning(&input.display().to_string()));
+        let metadata = if self.config.preserve_metadata {
+            FileMetadata::capture(input)?
+        } else {
+            FileMetadata::default()
+        };
+        progress::phase(Phase::Encrypting);
+        let (data, _) = strip::strip_if_enabled(data);
+        let encrypted = crate::encrypt_with_keys(&data, &metadata.to_bytes(), &self.params, &self.keys)?;
+        progress::phase(Phase::Writing);
+        throttle::write(output, &encrypted).map_err(|e| format!("Failed to write '{}': {}", output.display(), e))?;
+        Ok(warning)
+    }
+}
+
+/// Decrypt a tree made by `encrypt` from `source` into `dest`, decrypting
+/// the names too if it has a `NAMES_FILE`. Files whose output is already
+/// newer are skipped, and files may be decrypted several at once, as when
+/// encrypting.
+pub fn decrypt(
+    config: &Config,
+    source: &Path,
+    dest: &Path,
+    password: &str,
+    legacy_xor: bool,
+) -> Result<TreeSummary, String> {
+    if !source.is_dir() {
+        return Err(format!("'{}' is not a folder.", source.display()));
+    }
+    let names = NameKeys::load(source, password.as_bytes(), false)?;
+    fs::create_dir_all(dest).map_err(|e| format!("Failed to create '{}': {}", dest.display(), e))?;
+    let mut decryptor = Decryptor {
+        config,
+        password,
+        legacy_xor,
+        names,
+        keys: Mutex::new(HashMap::new()),
+        files: Vec::new(),
+        summary: TreeSummary::default(),
+    };
+    let _operation = progress::begin(Phase::Reading, None);
+    decryptor.walk(source, dest, Path::new(""))?;
+    let files = std::mem::take(&mut decryptor.files);
+    let warnings = tuning::run(&files, |(input, output)| decryptor.decrypt_file(input, output))?;
+    let mut summary = decryptor.summary;
+    summary.files += files.len();
+    summary.warnings.extend(warnings.into_iter().flatten());
+    Ok(summary)
+}
+
+struct Decryptor<'a> {
+    config: &'a Config,
+    password: &'a str,
+    legacy_xor: bool,
+    names: Option<NameKeys>,
+    /// Keys already derived, by salt and iteration count. Every file from
+    /// one run of `encrypt` shares them.
+    keys: Mutex<HashMap<([u8; SALT_LEN], u32), Keys>>,
+    /// Files to decrypt, with their outputs, found by `walk`.
+    files: Vec<(PathBuf, PathBuf)>,
+    summary: TreeSummary,
+}
+
+impl Decryptor<'_> {
+    fn walk(&mut self, from: &Path, to: &Path, relative: &Path) -> Result<(), String> {
+        for (name, path, kind) in read_dir_sorted(from)? {
+            if relative.as_os_str().is_empty() && name == NAMES_FILE {
+                continue;
+            }
+            if !kind.is_dir() && !kind.is_file() {
+                self.summary.warnings.push(format!(
+                    "Skipped '{}': not a regular file or folder.",
+                    path.display()
+                ));
+                continue;
+            }
+            let Some(plain_name) = self.plain_name(relative, &name, kind.is_file()) else {
+                self.summary.warnings.push(format!(
+                    "Skipped '{}': its name was not encrypted with this tree.",
+                    path.display()
+                ));
+                continue;
+            };
+            let target = to.join(&plain_name);
+            if kind.is_dir() {
+                fs::create_dir_all(&target)
+                    .map_err(|e| format!("Failed to create '{}': {}", target.display(), e))?;
+                self.summary.directories += 1;
+                self.walk(&path, &target, &relative.join(&plain_name))?;
+            } else if is_up_to_date(&path, &target) {
+                self.summary.unchanged += 1;
+            } else {
+                self.files.push((path, target));
+            }
+        }
+        Ok(())
+    }
+
+    fn plain_name(&self, parent: &Path, name: &OsStr, is_file: bool) -> Option<OsString> {
+        match &self.names {
+            Some(names) => names.decrypt(parent, name),
+            None if is_file => {
+                let stem = Path::new(name).file_stem()?;
+                (Path::new(name).extension()? == "enc").then(|| stem.to_os_string())
+            }
+            None => Some(name.to_os_string()),
+        }
+    }
+
+    fn decrypt_file(&self, input: &Path, output: &Path) -> Result<Vec<String>, String> {
+        let _claims = claims::claim_all(&[input, output], "decrypt")?;
+        memory::check_file(input)?;
+        let _span = trace::span("file").with_text("path", &input.display().to_string());
+        progress::phase(Phase::Reading);
+        let data = throttle::read(input).map_err(|e| format!("Failed to read '{}': {}", input.display(), e))?;
+        progress::phase(Phase::Decrypting);
+        let in_file = |e: String| format!("'{}': {}", input.display(), e);
+        let (plaintext, metadata) = match format::split_header(&data).map_err(|e| in_file(format::message(e)))? {
+            Some((version, _)) if version >= format::SEALED_VERSION => {
+                let sealed = format::split_sealed(&data).map_err(|e| in_file(format::message(e)))?;
+                let password = self.password;
+                // Locked while deriving, so workers wait for the one doing
+                // it instead of all deriving the same keys.
+                let keys = self
+                    .keys
+                    .lock()
+                    .unwrap_or_else(|e| e.into_inner())
+                    .entry((sealed.params.salt, sealed.params.iterations))
+                    .or_insert_with(|| {
+                        progress::deriving_key(sealed.params.iterations, || {
+                            sealing::derive_keys(password.as_bytes(), &sealed.params)
+                        })
+                    })
+                    .clone();
+                let (plaintext, metadata) = crate::open_sealed_with_keys(&sealed, &keys).map_err(|e| in_file(e.into()))?;
+                (plaintext, Some(metadata))
+            }
+            _ => {
+                let decrypted = crate::decrypt_bytes(&data, self.password.as_bytes(), self.legacy_xor).map_err(|e| in_file(e.into()))?;
+                (decrypted.plaintext, decrypted.metadata)
+            }
+        };
+        progress::phase(Phase::Writing);
+        throttle::write(output, &plaintext).map_err(|e| format!("Failed to write '{}': {}", output.display(), e))?;
+        Ok(match metadata {
+            Some(metadata) if self.config.preserve_metadata => metadata.restore(output),
+            _ => Vec::new(),
+        })
+    }
+}
+
+/// The entries of `dir` in name order, so runs and warnings are
+/// repeatable, with their types. Symbolic links are not followed.
+fn read_dir_sorted(dir: &Path) -> Result<Vec<(OsString, PathBuf, fs::FileType)>, String> {
+    let read_error = |e: std::io::Error| format!("Failed to read '{}': {}", dir.display(), e);
+    let mut entries = Vec::new();
+    for entry in fs::read_dir(dir).map_err(read_error)? {
+        let entry = entry.map_err(read_error)?;
+        let kind = entry.file_type().map_err(read_error)?;
+        entries.push((entry.file_name(), entry.path(), kind));
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(entries)
+}
+
+/// Whether `output` exists and was changed no earlier than `input`.
+fn is_up_to_date(input: &Path, output: &Path) -> bool {
+    let modified = |path: &Path| fs::metadata(path).and_then(|info| info.modified()).ok();
+    match (modified(input), modified(output)) {
+        (Some(input), Some(output)) => output >= input,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_bound_to_their_folder() {
+        let keys = NameKeys::derive(b"password", &[1; SALT_LEN], 1);
+        let folder = Path::new("photos/2024");
+        let name = OsStr::new("beach día.jpg");
+        let encrypted = keys.encrypt(folder, name).unwrap();
+        assert!(encrypted.bytes().all(|byte| byte.is_ascii_lowercase() || byte.is_ascii_digit()));
+        assert_eq!(keys.encrypt(folder, name).unwrap(), encrypted);
+        assert_eq!(keys.decrypt(folder, OsStr::new(&encrypted)).unwrap(), name);
+
+        // Moved to another folder, or read with other keys, it does not open.
+        assert_ne!(keys.encrypt(Path::new("photos"), name).unwrap(), encrypted);
+        assert!(keys.decrypt(Path::new("photos"), OsStr::new(&encrypted)).is_none());
+        let other = NameKeys::derive(b"other", &[1; SALT_LEN], 1);
+        assert!(other.decrypt(folder, OsStr::new(&encrypted)).is_none());
+        assert!(keys.decrypt(folder, OsStr::new("not base32!")).is_none());
+
+        let longest = "n".repeat(MAX_NAME_LEN);
+        let encrypted = keys.encrypt(folder, OsStr::new(&longest)).unwrap();
+        assert!(encrypted.len() <= 255);
+        assert!(keys.encrypt(folder, OsStr::new(&format!("{}n", longest))).is_err());
+    }
+}
@@ -0,0 +1,59 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+
+/// The controlling terminal, for prompts that must reach the person at the
+/// keyboard even when stdin and stdout are pipes.
+struct Terminal {
+    input: File,
+    output: File,
+}
+
+impl Terminal {
+    /// Open the terminal, or `None` if the process has none, as under cron
+    /// or a service manager.
+    fn open() -> Option<Self> {
+        #[cfg(unix)]
+        {
+            let tty = OpenOptions::new().read(true).write(true).open("/dev/tty").ok()?;
+            Some(Terminal {
+                output: tty.try_clone().ok()?,
+                input: tty,
+            })
+        }
+        #[cfg(windows)]
+        {
+            Some(Terminal {
+                input: OpenOptions::new().read(true).write(true).open("CONIN$").ok()?,
+                output: OpenOptions::new().write(true).open("CONOUT$").ok()?,
+            })
+        }
+        #[cfg(not(any(unix, windows)))]
+        {
+            None
+        }
+    }
+}
+
+/// Show `message` and read a line of answer, trimmed, for passwords and
+/// confirmations.
+///
+/// Both go through the controlling terminal (`/dev/tty`, or the console on
+/// Windows) when there is one, so a password is never read from, and a
+/// prompt never written into, data piped through the program. Without a
+/// terminal they fall back to stdin and stdout.
+pub fn prompt(message: &str) -> String {
+    let Some(mut terminal) = Terminal::open() else {
+        print!("{}", message);
+        crate::flush_stdout();
+        return crate::read_line_trimmed();
+    };
+    // Output already printed belongs before the prompt.
+    crate::flush_stdout();
+    let _ = terminal.output.write_all(message.as_bytes());
+    let _ = terminal.output.flush();
+    // A terminal in line mode hands over one line per read, so the buffer
+    // holds nothing past the answer when it is dropped.
+    let mut answer = String::new();
+    let _ = BufReader::new(terminal.input).read_line(&mut answer);
+    answer.trim().to_string()
+}
@@ -0,0 +1,254 @@
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::Config;
+
+/// Piece size files are read and written in when adaptive tuning is off,
+/// and where it starts.
+pub const DEFAULT_CHUNK_LEN: usize = 64 * 1024;
+
+/// Bytes moved between two looks at the chunk size. Enough for a few
+/// pieces at the largest size, so one slow call does not decide.
+const CHUNK_WINDOW: u64 = 16 << 20;
+
+/// Time between two looks at the number of workers.
+const WORKER_WINDOW: Duration = Duration::from_millis(500);
+
+/// A change must lose more than this share of throughput to be undone, so
+/// noise between windows does not make the settings wander.
+const TOLERANCE: f64 = 0.05;
+
+/// Whether adaptive tuning is on (`--adaptive` or `adaptive_io`).
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Current piece size for reads and writes.
+static CHUNK_LEN: AtomicUsize = AtomicUsize::new(DEFAULT_CHUNK_LEN);
+
+/// Number of workers allowed to take a new file.
+static WORKERS: AtomicUsize = AtomicUsize::new(1);
+
+static TUNER: Mutex<Option<Tuner>> = Mutex::new(None);
+
+/// Limits adaptive tuning stays within.
+#[derive(Debug, Clone, Copy)]
+pub struct Bounds {
+    pub min_chunk: usize,
+    pub max_chunk: usize,
+    pub max_workers: usize,
+}
+
+impl Bounds {
+    /// The limits set by `chunk_size_min`, `chunk_size_max`, and
+    /// `max_workers` in the config file.
+    pub fn from_config(config: &Config) -> Self {
+        let size = |bytes: u64| usize::try_from(bytes).unwrap_or(usize::MAX);
+        Bounds {
+            min_chunk: size(config.chunk_size_min),
+            max_chunk: size(config.chunk_size_max),
+            max_workers: match config.max_workers {
+                0 => thread::available_parallelism().map_or(1, |n| n.get()),
+                workers => size(workers),
+            },
+        }
+    }
+}
+
+/// One setting being climbed towards where throughput is highest: it is
+/// moved a step at a time, and when a step makes things worse the
+/// direction turns around.
+struct Climb {
+    /// Throughput of the last window, in bytes per second.
+    last_rate: Option<f64>,
+    /// Whether the last step made the setting larger.
+    rising: bool,
+}
+
+impl Climb {
+    fn new() -> Self {
+        Climb {
+            last_rate: None,
+            rising: true,
+        }
+    }
+
+    /// Take the window that just ended at `rate` into account and give the
+    /// next value for `value`, between `min` and `max`.
+    fn step(&mut self, rate: f64, value: usize, min: usize, max: usize, next: fn(usize, bool) -> usize) -> usize {
+        if let Some(last_rate) = self.last_rate
+            && rate < last_rate * (1.0 - TOLERANCE)
+        {
+            self.rising = !self.rising;
+        }
+        self.last_rate = Some(rate);
+        if (self.rising && value >= max) || (!self.rising && value <= min) {
+            self.rising = !self.rising;
+        }
+        next(value, self.rising).clamp(min, max)
+    }
+}
+
+struct Tuner {
+    bounds: Bounds,
+    chunk: Climb,
+    chunk_bytes: u64,
+    chunk_time: Duration,
+    workers: Climb,
+    worker_bytes: u64,
+    worker_started: Instant,
+}
+
+/// Let chunk size and worker count follow measured throughput, within
+/// `bounds`, for the rest of the run. Work starts with the default piece
+/// size and two workers (or one, if that is all that is allowed).
+pub fn enable(bounds: Bounds) {
+    let bounds = Bounds {
+        min_chunk: bounds.min_chunk.max(4096),
+        max_chunk: bounds.max_chunk.max(bounds.min_chunk.max(4096)),
+        max_workers: bounds.max_workers.max(1),
+    };
+    CHUNK_LEN.store(DEFAULT_CHUNK_LEN.clamp(bounds.min_chunk, bounds.max_chunk), Ordering::Relaxed);
+    WORKERS.store(2.min(bounds.max_workers), Ordering::Relaxed);
+    *TUNER.lock().unwrap_or_else(|e| e.into_inner()) = Some(Tuner {
+        bounds,
+        chunk: Climb::new(),
+        chunk_bytes: 0,
+        chunk_time: Duration::ZERO,
+        workers: Climb::new(),
+        worker_bytes: 0,
+        worker_started: Instant::now(),
+    });
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// Whether adaptive tuning is on.
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// Size of the next piece to read or write.
+pub fn chunk_len() -> usize {
+    CHUNK_LEN.load(Ordering::Relaxed)
+}
+
+/// Count a read or write of `bytes` that took `took`, done at the size
+/// `chunk_len` gave. Its speed tunes the chunk size.
+pub fn chunk_moved(bytes: usize, took: Duration) {
+    if !enabled() {
+        return;
+    }
+    let mut tuner = TUNER.lock().unwrap_or_else(|e| e.into_inner());
+    let Some(tuner) = tuner.as_mut() else {
+        return;
+    };
+    tuner.chunk_bytes += bytes as u64;
+    tuner.chunk_time += took;
+    if tuner.chunk_bytes >= CHUNK_WINDOW {
+        let rate = tuner.chunk_bytes as f64 / tuner.chunk_time.as_secs_f64().max(1e-6);
+        let Bounds { min_chunk, max_chunk, .. } = tuner.bounds;
+        let next = tuner.chunk.step(rate, chunk_len(), min_chunk, max_chunk, |len, up| {
+            if up { len * 2 } else { len / 2 }
+        });
+        CHUNK_LEN.store(next, Ordering::Relaxed);
+        tuner.chunk_bytes = 0;
+        tuner.chunk_time = Duration::ZERO;
+    }
+    tuner.count(bytes);
+}
+
+/// Count `bytes` of file contents read or written some other way, such as
+/// through io_uring, which only tunes the number of workers.
+pub fn moved(bytes: usize) {
+    if !enabled() {
+        return;
+    }
+    if let Some(tuner) = TUNER.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        tuner.count(bytes);
+    }
+}
+
+impl Tuner {
+    /// Add `bytes` to what all workers moved, and after each window turn
+    /// the number of workers up or down by one.
+    fn count(&mut self, bytes: usize) {
+        self.worker_bytes += bytes as u64;
+        let elapsed = self.worker_started.elapsed();
+        if elapsed < WORKER_WINDOW {
+            return;
+        }
+        let rate = self.worker_bytes as f64 / elapsed.as_secs_f64();
+        let workers = WORKERS.load(Ordering::Relaxed);
+        let next = self.workers.step(rate, workers, 1, self.bounds.max_workers, |count, up| {
+            if up { count + 1 } else { count.saturating_sub(1) }
+        });
+        WORKERS.store(next, Ordering::Relaxed);
+        self.worker_bytes = 0;
+        self.worker_started = Instant::now();
+    }
+}
+
+/// Run `work` on every item of `jobs` and return the results in the same
+/// order. Without adaptive tuning, or where memory is limited (every file
+/// is held whole), the items are done one after another. Otherwise as many
+/// run at once as the tuner currently allows. After an error no further
+/// items are started; the first error in `jobs` order is returned.
+pub fn run<T: Sync, R: Send>(jobs: &[T], work: impl Fn(&T) -> Result<R, String> + Sync) -> Result<Vec<R>, String> {
+    let threads = match TUNER.lock().unwrap_or_else(|e| e.into_inner()).as_mut() {
+        Some(tuner) if !crate::memory::is_limited() => {
+            // Measure this run from its start, not from idle time before.
+            tuner.worker_bytes = 0;
+            tuner.worker_started = Instant::now();
+            tuner.bounds.max_workers.min(jobs.len())
+        }
+        _ => 1,
+    };
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let results: Vec<Mutex<Option<Result<R, String>>>> = jobs.iter().map(|_| Mutex::new(None)).collect();
+    thread::scope(|scope| {
+        for worker in 0..threads {
+            let (next, failed, results, work) = (&next, &failed, &results, &work);
+            scope.spawn(move || {
+                loop {
+                    // Workers above the current count wait until it rises
+                    // or the work runs out.
+                    if worker > 0 && worker >= WORKERS.load(Ordering::Relaxed) {
+                        if next.load(Ordering::Relaxed) >= jobs.len() || failed.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        thread::sleep(Duration::from_millis(20));
+                        continue;
+                    }
+                    let index = next.fetch_add(1, Ordering::Relaxed);
+                    if index >= jobs.len() || failed.load(Ordering::Relaxed) {
+                        return;
+                    }
+                    let result = work(&jobs[index]);
+                    if result.is_err() {
+                        failed.store(true, Ordering::Relaxed);
+                    }
+                    *results[index].lock().unwrap_or_else(|e| e.into_inner()) = Some(result);
+                }
+            });
+        }
+    });
+    let mut done = Vec::with_capacity(jobs.len());
+    for result in results {
+        match result.into_inner().unwrap_or_else(|e| e.into_inner()) {
+            Some(result) => done.push(result?),
+            // Not started because an earlier item failed.
+            None => break,
+        }
+    }
+    Ok(done)
+}
+
+/// The settings in use, for `--adaptive` runs to report at the end.
+pub fn describe() -> String {
+    format!(
+        "Adaptive I/O settled on {} KiB pieces and {} worker(s).",
+        chunk_len() / 1024,
+        WORKERS.load(Ordering::Relaxed)
+    )
+}
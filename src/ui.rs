@@ -0,0 +1,187 @@
+use std::env;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether decorative output is turned off.
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// Decide once at startup whether to use plain output. It is on when asked
+/// for with `--plain` or the `plain` setting, and also on dumb terminals,
+/// which cannot draw decorations sensibly.
+pub fn init(requested: bool) {
+    let dumb_terminal = env::var("TERM").is_ok_and(|term| term == "dumb");
+    PLAIN.store(requested || dumb_terminal, Ordering::Relaxed);
+}
+
+/// Whether output should avoid decorations such as rules, arrows, colors,
+/// and redrawn progress lines, so screen readers only read the content.
+pub fn is_plain() -> bool {
+    PLAIN.load(Ordering::Relaxed)
+}
+
+/// Width assumed when it cannot be found out, as when output goes to a
+/// file and `COLUMNS` is not set.
+const DEFAULT_WIDTH: usize = 80;
+
+/// Narrowest width output is fitted to. Anything narrower wraps anyway.
+const MIN_WIDTH: usize = 20;
+
+/// Width of a full rule, when the terminal is at least this wide.
+const RULE_WIDTH: usize = 55;
+
+/// Columns available for a line of output. It is asked for each time,
+/// so output follows the terminal when it is resized. `COLUMNS`, if set,
+/// wins, so CI logs and pipes can ask for a width of their own.
+pub fn width() -> usize {
+    let columns = env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.trim().parse().ok())
+        .or_else(terminal_width)
+        .filter(|&columns| columns > 0)
+        .unwrap_or(DEFAULT_WIDTH);
+    columns.max(MIN_WIDTH)
+}
+
+#[cfg(unix)]
+fn terminal_width() -> Option<usize> {
+    #[repr(C)]
+    struct WinSize {
+        rows: u16,
+        columns: u16,
+        x_pixels: u16,
+        y_pixels: u16,
+    }
+    unsafe extern "C" {
+        fn ioctl(fd: i32, request: std::ffi::c_ulong, ...) -> i32;
+    }
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    const TIOCGWINSZ: std::ffi::c_ulong = 0x5413;
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    const TIOCGWINSZ: std::ffi::c_ulong = 0x4008_7468;
+
+    // Whichever of stdout, stderr, and stdin is the terminal.
+    [1, 2, 0].into_iter().find_map(|fd| {
+        let mut size = WinSize {
+            rows: 0,
+            columns: 0,
+            x_pixels: 0,
+            y_pixels: 0,
+        };
+        // SAFETY: TIOCGWINSZ writes one winsize structure, which `size`
+        // matches; on a descriptor that is not a terminal it fails and
+        // writes nothing.
+        let result = unsafe { ioctl(fd, TIOCGWINSZ, &mut size as *mut WinSize) };
+        (result == 0 && size.columns > 0).then_some(size.columns as usize)
+    })
+}
+
+#[cfg(windows)]
+fn terminal_width() -> Option<usize> {
+    #[repr(C)]
+    struct ScreenBufferInfo {
+        size: [i16; 2],
+        cursor_position: [i16; 2],
+        attributes: u16,
+        /// Left, top, right, and bottom of the visible window.
+        window: [i16; 4],
+        maximum_window_size: [i16; 2],
+    }
+    unsafe extern "system" {
+        fn GetStdHandle(which: u32) -> *mut std::ffi::c_void;
+        fn GetConsoleScreenBufferInfo(console: *mut std::ffi::c_void, info: *mut ScreenBufferInfo) -> i32;
+    }
+    const STD_OUTPUT_HANDLE: u32 = -11i32 as u32;
+    const STD_ERROR_HANDLE: u32 = -12i32 as u32;
+
+    [STD_OUTPUT_HANDLE, STD_ERROR_HANDLE].into_iter().find_map(|which| {
+        let mut info = ScreenBufferInfo {
+            size: [0; 2],
+            cursor_position: [0; 2],
+            attributes: 0,
+            window: [0; 4],
+            maximum_window_size: [0; 2],
+        };
+        // SAFETY: GetStdHandle takes a constant and returns a handle or
+        // null; GetConsoleScreenBufferInfo fails on anything that is not
+        // a console and otherwise fills in `info`, which matches
+        // CONSOLE_SCREEN_BUFFER_INFO.
+        let ok = unsafe { GetConsoleScreenBufferInfo(GetStdHandle(which), &mut info) } != 0;
+        let columns = info.window[2] - info.window[0] + 1;
+        (ok && columns > 0).then_some(columns as usize)
+    })
+}
+
+#[cfg(not(any(unix, windows)))]
+fn terminal_width() -> Option<usize> {
+    None
+}
+
+/// Shorten `text` to at most `max` characters by cutting out its middle,
+/// so both the start of a path and its file name stay visible:
+/// `/home/ana/Documents/…/2024/report.pdf`.
+pub fn truncate_middle(text: &str, max: usize) -> String {
+    let chars: Vec<char> = text.chars().collect();
+    if chars.len() <= max {
+        return text.to_string();
+    }
+    let marker = if is_plain() { "..." } else { "\u{2026}" };
+    let marker_len = marker.chars().count();
+    if max <= marker_len {
+        return chars[chars.len() - max..].iter().collect();
+    }
+    let keep = max - marker_len;
+    // The end usually says more (the file name), so it gets the extra one.
+    let head = keep / 2;
+    let tail = keep - head;
+    let mut shortened: String = chars[..head].iter().collect();
+    shortened.push_str(marker);
+    shortened.extend(&chars[chars.len() - tail..]);
+    shortened
+}
+
+/// Print a section heading such as "--- History ---". In plain mode the
+/// surrounding dashes or equals signs are dropped, and so are they when
+/// the heading would not fit on one line.
+pub fn heading(text: &str) {
+    let title = text.trim_matches(['-', '=', ' ']);
+    if is_plain() || text.chars().count() > width() {
+        println!("{}", truncate_middle(title, width()));
+    } else {
+        println!("{}", text);
+    }
+}
+
+/// Print a horizontal rule, as wide as the terminal allows, or nothing in
+/// plain mode.
+pub fn rule() {
+    if !is_plain() {
+        println!("{}", "=".repeat(RULE_WIDTH.min(width())));
+    }
+}
+
+/// Show `line` as the current progress, on stderr. Outside plain mode it
+/// replaces the previous progress line in place, cut to the width of the
+/// terminal so it never wraps onto a second line that `\r` cannot reach.
+/// In plain mode each report is a line of its own.
+pub fn progress(line: &str) {
+    if is_plain() {
+        eprintln!("{}", line);
+        return;
+    }
+    let columns = width() - 1;
+    eprint!("\r{:<columns$}", truncate_middle(line, columns));
+    let _ = std::io::stderr().flush();
+}
+
+/// Clear the progress line, so what follows starts at the beginning of an
+/// empty line. Does nothing in plain mode.
+pub fn end_progress() {
+    if !is_plain() {
+        eprint!("\r{}\r", " ".repeat(width() - 1));
+    }
+}
+
+/// The separator between a path and its result, such as `a.txt -> Success`.
+pub fn arrow() -> &'static str {
+    if is_plain() { ":" } else { " ->" }
+}
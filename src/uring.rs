@@ -0,0 +1,498 @@
+use std::ffi::c_void;
+use std::fs::File;
+use std::io;
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::ptr;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+/// Submission queue size. This many pieces of a file are in flight at once.
+const QUEUE_DEPTH: u32 = 8;
+
+/// Size of each read or write handed to the kernel.
+const PIECE_LEN: usize = 256 * 1024;
+
+// System call numbers and constants from <linux/io_uring.h>. The io_uring
+// calls have the same numbers on every architecture.
+const SYS_IO_URING_SETUP: i64 = 425;
+const SYS_IO_URING_ENTER: i64 = 426;
+const IORING_ENTER_GETEVENTS: u32 = 1;
+const IORING_FEAT_SINGLE_MMAP: u32 = 1;
+const IORING_OFF_SQ_RING: i64 = 0;
+const IORING_OFF_CQ_RING: i64 = 0x800_0000;
+const IORING_OFF_SQES: i64 = 0x1000_0000;
+const IORING_OP_READ: u8 = 22;
+const IORING_OP_WRITE: u8 = 23;
+
+const PROT_READ: i32 = 1;
+const PROT_WRITE: i32 = 2;
+const MAP_SHARED: i32 = 1;
+const MAP_POPULATE: i32 = 0x8000;
+
+unsafe extern "C" {
+    fn syscall(number: i64, ...) -> i64;
+    fn mmap(
+        address: *mut c_void,
+        len: usize,
+        prot: i32,
+        flags: i32,
+        fd: i32,
+        offset: i64,
+    ) -> *mut c_void;
+    fn munmap(address: *mut c_void, len: usize) -> i32;
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct SqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    flags: u32,
+    dropped: u32,
+    array: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct CqRingOffsets {
+    head: u32,
+    tail: u32,
+    ring_mask: u32,
+    ring_entries: u32,
+    overflow: u32,
+    cqes: u32,
+    flags: u32,
+    resv1: u32,
+    user_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Params {
+    sq_entries: u32,
+    cq_entries: u32,
+    flags: u32,
+    sq_thread_cpu: u32,
+    sq_thread_idle: u32,
+    features: u32,
+    wq_fd: u32,
+    resv: [u32; 3],
+    sq_off: SqRingOffsets,
+    cq_off: CqRingOffsets,
+}
+
+/// One submission queue entry.
+#[repr(C)]
+#[derive(Default)]
+struct Sqe {
+    opcode: u8,
+    flags: u8,
+    ioprio: u16,
+    fd: i32,
+    off: u64,
+    addr: u64,
+    len: u32,
+    rw_flags: u32,
+    user_data: u64,
+    pad: [u64; 3],
+}
+
+/// One completion queue entry.
+#[repr(C)]
+struct Cqe {
+    user_data: u64,
+    res: i32,
+    flags: u32,
+}
+
+/// A shared memory mapping, unmapped on drop.
+struct Mapping {
+    address: *mut u8,
+    len: usize,
+}
+
+impl Mapping {
+    fn new(fd: &OwnedFd, len: usize, offset: i64) -> io::Result<Self> {
+        // SAFETY: a fresh shared mapping of the ring; the kernel checks the
+        // descriptor, length, and offset.
+        let address = unsafe {
+            mmap(
+                ptr::null_mut(),
+                len,
+                PROT_READ | PROT_WRITE,
+                MAP_SHARED | MAP_POPULATE,
+                fd.as_raw_fd(),
+                offset,
+            )
+        };
+        if address as isize == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping {
+            address: address as *mut u8,
+            len,
+        })
+    }
+
+    /// The field `offset` bytes into the mapping.
+    fn at<T>(&self, offset: u32) -> *mut T {
+        // SAFETY: the kernel reported the offset as lying inside this
+        // mapping.
+        unsafe { self.address.add(offset as usize) as *mut T }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        // SAFETY: the mapping was created by `Mapping::new` with this length
+        // and nothing uses it after the ring is dropped.
+        unsafe { munmap(self.address as *mut c_void, self.len) };
+    }
+}
+
+/// A Linux io_uring with one submission and one completion queue.
+struct Ring {
+    // Field order matters: the mappings are unmapped before the descriptor
+    // is closed. The ring mappings are only reached through the pointers
+    // below, and are held here so they stay mapped.
+    _sq_ring: Mapping,
+    _cq_ring: Option<Mapping>,
+    sqes: Mapping,
+    fd: OwnedFd,
+    sq_entries: u32,
+    sq_head: *const AtomicU32,
+    sq_tail: *const AtomicU32,
+    sq_mask: u32,
+    sq_array: *mut u32,
+    cq_head: *const AtomicU32,
+    cq_tail: *const AtomicU32,
+    cq_mask: u32,
+    cqes: *const Cqe,
+    unsubmitted: u32,
+}
+
+impl Ring {
+    fn new(entries: u32) -> io::Result<Self> {
+        let mut params = Params::default();
+        // SAFETY: io_uring_setup writes into `params`, which outlives the
+        // call.
+        let fd = unsafe {
+            syscall(
+                SYS_IO_URING_SETUP,
+                entries as i64,
+                &mut params as *mut Params,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // SAFETY: the kernel just returned this descriptor and nothing else
+        // owns it.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as i32) };
+
+        let sq_len = params.sq_off.array as usize + params.sq_entries as usize * 4;
+        let cq_len = params.cq_off.cqes as usize + params.cq_entries as usize * size_of::<Cqe>();
+        let single_mmap = params.features & IORING_FEAT_SINGLE_MMAP != 0;
+        let sq_ring = Mapping::new(
+            &fd,
+            if single_mmap {
+                sq_len.max(cq_len)
+            } else {
+                sq_len
+            },
+            IORING_OFF_SQ_RING,
+        )?;
+        let cq_ring = if single_mmap {
+            None
+        } else {
+            Some(Mapping::new(&fd, cq_len, IORING_OFF_CQ_RING)?)
+        };
+        let sqes = Mapping::new(
+            &fd,
+            params.sq_entries as usize * size_of::<Sqe>(),
+            IORING_OFF_SQES,
+        )?;
+
+        let cq = cq_ring.as_ref().unwrap_or(&sq_ring);
+        // SAFETY: the masks are plain values in the rings the kernel laid
+        // out at these offsets.
+        let (sq_mask, cq_mask) = unsafe {
+            (
+                *sq_ring.at::<u32>(params.sq_off.ring_mask),
+                *cq.at::<u32>(params.cq_off.ring_mask),
+            )
+        };
+        Ok(Ring {
+            sq_entries: params.sq_entries,
+            sq_head: sq_ring.at(params.sq_off.head),
+            sq_tail: sq_ring.at(params.sq_off.tail),
+            sq_mask,
+            sq_array: sq_ring.at(params.sq_off.array),
+            cq_head: cq.at(params.cq_off.head),
+            cq_tail: cq.at(params.cq_off.tail),
+            cq_mask,
+            cqes: cq.at(params.cq_off.cqes),
+            unsubmitted: 0,
+            _sq_ring: sq_ring,
+            _cq_ring: cq_ring,
+            sqes,
+            fd,
+        })
+    }
+
+    /// Queue an entry. Returns false if the submission queue is full.
+    fn push(&mut self, sqe: Sqe) -> bool {
+        // SAFETY: the head, tail, array, and entries live in the mapped
+        // rings; the kernel only reads the slots between head and tail, and
+        // the tail is published after the slot is written.
+        unsafe {
+            let head = (*self.sq_head).load(Ordering::Acquire);
+            let tail = (*self.sq_tail).load(Ordering::Relaxed);
+            if tail.wrapping_sub(head) == self.sq_entries {
+                return false;
+            }
+            let index = tail & self.sq_mask;
+            ptr::write(self.sqes.at::<Sqe>(0).add(index as usize), sqe);
+            ptr::write(self.sq_array.add(index as usize), index);
+            (*self.sq_tail).store(tail.wrapping_add(1), Ordering::Release);
+        }
+        self.unsubmitted += 1;
+        true
+    }
+
+    /// Submit everything queued and wait until at least `min_complete`
+    /// completions are ready.
+    fn submit_and_wait(&mut self, min_complete: u32) -> io::Result<()> {
+        loop {
+            // SAFETY: io_uring_enter takes only integers and a null signal
+            // mask.
+            let result = unsafe {
+                syscall(
+                    SYS_IO_URING_ENTER,
+                    self.fd.as_raw_fd(),
+                    self.unsubmitted,
+                    min_complete,
+                    IORING_ENTER_GETEVENTS,
+                    ptr::null::<c_void>(),
+                    0usize,
+                )
+            };
+            if result >= 0 {
+                self.unsubmitted -= result as u32;
+                return Ok(());
+            }
+            let error = io::Error::last_os_error();
+            if error.kind() != io::ErrorKind::Interrupted {
+                return Err(error);
+            }
+        }
+    }
+
+    /// Take the next completion, if any.
+    fn pop(&mut self) -> Option<(u64, i32)> {
+        // SAFETY: the kernel publishes entries before moving the tail, and
+        // the slot is read before the head is moved past it.
+        unsafe {
+            let head = (*self.cq_head).load(Ordering::Relaxed);
+            let tail = (*self.cq_tail).load(Ordering::Acquire);
+            if head == tail {
+                return None;
+            }
+            let cqe = &*self.cqes.add((head & self.cq_mask) as usize);
+            let completion = (cqe.user_data, cqe.res);
+            (*self.cq_head).store(head.wrapping_add(1), Ordering::Release);
+            Some(completion)
+        }
+    }
+}
+
+/// Check that io_uring can be used here. Kernels before 5.6 and sandboxes
+/// that filter the system call refuse it.
+pub fn probe() -> io::Result<()> {
+    Ring::new(QUEUE_DEPTH).map(|_| ())
+}
+
+/// Fill `data` from the start of `file`, keeping several reads in flight.
+/// Returns how many bytes were read, which is less than `data.len()` only
+/// if the file is shorter. `pace` is called before each new piece is
+/// queued.
+pub fn read(file: &File, data: &mut [u8], pace: &dyn Fn(usize)) -> io::Result<usize> {
+    transfer(file, data.as_mut_ptr(), data.len(), IORING_OP_READ, pace)
+}
+
+/// Write all of `data` to the start of `file`, keeping several writes in
+/// flight.
+pub fn write(file: &File, data: &[u8], pace: &dyn Fn(usize)) -> io::Result<()> {
+    let written = transfer(
+        file,
+        data.as_ptr() as *mut u8,
+        data.len(),
+        IORING_OP_WRITE,
+        pace,
+    )?;
+    if written < data.len() {
+        return Err(io::ErrorKind::WriteZero.into());
+    }
+    Ok(())
+}
+
+/// Move `len` bytes between `buffer` and `file` in pieces, resubmitting
+/// the rest of any short transfer. Every queued piece is waited for before
+/// returning, even after an error, because the kernel still points into
+/// `buffer`. Returns how far from the start the file was transferred
+/// without a gap, which is short of `len` only where a piece moved nothing.
+fn transfer(
+    file: &File,
+    buffer: *mut u8,
+    len: usize,
+    opcode: u8,
+    pace: &dyn Fn(usize),
+) -> io::Result<usize> {
+    let mut ring = Ring::new(QUEUE_DEPTH)?;
+    // The pieces the kernel holds, found by the index passed as user_data.
+    let mut slots: Vec<(usize, usize)> = Vec::new();
+    let mut free_slots: Vec<usize> = Vec::new();
+    let mut pending: Vec<(usize, usize)> = Vec::new();
+    let mut next = 0;
+    let mut in_flight = 0;
+    // Where a piece first moved nothing: the end of the file for a read.
+    let mut end = len;
+    let mut refused_waits = 0;
+    let mut failure = None;
+
+    loop {
+        while failure.is_none() {
+            let piece = match pending.pop() {
+                Some(piece) => piece,
+                None if next < end => {
+                    let piece = (next, PIECE_LEN.min(end - next));
+                    next += piece.1;
+                    pace(piece.1);
+                    piece
+                }
+                None => break,
+            };
+            let slot = free_slots.pop().unwrap_or(slots.len());
+            let sqe = Sqe {
+                opcode,
+                fd: file.as_raw_fd(),
+                off: piece.0 as u64,
+                // SAFETY: the piece lies inside `buffer`.
+                addr: unsafe { buffer.add(piece.0) } as u64,
+                len: piece.1 as u32,
+                user_data: slot as u64,
+                ..Sqe::default()
+            };
+            if !ring.push(sqe) {
+                free_slots.push(slot);
+                pending.push(piece);
+                break;
+            }
+            if slot == slots.len() {
+                slots.push(piece);
+            } else {
+                slots[slot] = piece;
+            }
+            in_flight += 1;
+        }
+        if in_flight == 0 {
+            break;
+        }
+
+        if let Err(error) = ring.submit_and_wait(1) {
+            // Returning would free `buffer` under pieces the kernel may still
+            // hold, so stop queueing and keep waiting for them. A ring that
+            // refuses every wait leaves nothing safe to do.
+            refused_waits += 1;
+            if refused_waits == 100 {
+                std::process::abort();
+            }
+            failure.get_or_insert(error);
+        }
+        while let Some((user_data, result)) = ring.pop() {
+            refused_waits = 0;
+            in_flight -= 1;
+            let slot = user_data as usize;
+            let (start, piece_len) = slots[slot];
+            free_slots.push(slot);
+            match result {
+                error if error < 0 => {
+                    failure.get_or_insert(io::Error::from_raw_os_error(-error));
+                }
+                0 => {
+                    // Nothing lies past this point, so drop what was queued
+                    // beyond it but keep finishing the pieces before it.
+                    end = end.min(start);
+                    pending.retain(|&(start, _)| start < end);
+                }
+                moved => {
+                    let moved = moved as usize;
+                    if moved < piece_len && start + moved < end {
+                        pending.push((start + moved, piece_len - moved));
+                    }
+                }
+            }
+        }
+    }
+
+    match failure {
+        Some(error) => Err(error),
+        None => Ok(end),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::{self, OpenOptions};
+
+    #[test]
+    fn write_then_read_back() {
+        if probe().is_err() {
+            eprintln!("io_uring is not available here; skipping");
+            return;
+        }
+        let path = std::env::temp_dir().join(format!("file_encryptor_uring_{}", std::process::id()));
+        // More pieces than fit in the queue at once, and a partial last one.
+        let data: Vec<u8> = (0..QUEUE_DEPTH as usize * 2 * PIECE_LEN + 123)
+            .map(|index| (index % 251) as u8)
+            .collect();
+        let queued = std::cell::Cell::new(0);
+        let pace = |len: usize| queued.set(queued.get() + len);
+
+        let file = OpenOptions::new().create(true).truncate(true).read(true).write(true).open(&path).unwrap();
+        write(&file, &data, &pace).unwrap();
+        assert_eq!(queued.get(), data.len());
+        assert_eq!(fs::read(&path).unwrap(), data);
+
+        let mut back = vec![0u8; data.len()];
+        assert_eq!(read(&file, &mut back, &|_| {}).unwrap(), data.len());
+        assert_eq!(back, data);
+
+        // A buffer longer than the file is filled as far as the file goes.
+        let mut longer = vec![0u8; data.len() + PIECE_LEN];
+        assert_eq!(read(&file, &mut longer, &|_| {}).unwrap(), data.len());
+        assert_eq!(longer[..data.len()], data[..]);
+        drop(file);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        if probe().is_err() {
+            eprintln!("io_uring is not available here; skipping");
+            return;
+        }
+        let path = std::env::temp_dir().join(format!("file_encryptor_uring_ro_{}", std::process::id()));
+        fs::write(&path, b"read only").unwrap();
+        // Writing through a handle opened only for reading fails.
+        let file = fs::File::open(&path).unwrap();
+        assert!(write(&file, &[0u8; 3 * PIECE_LEN], &|_| {}).is_err());
+        drop(file);
+        assert_eq!(fs::read(&path).unwrap(), b"read only");
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::OpenError;
+use crate::format;
+use crate::storage;
+
+/// Bytes at the start of a decrypted vault, so an ordinary encrypted file
+/// is not mistaken for one.
+const VAULT_MAGIC: &[u8; 4] = b"FVLT";
+
+/// Record tags inside a vault. Every name record is followed by the value
+/// record that belongs to it.
+const TAG_NAME: u8 = 1;
+const TAG_VALUE: u8 = 2;
+
+/// Short named secrets, such as API keys or notes, kept together in one
+/// encrypted file. The file uses the same format and key derivation as any
+/// other encrypted file.
+pub struct Vault {
+    entries: BTreeMap<String, String>,
+}
+
+impl Vault {
+    /// Decrypt the vault at `path`, or start an empty one if the file does
+    /// not exist yet.
+    pub fn open(path: &str, password: &str) -> Result<Self, OpenError> {
+        if password.is_empty() {
+            return Err("Password cannot be empty.".to_string().into());
+        }
+        if !Path::new(path).exists() {
+            return Ok(Vault {
+                entries: BTreeMap::new(),
+            });
+        }
+
+        let data = fs::read(path).map_err(|e| format!("Failed to read vault: {}", e))?;
+        let decrypted = crate::decrypt_bytes(&data, password.as_bytes(), false)?;
+        let records = decrypted
+            .plaintext
+            .strip_prefix(VAULT_MAGIC)
+            .ok_or_else(|| format!("'{}' is an encrypted file but not a vault.", path))?;
+
+        let mut entries = BTreeMap::new();
+        let mut records = format::read_records(records)?.into_iter();
+        while let Some((tag, name)) = records.next() {
+            let value = match records.next() {
+                Some((TAG_VALUE, value)) if tag == TAG_NAME => value,
+                _ => return Err("The vault's contents are damaged.".to_string().into()),
+            };
+            entries.insert(
+                String::from_utf8_lossy(name).into_owned(),
+                String::from_utf8_lossy(value).into_owned(),
+            );
+        }
+        Ok(Vault { entries })
+    }
+
+    /// Encrypt the vault and write it to `path`. The new file is written
+    /// next to the old one and renamed over it, so a crash never leaves a
+    /// half-written vault behind.
+    pub fn save(&self, path: &str, password: &str) -> Result<(), String> {
+        let mut plaintext = VAULT_MAGIC.to_vec();
+        for (name, value) in &self.entries {
+            format::push_record(&mut plaintext, TAG_NAME, name.as_bytes());
+            format::push_record(&mut plaintext, TAG_VALUE, value.as_bytes());
+        }
+        let encrypted = crate::encrypt_bytes(&plaintext, &[], password.as_bytes())?;
+
+        let temp_path = format!("{}.saving", path);
+        fs::write(&temp_path, &encrypted)
+            .and_then(|_| storage::rename(&temp_path, path))
+            .map_err(|e| {
+                let _ = fs::remove_file(&temp_path);
+                format!("Failed to write vault: {}", e)
+            })
+    }
+
+    /// Store a new secret. Existing names are not overwritten.
+    pub fn add(&mut self, name: &str, value: &str) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("The name cannot be empty.".to_string());
+        }
+        if self.entries.contains_key(name) {
+            return Err(format!(
+                "'{}' is already in the vault. Remove it first to replace it.",
+                name
+            ));
+        }
+        self.entries.insert(name.to_string(), value.to_string());
+        Ok(())
+    }
+
+    /// The secret stored under `name`.
+    pub fn get(&self, name: &str) -> Result<&str, String> {
+        self.entries
+            .get(name)
+            .map(String::as_str)
+            .ok_or_else(|| format!("'{}' is not in the vault.", name))
+    }
+
+    /// Delete the secret stored under `name`.
+    pub fn remove(&mut self, name: &str) -> Result<(), String> {
+        self.entries
+            .remove(name)
+            .map(|_| ())
+            .ok_or_else(|| format!("'{}' is not in the vault.", name))
+    }
+
+    /// Every stored name, in sorted order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.entries.keys().map(String::as_str)
+    }
+}